@@ -0,0 +1,15 @@
+#![no_main]
+
+use ash_video::h264::avcc::AvcDecoderConfiguration;
+use libfuzzer_sys::fuzz_target;
+
+// The record comes straight from the file, anything may be in it. Parameter sets of
+// records that parse are fed on to the SPS and PPS parsers.
+//
+// `cargo fuzz run avcc` starts from the regression corpus in corpus/avcc, which
+// tests/h264_avcc.rs replays on every test run.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(config) = AvcDecoderConfiguration::parse(data) {
+        let _ = config.parameter_sets();
+    }
+});
@@ -2,17 +2,71 @@
 #extension GL_ARB_separate_shader_objects : enable
 #extension GL_ARB_shading_language_420pack : enable
 
+// Sampled through a YCbCr conversion that only expands the range, Cr, Y, Cb in r, g, b
 layout (binding = 1) uniform sampler2D samplerColor;
 
-layout (binding = 0) uniform UBO{
-    vec3 color;
+// ColorUniforms in src/color.rs
+layout (binding = 0) uniform UBO {
+    mat3 ycbcr_to_rgb;
+    mat3 gamut;
+    int transfer;
+    int srgb_output;
 } ubo;
 
-
 layout (location = 0) in vec2 o_uv;
 layout (location = 0) out vec4 uFragColor;
 
+// TransferCharacteristics::to_linear
+float to_linear(float value) {
+    value = max(value, 0.0);
+    if (ubo.transfer == 1) {
+        return value < 0.081 ? value / 4.5 : pow((value + 0.099) / 1.099, 1.0 / 0.45);
+    } else if (ubo.transfer == 2) {
+        return pow(value, 2.2);
+    } else if (ubo.transfer == 3) {
+        return pow(value, 2.8);
+    } else if (ubo.transfer == 4) {
+        return value < 0.0913 ? value / 4.0 : pow((value + 0.1115) / 1.1115, 1.0 / 0.45);
+    } else if (ubo.transfer == 6) {
+        return value <= 0.04045 ? value / 12.92 : pow((value + 0.055) / 1.055, 2.4);
+    } else if (ubo.transfer == 7) {
+        float m1 = 2610.0 / 16384.0;
+        float m2 = 2523.0 / 4096.0 * 128.0;
+        float c1 = 3424.0 / 4096.0;
+        float c2 = 2413.0 / 4096.0 * 32.0;
+        float c3 = 2392.0 / 4096.0 * 32.0;
+        float p = pow(value, 1.0 / m2);
+        return pow(max(p - c1, 0.0) / (c2 - c3 * p), 1.0 / m1) * 10000.0 / 203.0;
+    } else if (ubo.transfer == 8) {
+        return value <= 0.5 ? value * value / 3.0
+                            : (exp((value - 0.5599107) / 0.17883277) + 0.28466892) / 12.0;
+    }
+    return value;
+}
+
+float bt709_from_linear(float value) {
+    return value < 0.018 ? value * 4.5 : 1.099 * pow(value, 0.45) - 0.099;
+}
+
+float srgb_to_linear(float value) {
+    return value <= 0.04045 ? value / 12.92 : pow((value + 0.055) / 1.055, 2.4);
+}
+
 void main() {
-    vec4 color = texture(samplerColor, o_uv);
-    uFragColor = color;
+    vec3 ycbcr = texture(samplerColor, o_uv).gbr;
+    vec3 rgb = ubo.ycbcr_to_rgb * ycbcr;
+
+    if (ubo.transfer != 0) {
+        vec3 linear = ubo.gamut * vec3(to_linear(rgb.r), to_linear(rgb.g), to_linear(rgb.b));
+        linear = clamp(linear, 0.0, 1.0);
+        rgb = vec3(bt709_from_linear(linear.r), bt709_from_linear(linear.g),
+                   bt709_from_linear(linear.b));
+    }
+    rgb = clamp(rgb, 0.0, 1.0);
+
+    // An sRGB swapchain encodes again, hand it the light that comes out as rgb
+    if (ubo.srgb_output != 0) {
+        rgb = vec3(srgb_to_linear(rgb.r), srgb_to_linear(rgb.g), srgb_to_linear(rgb.b));
+    }
+    uFragColor = vec4(rgb, 1.0);
 }
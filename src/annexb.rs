@@ -0,0 +1,243 @@
+use anyhow::Result;
+
+use crate::h264::slice::{Slice, SliceHeader};
+use crate::h264::{find_start_code, NalUnit, NalUnitHeader, NalUnitType, NalUnits, ParameterSets};
+
+/// A primary coded picture together with the non-VCL NAL units that belong to it,
+/// still in Annex-B format so it can be handed to the decoder as is.
+#[derive(Clone, Debug)]
+pub struct AccessUnit<'a> {
+    /// Offset of the access unit in the elementary stream
+    pub offset: usize,
+    pub data: &'a [u8],
+    /// Slices with offsets relative to `data`
+    pub slices: Vec<Slice>,
+}
+
+impl<'a> AccessUnit<'a> {
+    pub fn is_idr(&self) -> bool {
+        self.slices.iter().any(|slice| slice.header.idr_pic_flag)
+    }
+}
+
+/// Splits a raw H.264 elementary stream (ITU-T H.264 Annex B) into access units, following
+/// the first VCL NAL unit detection of 7.4.1.2.4. Parameter sets are collected on the way.
+pub struct AnnexBReader<'a> {
+    data: &'a [u8],
+    nal_units: NalUnits<'a>,
+    detector: AccessUnitDetector,
+}
+
+impl<'a> AnnexBReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            nal_units: NalUnits::new(data),
+            detector: AccessUnitDetector::default(),
+        }
+    }
+
+    /// Parameter sets seen so far in the stream.
+    pub fn parameter_sets(&self) -> &ParameterSets {
+        &self.detector.parameter_sets
+    }
+
+    /// Reads the NAL units up to and including the first SPS and PPS so the stream
+    /// dimensions are known before decoding starts.
+    pub fn read_parameter_sets(data: &[u8]) -> Result<ParameterSets> {
+        let mut parameter_sets = ParameterSets::default();
+
+        for nal in NalUnits::new(data) {
+            parameter_sets.add_nal(nal.data)?;
+            if parameter_sets.sps_count() > 0 && parameter_sets.pps_count() > 0 {
+                break;
+            }
+        }
+
+        Ok(parameter_sets)
+    }
+}
+
+impl<'a> Iterator for AnnexBReader<'a> {
+    type Item = Result<AccessUnit<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (start, end, slices) = match self.nal_units.next() {
+                Some(nal) => match self.detector.push(&nal) {
+                    Ok(Some((start, slices))) => (start, nal.offset, slices),
+                    Ok(None) => continue,
+                    Err(err) => return Some(Err(err)),
+                },
+                None => {
+                    let (start, slices) = self.detector.finish(self.data.len())?;
+                    (start, self.data.len(), slices)
+                }
+            };
+            return Some(Ok(AccessUnit {
+                offset: start,
+                data: &self.data[start..end],
+                slices,
+            }));
+        }
+    }
+}
+
+/// Splits an Annex-B stream that arrives in pieces into access units like [`AnnexBReader`],
+/// keeping only the access unit being assembled and the data pushed after it.
+#[derive(Default)]
+pub struct AnnexBSplitter {
+    /// The stream from the start of the current access unit on
+    buffer: Vec<u8>,
+    /// Offset of `buffer` in the stream
+    buffer_offset: usize,
+    /// Where in `buffer` to look for the next NAL unit
+    next_nal: usize,
+    /// Where in `buffer` to continue looking for the start code after that NAL unit
+    scanned: usize,
+    detector: AccessUnitDetector,
+}
+
+impl AnnexBSplitter {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The next complete access unit with its offset in the stream. A NAL unit is only
+    /// complete once the start code after it has been pushed, or at `end_of_stream`, which
+    /// also completes the last access unit.
+    pub fn next_access_unit(&mut self, end_of_stream: bool) -> Option<Result<(usize, Vec<u8>)>> {
+        loop {
+            // Each byte is searched once, except for the last two in case a start code is
+            // split between pushes
+            let from = self.scanned.max(self.next_nal + 3);
+            let end = match find_start_code(&self.buffer, from) {
+                Some(start_code) if self.buffer[start_code - 1] == 0 => start_code - 1,
+                Some(start_code) => start_code,
+                None if end_of_stream => self.buffer.len(),
+                None => {
+                    self.scanned = self.buffer.len().saturating_sub(2).max(from);
+                    return None;
+                }
+            };
+
+            let nal = NalUnits::new(&self.buffer[self.next_nal..end])
+                .next()
+                .map(|nal| NalUnit {
+                    offset: self.buffer_offset + self.next_nal + nal.offset,
+                    ..nal
+                });
+            self.next_nal = end;
+            let Some(nal) = nal else {
+                if end == self.buffer.len() {
+                    break;
+                }
+                continue;
+            };
+            match self.detector.push(&nal) {
+                Ok(Some(_)) => {
+                    let end = nal.offset;
+                    return Some(Ok(self.take_access_unit(end)));
+                }
+                Ok(None) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        if !end_of_stream {
+            return None;
+        }
+        let end = self.buffer_offset + self.buffer.len();
+        self.detector.finish(end)?;
+        Some(Ok(self.take_access_unit(end)))
+    }
+
+    /// Removes the current access unit, which ends at `end` in the stream, from the buffer.
+    fn take_access_unit(&mut self, end: usize) -> (usize, Vec<u8>) {
+        let offset = self.buffer_offset;
+        let size = end - offset;
+        let access_unit = self.buffer.drain(..size).collect();
+        self.buffer_offset = end;
+        self.next_nal -= size;
+        self.scanned = self.scanned.saturating_sub(size);
+        (offset, access_unit)
+    }
+}
+
+/// The first VCL NAL unit detection, fed a NAL unit at a time with its offset in the
+/// stream.
+#[derive(Default)]
+struct AccessUnitDetector {
+    parameter_sets: ParameterSets,
+    access_unit_start: usize,
+    slices: Vec<Slice>,
+    has_vcl: bool,
+    previous_slice: Option<SliceHeader>,
+}
+
+impl AccessUnitDetector {
+    /// Takes the slices of the current access unit, which ends at `end`, and returns them
+    /// with the offset it starts at.
+    fn take_access_unit(&mut self, end: usize) -> (usize, Vec<Slice>) {
+        let start = std::mem::replace(&mut self.access_unit_start, end);
+        self.has_vcl = false;
+        (start, std::mem::take(&mut self.slices))
+    }
+
+    /// The access unit still open at the end of the stream, if it has a slice.
+    fn finish(&mut self, end: usize) -> Option<(usize, Vec<Slice>)> {
+        (self.access_unit_start < end && self.has_vcl).then(|| self.take_access_unit(end))
+    }
+
+    /// Returns the access unit that `nal` ends by starting the next one.
+    fn push(&mut self, nal: &NalUnit) -> Result<Option<(usize, Vec<Slice>)>> {
+        let header = NalUnitHeader::parse(nal.data)?;
+        let mut access_unit = None;
+
+        match header.nal_unit_type {
+            NalUnitType::Slice | NalUnitType::IdrSlice => {
+                let slice_header = SliceHeader::parse(nal.data, &self.parameter_sets)?;
+
+                if self.has_vcl {
+                    let poc_type = self
+                        .parameter_sets
+                        .active(slice_header.pic_parameter_set_id)
+                        .map_or(0, |(_, sps)| sps.pic_order_cnt_type);
+                    let new_picture = slice_header.first_mb_in_slice == 0
+                        || self.previous_slice.as_ref().map_or(true, |previous| {
+                            slice_header.is_new_picture(previous, poc_type)
+                        });
+                    if new_picture {
+                        access_unit = Some(self.take_access_unit(nal.offset));
+                    }
+                }
+
+                self.slices.push(Slice {
+                    offset: nal.offset - self.access_unit_start,
+                    size: nal.start_code_len + nal.data.len(),
+                    header: slice_header.clone(),
+                });
+                self.previous_slice = Some(slice_header);
+                self.has_vcl = true;
+            }
+            NalUnitType::SliceDataA | NalUnitType::SliceDataB | NalUnitType::SliceDataC => {
+                self.has_vcl = true;
+            }
+            NalUnitType::AccessUnitDelimiter
+            | NalUnitType::Sps
+            | NalUnitType::Pps
+            | NalUnitType::Sei
+            | NalUnitType::PrefixNal
+            | NalUnitType::SubsetSps
+            | NalUnitType::Reserved(16..=18) => {
+                if self.has_vcl {
+                    access_unit = Some(self.take_access_unit(nal.offset));
+                }
+                self.parameter_sets.add_nal(nal.data)?;
+            }
+            _ => {}
+        }
+
+        Ok(access_unit)
+    }
+}
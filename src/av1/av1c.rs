@@ -0,0 +1,66 @@
+use anyhow::{anyhow, Result};
+
+use crate::av1::obu::{ObuType, Obus};
+use crate::av1::sequence::SequenceHeader;
+
+/// AV1CodecConfigurationRecord, the payload of the `av1C` box of `av01` sample entries
+/// (AV1 Codec ISO Media File Format Binding 2.3.3).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Av1CodecConfiguration {
+    pub seq_profile: u8,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: u8,
+    pub bit_depth: u8,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay_minus_one: Option<u8>,
+    /// configOBUs, the sequence header and metadata OBUs in the low overhead format
+    pub config_obus: Vec<u8>,
+}
+
+impl Av1CodecConfiguration {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(anyhow!("Truncated av1C of {} bytes", data.len()));
+        }
+        // marker and version
+        if data[0] != 0x81 {
+            return Err(anyhow!(
+                "Unsupported av1C marker and version {:#04x}",
+                data[0]
+            ));
+        }
+
+        let high_bitdepth = data[2] & 0x40 != 0;
+        let twelve_bit = data[2] & 0x20 != 0;
+        Ok(Self {
+            seq_profile: data[1] >> 5,
+            seq_level_idx_0: data[1] & 0x1f,
+            seq_tier_0: data[2] >> 7,
+            bit_depth: match (high_bitdepth, twelve_bit) {
+                (true, true) => 12,
+                (true, false) => 10,
+                _ => 8,
+            },
+            monochrome: data[2] & 0x10 != 0,
+            chroma_subsampling_x: data[2] & 0x08 != 0,
+            chroma_subsampling_y: data[2] & 0x04 != 0,
+            chroma_sample_position: data[2] & 0b11,
+            initial_presentation_delay_minus_one: (data[3] & 0x10 != 0).then_some(data[3] & 0xf),
+            config_obus: data[4..].to_vec(),
+        })
+    }
+
+    /// The sequence header among the config OBUs. The binding requires it to be present.
+    pub fn sequence_header(&self) -> Result<SequenceHeader> {
+        for obu in Obus::new(&self.config_obus) {
+            let obu = obu?;
+            if obu.header.obu_type == ObuType::SequenceHeader {
+                return SequenceHeader::parse(obu.data);
+            }
+        }
+        Err(anyhow!("av1C without a sequence header OBU"))
+    }
+}
@@ -0,0 +1,183 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::StdVideoDecodeAV1ReferenceInfo;
+
+use crate::av1::frame::{FilmGrain, FrameHeader, FrameType, LoopFilter, Segmentation};
+use crate::av1::NUM_REF_FRAMES;
+
+/// The state saved for a reference frame by the reference frame update process (7.20),
+/// as far as frame headers and the Vulkan reference information depend on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReferenceFrame {
+    /// DPB slot holding the reconstructed frame
+    pub slot: usize,
+    /// RefFrameId
+    pub frame_id: u32,
+    pub frame_type: FrameType,
+    /// RefOrderHint
+    pub order_hint: u8,
+    /// SavedOrderHints, the OrderHints of the frame indexed by reference frame name
+    pub saved_order_hints: [u8; NUM_REF_FRAMES],
+    /// RefFrameSignBias of the frame, bit i for reference frame name i
+    pub ref_frame_sign_bias: u8,
+    pub upscaled_width: u32,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub render_width: u32,
+    pub render_height: u32,
+    pub showable_frame: bool,
+    pub disable_frame_end_update_cdf: bool,
+    pub loop_filter: LoopFilter,
+    pub segmentation: Segmentation,
+    /// SavedGmParams
+    pub gm_params: [[i32; 6]; NUM_REF_FRAMES],
+    pub film_grain: FilmGrain,
+}
+
+impl ReferenceFrame {
+    fn new(header: &FrameHeader, slot: usize) -> Self {
+        Self {
+            slot,
+            frame_id: header.current_frame_id,
+            frame_type: header.frame_type,
+            order_hint: header.order_hint,
+            saved_order_hints: header.order_hints,
+            ref_frame_sign_bias: header.ref_frame_sign_bias,
+            upscaled_width: header.upscaled_width,
+            frame_width: header.frame_width,
+            frame_height: header.frame_height,
+            render_width: header.render_width,
+            render_height: header.render_height,
+            showable_frame: header.showable_frame,
+            disable_frame_end_update_cdf: header.disable_frame_end_update_cdf,
+            loop_filter: header.loop_filter.clone(),
+            segmentation: header.segmentation.clone(),
+            gm_params: header.global_motion.gm_params,
+            film_grain: header.film_grain.clone(),
+        }
+    }
+
+    /// The reference information of the frame in its DPB slot, as set up when it was
+    /// decoded by [`FrameHeader::to_std_reference_info`].
+    pub fn to_std_reference_info(&self) -> StdVideoDecodeAV1ReferenceInfo {
+        let mut info: StdVideoDecodeAV1ReferenceInfo = unsafe { mem::zeroed() };
+
+        info.flags
+            .set_disable_frame_end_update_cdf(self.disable_frame_end_update_cdf as u32);
+        info.flags
+            .set_segmentation_enabled(self.segmentation.segmentation_enabled as u32);
+        info.frame_type = self.frame_type.to_std() as u8;
+        info.RefFrameSignBias = self.ref_frame_sign_bias;
+        info.OrderHint = self.order_hint;
+        info.SavedOrderHints = self.saved_order_hints;
+
+        info
+    }
+}
+
+/// The eight AV1 reference frame slots (ref_frame_idx 0 to 7) mapped onto DPB slots.
+///
+/// A frame goes through [`Dpb::start_frame`], which picks a free slot for it, and
+/// [`Dpb::finish_frame`] once it is decoded, which stores it in the reference slots of
+/// refresh_frame_flags. Several reference slots may share one DPB slot.
+#[derive(Clone, Debug)]
+pub struct Dpb {
+    slot_count: usize,
+    references: [Option<ReferenceFrame>; NUM_REF_FRAMES],
+    /// Slots of frames still in use outside the DPB, e.g. waiting for output
+    held_slots: Vec<usize>,
+}
+
+impl Dpb {
+    /// `slot_count` must leave room for the current frame next to the eight references.
+    pub fn new(slot_count: usize) -> Self {
+        Self {
+            slot_count,
+            references: Default::default(),
+            held_slots: Vec::new(),
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    /// The reference frames by ref_frame_idx, `None` for slots nothing was stored in.
+    pub fn references(&self) -> &[Option<ReferenceFrame>; NUM_REF_FRAMES] {
+        &self.references
+    }
+
+    /// Drops every reference frame, e.g. before seeking. Decoding resumes at the next key
+    /// frame. Held slots stay held.
+    pub fn clear(&mut self) {
+        self.references = Default::default();
+    }
+
+    /// Keeps `slot` from being picked for new frames after its frame stops being a
+    /// reference, until [`Dpb::release_slot`].
+    pub fn hold_slot(&mut self, slot: usize) {
+        self.held_slots.push(slot);
+    }
+
+    pub fn release_slot(&mut self, slot: usize) {
+        if let Some(index) = self.held_slots.iter().position(|&held| held == slot) {
+            self.held_slots.swap_remove(index);
+        }
+    }
+
+    /// Prepares the decoding of a frame that is not a show_existing_frame, returning the
+    /// slot to reconstruct it into.
+    pub fn start_frame(&mut self, header: &FrameHeader) -> Result<usize> {
+        // Error resilient frames name the order hints they expect, other frames are lost
+        if let Some(ref_order_hint) = header.ref_order_hint {
+            for (reference, hint) in self.references.iter_mut().zip(ref_order_hint) {
+                if reference.as_ref().is_some_and(|r| r.order_hint != hint) {
+                    *reference = None;
+                }
+            }
+        }
+
+        if !header.frame_type.is_intra() {
+            for &idx in &header.ref_frame_idx {
+                if self.references[idx as usize].is_none() {
+                    return Err(anyhow!("Frame refers to empty reference slot {}", idx));
+                }
+            }
+        }
+
+        (0..self.slot_count)
+            .find(|&slot| {
+                !self.held_slots.contains(&slot)
+                    && !self
+                        .references
+                        .iter()
+                        .flatten()
+                        .any(|reference| reference.slot == slot)
+            })
+            .ok_or_else(|| anyhow!("No free DPB slot out of {}", self.slot_count))
+    }
+
+    /// Stores the decoded frame in the reference slots of its refresh_frame_flags.
+    pub fn finish_frame(&mut self, header: &FrameHeader, slot: usize) {
+        let frame = ReferenceFrame::new(header, slot);
+        for (i, reference) in self.references.iter_mut().enumerate() {
+            if header.refresh_frame_flags & (1 << i) != 0 {
+                *reference = Some(frame.clone());
+            }
+        }
+    }
+
+    /// Handles a show_existing_frame header, returning the slot of the frame to show.
+    /// Showing a key frame reloads it into every reference slot (7.21).
+    pub fn show_existing_frame(&mut self, header: &FrameHeader) -> Result<usize> {
+        let idx = header.frame_to_show_map_idx as usize;
+        let frame = self.references[idx]
+            .clone()
+            .ok_or_else(|| anyhow!("Showing empty reference slot {}", idx))?;
+        if frame.frame_type == FrameType::Key {
+            self.references = std::array::from_fn(|_| Some(frame.clone()));
+        }
+        Ok(frame.slot)
+    }
+}
@@ -0,0 +1,1748 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoAV1CDEF, StdVideoAV1FilmGrain, StdVideoAV1FrameRestorationType, StdVideoAV1FrameType,
+    StdVideoAV1GlobalMotion, StdVideoAV1InterpolationFilter, StdVideoAV1LoopFilter,
+    StdVideoAV1LoopRestoration, StdVideoAV1Quantization, StdVideoAV1Segmentation,
+    StdVideoAV1TileInfo, StdVideoAV1TxMode, StdVideoDecodeAV1PictureInfo,
+    StdVideoDecodeAV1ReferenceInfo,
+};
+
+use crate::av1::dpb::ReferenceFrame;
+use crate::av1::obu::ObuHeader;
+use crate::av1::sequence::{
+    ColorConfig, SequenceHeader, SELECT_INTEGER_MV, SELECT_SCREEN_CONTENT_TOOLS,
+};
+use crate::av1::{
+    read_ns, read_su, ALTREF2_FRAME, ALTREF_FRAME, BWDREF_FRAME, GOLDEN_FRAME, LAST_FRAME,
+    MAX_LOOP_FILTER, MAX_SEGMENTS, MAX_TILE_COLS, MAX_TILE_ROWS, NUM_REF_FRAMES, PRIMARY_REF_NONE,
+    REFS_PER_FRAME, SEG_LVL_MAX, TOTAL_REFS_PER_FRAME, WARPEDMODEL_PREC_BITS,
+};
+use crate::bitreader::BitReader;
+
+const MAX_TILE_WIDTH: u32 = 4096;
+const MAX_TILE_AREA: u32 = 4096 * 2304;
+const SUPERRES_NUM: u32 = 8;
+const SUPERRES_DENOM_MIN: u32 = 9;
+const RESTORATION_TILESIZE_MAX: u16 = 256;
+
+/// interpolation_filter value letting each block choose
+pub const SWITCHABLE: u8 = 4;
+
+/// TxMode values
+pub const ONLY_4X4: u8 = 0;
+pub const TX_MODE_LARGEST: u8 = 1;
+pub const TX_MODE_SELECT: u8 = 2;
+
+/// GmType values
+pub const IDENTITY: u8 = 0;
+pub const TRANSLATION: u8 = 1;
+pub const ROTZOOM: u8 = 2;
+pub const AFFINE: u8 = 3;
+
+// Segmentation_Feature_Bits, Segmentation_Feature_Signed and Segmentation_Feature_Max
+const SEGMENTATION_FEATURE_BITS: [u32; SEG_LVL_MAX] = [8, 6, 6, 6, 6, 3, 0, 0];
+const SEGMENTATION_FEATURE_SIGNED: [bool; SEG_LVL_MAX] =
+    [true, true, true, true, true, false, false, false];
+const SEGMENTATION_FEATURE_MAX: [i32; SEG_LVL_MAX] = [
+    255,
+    MAX_LOOP_FILTER,
+    MAX_LOOP_FILTER,
+    MAX_LOOP_FILTER,
+    MAX_LOOP_FILTER,
+    7,
+    0,
+    0,
+];
+
+/// loop_filter_ref_deltas set up by setup_past_independence()
+const DEFAULT_LOOP_FILTER_REF_DELTAS: [i8; TOTAL_REFS_PER_FRAME] = [1, 0, 0, 0, -1, 0, -1, -1];
+
+/// frame_type, Table 6.8.2
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameType {
+    #[default]
+    Key,
+    Inter,
+    IntraOnly,
+    Switch,
+}
+
+impl FrameType {
+    fn from_bits(value: u32) -> Self {
+        match value {
+            0 => FrameType::Key,
+            1 => FrameType::Inter,
+            2 => FrameType::IntraOnly,
+            _ => FrameType::Switch,
+        }
+    }
+
+    /// FrameIsIntra
+    pub fn is_intra(self) -> bool {
+        matches!(self, FrameType::Key | FrameType::IntraOnly)
+    }
+
+    pub fn to_std(self) -> StdVideoAV1FrameType {
+        self as StdVideoAV1FrameType
+    }
+}
+
+/// tile_info(), 5.9.15, with the tile boundaries of both spacing modes resolved
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TileInfo {
+    pub uniform_tile_spacing_flag: bool,
+    pub tile_cols_log2: u32,
+    pub tile_rows_log2: u32,
+    /// MiColStarts, TileCols + 1 entries ending with MiCols
+    pub mi_col_starts: Vec<u16>,
+    /// MiRowStarts, TileRows + 1 entries ending with MiRows
+    pub mi_row_starts: Vec<u16>,
+    pub width_in_sbs_minus_1: Vec<u16>,
+    pub height_in_sbs_minus_1: Vec<u16>,
+    pub context_update_tile_id: u16,
+    pub tile_size_bytes_minus_1: u8,
+}
+
+impl TileInfo {
+    fn parse(
+        reader: &mut BitReader,
+        sequence: &SequenceHeader,
+        mi_cols: u32,
+        mi_rows: u32,
+    ) -> Result<Self> {
+        let sb_shift = if sequence.use_128x128_superblock {
+            5
+        } else {
+            4
+        };
+        let sb_cols = (mi_cols + (1 << sb_shift) - 1) >> sb_shift;
+        let sb_rows = (mi_rows + (1 << sb_shift) - 1) >> sb_shift;
+        let sb_size = sb_shift + 2;
+        let max_tile_width_sb = MAX_TILE_WIDTH >> sb_size;
+        let mut max_tile_area_sb = MAX_TILE_AREA >> (2 * sb_size);
+        let min_log2_tile_cols = tile_log2(max_tile_width_sb, sb_cols);
+        let max_log2_tile_cols = tile_log2(1, sb_cols.min(MAX_TILE_COLS as u32));
+        let max_log2_tile_rows = tile_log2(1, sb_rows.min(MAX_TILE_ROWS as u32));
+        let min_log2_tiles = min_log2_tile_cols.max(tile_log2(max_tile_area_sb, sb_rows * sb_cols));
+
+        let mut info = TileInfo {
+            uniform_tile_spacing_flag: reader.read_flag()?,
+            ..Default::default()
+        };
+
+        if info.uniform_tile_spacing_flag {
+            info.tile_cols_log2 = min_log2_tile_cols;
+            while info.tile_cols_log2 < max_log2_tile_cols && reader.read_flag()? {
+                info.tile_cols_log2 += 1;
+            }
+            let tile_width_sb = (sb_cols + (1 << info.tile_cols_log2) - 1) >> info.tile_cols_log2;
+            for start_sb in (0..sb_cols).step_by(tile_width_sb as usize) {
+                info.mi_col_starts.push((start_sb << sb_shift) as u16);
+                info.width_in_sbs_minus_1
+                    .push((tile_width_sb.min(sb_cols - start_sb) - 1) as u16);
+            }
+
+            info.tile_rows_log2 = min_log2_tiles.saturating_sub(info.tile_cols_log2);
+            while info.tile_rows_log2 < max_log2_tile_rows && reader.read_flag()? {
+                info.tile_rows_log2 += 1;
+            }
+            let tile_height_sb = (sb_rows + (1 << info.tile_rows_log2) - 1) >> info.tile_rows_log2;
+            for start_sb in (0..sb_rows).step_by(tile_height_sb as usize) {
+                info.mi_row_starts.push((start_sb << sb_shift) as u16);
+                info.height_in_sbs_minus_1
+                    .push((tile_height_sb.min(sb_rows - start_sb) - 1) as u16);
+            }
+        } else {
+            let mut widest_tile_sb = 0;
+            let mut start_sb = 0;
+            while start_sb < sb_cols {
+                if info.mi_col_starts.len() == MAX_TILE_COLS {
+                    return Err(anyhow!("More than {} tile columns", MAX_TILE_COLS));
+                }
+                info.mi_col_starts.push((start_sb << sb_shift) as u16);
+                let max_width = (sb_cols - start_sb).min(max_tile_width_sb);
+                let width_in_sbs_minus_1 = read_ns(reader, max_width)?;
+                info.width_in_sbs_minus_1.push(width_in_sbs_minus_1 as u16);
+                widest_tile_sb = widest_tile_sb.max(width_in_sbs_minus_1 + 1);
+                start_sb += width_in_sbs_minus_1 + 1;
+            }
+            info.tile_cols_log2 = tile_log2(1, info.mi_col_starts.len() as u32);
+
+            if min_log2_tiles > 0 {
+                max_tile_area_sb = (sb_rows * sb_cols) >> (min_log2_tiles + 1);
+            } else {
+                max_tile_area_sb = sb_rows * sb_cols;
+            }
+            let max_tile_height_sb = (max_tile_area_sb / widest_tile_sb).max(1);
+            start_sb = 0;
+            while start_sb < sb_rows {
+                if info.mi_row_starts.len() == MAX_TILE_ROWS {
+                    return Err(anyhow!("More than {} tile rows", MAX_TILE_ROWS));
+                }
+                info.mi_row_starts.push((start_sb << sb_shift) as u16);
+                let max_height = (sb_rows - start_sb).min(max_tile_height_sb);
+                let height_in_sbs_minus_1 = read_ns(reader, max_height)?;
+                info.height_in_sbs_minus_1
+                    .push(height_in_sbs_minus_1 as u16);
+                start_sb += height_in_sbs_minus_1 + 1;
+            }
+            info.tile_rows_log2 = tile_log2(1, info.mi_row_starts.len() as u32);
+        }
+        info.mi_col_starts.push(mi_cols as u16);
+        info.mi_row_starts.push(mi_rows as u16);
+
+        if info.tile_cols_log2 > 0 || info.tile_rows_log2 > 0 {
+            info.context_update_tile_id =
+                reader.read_bits(info.tile_rows_log2 + info.tile_cols_log2)? as u16;
+            info.tile_size_bytes_minus_1 = reader.read_bits(2)? as u8;
+            if info.context_update_tile_id as usize >= info.num_tiles() {
+                return Err(anyhow!(
+                    "context_update_tile_id {} out of {} tiles",
+                    info.context_update_tile_id,
+                    info.num_tiles()
+                ));
+            }
+        }
+
+        Ok(info)
+    }
+
+    /// TileCols
+    pub fn tile_cols(&self) -> usize {
+        self.mi_col_starts.len().saturating_sub(1)
+    }
+
+    /// TileRows
+    pub fn tile_rows(&self) -> usize {
+        self.mi_row_starts.len().saturating_sub(1)
+    }
+
+    /// NumTiles
+    pub fn num_tiles(&self) -> usize {
+        self.tile_cols() * self.tile_rows()
+    }
+
+    /// TileSizeBytes
+    pub fn tile_size_bytes(&self) -> usize {
+        self.tile_size_bytes_minus_1 as usize + 1
+    }
+}
+
+/// tile_log2(), the smallest k with blkSize << k >= target
+fn tile_log2(blk_size: u32, target: u32) -> u32 {
+    let mut k = 0;
+    while (blk_size << k) < target {
+        k += 1;
+    }
+    k
+}
+
+/// quantization_params(), 5.9.12
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Quantization {
+    pub base_q_idx: u8,
+    pub delta_q_y_dc: i8,
+    pub delta_q_u_dc: i8,
+    pub delta_q_u_ac: i8,
+    pub delta_q_v_dc: i8,
+    pub delta_q_v_ac: i8,
+    pub diff_uv_delta: bool,
+    pub using_qmatrix: bool,
+    pub qm_y: u8,
+    pub qm_u: u8,
+    pub qm_v: u8,
+}
+
+impl Quantization {
+    fn parse(reader: &mut BitReader, color_config: &ColorConfig) -> Result<Self> {
+        // read_delta_q()
+        let read_delta_q = |reader: &mut BitReader| -> Result<i8> {
+            Ok(if reader.read_flag()? {
+                read_su(reader, 7)? as i8
+            } else {
+                0
+            })
+        };
+
+        let mut quantization = Quantization {
+            base_q_idx: reader.read_bits(8)? as u8,
+            delta_q_y_dc: read_delta_q(reader)?,
+            ..Default::default()
+        };
+        if color_config.num_planes() > 1 {
+            quantization.diff_uv_delta = color_config.separate_uv_delta_q && reader.read_flag()?;
+            quantization.delta_q_u_dc = read_delta_q(reader)?;
+            quantization.delta_q_u_ac = read_delta_q(reader)?;
+            if quantization.diff_uv_delta {
+                quantization.delta_q_v_dc = read_delta_q(reader)?;
+                quantization.delta_q_v_ac = read_delta_q(reader)?;
+            } else {
+                quantization.delta_q_v_dc = quantization.delta_q_u_dc;
+                quantization.delta_q_v_ac = quantization.delta_q_u_ac;
+            }
+        }
+        quantization.using_qmatrix = reader.read_flag()?;
+        if quantization.using_qmatrix {
+            quantization.qm_y = reader.read_bits(4)? as u8;
+            quantization.qm_u = reader.read_bits(4)? as u8;
+            quantization.qm_v = if color_config.separate_uv_delta_q {
+                reader.read_bits(4)? as u8
+            } else {
+                quantization.qm_u
+            };
+        }
+
+        Ok(quantization)
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1Quantization {
+        let mut quantization: StdVideoAV1Quantization = unsafe { mem::zeroed() };
+
+        quantization
+            .flags
+            .set_using_qmatrix(self.using_qmatrix as u32);
+        quantization
+            .flags
+            .set_diff_uv_delta(self.diff_uv_delta as u32);
+        quantization.base_q_idx = self.base_q_idx;
+        quantization.DeltaQYDc = self.delta_q_y_dc;
+        quantization.DeltaQUDc = self.delta_q_u_dc;
+        quantization.DeltaQUAc = self.delta_q_u_ac;
+        quantization.DeltaQVDc = self.delta_q_v_dc;
+        quantization.DeltaQVAc = self.delta_q_v_ac;
+        quantization.qm_y = self.qm_y;
+        quantization.qm_u = self.qm_u;
+        quantization.qm_v = self.qm_v;
+
+        quantization
+    }
+}
+
+/// segmentation_params(), 5.9.14. Without segmentation_update_data the features are the
+/// ones loaded from the primary reference frame.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Segmentation {
+    pub segmentation_enabled: bool,
+    pub segmentation_update_map: bool,
+    pub segmentation_temporal_update: bool,
+    pub segmentation_update_data: bool,
+    /// FeatureEnabled, bit j of entry i for feature j of segment i
+    pub feature_enabled: [u8; MAX_SEGMENTS],
+    pub feature_data: [[i16; SEG_LVL_MAX]; MAX_SEGMENTS],
+}
+
+impl Segmentation {
+    fn parse(
+        reader: &mut BitReader,
+        primary_ref_frame: u8,
+        previous: Option<&Segmentation>,
+    ) -> Result<Self> {
+        let mut segmentation = Segmentation {
+            segmentation_enabled: reader.read_flag()?,
+            ..Default::default()
+        };
+        if !segmentation.segmentation_enabled {
+            return Ok(segmentation);
+        }
+
+        if primary_ref_frame == PRIMARY_REF_NONE {
+            segmentation.segmentation_update_map = true;
+            segmentation.segmentation_update_data = true;
+        } else {
+            segmentation.segmentation_update_map = reader.read_flag()?;
+            if segmentation.segmentation_update_map {
+                segmentation.segmentation_temporal_update = reader.read_flag()?;
+            }
+            segmentation.segmentation_update_data = reader.read_flag()?;
+        }
+
+        if segmentation.segmentation_update_data {
+            for i in 0..MAX_SEGMENTS {
+                for j in 0..SEG_LVL_MAX {
+                    if !reader.read_flag()? {
+                        continue;
+                    }
+                    let bits = SEGMENTATION_FEATURE_BITS[j];
+                    let limit = SEGMENTATION_FEATURE_MAX[j];
+                    let value = if SEGMENTATION_FEATURE_SIGNED[j] {
+                        read_su(reader, 1 + bits)?.clamp(-limit, limit)
+                    } else {
+                        (reader.read_bits(bits)? as i32).min(limit)
+                    };
+                    segmentation.feature_enabled[i] |= 1 << j;
+                    segmentation.feature_data[i][j] = value as i16;
+                }
+            }
+        } else if let Some(previous) = previous {
+            segmentation.feature_enabled = previous.feature_enabled;
+            segmentation.feature_data = previous.feature_data;
+        }
+
+        Ok(segmentation)
+    }
+
+    /// get_qindex() ignoring the block level delta, 7.12.2
+    fn qindex(&self, base_q_idx: u8, segment_id: usize) -> i32 {
+        // SEG_LVL_ALT_Q
+        if self.segmentation_enabled && self.feature_enabled[segment_id] & 1 != 0 {
+            (base_q_idx as i32 + self.feature_data[segment_id][0] as i32).clamp(0, 255)
+        } else {
+            base_q_idx as i32
+        }
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1Segmentation {
+        StdVideoAV1Segmentation {
+            FeatureEnabled: self.feature_enabled,
+            FeatureData: self.feature_data,
+        }
+    }
+}
+
+/// loop_filter_params(), 5.9.11, with the deltas loaded from the primary reference frame
+/// where they are not updated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopFilter {
+    pub loop_filter_level: [u8; 4],
+    pub loop_filter_sharpness: u8,
+    pub loop_filter_delta_enabled: bool,
+    pub loop_filter_delta_update: bool,
+    /// update_ref_delta, bit i for reference frame name i
+    pub update_ref_delta: u8,
+    pub loop_filter_ref_deltas: [i8; TOTAL_REFS_PER_FRAME],
+    /// update_mode_delta, bit i for mode delta i
+    pub update_mode_delta: u8,
+    pub loop_filter_mode_deltas: [i8; 2],
+}
+
+impl Default for LoopFilter {
+    /// The deltas of setup_past_independence()
+    fn default() -> Self {
+        Self {
+            loop_filter_level: [0; 4],
+            loop_filter_sharpness: 0,
+            loop_filter_delta_enabled: true,
+            loop_filter_delta_update: false,
+            update_ref_delta: 0,
+            loop_filter_ref_deltas: DEFAULT_LOOP_FILTER_REF_DELTAS,
+            update_mode_delta: 0,
+            loop_filter_mode_deltas: [0; 2],
+        }
+    }
+}
+
+impl LoopFilter {
+    fn parse(
+        reader: &mut BitReader,
+        color_config: &ColorConfig,
+        previous: Option<&LoopFilter>,
+    ) -> Result<Self> {
+        let mut loop_filter = LoopFilter::default();
+        if let Some(previous) = previous {
+            loop_filter.loop_filter_ref_deltas = previous.loop_filter_ref_deltas;
+            loop_filter.loop_filter_mode_deltas = previous.loop_filter_mode_deltas;
+        }
+
+        loop_filter.loop_filter_level[0] = reader.read_bits(6)? as u8;
+        loop_filter.loop_filter_level[1] = reader.read_bits(6)? as u8;
+        if color_config.num_planes() > 1
+            && (loop_filter.loop_filter_level[0] != 0 || loop_filter.loop_filter_level[1] != 0)
+        {
+            loop_filter.loop_filter_level[2] = reader.read_bits(6)? as u8;
+            loop_filter.loop_filter_level[3] = reader.read_bits(6)? as u8;
+        }
+        loop_filter.loop_filter_sharpness = reader.read_bits(3)? as u8;
+        loop_filter.loop_filter_delta_enabled = reader.read_flag()?;
+        if loop_filter.loop_filter_delta_enabled {
+            loop_filter.loop_filter_delta_update = reader.read_flag()?;
+            if loop_filter.loop_filter_delta_update {
+                for i in 0..TOTAL_REFS_PER_FRAME {
+                    if reader.read_flag()? {
+                        loop_filter.update_ref_delta |= 1 << i;
+                        loop_filter.loop_filter_ref_deltas[i] = read_su(reader, 7)? as i8;
+                    }
+                }
+                for i in 0..2 {
+                    if reader.read_flag()? {
+                        loop_filter.update_mode_delta |= 1 << i;
+                        loop_filter.loop_filter_mode_deltas[i] = read_su(reader, 7)? as i8;
+                    }
+                }
+            }
+        }
+
+        Ok(loop_filter)
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1LoopFilter {
+        let mut loop_filter: StdVideoAV1LoopFilter = unsafe { mem::zeroed() };
+
+        loop_filter
+            .flags
+            .set_loop_filter_delta_enabled(self.loop_filter_delta_enabled as u32);
+        loop_filter
+            .flags
+            .set_loop_filter_delta_update(self.loop_filter_delta_update as u32);
+        loop_filter.loop_filter_level = self.loop_filter_level;
+        loop_filter.loop_filter_sharpness = self.loop_filter_sharpness;
+        loop_filter.update_ref_delta = self.update_ref_delta;
+        loop_filter.loop_filter_ref_deltas = self.loop_filter_ref_deltas;
+        loop_filter.update_mode_delta = self.update_mode_delta;
+        loop_filter.loop_filter_mode_deltas = self.loop_filter_mode_deltas;
+
+        loop_filter
+    }
+}
+
+/// cdef_params(), 5.9.19. The secondary strengths are kept as coded, 3 is not yet 4.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cdef {
+    pub cdef_damping_minus_3: u8,
+    pub cdef_bits: u8,
+    pub cdef_y_pri_strength: [u8; 8],
+    pub cdef_y_sec_strength: [u8; 8],
+    pub cdef_uv_pri_strength: [u8; 8],
+    pub cdef_uv_sec_strength: [u8; 8],
+}
+
+impl Cdef {
+    fn parse(reader: &mut BitReader, color_config: &ColorConfig) -> Result<Self> {
+        let mut cdef = Cdef {
+            cdef_damping_minus_3: reader.read_bits(2)? as u8,
+            cdef_bits: reader.read_bits(2)? as u8,
+            ..Default::default()
+        };
+        for i in 0..1 << cdef.cdef_bits {
+            cdef.cdef_y_pri_strength[i] = reader.read_bits(4)? as u8;
+            cdef.cdef_y_sec_strength[i] = reader.read_bits(2)? as u8;
+            if color_config.num_planes() > 1 {
+                cdef.cdef_uv_pri_strength[i] = reader.read_bits(4)? as u8;
+                cdef.cdef_uv_sec_strength[i] = reader.read_bits(2)? as u8;
+            }
+        }
+
+        Ok(cdef)
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1CDEF {
+        StdVideoAV1CDEF {
+            cdef_damping_minus_3: self.cdef_damping_minus_3,
+            cdef_bits: self.cdef_bits,
+            cdef_y_pri_strength: self.cdef_y_pri_strength,
+            cdef_y_sec_strength: self.cdef_y_sec_strength,
+            cdef_uv_pri_strength: self.cdef_uv_pri_strength,
+            cdef_uv_sec_strength: self.cdef_uv_sec_strength,
+        }
+    }
+}
+
+/// lr_params(), 5.9.20
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoopRestoration {
+    /// FrameRestorationType per plane, RESTORE_NONE, WIENER, SGRPROJ or SWITCHABLE
+    pub frame_restoration_type: [u8; 3],
+    /// LoopRestorationSize per plane in samples
+    pub loop_restoration_size: [u16; 3],
+    pub uses_lr: bool,
+    pub uses_chroma_lr: bool,
+}
+
+impl LoopRestoration {
+    fn parse(
+        reader: &mut BitReader,
+        sequence: &SequenceHeader,
+        color_config: &ColorConfig,
+    ) -> Result<Self> {
+        // Remap_Lr_Type
+        const REMAP_LR_TYPE: [u8; 4] = [0, 3, 1, 2];
+
+        let mut restoration = LoopRestoration::default();
+        for i in 0..color_config.num_planes() {
+            let lr_type = REMAP_LR_TYPE[reader.read_bits(2)? as usize];
+            restoration.frame_restoration_type[i] = lr_type;
+            if lr_type != 0 {
+                restoration.uses_lr = true;
+                restoration.uses_chroma_lr |= i > 0;
+            }
+        }
+
+        if restoration.uses_lr {
+            let mut lr_unit_shift = reader.read_bits(1)?;
+            if sequence.use_128x128_superblock {
+                lr_unit_shift += 1;
+            } else if lr_unit_shift == 1 {
+                lr_unit_shift += reader.read_bits(1)?;
+            }
+            let size = RESTORATION_TILESIZE_MAX >> (2 - lr_unit_shift);
+            let lr_uv_shift = if color_config.subsampling_x
+                && color_config.subsampling_y
+                && restoration.uses_chroma_lr
+            {
+                reader.read_bits(1)?
+            } else {
+                0
+            };
+            restoration.loop_restoration_size = [size, size >> lr_uv_shift, size >> lr_uv_shift];
+        }
+
+        Ok(restoration)
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1LoopRestoration {
+        StdVideoAV1LoopRestoration {
+            FrameRestorationType: self
+                .frame_restoration_type
+                .map(|lr_type| lr_type as StdVideoAV1FrameRestorationType),
+            LoopRestorationSize: self.loop_restoration_size,
+        }
+    }
+}
+
+/// global_motion_params(), 5.9.24, indexed by reference frame name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlobalMotion {
+    pub gm_type: [u8; NUM_REF_FRAMES],
+    pub gm_params: [[i32; 6]; NUM_REF_FRAMES],
+}
+
+impl Default for GlobalMotion {
+    /// Identity transforms, also the PrevGmParams of setup_past_independence()
+    fn default() -> Self {
+        let identity = [
+            0,
+            0,
+            1 << WARPEDMODEL_PREC_BITS,
+            0,
+            0,
+            1 << WARPEDMODEL_PREC_BITS,
+        ];
+        Self {
+            gm_type: [IDENTITY; NUM_REF_FRAMES],
+            gm_params: [identity; NUM_REF_FRAMES],
+        }
+    }
+}
+
+impl GlobalMotion {
+    fn parse(
+        reader: &mut BitReader,
+        allow_high_precision_mv: bool,
+        prev_gm_params: &[[i32; 6]; NUM_REF_FRAMES],
+    ) -> Result<Self> {
+        let mut motion = GlobalMotion::default();
+        for (reference, prev_gm_params) in prev_gm_params
+            .iter()
+            .enumerate()
+            .take(ALTREF_FRAME + 1)
+            .skip(LAST_FRAME)
+        {
+            let gm_type = if !reader.read_flag()? {
+                IDENTITY
+            } else if reader.read_flag()? {
+                ROTZOOM
+            } else if reader.read_flag()? {
+                TRANSLATION
+            } else {
+                AFFINE
+            };
+            motion.gm_type[reference] = gm_type;
+
+            let mut read_param = |idx: usize| {
+                read_global_param(
+                    reader,
+                    gm_type,
+                    idx,
+                    allow_high_precision_mv,
+                    prev_gm_params[idx],
+                )
+            };
+            let params = &mut motion.gm_params[reference];
+            if gm_type >= ROTZOOM {
+                params[2] = read_param(2)?;
+                params[3] = read_param(3)?;
+                if gm_type == AFFINE {
+                    params[4] = read_param(4)?;
+                    params[5] = read_param(5)?;
+                } else {
+                    params[4] = -params[3];
+                    params[5] = params[2];
+                }
+            }
+            if gm_type >= TRANSLATION {
+                params[0] = read_param(0)?;
+                params[1] = read_param(1)?;
+            }
+        }
+
+        Ok(motion)
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1GlobalMotion {
+        StdVideoAV1GlobalMotion {
+            GmType: self.gm_type,
+            gm_params: self.gm_params,
+        }
+    }
+}
+
+/// read_global_param(), 5.9.25
+fn read_global_param(
+    reader: &mut BitReader,
+    gm_type: u8,
+    idx: usize,
+    allow_high_precision_mv: bool,
+    prev_gm_param: i32,
+) -> Result<i32> {
+    // GM_ABS_ALPHA_BITS, GM_ALPHA_PREC_BITS and the translation variants
+    let (abs_bits, prec_bits) = match (idx, gm_type) {
+        (0 | 1, TRANSLATION) => (
+            9 - !allow_high_precision_mv as u32,
+            3 - !allow_high_precision_mv as u32,
+        ),
+        (0 | 1, _) => (12, 6),
+        _ => (12, 15),
+    };
+    let prec_diff = WARPEDMODEL_PREC_BITS - prec_bits;
+    let (round, sub) = if idx % 3 == 2 {
+        (1 << WARPEDMODEL_PREC_BITS, 1 << prec_bits)
+    } else {
+        (0, 0)
+    };
+    let mx = 1 << abs_bits;
+    let r = (prev_gm_param >> prec_diff) - sub;
+
+    // decode_signed_subexp_with_ref(-mx, mx + 1, r)
+    let value = decode_unsigned_subexp_with_ref(reader, 2 * mx + 1, r + mx)? - mx;
+    Ok((value << prec_diff) + round)
+}
+
+/// decode_unsigned_subexp_with_ref(), 5.9.27
+fn decode_unsigned_subexp_with_ref(reader: &mut BitReader, mx: i32, r: i32) -> Result<i32> {
+    let v = decode_subexp(reader, mx)?;
+    Ok(if (r << 1) <= mx {
+        inverse_recenter(r, v)
+    } else {
+        mx - 1 - inverse_recenter(mx - 1 - r, v)
+    })
+}
+
+/// decode_subexp(), 5.9.28
+fn decode_subexp(reader: &mut BitReader, num_syms: i32) -> Result<i32> {
+    let mut i = 0;
+    let mut mk = 0;
+    let k = 3;
+    loop {
+        let b2 = if i > 0 { k + i - 1 } else { k };
+        let a = 1 << b2;
+        if num_syms <= mk + 3 * a {
+            return Ok(read_ns(reader, (num_syms - mk) as u32)? as i32 + mk);
+        }
+        if !reader.read_flag()? {
+            return Ok(reader.read_bits(b2 as u32)? as i32 + mk);
+        }
+        i += 1;
+        mk += a;
+    }
+}
+
+/// inverse_recenter(), 5.9.29
+fn inverse_recenter(r: i32, v: i32) -> i32 {
+    if v > 2 * r {
+        v
+    } else if v & 1 == 1 {
+        r - ((v + 1) >> 1)
+    } else {
+        r + (v >> 1)
+    }
+}
+
+/// film_grain_params(), 5.9.30. `apply_grain` is false when the frame has no film grain.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilmGrain {
+    pub apply_grain: bool,
+    pub grain_seed: u16,
+    pub update_grain: bool,
+    pub film_grain_params_ref_idx: u8,
+    pub num_y_points: u8,
+    pub point_y_value: [u8; 14],
+    pub point_y_scaling: [u8; 14],
+    pub chroma_scaling_from_luma: bool,
+    pub num_cb_points: u8,
+    pub point_cb_value: [u8; 10],
+    pub point_cb_scaling: [u8; 10],
+    pub num_cr_points: u8,
+    pub point_cr_value: [u8; 10],
+    pub point_cr_scaling: [u8; 10],
+    pub grain_scaling_minus_8: u8,
+    pub ar_coeff_lag: u8,
+    pub ar_coeffs_y_plus_128: [u8; 24],
+    pub ar_coeffs_cb_plus_128: [u8; 25],
+    pub ar_coeffs_cr_plus_128: [u8; 25],
+    pub ar_coeff_shift_minus_6: u8,
+    pub grain_scale_shift: u8,
+    pub cb_mult: u8,
+    pub cb_luma_mult: u8,
+    pub cb_offset: u16,
+    pub cr_mult: u8,
+    pub cr_luma_mult: u8,
+    pub cr_offset: u16,
+    pub overlap_flag: bool,
+    pub clip_to_restricted_range: bool,
+}
+
+impl FilmGrain {
+    fn parse(
+        reader: &mut BitReader,
+        color_config: &ColorConfig,
+        frame_type: FrameType,
+        references: &[Option<ReferenceFrame>; NUM_REF_FRAMES],
+    ) -> Result<Self> {
+        if !reader.read_flag()? {
+            return Ok(FilmGrain::default());
+        }
+
+        let grain_seed = reader.read_bits(16)? as u16;
+        let update_grain = frame_type != FrameType::Inter || reader.read_flag()?;
+        if !update_grain {
+            // load_grain_params() keeping the new seed
+            let film_grain_params_ref_idx = reader.read_bits(3)? as u8;
+            let reference = references[film_grain_params_ref_idx as usize]
+                .as_ref()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Film grain loaded from empty reference slot {}",
+                        film_grain_params_ref_idx
+                    )
+                })?;
+            return Ok(FilmGrain {
+                apply_grain: true,
+                grain_seed,
+                update_grain,
+                film_grain_params_ref_idx,
+                ..reference.film_grain.clone()
+            });
+        }
+
+        let mut grain = FilmGrain {
+            apply_grain: true,
+            grain_seed,
+            update_grain,
+            ..Default::default()
+        };
+
+        grain.num_y_points = reader.read_bits(4)? as u8;
+        if grain.num_y_points > 14 {
+            return Err(anyhow!("num_y_points {} exceeds 14", grain.num_y_points));
+        }
+        for i in 0..grain.num_y_points as usize {
+            grain.point_y_value[i] = reader.read_bits(8)? as u8;
+            grain.point_y_scaling[i] = reader.read_bits(8)? as u8;
+        }
+        grain.chroma_scaling_from_luma = !color_config.mono_chrome && reader.read_flag()?;
+        let chroma_points_implied = color_config.mono_chrome
+            || grain.chroma_scaling_from_luma
+            || (color_config.subsampling_x
+                && color_config.subsampling_y
+                && grain.num_y_points == 0);
+        if !chroma_points_implied {
+            grain.num_cb_points = reader.read_bits(4)? as u8;
+            if grain.num_cb_points > 10 {
+                return Err(anyhow!("num_cb_points {} exceeds 10", grain.num_cb_points));
+            }
+            for i in 0..grain.num_cb_points as usize {
+                grain.point_cb_value[i] = reader.read_bits(8)? as u8;
+                grain.point_cb_scaling[i] = reader.read_bits(8)? as u8;
+            }
+            grain.num_cr_points = reader.read_bits(4)? as u8;
+            if grain.num_cr_points > 10 {
+                return Err(anyhow!("num_cr_points {} exceeds 10", grain.num_cr_points));
+            }
+            for i in 0..grain.num_cr_points as usize {
+                grain.point_cr_value[i] = reader.read_bits(8)? as u8;
+                grain.point_cr_scaling[i] = reader.read_bits(8)? as u8;
+            }
+        }
+
+        grain.grain_scaling_minus_8 = reader.read_bits(2)? as u8;
+        grain.ar_coeff_lag = reader.read_bits(2)? as u8;
+        let lag = grain.ar_coeff_lag as usize;
+        let num_pos_luma = 2 * lag * (lag + 1);
+        let num_pos_chroma = if grain.num_y_points > 0 {
+            for i in 0..num_pos_luma {
+                grain.ar_coeffs_y_plus_128[i] = reader.read_bits(8)? as u8;
+            }
+            num_pos_luma + 1
+        } else {
+            num_pos_luma
+        };
+        if grain.chroma_scaling_from_luma || grain.num_cb_points > 0 {
+            for i in 0..num_pos_chroma {
+                grain.ar_coeffs_cb_plus_128[i] = reader.read_bits(8)? as u8;
+            }
+        }
+        if grain.chroma_scaling_from_luma || grain.num_cr_points > 0 {
+            for i in 0..num_pos_chroma {
+                grain.ar_coeffs_cr_plus_128[i] = reader.read_bits(8)? as u8;
+            }
+        }
+        grain.ar_coeff_shift_minus_6 = reader.read_bits(2)? as u8;
+        grain.grain_scale_shift = reader.read_bits(2)? as u8;
+        if grain.num_cb_points > 0 {
+            grain.cb_mult = reader.read_bits(8)? as u8;
+            grain.cb_luma_mult = reader.read_bits(8)? as u8;
+            grain.cb_offset = reader.read_bits(9)? as u16;
+        }
+        if grain.num_cr_points > 0 {
+            grain.cr_mult = reader.read_bits(8)? as u8;
+            grain.cr_luma_mult = reader.read_bits(8)? as u8;
+            grain.cr_offset = reader.read_bits(9)? as u16;
+        }
+        grain.overlap_flag = reader.read_flag()?;
+        grain.clip_to_restricted_range = reader.read_flag()?;
+
+        Ok(grain)
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1FilmGrain {
+        let mut grain: StdVideoAV1FilmGrain = unsafe { mem::zeroed() };
+
+        grain
+            .flags
+            .set_chroma_scaling_from_luma(self.chroma_scaling_from_luma as u32);
+        grain.flags.set_overlap_flag(self.overlap_flag as u32);
+        grain
+            .flags
+            .set_clip_to_restricted_range(self.clip_to_restricted_range as u32);
+        grain.flags.set_update_grain(self.update_grain as u32);
+        grain.grain_scaling_minus_8 = self.grain_scaling_minus_8;
+        grain.ar_coeff_lag = self.ar_coeff_lag;
+        grain.ar_coeff_shift_minus_6 = self.ar_coeff_shift_minus_6;
+        grain.grain_scale_shift = self.grain_scale_shift;
+        grain.grain_seed = self.grain_seed;
+        grain.film_grain_params_ref_idx = self.film_grain_params_ref_idx;
+        grain.num_y_points = self.num_y_points;
+        grain.point_y_value = self.point_y_value;
+        grain.point_y_scaling = self.point_y_scaling;
+        grain.num_cb_points = self.num_cb_points;
+        grain.point_cb_value = self.point_cb_value;
+        grain.point_cb_scaling = self.point_cb_scaling;
+        grain.num_cr_points = self.num_cr_points;
+        grain.point_cr_value = self.point_cr_value;
+        grain.point_cr_scaling = self.point_cr_scaling;
+        grain.ar_coeffs_y_plus_128 = self.ar_coeffs_y_plus_128.map(|c| c as i8);
+        grain.ar_coeffs_cb_plus_128 = self.ar_coeffs_cb_plus_128.map(|c| c as i8);
+        grain.ar_coeffs_cr_plus_128 = self.ar_coeffs_cr_plus_128.map(|c| c as i8);
+        grain.cb_mult = self.cb_mult;
+        grain.cb_luma_mult = self.cb_luma_mult;
+        grain.cb_offset = self.cb_offset;
+        grain.cr_mult = self.cr_mult;
+        grain.cr_luma_mult = self.cr_luma_mult;
+        grain.cr_offset = self.cr_offset;
+
+        grain
+    }
+}
+
+/// uncompressed_header(), 5.9.2, with the values derived while parsing it. Only the
+/// syntax of a show_existing_frame header is filled in for one, together with what the
+/// frame loading process (7.21) takes from the shown frame.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub show_existing_frame: bool,
+    pub frame_to_show_map_idx: u8,
+    pub frame_type: FrameType,
+    pub show_frame: bool,
+    pub showable_frame: bool,
+    pub error_resilient_mode: bool,
+    pub disable_cdf_update: bool,
+    pub allow_screen_content_tools: bool,
+    pub force_integer_mv: bool,
+    pub current_frame_id: u32,
+    pub frame_size_override_flag: bool,
+    /// OrderHint
+    pub order_hint: u8,
+    pub primary_ref_frame: u8,
+    pub buffer_removal_time_present_flag: bool,
+    pub refresh_frame_flags: u8,
+    /// ref_order_hint of error resilient frames, the order hints the reference slots
+    /// are expected to hold
+    pub ref_order_hint: Option<[u8; NUM_REF_FRAMES]>,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub upscaled_width: u32,
+    pub render_width: u32,
+    pub render_height: u32,
+    pub use_superres: bool,
+    pub coded_denom: u8,
+    pub render_and_frame_size_different: bool,
+    pub allow_intrabc: bool,
+    pub frame_refs_short_signaling: bool,
+    /// ref_frame_idx, the reference slot of reference frames LAST_FRAME to ALTREF_FRAME
+    pub ref_frame_idx: [u8; REFS_PER_FRAME],
+    /// expectedFrameId of reference frames LAST_FRAME to ALTREF_FRAME
+    pub expected_frame_id: [u32; REFS_PER_FRAME],
+    pub allow_high_precision_mv: bool,
+    pub is_filter_switchable: bool,
+    pub interpolation_filter: u8,
+    pub is_motion_mode_switchable: bool,
+    pub use_ref_frame_mvs: bool,
+    /// OrderHints, indexed by reference frame name
+    pub order_hints: [u8; NUM_REF_FRAMES],
+    /// RefFrameSignBias, bit i for reference frame name i
+    pub ref_frame_sign_bias: u8,
+    pub disable_frame_end_update_cdf: bool,
+    pub tile_info: TileInfo,
+    pub quantization: Quantization,
+    pub segmentation: Segmentation,
+    pub delta_q_present: bool,
+    pub delta_q_res: u8,
+    pub delta_lf_present: bool,
+    pub delta_lf_res: u8,
+    pub delta_lf_multi: bool,
+    /// CodedLossless
+    pub coded_lossless: bool,
+    /// AllLossless
+    pub all_lossless: bool,
+    pub loop_filter: LoopFilter,
+    pub cdef: Cdef,
+    pub loop_restoration: LoopRestoration,
+    /// TxMode
+    pub tx_mode: u8,
+    pub reference_select: bool,
+    pub skip_mode_present: bool,
+    /// SkipModeFrame
+    pub skip_mode_frame: [u8; 2],
+    pub allow_warped_motion: bool,
+    pub reduced_tx_set: bool,
+    pub global_motion: GlobalMotion,
+    pub film_grain: FilmGrain,
+    /// Size of the header in bytes, where the tile group of a frame OBU starts
+    pub header_bytes: usize,
+}
+
+impl FrameHeader {
+    /// Parses the payload of a frame header or frame OBU, given the reference slots as
+    /// they are before the frame.
+    pub fn parse(
+        data: &[u8],
+        sequence: &SequenceHeader,
+        obu_header: &ObuHeader,
+        references: &[Option<ReferenceFrame>; NUM_REF_FRAMES],
+    ) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+        let reader = &mut reader;
+        let mut header = FrameHeader::default();
+
+        let id_len = sequence.frame_id_length();
+        let all_frames = u8::MAX;
+
+        // temporal_point_info()
+        let skip_temporal_point_info = |reader: &mut BitReader| -> Result<()> {
+            if let (Some(timing_info), Some(decoder_model_info)) =
+                (&sequence.timing_info, &sequence.decoder_model_info)
+            {
+                if !timing_info.equal_picture_interval {
+                    reader.skip_bits(
+                        decoder_model_info.frame_presentation_time_length_minus_1 as usize + 1,
+                    )?;
+                }
+            }
+            Ok(())
+        };
+
+        if sequence.reduced_still_picture_header {
+            header.frame_type = FrameType::Key;
+            header.show_frame = true;
+        } else {
+            header.show_existing_frame = reader.read_flag()?;
+            if header.show_existing_frame {
+                header.frame_to_show_map_idx = reader.read_bits(3)? as u8;
+                skip_temporal_point_info(reader)?;
+                if sequence.frame_id_numbers_present_flag {
+                    // display_frame_id
+                    reader.skip_bits(id_len as usize)?;
+                }
+
+                let idx = header.frame_to_show_map_idx as usize;
+                let frame = references[idx]
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Showing empty reference slot {}", idx))?;
+                header.show_frame = true;
+                header.frame_type = frame.frame_type;
+                if frame.frame_type == FrameType::Key {
+                    header.refresh_frame_flags = all_frames;
+                }
+                header.current_frame_id = frame.frame_id;
+                header.order_hint = frame.order_hint;
+                header.order_hints = frame.saved_order_hints;
+                header.upscaled_width = frame.upscaled_width;
+                header.frame_width = frame.frame_width;
+                header.frame_height = frame.frame_height;
+                header.render_width = frame.render_width;
+                header.render_height = frame.render_height;
+                if sequence.film_grain_params_present {
+                    header.film_grain = frame.film_grain.clone();
+                }
+                header.header_bytes = reader.position().div_ceil(8);
+                return Ok(header);
+            }
+
+            header.frame_type = FrameType::from_bits(reader.read_bits(2)?);
+            header.show_frame = reader.read_flag()?;
+            if header.show_frame {
+                skip_temporal_point_info(reader)?;
+            }
+            header.showable_frame = if header.show_frame {
+                header.frame_type != FrameType::Key
+            } else {
+                reader.read_flag()?
+            };
+            header.error_resilient_mode = header.frame_type == FrameType::Switch
+                || (header.frame_type == FrameType::Key && header.show_frame)
+                || reader.read_flag()?;
+        }
+
+        let frame_is_intra = header.frame_type.is_intra();
+
+        // RefValid and RefOrderHint as this frame sees them
+        let mut ref_valid: [bool; NUM_REF_FRAMES] =
+            std::array::from_fn(|i| references[i].is_some());
+        let mut ref_order_hint: [u8; NUM_REF_FRAMES] =
+            std::array::from_fn(|i| references[i].as_ref().map_or(0, |r| r.order_hint));
+        if header.frame_type == FrameType::Key && header.show_frame {
+            ref_valid = [false; NUM_REF_FRAMES];
+            ref_order_hint = [0; NUM_REF_FRAMES];
+        }
+
+        header.disable_cdf_update = reader.read_flag()?;
+        header.allow_screen_content_tools =
+            if sequence.seq_force_screen_content_tools == SELECT_SCREEN_CONTENT_TOOLS {
+                reader.read_flag()?
+            } else {
+                sequence.seq_force_screen_content_tools == 1
+            };
+        if header.allow_screen_content_tools {
+            header.force_integer_mv = if sequence.seq_force_integer_mv == SELECT_INTEGER_MV {
+                reader.read_flag()?
+            } else {
+                sequence.seq_force_integer_mv == 1
+            };
+        }
+        if frame_is_intra {
+            header.force_integer_mv = true;
+        }
+
+        if sequence.frame_id_numbers_present_flag {
+            header.current_frame_id = reader.read_bits(id_len)?;
+
+            // mark_ref_frames()
+            let diff_len = sequence.delta_frame_id_length_minus_2 as u32 + 2;
+            let current = header.current_frame_id as i64;
+            for (valid, reference) in ref_valid.iter_mut().zip(references) {
+                let Some(reference) = reference else {
+                    continue;
+                };
+                let ref_frame_id = reference.frame_id as i64;
+                let too_old = if current > 1 << diff_len {
+                    ref_frame_id > current || ref_frame_id < current - (1 << diff_len)
+                } else {
+                    ref_frame_id > current
+                        && ref_frame_id < (1 << id_len) + current - (1 << diff_len)
+                };
+                if too_old {
+                    *valid = false;
+                }
+            }
+        }
+
+        header.frame_size_override_flag = if header.frame_type == FrameType::Switch {
+            true
+        } else {
+            !sequence.reduced_still_picture_header && reader.read_flag()?
+        };
+        header.order_hint = reader.read_bits(sequence.order_hint_bits as u32)? as u8;
+        header.primary_ref_frame = if frame_is_intra || header.error_resilient_mode {
+            PRIMARY_REF_NONE
+        } else {
+            reader.read_bits(3)? as u8
+        };
+
+        if let Some(decoder_model_info) = &sequence.decoder_model_info {
+            header.buffer_removal_time_present_flag = reader.read_flag()?;
+            if header.buffer_removal_time_present_flag {
+                for operating_point in &sequence.operating_points {
+                    if !operating_point.decoder_model_present_for_this_op {
+                        continue;
+                    }
+                    let idc = operating_point.operating_point_idc;
+                    let in_temporal_layer = (idc >> obu_header.temporal_id) & 1 == 1;
+                    let in_spatial_layer = (idc >> (obu_header.spatial_id + 8)) & 1 == 1;
+                    if idc == 0 || (in_temporal_layer && in_spatial_layer) {
+                        // buffer_removal_time
+                        reader.skip_bits(
+                            decoder_model_info.buffer_removal_time_length_minus_1 as usize + 1,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        header.refresh_frame_flags = if header.frame_type == FrameType::Switch
+            || (header.frame_type == FrameType::Key && header.show_frame)
+        {
+            all_frames
+        } else {
+            reader.read_bits(8)? as u8
+        };
+
+        if (!frame_is_intra || header.refresh_frame_flags != all_frames)
+            && header.error_resilient_mode
+            && sequence.enable_order_hint
+        {
+            let mut expected = [0; NUM_REF_FRAMES];
+            for i in 0..NUM_REF_FRAMES {
+                expected[i] = reader.read_bits(sequence.order_hint_bits as u32)? as u8;
+                if expected[i] != ref_order_hint[i] || !ref_valid[i] {
+                    ref_order_hint[i] = expected[i];
+                    ref_valid[i] = false;
+                }
+            }
+            header.ref_order_hint = Some(expected);
+        }
+
+        if frame_is_intra {
+            header.parse_frame_size(reader, sequence)?;
+            header.parse_render_size(reader)?;
+            if header.allow_screen_content_tools && header.upscaled_width == header.frame_width {
+                header.allow_intrabc = reader.read_flag()?;
+            }
+        } else {
+            if sequence.enable_order_hint {
+                header.frame_refs_short_signaling = reader.read_flag()?;
+            }
+            if header.frame_refs_short_signaling {
+                let last_frame_idx = reader.read_bits(3)? as u8;
+                let gold_frame_idx = reader.read_bits(3)? as u8;
+                header.ref_frame_idx = set_frame_refs(
+                    sequence,
+                    header.order_hint,
+                    &ref_order_hint,
+                    last_frame_idx,
+                    gold_frame_idx,
+                );
+            }
+            for i in 0..REFS_PER_FRAME {
+                if !header.frame_refs_short_signaling {
+                    header.ref_frame_idx[i] = reader.read_bits(3)? as u8;
+                }
+                if sequence.frame_id_numbers_present_flag {
+                    let delta_frame_id_minus_1 =
+                        reader.read_bits(sequence.delta_frame_id_length_minus_2 as u32 + 2)?;
+                    header.expected_frame_id[i] = ((header.current_frame_id as u64 + (1 << id_len)
+                        - (delta_frame_id_minus_1 as u64 + 1))
+                        % (1 << id_len)) as u32;
+                }
+            }
+
+            if header.frame_size_override_flag && !header.error_resilient_mode {
+                header.parse_frame_size_with_refs(reader, sequence, references)?;
+            } else {
+                header.parse_frame_size(reader, sequence)?;
+                header.parse_render_size(reader)?;
+            }
+
+            header.allow_high_precision_mv = !header.force_integer_mv && reader.read_flag()?;
+            // read_interpolation_filter()
+            header.is_filter_switchable = reader.read_flag()?;
+            header.interpolation_filter = if header.is_filter_switchable {
+                SWITCHABLE
+            } else {
+                reader.read_bits(2)? as u8
+            };
+            header.is_motion_mode_switchable = reader.read_flag()?;
+            header.use_ref_frame_mvs = !header.error_resilient_mode
+                && sequence.enable_ref_frame_mvs
+                && reader.read_flag()?;
+
+            for i in 0..REFS_PER_FRAME {
+                let hint = ref_order_hint[header.ref_frame_idx[i] as usize];
+                header.order_hints[LAST_FRAME + i] = hint;
+                if sequence.relative_dist(hint, header.order_hint) > 0 {
+                    header.ref_frame_sign_bias |= 1 << (LAST_FRAME + i);
+                }
+            }
+        }
+
+        header.disable_frame_end_update_cdf = sequence.reduced_still_picture_header
+            || header.disable_cdf_update
+            || reader.read_flag()?;
+
+        // load_previous() or setup_past_independence()
+        let previous = if header.primary_ref_frame == PRIMARY_REF_NONE {
+            None
+        } else {
+            let idx = header.ref_frame_idx[header.primary_ref_frame as usize] as usize;
+            Some(references[idx].as_ref().ok_or_else(|| {
+                anyhow!("primary_ref_frame refers to empty reference slot {}", idx)
+            })?)
+        };
+
+        let mi_cols = 2 * ((header.frame_width + 7) >> 3);
+        let mi_rows = 2 * ((header.frame_height + 7) >> 3);
+        header.tile_info = TileInfo::parse(reader, sequence, mi_cols, mi_rows)?;
+        header.quantization = Quantization::parse(reader, &sequence.color_config)?;
+        header.segmentation = Segmentation::parse(
+            reader,
+            header.primary_ref_frame,
+            previous.map(|frame| &frame.segmentation),
+        )?;
+
+        // delta_q_params() and delta_lf_params()
+        if header.quantization.base_q_idx > 0 {
+            header.delta_q_present = reader.read_flag()?;
+        }
+        if header.delta_q_present {
+            header.delta_q_res = reader.read_bits(2)? as u8;
+            if !header.allow_intrabc {
+                header.delta_lf_present = reader.read_flag()?;
+            }
+            if header.delta_lf_present {
+                header.delta_lf_res = reader.read_bits(2)? as u8;
+                header.delta_lf_multi = reader.read_flag()?;
+            }
+        }
+
+        let quantization = &header.quantization;
+        let no_delta_q = quantization.delta_q_y_dc == 0
+            && quantization.delta_q_u_ac == 0
+            && quantization.delta_q_u_dc == 0
+            && quantization.delta_q_v_ac == 0
+            && quantization.delta_q_v_dc == 0;
+        header.coded_lossless = no_delta_q
+            && (0..MAX_SEGMENTS).all(|segment_id| {
+                header
+                    .segmentation
+                    .qindex(quantization.base_q_idx, segment_id)
+                    == 0
+            });
+        header.all_lossless = header.coded_lossless && header.frame_width == header.upscaled_width;
+
+        let color_config = &sequence.color_config;
+        if header.coded_lossless || header.allow_intrabc {
+            header.loop_filter = LoopFilter::default();
+            header.loop_filter.loop_filter_delta_enabled = false;
+        } else {
+            header.loop_filter = LoopFilter::parse(
+                reader,
+                color_config,
+                previous.map(|frame| &frame.loop_filter),
+            )?;
+        }
+        if !header.coded_lossless && !header.allow_intrabc && sequence.enable_cdef {
+            header.cdef = Cdef::parse(reader, color_config)?;
+        }
+        if !header.all_lossless && !header.allow_intrabc && sequence.enable_restoration {
+            header.loop_restoration = LoopRestoration::parse(reader, sequence, color_config)?;
+        }
+
+        // read_tx_mode()
+        header.tx_mode = if header.coded_lossless {
+            ONLY_4X4
+        } else if reader.read_flag()? {
+            TX_MODE_SELECT
+        } else {
+            TX_MODE_LARGEST
+        };
+
+        header.reference_select = !frame_is_intra && reader.read_flag()?;
+
+        if let Some(skip_mode_frame) =
+            header.skip_mode_frames(sequence, frame_is_intra, &ref_order_hint)
+        {
+            header.skip_mode_present = reader.read_flag()?;
+            header.skip_mode_frame = skip_mode_frame;
+        }
+
+        header.allow_warped_motion = !frame_is_intra
+            && !header.error_resilient_mode
+            && sequence.enable_warped_motion
+            && reader.read_flag()?;
+        header.reduced_tx_set = reader.read_flag()?;
+
+        if !frame_is_intra {
+            let default_motion = GlobalMotion::default();
+            let prev_gm_params = match previous {
+                Some(frame) => &frame.gm_params,
+                None => &default_motion.gm_params,
+            };
+            header.global_motion =
+                GlobalMotion::parse(reader, header.allow_high_precision_mv, prev_gm_params)?;
+        }
+
+        if sequence.film_grain_params_present && (header.show_frame || header.showable_frame) {
+            header.film_grain =
+                FilmGrain::parse(reader, color_config, header.frame_type, references)?;
+        }
+
+        header.header_bytes = reader.position().div_ceil(8);
+        Ok(header)
+    }
+
+    /// frame_size() including superres_params() and compute_image_size()
+    fn parse_frame_size(
+        &mut self,
+        reader: &mut BitReader,
+        sequence: &SequenceHeader,
+    ) -> Result<()> {
+        if self.frame_size_override_flag {
+            self.frame_width = reader.read_bits(sequence.frame_width_bits_minus_1 as u32 + 1)? + 1;
+            self.frame_height =
+                reader.read_bits(sequence.frame_height_bits_minus_1 as u32 + 1)? + 1;
+        } else {
+            (self.frame_width, self.frame_height) = sequence.coded_extent();
+        }
+        self.parse_superres_params(reader, sequence)
+    }
+
+    /// superres_params(), which turns FrameWidth into the downscaled width
+    fn parse_superres_params(
+        &mut self,
+        reader: &mut BitReader,
+        sequence: &SequenceHeader,
+    ) -> Result<()> {
+        self.use_superres = sequence.enable_superres && reader.read_flag()?;
+        let superres_denom = if self.use_superres {
+            self.coded_denom = reader.read_bits(3)? as u8;
+            self.coded_denom as u32 + SUPERRES_DENOM_MIN
+        } else {
+            SUPERRES_NUM
+        };
+        self.upscaled_width = self.frame_width;
+        self.frame_width =
+            (self.upscaled_width * SUPERRES_NUM + superres_denom / 2) / superres_denom;
+        Ok(())
+    }
+
+    /// render_size()
+    fn parse_render_size(&mut self, reader: &mut BitReader) -> Result<()> {
+        self.render_and_frame_size_different = reader.read_flag()?;
+        if self.render_and_frame_size_different {
+            self.render_width = reader.read_bits(16)? + 1;
+            self.render_height = reader.read_bits(16)? + 1;
+        } else {
+            self.render_width = self.upscaled_width;
+            self.render_height = self.frame_height;
+        }
+        Ok(())
+    }
+
+    /// frame_size_with_refs()
+    fn parse_frame_size_with_refs(
+        &mut self,
+        reader: &mut BitReader,
+        sequence: &SequenceHeader,
+        references: &[Option<ReferenceFrame>; NUM_REF_FRAMES],
+    ) -> Result<()> {
+        for i in 0..REFS_PER_FRAME {
+            // found_ref
+            if !reader.read_flag()? {
+                continue;
+            }
+            let idx = self.ref_frame_idx[i] as usize;
+            let reference = references[idx]
+                .as_ref()
+                .ok_or_else(|| anyhow!("Frame size taken from empty reference slot {}", idx))?;
+            self.upscaled_width = reference.upscaled_width;
+            self.frame_width = self.upscaled_width;
+            self.frame_height = reference.frame_height;
+            self.render_width = reference.render_width;
+            self.render_height = reference.render_height;
+            return self.parse_superres_params(reader, sequence);
+        }
+
+        self.parse_frame_size(reader, sequence)?;
+        self.parse_render_size(reader)
+    }
+
+    /// The SkipModeFrame pair of skip_mode_params(), `None` when skip mode is not allowed
+    fn skip_mode_frames(
+        &self,
+        sequence: &SequenceHeader,
+        frame_is_intra: bool,
+        ref_order_hint: &[u8; NUM_REF_FRAMES],
+    ) -> Option<[u8; 2]> {
+        if frame_is_intra || !self.reference_select || !sequence.enable_order_hint {
+            return None;
+        }
+
+        let dist = |a, b| sequence.relative_dist(a, b);
+        let hints = self.ref_frame_idx.map(|idx| ref_order_hint[idx as usize]);
+        let mut forward: Option<(usize, u8)> = None;
+        let mut backward: Option<(usize, u8)> = None;
+        for (i, &ref_hint) in hints.iter().enumerate() {
+            if dist(ref_hint, self.order_hint) < 0 {
+                if forward.is_none_or(|(_, hint)| dist(ref_hint, hint) > 0) {
+                    forward = Some((i, ref_hint));
+                }
+            } else if dist(ref_hint, self.order_hint) > 0
+                && backward.is_none_or(|(_, hint)| dist(ref_hint, hint) < 0)
+            {
+                backward = Some((i, ref_hint));
+            }
+        }
+
+        let (forward_idx, forward_hint) = forward?;
+        let second_idx = match backward {
+            Some((backward_idx, _)) => backward_idx,
+            None => {
+                let mut second_forward: Option<(usize, u8)> = None;
+                for (i, &ref_hint) in hints.iter().enumerate() {
+                    if dist(ref_hint, forward_hint) < 0
+                        && second_forward.is_none_or(|(_, hint)| dist(ref_hint, hint) > 0)
+                    {
+                        second_forward = Some((i, ref_hint));
+                    }
+                }
+                second_forward?.0
+            }
+        };
+
+        Some([
+            (LAST_FRAME + forward_idx.min(second_idx)) as u8,
+            (LAST_FRAME + forward_idx.max(second_idx)) as u8,
+        ])
+    }
+
+    /// MiCols and MiRows
+    pub fn mi_size(&self) -> (u32, u32) {
+        (
+            2 * ((self.frame_width + 7) >> 3),
+            2 * ((self.frame_height + 7) >> 3),
+        )
+    }
+
+    pub fn to_std(&self) -> StdPictureInfo {
+        let mut info: StdVideoDecodeAV1PictureInfo = unsafe { mem::zeroed() };
+
+        info.flags
+            .set_error_resilient_mode(self.error_resilient_mode as u32);
+        info.flags
+            .set_disable_cdf_update(self.disable_cdf_update as u32);
+        info.flags.set_use_superres(self.use_superres as u32);
+        info.flags
+            .set_render_and_frame_size_different(self.render_and_frame_size_different as u32);
+        info.flags
+            .set_allow_screen_content_tools(self.allow_screen_content_tools as u32);
+        info.flags
+            .set_is_filter_switchable(self.is_filter_switchable as u32);
+        info.flags
+            .set_force_integer_mv(self.force_integer_mv as u32);
+        info.flags
+            .set_frame_size_override_flag(self.frame_size_override_flag as u32);
+        info.flags
+            .set_buffer_removal_time_present_flag(self.buffer_removal_time_present_flag as u32);
+        info.flags.set_allow_intrabc(self.allow_intrabc as u32);
+        info.flags
+            .set_frame_refs_short_signaling(self.frame_refs_short_signaling as u32);
+        info.flags
+            .set_allow_high_precision_mv(self.allow_high_precision_mv as u32);
+        info.flags
+            .set_is_motion_mode_switchable(self.is_motion_mode_switchable as u32);
+        info.flags
+            .set_use_ref_frame_mvs(self.use_ref_frame_mvs as u32);
+        info.flags
+            .set_disable_frame_end_update_cdf(self.disable_frame_end_update_cdf as u32);
+        info.flags
+            .set_allow_warped_motion(self.allow_warped_motion as u32);
+        info.flags.set_reduced_tx_set(self.reduced_tx_set as u32);
+        info.flags
+            .set_reference_select(self.reference_select as u32);
+        info.flags
+            .set_skip_mode_present(self.skip_mode_present as u32);
+        info.flags.set_delta_q_present(self.delta_q_present as u32);
+        info.flags
+            .set_delta_lf_present(self.delta_lf_present as u32);
+        info.flags.set_delta_lf_multi(self.delta_lf_multi as u32);
+        info.flags
+            .set_segmentation_enabled(self.segmentation.segmentation_enabled as u32);
+        info.flags
+            .set_segmentation_update_map(self.segmentation.segmentation_update_map as u32);
+        info.flags.set_segmentation_temporal_update(
+            self.segmentation.segmentation_temporal_update as u32,
+        );
+        info.flags
+            .set_segmentation_update_data(self.segmentation.segmentation_update_data as u32);
+        info.flags.set_UsesLr(self.loop_restoration.uses_lr as u32);
+        info.flags
+            .set_usesChromaLr(self.loop_restoration.uses_chroma_lr as u32);
+        info.flags
+            .set_apply_grain(self.film_grain.apply_grain as u32);
+
+        info.frame_type = self.frame_type.to_std();
+        info.current_frame_id = self.current_frame_id;
+        info.OrderHint = self.order_hint;
+        info.primary_ref_frame = self.primary_ref_frame;
+        info.refresh_frame_flags = self.refresh_frame_flags;
+        info.interpolation_filter = self.interpolation_filter as StdVideoAV1InterpolationFilter;
+        info.TxMode = self.tx_mode as StdVideoAV1TxMode;
+        info.delta_q_res = self.delta_q_res;
+        info.delta_lf_res = self.delta_lf_res;
+        info.SkipModeFrame = self.skip_mode_frame;
+        info.coded_denom = self.coded_denom;
+        info.OrderHints = self.order_hints;
+        info.expectedFrameId[..REFS_PER_FRAME].copy_from_slice(&self.expected_frame_id);
+
+        let mut tile_info: StdVideoAV1TileInfo = unsafe { mem::zeroed() };
+        tile_info
+            .flags
+            .set_uniform_tile_spacing_flag(self.tile_info.uniform_tile_spacing_flag as u32);
+        tile_info.TileCols = self.tile_info.tile_cols() as u8;
+        tile_info.TileRows = self.tile_info.tile_rows() as u8;
+        tile_info.context_update_tile_id = self.tile_info.context_update_tile_id;
+        tile_info.tile_size_bytes_minus_1 = self.tile_info.tile_size_bytes_minus_1;
+
+        let mut std = StdPictureInfo {
+            info,
+            tile_info: Box::new(tile_info),
+            mi_col_starts: self.tile_info.mi_col_starts.clone(),
+            mi_row_starts: self.tile_info.mi_row_starts.clone(),
+            width_in_sbs_minus_1: self.tile_info.width_in_sbs_minus_1.clone(),
+            height_in_sbs_minus_1: self.tile_info.height_in_sbs_minus_1.clone(),
+            quantization: Box::new(self.quantization.to_std()),
+            segmentation: Box::new(self.segmentation.to_std()),
+            loop_filter: Box::new(self.loop_filter.to_std()),
+            cdef: Box::new(self.cdef.to_std()),
+            loop_restoration: Box::new(self.loop_restoration.to_std()),
+            global_motion: Box::new(self.global_motion.to_std()),
+            film_grain: Box::new(self.film_grain.to_std()),
+        };
+
+        std.tile_info.pMiColStarts = std.mi_col_starts.as_ptr();
+        std.tile_info.pMiRowStarts = std.mi_row_starts.as_ptr();
+        std.tile_info.pWidthInSbsMinus1 = std.width_in_sbs_minus_1.as_ptr();
+        std.tile_info.pHeightInSbsMinus1 = std.height_in_sbs_minus_1.as_ptr();
+        std.info.pTileInfo = &*std.tile_info;
+        std.info.pQuantization = &*std.quantization;
+        if self.segmentation.segmentation_enabled {
+            std.info.pSegmentation = &*std.segmentation;
+        }
+        std.info.pLoopFilter = &*std.loop_filter;
+        std.info.pCDEF = &*std.cdef;
+        std.info.pLoopRestoration = &*std.loop_restoration;
+        std.info.pGlobalMotion = &*std.global_motion;
+        if self.film_grain.apply_grain {
+            std.info.pFilmGrain = &*std.film_grain;
+        }
+
+        std
+    }
+
+    /// The reference information of the frame once it is stored in a DPB slot.
+    pub fn to_std_reference_info(&self) -> StdVideoDecodeAV1ReferenceInfo {
+        let mut info: StdVideoDecodeAV1ReferenceInfo = unsafe { mem::zeroed() };
+
+        info.flags
+            .set_disable_frame_end_update_cdf(self.disable_frame_end_update_cdf as u32);
+        info.flags
+            .set_segmentation_enabled(self.segmentation.segmentation_enabled as u32);
+        info.frame_type = self.frame_type.to_std() as u8;
+        info.RefFrameSignBias = self.ref_frame_sign_bias;
+        info.OrderHint = self.order_hint;
+        info.SavedOrderHints = self.order_hints;
+
+        info
+    }
+}
+
+/// set_frame_refs(), 7.8, the reference slots of frame_refs_short_signaling
+fn set_frame_refs(
+    sequence: &SequenceHeader,
+    order_hint: u8,
+    ref_order_hint: &[u8; NUM_REF_FRAMES],
+    last_frame_idx: u8,
+    gold_frame_idx: u8,
+) -> [u8; REFS_PER_FRAME] {
+    let mut ref_frame_idx: [Option<u8>; REFS_PER_FRAME] = [None; REFS_PER_FRAME];
+    ref_frame_idx[0] = Some(last_frame_idx);
+    ref_frame_idx[GOLDEN_FRAME - LAST_FRAME] = Some(gold_frame_idx);
+
+    let mut used_frame = [false; NUM_REF_FRAMES];
+    used_frame[last_frame_idx as usize] = true;
+    used_frame[gold_frame_idx as usize] = true;
+
+    let cur_frame_hint = 1 << (sequence.order_hint_bits - 1);
+    let shifted_order_hints: [i32; NUM_REF_FRAMES] = std::array::from_fn(|i| {
+        cur_frame_hint + sequence.relative_dist(ref_order_hint[i], order_hint)
+    });
+
+    // find_latest_backward(), find_earliest_backward() and find_latest_forward()
+    let find = |used_frame: &[bool; NUM_REF_FRAMES], backward: bool, latest: bool| {
+        let mut found: Option<(usize, i32)> = None;
+        for (i, &hint) in shifted_order_hints.iter().enumerate() {
+            if used_frame[i] || (hint >= cur_frame_hint) != backward {
+                continue;
+            }
+            let better = match found {
+                None => true,
+                Some((_, best)) if latest => hint >= best,
+                Some((_, best)) => hint < best,
+            };
+            if better {
+                found = Some((i, hint));
+            }
+        }
+        found.map(|(i, _)| i)
+    };
+
+    if let Some(i) = find(&used_frame, true, true) {
+        ref_frame_idx[ALTREF_FRAME - LAST_FRAME] = Some(i as u8);
+        used_frame[i] = true;
+    }
+    if let Some(i) = find(&used_frame, true, false) {
+        ref_frame_idx[BWDREF_FRAME - LAST_FRAME] = Some(i as u8);
+        used_frame[i] = true;
+    }
+    if let Some(i) = find(&used_frame, true, false) {
+        ref_frame_idx[ALTREF2_FRAME - LAST_FRAME] = Some(i as u8);
+        used_frame[i] = true;
+    }
+
+    // Ref_Frame_List
+    for reference in [2, 3, 5, 6, 7] {
+        if ref_frame_idx[reference - LAST_FRAME].is_none() {
+            if let Some(i) = find(&used_frame, false, true) {
+                ref_frame_idx[reference - LAST_FRAME] = Some(i as u8);
+                used_frame[i] = true;
+            }
+        }
+    }
+
+    // Anything left refers to the frame with the earliest order hint
+    let mut earliest = 0;
+    for (i, &hint) in shifted_order_hints.iter().enumerate() {
+        if hint < shifted_order_hints[earliest] {
+            earliest = i;
+        }
+    }
+    ref_frame_idx.map(|idx| idx.unwrap_or(earliest as u8))
+}
+
+/// `StdVideoDecodeAV1PictureInfo` together with the structures its pointers reference.
+/// The pointers stay valid for as long as this value is alive, moving it is fine.
+pub struct StdPictureInfo {
+    pub info: StdVideoDecodeAV1PictureInfo,
+    tile_info: Box<StdVideoAV1TileInfo>,
+    mi_col_starts: Vec<u16>,
+    mi_row_starts: Vec<u16>,
+    width_in_sbs_minus_1: Vec<u16>,
+    height_in_sbs_minus_1: Vec<u16>,
+    quantization: Box<StdVideoAV1Quantization>,
+    segmentation: Box<StdVideoAV1Segmentation>,
+    loop_filter: Box<StdVideoAV1LoopFilter>,
+    cdef: Box<StdVideoAV1CDEF>,
+    loop_restoration: Box<StdVideoAV1LoopRestoration>,
+    global_motion: Box<StdVideoAV1GlobalMotion>,
+    film_grain: Box<StdVideoAV1FilmGrain>,
+}
@@ -0,0 +1,89 @@
+pub mod av1c;
+pub mod dpb;
+pub mod frame;
+pub mod obu;
+pub mod sequence;
+pub mod tile_group;
+
+use anyhow::{anyhow, Result};
+
+use crate::bitreader::BitReader;
+
+/// Constants of section 3 of the AV1 specification
+pub const NUM_REF_FRAMES: usize = 8;
+pub const REFS_PER_FRAME: usize = 7;
+pub const TOTAL_REFS_PER_FRAME: usize = 8;
+pub const PRIMARY_REF_NONE: u8 = 7;
+pub const MAX_SEGMENTS: usize = 8;
+pub const SEG_LVL_MAX: usize = 8;
+pub const MAX_TILE_COLS: usize = 64;
+pub const MAX_TILE_ROWS: usize = 64;
+pub const MAX_LOOP_FILTER: i32 = 63;
+pub const WARPEDMODEL_PREC_BITS: u32 = 16;
+
+/// Reference frame names, indexing OrderHints, RefFrameSignBias, gm_params and the loop
+/// filter reference deltas
+pub const INTRA_FRAME: usize = 0;
+pub const LAST_FRAME: usize = 1;
+pub const LAST2_FRAME: usize = 2;
+pub const LAST3_FRAME: usize = 3;
+pub const GOLDEN_FRAME: usize = 4;
+pub const BWDREF_FRAME: usize = 5;
+pub const ALTREF2_FRAME: usize = 6;
+pub const ALTREF_FRAME: usize = 7;
+
+/// su(n), a signed integer of n bits in two's complement
+pub fn read_su(reader: &mut BitReader, n: u32) -> Result<i32> {
+    let value = reader.read_bits(n)? as i64;
+    let sign_mask = 1i64 << (n - 1);
+    Ok(((value & (sign_mask - 1)) - (value & sign_mask)) as i32)
+}
+
+/// ns(n), a non-symmetric unsigned value in 0..n
+pub fn read_ns(reader: &mut BitReader, n: u32) -> Result<u32> {
+    if n <= 1 {
+        return Ok(0);
+    }
+    let w = 32 - n.leading_zeros();
+    let m = (1 << w) - n;
+    let v = reader.read_bits(w - 1)?;
+    if v < m {
+        return Ok(v);
+    }
+    let extra_bit = reader.read_bit()?;
+    Ok((v << 1) - m + extra_bit)
+}
+
+/// le(n), an unsigned little-endian value of n bytes
+pub fn read_le(reader: &mut BitReader, n: u32) -> Result<u32> {
+    let mut value = 0;
+    for i in 0..n {
+        value |= reader.read_bits(8)? << (i * 8);
+    }
+    Ok(value)
+}
+
+/// uvlc(), a variable length unsigned value of up to 32 bits
+pub fn read_uvlc(reader: &mut BitReader) -> Result<u32> {
+    let mut leading_zeros = 0;
+    while !reader.read_flag()? {
+        leading_zeros += 1;
+    }
+    if leading_zeros >= 32 {
+        return Ok(u32::MAX);
+    }
+    let value = reader.read_bits(leading_zeros)? as u64;
+    Ok((value + (1 << leading_zeros) - 1) as u32)
+}
+
+/// leb128(), returning the value and the number of bytes it took.
+pub fn read_leb128(data: &[u8]) -> Result<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(anyhow!("Truncated or overlong leb128 value"))
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Result};
+
+use crate::av1::read_leb128;
+
+/// obu_type, Table 6.2.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObuType {
+    SequenceHeader,
+    TemporalDelimiter,
+    FrameHeader,
+    TileGroup,
+    Metadata,
+    Frame,
+    RedundantFrameHeader,
+    TileList,
+    Padding,
+    Reserved(u8),
+}
+
+impl From<u8> for ObuType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => ObuType::SequenceHeader,
+            2 => ObuType::TemporalDelimiter,
+            3 => ObuType::FrameHeader,
+            4 => ObuType::TileGroup,
+            5 => ObuType::Metadata,
+            6 => ObuType::Frame,
+            7 => ObuType::RedundantFrameHeader,
+            8 => ObuType::TileList,
+            15 => ObuType::Padding,
+            _ => ObuType::Reserved(value),
+        }
+    }
+}
+
+/// obu_header(), 5.3.2, with the optional extension folded in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObuHeader {
+    pub obu_type: ObuType,
+    pub obu_extension_flag: bool,
+    pub obu_has_size_field: bool,
+    pub temporal_id: u8,
+    pub spatial_id: u8,
+}
+
+impl ObuHeader {
+    /// Parses the one or two header bytes, returning the header and its size.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        let byte = *data.first().ok_or_else(|| anyhow!("Empty OBU"))?;
+        if byte & 0x80 != 0 {
+            return Err(anyhow!("obu_forbidden_bit is set"));
+        }
+
+        let mut header = ObuHeader {
+            obu_type: ObuType::from((byte >> 3) & 0xf),
+            obu_extension_flag: byte & 0x04 != 0,
+            obu_has_size_field: byte & 0x02 != 0,
+            temporal_id: 0,
+            spatial_id: 0,
+        };
+        if !header.obu_extension_flag {
+            return Ok((header, 1));
+        }
+
+        let extension = *data
+            .get(1)
+            .ok_or_else(|| anyhow!("Truncated OBU extension header"))?;
+        header.temporal_id = extension >> 5;
+        header.spatial_id = (extension >> 3) & 0b11;
+        Ok((header, 2))
+    }
+}
+
+/// An OBU within a temporal unit. `data` is the payload following the header and size field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Obu<'a> {
+    pub header: ObuHeader,
+    /// Offset of the OBU, starting at its header, within the data it was read from
+    pub offset: usize,
+    /// Size of the whole OBU including its header and size field
+    pub size: usize,
+    pub data: &'a [u8],
+}
+
+/// Iterates over the OBUs of data in the low overhead bitstream format (5.2), as stored in
+/// MP4 samples. An OBU without obu_has_size_field extends to the end of the data.
+pub struct Obus<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Obus<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn read_obu(&self) -> Result<Obu<'a>> {
+        let rest = &self.data[self.offset..];
+        let (header, header_size) = ObuHeader::parse(rest)?;
+
+        let (payload_start, payload_size) = if header.obu_has_size_field {
+            let (obu_size, leb128_size) = read_leb128(&rest[header_size..])?;
+            (header_size + leb128_size, obu_size as usize)
+        } else {
+            (header_size, rest.len() - header_size)
+        };
+        let data = rest
+            .get(payload_start..payload_start.saturating_add(payload_size))
+            .ok_or_else(|| anyhow!("OBU of {} bytes is truncated", payload_size))?;
+
+        Ok(Obu {
+            header,
+            offset: self.offset,
+            size: payload_start + payload_size,
+            data,
+        })
+    }
+}
+
+impl<'a> Iterator for Obus<'a> {
+    type Item = Result<Obu<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        match self.read_obu() {
+            Ok(obu) => {
+                self.offset += obu.size;
+                Some(Ok(obu))
+            }
+            Err(err) => {
+                // Nothing after a malformed OBU can be found
+                self.offset = self.data.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
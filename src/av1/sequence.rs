@@ -0,0 +1,541 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoAV1ChromaSamplePosition, StdVideoAV1ColorConfig, StdVideoAV1ColorPrimaries,
+    StdVideoAV1MatrixCoefficients, StdVideoAV1Profile, StdVideoAV1SequenceHeader,
+    StdVideoAV1TimingInfo, StdVideoAV1TransferCharacteristics,
+};
+
+use crate::av1::read_uvlc;
+use crate::bitreader::BitReader;
+use crate::timestamp::Timestamp;
+
+/// seq_force_screen_content_tools and seq_force_integer_mv value letting each frame choose
+pub const SELECT_SCREEN_CONTENT_TOOLS: u8 = 2;
+pub const SELECT_INTEGER_MV: u8 = 2;
+
+/// chroma_sample_position values, Table 6.4.2
+pub const CSP_UNKNOWN: u8 = 0;
+pub const CSP_VERTICAL: u8 = 1;
+pub const CSP_COLOCATED: u8 = 2;
+
+/// timing_info(), 5.5.3
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimingInfo {
+    pub num_units_in_display_tick: u32,
+    pub time_scale: u32,
+    pub equal_picture_interval: bool,
+    pub num_ticks_per_picture_minus_1: u32,
+}
+
+/// decoder_model_info(), 5.5.4
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecoderModelInfo {
+    pub buffer_delay_length_minus_1: u8,
+    pub num_units_in_decoding_tick: u32,
+    pub buffer_removal_time_length_minus_1: u8,
+    pub frame_presentation_time_length_minus_1: u8,
+}
+
+/// The per operating point values of the sequence header
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperatingPoint {
+    pub operating_point_idc: u16,
+    pub seq_level_idx: u8,
+    pub seq_tier: u8,
+    pub decoder_model_present_for_this_op: bool,
+    pub decoder_buffer_delay: u32,
+    pub encoder_buffer_delay: u32,
+    pub low_delay_mode_flag: bool,
+    pub initial_display_delay_minus_1: Option<u8>,
+}
+
+/// color_config(), 5.5.2, with the inferred values filled in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorConfig {
+    pub bit_depth: u8,
+    pub mono_chrome: bool,
+    pub color_description_present_flag: bool,
+    pub color_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub color_range: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub separate_uv_delta_q: bool,
+}
+
+impl ColorConfig {
+    fn parse(reader: &mut BitReader, seq_profile: u8) -> Result<Self> {
+        let high_bitdepth = reader.read_flag()?;
+        let bit_depth = match (seq_profile, high_bitdepth) {
+            (2, true) => {
+                if reader.read_flag()? {
+                    12
+                } else {
+                    10
+                }
+            }
+            (0..=2, true) => 10,
+            (0..=2, false) => 8,
+            _ => return Err(anyhow!("Unsupported AV1 seq_profile {}", seq_profile)),
+        };
+        let mono_chrome = seq_profile != 1 && reader.read_flag()?;
+
+        let color_description_present_flag = reader.read_flag()?;
+        let (color_primaries, transfer_characteristics, matrix_coefficients) =
+            if color_description_present_flag {
+                (
+                    reader.read_bits(8)? as u8,
+                    reader.read_bits(8)? as u8,
+                    reader.read_bits(8)? as u8,
+                )
+            } else {
+                // CP_UNSPECIFIED, TC_UNSPECIFIED and MC_UNSPECIFIED
+                (2, 2, 2)
+            };
+
+        let mut config = ColorConfig {
+            bit_depth,
+            mono_chrome,
+            color_description_present_flag,
+            color_primaries,
+            transfer_characteristics,
+            matrix_coefficients,
+            color_range: false,
+            subsampling_x: true,
+            subsampling_y: true,
+            chroma_sample_position: CSP_UNKNOWN,
+            separate_uv_delta_q: false,
+        };
+
+        if mono_chrome {
+            config.color_range = reader.read_flag()?;
+            return Ok(config);
+        }
+
+        // CP_BT_709, TC_SRGB and MC_IDENTITY signal 4:4:4 RGB
+        if (
+            color_primaries,
+            transfer_characteristics,
+            matrix_coefficients,
+        ) == (1, 13, 0)
+        {
+            config.color_range = true;
+            config.subsampling_x = false;
+            config.subsampling_y = false;
+        } else {
+            config.color_range = reader.read_flag()?;
+            match seq_profile {
+                0 => {}
+                1 => {
+                    config.subsampling_x = false;
+                    config.subsampling_y = false;
+                }
+                _ if bit_depth == 12 => {
+                    config.subsampling_x = reader.read_flag()?;
+                    config.subsampling_y = config.subsampling_x && reader.read_flag()?;
+                }
+                _ => config.subsampling_y = false,
+            }
+            if config.subsampling_x && config.subsampling_y {
+                config.chroma_sample_position = reader.read_bits(2)? as u8;
+            }
+        }
+        config.separate_uv_delta_q = reader.read_flag()?;
+
+        Ok(config)
+    }
+
+    /// NumPlanes
+    pub fn num_planes(&self) -> usize {
+        if self.mono_chrome {
+            1
+        } else {
+            3
+        }
+    }
+
+    pub fn to_std(&self) -> StdVideoAV1ColorConfig {
+        let mut config: StdVideoAV1ColorConfig = unsafe { mem::zeroed() };
+
+        config.flags.set_mono_chrome(self.mono_chrome as u32);
+        config.flags.set_color_range(self.color_range as u32);
+        config
+            .flags
+            .set_separate_uv_delta_q(self.separate_uv_delta_q as u32);
+        config
+            .flags
+            .set_color_description_present_flag(self.color_description_present_flag as u32);
+        config.BitDepth = self.bit_depth;
+        config.subsampling_x = self.subsampling_x as u8;
+        config.subsampling_y = self.subsampling_y as u8;
+        config.color_primaries = self.color_primaries as StdVideoAV1ColorPrimaries;
+        config.transfer_characteristics =
+            self.transfer_characteristics as StdVideoAV1TransferCharacteristics;
+        config.matrix_coefficients = self.matrix_coefficients as StdVideoAV1MatrixCoefficients;
+        config.chroma_sample_position =
+            self.chroma_sample_position as StdVideoAV1ChromaSamplePosition;
+
+        config
+    }
+}
+
+/// sequence_header_obu(), 5.5.1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequenceHeader {
+    pub seq_profile: u8,
+    pub still_picture: bool,
+    pub reduced_still_picture_header: bool,
+    pub timing_info: Option<TimingInfo>,
+    pub decoder_model_info: Option<DecoderModelInfo>,
+    pub initial_display_delay_present_flag: bool,
+    pub operating_points: Vec<OperatingPoint>,
+    pub frame_width_bits_minus_1: u8,
+    pub frame_height_bits_minus_1: u8,
+    pub max_frame_width_minus_1: u16,
+    pub max_frame_height_minus_1: u16,
+    pub frame_id_numbers_present_flag: bool,
+    pub delta_frame_id_length_minus_2: u8,
+    pub additional_frame_id_length_minus_1: u8,
+    pub use_128x128_superblock: bool,
+    pub enable_filter_intra: bool,
+    pub enable_intra_edge_filter: bool,
+    pub enable_interintra_compound: bool,
+    pub enable_masked_compound: bool,
+    pub enable_warped_motion: bool,
+    pub enable_dual_filter: bool,
+    pub enable_order_hint: bool,
+    pub enable_jnt_comp: bool,
+    pub enable_ref_frame_mvs: bool,
+    pub seq_force_screen_content_tools: u8,
+    pub seq_force_integer_mv: u8,
+    /// OrderHintBits, 0 without order hints
+    pub order_hint_bits: u8,
+    pub enable_superres: bool,
+    pub enable_cdef: bool,
+    pub enable_restoration: bool,
+    pub color_config: ColorConfig,
+    pub film_grain_params_present: bool,
+}
+
+impl SequenceHeader {
+    /// Parses the payload of a sequence header OBU, without the OBU header.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+        let reader = &mut reader;
+
+        let seq_profile = reader.read_bits(3)? as u8;
+        let still_picture = reader.read_flag()?;
+        let reduced_still_picture_header = reader.read_flag()?;
+
+        let mut timing_info = None;
+        let mut decoder_model_info = None;
+        let mut initial_display_delay_present_flag = false;
+        let mut operating_points = Vec::new();
+        if reduced_still_picture_header {
+            operating_points.push(OperatingPoint {
+                seq_level_idx: reader.read_bits(5)? as u8,
+                ..Default::default()
+            });
+        } else {
+            if reader.read_flag()? {
+                let num_units_in_display_tick = reader.read_bits(32)?;
+                let time_scale = reader.read_bits(32)?;
+                let equal_picture_interval = reader.read_flag()?;
+                let num_ticks_per_picture_minus_1 = if equal_picture_interval {
+                    read_uvlc(reader)?
+                } else {
+                    0
+                };
+                timing_info = Some(TimingInfo {
+                    num_units_in_display_tick,
+                    time_scale,
+                    equal_picture_interval,
+                    num_ticks_per_picture_minus_1,
+                });
+
+                if reader.read_flag()? {
+                    decoder_model_info = Some(DecoderModelInfo {
+                        buffer_delay_length_minus_1: reader.read_bits(5)? as u8,
+                        num_units_in_decoding_tick: reader.read_bits(32)?,
+                        buffer_removal_time_length_minus_1: reader.read_bits(5)? as u8,
+                        frame_presentation_time_length_minus_1: reader.read_bits(5)? as u8,
+                    });
+                }
+            }
+
+            initial_display_delay_present_flag = reader.read_flag()?;
+            let operating_points_cnt_minus_1 = reader.read_bits(5)?;
+            for _ in 0..=operating_points_cnt_minus_1 {
+                let mut operating_point = OperatingPoint {
+                    operating_point_idc: reader.read_bits(12)? as u16,
+                    seq_level_idx: reader.read_bits(5)? as u8,
+                    ..Default::default()
+                };
+                if operating_point.seq_level_idx > 7 {
+                    operating_point.seq_tier = reader.read_bits(1)? as u8;
+                }
+                if let Some(decoder_model_info) = &decoder_model_info {
+                    operating_point.decoder_model_present_for_this_op = reader.read_flag()?;
+                    if operating_point.decoder_model_present_for_this_op {
+                        let n = decoder_model_info.buffer_delay_length_minus_1 as u32 + 1;
+                        operating_point.decoder_buffer_delay = reader.read_bits(n)?;
+                        operating_point.encoder_buffer_delay = reader.read_bits(n)?;
+                        operating_point.low_delay_mode_flag = reader.read_flag()?;
+                    }
+                }
+                if initial_display_delay_present_flag && reader.read_flag()? {
+                    operating_point.initial_display_delay_minus_1 =
+                        Some(reader.read_bits(4)? as u8);
+                }
+                operating_points.push(operating_point);
+            }
+        }
+
+        let frame_width_bits_minus_1 = reader.read_bits(4)? as u8;
+        let frame_height_bits_minus_1 = reader.read_bits(4)? as u8;
+        let max_frame_width_minus_1 = reader.read_bits(frame_width_bits_minus_1 as u32 + 1)?;
+        let max_frame_height_minus_1 = reader.read_bits(frame_height_bits_minus_1 as u32 + 1)?;
+        if max_frame_width_minus_1 > u16::MAX as u32 || max_frame_height_minus_1 > u16::MAX as u32 {
+            return Err(anyhow!(
+                "AV1 frame size {}x{} is too large",
+                max_frame_width_minus_1 + 1,
+                max_frame_height_minus_1 + 1
+            ));
+        }
+
+        let frame_id_numbers_present_flag = !reduced_still_picture_header && reader.read_flag()?;
+        let (delta_frame_id_length_minus_2, additional_frame_id_length_minus_1) =
+            if frame_id_numbers_present_flag {
+                (reader.read_bits(4)? as u8, reader.read_bits(3)? as u8)
+            } else {
+                (0, 0)
+            };
+
+        let use_128x128_superblock = reader.read_flag()?;
+        let enable_filter_intra = reader.read_flag()?;
+        let enable_intra_edge_filter = reader.read_flag()?;
+
+        let mut header = SequenceHeader {
+            seq_profile,
+            still_picture,
+            reduced_still_picture_header,
+            timing_info,
+            decoder_model_info,
+            initial_display_delay_present_flag,
+            operating_points,
+            frame_width_bits_minus_1,
+            frame_height_bits_minus_1,
+            max_frame_width_minus_1: max_frame_width_minus_1 as u16,
+            max_frame_height_minus_1: max_frame_height_minus_1 as u16,
+            frame_id_numbers_present_flag,
+            delta_frame_id_length_minus_2,
+            additional_frame_id_length_minus_1,
+            use_128x128_superblock,
+            enable_filter_intra,
+            enable_intra_edge_filter,
+            enable_interintra_compound: false,
+            enable_masked_compound: false,
+            enable_warped_motion: false,
+            enable_dual_filter: false,
+            enable_order_hint: false,
+            enable_jnt_comp: false,
+            enable_ref_frame_mvs: false,
+            seq_force_screen_content_tools: SELECT_SCREEN_CONTENT_TOOLS,
+            seq_force_integer_mv: SELECT_INTEGER_MV,
+            order_hint_bits: 0,
+            enable_superres: false,
+            enable_cdef: false,
+            enable_restoration: false,
+            color_config: ColorConfig {
+                bit_depth: 8,
+                mono_chrome: false,
+                color_description_present_flag: false,
+                color_primaries: 2,
+                transfer_characteristics: 2,
+                matrix_coefficients: 2,
+                color_range: false,
+                subsampling_x: true,
+                subsampling_y: true,
+                chroma_sample_position: CSP_UNKNOWN,
+                separate_uv_delta_q: false,
+            },
+            film_grain_params_present: false,
+        };
+
+        if !reduced_still_picture_header {
+            header.enable_interintra_compound = reader.read_flag()?;
+            header.enable_masked_compound = reader.read_flag()?;
+            header.enable_warped_motion = reader.read_flag()?;
+            header.enable_dual_filter = reader.read_flag()?;
+            header.enable_order_hint = reader.read_flag()?;
+            if header.enable_order_hint {
+                header.enable_jnt_comp = reader.read_flag()?;
+                header.enable_ref_frame_mvs = reader.read_flag()?;
+            }
+            // seq_choose_screen_content_tools
+            if !reader.read_flag()? {
+                header.seq_force_screen_content_tools = reader.read_bits(1)? as u8;
+            }
+            // seq_choose_integer_mv
+            if header.seq_force_screen_content_tools > 0 && !reader.read_flag()? {
+                header.seq_force_integer_mv = reader.read_bits(1)? as u8;
+            }
+            if header.enable_order_hint {
+                header.order_hint_bits = reader.read_bits(3)? as u8 + 1;
+            }
+        }
+
+        header.enable_superres = reader.read_flag()?;
+        header.enable_cdef = reader.read_flag()?;
+        header.enable_restoration = reader.read_flag()?;
+        header.color_config = ColorConfig::parse(reader, seq_profile)?;
+        header.film_grain_params_present = reader.read_flag()?;
+
+        Ok(header)
+    }
+
+    /// The length of frame IDs, idLen
+    pub fn frame_id_length(&self) -> u32 {
+        self.additional_frame_id_length_minus_1 as u32
+            + self.delta_frame_id_length_minus_2 as u32
+            + 3
+    }
+
+    /// get_relative_dist(), the signed distance between two order hints, 7.12.3
+    pub fn relative_dist(&self, a: u8, b: u8) -> i32 {
+        if !self.enable_order_hint {
+            return 0;
+        }
+        let diff = a as i32 - b as i32;
+        let m = 1 << (self.order_hint_bits - 1);
+        (diff & (m - 1)) - (diff & m)
+    }
+
+    /// The largest frame size of the sequence, which the decoded pictures are allocated with.
+    pub fn coded_extent(&self) -> (u32, u32) {
+        (
+            self.max_frame_width_minus_1 as u32 + 1,
+            self.max_frame_height_minus_1 as u32 + 1,
+        )
+    }
+
+    /// Duration of a frame from the timing information, only known for a constant frame rate.
+    pub fn frame_duration(&self) -> Option<Timestamp> {
+        let timing_info = self.timing_info.as_ref()?;
+        if !timing_info.equal_picture_interval
+            || timing_info.num_units_in_display_tick == 0
+            || timing_info.time_scale == 0
+        {
+            return None;
+        }
+        Some(Timestamp::new(
+            timing_info.num_units_in_display_tick as i64
+                * (timing_info.num_ticks_per_picture_minus_1 as i64 + 1),
+            timing_info.time_scale,
+        ))
+    }
+
+    pub fn to_std(&self) -> StdSequenceHeader {
+        let mut header: StdVideoAV1SequenceHeader = unsafe { mem::zeroed() };
+
+        header.flags.set_still_picture(self.still_picture as u32);
+        header
+            .flags
+            .set_reduced_still_picture_header(self.reduced_still_picture_header as u32);
+        header
+            .flags
+            .set_use_128x128_superblock(self.use_128x128_superblock as u32);
+        header
+            .flags
+            .set_enable_filter_intra(self.enable_filter_intra as u32);
+        header
+            .flags
+            .set_enable_intra_edge_filter(self.enable_intra_edge_filter as u32);
+        header
+            .flags
+            .set_enable_interintra_compound(self.enable_interintra_compound as u32);
+        header
+            .flags
+            .set_enable_masked_compound(self.enable_masked_compound as u32);
+        header
+            .flags
+            .set_enable_warped_motion(self.enable_warped_motion as u32);
+        header
+            .flags
+            .set_enable_dual_filter(self.enable_dual_filter as u32);
+        header
+            .flags
+            .set_enable_order_hint(self.enable_order_hint as u32);
+        header
+            .flags
+            .set_enable_jnt_comp(self.enable_jnt_comp as u32);
+        header
+            .flags
+            .set_enable_ref_frame_mvs(self.enable_ref_frame_mvs as u32);
+        header
+            .flags
+            .set_frame_id_numbers_present_flag(self.frame_id_numbers_present_flag as u32);
+        header
+            .flags
+            .set_enable_superres(self.enable_superres as u32);
+        header.flags.set_enable_cdef(self.enable_cdef as u32);
+        header
+            .flags
+            .set_enable_restoration(self.enable_restoration as u32);
+        header
+            .flags
+            .set_film_grain_params_present(self.film_grain_params_present as u32);
+        header
+            .flags
+            .set_timing_info_present_flag(self.timing_info.is_some() as u32);
+        header
+            .flags
+            .set_initial_display_delay_present_flag(self.initial_display_delay_present_flag as u32);
+
+        header.seq_profile = self.seq_profile as StdVideoAV1Profile;
+        header.frame_width_bits_minus_1 = self.frame_width_bits_minus_1;
+        header.frame_height_bits_minus_1 = self.frame_height_bits_minus_1;
+        header.max_frame_width_minus_1 = self.max_frame_width_minus_1;
+        header.max_frame_height_minus_1 = self.max_frame_height_minus_1;
+        header.delta_frame_id_length_minus_2 = self.delta_frame_id_length_minus_2;
+        header.additional_frame_id_length_minus_1 = self.additional_frame_id_length_minus_1;
+        header.order_hint_bits_minus_1 = self.order_hint_bits.saturating_sub(1);
+        header.seq_force_integer_mv = self.seq_force_integer_mv;
+        header.seq_force_screen_content_tools = self.seq_force_screen_content_tools;
+
+        let mut timing_info: StdVideoAV1TimingInfo = unsafe { mem::zeroed() };
+        if let Some(timing) = &self.timing_info {
+            timing_info
+                .flags
+                .set_equal_picture_interval(timing.equal_picture_interval as u32);
+            timing_info.num_units_in_display_tick = timing.num_units_in_display_tick;
+            timing_info.time_scale = timing.time_scale;
+            timing_info.num_ticks_per_picture_minus_1 = timing.num_ticks_per_picture_minus_1;
+        }
+
+        let mut std = StdSequenceHeader {
+            header,
+            color_config: Box::new(self.color_config.to_std()),
+            timing_info: Box::new(timing_info),
+        };
+
+        std.header.pColorConfig = &*std.color_config;
+        if self.timing_info.is_some() {
+            std.header.pTimingInfo = &*std.timing_info;
+        }
+
+        std
+    }
+}
+
+/// `StdVideoAV1SequenceHeader` together with the structures its pointers reference.
+/// The pointers stay valid for as long as this value is alive, moving it is fine.
+pub struct StdSequenceHeader {
+    pub header: StdVideoAV1SequenceHeader,
+    color_config: Box<StdVideoAV1ColorConfig>,
+    timing_info: Box<StdVideoAV1TimingInfo>,
+}
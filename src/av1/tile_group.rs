@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Result};
+
+use crate::av1::frame::TileInfo;
+use crate::av1::read_le;
+use crate::bitreader::BitReader;
+
+/// A tile within the data of its tile group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    /// Offset of the tile data, past its tile_size_minus_1 field
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// tile_group_obu(), 5.11.1, down to the tile boundaries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileGroup {
+    pub tg_start: usize,
+    pub tg_end: usize,
+    /// Tiles tg_start to tg_end
+    pub tiles: Vec<Tile>,
+}
+
+impl TileGroup {
+    /// Parses the payload of a tile group OBU, or what follows the frame header of a frame
+    /// OBU, given the tile info of the frame header.
+    pub fn parse(data: &[u8], tile_info: &TileInfo) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+        let num_tiles = tile_info.num_tiles();
+
+        let tile_start_and_end_present_flag = num_tiles > 1 && reader.read_flag()?;
+        let (tg_start, tg_end) = if tile_start_and_end_present_flag {
+            let tile_bits = tile_info.tile_cols_log2 + tile_info.tile_rows_log2;
+            (
+                reader.read_bits(tile_bits)? as usize,
+                reader.read_bits(tile_bits)? as usize,
+            )
+        } else {
+            (0, num_tiles.saturating_sub(1))
+        };
+        if tg_start > tg_end || tg_end >= num_tiles {
+            return Err(anyhow!(
+                "Tile group {}..={} out of {} tiles",
+                tg_start,
+                tg_end,
+                num_tiles
+            ));
+        }
+        reader.align();
+
+        let tile_size_bytes = tile_info.tile_size_bytes();
+        let mut offset = reader.position() / 8;
+        let mut tiles = Vec::with_capacity(tg_end - tg_start + 1);
+        for tile_num in tg_start..=tg_end {
+            let size = if tile_num == tg_end {
+                data.len()
+                    .checked_sub(offset)
+                    .ok_or_else(|| anyhow!("Tile group of {} bytes is truncated", data.len()))?
+            } else {
+                let mut size_reader = BitReader::new(data.get(offset..).unwrap_or_default());
+                let size = read_le(&mut size_reader, tile_size_bytes as u32)? as usize + 1;
+                offset += tile_size_bytes;
+                size
+            };
+            if offset + size > data.len() {
+                return Err(anyhow!(
+                    "Tile {} of {} bytes exceeds its tile group",
+                    tile_num,
+                    size
+                ));
+            }
+            tiles.push(Tile { offset, size });
+            offset += size;
+        }
+
+        Ok(Self {
+            tg_start,
+            tg_end,
+            tiles,
+        })
+    }
+
+    /// Whether the tile group holds the last tile of the frame.
+    pub fn is_last(&self, tile_info: &TileInfo) -> bool {
+        self.tg_end + 1 == tile_info.num_tiles()
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+
+/// Removes the emulation prevention bytes (`0x03` following two zero bytes) from a NAL unit,
+/// turning its payload into a raw byte sequence payload (RBSP) that can be read bit by bit.
+pub fn nal_to_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+
+        if byte == 0 {
+            zeros += 1;
+        } else {
+            zeros = 0;
+        }
+
+        rbsp.push(byte);
+    }
+
+    rbsp
+}
+
+/// MSB-first bit reader with the Exp-Golomb descriptors used by the H.264 syntax tables.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Current position in bits from the start of the data.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn bits_left(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.position)
+    }
+
+    pub fn byte_aligned(&self) -> bool {
+        self.position % 8 == 0
+    }
+
+    pub fn skip_bits(&mut self, count: usize) -> Result<()> {
+        if count > self.bits_left() {
+            return Err(anyhow!("Unexpected end of bitstream"));
+        }
+        self.position += count;
+        Ok(())
+    }
+
+    /// Skips to the next byte boundary.
+    pub fn align(&mut self) {
+        self.position = (self.position + 7) & !7;
+    }
+
+    /// u(1)
+    pub fn read_bit(&mut self) -> Result<u32> {
+        let byte = self
+            .data
+            .get(self.position / 8)
+            .ok_or_else(|| anyhow!("Unexpected end of bitstream"))?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Ok(bit as u32)
+    }
+
+    pub fn read_flag(&mut self) -> Result<bool> {
+        Ok(self.read_bit()? == 1)
+    }
+
+    /// u(n) for n up to 32
+    pub fn read_bits(&mut self, count: u32) -> Result<u32> {
+        assert!(count <= 32);
+        if count as usize > self.bits_left() {
+            return Err(anyhow!("Unexpected end of bitstream"));
+        }
+
+        let mut value: u64 = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Ok(value as u32)
+    }
+
+    /// Returns the next `count` bits without advancing.
+    pub fn peek_bits(&self, count: u32) -> Result<u32> {
+        let mut reader = BitReader {
+            data: self.data,
+            position: self.position,
+        };
+        reader.read_bits(count)
+    }
+
+    /// ue(v)
+    pub fn read_ue(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+        while self.read_bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(anyhow!("Exp-Golomb code exceeds 32 bits"));
+            }
+        }
+
+        let suffix = self.read_bits(leading_zeros)? as u64;
+        Ok(((1u64 << leading_zeros) - 1 + suffix) as u32)
+    }
+
+    /// se(v)
+    pub fn read_se(&mut self) -> Result<i32> {
+        let code = self.read_ue()? as i64;
+        let value = if code & 1 == 1 {
+            (code + 1) / 2
+        } else {
+            -(code / 2)
+        };
+        Ok(value as i32)
+    }
+
+    /// te(v) with the given range of the syntax element
+    pub fn read_te(&mut self, range: u32) -> Result<u32> {
+        if range > 1 {
+            self.read_ue()
+        } else {
+            Ok(1 - self.read_bit()?)
+        }
+    }
+
+    /// more_rbsp_data() as specified in 7.2: true while anything other than the
+    /// rbsp_trailing_bits remains.
+    pub fn more_rbsp_data(&self) -> bool {
+        let last_one = match self.data.iter().rposition(|&b| b != 0) {
+            Some(byte) => byte * 8 + 7 - self.data[byte].trailing_zeros() as usize,
+            None => return false,
+        };
+        self.position < last_one
+    }
+}
@@ -0,0 +1,147 @@
+//! What differs between the supported codecs, for the parts of the pipeline that are
+//! otherwise codec agnostic.
+
+use std::ffi::CStr;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::color::ColorSpace;
+use crate::timestamp::Timestamp;
+use crate::{av1, h264, h265};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Av1,
+    H264,
+    H265,
+}
+
+impl Codec {
+    pub fn decode_operation(self) -> vk::VideoCodecOperationFlagsKHR {
+        match self {
+            Codec::Av1 => vk::VideoCodecOperationFlagsKHR::DECODE_AV1,
+            Codec::H264 => vk::VideoCodecOperationFlagsKHR::DECODE_H264,
+            Codec::H265 => vk::VideoCodecOperationFlagsKHR::DECODE_H265,
+        }
+    }
+
+    /// The device extension needed on top of `VK_KHR_video_decode_queue`.
+    pub fn decode_extension_name(self) -> &'static CStr {
+        match self {
+            Codec::Av1 => vk::KhrVideoDecodeAv1Fn::NAME,
+            Codec::H264 => vk::KhrVideoDecodeH264Fn::NAME,
+            Codec::H265 => vk::KhrVideoDecodeH265Fn::NAME,
+        }
+    }
+}
+
+/// The parameter sets of a stream, out of band ones from the container or those read
+/// ahead from the stream. For AV1 that is the sequence header.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterSets {
+    Av1(av1::sequence::SequenceHeader),
+    H264(h264::ParameterSets),
+    H265(h265::ParameterSets),
+}
+
+impl From<av1::sequence::SequenceHeader> for ParameterSets {
+    fn from(sequence_header: av1::sequence::SequenceHeader) -> Self {
+        ParameterSets::Av1(sequence_header)
+    }
+}
+
+impl From<h264::ParameterSets> for ParameterSets {
+    fn from(parameter_sets: h264::ParameterSets) -> Self {
+        ParameterSets::H264(parameter_sets)
+    }
+}
+
+impl From<h265::ParameterSets> for ParameterSets {
+    fn from(parameter_sets: h265::ParameterSets) -> Self {
+        ParameterSets::H265(parameter_sets)
+    }
+}
+
+/// Properties of the stream taken from its first SPS or sequence header, which the decoder
+/// is created for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamInfo {
+    pub codec: Codec,
+    /// Width and height of the decoded pictures
+    pub coded_extent: (u32, u32),
+    /// Visible area as (x, y, width, height)
+    pub crop_rect: (u32, u32, u32, u32),
+    pub frame_duration: Option<Timestamp>,
+    pub color_space: ColorSpace,
+    /// chroma_sample_loc_type_top_field of the VUI, 0 when absent
+    pub chroma_sample_loc_type: u8,
+}
+
+impl ParameterSets {
+    pub fn codec(&self) -> Codec {
+        match self {
+            ParameterSets::Av1(_) => Codec::Av1,
+            ParameterSets::H264(_) => Codec::H264,
+            ParameterSets::H265(_) => Codec::H265,
+        }
+    }
+
+    pub fn stream_info(&self) -> Result<StreamInfo> {
+        let no_sps = || anyhow!("No sequence parameter set in the stream");
+        match self {
+            ParameterSets::Av1(sequence_header) => {
+                let (width, height) = sequence_header.coded_extent();
+                let color_config = &sequence_header.color_config;
+                Ok(StreamInfo {
+                    codec: Codec::Av1,
+                    coded_extent: (width, height),
+                    // Frames may be smaller than the maximum size, their render size
+                    // is not known up front
+                    crop_rect: (0, 0, width, height),
+                    frame_duration: sequence_header.frame_duration(),
+                    color_space: ColorSpace::from_av1_sequence_header(sequence_header),
+                    // CSP_COLOCATED is sited like chroma_sample_loc_type 2
+                    chroma_sample_loc_type: match color_config.chroma_sample_position {
+                        av1::sequence::CSP_COLOCATED => 2,
+                        _ => 0,
+                    },
+                })
+            }
+            ParameterSets::H264(parameter_sets) => {
+                let sps = (0..h264::sps::MAX_SPS_COUNT as u8)
+                    .find_map(|id| parameter_sets.sps(id))
+                    .ok_or_else(no_sps)?;
+                Ok(StreamInfo {
+                    codec: Codec::H264,
+                    coded_extent: sps.coded_extent(),
+                    crop_rect: sps.crop_rect(),
+                    frame_duration: sps.frame_duration(),
+                    color_space: ColorSpace::from_sps(sps),
+                    chroma_sample_loc_type: sps
+                        .vui
+                        .as_ref()
+                        .filter(|vui| vui.chroma_loc_info_present_flag)
+                        .map_or(0, |vui| vui.chroma_sample_loc_type_top_field),
+                })
+            }
+            ParameterSets::H265(parameter_sets) => {
+                let sps = (0..h265::sps::MAX_SPS_COUNT as u8)
+                    .find_map(|id| parameter_sets.sps(id))
+                    .ok_or_else(no_sps)?;
+                Ok(StreamInfo {
+                    codec: Codec::H265,
+                    coded_extent: sps.coded_extent(),
+                    crop_rect: sps.crop_rect(),
+                    frame_duration: sps.frame_duration(),
+                    color_space: ColorSpace::from_h265_sps(sps),
+                    chroma_sample_loc_type: sps
+                        .vui
+                        .as_ref()
+                        .filter(|vui| vui.chroma_loc_info_present_flag)
+                        .map_or(0, |vui| vui.chroma_sample_loc_type_top_field),
+                })
+            }
+        }
+    }
+}
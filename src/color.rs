@@ -0,0 +1,431 @@
+//! Colour description of the VUI, E.2.1, and the conversion of decoded pictures to
+//! BT.709 R'G'B' for display.
+//!
+//! The sampler YCbCr conversion only expands the sample range, the matrix, the transfer
+//! function and the primaries are applied by `shader/texture/texture.frag` from
+//! [`ColorUniforms`], or on the CPU by [`ColorSpace::to_rgb8`] in the same way.
+
+use crate::av1;
+use crate::h264::sps::Sps;
+use crate::h265;
+
+/// colour_primaries, Table E-3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColourPrimaries {
+    Bt709,
+    Bt470M,
+    Bt470Bg,
+    Smpte170M,
+    Bt2020,
+}
+
+/// transfer_characteristics, Table E-4. The BT.601, BT.709 and BT.2020 curves are the
+/// same for 8 bit samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferCharacteristics {
+    Bt709,
+    Gamma22,
+    Gamma28,
+    Smpte240M,
+    Linear,
+    Srgb,
+    Pq,
+    Hlg,
+}
+
+/// matrix_coefficients, Table E-5. BT.2020 constant luminance is treated as the
+/// non-constant luminance matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatrixCoefficients {
+    Identity,
+    Bt709,
+    Fcc,
+    Bt601,
+    Smpte240M,
+    YCgCo,
+    Bt2020,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColorSpace {
+    pub primaries: ColourPrimaries,
+    pub transfer: TransferCharacteristics,
+    pub matrix: MatrixCoefficients,
+    pub full_range: bool,
+}
+
+impl Default for ColorSpace {
+    /// BT.709 with limited range
+    fn default() -> Self {
+        Self {
+            primaries: ColourPrimaries::Bt709,
+            transfer: TransferCharacteristics::Bt709,
+            matrix: MatrixCoefficients::Bt709,
+            full_range: false,
+        }
+    }
+}
+
+/// Uniform block of the fragment shader, std140 layout with matrix columns padded to
+/// four floats.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorUniforms {
+    pub ycbcr_to_rgb: [[f32; 4]; 3],
+    pub gamut: [[f32; 4]; 3],
+    /// 0 when the picture is BT.709 already, otherwise one more than the
+    /// [`TransferCharacteristics`] discriminant
+    pub transfer: i32,
+    /// Set when the swapchain format encodes to sRGB itself
+    pub srgb_output: i32,
+    pub _pad: [i32; 2],
+}
+
+type Matrix3 = [[f32; 3]; 3];
+
+fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut product = [[0.0; 3]; 3];
+    for (row, product_row) in product.iter_mut().enumerate() {
+        for (column, value) in product_row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[row][k] * b[k][column]).sum();
+        }
+    }
+    product
+}
+
+fn transform(m: &Matrix3, v: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| m[row][0] * v[0] + m[row][1] * v[1] + m[row][2] * v[2])
+}
+
+fn invert(m: &Matrix3) -> Matrix3 {
+    let cofactor = |row: usize, column: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((column + 1) % 3, (column + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant: f32 = (0..3)
+        .map(|column| m[0][column] * cofactor(0, column))
+        .sum();
+    let mut inverse = [[0.0; 3]; 3];
+    for (row, inverse_row) in inverse.iter_mut().enumerate() {
+        for (column, value) in inverse_row.iter_mut().enumerate() {
+            *value = cofactor(column, row) / determinant;
+        }
+    }
+    inverse
+}
+
+impl ColourPrimaries {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Bt709),
+            4 => Some(Self::Bt470M),
+            5 => Some(Self::Bt470Bg),
+            6 | 7 => Some(Self::Smpte170M),
+            9 => Some(Self::Bt2020),
+            _ => None,
+        }
+    }
+
+    /// x, y chromaticity of red, green, blue and white.
+    fn chromaticities(self) -> [[f32; 2]; 4] {
+        const D65: [f32; 2] = [0.3127, 0.3290];
+        match self {
+            Self::Bt709 => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06], D65],
+            Self::Bt470M => [[0.67, 0.33], [0.21, 0.71], [0.14, 0.08], [0.310, 0.316]],
+            Self::Bt470Bg => [[0.64, 0.33], [0.29, 0.60], [0.15, 0.06], D65],
+            Self::Smpte170M => [[0.630, 0.340], [0.310, 0.595], [0.155, 0.070], D65],
+            Self::Bt2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046], D65],
+        }
+    }
+
+    /// Linear RGB to CIE XYZ.
+    fn to_xyz(self) -> Matrix3 {
+        let [red, green, blue, white] = self.chromaticities();
+        let xyz = |[x, y]: [f32; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+        let (r, g, b) = (xyz(red), xyz(green), xyz(blue));
+        let primaries = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        // Scaled so that RGB 1, 1, 1 is the white point
+        let scale = transform(&invert(&primaries), xyz(white));
+        [0, 1, 2].map(|row| [0, 1, 2].map(|column| primaries[row][column] * scale[column]))
+    }
+}
+
+impl TransferCharacteristics {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 | 6 | 14 | 15 => Some(Self::Bt709),
+            4 => Some(Self::Gamma22),
+            5 => Some(Self::Gamma28),
+            7 => Some(Self::Smpte240M),
+            8 => Some(Self::Linear),
+            13 => Some(Self::Srgb),
+            16 => Some(Self::Pq),
+            18 => Some(Self::Hlg),
+            _ => None,
+        }
+    }
+
+    /// Non-linear signal to linear light, 1.0 being SDR reference white.
+    pub fn to_linear(self, value: f32) -> f32 {
+        let value = value.max(0.0);
+        match self {
+            Self::Bt709 if value < 0.081 => value / 4.5,
+            Self::Bt709 => ((value + 0.099) / 1.099).powf(1.0 / 0.45),
+            Self::Gamma22 => value.powf(2.2),
+            Self::Gamma28 => value.powf(2.8),
+            Self::Smpte240M if value < 0.0913 => value / 4.0,
+            Self::Smpte240M => ((value + 0.1115) / 1.1115).powf(1.0 / 0.45),
+            Self::Linear => value,
+            Self::Srgb if value <= 0.04045 => value / 12.92,
+            Self::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            // ST 2084 in cd/m², with SDR white at the 203 cd/m² of BT.2408
+            Self::Pq => {
+                let (m1, m2) = (2610.0 / 16384.0, 2523.0 / 4096.0 * 128.0);
+                let (c1, c2, c3) = (
+                    3424.0 / 4096.0,
+                    2413.0 / 4096.0 * 32.0,
+                    2392.0 / 4096.0 * 32.0,
+                );
+                let power = value.powf(1.0 / m2);
+                let luminance = ((power - c1).max(0.0) / (c2 - c3 * power)).powf(1.0 / m1);
+                luminance * 10000.0 / 203.0
+            }
+            Self::Hlg if value <= 0.5 => value * value / 3.0,
+            Self::Hlg => {
+                let (a, b, c) = (0.17883277, 0.28466892, 0.5599107);
+                (((value - c) / a).exp() + b) / 12.0
+            }
+        }
+    }
+}
+
+/// BT.709 OETF, the encoding the output is in.
+fn bt709_from_linear(value: f32) -> f32 {
+    if value < 0.018 {
+        value * 4.5
+    } else {
+        1.099 * value.powf(0.45) - 0.099
+    }
+}
+
+impl MatrixCoefficients {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Identity),
+            1 => Some(Self::Bt709),
+            4 => Some(Self::Fcc),
+            5 | 6 => Some(Self::Bt601),
+            7 => Some(Self::Smpte240M),
+            8 => Some(Self::YCgCo),
+            9 | 10 => Some(Self::Bt2020),
+            _ => None,
+        }
+    }
+
+    /// Y, Cb - 0.5, Cr - 0.5 to R'G'B', row major.
+    pub fn to_rgb(self) -> [[f32; 3]; 3] {
+        let (kr, kb) = match self {
+            Self::Identity => return [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            Self::YCgCo => return [[1.0, -1.0, 1.0], [1.0, 1.0, 0.0], [1.0, -1.0, -1.0]],
+            Self::Bt709 => (0.2126, 0.0722),
+            Self::Fcc => (0.30, 0.11),
+            Self::Bt601 => (0.299, 0.114),
+            Self::Smpte240M => (0.212, 0.087),
+            Self::Bt2020 => (0.2627, 0.0593),
+        };
+        let kg = 1.0 - kr - kb;
+        [
+            [1.0, 0.0, 2.0 * (1.0 - kr)],
+            [
+                1.0,
+                -2.0 * kb * (1.0 - kb) / kg,
+                -2.0 * kr * (1.0 - kr) / kg,
+            ],
+            [1.0, 2.0 * (1.0 - kb), 0.0],
+        ]
+    }
+}
+
+impl ColorSpace {
+    /// Reads the video signal type of the VUI. Unspecified values are guessed from the
+    /// picture size, BT.709 for HD and BT.601 for smaller pictures.
+    pub fn from_sps(sps: &Sps) -> Self {
+        let mut color_space = Self::guess(sps.crop_rect().3);
+        if let Some(vui) = sps
+            .vui
+            .as_ref()
+            .filter(|vui| vui.video_signal_type_present_flag)
+        {
+            color_space.apply_video_signal(
+                vui.video_full_range_flag,
+                vui.colour_description_present_flag.then_some((
+                    vui.colour_primaries,
+                    vui.transfer_characteristics,
+                    vui.matrix_coefficients,
+                )),
+            );
+        }
+        color_space
+    }
+
+    /// [`ColorSpace::from_sps`] for H.265, whose VUI codes the colour description the
+    /// same way (E.3.1).
+    pub fn from_h265_sps(sps: &h265::sps::Sps) -> Self {
+        let mut color_space = Self::guess(sps.crop_rect().3);
+        if let Some(vui) = sps
+            .vui
+            .as_ref()
+            .filter(|vui| vui.video_signal_type_present_flag)
+        {
+            color_space.apply_video_signal(
+                vui.video_full_range_flag,
+                vui.colour_description_present_flag.then_some((
+                    vui.colour_primaries,
+                    vui.transfer_characteristics,
+                    vui.matrix_coeffs,
+                )),
+            );
+        }
+        color_space
+    }
+
+    /// [`ColorSpace::from_sps`] for AV1, whose color_config() uses the same code points
+    /// and always signals the range.
+    pub fn from_av1_sequence_header(sequence: &av1::sequence::SequenceHeader) -> Self {
+        let color_config = &sequence.color_config;
+        let mut color_space = Self::guess(sequence.coded_extent().1);
+        color_space.apply_video_signal(
+            color_config.color_range,
+            color_config.color_description_present_flag.then_some((
+                color_config.color_primaries,
+                color_config.transfer_characteristics,
+                color_config.matrix_coefficients,
+            )),
+        );
+        color_space
+    }
+
+    /// BT.709 for HD pictures, BT.601 for smaller ones.
+    fn guess(height: u32) -> Self {
+        if height >= 720 {
+            Self::default()
+        } else {
+            Self {
+                primaries: ColourPrimaries::Smpte170M,
+                matrix: MatrixCoefficients::Bt601,
+                ..Self::default()
+            }
+        }
+    }
+
+    /// Applies video_full_range_flag and the colour_primaries, transfer_characteristics
+    /// and matrix_coefficients codes, keeping the current values for unknown codes.
+    fn apply_video_signal(&mut self, full_range: bool, colour_description: Option<(u8, u8, u8)>) {
+        self.full_range = full_range;
+        if let Some((primaries, transfer, matrix)) = colour_description {
+            if let Some(primaries) = ColourPrimaries::from_code(primaries) {
+                self.primaries = primaries;
+            }
+            if let Some(transfer) = TransferCharacteristics::from_code(transfer) {
+                self.transfer = transfer;
+            }
+            if let Some(matrix) = MatrixCoefficients::from_code(matrix) {
+                self.matrix = matrix;
+            }
+        }
+    }
+
+    /// Whether the signal has to go through linear light to end up as BT.709.
+    pub fn needs_linearization(&self) -> bool {
+        self.primaries != ColourPrimaries::Bt709 || self.transfer != TransferCharacteristics::Bt709
+    }
+
+    /// Linear RGB in the stream's primaries to linear BT.709 RGB, row major. White points
+    /// other than D65 are not adapted.
+    pub fn gamut(&self) -> [[f32; 3]; 3] {
+        multiply(
+            &invert(&ColourPrimaries::Bt709.to_xyz()),
+            &self.primaries.to_xyz(),
+        )
+    }
+
+    /// Normalized Y, Cb and Cr samples as the sampler YCbCr conversion expands them,
+    /// chroma centered on zero. G, B and R samples are only normalized.
+    pub fn expand_range(&self, y: u8, cb: u8, cr: u8) -> [f32; 3] {
+        if self.matrix == MatrixCoefficients::Identity {
+            return [y, cb, cr].map(|value| value as f32 / 255.0);
+        }
+
+        let (y, cb, cr) = (y as f32, cb as f32 - 128.0, cr as f32 - 128.0);
+        if self.full_range {
+            [y / 255.0, cb / 255.0, cr / 255.0]
+        } else {
+            [(y - 16.0) / 219.0, cb / 224.0, cr / 224.0]
+        }
+    }
+
+    /// The CPU side of the shader, with the matrices worked out once.
+    pub fn converter(&self) -> RgbConverter {
+        RgbConverter {
+            color_space: *self,
+            matrix: self.matrix.to_rgb(),
+            gamut: self.needs_linearization().then(|| self.gamut()),
+        }
+    }
+
+    /// Converts one 8 bit sample triple to 8 bit BT.709 R'G'B'.
+    pub fn to_rgb8(&self, y: u8, cb: u8, cr: u8) -> [u8; 3] {
+        self.converter().to_rgb8(y, cb, cr)
+    }
+
+    /// The uniform block for the fragment shader. `srgb_output` is set when the
+    /// shader writes to an sRGB format that encodes on its own.
+    pub fn uniforms(&self, srgb_output: bool) -> ColorUniforms {
+        // GLSL matrices are column major
+        let columns = |m: [[f32; 3]; 3]| {
+            [0, 1, 2].map(|column| [m[0][column], m[1][column], m[2][column], 0.0])
+        };
+        ColorUniforms {
+            ycbcr_to_rgb: columns(self.matrix.to_rgb()),
+            gamut: columns(self.gamut()),
+            transfer: if self.needs_linearization() {
+                self.transfer as i32 + 1
+            } else {
+                0
+            },
+            srgb_output: srgb_output as i32,
+            _pad: [0; 2],
+        }
+    }
+}
+
+/// Converts samples of one [`ColorSpace`] to BT.709 R'G'B'.
+#[derive(Clone, Debug)]
+pub struct RgbConverter {
+    color_space: ColorSpace,
+    matrix: Matrix3,
+    /// Set when the conversion goes through linear light
+    gamut: Option<Matrix3>,
+}
+
+impl RgbConverter {
+    /// Converts expanded Y, Cb, Cr to R'G'B' in 0..=1.
+    pub fn to_rgb(&self, ycbcr: [f32; 3]) -> [f32; 3] {
+        let rgb = transform(&self.matrix, ycbcr);
+        let rgb = match &self.gamut {
+            Some(gamut) => {
+                let transfer = self.color_space.transfer;
+                let linear = transform(gamut, rgb.map(|value| transfer.to_linear(value)));
+                linear.map(|value| bt709_from_linear(value.clamp(0.0, 1.0)))
+            }
+            None => rgb,
+        };
+        rgb.map(|value| value.clamp(0.0, 1.0))
+    }
+
+    pub fn to_rgb8(&self, y: u8, cb: u8, cr: u8) -> [u8; 3] {
+        self.to_rgb(self.color_space.expand_range(y, cb, cr))
+            .map(|value| (value * 255.0).round() as u8)
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use ash::vk::native::{
+    StdVideoDecodeAV1PictureInfo, StdVideoDecodeAV1ReferenceInfo, StdVideoDecodeH264PictureInfo,
+    StdVideoDecodeH264ReferenceInfo, StdVideoDecodeH265PictureInfo,
+    StdVideoDecodeH265ReferenceInfo,
+};
+
+use crate::av1;
+use crate::codec::{Codec, ParameterSets};
+use crate::timestamp::Timestamp;
+
+/// The limits of a backend for the profile of a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackendCapabilities {
+    pub max_dpb_slots: u32,
+    pub max_active_reference_pictures: u32,
+}
+
+/// What a session is created for, sized by the [`Decoder`](super::Decoder) from the
+/// stream and the [`BackendCapabilities`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub codec: Codec,
+    /// Coded size of the stream, backends round it up to their own granularity
+    pub coded_extent: (u32, u32),
+    pub dpb_slots: u32,
+    pub max_active_reference_pictures: u32,
+}
+
+/// The codec's reference information of the picture in a DPB slot.
+#[derive(Clone, Copy, Debug)]
+pub enum ReferenceInfo {
+    Av1(StdVideoDecodeAV1ReferenceInfo),
+    H264(StdVideoDecodeH264ReferenceInfo),
+    H265(StdVideoDecodeH265ReferenceInfo),
+}
+
+/// A DPB slot with the picture it holds or is about to hold.
+#[derive(Clone, Copy, Debug)]
+pub struct ReferenceSlot {
+    pub slot: usize,
+    pub info: ReferenceInfo,
+}
+
+/// The codec's parameters of the picture being decoded. Pointers in the AV1 picture info
+/// are only valid during [`DecodeBackend::decode`].
+#[derive(Clone, Debug)]
+pub enum PictureInfo {
+    Av1 {
+        std_picture_info: StdVideoDecodeAV1PictureInfo,
+        /// DPB slot of each reference name, -1 for intra frames
+        reference_name_slot_indices: [i32; av1::REFS_PER_FRAME],
+        /// Relative to the frame header OBU
+        tile_offsets: Vec<u32>,
+        tile_sizes: Vec<u32>,
+    },
+    H264 {
+        std_picture_info: StdVideoDecodeH264PictureInfo,
+        /// Start codes of the slices, relative to the first one
+        slice_offsets: Vec<u32>,
+    },
+    H265 {
+        std_picture_info: StdVideoDecodeH265PictureInfo,
+        /// Start codes of the slice segments, relative to the first one
+        slice_segment_offsets: Vec<u32>,
+    },
+}
+
+/// Starts decoding a picture, see `vkCmdBeginVideoCodingKHR`.
+#[derive(Clone, Copy, Debug)]
+pub struct BeginCodingInfo<'a> {
+    /// The session is new and has to be reset first
+    pub reset: bool,
+    /// The pictures the one being decoded may refer to
+    pub reference_slots: &'a [ReferenceSlot],
+    /// The slot the picture is reconstructed into, not associated with a picture yet
+    pub setup_slot: usize,
+}
+
+/// Decodes a picture between [`DecodeBackend::begin_coding`] and
+/// [`DecodeBackend::end_coding`], see `vkCmdDecodeVideoKHR`.
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeInfo<'a> {
+    pub picture: &'a PictureInfo,
+    /// The coded picture, the offsets of `picture` are relative to it
+    pub bitstream: &'a [u8],
+    pub setup_slot: ReferenceSlot,
+    pub reference_slots: &'a [ReferenceSlot],
+}
+
+/// Where the pictures picked by a [`Decoder`](super::Decoder) are decoded.
+///
+/// The decoder parses the stream, assigns DPB slots and orders the output; a backend only
+/// keeps the session, the parameter sets and the pictures in their slots. The calls
+/// follow the structure of Vulkan Video: a session is created once, then every picture
+/// is decoded between `begin_coding` and `end_coding`, with `update_parameters` whenever
+/// the stream brings new parameter sets.
+pub trait DecodeBackend {
+    /// How decoded pictures are handed out
+    type Frame;
+
+    /// The limits for streams with the profile of `parameter_sets`.
+    fn capabilities(&mut self, parameter_sets: &ParameterSets) -> Result<BackendCapabilities>;
+
+    /// Creates the session the pictures of the stream are decoded in.
+    fn create_session(&mut self, parameter_sets: &ParameterSets, info: &SessionInfo) -> Result<()>;
+
+    /// Replaces the parameter sets pictures are decoded with.
+    fn update_parameters(&mut self, parameter_sets: &ParameterSets) -> Result<()>;
+
+    fn begin_coding(&mut self, info: &BeginCodingInfo) -> Result<()>;
+
+    fn decode(&mut self, info: &DecodeInfo) -> Result<()>;
+
+    /// Completes the picture, it is in its slot once this returns.
+    fn end_coding(&mut self) -> Result<()>;
+
+    /// The picture in `slot` for output. The decoder does not decode into the slot until
+    /// the frame has been returned and the next call comes in.
+    fn frame(&mut self, slot: usize, pts: Timestamp) -> Result<Self::Frame>;
+}
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+
+use super::backend::{
+    BackendCapabilities, BeginCodingInfo, DecodeBackend, DecodeInfo, PictureInfo, ReferenceSlot,
+    SessionInfo,
+};
+use crate::codec::ParameterSets;
+use crate::timestamp::Timestamp;
+
+/// A call made to a [`MockBackend`], with copies of its arguments.
+#[derive(Clone, Debug)]
+pub enum Call {
+    CreateSession(SessionInfo),
+    UpdateParameters(ParameterSets),
+    BeginCoding {
+        reset: bool,
+        reference_slots: Vec<ReferenceSlot>,
+        setup_slot: usize,
+    },
+    Decode {
+        picture: PictureInfo,
+        bitstream: Vec<u8>,
+        setup_slot: ReferenceSlot,
+        reference_slots: Vec<ReferenceSlot>,
+    },
+    EndCoding,
+}
+
+/// A frame of the [`MockBackend`].
+#[derive(Clone, Copy, Debug)]
+pub struct MockFrame {
+    pub slot: usize,
+    /// Counts the decoded pictures, starting at 0
+    pub picture: usize,
+    pub pts: Timestamp,
+}
+
+/// Records the calls of a [`Decoder`](super::Decoder) instead of decoding, so the parsing,
+/// slot assignment and output order can be tested without a video device.
+///
+/// Calls out of order, slots out of range and references to slots without a picture are
+/// errors.
+#[derive(Clone, Debug)]
+pub struct MockBackend {
+    /// Returned for every stream
+    pub capabilities: BackendCapabilities,
+    pub calls: Vec<Call>,
+    /// The picture in each slot of the session, numbered like [`MockFrame::picture`]
+    pub slots: Vec<Option<usize>>,
+    /// The slot being decoded into between begin and end of coding
+    coding: Option<usize>,
+    pictures: usize,
+}
+
+impl Default for MockBackend {
+    /// Slots for 16 references and 16 reordered pictures, enough for any stream.
+    fn default() -> Self {
+        Self::new(BackendCapabilities {
+            max_dpb_slots: 33,
+            max_active_reference_pictures: 16,
+        })
+    }
+}
+
+impl MockBackend {
+    pub fn new(capabilities: BackendCapabilities) -> Self {
+        Self {
+            capabilities,
+            calls: Vec::new(),
+            slots: Vec::new(),
+            coding: None,
+            pictures: 0,
+        }
+    }
+
+    /// The pictures decoded so far, in decode order.
+    pub fn decodes(
+        &self,
+    ) -> impl Iterator<Item = (&PictureInfo, &ReferenceSlot, &[ReferenceSlot])> {
+        self.calls.iter().filter_map(|call| match call {
+            Call::Decode {
+                picture,
+                setup_slot,
+                reference_slots,
+                ..
+            } => Some((picture, setup_slot, &reference_slots[..])),
+            _ => None,
+        })
+    }
+
+    fn check_slot(&self, slot: usize) -> Result<()> {
+        if slot < self.slots.len() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Slot {} out of the {} of the session",
+                slot,
+                self.slots.len()
+            ))
+        }
+    }
+
+    /// The references must hold pictures and can not be decoded into.
+    fn check_references(&self, reference_slots: &[ReferenceSlot], setup_slot: usize) -> Result<()> {
+        self.check_slot(setup_slot)?;
+        for reference in reference_slots {
+            self.check_slot(reference.slot)?;
+            if reference.slot == setup_slot {
+                return Err(anyhow!(
+                    "Slot {} is both reference and setup slot",
+                    setup_slot
+                ));
+            }
+            if self.slots[reference.slot].is_none() {
+                return Err(anyhow!(
+                    "Reference slot {} holds no picture",
+                    reference.slot
+                ));
+            }
+        }
+        if reference_slots.len() > self.capabilities.max_active_reference_pictures as usize {
+            return Err(anyhow!("{} active references", reference_slots.len()));
+        }
+        Ok(())
+    }
+}
+
+impl DecodeBackend for MockBackend {
+    type Frame = MockFrame;
+
+    fn capabilities(&mut self, _parameter_sets: &ParameterSets) -> Result<BackendCapabilities> {
+        Ok(self.capabilities)
+    }
+
+    fn create_session(
+        &mut self,
+        _parameter_sets: &ParameterSets,
+        info: &SessionInfo,
+    ) -> Result<()> {
+        if info.dpb_slots > self.capabilities.max_dpb_slots {
+            return Err(anyhow!("{} DPB slots requested", info.dpb_slots));
+        }
+        self.calls.push(Call::CreateSession(*info));
+        self.slots = vec![None; info.dpb_slots as usize];
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, parameter_sets: &ParameterSets) -> Result<()> {
+        if self.coding.is_some() {
+            return Err(anyhow!("Parameters updated while coding"));
+        }
+        self.calls
+            .push(Call::UpdateParameters(parameter_sets.clone()));
+        Ok(())
+    }
+
+    fn begin_coding(&mut self, info: &BeginCodingInfo) -> Result<()> {
+        if self.coding.is_some() {
+            return Err(anyhow!("Coding begun twice"));
+        }
+        if info.reset {
+            self.slots.fill(None);
+        }
+        self.check_references(info.reference_slots, info.setup_slot)?;
+        self.calls.push(Call::BeginCoding {
+            reset: info.reset,
+            reference_slots: info.reference_slots.to_vec(),
+            setup_slot: info.setup_slot,
+        });
+        self.coding = Some(info.setup_slot);
+        Ok(())
+    }
+
+    fn decode(&mut self, info: &DecodeInfo) -> Result<()> {
+        if self.coding != Some(info.setup_slot.slot) {
+            return Err(anyhow!(
+                "Decode into slot {} outside of its begin and end of coding",
+                info.setup_slot.slot
+            ));
+        }
+        self.check_references(info.reference_slots, info.setup_slot.slot)?;
+        self.calls.push(Call::Decode {
+            picture: info.picture.clone(),
+            bitstream: info.bitstream.to_vec(),
+            setup_slot: info.setup_slot,
+            reference_slots: info.reference_slots.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn end_coding(&mut self) -> Result<()> {
+        let slot = self
+            .coding
+            .take()
+            .ok_or_else(|| anyhow!("Coding ended without beginning"))?;
+        self.calls.push(Call::EndCoding);
+        self.slots[slot] = Some(self.pictures);
+        self.pictures += 1;
+        Ok(())
+    }
+
+    fn frame(&mut self, slot: usize, pts: Timestamp) -> Result<MockFrame> {
+        self.check_slot(slot)?;
+        let picture = self.slots[slot].ok_or_else(|| anyhow!("Slot {} holds no picture", slot))?;
+        Ok(MockFrame { slot, picture, pts })
+    }
+}
@@ -0,0 +1,896 @@
+use std::mem;
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoDecodeH264PictureInfo, StdVideoDecodeH264ReferenceInfo, StdVideoDecodeH265PictureInfo,
+    StdVideoDecodeH265ReferenceInfo,
+};
+use ash::{vk, Device, Entry, Instance};
+
+use crate::av1::frame::FrameHeader;
+use crate::av1::obu::{ObuType, Obus};
+use crate::av1::tile_group::TileGroup;
+use crate::codec::{Codec, ParameterSets};
+use crate::h264::output::OutputQueue;
+use crate::h264::slice::{Mmco, SliceHeader};
+use crate::h264::NalUnits;
+use crate::timestamp::Timestamp;
+use crate::{av1, h264, h265};
+
+pub mod backend;
+pub mod mock;
+pub mod vulkan;
+
+pub use backend::DecodeBackend;
+use backend::{
+    BeginCodingInfo, DecodeInfo, PictureInfo, ReferenceInfo, ReferenceSlot, SessionInfo,
+};
+pub use vulkan::{DecodedFrame, VulkanBackend};
+
+/// Reference picture bookkeeping of the codec being decoded.
+enum Dpb {
+    Av1(Box<av1::dpb::Dpb>),
+    H264(h264::dpb::Dpb),
+    H265(h265::dpb::Dpb),
+}
+
+impl Dpb {
+    fn hold_slot(&mut self, slot: usize) {
+        match self {
+            Dpb::Av1(dpb) => dpb.hold_slot(slot),
+            Dpb::H264(dpb) => dpb.hold_slot(slot),
+            Dpb::H265(dpb) => dpb.hold_slot(slot),
+        }
+    }
+
+    fn release_slot(&mut self, slot: usize) {
+        match self {
+            Dpb::Av1(dpb) => dpb.release_slot(slot),
+            Dpb::H264(dpb) => dpb.release_slot(slot),
+            Dpb::H265(dpb) => dpb.release_slot(slot),
+        }
+    }
+
+    /// H.264 and AV1 streams restart with an IDR picture or key frame anyway.
+    fn end_sequence(&mut self) {
+        if let Dpb::H265(dpb) = self {
+            dpb.end_sequence();
+        }
+    }
+}
+
+/// A picture decoded into a DPB slot, as far as output is concerned.
+struct DecodedPicture {
+    slot: usize,
+    pic_order_cnt: i32,
+    /// The picture starts a new sequence, earlier pictures are output first
+    new_sequence: bool,
+    output: bool,
+}
+
+/// What decoding a sequence takes, from its SPS or sequence header.
+#[derive(Clone, Copy, Debug)]
+struct SequenceLimits {
+    coded_extent: (u32, u32),
+    max_num_ref_frames: u32,
+    max_num_reorder_frames: u32,
+    max_dec_frame_buffering: u32,
+}
+
+impl SequenceLimits {
+    fn av1(sequence_header: &av1::sequence::SequenceHeader) -> Result<Self> {
+        let color_config = &sequence_header.color_config;
+        if color_config.mono_chrome
+            || !color_config.subsampling_x
+            || !color_config.subsampling_y
+            || !matches!(color_config.bit_depth, 8 | 10)
+        {
+            return Err(anyhow!(
+                "Only 8 and 10 bit 4:2:0 AV1 streams are supported, got seq_profile {} bit depth {}",
+                sequence_header.seq_profile,
+                color_config.bit_depth
+            ));
+        }
+        // The shown frame may be held outside of the eight reference slots while the next
+        // one decodes. Frames are output in decode order.
+        Ok(Self {
+            coded_extent: sequence_header.coded_extent(),
+            max_num_ref_frames: av1::NUM_REF_FRAMES as u32 + 1,
+            max_num_reorder_frames: 0,
+            max_dec_frame_buffering: 1,
+        })
+    }
+
+    fn h264(sps: &h264::sps::Sps) -> Result<Self> {
+        if sps.chroma_format_idc != 1
+            || sps.bit_depth_luma_minus8 != 0
+            || sps.bit_depth_chroma_minus8 != 0
+        {
+            return Err(anyhow!(
+                "Only 8 bit 4:2:0 H.264 streams are supported, got chroma_format_idc {} bit depth {}",
+                sps.chroma_format_idc,
+                sps.bit_depth_luma_minus8.max(sps.bit_depth_chroma_minus8) + 8
+            ));
+        }
+        Ok(Self {
+            coded_extent: sps.coded_extent(),
+            max_num_ref_frames: sps.max_num_ref_frames as u32,
+            max_num_reorder_frames: sps.max_num_reorder_frames(),
+            max_dec_frame_buffering: sps.max_dec_frame_buffering(),
+        })
+    }
+
+    fn h265(sps: &h265::sps::Sps) -> Result<Self> {
+        if sps.chroma_format_idc != 1
+            || !matches!(sps.bit_depth_luma_minus8, 0 | 2)
+            || sps.bit_depth_chroma_minus8 != sps.bit_depth_luma_minus8
+        {
+            return Err(anyhow!(
+                "Only 8 and 10 bit 4:2:0 H.265 streams are supported, got chroma_format_idc {} bit depth {}",
+                sps.chroma_format_idc,
+                sps.bit_depth_luma_minus8 + 8
+            ));
+        }
+        // sps_max_dec_pic_buffering counts the current picture as well
+        Ok(Self {
+            coded_extent: sps.coded_extent(),
+            max_num_ref_frames: sps.max_dec_pic_buffering() - 1,
+            max_num_reorder_frames: sps.max_num_reorder_pics(),
+            max_dec_frame_buffering: sps.max_dec_pic_buffering(),
+        })
+    }
+}
+
+/// H.264, H.265 and AV1 decoder. Parses the stream, keeps the DPB and puts the pictures
+/// in display order; the pictures themselves are decoded by a [`DecodeBackend`], by
+/// default on a Vulkan video decode queue.
+pub struct Decoder<B: DecodeBackend = VulkanBackend> {
+    backend: B,
+    /// A new session has to be reset before its first decode
+    reset_pending: bool,
+    parameter_sets: ParameterSets,
+    dpb: Dpb,
+    output_queue: OutputQueue<(usize, B::Frame)>,
+    /// Slots of the frames last returned, held until the next call
+    output_slots: Vec<usize>,
+    /// The sequence the session was created for, later ones have to fit it
+    limits: SequenceLimits,
+}
+
+impl Decoder<VulkanBackend> {
+    /// The largest coded extent `pdevice` decodes `codec` at, in the profile most streams
+    /// use. Lets a player pick among variants of a stream before it has their parameter
+    /// sets.
+    ///
+    /// # Safety
+    ///
+    /// `instance`, `device` and `pdevice` must be valid and belong together.
+    pub unsafe fn max_coded_extent(
+        entry: &Entry,
+        instance: &Instance,
+        device: &Device,
+        pdevice: vk::PhysicalDevice,
+        codec: Codec,
+    ) -> Result<vk::Extent2D> {
+        VulkanBackend::max_coded_extent(entry, instance, device, pdevice, codec)
+    }
+
+    /// Creates a decoder on `queue` for the codec of `parameter_sets`, sized for their
+    /// first SPS. See [`VulkanBackend`] for the extensions the device needs.
+    ///
+    /// # Safety
+    ///
+    /// `instance`, `device`, `pdevice` and `queue` must be valid and belong together,
+    /// and the device must outlive the decoder.
+    pub unsafe fn new(
+        entry: &Entry,
+        instance: &Instance,
+        device: &Device,
+        pdevice: vk::PhysicalDevice,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        parameter_sets: &ParameterSets,
+    ) -> Result<Self> {
+        let backend =
+            VulkanBackend::new(entry, instance, device, pdevice, queue_family_index, queue)?;
+        Self::with_backend(backend, parameter_sets)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.backend.extent()
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.backend.format()
+    }
+
+    /// See [`VulkanBackend::use_frame`].
+    ///
+    /// # Safety
+    ///
+    /// `frame` must be the last frame returned by the decoder, `queue` must belong to the
+    /// decoder's device and `command_buffer` must be resettable and not in use.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn use_frame<F: FnOnce(&Device, vk::CommandBuffer)>(
+        &mut self,
+        frame: &DecodedFrame,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+        wait_mask: &[vk::PipelineStageFlags],
+        wait_semaphores: &[vk::Semaphore],
+        signal_semaphores: &[vk::Semaphore],
+        record: F,
+    ) -> Result<()> {
+        self.backend.use_frame(
+            frame,
+            queue_family_index,
+            queue,
+            command_buffer,
+            layout,
+            wait_mask,
+            wait_semaphores,
+            signal_semaphores,
+            record,
+        )
+    }
+}
+
+impl<B: DecodeBackend> Decoder<B> {
+    /// Creates the session of `backend` for the codec of `parameter_sets`, sized for their
+    /// first SPS.
+    pub fn with_backend(mut backend: B, parameter_sets: &ParameterSets) -> Result<Self> {
+        let no_sps = || anyhow!("No sequence parameter set to create the decoder for");
+        let limits = match parameter_sets {
+            ParameterSets::Av1(sequence_header) => SequenceLimits::av1(sequence_header)?,
+            ParameterSets::H264(parameter_sets) => SequenceLimits::h264(
+                (0..h264::sps::MAX_SPS_COUNT as u8)
+                    .find_map(|id| parameter_sets.sps(id))
+                    .ok_or_else(no_sps)?,
+            )?,
+            ParameterSets::H265(parameter_sets) => SequenceLimits::h265(
+                (0..h265::sps::MAX_SPS_COUNT as u8)
+                    .find_map(|id| parameter_sets.sps(id))
+                    .ok_or_else(no_sps)?,
+            )?,
+        };
+
+        let capabilities = backend.capabilities(parameter_sets)?;
+        // The current picture needs a slot next to its references and the pictures waiting
+        // for output. Intra-only streams still keep their last reference picture around.
+        let max_num_ref_frames = limits.max_num_ref_frames.max(1);
+        let max_active_reference_pictures =
+            max_num_ref_frames.min(capabilities.max_active_reference_pictures);
+        // Without bitstream restrictions up to 16 pictures may be reordered, more than
+        // implementations usually have slots for. Output order suffers instead of decoding.
+        let max_num_reorder_frames = limits.max_num_reorder_frames.min(
+            capabilities
+                .max_dpb_slots
+                .saturating_sub(max_num_ref_frames + 1),
+        );
+        let dpb_slots =
+            (max_num_ref_frames + max_num_reorder_frames + 1).min(capabilities.max_dpb_slots);
+
+        backend.create_session(
+            parameter_sets,
+            &SessionInfo {
+                codec: parameter_sets.codec(),
+                coded_extent: limits.coded_extent,
+                dpb_slots,
+                max_active_reference_pictures,
+            },
+        )?;
+        backend.update_parameters(parameter_sets)?;
+
+        Ok(Self {
+            backend,
+            reset_pending: true,
+            parameter_sets: parameter_sets.clone(),
+            dpb: match parameter_sets {
+                ParameterSets::Av1(_) => Dpb::Av1(Box::new(av1::dpb::Dpb::new(dpb_slots as usize))),
+                ParameterSets::H264(_) => Dpb::H264(h264::dpb::Dpb::new(dpb_slots as usize)),
+                ParameterSets::H265(_) => Dpb::H265(h265::dpb::Dpb::new(dpb_slots as usize)),
+            },
+            output_queue: OutputQueue::new(
+                max_num_reorder_frames as usize,
+                limits.max_dec_frame_buffering as usize,
+            ),
+            output_slots: Vec::new(),
+            limits,
+        })
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn parameter_sets(&self) -> &ParameterSets {
+        &self.parameter_sets
+    }
+
+    /// Decodes one access unit, Annex-B formatted for H.264 and H.265 and a temporal unit
+    /// of OBUs for AV1. Parameter sets and sequence headers in it are picked up on the way.
+    /// Returns the frames due for display, in display order, which is none while pictures
+    /// wait to be reordered. A new SPS or sequence header that changes the coded size or
+    /// needs more reference frames than the decoder was created for is an error.
+    ///
+    /// Blocks until the picture is decoded.
+    pub fn decode(&mut self, access_unit: &[u8], pts: Timestamp) -> Result<Vec<B::Frame>> {
+        self.release_output_slots();
+
+        let codec = self.parameter_sets.codec();
+        let previous_parameter_sets =
+            access_unit_has_parameter_sets(codec, access_unit).then(|| self.parameter_sets.clone());
+
+        let picture = unsafe {
+            match codec {
+                Codec::Av1 => self.decode_av1(access_unit, previous_parameter_sets)?,
+                Codec::H264 => self.decode_h264(access_unit, previous_parameter_sets)?,
+                Codec::H265 => self.decode_h265(access_unit, previous_parameter_sets)?,
+            }
+        };
+
+        let mut output = Vec::new();
+        match picture {
+            Some(picture) if picture.output => {
+                let frame = self.backend.frame(picture.slot, pts)?;
+
+                // The slot must not be decoded into before the frame is displayed
+                self.dpb.hold_slot(picture.slot);
+                output = self.output_queue.push(
+                    picture.pic_order_cnt,
+                    picture.new_sequence,
+                    (picture.slot, frame),
+                );
+            }
+            Some(picture) if picture.new_sequence => output = self.output_queue.flush(),
+            _ => {}
+        }
+        if access_unit_ends_sequence(codec, access_unit) {
+            output.extend(self.output_queue.flush());
+            self.dpb.end_sequence();
+        }
+        self.output_slots = output.iter().map(|&(slot, _)| slot).collect();
+
+        Ok(output.into_iter().map(|(_, frame)| frame).collect())
+    }
+
+    /// Decodes the frames of an AV1 temporal unit, returning the shown one. `None` when the
+    /// temporal unit shows no frame.
+    unsafe fn decode_av1(
+        &mut self,
+        temporal_unit: &[u8],
+        previous_parameter_sets: Option<ParameterSets>,
+    ) -> Result<Option<DecodedPicture>> {
+        let ParameterSets::Av1(sequence_header) = &mut self.parameter_sets else {
+            unreachable!()
+        };
+        for obu in Obus::new(temporal_unit) {
+            let obu = obu?;
+            if obu.header.obu_type == ObuType::SequenceHeader {
+                *sequence_header = av1::sequence::SequenceHeader::parse(obu.data)?;
+            }
+        }
+        if previous_parameter_sets.is_some() {
+            let limits = SequenceLimits::av1(sequence_header)?;
+            self.check_sequence(&limits)?;
+        }
+        self.update_session_parameters(previous_parameter_sets)?;
+
+        let mut shown = None;
+        // The frame waiting for its tile groups with the offset of its frame header OBU,
+        // the tiles so far relative to that offset
+        let mut frame: Option<(FrameHeader, usize)> = None;
+        let mut tile_offsets = Vec::new();
+        let mut tile_sizes = Vec::new();
+        for obu in Obus::new(temporal_unit) {
+            let obu = obu?;
+            let payload_offset = obu.offset + obu.size - obu.data.len();
+            // Frame header OBUs repeated while the tile groups come in are redundant copies
+            let (tile_group_data, tile_group_offset) = match obu.header.obu_type {
+                ObuType::FrameHeader | ObuType::Frame if frame.is_none() => {
+                    let (ParameterSets::Av1(sequence_header), Dpb::Av1(dpb)) =
+                        (&self.parameter_sets, &mut self.dpb)
+                    else {
+                        unreachable!()
+                    };
+                    let header = FrameHeader::parse(
+                        obu.data,
+                        sequence_header,
+                        &obu.header,
+                        dpb.references(),
+                    )?;
+                    if header.show_existing_frame {
+                        shown = Some(DecodedPicture {
+                            slot: dpb.show_existing_frame(&header)?,
+                            pic_order_cnt: 0,
+                            new_sequence: header.frame_type == av1::frame::FrameType::Key,
+                            output: true,
+                        });
+                        continue;
+                    }
+
+                    tile_offsets.clear();
+                    tile_sizes.clear();
+                    let header_bytes = header.header_bytes;
+                    frame = Some((header, obu.offset));
+                    if obu.header.obu_type == ObuType::FrameHeader {
+                        continue;
+                    }
+                    (&obu.data[header_bytes..], payload_offset + header_bytes)
+                }
+                ObuType::TileGroup if frame.is_some() => (obu.data, payload_offset),
+                _ => continue,
+            };
+
+            let (header, frame_offset) = frame.as_ref().unwrap();
+            let tile_group = TileGroup::parse(tile_group_data, &header.tile_info)?;
+            for tile in &tile_group.tiles {
+                tile_offsets.push((tile_group_offset + tile.offset - frame_offset) as u32);
+                tile_sizes.push(tile.size as u32);
+            }
+            if tile_group.is_last(&header.tile_info) {
+                let (header, frame_offset) = frame.take().unwrap();
+                let picture = self.decode_av1_frame(
+                    &header,
+                    &temporal_unit[frame_offset..obu.offset + obu.size],
+                    &tile_offsets,
+                    &tile_sizes,
+                )?;
+                if picture.output {
+                    shown = Some(picture);
+                }
+            }
+        }
+
+        Ok(shown)
+    }
+
+    /// Decodes one AV1 frame. `data` runs from its frame header OBU to the end of its last
+    /// tile group, the tile offsets are relative to it.
+    unsafe fn decode_av1_frame(
+        &mut self,
+        header: &FrameHeader,
+        data: &[u8],
+        tile_offsets: &[u32],
+        tile_sizes: &[u32],
+    ) -> Result<DecodedPicture> {
+        let Dpb::Av1(dpb) = &mut self.dpb else {
+            unreachable!()
+        };
+        let slot = dpb.start_frame(header)?;
+
+        // Current frame. Film grain is up to the application, the profile leaves it out.
+        let mut std_picture_info = header.to_std();
+        std_picture_info.info.flags.set_apply_grain(0);
+        std_picture_info.info.pFilmGrain = ptr::null();
+
+        // Reference frames by name, several names may share a slot
+        let mut reference_name_slot_indices = [-1; av1::REFS_PER_FRAME];
+        let mut reference_slots: Vec<ReferenceSlot> = Vec::new();
+        if !header.frame_type.is_intra() {
+            for (slot_index, &idx) in reference_name_slot_indices
+                .iter_mut()
+                .zip(&header.ref_frame_idx)
+            {
+                // Present, checked by start_frame
+                let reference = dpb.references()[idx as usize].as_ref().unwrap();
+                *slot_index = reference.slot as i32;
+                if !reference_slots
+                    .iter()
+                    .any(|reference_slot| reference_slot.slot == reference.slot)
+                {
+                    reference_slots.push(ReferenceSlot {
+                        slot: reference.slot,
+                        info: ReferenceInfo::Av1(reference.to_std_reference_info()),
+                    });
+                }
+            }
+        }
+
+        let picture_info = PictureInfo::Av1 {
+            std_picture_info: std_picture_info.info,
+            reference_name_slot_indices,
+            tile_offsets: tile_offsets.to_vec(),
+            tile_sizes: tile_sizes.to_vec(),
+        };
+
+        // The frame is reconstructed into the slot picked by the DPB
+        let setup_slot = ReferenceSlot {
+            slot,
+            info: ReferenceInfo::Av1(header.to_std_reference_info()),
+        };
+
+        self.decode_picture(&picture_info, data, setup_slot, &reference_slots)?;
+
+        let Dpb::Av1(dpb) = &mut self.dpb else {
+            unreachable!()
+        };
+        dpb.finish_frame(header, slot);
+
+        Ok(DecodedPicture {
+            slot,
+            pic_order_cnt: 0,
+            new_sequence: header.frame_type == av1::frame::FrameType::Key && header.show_frame,
+            output: header.show_frame,
+        })
+    }
+
+    /// Decodes the picture of an H.264 access unit, `None` when it has no slices.
+    unsafe fn decode_h264(
+        &mut self,
+        access_unit: &[u8],
+        previous_parameter_sets: Option<ParameterSets>,
+    ) -> Result<Option<DecodedPicture>> {
+        let ParameterSets::H264(parameter_sets) = &mut self.parameter_sets else {
+            unreachable!()
+        };
+        let slices = h264::slice::parse_slices(access_unit, parameter_sets)?;
+        if let Some(first) = slices.first() {
+            let (_, sps) = parameter_sets.active(first.header.pic_parameter_set_id)?;
+            let limits = SequenceLimits::h264(sps)?;
+            self.check_sequence(&limits)?;
+        }
+        self.update_session_parameters(previous_parameter_sets)?;
+
+        let (first, last) = match (slices.first(), slices.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(None),
+        };
+        let header = &first.header;
+        let (ParameterSets::H264(parameter_sets), Dpb::H264(dpb)) =
+            (&self.parameter_sets, &mut self.dpb)
+        else {
+            unreachable!()
+        };
+        let (pps, sps) = parameter_sets.active(header.pic_parameter_set_id)?;
+        let picture = dpb.start_picture(header, sps)?;
+
+        // Current picture
+        let mut std_picture_info: StdVideoDecodeH264PictureInfo = mem::zeroed();
+        std_picture_info
+            .flags
+            .set_field_pic_flag(header.field_pic_flag as u32);
+        std_picture_info
+            .flags
+            .set_bottom_field_flag(header.bottom_field_flag as u32);
+        std_picture_info.flags.set_is_intra(
+            slices
+                .iter()
+                .all(|slice| slice.header.slice_type.is_intra()) as u32,
+        );
+        std_picture_info
+            .flags
+            .set_IdrPicFlag(header.idr_pic_flag as u32);
+        std_picture_info
+            .flags
+            .set_is_reference(header.is_reference() as u32);
+        std_picture_info.seq_parameter_set_id = sps.seq_parameter_set_id;
+        std_picture_info.pic_parameter_set_id = pps.pic_parameter_set_id;
+        std_picture_info.frame_num = header.frame_num as u16;
+        std_picture_info.idr_pic_id = header.idr_pic_id as u16;
+        std_picture_info.PicOrderCnt =
+            [picture.top_field_order_cnt, picture.bottom_field_order_cnt];
+
+        let picture_info = PictureInfo::H264 {
+            std_picture_info,
+            slice_offsets: h264::slice::slice_offsets(&slices),
+        };
+
+        // Reference pictures. The frames inferred for a gap in frame_num have no slot,
+        // a stream referring to them is broken anyway.
+        let reference_slots: Vec<_> = dpb
+            .references()
+            .iter()
+            .filter_map(|frame| {
+                let mut std_reference_info: StdVideoDecodeH264ReferenceInfo = mem::zeroed();
+                std_reference_info
+                    .flags
+                    .set_used_for_long_term_reference(frame.is_long_term() as u32);
+                // LongTermFrameIdx for long-term references
+                std_reference_info.FrameNum = match frame.long_term_frame_idx {
+                    Some(long_term_frame_idx) => long_term_frame_idx as u16,
+                    None => frame.frame_num as u16,
+                };
+                std_reference_info.PicOrderCnt =
+                    [frame.top_field_order_cnt, frame.bottom_field_order_cnt];
+                Some(ReferenceSlot {
+                    slot: frame.slot?,
+                    info: ReferenceInfo::H264(std_reference_info),
+                })
+            })
+            .collect();
+
+        // The picture is reconstructed into the slot picked by the DPB
+        let mut std_reference_info: StdVideoDecodeH264ReferenceInfo = mem::zeroed();
+        std_reference_info
+            .flags
+            .set_used_for_long_term_reference(is_marked_long_term(header) as u32);
+        std_reference_info.FrameNum = header.frame_num as u16;
+        std_reference_info.PicOrderCnt = std_picture_info.PicOrderCnt;
+        let setup_slot = ReferenceSlot {
+            slot: picture.slot,
+            info: ReferenceInfo::H264(std_reference_info),
+        };
+
+        // Only the slices are decoded, the offsets are relative to the first one
+        self.decode_picture(
+            &picture_info,
+            &access_unit[first.offset..last.offset + last.size],
+            setup_slot,
+            &reference_slots,
+        )?;
+
+        let (ParameterSets::H264(parameter_sets), Dpb::H264(dpb)) =
+            (&self.parameter_sets, &mut self.dpb)
+        else {
+            unreachable!()
+        };
+        let (_, sps) = parameter_sets.active(header.pic_parameter_set_id)?;
+        let picture = dpb.finish_picture(header, sps, picture)?;
+
+        Ok(Some(DecodedPicture {
+            slot: picture.slot,
+            pic_order_cnt: picture.pic_order_cnt(),
+            new_sequence: picture.idr || picture.has_mmco5,
+            output: true,
+        }))
+    }
+
+    /// Decodes the picture of an H.265 access unit, `None` when it has no slice segments
+    /// or the picture can not be decoded, see [`h265::dpb::Dpb::start_picture`].
+    unsafe fn decode_h265(
+        &mut self,
+        access_unit: &[u8],
+        previous_parameter_sets: Option<ParameterSets>,
+    ) -> Result<Option<DecodedPicture>> {
+        let ParameterSets::H265(parameter_sets) = &mut self.parameter_sets else {
+            unreachable!()
+        };
+        let segments = h265::slice::parse_slice_segments(access_unit, parameter_sets)?;
+        if let Some(first) = segments.first() {
+            let (_, sps) = parameter_sets.active(first.header.slice_pic_parameter_set_id)?;
+            let limits = SequenceLimits::h265(sps)?;
+            self.check_sequence(&limits)?;
+        }
+        self.update_session_parameters(previous_parameter_sets)?;
+
+        let (first, last) = match (segments.first(), segments.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(None),
+        };
+        let header = &first.header;
+        let (ParameterSets::H265(parameter_sets), Dpb::H265(dpb)) =
+            (&self.parameter_sets, &mut self.dpb)
+        else {
+            unreachable!()
+        };
+        let (pps, sps) = parameter_sets.active(header.slice_pic_parameter_set_id)?;
+        let picture = match dpb.start_picture(header, sps)? {
+            Some(picture) => picture,
+            None => return Ok(None),
+        };
+
+        // Current picture
+        let mut std_picture_info: StdVideoDecodeH265PictureInfo = mem::zeroed();
+        std_picture_info.flags.set_IrapPicFlag(picture.irap as u32);
+        std_picture_info.flags.set_IdrPicFlag(picture.idr as u32);
+        std_picture_info
+            .flags
+            .set_IsReference(picture.is_reference as u32);
+        std_picture_info
+            .flags
+            .set_short_term_ref_pic_set_sps_flag(header.short_term_ref_pic_set_sps_flag as u32);
+        std_picture_info.sps_video_parameter_set_id = sps.sps_video_parameter_set_id;
+        std_picture_info.pps_seq_parameter_set_id = sps.sps_seq_parameter_set_id;
+        std_picture_info.pps_pic_parameter_set_id = pps.pps_pic_parameter_set_id;
+        std_picture_info.NumDeltaPocsOfRefRpsIdx = header.num_delta_pocs_of_ref_rps_idx(sps) as u8;
+        std_picture_info.PicOrderCntVal = picture.pic_order_cnt;
+        std_picture_info.NumBitsForSTRefPicSetInSlice = header.st_ref_pic_set_bits as u16;
+        // DPB slots of the pictures used by the current one, 0xff for "no reference picture"
+        for (std_slots, slots) in [
+            (
+                &mut std_picture_info.RefPicSetStCurrBefore,
+                &picture.st_curr_before,
+            ),
+            (
+                &mut std_picture_info.RefPicSetStCurrAfter,
+                &picture.st_curr_after,
+            ),
+            (&mut std_picture_info.RefPicSetLtCurr, &picture.lt_curr),
+        ] {
+            std_slots.fill(0xff);
+            for (std_slot, slot) in std_slots.iter_mut().zip(slots) {
+                *std_slot = slot.map_or(0xff, |slot| slot as u8);
+            }
+        }
+
+        let picture_info = PictureInfo::H265 {
+            std_picture_info,
+            slice_segment_offsets: h265::slice::slice_segment_offsets(&segments),
+        };
+
+        // Reference pictures, including those only kept for following pictures
+        let reference_slots: Vec<_> = dpb
+            .references()
+            .iter()
+            .map(|reference| {
+                let mut std_reference_info: StdVideoDecodeH265ReferenceInfo = mem::zeroed();
+                std_reference_info
+                    .flags
+                    .set_used_for_long_term_reference(reference.long_term as u32);
+                std_reference_info.PicOrderCntVal = reference.pic_order_cnt;
+                ReferenceSlot {
+                    slot: reference.slot,
+                    info: ReferenceInfo::H265(std_reference_info),
+                }
+            })
+            .collect();
+
+        // The picture is reconstructed into the slot picked by the DPB
+        let mut std_reference_info: StdVideoDecodeH265ReferenceInfo = mem::zeroed();
+        std_reference_info.PicOrderCntVal = picture.pic_order_cnt;
+        let setup_slot = ReferenceSlot {
+            slot: picture.slot,
+            info: ReferenceInfo::H265(std_reference_info),
+        };
+
+        // Only the slice segments are decoded, the offsets are relative to the first one
+        self.decode_picture(
+            &picture_info,
+            &access_unit[first.offset..last.offset + last.size],
+            setup_slot,
+            &reference_slots,
+        )?;
+
+        let Dpb::H265(dpb) = &mut self.dpb else {
+            unreachable!()
+        };
+        dpb.finish_picture(&picture);
+
+        Ok(Some(DecodedPicture {
+            slot: picture.slot,
+            pic_order_cnt: picture.pic_order_cnt,
+            new_sequence: picture.no_rasl_output_flag,
+            output: picture.pic_output_flag,
+        }))
+    }
+
+    /// Checks that a sequence fits the session, whose pictures are decoded at its coded size
+    /// into a DPB sized for the reference frames of the first sequence. Recreating the
+    /// session would take the images from under the frames still waiting for display, so
+    /// a stream that changes its size needs a new decoder.
+    fn check_sequence(&self, limits: &SequenceLimits) -> Result<()> {
+        if limits.coded_extent != self.limits.coded_extent {
+            return Err(anyhow!(
+                "Coded size changed from {}x{} to {}x{}",
+                self.limits.coded_extent.0,
+                self.limits.coded_extent.1,
+                limits.coded_extent.0,
+                limits.coded_extent.1
+            ));
+        }
+        if limits.max_num_ref_frames > self.limits.max_num_ref_frames.max(1) {
+            return Err(anyhow!(
+                "Reference frames increased from {} to {}",
+                self.limits.max_num_ref_frames,
+                limits.max_num_ref_frames
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Has the backend pick up the parameter sets if the access unit changed them.
+    fn update_session_parameters(
+        &mut self,
+        previous_parameter_sets: Option<ParameterSets>,
+    ) -> Result<()> {
+        // In-band parameter sets, usually repeated unchanged before every IDR picture
+        if previous_parameter_sets.is_some_and(|previous| previous != self.parameter_sets) {
+            self.backend.update_parameters(&self.parameter_sets)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decodes `bitstream` into the setup slot, resetting a new session first.
+    fn decode_picture(
+        &mut self,
+        picture: &PictureInfo,
+        bitstream: &[u8],
+        setup_slot: ReferenceSlot,
+        reference_slots: &[ReferenceSlot],
+    ) -> Result<()> {
+        self.backend.begin_coding(&BeginCodingInfo {
+            reset: self.reset_pending,
+            reference_slots,
+            setup_slot: setup_slot.slot,
+        })?;
+        self.reset_pending = false;
+        self.backend.decode(&DecodeInfo {
+            picture,
+            bitstream,
+            setup_slot,
+            reference_slots,
+        })?;
+        self.backend.end_coding()
+    }
+
+    /// Returns the frames still waiting for display, at the end of the stream.
+    pub fn flush(&mut self) -> Vec<B::Frame> {
+        self.release_output_slots();
+
+        let output = self.output_queue.flush();
+        self.output_slots = output.iter().map(|&(slot, _)| slot).collect();
+        output.into_iter().map(|(_, frame)| frame).collect()
+    }
+
+    /// The frames returned last are no longer in use once the caller comes back.
+    fn release_output_slots(&mut self) {
+        for slot in self.output_slots.drain(..) {
+            self.dpb.release_slot(slot);
+        }
+    }
+}
+
+/// Whether the H.264 picture is marked as long-term reference right after decoding.
+fn is_marked_long_term(header: &SliceHeader) -> bool {
+    header.dec_ref_pic_marking.as_ref().is_some_and(|marking| {
+        marking.long_term_reference_flag
+            || marking
+                .mmco
+                .iter()
+                .any(|mmco| matches!(mmco, Mmco::CurrentToLongTerm { .. }))
+    })
+}
+
+fn access_unit_ends_sequence(codec: Codec, access_unit: &[u8]) -> bool {
+    let mut nal_units = NalUnits::new(access_unit);
+    match codec {
+        // Temporal delimiters only separate temporal units, a new sequence starts at a key frame
+        Codec::Av1 => false,
+        Codec::H264 => nal_units.any(|nal| {
+            matches!(
+                h264::NalUnitHeader::parse(nal.data).map(|header| header.nal_unit_type),
+                Ok(h264::NalUnitType::EndOfSequence | h264::NalUnitType::EndOfStream)
+            )
+        }),
+        Codec::H265 => nal_units.any(|nal| {
+            matches!(
+                h265::NalUnitHeader::parse(nal.data).map(|header| header.nal_unit_type),
+                Ok(h265::NalUnitType::EndOfSequence | h265::NalUnitType::EndOfBitstream)
+            )
+        }),
+    }
+}
+
+fn access_unit_has_parameter_sets(codec: Codec, access_unit: &[u8]) -> bool {
+    let mut nal_units = NalUnits::new(access_unit);
+    match codec {
+        Codec::Av1 => Obus::new(access_unit).any(|obu| {
+            matches!(
+                obu.map(|obu| obu.header.obu_type),
+                Ok(ObuType::SequenceHeader)
+            )
+        }),
+        Codec::H264 => nal_units.any(|nal| {
+            matches!(
+                h264::NalUnitHeader::parse(nal.data).map(|header| header.nal_unit_type),
+                Ok(h264::NalUnitType::Sps | h264::NalUnitType::Pps)
+            )
+        }),
+        Codec::H265 => nal_units.any(|nal| {
+            matches!(
+                h265::NalUnitHeader::parse(nal.data).map(|header| header.nal_unit_type),
+                Ok(h265::NalUnitType::Vps | h265::NalUnitType::Sps | h265::NalUnitType::Pps)
+            )
+        }),
+    }
+}
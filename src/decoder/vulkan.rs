@@ -0,0 +1,1408 @@
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use ash::extensions::khr::{VideoDecodeQueue, VideoQueue};
+use ash::vk::native::{
+    StdVideoAV1Profile, StdVideoAV1Profile_STD_VIDEO_AV1_PROFILE_MAIN, StdVideoH264ProfileIdc,
+    StdVideoH264ProfileIdc_STD_VIDEO_H264_PROFILE_IDC_HIGH, StdVideoH265ProfileIdc,
+    StdVideoH265ProfileIdc_STD_VIDEO_H265_PROFILE_IDC_MAIN,
+};
+use ash::{vk, Device, Entry, Instance};
+
+use super::backend::{
+    BackendCapabilities, BeginCodingInfo, DecodeBackend, DecodeInfo, PictureInfo, ReferenceInfo,
+    SessionInfo,
+};
+use crate::codec::{Codec, ParameterSets};
+use crate::timestamp::Timestamp;
+use crate::{av1, find_memorytype_index, find_video_format, h264, h265};
+
+/// A decoded picture. The image stays in its video decode layout, owned by the decode
+/// queue family, and is only valid until the next call to
+/// [`Decoder::decode`](super::Decoder::decode) or [`Decoder::flush`](super::Decoder::flush).
+/// Other queues get at it through [`Decoder::use_frame`](super::Decoder::use_frame).
+#[derive(Clone, Copy, Debug)]
+pub struct DecodedFrame {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    /// Array layer of `image` holding the picture
+    pub array_layer: u32,
+    pub format: vk::Format,
+    /// Coded size, see [`StreamInfo::crop_rect`](crate::codec::StreamInfo::crop_rect)
+    /// for the visible area
+    pub extent: vk::Extent2D,
+    pub pts: Timestamp,
+}
+
+/// The subset of `VkVideoCapabilitiesKHR` the decoder needs once the query is done.
+#[derive(Clone, Copy, Debug)]
+struct Capabilities {
+    decode_flags: vk::VideoDecodeCapabilityFlagsKHR,
+    min_bitstream_buffer_offset_alignment: u64,
+    min_bitstream_buffer_size_alignment: u64,
+    picture_access_granularity: vk::Extent2D,
+    min_coded_extent: vk::Extent2D,
+    max_coded_extent: vk::Extent2D,
+    max_dpb_slots: u32,
+    max_active_reference_pictures: u32,
+    std_header_version: vk::ExtensionProperties,
+}
+
+/// The codec specific part of the decode profile.
+enum CodecProfile {
+    Av1(Box<vk::VideoDecodeAV1ProfileInfoKHR<'static>>),
+    H264(Box<vk::VideoDecodeH264ProfileInfoKHR<'static>>),
+    H265(Box<vk::VideoDecodeH265ProfileInfoKHR<'static>>),
+}
+
+/// Decode profile of the stream. The structures are boxed because they point at each
+/// other and the session, image and buffer create infos point at the list.
+struct VideoProfile {
+    _codec: CodecProfile,
+    info: Box<[vk::VideoProfileInfoKHR<'static>; 1]>,
+    list: Box<vk::VideoProfileListInfoKHR<'static>>,
+}
+
+impl VideoProfile {
+    /// The profile of the first sequence header or SPS of `parameter_sets`.
+    fn for_stream(parameter_sets: &ParameterSets) -> Result<Self> {
+        let no_sps = || anyhow!("No sequence parameter set to create the decoder for");
+        Ok(match parameter_sets {
+            ParameterSets::Av1(sequence_header) => Self::av1(sequence_header),
+            ParameterSets::H264(parameter_sets) => Self::h264(
+                (0..h264::sps::MAX_SPS_COUNT as u8)
+                    .find_map(|id| parameter_sets.sps(id))
+                    .ok_or_else(no_sps)?,
+            ),
+            ParameterSets::H265(parameter_sets) => Self::h265(
+                (0..h265::sps::MAX_SPS_COUNT as u8)
+                    .find_map(|id| parameter_sets.sps(id))
+                    .ok_or_else(no_sps)?,
+            ),
+        })
+    }
+
+    /// Main and High profile streams, without film grain application. 10 bit streams are
+    /// decoded with 10 bit components.
+    fn av1(sequence_header: &av1::sequence::SequenceHeader) -> Self {
+        let mut av1 = Box::new(
+            vk::VideoDecodeAV1ProfileInfoKHR::default()
+                .std_profile(sequence_header.seq_profile as StdVideoAV1Profile)
+                .film_grain_support(false),
+        );
+        let codec_info = &mut *av1 as *mut _ as *const c_void;
+
+        Self::new(
+            CodecProfile::Av1(av1),
+            codec_info,
+            Codec::Av1,
+            if sequence_header.color_config.bit_depth == 8 {
+                vk::VideoComponentBitDepthFlagsKHR::TYPE_8
+            } else {
+                vk::VideoComponentBitDepthFlagsKHR::TYPE_10
+            },
+        )
+    }
+
+    fn h264(sps: &h264::sps::Sps) -> Self {
+        let mut h264 = Box::new(
+            vk::VideoDecodeH264ProfileInfoKHR::default()
+                .std_profile_idc(sps.profile_idc as StdVideoH264ProfileIdc)
+                .picture_layout(if sps.frame_mbs_only_flag {
+                    vk::VideoDecodeH264PictureLayoutFlagsKHR::PROGRESSIVE
+                } else {
+                    vk::VideoDecodeH264PictureLayoutFlagsKHR::INTERLACED_INTERLEAVED_LINES
+                }),
+        );
+        let codec_info = &mut *h264 as *mut _ as *const c_void;
+
+        Self::new(
+            CodecProfile::H264(h264),
+            codec_info,
+            Codec::H264,
+            vk::VideoComponentBitDepthFlagsKHR::TYPE_8,
+        )
+    }
+
+    /// Main, Main 10 and the other profiles of the general profile_idc. Main 10 streams
+    /// are decoded with 10 bit components.
+    fn h265(sps: &h265::sps::Sps) -> Self {
+        let mut h265 = Box::new(
+            vk::VideoDecodeH265ProfileInfoKHR::default()
+                .std_profile_idc(sps.profile_tier_level.profile_idc() as StdVideoH265ProfileIdc),
+        );
+        let codec_info = &mut *h265 as *mut _ as *const c_void;
+
+        Self::new(
+            CodecProfile::H265(h265),
+            codec_info,
+            Codec::H265,
+            if sps.bit_depth_luma_minus8 == 0 {
+                vk::VideoComponentBitDepthFlagsKHR::TYPE_8
+            } else {
+                vk::VideoComponentBitDepthFlagsKHR::TYPE_10
+            },
+        )
+    }
+
+    /// The profile most streams of `codec` use, 8 bit High for H.264 and 8 bit Main for
+    /// H.265 and AV1, for queries made before the parameter sets are known.
+    fn common(codec: Codec) -> Self {
+        let bit_depth = vk::VideoComponentBitDepthFlagsKHR::TYPE_8;
+        match codec {
+            Codec::Av1 => {
+                let mut av1 = Box::new(
+                    vk::VideoDecodeAV1ProfileInfoKHR::default()
+                        .std_profile(StdVideoAV1Profile_STD_VIDEO_AV1_PROFILE_MAIN)
+                        .film_grain_support(false),
+                );
+                let codec_info = &mut *av1 as *mut _ as *const c_void;
+                Self::new(CodecProfile::Av1(av1), codec_info, codec, bit_depth)
+            }
+            Codec::H264 => {
+                let mut h264 = Box::new(
+                    vk::VideoDecodeH264ProfileInfoKHR::default()
+                        .std_profile_idc(StdVideoH264ProfileIdc_STD_VIDEO_H264_PROFILE_IDC_HIGH)
+                        .picture_layout(vk::VideoDecodeH264PictureLayoutFlagsKHR::PROGRESSIVE),
+                );
+                let codec_info = &mut *h264 as *mut _ as *const c_void;
+                Self::new(CodecProfile::H264(h264), codec_info, codec, bit_depth)
+            }
+            Codec::H265 => {
+                let mut h265 = Box::new(
+                    vk::VideoDecodeH265ProfileInfoKHR::default()
+                        .std_profile_idc(StdVideoH265ProfileIdc_STD_VIDEO_H265_PROFILE_IDC_MAIN),
+                );
+                let codec_info = &mut *h265 as *mut _ as *const c_void;
+                Self::new(CodecProfile::H265(h265), codec_info, codec, bit_depth)
+            }
+        }
+    }
+
+    fn new(
+        codec_profile: CodecProfile,
+        codec_info: *const c_void,
+        codec: Codec,
+        bit_depth: vk::VideoComponentBitDepthFlagsKHR,
+    ) -> Self {
+        let mut info = Box::new([vk::VideoProfileInfoKHR::default()
+            .video_codec_operation(codec.decode_operation())
+            .chroma_subsampling(vk::VideoChromaSubsamplingFlagsKHR::TYPE_420)
+            .luma_bit_depth(bit_depth)
+            .chroma_bit_depth(bit_depth)]);
+        info[0].p_next = codec_info;
+
+        let mut list = Box::new(vk::VideoProfileListInfoKHR::default());
+        list.profile_count = 1;
+        list.p_profiles = info.as_ptr();
+
+        Self {
+            _codec: codec_profile,
+            info,
+            list,
+        }
+    }
+
+    /// The profile list to chain into image, buffer and format queries. Any chain
+    /// left over from a previous use is cleared first.
+    fn list(&mut self) -> &mut vk::VideoProfileListInfoKHR<'static> {
+        self.list.p_next = ptr::null();
+        &mut self.list
+    }
+}
+
+/// An image with its memory and a view covering all of its array layers.
+pub(crate) struct VideoImage {
+    pub(crate) image: vk::Image,
+    memory: vk::DeviceMemory,
+    pub(crate) view: vk::ImageView,
+    pub(crate) layout: vk::ImageLayout,
+}
+
+impl VideoImage {
+    /// An image for the profiles of `profile_list`, which the create info chains in.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) unsafe fn new(
+        device: &Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        profile_list: &mut vk::VideoProfileListInfoKHR<'static>,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        array_layers: u32,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<Self> {
+        let image_create_info = vk::ImageCreateInfo {
+            p_next: profile_list as *mut _ as _,
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: extent.into(),
+            mip_levels: 1,
+            array_layers,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let image = device.create_image(&image_create_info, None)?;
+
+        let memory_req = device.get_image_memory_requirements(image);
+        let memory_index = find_memorytype_index(
+            &memory_req,
+            device_memory_properties,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .ok_or_else(|| anyhow!("Unable to find suitable memory index for video image"))?;
+
+        let allocate_info = vk::MemoryAllocateInfo {
+            allocation_size: memory_req.size,
+            memory_type_index: memory_index,
+            ..Default::default()
+        };
+        let memory = device.allocate_memory(&allocate_info, None)?;
+        device.bind_image_memory(image, memory, 0)?;
+
+        let mut image_view_usage_create_info = vk::ImageViewUsageCreateInfo {
+            usage,
+            ..Default::default()
+        };
+        let image_view_info = vk::ImageViewCreateInfo {
+            p_next: &mut image_view_usage_create_info as *mut _ as _,
+            view_type: if array_layers > 1 {
+                vk::ImageViewType::TYPE_2D_ARRAY
+            } else {
+                vk::ImageViewType::TYPE_2D
+            },
+            format,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                level_count: 1,
+                layer_count: array_layers,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let view = device.create_image_view(&image_view_info, None)?;
+
+        Ok(Self {
+            image,
+            memory,
+            view,
+            layout: vk::ImageLayout::UNDEFINED,
+        })
+    }
+
+    /// Moves all layers to `layout`, discarding the contents on the first transition.
+    pub(crate) unsafe fn transition(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+    ) {
+        if self.layout == layout {
+            return;
+        }
+
+        let barrier = vk::ImageMemoryBarrier {
+            old_layout: self.layout,
+            new_layout: layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: self.image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                level_count: 1,
+                layer_count: vk::REMAINING_ARRAY_LAYERS,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+        self.layout = layout;
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+/// Host visible buffer the access units are copied into before decoding, or the encoded
+/// pictures are read back from.
+pub(crate) struct BitstreamBuffer {
+    pub(crate) buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pub(crate) ptr: *mut u8,
+    pub(crate) size: u64,
+}
+
+impl BitstreamBuffer {
+    pub(crate) unsafe fn new(
+        device: &Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        profile_list: &mut vk::VideoProfileListInfoKHR<'static>,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self> {
+        let buffer_info = vk::BufferCreateInfo {
+            p_next: profile_list as *mut _ as _,
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let buffer = device.create_buffer(&buffer_info, None)?;
+
+        let memory_req = device.get_buffer_memory_requirements(buffer);
+        let memory_index = find_memorytype_index(
+            &memory_req,
+            device_memory_properties,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+        .ok_or_else(|| anyhow!("Unable to find suitable memorytype for the bitstream buffer"))?;
+
+        let allocate_info = vk::MemoryAllocateInfo {
+            allocation_size: memory_req.size,
+            memory_type_index: memory_index,
+            ..Default::default()
+        };
+        let memory = device.allocate_memory(&allocate_info, None)?;
+        device.bind_buffer_memory(buffer, memory, 0)?;
+
+        // Kept mapped for the lifetime of the buffer
+        let ptr = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8;
+
+        Ok(Self {
+            buffer,
+            memory,
+            ptr,
+            size,
+        })
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.unmap_memory(self.memory);
+        device.destroy_buffer(self.buffer, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+/// Access units are staged into this many bitstream buffers in turn
+const BITSTREAM_RING_SIZE: usize = 4;
+
+pub(crate) fn align_up(value: u64, alignment: u64) -> u64 {
+    let alignment = alignment.max(1);
+    value.div_ceil(alignment) * alignment
+}
+
+/// The codec's DPB slot info, chained into the reference slot infos.
+enum DpbSlotInfo<'a> {
+    Av1(vk::VideoDecodeAV1DpbSlotInfoKHR<'a>),
+    H264(vk::VideoDecodeH264DpbSlotInfoKHR<'a>),
+    H265(vk::VideoDecodeH265DpbSlotInfoKHR<'a>),
+}
+
+impl<'a> DpbSlotInfo<'a> {
+    fn new(info: &'a ReferenceInfo) -> Self {
+        match info {
+            ReferenceInfo::Av1(std_reference_info) => DpbSlotInfo::Av1(
+                vk::VideoDecodeAV1DpbSlotInfoKHR::default().std_reference_info(std_reference_info),
+            ),
+            ReferenceInfo::H264(std_reference_info) => DpbSlotInfo::H264(
+                vk::VideoDecodeH264DpbSlotInfoKHR::default().std_reference_info(std_reference_info),
+            ),
+            ReferenceInfo::H265(std_reference_info) => DpbSlotInfo::H265(
+                vk::VideoDecodeH265DpbSlotInfoKHR::default().std_reference_info(std_reference_info),
+            ),
+        }
+    }
+
+    fn as_ptr(&self) -> *const c_void {
+        match self {
+            DpbSlotInfo::Av1(info) => info as *const _ as *const c_void,
+            DpbSlotInfo::H264(info) => info as *const _ as *const c_void,
+            DpbSlotInfo::H265(info) => info as *const _ as *const c_void,
+        }
+    }
+}
+
+/// The video session with everything sized for the stream.
+struct Session {
+    capabilities: Capabilities,
+    profile: VideoProfile,
+    dst_format: vk::Format,
+    extent: vk::Extent2D,
+
+    video_session: vk::VideoSessionKHR,
+    video_session_memory: Vec<vk::DeviceMemory>,
+    video_session_parameters: vk::VideoSessionParametersKHR,
+
+    /// One layer per DPB slot
+    dpb_image: VideoImage,
+    /// Separate output picture, `None` when the DPB slot doubles as output
+    dst_image: Option<VideoImage>,
+    /// Created on first use and grown to the largest access unit staged in them, so their
+    /// size follows the largest pictures rather than the length of the stream
+    bitstream_ring: Vec<BitstreamBuffer>,
+    /// The buffer of the access unit being decoded
+    bitstream_index: usize,
+}
+
+impl Session {
+    /// The decode output of the picture in `slot`.
+    fn frame(&self, slot: usize, pts: Timestamp) -> DecodedFrame {
+        let image = self.dst_image.as_ref().unwrap_or(&self.dpb_image);
+        DecodedFrame {
+            image: image.image,
+            image_view: image.view,
+            array_layer: slot as u32,
+            format: self.dst_format,
+            extent: self.extent,
+            pts,
+        }
+    }
+
+    unsafe fn destroy(&self, device: &Device, video_queue_loader: &VideoQueue) {
+        if self.video_session_parameters != vk::VideoSessionParametersKHR::null() {
+            video_queue_loader
+                .destroy_video_session_parameters(self.video_session_parameters, None);
+        }
+        video_queue_loader.destroy_video_session(self.video_session, None);
+        for &memory in self.video_session_memory.iter() {
+            device.free_memory(memory, None);
+        }
+
+        for bitstream in &self.bitstream_ring {
+            bitstream.destroy(device);
+        }
+        self.dpb_image.destroy(device);
+        if let Some(dst_image) = &self.dst_image {
+            dst_image.destroy(device);
+        }
+    }
+}
+
+/// Decodes on a queue with `VK_KHR_video_decode_queue`.
+///
+/// The backend borrows the instance and device of the application; the device must have
+/// been created with `VK_KHR_video_queue`, `VK_KHR_video_decode_queue` and the decode
+/// extension of the codec, see [`Codec::decode_extension_name`], enabled and a queue from
+/// `queue_family_index`.
+/// All Vulkan objects created by the backend are released on drop.
+pub struct VulkanBackend {
+    device: Device,
+    video_queue_loader: VideoQueue,
+    video_decode_queue_loader: VideoDecodeQueue,
+    pdevice: vk::PhysicalDevice,
+    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    queue_family_index: u32,
+    queue: vk::Queue,
+
+    session: Option<Session>,
+
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    /// Signalled when a frame is handed to another queue and when it comes back
+    handover_semaphores: [vk::Semaphore; 2],
+}
+
+impl VulkanBackend {
+    /// The largest coded extent `pdevice` decodes `codec` at, in the profile most streams
+    /// use.
+    ///
+    /// # Safety
+    ///
+    /// `instance`, `device` and `pdevice` must be valid and belong together.
+    pub unsafe fn max_coded_extent(
+        entry: &Entry,
+        instance: &Instance,
+        device: &Device,
+        pdevice: vk::PhysicalDevice,
+        codec: Codec,
+    ) -> Result<vk::Extent2D> {
+        let capabilities = query_capabilities(
+            &VideoQueue::new(entry, instance, device),
+            pdevice,
+            codec,
+            &VideoProfile::common(codec),
+        )?;
+        Ok(capabilities.max_coded_extent)
+    }
+
+    /// Creates the command buffer and synchronisation objects, the session follows once
+    /// the stream is known.
+    ///
+    /// # Safety
+    ///
+    /// `instance`, `device`, `pdevice` and `queue` must be valid and belong together,
+    /// and the device must outlive the backend.
+    pub unsafe fn new(
+        entry: &Entry,
+        instance: &Instance,
+        device: &Device,
+        pdevice: vk::PhysicalDevice,
+        queue_family_index: u32,
+        queue: vk::Queue,
+    ) -> Result<Self> {
+        let command_pool_create_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(queue_family_index);
+        let command_pool = device.create_command_pool(&command_pool_create_info, None)?;
+
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_buffer_count(1)
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let command_buffer = device.allocate_command_buffers(&command_buffer_allocate_info)?[0];
+
+        let fence_create_info =
+            vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+        let fence = device.create_fence(&fence_create_info, None)?;
+        let handover_semaphores = [
+            device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?,
+            device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?,
+        ];
+
+        Ok(Self {
+            device: device.clone(),
+            video_queue_loader: VideoQueue::new(entry, instance, device),
+            video_decode_queue_loader: VideoDecodeQueue::new(entry, instance, device),
+            pdevice,
+            device_memory_properties: instance.get_physical_device_memory_properties(pdevice),
+            queue_family_index,
+            queue,
+            session: None,
+            command_pool,
+            command_buffer,
+            fence,
+            handover_semaphores,
+        })
+    }
+
+    fn session(&mut self) -> Result<&mut Session> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| anyhow!("No video session created yet"))
+    }
+
+    /// Coded size of the frames, the stream's rounded up to the picture access granularity
+    pub fn extent(&self) -> vk::Extent2D {
+        self.session
+            .as_ref()
+            .map_or(vk::Extent2D::default(), |session| session.extent)
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.session
+            .as_ref()
+            .map_or(vk::Format::UNDEFINED, |session| session.dst_format)
+    }
+
+    /// Makes sure the buffer `index` of the bitstream ring exists and can hold `size` bytes.
+    unsafe fn reserve_bitstream(&mut self, index: usize, size: u64) -> Result<()> {
+        let device = &self.device;
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+        if session
+            .bitstream_ring
+            .get(index)
+            .is_some_and(|bitstream| size <= bitstream.size)
+        {
+            return Ok(());
+        }
+
+        let bitstream = BitstreamBuffer::new(
+            device,
+            &self.device_memory_properties,
+            session.profile.list(),
+            align_up(
+                size * 2,
+                session.capabilities.min_bitstream_buffer_size_alignment,
+            ),
+            vk::BufferUsageFlags::VIDEO_DECODE_SRC_KHR,
+        )?;
+        if index < session.bitstream_ring.len() {
+            mem::replace(&mut session.bitstream_ring[index], bitstream).destroy(device);
+        } else {
+            session.bitstream_ring.push(bitstream);
+        }
+
+        Ok(())
+    }
+
+    /// Copies `data` into the next buffer of the bitstream ring, zero padded to the size
+    /// alignment. Returns the padded size. Decodes complete before they return, so a
+    /// buffer is free again by the time the ring comes back to it.
+    unsafe fn upload_bitstream(&mut self, data: &[u8]) -> Result<u64> {
+        let session = self.session()?;
+        let range = align_up(
+            data.len() as u64,
+            session.capabilities.min_bitstream_buffer_size_alignment,
+        );
+        let index = (session.bitstream_index + 1) % BITSTREAM_RING_SIZE;
+        self.reserve_bitstream(index, range)?;
+        let session = self.session()?;
+        session.bitstream_index = index;
+
+        let bitstream = &session.bitstream_ring[index];
+        ptr::copy_nonoverlapping(data.as_ptr(), bitstream.ptr, data.len());
+        ptr::write_bytes(
+            bitstream.ptr.add(data.len()),
+            0,
+            (range - data.len() as u64) as usize,
+        );
+
+        Ok(range)
+    }
+
+    /// Lends `frame` to commands recorded by `record` into `command_buffer`, which is
+    /// submitted to `queue` from `queue_family_index` with the given semaphores. The frame
+    /// is moved to `layout` and acquired by that queue family for the commands, then
+    /// handed back to the decoder. Returns once the commands have completed.
+    ///
+    /// # Safety
+    ///
+    /// `frame` must be the last frame returned by the decoder, `queue` must belong to the
+    /// decoder's device and `command_buffer` must be resettable and not in use.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn use_frame<F: FnOnce(&Device, vk::CommandBuffer)>(
+        &mut self,
+        frame: &DecodedFrame,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+        wait_mask: &[vk::PipelineStageFlags],
+        wait_semaphores: &[vk::Semaphore],
+        signal_semaphores: &[vk::Semaphore],
+        record: F,
+    ) -> Result<()> {
+        let device = &self.device;
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+        let decode_layout = match &session.dst_image {
+            Some(dst_image) if dst_image.image == frame.image => dst_image.layout,
+            _ => session.dpb_image.layout,
+        };
+        let (src_queue_family_index, dst_queue_family_index) =
+            if queue_family_index == self.queue_family_index {
+                (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+            } else {
+                (self.queue_family_index, queue_family_index)
+            };
+
+        let barrier = |old_layout, new_layout, src_queue_family_index, dst_queue_family_index| {
+            vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+                dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                old_layout,
+                new_layout,
+                src_queue_family_index,
+                dst_queue_family_index,
+                image: frame.image,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    level_count: 1,
+                    base_array_layer: frame.array_layer,
+                    layer_count: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        };
+        let to_frame_layout = barrier(
+            decode_layout,
+            layout,
+            src_queue_family_index,
+            dst_queue_family_index,
+        );
+        let to_decode_layout = barrier(
+            layout,
+            decode_layout,
+            dst_queue_family_index,
+            src_queue_family_index,
+        );
+        let record_barrier = |command_buffer, barrier: vk::ImageMemoryBarrier| {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        };
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+
+        if queue_family_index == self.queue_family_index {
+            device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            device.begin_command_buffer(command_buffer, &begin_info)?;
+            record_barrier(command_buffer, to_frame_layout);
+            record(device, command_buffer);
+            record_barrier(command_buffer, to_decode_layout);
+            device.end_command_buffer(command_buffer)?;
+
+            let command_buffers = [command_buffer];
+            let submit_info = vk::SubmitInfo::default()
+                .wait_semaphores(wait_semaphores)
+                .wait_dst_stage_mask(wait_mask)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(signal_semaphores);
+            device.reset_fences(&[self.fence])?;
+            device.queue_submit(queue, &[submit_info], self.fence)?;
+            device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+            return Ok(());
+        }
+
+        // Ownership transfers need a release on one queue and an acquire on the other
+        let decode_command_buffers = [self.command_buffer];
+        let command_buffers = [command_buffer];
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+
+        device.reset_command_buffer(self.command_buffer, vk::CommandBufferResetFlags::empty())?;
+        device.begin_command_buffer(self.command_buffer, &begin_info)?;
+        record_barrier(self.command_buffer, to_frame_layout);
+        device.end_command_buffer(self.command_buffer)?;
+        let released = [self.handover_semaphores[0]];
+        let release = vk::SubmitInfo::default()
+            .command_buffers(&decode_command_buffers)
+            .signal_semaphores(&released);
+        device.reset_fences(&[self.fence])?;
+        device.queue_submit(self.queue, &[release], self.fence)?;
+
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+        record_barrier(command_buffer, to_frame_layout);
+        record(device, command_buffer);
+        record_barrier(command_buffer, to_decode_layout);
+        device.end_command_buffer(command_buffer)?;
+        let wait_semaphores = [&released[..], wait_semaphores].concat();
+        let wait_mask = [&wait_stages[..], wait_mask].concat();
+        let returned = [self.handover_semaphores[1]];
+        let signal_semaphores = [&returned[..], signal_semaphores].concat();
+        let submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_mask)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        device.queue_submit(queue, &[submit_info], vk::Fence::null())?;
+
+        // The decode command buffer is free again once the release has completed
+        device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+        device.reset_command_buffer(self.command_buffer, vk::CommandBufferResetFlags::empty())?;
+        device.begin_command_buffer(self.command_buffer, &begin_info)?;
+        record_barrier(self.command_buffer, to_decode_layout);
+        device.end_command_buffer(self.command_buffer)?;
+        let acquire = vk::SubmitInfo::default()
+            .wait_semaphores(&returned)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&decode_command_buffers);
+        device.reset_fences(&[self.fence])?;
+        device.queue_submit(self.queue, &[acquire], self.fence)?;
+        device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+
+        Ok(())
+    }
+
+    /// The picture resource of the DPB slot `slot`.
+    fn dpb_picture_resource(
+        session: &Session,
+        slot: usize,
+    ) -> vk::VideoPictureResourceInfoKHR<'static> {
+        vk::VideoPictureResourceInfoKHR::default()
+            .coded_extent(session.extent)
+            .base_array_layer(slot as u32)
+            .image_view_binding(session.dpb_image.view)
+    }
+}
+
+/// Queries the capabilities of `pdevice` for `profile`.
+unsafe fn query_capabilities(
+    video_queue_loader: &VideoQueue,
+    pdevice: vk::PhysicalDevice,
+    codec: Codec,
+    profile: &VideoProfile,
+) -> Result<Capabilities> {
+    let mut av1_decode_capabilities = vk::VideoDecodeAV1CapabilitiesKHR::default();
+    let mut h264_decode_capabilities = vk::VideoDecodeH264CapabilitiesKHR::default();
+    let mut h265_decode_capabilities = vk::VideoDecodeH265CapabilitiesKHR::default();
+    let mut decode_capabilities = vk::VideoDecodeCapabilitiesKHR {
+        p_next: match codec {
+            Codec::Av1 => &mut av1_decode_capabilities as *mut _ as *mut c_void,
+            Codec::H264 => &mut h264_decode_capabilities as *mut _ as *mut c_void,
+            Codec::H265 => &mut h265_decode_capabilities as *mut _ as *mut c_void,
+        },
+        ..Default::default()
+    };
+    let mut video_capabilities =
+        vk::VideoCapabilitiesKHR::default().push_next(&mut decode_capabilities);
+
+    video_queue_loader.get_physical_device_video_capabilities(
+        pdevice,
+        &profile.info[0],
+        &mut video_capabilities,
+    )?;
+
+    Ok(Capabilities {
+        min_bitstream_buffer_offset_alignment: video_capabilities
+            .min_bitstream_buffer_offset_alignment,
+        min_bitstream_buffer_size_alignment: video_capabilities.min_bitstream_buffer_size_alignment,
+        picture_access_granularity: video_capabilities.picture_access_granularity,
+        min_coded_extent: video_capabilities.min_coded_extent,
+        max_coded_extent: video_capabilities.max_coded_extent,
+        max_dpb_slots: video_capabilities.max_dpb_slots,
+        max_active_reference_pictures: video_capabilities.max_active_reference_pictures,
+        std_header_version: video_capabilities.std_header_version,
+        decode_flags: decode_capabilities.flags,
+    })
+}
+
+/// Allocates and binds the memory `video_session` asks for.
+pub(crate) unsafe fn allocate_session_memory(
+    device: &Device,
+    video_queue_loader: &VideoQueue,
+    device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    video_session: vk::VideoSessionKHR,
+) -> Result<Vec<vk::DeviceMemory>> {
+    let video_session_memory_requirements_count =
+        video_queue_loader.get_video_session_memory_requirements_len(video_session);
+    let mut video_session_memory_requirements = vec![
+        vk::VideoSessionMemoryRequirementsKHR::default(
+        );
+        video_session_memory_requirements_count
+    ];
+    video_queue_loader.get_video_session_memory_requirements(
+        video_session,
+        &mut video_session_memory_requirements,
+    )?;
+
+    let mut video_session_memory = Vec::new();
+    let mut video_session_bind_memory = Vec::new();
+    for requirements in video_session_memory_requirements.iter() {
+        let memory_requirements = requirements.memory_requirements;
+        let memory_type_index = find_memorytype_index(
+            &memory_requirements,
+            device_memory_properties,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .or_else(|| {
+            find_memorytype_index(
+                &memory_requirements,
+                device_memory_properties,
+                vk::MemoryPropertyFlags::empty(),
+            )
+        })
+        .ok_or_else(|| anyhow!("Unable to find suitable memory for the video session"))?;
+
+        let allocate_info = vk::MemoryAllocateInfo {
+            allocation_size: memory_requirements.size,
+            memory_type_index,
+            ..Default::default()
+        };
+        let memory = device.allocate_memory(&allocate_info, None)?;
+        video_session_memory.push(memory);
+
+        video_session_bind_memory.push(
+            vk::BindVideoSessionMemoryInfoKHR::default()
+                .memory_bind_index(requirements.memory_bind_index)
+                .memory(memory)
+                .memory_offset(0)
+                .memory_size(memory_requirements.size),
+        );
+    }
+
+    video_queue_loader.bind_video_session_memory(video_session, &mut video_session_bind_memory)?;
+
+    Ok(video_session_memory)
+}
+
+impl DecodeBackend for VulkanBackend {
+    type Frame = DecodedFrame;
+
+    fn capabilities(&mut self, parameter_sets: &ParameterSets) -> Result<BackendCapabilities> {
+        let profile = VideoProfile::for_stream(parameter_sets)?;
+        let capabilities = unsafe {
+            query_capabilities(
+                &self.video_queue_loader,
+                self.pdevice,
+                parameter_sets.codec(),
+                &profile,
+            )?
+        };
+        Ok(BackendCapabilities {
+            max_dpb_slots: capabilities.max_dpb_slots,
+            max_active_reference_pictures: capabilities.max_active_reference_pictures,
+        })
+    }
+
+    fn create_session(&mut self, parameter_sets: &ParameterSets, info: &SessionInfo) -> Result<()> {
+        let device = &self.device;
+        let video_queue_loader = &self.video_queue_loader;
+        let pdevice = self.pdevice;
+        let mut profile = VideoProfile::for_stream(parameter_sets)?;
+
+        unsafe {
+            let capabilities =
+                query_capabilities(video_queue_loader, pdevice, info.codec, &profile)?;
+
+            let (width, height) = info.coded_extent;
+            let extent = vk::Extent2D {
+                width: align_up(
+                    width as u64,
+                    capabilities.picture_access_granularity.width as u64,
+                )
+                .max(capabilities.min_coded_extent.width as u64) as u32,
+                height: align_up(
+                    height as u64,
+                    capabilities.picture_access_granularity.height as u64,
+                )
+                .max(capabilities.min_coded_extent.height as u64) as u32,
+            };
+            if extent.width > capabilities.max_coded_extent.width
+                || extent.height > capabilities.max_coded_extent.height
+            {
+                return Err(anyhow!(
+                    "Coded extent {}x{} exceeds the supported {}x{}",
+                    extent.width,
+                    extent.height,
+                    capabilities.max_coded_extent.width,
+                    capabilities.max_coded_extent.height
+                ));
+            }
+
+            // Formats
+            let distinct_output = capabilities
+                .decode_flags
+                .contains(vk::VideoDecodeCapabilityFlagsKHR::DPB_AND_OUTPUT_DISTINCT);
+
+            let (dst_format, dpb_format) = if distinct_output {
+                (
+                    find_video_format(
+                        pdevice,
+                        video_queue_loader,
+                        vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR,
+                        profile.list(),
+                    )?,
+                    find_video_format(
+                        pdevice,
+                        video_queue_loader,
+                        vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR,
+                        profile.list(),
+                    )?,
+                )
+            } else {
+                let format = find_video_format(
+                    pdevice,
+                    video_queue_loader,
+                    vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR
+                        | vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR,
+                    profile.list(),
+                )?;
+                (format, format)
+            };
+
+            // Images
+            let dpb_image = VideoImage::new(
+                device,
+                &self.device_memory_properties,
+                profile.list(),
+                dpb_format,
+                extent,
+                info.dpb_slots,
+                if distinct_output {
+                    vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR
+                } else {
+                    vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR
+                        | vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC
+                },
+            )?;
+            let dst_image = if distinct_output {
+                Some(VideoImage::new(
+                    device,
+                    &self.device_memory_properties,
+                    profile.list(),
+                    dst_format,
+                    extent,
+                    // Pictures waiting for output keep their slot's layer
+                    info.dpb_slots,
+                    vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC,
+                )?)
+            } else {
+                None
+            };
+
+            // Video session
+            let video_session_info = vk::VideoSessionCreateInfoKHR::default()
+                .queue_family_index(self.queue_family_index)
+                .video_profile(&profile.info[0])
+                .picture_format(dst_format)
+                .max_coded_extent(extent)
+                .reference_picture_format(dpb_format)
+                .max_dpb_slots(info.dpb_slots)
+                .max_active_reference_pictures(info.max_active_reference_pictures)
+                .std_header_version(&capabilities.std_header_version);
+
+            let video_session = video_queue_loader.create_video_session(
+                device.handle(),
+                &video_session_info,
+                None,
+            )?;
+
+            let video_session_memory = allocate_session_memory(
+                device,
+                video_queue_loader,
+                &self.device_memory_properties,
+                video_session,
+            )?;
+
+            let session = Session {
+                capabilities,
+                profile,
+                dst_format,
+                extent,
+                video_session,
+                video_session_memory,
+                video_session_parameters: vk::VideoSessionParametersKHR::null(),
+                dpb_image,
+                dst_image,
+                bitstream_ring: Vec::new(),
+                // The first access unit goes to the first buffer
+                bitstream_index: BITSTREAM_RING_SIZE - 1,
+            };
+            if let Some(previous) = self.session.replace(session) {
+                device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+                previous.destroy(device, video_queue_loader);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the previous decode to complete, then recreates the session parameters
+    /// object from all of `parameter_sets`.
+    fn update_parameters(&mut self, parameter_sets: &ParameterSets) -> Result<()> {
+        unsafe {
+            self.device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+        }
+        let video_queue_loader = &self.video_queue_loader;
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+
+        let video_session_parameters = unsafe {
+            match parameter_sets {
+                ParameterSets::Av1(sequence_header) => {
+                    let std_sequence_header = sequence_header.to_std();
+
+                    let mut av1_create_info =
+                        vk::VideoDecodeAV1SessionParametersCreateInfoKHR::default()
+                            .std_sequence_header(&std_sequence_header.header);
+
+                    let create_info = vk::VideoSessionParametersCreateInfoKHR::default()
+                        .push_next(&mut av1_create_info)
+                        .video_session(session.video_session);
+
+                    video_queue_loader.create_video_session_parameters(&create_info, None)?
+                }
+                ParameterSets::H264(parameter_sets) => {
+                    let std_parameter_sets = parameter_sets.to_std();
+                    let add_info = std_parameter_sets.add_info();
+
+                    let mut h264_create_info =
+                        vk::VideoDecodeH264SessionParametersCreateInfoKHR::default()
+                            .max_std_sps_count(h264::sps::MAX_SPS_COUNT as u32)
+                            .max_std_pps_count(h264::pps::MAX_PPS_COUNT as u32)
+                            .parameters_add_info(&add_info);
+
+                    let create_info = vk::VideoSessionParametersCreateInfoKHR::default()
+                        .push_next(&mut h264_create_info)
+                        .video_session(session.video_session);
+
+                    video_queue_loader.create_video_session_parameters(&create_info, None)?
+                }
+                ParameterSets::H265(parameter_sets) => {
+                    let std_parameter_sets = parameter_sets.to_std();
+                    let add_info = std_parameter_sets.add_info();
+
+                    let mut h265_create_info =
+                        vk::VideoDecodeH265SessionParametersCreateInfoKHR::default()
+                            .max_std_vps_count(h265::vps::MAX_VPS_COUNT as u32)
+                            .max_std_sps_count(h265::sps::MAX_SPS_COUNT as u32)
+                            .max_std_pps_count(h265::pps::MAX_PPS_COUNT as u32)
+                            .parameters_add_info(&add_info);
+
+                    let create_info = vk::VideoSessionParametersCreateInfoKHR::default()
+                        .push_next(&mut h265_create_info)
+                        .video_session(session.video_session);
+
+                    video_queue_loader.create_video_session_parameters(&create_info, None)?
+                }
+            }
+        };
+
+        if session.video_session_parameters != vk::VideoSessionParametersKHR::null() {
+            unsafe {
+                video_queue_loader
+                    .destroy_video_session_parameters(session.video_session_parameters, None);
+            }
+        }
+        session.video_session_parameters = video_session_parameters;
+
+        Ok(())
+    }
+
+    /// Starts recording the decode command buffer: the images are moved to their decode
+    /// layouts and the reference pictures bound, along with the setup slot.
+    fn begin_coding(&mut self, info: &BeginCodingInfo) -> Result<()> {
+        let device = &self.device;
+        let command_buffer = self.command_buffer;
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+
+        unsafe {
+            device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device.begin_command_buffer(command_buffer, &begin_info)?;
+
+            session.dpb_image.transition(
+                device,
+                command_buffer,
+                vk::ImageLayout::VIDEO_DECODE_DPB_KHR,
+            );
+            if let Some(dst_image) = &mut session.dst_image {
+                dst_image.transition(
+                    device,
+                    command_buffer,
+                    vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+                );
+            }
+        }
+
+        let session = &*session;
+        let dpb_slot_infos: Vec<_> = info
+            .reference_slots
+            .iter()
+            .map(|reference| DpbSlotInfo::new(&reference.info))
+            .collect();
+        let picture_resources: Vec<_> = info
+            .reference_slots
+            .iter()
+            .map(|reference| Self::dpb_picture_resource(session, reference.slot))
+            .chain([Self::dpb_picture_resource(session, info.setup_slot)])
+            .collect();
+        // The setup slot is not associated with a picture yet when coding begins
+        let begin_reference_slots: Vec<_> = info
+            .reference_slots
+            .iter()
+            .zip(&dpb_slot_infos)
+            .map(|(reference, dpb_slot_info)| vk::VideoReferenceSlotInfoKHR {
+                p_next: dpb_slot_info.as_ptr(),
+                slot_index: reference.slot as i32,
+                ..Default::default()
+            })
+            .chain([vk::VideoReferenceSlotInfoKHR::default().slot_index(-1)])
+            .zip(&picture_resources)
+            .map(|(reference_slot, picture_resource)| {
+                reference_slot.picture_resource(picture_resource)
+            })
+            .collect();
+        let begin_coding_info = vk::VideoBeginCodingInfoKHR::default()
+            .video_session(session.video_session)
+            .video_session_parameters(session.video_session_parameters)
+            .reference_slots(&begin_reference_slots);
+
+        unsafe {
+            self.video_queue_loader
+                .cmd_begin_video_coding(command_buffer, &begin_coding_info);
+            if info.reset {
+                let control_info = vk::VideoCodingControlInfoKHR::default()
+                    .flags(vk::VideoCodingControlFlagsKHR::RESET);
+                self.video_queue_loader
+                    .cmd_control_video_coding(command_buffer, &control_info);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Uploads the bitstream and records the decode into the setup slot.
+    fn decode(&mut self, info: &DecodeInfo) -> Result<()> {
+        let range = unsafe { self.upload_bitstream(info.bitstream)? };
+        let command_buffer = self.command_buffer;
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+
+        // Reference pictures
+        let dpb_slot_infos: Vec<_> = info
+            .reference_slots
+            .iter()
+            .map(|reference| DpbSlotInfo::new(&reference.info))
+            .collect();
+        let reference_picture_resources: Vec<_> = info
+            .reference_slots
+            .iter()
+            .map(|reference| Self::dpb_picture_resource(session, reference.slot))
+            .collect();
+        let reference_slots: Vec<_> = info
+            .reference_slots
+            .iter()
+            .zip(&dpb_slot_infos)
+            .zip(&reference_picture_resources)
+            .map(|((reference, dpb_slot_info), picture_resource)| {
+                vk::VideoReferenceSlotInfoKHR {
+                    p_next: dpb_slot_info.as_ptr(),
+                    slot_index: reference.slot as i32,
+                    ..Default::default()
+                }
+                .picture_resource(picture_resource)
+            })
+            .collect();
+
+        // The picture is reconstructed into the slot picked by the DPB
+        let setup_slot = info.setup_slot.slot;
+        let setup_dpb_slot_info = DpbSlotInfo::new(&info.setup_slot.info);
+        let setup_picture_resource = Self::dpb_picture_resource(session, setup_slot);
+        let setup_reference_slot = vk::VideoReferenceSlotInfoKHR {
+            p_next: setup_dpb_slot_info.as_ptr(),
+            slot_index: setup_slot as i32,
+            ..Default::default()
+        }
+        .picture_resource(&setup_picture_resource);
+
+        let dst_picture_resource = match &session.dst_image {
+            Some(dst_image) => vk::VideoPictureResourceInfoKHR::default()
+                .coded_extent(session.extent)
+                .base_array_layer(setup_slot as u32)
+                .image_view_binding(dst_image.view),
+            None => setup_picture_resource,
+        };
+
+        let decode_info = vk::VideoDecodeInfoKHR::default()
+            .src_buffer(session.bitstream_ring[session.bitstream_index].buffer)
+            .src_buffer_offset(0)
+            .src_buffer_range(range)
+            .dst_picture_resource(dst_picture_resource)
+            .setup_reference_slot(&setup_reference_slot)
+            .reference_slots(&reference_slots);
+        unsafe {
+            match info.picture {
+                PictureInfo::Av1 {
+                    std_picture_info,
+                    reference_name_slot_indices,
+                    tile_offsets,
+                    tile_sizes,
+                } => {
+                    let mut av1_picture_info = vk::VideoDecodeAV1PictureInfoKHR::default()
+                        .std_picture_info(std_picture_info)
+                        .reference_name_slot_indices(*reference_name_slot_indices)
+                        .frame_header_offset(0)
+                        .tile_offsets(tile_offsets)
+                        .tile_sizes(tile_sizes);
+                    self.video_decode_queue_loader.cmd_decode_video(
+                        command_buffer,
+                        &decode_info.push_next(&mut av1_picture_info),
+                    );
+                }
+                PictureInfo::H264 {
+                    std_picture_info,
+                    slice_offsets,
+                } => {
+                    let mut h264_picture_info = vk::VideoDecodeH264PictureInfoKHR::default()
+                        .std_picture_info(std_picture_info)
+                        .slice_offsets(slice_offsets);
+                    self.video_decode_queue_loader.cmd_decode_video(
+                        command_buffer,
+                        &decode_info.push_next(&mut h264_picture_info),
+                    );
+                }
+                PictureInfo::H265 {
+                    std_picture_info,
+                    slice_segment_offsets,
+                } => {
+                    let mut h265_picture_info = vk::VideoDecodeH265PictureInfoKHR::default()
+                        .std_picture_info(std_picture_info)
+                        .slice_segment_offsets(slice_segment_offsets);
+                    self.video_decode_queue_loader.cmd_decode_video(
+                        command_buffer,
+                        &decode_info.push_next(&mut h265_picture_info),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Submits the recorded decode and waits for it to complete.
+    fn end_coding(&mut self) -> Result<()> {
+        let device = &self.device;
+        unsafe {
+            self.video_queue_loader
+                .cmd_end_video_coding(self.command_buffer, &vk::VideoEndCodingInfoKHR::default());
+            device.end_command_buffer(self.command_buffer)?;
+
+            let command_buffers = [self.command_buffer];
+            let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+            device.reset_fences(&[self.fence])?;
+            device.queue_submit(self.queue, &[submit_info], self.fence)?;
+            device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+        }
+
+        Ok(())
+    }
+
+    fn frame(&mut self, slot: usize, pts: Timestamp) -> Result<DecodedFrame> {
+        Ok(self.session()?.frame(slot, pts))
+    }
+}
+
+impl Drop for VulkanBackend {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .ok();
+
+            if let Some(session) = &self.session {
+                session.destroy(&self.device, &self.video_queue_loader);
+            }
+
+            for &semaphore in self.handover_semaphores.iter() {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::ts;
+
+/// Container formats the player can open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerFormat {
+    /// ISO base media file format
+    Mp4,
+    /// Raw H.264 elementary stream
+    AnnexB,
+    /// Matroska and WebM
+    Matroska,
+    /// MPEG-2 transport stream, also with the 192 byte packets of `.m2ts`
+    MpegTs,
+    /// H.264 over RTP from an RTSP server, never probed from file content
+    Rtp,
+}
+
+/// Guesses the container format from the first bytes of a file.
+pub fn probe(data: &[u8]) -> Option<ContainerFormat> {
+    // EBML header ID
+    if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        return Some(ContainerFormat::Matroska);
+    }
+
+    if let Some(box_type) = data.get(4..8) {
+        if matches!(
+            box_type,
+            b"ftyp" | b"styp" | b"moov" | b"moof" | b"mdat" | b"free" | b"skip" | b"wide"
+        ) {
+            return Some(ContainerFormat::Mp4);
+        }
+    }
+
+    if ts::packet_size(data).is_some() {
+        return Some(ContainerFormat::MpegTs);
+    }
+
+    // leading_zero_8bits followed by a start code and a NAL unit header with the
+    // forbidden_zero_bit cleared
+    let zeros = data.iter().take_while(|&&byte| byte == 0).count();
+    if zeros >= 2 && data.get(zeros) == Some(&1) {
+        if let Some(header) = data.get(zeros + 1) {
+            if header & 0x80 == 0 {
+                return Some(ContainerFormat::AnnexB);
+            }
+        }
+    }
+
+    None
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use ash::vk;
+use ash::vk::native::{
+    StdVideoEncodeH264PictureInfo, StdVideoEncodeH264ReferenceInfo,
+    StdVideoEncodeH264ReferenceListsInfo, StdVideoEncodeH264SliceHeader,
+};
+
+use super::gop::GopStructure;
+use crate::h264::ParameterSets;
+use crate::yuv::Nv12Frame;
+
+/// The limits of a backend for 8 bit 4:2:0 H.264 encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackendCapabilities {
+    pub max_coded_extent: (u32, u32),
+    pub max_dpb_slots: u32,
+    pub max_active_reference_pictures: u32,
+    /// Most references in list 0 of P pictures
+    pub max_p_l0_references: u32,
+    /// Most references in list 0 of B pictures
+    pub max_b_l0_references: u32,
+    /// Most references in list 1, 0 when B pictures are not supported
+    pub max_l1_references: u32,
+    pub rate_control_modes: vk::VideoEncodeRateControlModeFlagsKHR,
+    /// In bits per second
+    pub max_bitrate: u64,
+    pub min_qp: i32,
+    pub max_qp: i32,
+}
+
+/// How the size of the coded pictures is controlled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateControl {
+    /// No rate control, every slice is coded with the QP of its picture type
+    ConstantQp { i: i32, p: i32, b: i32 },
+    /// Bitrates in bits per second
+    ConstantBitrate { bitrate: u64 },
+    VariableBitrate {
+        average_bitrate: u64,
+        max_bitrate: u64,
+    },
+}
+
+impl RateControl {
+    pub fn mode(&self) -> vk::VideoEncodeRateControlModeFlagsKHR {
+        match self {
+            RateControl::ConstantQp { .. } => vk::VideoEncodeRateControlModeFlagsKHR::DISABLED,
+            RateControl::ConstantBitrate { .. } => vk::VideoEncodeRateControlModeFlagsKHR::CBR,
+            RateControl::VariableBitrate { .. } => vk::VideoEncodeRateControlModeFlagsKHR::VBR,
+        }
+    }
+
+    /// The highest bitrate the stream may reach, `None` without rate control
+    pub fn max_bitrate(&self) -> Option<u64> {
+        match *self {
+            RateControl::ConstantQp { .. } => None,
+            RateControl::ConstantBitrate { bitrate } => Some(bitrate),
+            RateControl::VariableBitrate { max_bitrate, .. } => Some(max_bitrate),
+        }
+    }
+}
+
+/// What a session is created for, sized by the [`Encoder`](super::Encoder) from its
+/// configuration and the [`BackendCapabilities`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Macroblock aligned size of the pictures, backends may round it up further
+    pub coded_extent: (u32, u32),
+    /// Frames per second as numerator and denominator
+    pub frame_rate: (u32, u32),
+    pub gop: GopStructure,
+    pub dpb_slots: u32,
+    pub max_active_reference_pictures: u32,
+}
+
+/// A DPB slot with the reference picture it holds or is about to hold.
+#[derive(Clone, Copy, Debug)]
+pub struct ReferenceSlot {
+    pub slot: usize,
+    pub info: StdVideoEncodeH264ReferenceInfo,
+}
+
+/// The parameters of the picture being encoded. The pointers in the std structures are
+/// left null, backends point them at each other during [`EncodeBackend::encode`].
+#[derive(Clone, Copy, Debug)]
+pub struct PictureInfo {
+    pub std_picture_info: StdVideoEncodeH264PictureInfo,
+    /// Lists of DPB slot indices, [`NO_REFERENCE_PICTURE`](super::NO_REFERENCE_PICTURE)
+    /// past their end
+    pub std_reference_lists: StdVideoEncodeH264ReferenceListsInfo,
+    /// Header of the one slice covering the picture
+    pub std_slice_header: StdVideoEncodeH264SliceHeader,
+    /// QP of the slice for [`RateControl::ConstantQp`], otherwise left to the rate
+    /// control
+    pub constant_qp: Option<i32>,
+}
+
+/// Starts encoding a picture, see `vkCmdBeginVideoCodingKHR`.
+#[derive(Clone, Copy, Debug)]
+pub struct BeginCodingInfo<'a> {
+    /// The session is new and has to be reset first
+    pub reset: bool,
+    /// Rate control to switch to before encoding, always set along with `reset`
+    pub rate_control: Option<&'a RateControl>,
+    /// The pictures the one being encoded refers to
+    pub reference_slots: &'a [ReferenceSlot],
+    /// The slot a reference picture is reconstructed into, not associated with a picture
+    /// yet. `None` for pictures that are not used for reference.
+    pub setup_slot: Option<usize>,
+}
+
+/// Encodes a picture between [`EncodeBackend::begin_coding`] and
+/// [`EncodeBackend::end_coding`], see `vkCmdEncodeVideoKHR`.
+#[derive(Clone, Copy, Debug)]
+pub struct EncodeInfo<'a> {
+    pub picture: &'a PictureInfo,
+    /// The frame at the size it was configured with, smaller than the coded extent when
+    /// that is not macroblock aligned
+    pub source: &'a Nv12Frame,
+    pub setup_slot: Option<ReferenceSlot>,
+    pub reference_slots: &'a [ReferenceSlot],
+}
+
+/// The outcome of an encode, see `VK_QUERY_TYPE_VIDEO_ENCODE_FEEDBACK_KHR`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodeFeedback {
+    /// Where the coded picture starts in the bitstream buffer
+    pub offset: u64,
+    pub bytes_written: u64,
+    pub status: vk::QueryResultStatusKHR,
+}
+
+/// Where the pictures of an [`Encoder`](super::Encoder) are encoded.
+///
+/// The encoder picks the picture types, the DPB slots and the reference lists; a backend
+/// keeps the session, the parameter sets and the reconstructed pictures in their slots.
+/// As with [`DecodeBackend`](crate::decoder::DecodeBackend), a session is created once,
+/// then every picture is encoded between `begin_coding` and `end_coding`.
+pub trait EncodeBackend {
+    fn capabilities(&mut self) -> Result<BackendCapabilities>;
+
+    /// Creates the session the pictures are encoded in.
+    fn create_session(&mut self, info: &SessionInfo) -> Result<()>;
+
+    /// Replaces the parameter sets pictures are encoded with.
+    fn update_parameters(&mut self, parameter_sets: &ParameterSets) -> Result<()>;
+
+    /// The SPS and PPS with the given ids as Annex-B NAL units, the way the backend
+    /// writes them. It may override parts of the parameter sets it was given.
+    fn encoded_parameters(&mut self, sps_id: u8, pps_id: u8) -> Result<Vec<u8>>;
+
+    fn begin_coding(&mut self, info: &BeginCodingInfo) -> Result<()>;
+
+    fn encode(&mut self, info: &EncodeInfo) -> Result<()>;
+
+    /// Completes the picture and reports where its slice data went. A reference picture
+    /// is in its slot once this returns.
+    fn end_coding(&mut self) -> Result<EncodeFeedback>;
+
+    /// The bytes written for the picture of `feedback`, valid until the next encode.
+    fn bitstream(&self, feedback: &EncodeFeedback) -> Result<&[u8]>;
+}
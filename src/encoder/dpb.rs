@@ -0,0 +1,80 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::StdVideoEncodeH264ReferenceInfo;
+
+use super::gop::FrameType;
+
+/// A reconstructed picture kept for reference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reference {
+    pub slot: usize,
+    pub frame_type: FrameType,
+    pub frame_num: u32,
+    pub pic_order_cnt: i32,
+}
+
+impl Reference {
+    pub fn to_std(&self) -> StdVideoEncodeH264ReferenceInfo {
+        let mut info: StdVideoEncodeH264ReferenceInfo = unsafe { mem::zeroed() };
+        info.primary_pic_type = self.frame_type.std_picture_type();
+        info.FrameNum = self.frame_num;
+        info.PicOrderCnt = self.pic_order_cnt;
+        info
+    }
+}
+
+/// The reference pictures of the encoder, marked by the sliding window of 8.2.5.3 alone.
+/// B pictures are not used for reference and never enter it.
+#[derive(Clone, Debug)]
+pub struct Dpb {
+    max_num_ref_frames: usize,
+    slots: usize,
+    /// Oldest first
+    references: Vec<Reference>,
+}
+
+impl Dpb {
+    /// A DPB keeping `max_num_ref_frames` references in `slots`, which needs one slot more
+    /// for the picture being encoded.
+    pub fn new(max_num_ref_frames: usize, slots: usize) -> Self {
+        Self {
+            max_num_ref_frames,
+            slots,
+            references: Vec::new(),
+        }
+    }
+
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    /// The most recent reference picture
+    pub fn last(&self) -> Option<&Reference> {
+        self.references.last()
+    }
+
+    /// Picks the slot a reference picture is reconstructed into. IDR pictures drop all
+    /// references first.
+    pub fn start_picture(&mut self, frame_type: FrameType) -> Result<usize> {
+        if frame_type == FrameType::Idr {
+            self.references.clear();
+        }
+        (0..self.slots)
+            .find(|&slot| {
+                self.references
+                    .iter()
+                    .all(|reference| reference.slot != slot)
+            })
+            .ok_or_else(|| anyhow!("No free DPB slot out of {}", self.slots))
+    }
+
+    /// Marks an encoded picture as used for reference, dropping the oldest reference once
+    /// the DPB is full.
+    pub fn mark(&mut self, reference: Reference) {
+        if self.references.len() >= self.max_num_ref_frames {
+            self.references.remove(0);
+        }
+        self.references.push(reference);
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoH264PictureType, StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_B,
+    StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_I,
+    StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_IDR,
+    StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_P, StdVideoH264SliceType,
+    StdVideoH264SliceType_STD_VIDEO_H264_SLICE_TYPE_B,
+    StdVideoH264SliceType_STD_VIDEO_H264_SLICE_TYPE_I,
+    StdVideoH264SliceType_STD_VIDEO_H264_SLICE_TYPE_P,
+};
+
+/// How a picture is coded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameType {
+    /// Starts a new coded video sequence, nothing before it is referred to
+    Idr,
+    /// Intra coded, pictures after it may still refer to pictures before it
+    I,
+    P,
+    /// Predicted from the reference pictures on both sides, not used for reference itself
+    B,
+}
+
+impl FrameType {
+    pub fn is_reference(self) -> bool {
+        self != FrameType::B
+    }
+
+    pub fn is_intra(self) -> bool {
+        matches!(self, FrameType::Idr | FrameType::I)
+    }
+
+    /// primary_pic_type of the picture
+    pub fn std_picture_type(self) -> StdVideoH264PictureType {
+        match self {
+            FrameType::Idr => StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_IDR,
+            FrameType::I => StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_I,
+            FrameType::P => StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_P,
+            FrameType::B => StdVideoH264PictureType_STD_VIDEO_H264_PICTURE_TYPE_B,
+        }
+    }
+
+    /// slice_type of the slices of the picture
+    pub fn std_slice_type(self) -> StdVideoH264SliceType {
+        match self {
+            FrameType::Idr | FrameType::I => StdVideoH264SliceType_STD_VIDEO_H264_SLICE_TYPE_I,
+            FrameType::P => StdVideoH264SliceType_STD_VIDEO_H264_SLICE_TYPE_P,
+            FrameType::B => StdVideoH264SliceType_STD_VIDEO_H264_SLICE_TYPE_B,
+        }
+    }
+}
+
+/// The pattern of picture types in display order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GopStructure {
+    /// Frames from one IDR picture to the next, 0 for an IDR picture at the start only
+    pub idr_period: u32,
+    /// Frames from one I picture to the next, 0 for no I pictures besides the IDR ones.
+    /// A multiple of `consecutive_b_frames + 1`, so I pictures fall on reference pictures.
+    pub intra_period: u32,
+    /// B pictures between two reference pictures
+    pub consecutive_b_frames: u32,
+}
+
+impl Default for GopStructure {
+    /// An IDR picture every 60 frames with P pictures in between.
+    fn default() -> Self {
+        Self {
+            idr_period: 60,
+            intra_period: 0,
+            consecutive_b_frames: 0,
+        }
+    }
+}
+
+impl GopStructure {
+    pub fn validate(&self) -> Result<()> {
+        if self.intra_period % (self.consecutive_b_frames + 1) != 0 {
+            return Err(anyhow!(
+                "An intra period of {} frames does not fit groups of {} B frames",
+                self.intra_period,
+                self.consecutive_b_frames
+            ));
+        }
+        Ok(())
+    }
+
+    /// The number of frames the rate control spreads its budget over.
+    pub fn gop_frame_count(&self) -> u32 {
+        match (self.intra_period, self.idr_period) {
+            (0, idr_period) => idr_period,
+            (intra_period, 0) => intra_period,
+            (intra_period, idr_period) => intra_period.min(idr_period),
+        }
+    }
+
+    /// The type of the frame at `index` in display order.
+    ///
+    /// Every `consecutive_b_frames + 1`th frame is a reference picture, the frames in
+    /// between are B pictures. The last frame before an IDR picture is always a P picture,
+    /// as B pictures can not refer across it.
+    pub fn frame_type(&self, index: u64) -> FrameType {
+        let position = match self.idr_period {
+            0 => index,
+            idr_period => index % idr_period as u64,
+        };
+
+        if position == 0 {
+            FrameType::Idr
+        } else if position % (self.consecutive_b_frames as u64 + 1) == 0 {
+            if self.intra_period != 0 && position % self.intra_period as u64 == 0 {
+                FrameType::I
+            } else {
+                FrameType::P
+            }
+        } else if self.idr_period != 0 && position == self.idr_period as u64 - 1 {
+            FrameType::P
+        } else {
+            FrameType::B
+        }
+    }
+}
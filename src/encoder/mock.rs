@@ -0,0 +1,277 @@
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use super::backend::{
+    BackendCapabilities, BeginCodingInfo, EncodeBackend, EncodeFeedback, EncodeInfo, PictureInfo,
+    RateControl, ReferenceSlot, SessionInfo,
+};
+use super::NO_REFERENCE_PICTURE;
+use crate::h264::ParameterSets;
+use crate::yuv::Nv12Frame;
+
+/// A call made to a [`MockBackend`], with copies of its arguments.
+#[derive(Clone, Debug)]
+pub enum Call {
+    CreateSession(SessionInfo),
+    UpdateParameters(ParameterSets),
+    BeginCoding {
+        reset: bool,
+        rate_control: Option<RateControl>,
+        reference_slots: Vec<ReferenceSlot>,
+        setup_slot: Option<usize>,
+    },
+    Encode {
+        picture: Box<PictureInfo>,
+        source: Nv12Frame,
+        setup_slot: Option<ReferenceSlot>,
+        reference_slots: Vec<ReferenceSlot>,
+    },
+    EndCoding,
+}
+
+/// Records the calls of an [`Encoder`](super::Encoder) instead of encoding, so the picture
+/// types, slot assignment and reference lists can be tested without a video device.
+///
+/// Each picture comes out as a single NAL unit of the right type whose one byte payload
+/// counts the pictures, the parameter sets as NAL units carrying their id.
+/// Calls out of order, slots out of range and references to slots without a picture are
+/// errors.
+#[derive(Clone, Debug)]
+pub struct MockBackend {
+    /// Returned for every session
+    pub capabilities: BackendCapabilities,
+    /// Reported for every picture
+    pub status: vk::QueryResultStatusKHR,
+    pub calls: Vec<Call>,
+    /// The picture in each slot of the session, counting the encoded pictures from 0
+    pub slots: Vec<Option<usize>>,
+    parameter_sets: Option<ParameterSets>,
+    /// The setup slot between begin and end of coding
+    coding: Option<Option<usize>>,
+    /// The NAL unit header byte of the picture being encoded
+    nal_header: Option<u8>,
+    bitstream: Vec<u8>,
+    pictures: usize,
+}
+
+impl Default for MockBackend {
+    /// Limits in the range of current hardware encoders, with all rate control modes.
+    fn default() -> Self {
+        Self::new(BackendCapabilities {
+            max_coded_extent: (4096, 4096),
+            max_dpb_slots: 17,
+            max_active_reference_pictures: 16,
+            max_p_l0_references: 4,
+            max_b_l0_references: 4,
+            max_l1_references: 1,
+            rate_control_modes: vk::VideoEncodeRateControlModeFlagsKHR::DISABLED
+                | vk::VideoEncodeRateControlModeFlagsKHR::CBR
+                | vk::VideoEncodeRateControlModeFlagsKHR::VBR,
+            max_bitrate: 100_000_000,
+            min_qp: 0,
+            max_qp: 51,
+        })
+    }
+}
+
+impl MockBackend {
+    pub fn new(capabilities: BackendCapabilities) -> Self {
+        Self {
+            capabilities,
+            status: vk::QueryResultStatusKHR::COMPLETE,
+            calls: Vec::new(),
+            slots: Vec::new(),
+            parameter_sets: None,
+            coding: None,
+            nal_header: None,
+            bitstream: Vec::new(),
+            pictures: 0,
+        }
+    }
+
+    /// The pictures encoded so far, in decode order.
+    pub fn encodes(
+        &self,
+    ) -> impl Iterator<Item = (&PictureInfo, Option<&ReferenceSlot>, &[ReferenceSlot])> {
+        self.calls.iter().filter_map(|call| match call {
+            Call::Encode {
+                picture,
+                setup_slot,
+                reference_slots,
+                ..
+            } => Some((&**picture, setup_slot.as_ref(), &reference_slots[..])),
+            _ => None,
+        })
+    }
+
+    fn check_slot(&self, slot: usize) -> Result<()> {
+        if slot < self.slots.len() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Slot {} out of the {} of the session",
+                slot,
+                self.slots.len()
+            ))
+        }
+    }
+
+    /// The references must hold pictures and can not be encoded into.
+    fn check_references(
+        &self,
+        reference_slots: &[ReferenceSlot],
+        setup_slot: Option<usize>,
+    ) -> Result<()> {
+        if let Some(setup_slot) = setup_slot {
+            self.check_slot(setup_slot)?;
+        }
+        for reference in reference_slots {
+            self.check_slot(reference.slot)?;
+            if Some(reference.slot) == setup_slot {
+                return Err(anyhow!(
+                    "Slot {} is both reference and setup slot",
+                    reference.slot
+                ));
+            }
+            if self.slots[reference.slot].is_none() {
+                return Err(anyhow!(
+                    "Reference slot {} holds no picture",
+                    reference.slot
+                ));
+            }
+        }
+        if reference_slots.len() > self.capabilities.max_active_reference_pictures as usize {
+            return Err(anyhow!("{} active references", reference_slots.len()));
+        }
+        Ok(())
+    }
+}
+
+impl EncodeBackend for MockBackend {
+    fn capabilities(&mut self) -> Result<BackendCapabilities> {
+        Ok(self.capabilities)
+    }
+
+    fn create_session(&mut self, info: &SessionInfo) -> Result<()> {
+        if info.dpb_slots > self.capabilities.max_dpb_slots {
+            return Err(anyhow!("{} DPB slots requested", info.dpb_slots));
+        }
+        self.calls.push(Call::CreateSession(*info));
+        self.slots = vec![None; info.dpb_slots as usize];
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, parameter_sets: &ParameterSets) -> Result<()> {
+        if self.coding.is_some() {
+            return Err(anyhow!("Parameters updated while coding"));
+        }
+        self.calls
+            .push(Call::UpdateParameters(parameter_sets.clone()));
+        self.parameter_sets = Some(parameter_sets.clone());
+        Ok(())
+    }
+
+    fn encoded_parameters(&mut self, sps_id: u8, pps_id: u8) -> Result<Vec<u8>> {
+        let parameter_sets = self
+            .parameter_sets
+            .as_ref()
+            .ok_or_else(|| anyhow!("No parameter sets to encode"))?;
+        let (pps, _) = parameter_sets.active(pps_id)?;
+        if pps.seq_parameter_set_id != sps_id {
+            return Err(anyhow!("PPS {} does not refer to SPS {}", pps_id, sps_id));
+        }
+        Ok(vec![0, 0, 0, 1, 0x67, sps_id, 0, 0, 0, 1, 0x68, pps_id])
+    }
+
+    fn begin_coding(&mut self, info: &BeginCodingInfo) -> Result<()> {
+        if self.coding.is_some() {
+            return Err(anyhow!("Coding begun twice"));
+        }
+        if info.reset {
+            if info.rate_control.is_none() {
+                return Err(anyhow!("Session reset without rate control"));
+            }
+            self.slots.fill(None);
+        }
+        self.check_references(info.reference_slots, info.setup_slot)?;
+        self.calls.push(Call::BeginCoding {
+            reset: info.reset,
+            rate_control: info.rate_control.copied(),
+            reference_slots: info.reference_slots.to_vec(),
+            setup_slot: info.setup_slot,
+        });
+        self.coding = Some(info.setup_slot);
+        self.nal_header = None;
+        Ok(())
+    }
+
+    fn encode(&mut self, info: &EncodeInfo) -> Result<()> {
+        let setup_slot = info.setup_slot.map(|setup_slot| setup_slot.slot);
+        if self.coding != Some(setup_slot) || self.nal_header.is_some() {
+            return Err(anyhow!(
+                "Encode into slot {:?} outside of its begin and end of coding",
+                setup_slot
+            ));
+        }
+        self.check_references(info.reference_slots, setup_slot)?;
+
+        // The lists may only name the references coding began with
+        let lists = &info.picture.std_reference_lists;
+        for &slot in lists.RefPicList0.iter().chain(&lists.RefPicList1) {
+            if slot != NO_REFERENCE_PICTURE
+                && !info
+                    .reference_slots
+                    .iter()
+                    .any(|reference| reference.slot == slot as usize)
+            {
+                return Err(anyhow!("Slot {} in a reference list is not bound", slot));
+            }
+        }
+
+        let flags = &info.picture.std_picture_info.flags;
+        self.nal_header = Some(match (flags.IdrPicFlag(), flags.is_reference()) {
+            (1, _) => 0x65,
+            (_, 1) => 0x41,
+            _ => 0x01,
+        });
+        self.calls.push(Call::Encode {
+            picture: Box::new(*info.picture),
+            source: info.source.clone(),
+            setup_slot: info.setup_slot,
+            reference_slots: info.reference_slots.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn end_coding(&mut self) -> Result<EncodeFeedback> {
+        let setup_slot = self
+            .coding
+            .take()
+            .ok_or_else(|| anyhow!("Coding ended without beginning"))?;
+        let nal_header = self
+            .nal_header
+            .take()
+            .ok_or_else(|| anyhow!("Coding ended without an encode"))?;
+        self.calls.push(Call::EndCoding);
+
+        if let Some(slot) = setup_slot {
+            self.slots[slot] = Some(self.pictures);
+        }
+        // Never zero, so the payload can not form a start code
+        self.bitstream = vec![0, 0, 0, 1, nal_header, (self.pictures % 255) as u8 + 1];
+        self.pictures += 1;
+
+        Ok(EncodeFeedback {
+            offset: 0,
+            bytes_written: self.bitstream.len() as u64,
+            status: self.status,
+        })
+    }
+
+    fn bitstream(&self, feedback: &EncodeFeedback) -> Result<&[u8]> {
+        let start = feedback.offset as usize;
+        self.bitstream
+            .get(start..start + feedback.bytes_written as usize)
+            .ok_or_else(|| anyhow!("Feedback {:?} outside of the bitstream", feedback))
+    }
+}
@@ -0,0 +1,662 @@
+use std::cmp::Reverse;
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoEncodeH264PictureInfo, StdVideoEncodeH264ReferenceListsInfo,
+    StdVideoEncodeH264SliceHeader,
+};
+use ash::{vk, Device, Entry, Instance};
+
+use crate::h264::pps::Pps;
+use crate::h264::sps::{ScalingLists, Sps, Vui};
+use crate::h264::ParameterSets;
+use crate::timestamp::Timestamp;
+use crate::yuv::Nv12Frame;
+
+pub mod backend;
+pub mod dpb;
+pub mod gop;
+pub mod mock;
+pub mod vulkan;
+
+use backend::{
+    BackendCapabilities, BeginCodingInfo, EncodeInfo, PictureInfo, ReferenceSlot, SessionInfo,
+};
+pub use backend::{EncodeBackend, RateControl};
+use dpb::{Dpb, Reference};
+pub use gop::{FrameType, GopStructure};
+pub use vulkan::VulkanBackend;
+
+/// Marks the unused entries of the reference picture lists,
+/// `STD_VIDEO_H264_NO_REFERENCE_PICTURE`
+pub const NO_REFERENCE_PICTURE: u8 = 0xff;
+
+/// frame_num and pic_order_cnt_lsb wrap at 256, far beyond the reference pictures and B
+/// pictures the encoder keeps apart
+const LOG2_MAX_FRAME_NUM_MINUS4: u8 = 4;
+const LOG2_MAX_PIC_ORDER_CNT_LSB_MINUS4: u8 = 4;
+
+/// What an [`Encoder`] produces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncoderConfig {
+    /// Size of the frames in luma samples, both even
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second as numerator and denominator
+    pub frame_rate: (u32, u32),
+    pub gop: GopStructure,
+    pub rate_control: RateControl,
+    /// Reference frames P pictures pick from. B pictures need at least 2, one on each
+    /// side.
+    pub max_reference_frames: u32,
+}
+
+impl EncoderConfig {
+    /// 30 frames per second with the default [`GopStructure`], one reference frame and
+    /// constant QPs.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            frame_rate: (30, 1),
+            gop: GopStructure::default(),
+            rate_control: RateControl::ConstantQp {
+                i: 24,
+                p: 26,
+                b: 28,
+            },
+            max_reference_frames: 1,
+        }
+    }
+}
+
+/// An encoded picture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodedPicture {
+    /// Annex-B NAL units, preceded by the SPS and PPS for IDR pictures
+    pub data: Vec<u8>,
+    pub pts: Timestamp,
+    pub frame_type: FrameType,
+}
+
+/// H.264 encoder. Picks the picture types from the [`GopStructure`], keeps the reference
+/// pictures and reorders B frames; the pictures themselves are encoded by an
+/// [`EncodeBackend`], by default on a Vulkan video encode queue.
+pub struct Encoder<B: EncodeBackend = VulkanBackend> {
+    backend: B,
+    config: EncoderConfig,
+    capabilities: BackendCapabilities,
+    sps: Sps,
+    /// SPS and PPS the way the backend writes them
+    encoded_parameters: Vec<u8>,
+    dpb: Dpb,
+    /// A new session has to be reset before its first encode
+    reset_pending: bool,
+    /// The rate control changed since the last picture
+    rate_control_pending: bool,
+    /// Frames passed in so far, in display order
+    frame_count: u64,
+    /// Display index of the last IDR picture, POCs count from it
+    idr_index: u64,
+    idr_pic_id: u16,
+    /// B frames waiting for the reference picture after them, with their display index
+    pending: Vec<(Nv12Frame, Timestamp, u64)>,
+}
+
+impl Encoder<VulkanBackend> {
+    /// Creates an encoder on `queue`, which comes from a queue family with
+    /// `VK_QUEUE_VIDEO_ENCODE_BIT_KHR`. Frames are uploaded through `transfer_queue`, which
+    /// may be `queue` itself when its family supports transfers. See [`VulkanBackend`] for
+    /// the extensions the device needs.
+    ///
+    /// # Safety
+    ///
+    /// `instance`, `device`, `pdevice` and the queues must be valid and belong together,
+    /// and the device must outlive the encoder.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn new(
+        entry: &Entry,
+        instance: &Instance,
+        device: &Device,
+        pdevice: vk::PhysicalDevice,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        transfer_queue_family_index: u32,
+        transfer_queue: vk::Queue,
+        config: EncoderConfig,
+    ) -> Result<Self> {
+        let backend = VulkanBackend::new(
+            entry,
+            instance,
+            device,
+            pdevice,
+            queue_family_index,
+            queue,
+            transfer_queue_family_index,
+            transfer_queue,
+        )?;
+        Self::with_backend(backend, config)
+    }
+}
+
+impl<B: EncodeBackend> Encoder<B> {
+    /// Creates the session of `backend` for `config` and hands it the parameter sets.
+    pub fn with_backend(mut backend: B, config: EncoderConfig) -> Result<Self> {
+        config.gop.validate()?;
+        if config.width == 0
+            || config.height == 0
+            || config.width % 2 != 0
+            || config.height % 2 != 0
+        {
+            return Err(anyhow!(
+                "Frames of {}x{} can not be encoded, width and height have to be even",
+                config.width,
+                config.height
+            ));
+        }
+        if config.frame_rate.0 == 0 || config.frame_rate.1 == 0 {
+            return Err(anyhow!(
+                "Invalid frame rate {}/{}",
+                config.frame_rate.0,
+                config.frame_rate.1
+            ));
+        }
+
+        let capabilities = backend.capabilities()?;
+        validate_rate_control(&config.rate_control, &capabilities)?;
+
+        let coded_extent = (
+            config.width.div_ceil(16) * 16,
+            config.height.div_ceil(16) * 16,
+        );
+        let (max_width, max_height) = capabilities.max_coded_extent;
+        if coded_extent.0 > max_width || coded_extent.1 > max_height {
+            return Err(anyhow!(
+                "Coded extent {}x{} exceeds the supported {}x{}",
+                coded_extent.0,
+                coded_extent.1,
+                max_width,
+                max_height
+            ));
+        }
+
+        // B pictures refer to a reference picture on either side
+        let b_frames = config.gop.consecutive_b_frames > 0;
+        if capabilities.max_p_l0_references == 0 {
+            return Err(anyhow!("The backend does not encode P pictures"));
+        }
+        if b_frames
+            && (capabilities.max_b_l0_references == 0 || capabilities.max_l1_references == 0)
+        {
+            return Err(anyhow!("The backend does not encode B pictures"));
+        }
+        let min_reference_frames = if b_frames { 2 } else { 1 };
+        // The picture being encoded needs a slot next to its references
+        let max_num_ref_frames = config
+            .max_reference_frames
+            .clamp(min_reference_frames, 16)
+            .min(capabilities.max_active_reference_pictures)
+            .min(capabilities.max_dpb_slots.saturating_sub(1));
+        if max_num_ref_frames < min_reference_frames {
+            return Err(anyhow!(
+                "{} reference pictures needed, the backend supports {}",
+                min_reference_frames,
+                max_num_ref_frames
+            ));
+        }
+        let dpb_slots = max_num_ref_frames + 1;
+
+        let sps = sequence_parameter_set(&config, max_num_ref_frames);
+        let mut parameter_sets = ParameterSets::default();
+        parameter_sets.insert_sps(sps.clone());
+        parameter_sets.insert_pps(picture_parameter_set());
+
+        backend.create_session(&SessionInfo {
+            coded_extent,
+            frame_rate: config.frame_rate,
+            gop: config.gop,
+            dpb_slots,
+            max_active_reference_pictures: max_num_ref_frames,
+        })?;
+        backend.update_parameters(&parameter_sets)?;
+        let encoded_parameters = backend.encoded_parameters(0, 0)?;
+
+        Ok(Self {
+            backend,
+            config,
+            capabilities,
+            sps,
+            encoded_parameters,
+            dpb: Dpb::new(max_num_ref_frames as usize, dpb_slots as usize),
+            reset_pending: true,
+            rate_control_pending: false,
+            frame_count: 0,
+            idr_index: 0,
+            idr_pic_id: 0,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    /// The SPS and PPS as Annex-B NAL units, for containers that keep them out of band.
+    pub fn parameter_sets(&self) -> &[u8] {
+        &self.encoded_parameters
+    }
+
+    /// Switches to `rate_control` from the next picture on.
+    pub fn set_rate_control(&mut self, rate_control: RateControl) -> Result<()> {
+        validate_rate_control(&rate_control, &self.capabilities)?;
+        self.config.rate_control = rate_control;
+        self.rate_control_pending = true;
+        Ok(())
+    }
+
+    /// Encodes the next frame in display order. Returns the pictures encoded on the way,
+    /// in decode order, which is none while B frames wait for the reference picture after
+    /// them.
+    ///
+    /// Blocks until the pictures are encoded.
+    pub fn encode(&mut self, frame: Nv12Frame, pts: Timestamp) -> Result<Vec<EncodedPicture>> {
+        if frame.width != self.config.width || frame.height != self.config.height {
+            return Err(anyhow!(
+                "Frame of {}x{} does not fit the encoder for {}x{}",
+                frame.width,
+                frame.height,
+                self.config.width,
+                self.config.height
+            ));
+        }
+
+        let index = self.frame_count;
+        self.frame_count += 1;
+        let frame_type = self.config.gop.frame_type(index);
+        if frame_type == FrameType::B {
+            self.pending.push((frame, pts, index));
+            return Ok(Vec::new());
+        }
+
+        let mut output = vec![self.encode_picture(&frame, pts, index, frame_type)?];
+        output.extend(self.encode_pending()?);
+        Ok(output)
+    }
+
+    /// Encodes the frames still waiting, at the end of the stream. The last of them becomes
+    /// a P picture the others refer to.
+    pub fn flush(&mut self) -> Result<Vec<EncodedPicture>> {
+        let Some((frame, pts, index)) = self.pending.pop() else {
+            return Ok(Vec::new());
+        };
+
+        let mut output = vec![self.encode_picture(&frame, pts, index, FrameType::P)?];
+        output.extend(self.encode_pending()?);
+        Ok(output)
+    }
+
+    /// Encodes the waiting B frames once the reference picture after them is in the DPB.
+    fn encode_pending(&mut self) -> Result<Vec<EncodedPicture>> {
+        mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(frame, pts, index)| self.encode_picture(&frame, pts, index, FrameType::B))
+            .collect()
+    }
+
+    fn encode_picture(
+        &mut self,
+        frame: &Nv12Frame,
+        pts: Timestamp,
+        index: u64,
+        frame_type: FrameType,
+    ) -> Result<EncodedPicture> {
+        if frame_type == FrameType::Idr {
+            self.idr_index = index;
+        }
+        // B pictures are not reconstructed into a slot
+        let setup_slot = if frame_type.is_reference() {
+            Some(self.dpb.start_picture(frame_type)?)
+        } else {
+            None
+        };
+        let frame_num = match (frame_type, self.dpb.last()) {
+            (FrameType::Idr, _) | (_, None) => 0,
+            (_, Some(last)) => (last.frame_num + 1) % self.sps.max_frame_num(),
+        };
+        let pic_order_cnt = 2 * (index - self.idr_index) as i32;
+
+        // Reference picture lists, in the order of 8.2.4.2 cut to the lengths the backend
+        // supports so no modification is needed
+        let references = self.dpb.references();
+        let (list0, list1): (Vec<&Reference>, Vec<&Reference>) = match frame_type {
+            FrameType::Idr | FrameType::I => (Vec::new(), Vec::new()),
+            FrameType::P => (
+                references
+                    .iter()
+                    .rev()
+                    .take(self.capabilities.max_p_l0_references as usize)
+                    .collect(),
+                Vec::new(),
+            ),
+            FrameType::B => {
+                let mut before: Vec<_> = references
+                    .iter()
+                    .filter(|reference| reference.pic_order_cnt < pic_order_cnt)
+                    .collect();
+                before.sort_by_key(|reference| Reverse(reference.pic_order_cnt));
+                before.truncate(self.capabilities.max_b_l0_references as usize);
+                let mut after: Vec<_> = references
+                    .iter()
+                    .filter(|reference| reference.pic_order_cnt > pic_order_cnt)
+                    .collect();
+                after.sort_by_key(|reference| reference.pic_order_cnt);
+                after.truncate(self.capabilities.max_l1_references as usize);
+                (before, after)
+            }
+        };
+        if !frame_type.is_intra() && list0.is_empty() {
+            return Err(anyhow!(
+                "No reference picture for the {:?} picture {}",
+                frame_type,
+                index
+            ));
+        }
+
+        let mut std_reference_lists: StdVideoEncodeH264ReferenceListsInfo =
+            unsafe { mem::zeroed() };
+        std_reference_lists.num_ref_idx_l0_active_minus1 = list0.len().saturating_sub(1) as u8;
+        std_reference_lists.num_ref_idx_l1_active_minus1 = list1.len().saturating_sub(1) as u8;
+        std_reference_lists.RefPicList0 = [NO_REFERENCE_PICTURE; 32];
+        std_reference_lists.RefPicList1 = [NO_REFERENCE_PICTURE; 32];
+        for (entry, reference) in std_reference_lists.RefPicList0.iter_mut().zip(&list0) {
+            *entry = reference.slot as u8;
+        }
+        for (entry, reference) in std_reference_lists.RefPicList1.iter_mut().zip(&list1) {
+            *entry = reference.slot as u8;
+        }
+        let reference_slots: Vec<_> = list0
+            .iter()
+            .chain(&list1)
+            .map(|reference| ReferenceSlot {
+                slot: reference.slot,
+                info: reference.to_std(),
+            })
+            .collect();
+
+        let mut std_picture_info: StdVideoEncodeH264PictureInfo = unsafe { mem::zeroed() };
+        std_picture_info
+            .flags
+            .set_IdrPicFlag((frame_type == FrameType::Idr) as u32);
+        std_picture_info
+            .flags
+            .set_is_reference(frame_type.is_reference() as u32);
+        std_picture_info.idr_pic_id = self.idr_pic_id;
+        std_picture_info.primary_pic_type = frame_type.std_picture_type();
+        std_picture_info.frame_num = frame_num;
+        std_picture_info.PicOrderCnt = pic_order_cnt;
+
+        // The PPS defaults to one reference per list
+        let mut std_slice_header: StdVideoEncodeH264SliceHeader = unsafe { mem::zeroed() };
+        std_slice_header.slice_type = frame_type.std_slice_type();
+        std_slice_header
+            .flags
+            .set_num_ref_idx_active_override_flag((list0.len() > 1 || list1.len() > 1) as u32);
+        std_slice_header
+            .flags
+            .set_direct_spatial_mv_pred_flag((frame_type == FrameType::B) as u32);
+
+        let picture = PictureInfo {
+            std_picture_info,
+            std_reference_lists,
+            std_slice_header,
+            constant_qp: match self.config.rate_control {
+                RateControl::ConstantQp { i, p, b } => Some(match frame_type {
+                    FrameType::Idr | FrameType::I => i,
+                    FrameType::P => p,
+                    FrameType::B => b,
+                }),
+                _ => None,
+            },
+        };
+        let reference = setup_slot.map(|slot| Reference {
+            slot,
+            frame_type,
+            frame_num,
+            pic_order_cnt,
+        });
+
+        self.backend.begin_coding(&BeginCodingInfo {
+            reset: self.reset_pending,
+            rate_control: (self.reset_pending || self.rate_control_pending)
+                .then_some(&self.config.rate_control),
+            reference_slots: &reference_slots,
+            setup_slot,
+        })?;
+        self.reset_pending = false;
+        self.rate_control_pending = false;
+        self.backend.encode(&EncodeInfo {
+            picture: &picture,
+            source: frame,
+            setup_slot: reference.map(|reference| ReferenceSlot {
+                slot: reference.slot,
+                info: reference.to_std(),
+            }),
+            reference_slots: &reference_slots,
+        })?;
+        let feedback = self.backend.end_coding()?;
+        if feedback.status != vk::QueryResultStatusKHR::COMPLETE {
+            return Err(anyhow!(
+                "Encoding the {:?} picture {} failed with status {:?}",
+                frame_type,
+                index,
+                feedback.status
+            ));
+        }
+
+        let mut data = Vec::new();
+        if frame_type == FrameType::Idr {
+            data.extend_from_slice(&self.encoded_parameters);
+            self.idr_pic_id = self.idr_pic_id.wrapping_add(1);
+        }
+        data.extend_from_slice(self.backend.bitstream(&feedback)?);
+        if let Some(reference) = reference {
+            self.dpb.mark(reference);
+        }
+
+        Ok(EncodedPicture {
+            data,
+            pts,
+            frame_type,
+        })
+    }
+}
+
+fn validate_rate_control(
+    rate_control: &RateControl,
+    capabilities: &BackendCapabilities,
+) -> Result<()> {
+    if !capabilities
+        .rate_control_modes
+        .contains(rate_control.mode())
+    {
+        return Err(anyhow!(
+            "Rate control mode {:?} is not supported",
+            rate_control.mode()
+        ));
+    }
+
+    match *rate_control {
+        RateControl::ConstantQp { i, p, b } => {
+            if let Some(qp) = [i, p, b]
+                .into_iter()
+                .find(|qp| !(capabilities.min_qp..=capabilities.max_qp).contains(qp))
+            {
+                return Err(anyhow!(
+                    "QP {} out of the supported {}..={}",
+                    qp,
+                    capabilities.min_qp,
+                    capabilities.max_qp
+                ));
+            }
+        }
+        RateControl::VariableBitrate {
+            average_bitrate,
+            max_bitrate,
+        } if average_bitrate == 0 || average_bitrate > max_bitrate => {
+            return Err(anyhow!(
+                "Average bitrate {} has to be between 1 and the maximum bitrate {}",
+                average_bitrate,
+                max_bitrate
+            ));
+        }
+        _ => {}
+    }
+    if let Some(bitrate) = rate_control.max_bitrate() {
+        if bitrate == 0 || bitrate > capabilities.max_bitrate {
+            return Err(anyhow!(
+                "Bitrate {} out of the supported 1..={}",
+                bitrate,
+                capabilities.max_bitrate
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The lowest level of Table A-1 the stream fits in, 6.2 when it fits none.
+fn level_idc(frame_size_in_mbs: u64, mbs_per_second: u64, dpb_mbs: u64, bitrate: u64) -> u8 {
+    // level_idc, MaxMBPS, MaxFS, MaxDpbMbs and MaxBR in 1000 bits per second
+    const LEVELS: [(u8, u64, u64, u64, u64); 19] = [
+        (10, 1485, 99, 396, 64),
+        (11, 3000, 396, 900, 192),
+        (12, 6000, 396, 2376, 384),
+        (13, 11880, 396, 2376, 768),
+        (20, 11880, 396, 2376, 2000),
+        (21, 19800, 792, 4752, 4000),
+        (22, 20250, 1620, 8100, 4000),
+        (30, 40500, 1620, 8100, 10000),
+        (31, 108000, 3600, 18000, 14000),
+        (32, 216000, 5120, 20480, 20000),
+        (40, 245760, 8192, 32768, 20000),
+        (41, 245760, 8192, 32768, 50000),
+        (42, 522240, 8704, 34816, 50000),
+        (50, 589824, 22080, 110400, 135000),
+        (51, 983040, 36864, 184320, 240000),
+        (52, 2073600, 36864, 184320, 240000),
+        (60, 4177920, 139264, 696320, 240000),
+        (61, 8355840, 139264, 696320, 480000),
+        (62, 16711680, 139264, 696320, 800000),
+    ];
+    // cpbBrVclFactor of the High profile, Table A-2
+    const HIGH_BITRATE_FACTOR: u64 = 1250;
+
+    LEVELS
+        .iter()
+        .find(|&&(_, max_mbps, max_fs, max_dpb_mbs, max_br)| {
+            mbs_per_second <= max_mbps
+                && frame_size_in_mbs <= max_fs
+                && dpb_mbs <= max_dpb_mbs
+                && bitrate <= max_br * HIGH_BITRATE_FACTOR
+        })
+        .map_or(62, |&(level_idc, ..)| level_idc)
+}
+
+/// The SPS of the stream: High profile progressive frames, macroblock aligned and cropped
+/// to the frame size, with the frame rate and the reordering of B frames in the VUI.
+fn sequence_parameter_set(config: &EncoderConfig, max_num_ref_frames: u32) -> Sps {
+    let width_in_mbs = config.width.div_ceil(16);
+    let height_in_mbs = config.height.div_ceil(16);
+    let frame_size_in_mbs = width_in_mbs as u64 * height_in_mbs as u64;
+    let (numerator, denominator) = config.frame_rate;
+    let level_idc = level_idc(
+        frame_size_in_mbs,
+        (frame_size_in_mbs * numerator as u64).div_ceil(denominator as u64),
+        frame_size_in_mbs * max_num_ref_frames as u64,
+        config.rate_control.max_bitrate().unwrap_or(0),
+    );
+
+    // Offsets in units of two luma samples for 4:2:0 frames
+    let crop_right = (width_in_mbs * 16 - config.width) / 2;
+    let crop_bottom = (height_in_mbs * 16 - config.height) / 2;
+
+    Sps {
+        profile_idc: 100,
+        constraint_set_flags: 0,
+        level_idc,
+        seq_parameter_set_id: 0,
+        chroma_format_idc: 1,
+        separate_colour_plane_flag: false,
+        bit_depth_luma_minus8: 0,
+        bit_depth_chroma_minus8: 0,
+        qpprime_y_zero_transform_bypass_flag: false,
+        seq_scaling_matrix_present_flag: false,
+        scaling_lists: ScalingLists::default(),
+        log2_max_frame_num_minus4: LOG2_MAX_FRAME_NUM_MINUS4,
+        pic_order_cnt_type: 0,
+        log2_max_pic_order_cnt_lsb_minus4: LOG2_MAX_PIC_ORDER_CNT_LSB_MINUS4,
+        delta_pic_order_always_zero_flag: false,
+        offset_for_non_ref_pic: 0,
+        offset_for_top_to_bottom_field: 0,
+        offset_for_ref_frame: Vec::new(),
+        max_num_ref_frames: max_num_ref_frames as u8,
+        gaps_in_frame_num_value_allowed_flag: false,
+        pic_width_in_mbs_minus1: width_in_mbs - 1,
+        pic_height_in_map_units_minus1: height_in_mbs - 1,
+        frame_mbs_only_flag: true,
+        mb_adaptive_frame_field_flag: false,
+        direct_8x8_inference_flag: true,
+        frame_cropping_flag: crop_right != 0 || crop_bottom != 0,
+        frame_crop_left_offset: 0,
+        frame_crop_right_offset: crop_right,
+        frame_crop_top_offset: 0,
+        frame_crop_bottom_offset: crop_bottom,
+        // A tick is a field period
+        vui: Some(Vui {
+            timing_info_present_flag: true,
+            num_units_in_tick: denominator,
+            time_scale: 2 * numerator,
+            fixed_frame_rate_flag: true,
+            bitstream_restriction_flag: true,
+            max_num_reorder_frames: (config.gop.consecutive_b_frames > 0) as u32,
+            max_dec_frame_buffering: max_num_ref_frames,
+            ..Default::default()
+        }),
+    }
+}
+
+/// The PPS of the stream: CABAC with 8x8 transforms, one reference per list unless a slice
+/// overrides it.
+fn picture_parameter_set() -> Pps {
+    Pps {
+        pic_parameter_set_id: 0,
+        seq_parameter_set_id: 0,
+        entropy_coding_mode_flag: true,
+        bottom_field_pic_order_in_frame_present_flag: false,
+        num_slice_groups_minus1: 0,
+        slice_group_map: None,
+        num_ref_idx_l0_default_active_minus1: 0,
+        num_ref_idx_l1_default_active_minus1: 0,
+        weighted_pred_flag: false,
+        weighted_bipred_idc: 0,
+        pic_init_qp_minus26: 0,
+        pic_init_qs_minus26: 0,
+        chroma_qp_index_offset: 0,
+        deblocking_filter_control_present_flag: true,
+        constrained_intra_pred_flag: false,
+        redundant_pic_cnt_present_flag: false,
+        transform_8x8_mode_flag: true,
+        pic_scaling_matrix_present_flag: false,
+        scaling_lists: ScalingLists::default(),
+        second_chroma_qp_index_offset: 0,
+    }
+}
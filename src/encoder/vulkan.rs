@@ -0,0 +1,1109 @@
+use std::os::raw::c_void;
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use ash::extensions::khr::{VideoEncodeQueue, VideoQueue};
+use ash::vk::native::StdVideoH264ProfileIdc_STD_VIDEO_H264_PROFILE_IDC_HIGH;
+use ash::{vk, Device, Entry, Instance};
+
+use super::backend::{
+    BackendCapabilities, BeginCodingInfo, EncodeBackend, EncodeFeedback, EncodeInfo, RateControl,
+    ReferenceSlot, SessionInfo,
+};
+use super::gop::GopStructure;
+use crate::decoder::vulkan::{align_up, allocate_session_memory, BitstreamBuffer, VideoImage};
+use crate::find_video_format;
+use crate::h264::{self, ParameterSets};
+
+/// The format of the source pictures, the NV12 layout of the frames passed in
+const SOURCE_FORMAT: vk::Format = vk::Format::G8_B8R8_2PLANE_420_UNORM;
+
+/// The feedback read back for every picture
+const FEEDBACK_FLAGS: vk::VideoEncodeFeedbackFlagsKHR = vk::VideoEncodeFeedbackFlagsKHR::from_raw(
+    vk::VideoEncodeFeedbackFlagsKHR::BITSTREAM_BUFFER_OFFSET.as_raw()
+        | vk::VideoEncodeFeedbackFlagsKHR::BITSTREAM_BYTES_WRITTEN.as_raw(),
+);
+
+/// The subset of `VkVideoCapabilitiesKHR` and its encode extensions the backend needs once
+/// the query is done.
+#[derive(Clone, Copy, Debug)]
+struct Capabilities {
+    min_bitstream_buffer_size_alignment: u64,
+    picture_access_granularity: vk::Extent2D,
+    encode_input_picture_granularity: vk::Extent2D,
+    min_coded_extent: vk::Extent2D,
+    max_coded_extent: vk::Extent2D,
+    max_dpb_slots: u32,
+    max_active_reference_pictures: u32,
+    std_header_version: vk::ExtensionProperties,
+    rate_control_modes: vk::VideoEncodeRateControlModeFlagsKHR,
+    max_bitrate: u64,
+    supported_encode_feedback_flags: vk::VideoEncodeFeedbackFlagsKHR,
+    max_p_picture_l0_reference_count: u32,
+    max_b_picture_l0_reference_count: u32,
+    max_l1_reference_count: u32,
+    min_qp: i32,
+    max_qp: i32,
+}
+
+/// The 8 bit 4:2:0 High profile pictures are encoded in. The structures are boxed because
+/// they point at each other and the session, image and query pool create infos point at
+/// them.
+struct EncodeProfile {
+    _h264: Box<vk::VideoEncodeH264ProfileInfoKHR<'static>>,
+    info: Box<[vk::VideoProfileInfoKHR<'static>; 1]>,
+    list: Box<vk::VideoProfileListInfoKHR<'static>>,
+}
+
+impl EncodeProfile {
+    fn new() -> Self {
+        let mut h264 = Box::new(
+            vk::VideoEncodeH264ProfileInfoKHR::default()
+                .std_profile_idc(StdVideoH264ProfileIdc_STD_VIDEO_H264_PROFILE_IDC_HIGH),
+        );
+        let bit_depth = vk::VideoComponentBitDepthFlagsKHR::TYPE_8;
+        let mut info = Box::new([vk::VideoProfileInfoKHR::default()
+            .video_codec_operation(vk::VideoCodecOperationFlagsKHR::ENCODE_H264)
+            .chroma_subsampling(vk::VideoChromaSubsamplingFlagsKHR::TYPE_420)
+            .luma_bit_depth(bit_depth)
+            .chroma_bit_depth(bit_depth)]);
+        info[0].p_next = &mut *h264 as *mut _ as *const c_void;
+
+        let mut list = Box::new(vk::VideoProfileListInfoKHR::default());
+        list.profile_count = 1;
+        list.p_profiles = info.as_ptr();
+
+        Self {
+            _h264: h264,
+            info,
+            list,
+        }
+    }
+
+    /// The profile list to chain into image, buffer and format queries. Any chain
+    /// left over from a previous use is cleared first.
+    fn list(&mut self) -> &mut vk::VideoProfileListInfoKHR<'static> {
+        self.list.p_next = ptr::null();
+        &mut self.list
+    }
+}
+
+/// `VkVideoEncodeRateControlInfoKHR` with the H.264 rate control and the one layer it
+/// points at, boxed for the same reason as [`EncodeProfile`].
+struct RateControlInfo {
+    layers: [vk::VideoEncodeRateControlLayerInfoKHR<'static>; 1],
+    h264: vk::VideoEncodeH264RateControlInfoKHR<'static>,
+    info: vk::VideoEncodeRateControlInfoKHR<'static>,
+}
+
+impl RateControlInfo {
+    fn new(rate_control: &RateControl, frame_rate: (u32, u32), gop: &GopStructure) -> Box<Self> {
+        let (average_bitrate, max_bitrate) = match *rate_control {
+            RateControl::ConstantQp { .. } => (0, 0),
+            RateControl::ConstantBitrate { bitrate } => (bitrate, bitrate),
+            RateControl::VariableBitrate {
+                average_bitrate,
+                max_bitrate,
+            } => (average_bitrate, max_bitrate),
+        };
+        let mut info = Box::new(Self {
+            layers: [vk::VideoEncodeRateControlLayerInfoKHR::default()
+                .average_bitrate(average_bitrate)
+                .max_bitrate(max_bitrate)
+                .frame_rate_numerator(frame_rate.0)
+                .frame_rate_denominator(frame_rate.1)],
+            h264: vk::VideoEncodeH264RateControlInfoKHR::default()
+                .gop_frame_count(gop.gop_frame_count())
+                .idr_period(gop.idr_period)
+                .consecutive_b_frame_count(gop.consecutive_b_frames)
+                .temporal_layer_count(1),
+            info: vk::VideoEncodeRateControlInfoKHR::default()
+                .rate_control_mode(rate_control.mode()),
+        });
+        info.info.p_next = &info.h264 as *const _ as *const c_void;
+        // Without rate control there are no layers to describe
+        if !matches!(rate_control, RateControl::ConstantQp { .. }) {
+            info.info.layer_count = 1;
+            info.info.p_layers = info.layers.as_ptr();
+            info.info.virtual_buffer_size_in_ms = 1000;
+            info.info.initial_virtual_buffer_size_in_ms = 500;
+        }
+        info
+    }
+
+    fn as_ptr(&self) -> *const c_void {
+        &self.info as *const _ as *const c_void
+    }
+}
+
+/// What [`EncodeBackend::begin_coding`] was called with. The commands are recorded with
+/// the encode, as the source has to be uploaded outside of the video coding scope.
+struct Coding {
+    reset: bool,
+    rate_control: Option<RateControl>,
+    reference_slots: Vec<ReferenceSlot>,
+    setup_slot: Option<usize>,
+    encoded: bool,
+    /// The source comes from the transfer queue, the encode waits for it
+    wait_for_upload: bool,
+}
+
+/// The video session with everything sized for the stream.
+struct Session {
+    capabilities: Capabilities,
+    extent: vk::Extent2D,
+    frame_rate: (u32, u32),
+    gop: GopStructure,
+
+    video_session: vk::VideoSessionKHR,
+    video_session_memory: Vec<vk::DeviceMemory>,
+    video_session_parameters: vk::VideoSessionParametersKHR,
+
+    /// One layer per DPB slot
+    dpb_image: VideoImage,
+    source_image: VideoImage,
+    /// Host visible copy of the source, padded to the coded extent
+    staging: BitstreamBuffer,
+    /// The coded picture is written here and read back
+    bitstream: BitstreamBuffer,
+    feedback_query_pool: vk::QueryPool,
+    /// The rate control the session is set to, `None` until the first picture
+    rate_control: Option<RateControl>,
+}
+
+impl Session {
+    unsafe fn destroy(&self, device: &Device, video_queue_loader: &VideoQueue) {
+        if self.video_session_parameters != vk::VideoSessionParametersKHR::null() {
+            video_queue_loader
+                .destroy_video_session_parameters(self.video_session_parameters, None);
+        }
+        video_queue_loader.destroy_video_session(self.video_session, None);
+        for &memory in self.video_session_memory.iter() {
+            device.free_memory(memory, None);
+        }
+
+        device.destroy_query_pool(self.feedback_query_pool, None);
+        self.staging.destroy(device);
+        self.bitstream.destroy(device);
+        self.dpb_image.destroy(device);
+        self.source_image.destroy(device);
+    }
+
+    /// The picture resource of the DPB slot `slot`.
+    fn dpb_picture_resource(&self, slot: usize) -> vk::VideoPictureResourceInfoKHR<'static> {
+        vk::VideoPictureResourceInfoKHR::default()
+            .coded_extent(self.extent)
+            .base_array_layer(slot as u32)
+            .image_view_binding(self.dpb_image.view)
+    }
+}
+
+/// Encodes on a queue with `VK_KHR_video_encode_queue`.
+///
+/// The backend borrows the instance and device of the application; the device must have
+/// been created with `VK_KHR_video_queue`, `VK_KHR_video_encode_queue` and
+/// `VK_KHR_video_encode_h264` enabled, a queue from `queue_family_index` and one from
+/// `transfer_queue_family_index` the frames are uploaded on.
+/// All Vulkan objects created by the backend are released on drop.
+pub struct VulkanBackend {
+    device: Device,
+    video_queue_loader: VideoQueue,
+    video_encode_queue_loader: VideoEncodeQueue,
+    pdevice: vk::PhysicalDevice,
+    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    queue_family_index: u32,
+    queue: vk::Queue,
+    transfer_queue_family_index: u32,
+    transfer_queue: vk::Queue,
+
+    profile: EncodeProfile,
+    session: Option<Session>,
+    coding: Option<Coding>,
+
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    /// Uploads on the transfer queue, `None` when it shares the encode queue family
+    transfer_commands: Option<(vk::CommandPool, vk::CommandBuffer)>,
+    fence: vk::Fence,
+    /// Signalled when the uploaded source is released to the encode queue family
+    upload_semaphore: vk::Semaphore,
+}
+
+impl VulkanBackend {
+    /// Creates the command buffers and synchronisation objects, the session follows once
+    /// the encoder is configured.
+    ///
+    /// # Safety
+    ///
+    /// `instance`, `device`, `pdevice` and the queues must be valid and belong together,
+    /// and the device must outlive the backend.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn new(
+        entry: &Entry,
+        instance: &Instance,
+        device: &Device,
+        pdevice: vk::PhysicalDevice,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        transfer_queue_family_index: u32,
+        transfer_queue: vk::Queue,
+    ) -> Result<Self> {
+        let create_commands = |queue_family_index| -> Result<_> {
+            let command_pool_create_info = vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(queue_family_index);
+            let command_pool = device.create_command_pool(&command_pool_create_info, None)?;
+
+            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
+                .command_buffer_count(1)
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+            let command_buffer = device.allocate_command_buffers(&command_buffer_allocate_info)?[0];
+            Ok((command_pool, command_buffer))
+        };
+        let (command_pool, command_buffer) = create_commands(queue_family_index)?;
+        let transfer_commands = if transfer_queue_family_index != queue_family_index {
+            Some(create_commands(transfer_queue_family_index)?)
+        } else {
+            None
+        };
+
+        let fence_create_info =
+            vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+        let fence = device.create_fence(&fence_create_info, None)?;
+        let upload_semaphore =
+            device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+
+        Ok(Self {
+            device: device.clone(),
+            video_queue_loader: VideoQueue::new(entry, instance, device),
+            video_encode_queue_loader: VideoEncodeQueue::new(entry, instance, device),
+            pdevice,
+            device_memory_properties: instance.get_physical_device_memory_properties(pdevice),
+            queue_family_index,
+            queue,
+            transfer_queue_family_index,
+            transfer_queue,
+            profile: EncodeProfile::new(),
+            session: None,
+            coding: None,
+            command_pool,
+            command_buffer,
+            transfer_commands,
+            fence,
+            upload_semaphore,
+        })
+    }
+
+    /// Coded size of the pictures, the configured one rounded up to the granularities of
+    /// the implementation
+    pub fn extent(&self) -> vk::Extent2D {
+        self.session
+            .as_ref()
+            .map_or(vk::Extent2D::default(), |session| session.extent)
+    }
+
+    /// Copies `source` into the staging buffer and records its upload into the source
+    /// image, which ends up in the encode layout, owned by the encode queue family.
+    /// Returns whether the upload went through the transfer queue.
+    unsafe fn upload_source(&mut self, source: &crate::yuv::Nv12Frame) -> Result<bool> {
+        let device = &self.device;
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+        let vk::Extent2D { width, height } = session.extent;
+        let padded = source.pad(width, height);
+        if padded.width != width || padded.height != height {
+            return Err(anyhow!(
+                "Frame of {}x{} does not fit the session for {}x{}",
+                source.width,
+                source.height,
+                width,
+                height
+            ));
+        }
+        let luma_size = padded.y.len();
+        ptr::copy_nonoverlapping(padded.y.as_ptr(), session.staging.ptr, luma_size);
+        ptr::copy_nonoverlapping(
+            padded.uv.as_ptr(),
+            session.staging.ptr.add(luma_size),
+            padded.uv.len(),
+        );
+
+        let plane = |aspect_mask, buffer_offset, width, height| vk::BufferImageCopy {
+            buffer_offset,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            ..Default::default()
+        };
+        let regions = [
+            plane(vk::ImageAspectFlags::PLANE_0, 0, width, height),
+            plane(
+                vk::ImageAspectFlags::PLANE_1,
+                luma_size as u64,
+                width / 2,
+                height / 2,
+            ),
+        ];
+        let barrier = |src_access_mask,
+                       dst_access_mask,
+                       old_layout,
+                       new_layout,
+                       src_queue_family_index,
+                       dst_queue_family_index| vk::ImageMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            old_layout,
+            new_layout,
+            src_queue_family_index,
+            dst_queue_family_index,
+            image: session.source_image.image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                level_count: 1,
+                layer_count: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // The previous contents are overwritten as a whole
+        let to_transfer = barrier(
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::QUEUE_FAMILY_IGNORED,
+            vk::QUEUE_FAMILY_IGNORED,
+        );
+        let record_copy = |command_buffer| {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                session.staging.buffer,
+                session.source_image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+        };
+
+        let Some((_, transfer_command_buffer)) = self.transfer_commands else {
+            record_copy(self.command_buffer);
+            let to_encode = barrier(
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::MEMORY_READ,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::VIDEO_ENCODE_SRC_KHR,
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+            );
+            device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_encode],
+            );
+            return Ok(false);
+        };
+
+        // Ownership transfers need a release on one queue and an acquire on the other. The
+        // transfer command buffer is free again, the previous encode waited for it.
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.reset_command_buffer(
+            transfer_command_buffer,
+            vk::CommandBufferResetFlags::empty(),
+        )?;
+        device.begin_command_buffer(transfer_command_buffer, &begin_info)?;
+        record_copy(transfer_command_buffer);
+        let release = barrier(
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::empty(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::VIDEO_ENCODE_SRC_KHR,
+            self.transfer_queue_family_index,
+            self.queue_family_index,
+        );
+        device.cmd_pipeline_barrier(
+            transfer_command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[release],
+        );
+        device.end_command_buffer(transfer_command_buffer)?;
+        let command_buffers = [transfer_command_buffer];
+        let signal_semaphores = [self.upload_semaphore];
+        let submit_info = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        device.queue_submit(self.transfer_queue, &[submit_info], vk::Fence::null())?;
+
+        let acquire = barrier(
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::MEMORY_READ,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::VIDEO_ENCODE_SRC_KHR,
+            self.transfer_queue_family_index,
+            self.queue_family_index,
+        );
+        device.cmd_pipeline_barrier(
+            self.command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[acquire],
+        );
+        Ok(true)
+    }
+}
+
+/// Queries the encode capabilities of `pdevice` for `profile`.
+unsafe fn query_capabilities(
+    video_queue_loader: &VideoQueue,
+    pdevice: vk::PhysicalDevice,
+    profile: &EncodeProfile,
+) -> Result<Capabilities> {
+    let mut h264_encode_capabilities = vk::VideoEncodeH264CapabilitiesKHR::default();
+    let mut encode_capabilities = vk::VideoEncodeCapabilitiesKHR {
+        p_next: &mut h264_encode_capabilities as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    let mut video_capabilities = vk::VideoCapabilitiesKHR {
+        p_next: &mut encode_capabilities as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+
+    video_queue_loader.get_physical_device_video_capabilities(
+        pdevice,
+        &profile.info[0],
+        &mut video_capabilities,
+    )?;
+
+    Ok(Capabilities {
+        min_bitstream_buffer_size_alignment: video_capabilities.min_bitstream_buffer_size_alignment,
+        picture_access_granularity: video_capabilities.picture_access_granularity,
+        encode_input_picture_granularity: encode_capabilities.encode_input_picture_granularity,
+        min_coded_extent: video_capabilities.min_coded_extent,
+        max_coded_extent: video_capabilities.max_coded_extent,
+        max_dpb_slots: video_capabilities.max_dpb_slots,
+        max_active_reference_pictures: video_capabilities.max_active_reference_pictures,
+        std_header_version: video_capabilities.std_header_version,
+        rate_control_modes: encode_capabilities.rate_control_modes,
+        max_bitrate: encode_capabilities.max_bitrate,
+        supported_encode_feedback_flags: encode_capabilities.supported_encode_feedback_flags,
+        max_p_picture_l0_reference_count: h264_encode_capabilities.max_p_picture_l0_reference_count,
+        max_b_picture_l0_reference_count: h264_encode_capabilities.max_b_picture_l0_reference_count,
+        max_l1_reference_count: h264_encode_capabilities.max_l1_reference_count,
+        min_qp: h264_encode_capabilities.min_qp,
+        max_qp: h264_encode_capabilities.max_qp,
+    })
+}
+
+impl EncodeBackend for VulkanBackend {
+    fn capabilities(&mut self) -> Result<BackendCapabilities> {
+        let capabilities =
+            unsafe { query_capabilities(&self.video_queue_loader, self.pdevice, &self.profile)? };
+        Ok(BackendCapabilities {
+            max_coded_extent: (
+                capabilities.max_coded_extent.width,
+                capabilities.max_coded_extent.height,
+            ),
+            max_dpb_slots: capabilities.max_dpb_slots,
+            max_active_reference_pictures: capabilities.max_active_reference_pictures,
+            max_p_l0_references: capabilities.max_p_picture_l0_reference_count,
+            max_b_l0_references: capabilities.max_b_picture_l0_reference_count,
+            max_l1_references: capabilities.max_l1_reference_count,
+            rate_control_modes: capabilities.rate_control_modes,
+            max_bitrate: capabilities.max_bitrate,
+            min_qp: capabilities.min_qp,
+            max_qp: capabilities.max_qp,
+        })
+    }
+
+    fn create_session(&mut self, info: &SessionInfo) -> Result<()> {
+        let device = &self.device;
+        let video_queue_loader = &self.video_queue_loader;
+        let pdevice = self.pdevice;
+        let profile = &mut self.profile;
+
+        unsafe {
+            let capabilities = query_capabilities(video_queue_loader, pdevice, profile)?;
+            if !capabilities
+                .supported_encode_feedback_flags
+                .contains(FEEDBACK_FLAGS)
+            {
+                return Err(anyhow!(
+                    "Encode feedback {:?} is not supported",
+                    FEEDBACK_FLAGS
+                ));
+            }
+
+            let align = |value, granularity: fn(&vk::Extent2D) -> u32, minimum| {
+                let value = align_up(
+                    value as u64,
+                    granularity(&capabilities.picture_access_granularity) as u64,
+                );
+                align_up(
+                    value,
+                    granularity(&capabilities.encode_input_picture_granularity) as u64,
+                )
+                .max(minimum as u64) as u32
+            };
+            let (width, height) = info.coded_extent;
+            let extent = vk::Extent2D {
+                width: align(
+                    width,
+                    |extent| extent.width,
+                    capabilities.min_coded_extent.width,
+                ),
+                height: align(
+                    height,
+                    |extent| extent.height,
+                    capabilities.min_coded_extent.height,
+                ),
+            };
+            if extent.width > capabilities.max_coded_extent.width
+                || extent.height > capabilities.max_coded_extent.height
+            {
+                return Err(anyhow!(
+                    "Coded extent {}x{} exceeds the supported {}x{}",
+                    extent.width,
+                    extent.height,
+                    capabilities.max_coded_extent.width,
+                    capabilities.max_coded_extent.height
+                ));
+            }
+
+            // Formats
+            let source_format = find_video_format(
+                pdevice,
+                video_queue_loader,
+                vk::ImageUsageFlags::VIDEO_ENCODE_SRC_KHR,
+                profile.list(),
+            )?;
+            if source_format != SOURCE_FORMAT {
+                return Err(anyhow!(
+                    "Encode source format {:?} is not supported, frames are NV12",
+                    source_format
+                ));
+            }
+            let dpb_format = find_video_format(
+                pdevice,
+                video_queue_loader,
+                vk::ImageUsageFlags::VIDEO_ENCODE_DPB_KHR,
+                profile.list(),
+            )?;
+
+            // Images and buffers
+            let dpb_image = VideoImage::new(
+                device,
+                &self.device_memory_properties,
+                profile.list(),
+                dpb_format,
+                extent,
+                info.dpb_slots,
+                vk::ImageUsageFlags::VIDEO_ENCODE_DPB_KHR,
+            )?;
+            let source_image = VideoImage::new(
+                device,
+                &self.device_memory_properties,
+                profile.list(),
+                SOURCE_FORMAT,
+                extent,
+                1,
+                vk::ImageUsageFlags::VIDEO_ENCODE_SRC_KHR | vk::ImageUsageFlags::TRANSFER_DST,
+            )?;
+            let frame_size = extent.width as u64 * extent.height as u64 * 3 / 2;
+            let staging = BitstreamBuffer::new(
+                device,
+                &self.device_memory_properties,
+                profile.list(),
+                frame_size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            )?;
+            // Coded pictures hardly ever come out larger than the raw frame
+            let bitstream = BitstreamBuffer::new(
+                device,
+                &self.device_memory_properties,
+                profile.list(),
+                align_up(frame_size, capabilities.min_bitstream_buffer_size_alignment),
+                vk::BufferUsageFlags::VIDEO_ENCODE_DST_KHR,
+            )?;
+
+            let mut feedback_create_info = vk::QueryPoolVideoEncodeFeedbackCreateInfoKHR::default()
+                .encode_feedback_flags(FEEDBACK_FLAGS);
+            feedback_create_info.p_next = &profile.info[0] as *const _ as *const c_void;
+            let query_pool_create_info = vk::QueryPoolCreateInfo {
+                p_next: &feedback_create_info as *const _ as *const c_void,
+                query_type: vk::QueryType::VIDEO_ENCODE_FEEDBACK_KHR,
+                query_count: 1,
+                ..Default::default()
+            };
+            let feedback_query_pool = device.create_query_pool(&query_pool_create_info, None)?;
+
+            // Video session
+            let video_session_info = vk::VideoSessionCreateInfoKHR::default()
+                .queue_family_index(self.queue_family_index)
+                .video_profile(&profile.info[0])
+                .picture_format(SOURCE_FORMAT)
+                .max_coded_extent(extent)
+                .reference_picture_format(dpb_format)
+                .max_dpb_slots(info.dpb_slots)
+                .max_active_reference_pictures(info.max_active_reference_pictures)
+                .std_header_version(&capabilities.std_header_version);
+
+            let video_session = video_queue_loader.create_video_session(
+                device.handle(),
+                &video_session_info,
+                None,
+            )?;
+            let video_session_memory = allocate_session_memory(
+                device,
+                video_queue_loader,
+                &self.device_memory_properties,
+                video_session,
+            )?;
+
+            let session = Session {
+                capabilities,
+                extent,
+                frame_rate: info.frame_rate,
+                gop: info.gop,
+                video_session,
+                video_session_memory,
+                video_session_parameters: vk::VideoSessionParametersKHR::null(),
+                dpb_image,
+                source_image,
+                staging,
+                bitstream,
+                feedback_query_pool,
+                rate_control: None,
+            };
+            if let Some(previous) = self.session.replace(session) {
+                device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+                previous.destroy(device, video_queue_loader);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the previous encode to complete, then recreates the session parameters
+    /// object from all of `parameter_sets`.
+    fn update_parameters(&mut self, parameter_sets: &ParameterSets) -> Result<()> {
+        unsafe {
+            self.device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+        }
+        let video_queue_loader = &self.video_queue_loader;
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+
+        let std_parameter_sets = parameter_sets.to_std();
+        let add_info = std_parameter_sets.encode_add_info();
+        let mut h264_create_info = vk::VideoEncodeH264SessionParametersCreateInfoKHR::default()
+            .max_std_sps_count(h264::sps::MAX_SPS_COUNT as u32)
+            .max_std_pps_count(h264::pps::MAX_PPS_COUNT as u32)
+            .parameters_add_info(&add_info);
+        let create_info = vk::VideoSessionParametersCreateInfoKHR::default()
+            .push_next(&mut h264_create_info)
+            .video_session(session.video_session);
+        let video_session_parameters =
+            unsafe { video_queue_loader.create_video_session_parameters(&create_info, None)? };
+
+        if session.video_session_parameters != vk::VideoSessionParametersKHR::null() {
+            unsafe {
+                video_queue_loader
+                    .destroy_video_session_parameters(session.video_session_parameters, None);
+            }
+        }
+        session.video_session_parameters = video_session_parameters;
+
+        Ok(())
+    }
+
+    fn encoded_parameters(&mut self, sps_id: u8, pps_id: u8) -> Result<Vec<u8>> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+
+        let mut h264_get_info = vk::VideoEncodeH264SessionParametersGetInfoKHR::default()
+            .write_std_sps(true)
+            .write_std_pps(true)
+            .std_sps_id(sps_id as u32)
+            .std_pps_id(pps_id as u32);
+        let get_info = vk::VideoEncodeSessionParametersGetInfoKHR::default()
+            .video_session_parameters(session.video_session_parameters)
+            .push_next(&mut h264_get_info);
+        let mut feedback = vk::VideoEncodeSessionParametersFeedbackInfoKHR::default();
+
+        unsafe {
+            let len = self
+                .video_encode_queue_loader
+                .get_encoded_video_session_parameters_len(&get_info, &mut feedback)?;
+            let mut data = vec![0; len];
+            self.video_encode_queue_loader
+                .get_encoded_video_session_parameters(&get_info, &mut feedback, &mut data)?;
+            Ok(data)
+        }
+    }
+
+    fn begin_coding(&mut self, info: &BeginCodingInfo) -> Result<()> {
+        if self.session.is_none() {
+            return Err(anyhow!("No video session created yet"));
+        }
+        if self.coding.is_some() {
+            return Err(anyhow!("Coding begun twice"));
+        }
+        self.coding = Some(Coding {
+            reset: info.reset,
+            rate_control: info.rate_control.copied(),
+            reference_slots: info.reference_slots.to_vec(),
+            setup_slot: info.setup_slot,
+            encoded: false,
+            wait_for_upload: false,
+        });
+        Ok(())
+    }
+
+    /// Uploads the source and records the encode: the reference pictures are bound along
+    /// with the setup slot, the session is reset and its rate control set where needed, and
+    /// the picture is encoded with its feedback query.
+    fn encode(&mut self, info: &EncodeInfo) -> Result<()> {
+        match &self.coding {
+            Some(coding) if !coding.encoded => {}
+            _ => return Err(anyhow!("Encode outside of begin and end of coding")),
+        }
+        let device = &self.device;
+        let command_buffer = self.command_buffer;
+
+        unsafe {
+            device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+            device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device.begin_command_buffer(command_buffer, &begin_info)?;
+        }
+        let wait_for_upload = unsafe { self.upload_source(info.source)? };
+
+        let device = &self.device;
+        let (Some(session), Some(coding)) = (self.session.as_mut(), self.coding.as_mut()) else {
+            unreachable!()
+        };
+        coding.encoded = true;
+        coding.wait_for_upload = wait_for_upload;
+        unsafe {
+            session.dpb_image.transition(
+                device,
+                command_buffer,
+                vk::ImageLayout::VIDEO_ENCODE_DPB_KHR,
+            );
+            device.cmd_reset_query_pool(command_buffer, session.feedback_query_pool, 0, 1);
+        }
+
+        // The rate control in effect has to be restated when coding begins, a reset
+        // returns to the default
+        let current_rate_control =
+            session
+                .rate_control
+                .filter(|_| !coding.reset)
+                .map(|rate_control| {
+                    RateControlInfo::new(&rate_control, session.frame_rate, &session.gop)
+                });
+        let new_rate_control = coding.rate_control.map(|rate_control| {
+            RateControlInfo::new(&rate_control, session.frame_rate, &session.gop)
+        });
+
+        // The setup slot is not associated with a picture yet when coding begins
+        let begin_dpb_slot_infos: Vec<_> = coding
+            .reference_slots
+            .iter()
+            .map(|reference| {
+                vk::VideoEncodeH264DpbSlotInfoKHR::default().std_reference_info(&reference.info)
+            })
+            .collect();
+        let begin_picture_resources: Vec<_> = coding
+            .reference_slots
+            .iter()
+            .map(|reference| reference.slot)
+            .chain(coding.setup_slot)
+            .map(|slot| session.dpb_picture_resource(slot))
+            .collect();
+        let begin_reference_slots: Vec<_> = coding
+            .reference_slots
+            .iter()
+            .zip(&begin_dpb_slot_infos)
+            .map(|(reference, dpb_slot_info)| vk::VideoReferenceSlotInfoKHR {
+                p_next: dpb_slot_info as *const _ as *const c_void,
+                slot_index: reference.slot as i32,
+                ..Default::default()
+            })
+            .chain(
+                coding
+                    .setup_slot
+                    .map(|_| vk::VideoReferenceSlotInfoKHR::default().slot_index(-1)),
+            )
+            .zip(&begin_picture_resources)
+            .map(|(reference_slot, picture_resource)| {
+                reference_slot.picture_resource(picture_resource)
+            })
+            .collect();
+        let mut begin_coding_info = vk::VideoBeginCodingInfoKHR::default()
+            .video_session(session.video_session)
+            .video_session_parameters(session.video_session_parameters)
+            .reference_slots(&begin_reference_slots);
+        if let Some(rate_control) = &current_rate_control {
+            begin_coding_info.p_next = rate_control.as_ptr();
+        }
+
+        // Reference pictures
+        let dpb_slot_infos: Vec<_> = info
+            .reference_slots
+            .iter()
+            .map(|reference| {
+                vk::VideoEncodeH264DpbSlotInfoKHR::default().std_reference_info(&reference.info)
+            })
+            .collect();
+        let reference_picture_resources: Vec<_> = info
+            .reference_slots
+            .iter()
+            .map(|reference| session.dpb_picture_resource(reference.slot))
+            .collect();
+        let reference_slots: Vec<_> = info
+            .reference_slots
+            .iter()
+            .zip(&dpb_slot_infos)
+            .zip(&reference_picture_resources)
+            .map(|((reference, dpb_slot_info), picture_resource)| {
+                vk::VideoReferenceSlotInfoKHR {
+                    p_next: dpb_slot_info as *const _ as *const c_void,
+                    slot_index: reference.slot as i32,
+                    ..Default::default()
+                }
+                .picture_resource(picture_resource)
+            })
+            .collect();
+
+        // Reference pictures are reconstructed into the slot picked by the encoder
+        let setup_dpb_slot_info = info.setup_slot.as_ref().map(|setup_slot| {
+            vk::VideoEncodeH264DpbSlotInfoKHR::default().std_reference_info(&setup_slot.info)
+        });
+        let setup_picture_resource = info
+            .setup_slot
+            .map(|setup_slot| session.dpb_picture_resource(setup_slot.slot));
+        let setup_reference_slot = info
+            .setup_slot
+            .iter()
+            .zip(&setup_dpb_slot_info)
+            .zip(&setup_picture_resource)
+            .map(|((setup_slot, dpb_slot_info), picture_resource)| {
+                vk::VideoReferenceSlotInfoKHR {
+                    p_next: dpb_slot_info as *const _ as *const c_void,
+                    slot_index: setup_slot.slot as i32,
+                    ..Default::default()
+                }
+                .picture_resource(picture_resource)
+            })
+            .next();
+
+        // The picture, its slice and reference lists
+        let std_reference_lists = info.picture.std_reference_lists;
+        let std_slice_header = info.picture.std_slice_header;
+        let mut std_picture_info = info.picture.std_picture_info;
+        std_picture_info.pRefLists = &std_reference_lists;
+        let slices = [vk::VideoEncodeH264NaluSliceInfoKHR::default()
+            .constant_qp(info.picture.constant_qp.unwrap_or(0))
+            .std_slice_header(&std_slice_header)];
+        let mut h264_picture_info = vk::VideoEncodeH264PictureInfoKHR::default()
+            .nalu_slice_entries(&slices)
+            .std_picture_info(&std_picture_info);
+
+        let source_picture_resource = vk::VideoPictureResourceInfoKHR::default()
+            .coded_extent(session.extent)
+            .image_view_binding(session.source_image.view);
+        let mut encode_info = vk::VideoEncodeInfoKHR::default()
+            .dst_buffer(session.bitstream.buffer)
+            .dst_buffer_offset(0)
+            .dst_buffer_range(session.bitstream.size)
+            .src_picture_resource(source_picture_resource)
+            .reference_slots(&reference_slots);
+        if let Some(setup_reference_slot) = &setup_reference_slot {
+            encode_info = encode_info.setup_reference_slot(setup_reference_slot);
+        }
+
+        unsafe {
+            self.video_queue_loader
+                .cmd_begin_video_coding(command_buffer, &begin_coding_info);
+            let mut control_flags = vk::VideoCodingControlFlagsKHR::empty();
+            if coding.reset {
+                control_flags |= vk::VideoCodingControlFlagsKHR::RESET;
+            }
+            let mut control_info = vk::VideoCodingControlInfoKHR::default();
+            if let Some(rate_control) = &new_rate_control {
+                control_flags |= vk::VideoCodingControlFlagsKHR::ENCODE_RATE_CONTROL;
+                control_info.p_next = rate_control.as_ptr();
+            }
+            if !control_flags.is_empty() {
+                self.video_queue_loader
+                    .cmd_control_video_coding(command_buffer, &control_info.flags(control_flags));
+            }
+            session.rate_control = coding.rate_control.or(session.rate_control);
+
+            device.cmd_begin_query(
+                command_buffer,
+                session.feedback_query_pool,
+                0,
+                vk::QueryControlFlags::empty(),
+            );
+            self.video_encode_queue_loader.cmd_encode_video(
+                command_buffer,
+                &encode_info.push_next(&mut h264_picture_info),
+            );
+            device.cmd_end_query(command_buffer, session.feedback_query_pool, 0);
+        }
+
+        Ok(())
+    }
+
+    /// Submits the recorded encode, waits for it to complete and reads the feedback.
+    fn end_coding(&mut self) -> Result<EncodeFeedback> {
+        let coding = self
+            .coding
+            .take()
+            .filter(|coding| coding.encoded)
+            .ok_or_else(|| anyhow!("Coding ended without an encode"))?;
+        let device = &self.device;
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+
+        let mut results = [[0u64; 3]];
+        unsafe {
+            self.video_queue_loader
+                .cmd_end_video_coding(self.command_buffer, &vk::VideoEndCodingInfoKHR::default());
+            // The bitstream is read on the host
+            let barrier = vk::BufferMemoryBarrier {
+                src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+                dst_access_mask: vk::AccessFlags::HOST_READ,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                buffer: session.bitstream.buffer,
+                size: vk::WHOLE_SIZE,
+                ..Default::default()
+            };
+            device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+            device.end_command_buffer(self.command_buffer)?;
+
+            let command_buffers = [self.command_buffer];
+            let wait_semaphores = [self.upload_semaphore];
+            let wait_mask = [vk::PipelineStageFlags::ALL_COMMANDS];
+            let mut submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+            if coding.wait_for_upload {
+                submit_info = submit_info
+                    .wait_semaphores(&wait_semaphores)
+                    .wait_dst_stage_mask(&wait_mask);
+            }
+            device.reset_fences(&[self.fence])?;
+            device.queue_submit(self.queue, &[submit_info], self.fence)?;
+            device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+
+            device.get_query_pool_results(
+                session.feedback_query_pool,
+                0,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64
+                    | vk::QueryResultFlags::WAIT
+                    | vk::QueryResultFlags::WITH_STATUS_KHR,
+            )?;
+        }
+
+        // Offset and bytes written in the order of their flags, then the status
+        let [offset, bytes_written, status] = results[0];
+        Ok(EncodeFeedback {
+            offset,
+            bytes_written,
+            status: vk::QueryResultStatusKHR::from_raw(status as i32),
+        })
+    }
+
+    fn bitstream(&self, feedback: &EncodeFeedback) -> Result<&[u8]> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow!("No video session created yet"))?;
+        if feedback.offset + feedback.bytes_written > session.bitstream.size {
+            return Err(anyhow!(
+                "Feedback {:?} outside of the bitstream buffer of {} bytes",
+                feedback,
+                session.bitstream.size
+            ));
+        }
+        // The buffer stays mapped and is not written until the next encode
+        Ok(unsafe {
+            std::slice::from_raw_parts(
+                session.bitstream.ptr.add(feedback.offset as usize),
+                feedback.bytes_written as usize,
+            )
+        })
+    }
+}
+
+impl Drop for VulkanBackend {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .ok();
+            // An upload may be in flight without an encode waiting for it
+            self.device.queue_wait_idle(self.transfer_queue).ok();
+
+            if let Some(session) = &self.session {
+                session.destroy(&self.device, &self.video_queue_loader);
+            }
+
+            self.device.destroy_semaphore(self.upload_semaphore, None);
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+            if let Some((transfer_command_pool, _)) = self.transfer_commands {
+                self.device
+                    .destroy_command_pool(transfer_command_pool, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::h264::ParameterSets;
+
+/// Why an avcC record could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AvcConfigError {
+    /// The record ends before the field at `offset`
+    Truncated { offset: usize, len: usize },
+    /// configurationVersion other than 1
+    UnsupportedVersion(u8),
+    /// lengthSizeMinusOne of 2, NAL unit lengths are 1, 2 or 4 bytes
+    InvalidLengthSize(u8),
+    /// A parameter set claims more bytes than the record has left
+    OversizedNalUnit { length: usize, remaining: usize },
+    /// A zero length parameter set
+    EmptyNalUnit,
+}
+
+impl fmt::Display for AvcConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AvcConfigError::Truncated { offset, len } => {
+                write!(f, "avcC of {} bytes is truncated at offset {}", len, offset)
+            }
+            AvcConfigError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported avcC version {}", version)
+            }
+            AvcConfigError::InvalidLengthSize(length_size_minus_one) => write!(
+                f,
+                "Invalid avcC NAL unit length size {}",
+                length_size_minus_one + 1
+            ),
+            AvcConfigError::OversizedNalUnit { length, remaining } => write!(
+                f,
+                "avcC NAL unit of {} bytes exceeds the {} bytes left",
+                length, remaining
+            ),
+            AvcConfigError::EmptyNalUnit => write!(f, "Empty avcC NAL unit"),
+        }
+    }
+}
+
+impl Error for AvcConfigError {}
+
+/// The fields avcC records of the High profiles append after the PPS
+/// (ISO/IEC 14496-15 5.3.3.1.2).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HighProfileExtension {
+    pub chroma_format: u8,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    /// Sequence parameter set extension NAL units
+    pub sps_ext: Vec<Vec<u8>>,
+}
+
+/// AVCDecoderConfigurationRecord, the payload of the `avcC` box of `avc1` and `avc3`
+/// sample entries (ISO/IEC 14496-15 5.3.3.1).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AvcDecoderConfiguration {
+    pub profile_indication: u8,
+    pub profile_compatibility: u8,
+    pub level_indication: u8,
+    /// Size of the NAL unit length fields of the samples, lengthSizeMinusOne + 1
+    pub length_size: usize,
+    pub sps: Vec<Vec<u8>>,
+    pub pps: Vec<Vec<u8>>,
+    /// Present for the High profiles, unless the muxer left it out as many do
+    pub high_profile: Option<HighProfileExtension>,
+}
+
+/// Reads the record front to back, bounds checked.
+struct RecordReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn u8(&mut self) -> Result<u8, AvcConfigError> {
+        let byte = *self
+            .data
+            .get(self.offset)
+            .ok_or(AvcConfigError::Truncated {
+                offset: self.offset,
+                len: self.data.len(),
+            })?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, AvcConfigError> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    /// `count` NAL units, each with a 16 bit length.
+    fn nal_units(&mut self, count: u8) -> Result<Vec<Vec<u8>>, AvcConfigError> {
+        let mut nal_units = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let length = self.u16()? as usize;
+            if length == 0 {
+                return Err(AvcConfigError::EmptyNalUnit);
+            }
+            if length > self.remaining() {
+                return Err(AvcConfigError::OversizedNalUnit {
+                    length,
+                    remaining: self.remaining(),
+                });
+            }
+            nal_units.push(self.data[self.offset..self.offset + length].to_vec());
+            self.offset += length;
+        }
+        Ok(nal_units)
+    }
+}
+
+impl AvcDecoderConfiguration {
+    pub fn parse(data: &[u8]) -> Result<Self, AvcConfigError> {
+        let mut reader = RecordReader { data, offset: 0 };
+
+        let version = reader.u8()?;
+        if version != 1 {
+            return Err(AvcConfigError::UnsupportedVersion(version));
+        }
+        let profile_indication = reader.u8()?;
+        let profile_compatibility = reader.u8()?;
+        let level_indication = reader.u8()?;
+        let length_size_minus_one = reader.u8()? & 0b11;
+        if length_size_minus_one == 2 {
+            return Err(AvcConfigError::InvalidLengthSize(length_size_minus_one));
+        }
+
+        let num_sps = reader.u8()? & 0x1f;
+        let sps = reader.nal_units(num_sps)?;
+        let num_pps = reader.u8()?;
+        let pps = reader.nal_units(num_pps)?;
+
+        // High, High 10, High 4:2:2 and High 4:4:4 records without the extension are
+        // common enough to accept
+        let has_extension =
+            matches!(profile_indication, 100 | 110 | 122 | 144) && reader.remaining() > 0;
+        let high_profile = if has_extension {
+            let chroma_format = reader.u8()? & 0b11;
+            let bit_depth_luma = (reader.u8()? & 0b111) + 8;
+            let bit_depth_chroma = (reader.u8()? & 0b111) + 8;
+            let num_sps_ext = reader.u8()?;
+            Some(HighProfileExtension {
+                chroma_format,
+                bit_depth_luma,
+                bit_depth_chroma,
+                sps_ext: reader.nal_units(num_sps_ext)?,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            profile_indication,
+            profile_compatibility,
+            level_indication,
+            length_size: length_size_minus_one as usize + 1,
+            sps,
+            pps,
+            high_profile,
+        })
+    }
+
+    /// The SPS and PPS of the record. SPS extensions are not used for decoding.
+    pub fn parameter_sets(&self) -> Result<ParameterSets> {
+        let mut parameter_sets = ParameterSets::default();
+        for nal in self.sps.iter().chain(self.pps.iter()) {
+            parameter_sets.add_nal(nal)?;
+        }
+        Ok(parameter_sets)
+    }
+}
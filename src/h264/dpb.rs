@@ -0,0 +1,524 @@
+use anyhow::{anyhow, Result};
+
+use crate::h264::slice::{Mmco, SliceHeader};
+use crate::h264::sps::Sps;
+
+/// A frame marked as used for reference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReferenceFrame {
+    /// DPB slot holding the reconstructed frame, `None` for the frames inferred for a gap
+    /// in frame_num, which are never actually decoded
+    pub slot: Option<usize>,
+    pub frame_num: u32,
+    /// FrameNumWrap relative to the picture being decoded, 8.2.4.1
+    pub frame_num_wrap: i32,
+    /// Set for long-term reference frames
+    pub long_term_frame_idx: Option<u32>,
+    pub top_field_order_cnt: i32,
+    pub bottom_field_order_cnt: i32,
+}
+
+impl ReferenceFrame {
+    pub fn is_long_term(&self) -> bool {
+        self.long_term_frame_idx.is_some()
+    }
+
+    pub fn is_non_existing(&self) -> bool {
+        self.slot.is_none()
+    }
+
+    pub fn pic_order_cnt(&self) -> i32 {
+        self.top_field_order_cnt.min(self.bottom_field_order_cnt)
+    }
+
+    /// PicNum of a short-term or LongTermPicNum of a long-term frame
+    pub fn pic_num(&self) -> i32 {
+        match self.long_term_frame_idx {
+            Some(long_term_frame_idx) => long_term_frame_idx as i32,
+            None => self.frame_num_wrap,
+        }
+    }
+}
+
+/// The picture being decoded, returned by [`Dpb::start_picture`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CurrentPicture {
+    /// DPB slot the picture is reconstructed into
+    pub slot: usize,
+    pub frame_num: u32,
+    pub idr: bool,
+    pub is_reference: bool,
+    /// Set when the picture is marked as long-term, by long_term_reference_flag or MMCO 6
+    pub long_term_frame_idx: Option<u32>,
+    /// The picture carries MMCO 5, which resets frame_num and POC for the following pictures
+    pub has_mmco5: bool,
+    pub top_field_order_cnt: i32,
+    pub bottom_field_order_cnt: i32,
+}
+
+impl CurrentPicture {
+    pub fn pic_order_cnt(&self) -> i32 {
+        self.top_field_order_cnt.min(self.bottom_field_order_cnt)
+    }
+}
+
+/// Reference picture marking (8.2.5) and picture order count derivation (8.2.1) for
+/// frame coded H.264 streams, together with the assignment of DPB slots.
+///
+/// Each picture goes through [`Dpb::start_picture`], which handles gaps in frame_num,
+/// computes the POC and picks a free slot, and [`Dpb::finish_picture`] once it is decoded,
+/// which applies the sliding window or the MMCOs and stores the picture for reference.
+#[derive(Clone, Debug)]
+pub struct Dpb {
+    slot_count: usize,
+    frames: Vec<ReferenceFrame>,
+    /// Slots of pictures still in use outside the DPB, e.g. waiting for output
+    held_slots: Vec<usize>,
+    /// MaxLongTermFrameIdx, `None` for "no long-term frame indices"
+    max_long_term_frame_idx: Option<u32>,
+
+    prev_pic_order_cnt_msb: i32,
+    prev_pic_order_cnt_lsb: i32,
+    prev_frame_num_offset: i32,
+    prev_frame_num: u32,
+    prev_ref_frame_num: u32,
+
+    /// PicOrderCntMsb and FrameNumOffset of the picture between start and finish
+    pic_order_cnt_msb: i32,
+    frame_num_offset: i32,
+}
+
+impl Dpb {
+    /// `slot_count` must leave room for the current picture next to max_num_ref_frames
+    /// references.
+    pub fn new(slot_count: usize) -> Self {
+        Self {
+            slot_count,
+            frames: Vec::new(),
+            held_slots: Vec::new(),
+            max_long_term_frame_idx: None,
+            prev_pic_order_cnt_msb: 0,
+            prev_pic_order_cnt_lsb: 0,
+            prev_frame_num_offset: 0,
+            prev_frame_num: 0,
+            prev_ref_frame_num: 0,
+            pic_order_cnt_msb: 0,
+            frame_num_offset: 0,
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    /// Frames currently marked as used for reference, including non-existing ones.
+    pub fn references(&self) -> &[ReferenceFrame] {
+        &self.frames
+    }
+
+    /// Marks every frame as unused for reference, e.g. before seeking. Held slots stay
+    /// held.
+    pub fn clear(&mut self) {
+        let held_slots = std::mem::take(&mut self.held_slots);
+        *self = Self::new(self.slot_count);
+        self.held_slots = held_slots;
+    }
+
+    /// Keeps `slot` from being picked for new pictures after its picture stops being a
+    /// reference, until [`Dpb::release_slot`].
+    pub fn hold_slot(&mut self, slot: usize) {
+        self.held_slots.push(slot);
+    }
+
+    pub fn release_slot(&mut self, slot: usize) {
+        if let Some(index) = self.held_slots.iter().position(|&held| held == slot) {
+            self.held_slots.swap_remove(index);
+        }
+    }
+
+    /// Prepares the decoding of the picture the slice belongs to.
+    pub fn start_picture(&mut self, header: &SliceHeader, sps: &Sps) -> Result<CurrentPicture> {
+        if header.field_pic_flag {
+            return Err(anyhow!("Field pictures are not supported"));
+        }
+
+        let max_frame_num = sps.max_frame_num();
+
+        if header.idr_pic_flag {
+            // All reference pictures are marked as unused, 8.2.5.1, none of them can be
+            // referenced by the IDR picture itself
+            self.frames.clear();
+            self.max_long_term_frame_idx = None;
+        } else if header.frame_num != self.prev_ref_frame_num
+            && header.frame_num != (self.prev_ref_frame_num + 1) % max_frame_num
+        {
+            // Also done when gaps_in_frame_num_value_allowed_flag is 0, where the gap means
+            // pictures were lost, so there is something to refer to in their place
+            self.fill_frame_num_gap(header.frame_num, sps);
+        }
+
+        self.update_frame_num_wrap(header.frame_num, max_frame_num);
+
+        let (top_field_order_cnt, bottom_field_order_cnt) = self.pic_order_cnt(header, sps);
+
+        let slot = (0..self.slot_count)
+            .find(|&slot| {
+                !self.held_slots.contains(&slot)
+                    && !self.frames.iter().any(|frame| frame.slot == Some(slot))
+            })
+            .ok_or_else(|| anyhow!("No free DPB slot out of {}", self.slot_count))?;
+
+        Ok(CurrentPicture {
+            slot,
+            frame_num: header.frame_num,
+            idr: header.idr_pic_flag,
+            is_reference: header.is_reference(),
+            long_term_frame_idx: None,
+            has_mmco5: header.has_mmco5(),
+            top_field_order_cnt,
+            bottom_field_order_cnt,
+        })
+    }
+
+    /// Runs the reference picture marking process for the decoded picture and stores it
+    /// when it is a reference. Returns the picture with its final marking and, after
+    /// MMCO 5, its POC relative to itself.
+    pub fn finish_picture(
+        &mut self,
+        header: &SliceHeader,
+        sps: &Sps,
+        mut picture: CurrentPicture,
+    ) -> Result<CurrentPicture> {
+        let max_num_ref_frames = (sps.max_num_ref_frames as usize).max(1);
+
+        if picture.is_reference {
+            let marking = header.dec_ref_pic_marking.clone().unwrap_or_default();
+
+            if picture.idr {
+                if marking.long_term_reference_flag {
+                    picture.long_term_frame_idx = Some(0);
+                    self.max_long_term_frame_idx = Some(0);
+                } else {
+                    self.max_long_term_frame_idx = None;
+                }
+            } else if marking.adaptive_ref_pic_marking_mode_flag {
+                for &mmco in marking.mmco.iter() {
+                    self.apply_mmco(mmco, &mut picture);
+                }
+            }
+
+            // The sliding window of 8.2.5.3, also applied after MMCOs that leave no room
+            // for the current picture, which a conforming stream never does but a broken one
+            // would otherwise run out of slots with
+            if picture.long_term_frame_idx.is_none() {
+                self.sliding_window(max_num_ref_frames);
+            }
+            while self.frames.len() >= max_num_ref_frames {
+                self.frames.remove(0);
+            }
+        }
+
+        if picture.has_mmco5 {
+            // 7.4.3 and 8.2.1: the picture is treated as having frame_num 0 and a POC
+            // relative to itself
+            let temp_pic_order_cnt = picture.pic_order_cnt();
+            picture.top_field_order_cnt -= temp_pic_order_cnt;
+            picture.bottom_field_order_cnt -= temp_pic_order_cnt;
+            picture.frame_num = 0;
+        }
+
+        if picture.is_reference {
+            self.frames.push(ReferenceFrame {
+                slot: Some(picture.slot),
+                frame_num: picture.frame_num,
+                frame_num_wrap: picture.frame_num as i32,
+                long_term_frame_idx: picture.long_term_frame_idx,
+                top_field_order_cnt: picture.top_field_order_cnt,
+                bottom_field_order_cnt: picture.bottom_field_order_cnt,
+            });
+
+            if picture.has_mmco5 {
+                self.prev_pic_order_cnt_msb = 0;
+                self.prev_pic_order_cnt_lsb = picture.top_field_order_cnt;
+            } else {
+                self.prev_pic_order_cnt_msb = self.pic_order_cnt_msb;
+                self.prev_pic_order_cnt_lsb = header.pic_order_cnt_lsb as i32;
+            }
+            self.prev_ref_frame_num = picture.frame_num;
+        }
+
+        self.prev_frame_num_offset = if picture.has_mmco5 {
+            0
+        } else {
+            self.frame_num_offset
+        };
+        self.prev_frame_num = picture.frame_num;
+
+        Ok(picture)
+    }
+
+    /// 8.2.4.1, relative to the current frame_num
+    fn update_frame_num_wrap(&mut self, frame_num: u32, max_frame_num: u32) {
+        for frame in self.frames.iter_mut() {
+            frame.frame_num_wrap = if frame.frame_num > frame_num {
+                frame.frame_num as i32 - max_frame_num as i32
+            } else {
+                frame.frame_num as i32
+            };
+        }
+    }
+
+    /// 8.2.5.2, inserts a non-existing short-term frame for every missing frame_num.
+    fn fill_frame_num_gap(&mut self, frame_num: u32, sps: &Sps) {
+        let max_frame_num = sps.max_frame_num();
+        let max_num_ref_frames = (sps.max_num_ref_frames as usize).max(1);
+
+        let mut unused_short_term_frame_num = (self.prev_ref_frame_num + 1) % max_frame_num;
+        while unused_short_term_frame_num != frame_num {
+            self.update_frame_num_wrap(unused_short_term_frame_num, max_frame_num);
+            self.sliding_window(max_num_ref_frames);
+
+            // Only POC types 1 and 2 derive a POC for the inferred frames
+            let (top_field_order_cnt, bottom_field_order_cnt) = match sps.pic_order_cnt_type {
+                0 => (0, 0),
+                _ => {
+                    let frame_num_offset =
+                        self.frame_num_offset(false, unused_short_term_frame_num, max_frame_num);
+                    let pic_order_cnt = self.expected_pic_order_cnt(
+                        sps,
+                        frame_num_offset,
+                        unused_short_term_frame_num,
+                        1,
+                    );
+                    self.frame_num_offset = frame_num_offset;
+                    (pic_order_cnt, pic_order_cnt)
+                }
+            };
+
+            self.frames.push(ReferenceFrame {
+                slot: None,
+                frame_num: unused_short_term_frame_num,
+                frame_num_wrap: unused_short_term_frame_num as i32,
+                long_term_frame_idx: None,
+                top_field_order_cnt,
+                bottom_field_order_cnt,
+            });
+
+            self.prev_frame_num_offset = self.frame_num_offset;
+            self.prev_frame_num = unused_short_term_frame_num;
+            self.prev_ref_frame_num = unused_short_term_frame_num;
+            unused_short_term_frame_num = (unused_short_term_frame_num + 1) % max_frame_num;
+        }
+    }
+
+    /// 8.2.5.3, drops the short-term frame with the smallest FrameNumWrap once the DPB
+    /// holds max_num_ref_frames frames.
+    fn sliding_window(&mut self, max_num_ref_frames: usize) {
+        while self.frames.len() >= max_num_ref_frames {
+            let oldest = self
+                .frames
+                .iter()
+                .enumerate()
+                .filter(|(_, frame)| !frame.is_long_term())
+                .min_by_key(|(_, frame)| frame.frame_num_wrap)
+                .map(|(index, _)| index);
+
+            match oldest {
+                Some(index) => {
+                    self.frames.remove(index);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// 8.2.5.4 for frames, CurrPicNum being frame_num.
+    fn apply_mmco(&mut self, mmco: Mmco, picture: &mut CurrentPicture) {
+        let curr_pic_num = picture.frame_num as i32;
+
+        match mmco {
+            Mmco::ForgetShortTerm {
+                difference_of_pic_nums_minus1,
+            } => {
+                let pic_num_x = curr_pic_num - (difference_of_pic_nums_minus1 as i32 + 1);
+                self.frames
+                    .retain(|frame| frame.is_long_term() || frame.frame_num_wrap != pic_num_x);
+            }
+            Mmco::ForgetLongTerm { long_term_pic_num } => {
+                self.frames
+                    .retain(|frame| frame.long_term_frame_idx != Some(long_term_pic_num));
+            }
+            Mmco::ShortTermToLongTerm {
+                difference_of_pic_nums_minus1,
+                long_term_frame_idx,
+            } => {
+                let pic_num_x = curr_pic_num - (difference_of_pic_nums_minus1 as i32 + 1);
+                let index = self
+                    .frames
+                    .iter()
+                    .position(|frame| !frame.is_long_term() && frame.frame_num_wrap == pic_num_x);
+
+                if let Some(index) = index {
+                    let frame_num = self.frames[index].frame_num;
+                    self.frames.retain(|frame| {
+                        frame.long_term_frame_idx != Some(long_term_frame_idx)
+                            || frame.frame_num == frame_num
+                    });
+                    if let Some(frame) = self
+                        .frames
+                        .iter_mut()
+                        .find(|frame| !frame.is_long_term() && frame.frame_num_wrap == pic_num_x)
+                    {
+                        frame.long_term_frame_idx = Some(long_term_frame_idx);
+                    }
+                }
+            }
+            Mmco::MaxLongTermFrameIdx {
+                max_long_term_frame_idx_plus1,
+            } => {
+                self.max_long_term_frame_idx = max_long_term_frame_idx_plus1.checked_sub(1);
+                let max_long_term_frame_idx = self.max_long_term_frame_idx;
+                self.frames.retain(|frame| match frame.long_term_frame_idx {
+                    Some(long_term_frame_idx) => {
+                        max_long_term_frame_idx.is_some_and(|max| long_term_frame_idx <= max)
+                    }
+                    None => true,
+                });
+            }
+            Mmco::ForgetAll => {
+                self.frames.clear();
+                self.max_long_term_frame_idx = None;
+            }
+            Mmco::CurrentToLongTerm {
+                long_term_frame_idx,
+            } => {
+                self.frames
+                    .retain(|frame| frame.long_term_frame_idx != Some(long_term_frame_idx));
+                picture.long_term_frame_idx = Some(long_term_frame_idx);
+            }
+        }
+    }
+
+    /// FrameNumOffset for POC types 1 and 2
+    fn frame_num_offset(&self, idr: bool, frame_num: u32, max_frame_num: u32) -> i32 {
+        if idr {
+            0
+        } else if self.prev_frame_num > frame_num {
+            self.prev_frame_num_offset + max_frame_num as i32
+        } else {
+            self.prev_frame_num_offset
+        }
+    }
+
+    /// expectedPicOrderCnt of POC type 1, or tempPicOrderCnt of type 2
+    fn expected_pic_order_cnt(
+        &self,
+        sps: &Sps,
+        frame_num_offset: i32,
+        frame_num: u32,
+        nal_ref_idc: u8,
+    ) -> i32 {
+        if sps.pic_order_cnt_type == 2 {
+            return match nal_ref_idc {
+                0 => 2 * (frame_num_offset + frame_num as i32) - 1,
+                _ => 2 * (frame_num_offset + frame_num as i32),
+            };
+        }
+
+        let num_ref_frames_in_pic_order_cnt_cycle = sps.offset_for_ref_frame.len() as i32;
+        let mut abs_frame_num = if num_ref_frames_in_pic_order_cnt_cycle != 0 {
+            frame_num_offset + frame_num as i32
+        } else {
+            0
+        };
+        if nal_ref_idc == 0 && abs_frame_num > 0 {
+            abs_frame_num -= 1;
+        }
+
+        let mut expected_pic_order_cnt = 0;
+        if abs_frame_num > 0 {
+            let pic_order_cnt_cycle_cnt =
+                (abs_frame_num - 1) / num_ref_frames_in_pic_order_cnt_cycle;
+            let frame_num_in_pic_order_cnt_cycle =
+                (abs_frame_num - 1) % num_ref_frames_in_pic_order_cnt_cycle;
+            let expected_delta_per_pic_order_cnt_cycle: i32 = sps.offset_for_ref_frame.iter().sum();
+
+            expected_pic_order_cnt = pic_order_cnt_cycle_cnt
+                * expected_delta_per_pic_order_cnt_cycle
+                + sps.offset_for_ref_frame[..=frame_num_in_pic_order_cnt_cycle as usize]
+                    .iter()
+                    .sum::<i32>();
+        }
+        if nal_ref_idc == 0 {
+            expected_pic_order_cnt += sps.offset_for_non_ref_pic;
+        }
+
+        expected_pic_order_cnt
+    }
+
+    /// 8.2.1, returns TopFieldOrderCnt and BottomFieldOrderCnt of a frame.
+    fn pic_order_cnt(&mut self, header: &SliceHeader, sps: &Sps) -> (i32, i32) {
+        let idr = header.idr_pic_flag;
+        let nal_ref_idc = header.nal_ref_idc;
+        let frame_num = header.frame_num;
+
+        match sps.pic_order_cnt_type {
+            0 => {
+                // 8.2.1.1
+                let (prev_pic_order_cnt_msb, prev_pic_order_cnt_lsb) = if idr {
+                    (0, 0)
+                } else {
+                    (self.prev_pic_order_cnt_msb, self.prev_pic_order_cnt_lsb)
+                };
+                let max_pic_order_cnt_lsb = sps.max_pic_order_cnt_lsb() as i32;
+                let pic_order_cnt_lsb = header.pic_order_cnt_lsb as i32;
+
+                self.pic_order_cnt_msb = if pic_order_cnt_lsb < prev_pic_order_cnt_lsb
+                    && prev_pic_order_cnt_lsb - pic_order_cnt_lsb >= max_pic_order_cnt_lsb / 2
+                {
+                    prev_pic_order_cnt_msb + max_pic_order_cnt_lsb
+                } else if pic_order_cnt_lsb > prev_pic_order_cnt_lsb
+                    && pic_order_cnt_lsb - prev_pic_order_cnt_lsb > max_pic_order_cnt_lsb / 2
+                {
+                    prev_pic_order_cnt_msb - max_pic_order_cnt_lsb
+                } else {
+                    prev_pic_order_cnt_msb
+                };
+
+                let top_field_order_cnt = self.pic_order_cnt_msb + pic_order_cnt_lsb;
+                (
+                    top_field_order_cnt,
+                    top_field_order_cnt + header.delta_pic_order_cnt_bottom,
+                )
+            }
+            pic_order_cnt_type => {
+                // 8.2.1.2 and 8.2.1.3
+                self.frame_num_offset = self.frame_num_offset(idr, frame_num, sps.max_frame_num());
+
+                if pic_order_cnt_type == 2 {
+                    let temp_pic_order_cnt = if idr {
+                        0
+                    } else {
+                        self.expected_pic_order_cnt(
+                            sps,
+                            self.frame_num_offset,
+                            frame_num,
+                            nal_ref_idc,
+                        )
+                    };
+                    return (temp_pic_order_cnt, temp_pic_order_cnt);
+                }
+
+                let top_field_order_cnt =
+                    self.expected_pic_order_cnt(sps, self.frame_num_offset, frame_num, nal_ref_idc)
+                        + header.delta_pic_order_cnt[0];
+                (
+                    top_field_order_cnt,
+                    top_field_order_cnt
+                        + sps.offset_for_top_to_bottom_field
+                        + header.delta_pic_order_cnt[1],
+                )
+            }
+        }
+    }
+}
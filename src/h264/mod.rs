@@ -0,0 +1,275 @@
+pub mod avcc;
+pub mod dpb;
+pub mod output;
+pub mod pps;
+pub mod slice;
+pub mod software;
+pub mod sps;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use ash::vk::native::{StdVideoH264PictureParameterSet, StdVideoH264SequenceParameterSet};
+
+use crate::bitreader::nal_to_rbsp;
+use pps::{Pps, StdPps, MAX_PPS_COUNT};
+use sps::{Sps, StdSps, MAX_SPS_COUNT};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NalUnitType {
+    Unspecified(u8),
+    Slice,
+    SliceDataA,
+    SliceDataB,
+    SliceDataC,
+    IdrSlice,
+    Sei,
+    Sps,
+    Pps,
+    AccessUnitDelimiter,
+    EndOfSequence,
+    EndOfStream,
+    FillerData,
+    SpsExtension,
+    PrefixNal,
+    SubsetSps,
+    AuxiliarySlice,
+    SliceExtension,
+    Reserved(u8),
+}
+
+impl From<u8> for NalUnitType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => NalUnitType::Slice,
+            2 => NalUnitType::SliceDataA,
+            3 => NalUnitType::SliceDataB,
+            4 => NalUnitType::SliceDataC,
+            5 => NalUnitType::IdrSlice,
+            6 => NalUnitType::Sei,
+            7 => NalUnitType::Sps,
+            8 => NalUnitType::Pps,
+            9 => NalUnitType::AccessUnitDelimiter,
+            10 => NalUnitType::EndOfSequence,
+            11 => NalUnitType::EndOfStream,
+            12 => NalUnitType::FillerData,
+            13 => NalUnitType::SpsExtension,
+            14 => NalUnitType::PrefixNal,
+            15 => NalUnitType::SubsetSps,
+            19 => NalUnitType::AuxiliarySlice,
+            20 => NalUnitType::SliceExtension,
+            0 | 24..=31 => NalUnitType::Unspecified(value),
+            _ => NalUnitType::Reserved(value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NalUnitHeader {
+    pub nal_ref_idc: u8,
+    pub nal_unit_type: NalUnitType,
+}
+
+impl NalUnitHeader {
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let byte = *nal.first().ok_or_else(|| anyhow!("Empty NAL unit"))?;
+
+        if byte & 0x80 != 0 {
+            return Err(anyhow!("forbidden_zero_bit is set"));
+        }
+
+        Ok(Self {
+            nal_ref_idc: (byte >> 5) & 0b11,
+            nal_unit_type: NalUnitType::from(byte & 0x1f),
+        })
+    }
+}
+
+/// A NAL unit found in an Annex-B byte stream.
+#[derive(Clone, Copy, Debug)]
+pub struct NalUnit<'a> {
+    /// Offset of the start code prefix
+    pub offset: usize,
+    /// 3 or 4 bytes
+    pub start_code_len: usize,
+    /// The NAL unit without its start code, still containing emulation prevention bytes
+    pub data: &'a [u8],
+}
+
+impl<'a> NalUnit<'a> {
+    /// The NAL unit payload with the emulation prevention bytes removed
+    pub fn rbsp(&self) -> Vec<u8> {
+        nal_to_rbsp(self.data)
+    }
+}
+
+pub(crate) fn find_start_code(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(3)
+        .position(|window| window == [0, 0, 1])
+        .map(|position| from + position)
+}
+
+/// Iterates over the NAL units of an Annex-B byte stream, splitting on 3 and 4 byte start codes.
+pub struct NalUnits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> NalUnits<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = NalUnit<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start_code = find_start_code(self.data, self.position)?;
+            let (offset, start_code_len) =
+                if start_code > self.position && self.data[start_code - 1] == 0 {
+                    (start_code - 1, 4)
+                } else {
+                    (start_code, 3)
+                };
+
+            let begin = start_code + 3;
+            let end = find_start_code(self.data, begin).unwrap_or(self.data.len());
+            // A zero before the next start code is taken as the zero_byte of a 4 byte one
+            self.position = if end > begin && self.data[end - 1] == 0 {
+                end - 1
+            } else {
+                end
+            };
+
+            // trailing_zero_8bits and the leading zero of a following 4 byte start code
+            let mut nal = &self.data[begin..end];
+            while let [rest @ .., 0] = nal {
+                nal = rest;
+            }
+
+            if !nal.is_empty() {
+                return Some(NalUnit {
+                    offset,
+                    start_code_len,
+                    data: nal,
+                });
+            }
+        }
+    }
+}
+
+/// Active SPS and PPS tables, indexed by their ids.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParameterSets {
+    sps: Vec<Option<Sps>>,
+    pps: Vec<Option<Pps>>,
+}
+
+impl Default for ParameterSets {
+    fn default() -> Self {
+        Self {
+            sps: vec![None; MAX_SPS_COUNT],
+            pps: vec![None; MAX_PPS_COUNT],
+        }
+    }
+}
+
+impl ParameterSets {
+    /// Parses an SPS or PPS NAL unit and stores it under its id, replacing any previous one.
+    /// Returns false for NAL units of any other type.
+    pub fn add_nal(&mut self, nal: &[u8]) -> Result<bool> {
+        match NalUnitHeader::parse(nal)?.nal_unit_type {
+            NalUnitType::Sps => {
+                self.insert_sps(Sps::parse(nal)?);
+                Ok(true)
+            }
+            NalUnitType::Pps => {
+                let pps = Pps::parse(nal, |id| self.sps(id))?;
+                self.insert_pps(pps);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Stores an SPS under its id, replacing any previous one.
+    pub fn insert_sps(&mut self, sps: Sps) {
+        let id = sps.seq_parameter_set_id as usize;
+        self.sps[id] = Some(sps);
+    }
+
+    /// Stores a PPS under its id, replacing any previous one.
+    pub fn insert_pps(&mut self, pps: Pps) {
+        let id = pps.pic_parameter_set_id as usize;
+        self.pps[id] = Some(pps);
+    }
+
+    pub fn sps(&self, id: u8) -> Option<&Sps> {
+        self.sps.get(id as usize)?.as_ref()
+    }
+
+    pub fn pps(&self, id: u8) -> Option<&Pps> {
+        self.pps.get(id as usize)?.as_ref()
+    }
+
+    /// Looks up a PPS together with the SPS it depends on.
+    pub fn active(&self, pic_parameter_set_id: u8) -> Result<(&Pps, &Sps)> {
+        let pps = self
+            .pps(pic_parameter_set_id)
+            .ok_or_else(|| anyhow!("Unknown PPS {}", pic_parameter_set_id))?;
+        let sps = self.sps(pps.seq_parameter_set_id).ok_or_else(|| {
+            anyhow!(
+                "PPS {} references unknown SPS {}",
+                pic_parameter_set_id,
+                pps.seq_parameter_set_id
+            )
+        })?;
+        Ok((pps, sps))
+    }
+
+    pub fn sps_count(&self) -> usize {
+        self.sps.iter().flatten().count()
+    }
+
+    pub fn pps_count(&self) -> usize {
+        self.pps.iter().flatten().count()
+    }
+
+    pub fn to_std(&self) -> StdParameterSets {
+        let sps_backing: Vec<StdSps> = self.sps.iter().flatten().map(Sps::to_std).collect();
+        let pps_backing: Vec<StdPps> = self.pps.iter().flatten().map(Pps::to_std).collect();
+
+        StdParameterSets {
+            sps: sps_backing.iter().map(|sps| sps.sps).collect(),
+            pps: pps_backing.iter().map(|pps| pps.pps).collect(),
+            _sps_backing: sps_backing,
+            _pps_backing: pps_backing,
+        }
+    }
+}
+
+/// Contiguous arrays of std parameter sets, as expected by
+/// `VkVideoDecodeH264SessionParametersAddInfoKHR` and
+/// `VkVideoEncodeH264SessionParametersAddInfoKHR`.
+pub struct StdParameterSets {
+    pub sps: Vec<StdVideoH264SequenceParameterSet>,
+    pub pps: Vec<StdVideoH264PictureParameterSet>,
+    _sps_backing: Vec<StdSps>,
+    _pps_backing: Vec<StdPps>,
+}
+
+impl StdParameterSets {
+    pub fn add_info(&self) -> vk::VideoDecodeH264SessionParametersAddInfoKHR<'_> {
+        vk::VideoDecodeH264SessionParametersAddInfoKHR::default()
+            .std_sp_ss(&self.sps)
+            .std_pp_ss(&self.pps)
+    }
+
+    pub fn encode_add_info(&self) -> vk::VideoEncodeH264SessionParametersAddInfoKHR<'_> {
+        vk::VideoEncodeH264SessionParametersAddInfoKHR::default()
+            .std_sp_ss(&self.sps)
+            .std_pp_ss(&self.pps)
+    }
+}
@@ -0,0 +1,80 @@
+use crate::h264::sps::Sps;
+
+/// Puts decoded pictures back into display order, following the bumping process of C.4.5.
+///
+/// Pictures wait until more than max_num_reorder_frames of them are queued, then the one
+/// with the smallest POC is output first. An IDR picture, or one with MMCO 5, starts a new
+/// POC sequence, so everything queued before it is output first.
+#[derive(Clone, Debug)]
+pub struct OutputQueue<T> {
+    max_num_reorder_frames: usize,
+    max_dec_frame_buffering: usize,
+    pictures: Vec<(i32, T)>,
+}
+
+impl<T> OutputQueue<T> {
+    pub fn new(max_num_reorder_frames: usize, max_dec_frame_buffering: usize) -> Self {
+        Self {
+            max_num_reorder_frames,
+            max_dec_frame_buffering,
+            pictures: Vec::new(),
+        }
+    }
+
+    /// Sized from the bitstream restrictions of the SPS, or the level limits without them.
+    pub fn from_sps(sps: &Sps) -> Self {
+        Self::new(
+            sps.max_num_reorder_frames() as usize,
+            sps.max_dec_frame_buffering() as usize,
+        )
+    }
+
+    /// Number of pictures waiting for output.
+    pub fn len(&self) -> usize {
+        self.pictures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pictures.is_empty()
+    }
+
+    /// Pictures waiting for output, in decoding order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.pictures.iter().map(|(_, picture)| picture)
+    }
+
+    /// Queues a decoded picture. `idr` is set for IDR pictures and pictures with MMCO 5.
+    /// Returns the pictures that are ready for output, in output order.
+    pub fn push(&mut self, pic_order_cnt: i32, idr: bool, picture: T) -> Vec<T> {
+        let mut output = if idr { self.flush() } else { Vec::new() };
+
+        self.pictures.push((pic_order_cnt, picture));
+        while self.pictures.len() > self.max_num_reorder_frames
+            || self.pictures.len() > self.max_dec_frame_buffering
+        {
+            output.extend(self.bump());
+        }
+
+        output
+    }
+
+    /// Outputs every queued picture, at the end of the stream or of a sequence.
+    pub fn flush(&mut self) -> Vec<T> {
+        let mut output = Vec::with_capacity(self.pictures.len());
+        while let Some(picture) = self.bump() {
+            output.push(picture);
+        }
+        output
+    }
+
+    /// Removes the picture with the smallest POC, the first one in decoding order on ties.
+    fn bump(&mut self) -> Option<T> {
+        let index = self
+            .pictures
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (pic_order_cnt, _))| *pic_order_cnt)
+            .map(|(index, _)| index)?;
+        Some(self.pictures.remove(index).1)
+    }
+}
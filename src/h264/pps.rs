@@ -0,0 +1,287 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{StdVideoH264PictureParameterSet, StdVideoH264ScalingLists};
+
+use crate::bitreader::{nal_to_rbsp, BitReader};
+use crate::h264::sps::{ScalingLists, Sps};
+use crate::h264::{NalUnitHeader, NalUnitType};
+
+pub const MAX_PPS_COUNT: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SliceGroupMap {
+    Interleaved {
+        run_length_minus1: Vec<u32>,
+    },
+    Dispersed,
+    Foreground {
+        top_left: Vec<u32>,
+        bottom_right: Vec<u32>,
+    },
+    /// Box-out (3), raster scan (4) and wipe (5)
+    Changing {
+        slice_group_map_type: u8,
+        slice_group_change_direction_flag: bool,
+        slice_group_change_rate_minus1: u32,
+    },
+    Explicit {
+        slice_group_id: Vec<u32>,
+    },
+}
+
+/// Picture parameter set, 7.3.2.2
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pps {
+    pub pic_parameter_set_id: u8,
+    pub seq_parameter_set_id: u8,
+    pub entropy_coding_mode_flag: bool,
+    pub bottom_field_pic_order_in_frame_present_flag: bool,
+    pub num_slice_groups_minus1: u32,
+    pub slice_group_map: Option<SliceGroupMap>,
+    pub num_ref_idx_l0_default_active_minus1: u8,
+    pub num_ref_idx_l1_default_active_minus1: u8,
+    pub weighted_pred_flag: bool,
+    pub weighted_bipred_idc: u8,
+    pub pic_init_qp_minus26: i8,
+    pub pic_init_qs_minus26: i8,
+    pub chroma_qp_index_offset: i8,
+    pub deblocking_filter_control_present_flag: bool,
+    pub constrained_intra_pred_flag: bool,
+    pub redundant_pic_cnt_present_flag: bool,
+    pub transform_8x8_mode_flag: bool,
+    pub pic_scaling_matrix_present_flag: bool,
+    pub scaling_lists: ScalingLists,
+    pub second_chroma_qp_index_offset: i8,
+}
+
+impl Pps {
+    /// Parses a PPS NAL unit. `sps_lookup` resolves the referenced sequence parameter set,
+    /// which is needed for the chroma format and the scaling list fall-back.
+    pub fn parse<'a, F>(nal: &[u8], sps_lookup: F) -> Result<Self>
+    where
+        F: FnOnce(u8) -> Option<&'a Sps>,
+    {
+        let header = NalUnitHeader::parse(nal)?;
+        if header.nal_unit_type != NalUnitType::Pps {
+            return Err(anyhow!(
+                "Expected a PPS NAL unit, got {:?}",
+                header.nal_unit_type
+            ));
+        }
+
+        let rbsp = nal_to_rbsp(&nal[1..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let pic_parameter_set_id = reader.read_ue()?;
+        if pic_parameter_set_id as usize >= MAX_PPS_COUNT {
+            return Err(anyhow!(
+                "Invalid pic_parameter_set_id {}",
+                pic_parameter_set_id
+            ));
+        }
+        let seq_parameter_set_id = reader.read_ue()?;
+        let sps = u8::try_from(seq_parameter_set_id)
+            .ok()
+            .and_then(sps_lookup)
+            .ok_or_else(|| {
+                anyhow!(
+                    "PPS {} references unknown SPS {}",
+                    pic_parameter_set_id,
+                    seq_parameter_set_id
+                )
+            })?;
+
+        let entropy_coding_mode_flag = reader.read_flag()?;
+        let bottom_field_pic_order_in_frame_present_flag = reader.read_flag()?;
+
+        let num_slice_groups_minus1 = reader.read_ue()?;
+        if num_slice_groups_minus1 > 7 {
+            return Err(anyhow!(
+                "Invalid num_slice_groups_minus1 {}",
+                num_slice_groups_minus1
+            ));
+        }
+
+        let slice_group_map = if num_slice_groups_minus1 > 0 {
+            Some(match reader.read_ue()? {
+                0 => SliceGroupMap::Interleaved {
+                    run_length_minus1: (0..=num_slice_groups_minus1)
+                        .map(|_| reader.read_ue())
+                        .collect::<Result<_>>()?,
+                },
+                1 => SliceGroupMap::Dispersed,
+                2 => {
+                    let mut top_left = Vec::new();
+                    let mut bottom_right = Vec::new();
+                    for _ in 0..num_slice_groups_minus1 {
+                        top_left.push(reader.read_ue()?);
+                        bottom_right.push(reader.read_ue()?);
+                    }
+                    SliceGroupMap::Foreground {
+                        top_left,
+                        bottom_right,
+                    }
+                }
+                slice_group_map_type @ 3..=5 => SliceGroupMap::Changing {
+                    slice_group_map_type: slice_group_map_type as u8,
+                    slice_group_change_direction_flag: reader.read_flag()?,
+                    slice_group_change_rate_minus1: reader.read_ue()?,
+                },
+                6 => {
+                    let pic_size_in_map_units_minus1 = reader.read_ue()?;
+                    let bits = 32 - num_slice_groups_minus1.leading_zeros();
+                    SliceGroupMap::Explicit {
+                        slice_group_id: (0..=pic_size_in_map_units_minus1)
+                            .map(|_| reader.read_bits(bits))
+                            .collect::<Result<_>>()?,
+                    }
+                }
+                slice_group_map_type => {
+                    return Err(anyhow!(
+                        "Invalid slice_group_map_type {}",
+                        slice_group_map_type
+                    ))
+                }
+            })
+        } else {
+            None
+        };
+
+        let num_ref_idx_l0_default_active_minus1 = reader.read_ue()?;
+        let num_ref_idx_l1_default_active_minus1 = reader.read_ue()?;
+        if num_ref_idx_l0_default_active_minus1 > 31 || num_ref_idx_l1_default_active_minus1 > 31 {
+            return Err(anyhow!(
+                "Invalid num_ref_idx_default_active_minus1 l0 {} l1 {}",
+                num_ref_idx_l0_default_active_minus1,
+                num_ref_idx_l1_default_active_minus1
+            ));
+        }
+
+        let weighted_pred_flag = reader.read_flag()?;
+        let weighted_bipred_idc = reader.read_bits(2)?;
+        if weighted_bipred_idc > 2 {
+            return Err(anyhow!("Invalid weighted_bipred_idc 3"));
+        }
+
+        let pic_init_qp_minus26 = reader.read_se()?;
+        let pic_init_qs_minus26 = reader.read_se()?;
+        let chroma_qp_index_offset = reader.read_se()?;
+        if !(-(26 + 6 * sps.bit_depth_luma_minus8 as i32)..=25).contains(&pic_init_qp_minus26)
+            || !(-26..=25).contains(&pic_init_qs_minus26)
+            || !(-12..=12).contains(&chroma_qp_index_offset)
+        {
+            return Err(anyhow!(
+                "Invalid quantization parameters qp {} qs {} chroma offset {}",
+                pic_init_qp_minus26,
+                pic_init_qs_minus26,
+                chroma_qp_index_offset
+            ));
+        }
+
+        let deblocking_filter_control_present_flag = reader.read_flag()?;
+        let constrained_intra_pred_flag = reader.read_flag()?;
+        let redundant_pic_cnt_present_flag = reader.read_flag()?;
+
+        // Without a picture level matrix the sequence level one applies
+        let fallback = sps
+            .seq_scaling_matrix_present_flag
+            .then_some(&sps.scaling_lists);
+        let mut transform_8x8_mode_flag = false;
+        let mut pic_scaling_matrix_present_flag = false;
+        let mut scaling_lists = sps.scaling_lists.clone();
+        let mut second_chroma_qp_index_offset = chroma_qp_index_offset;
+
+        if reader.more_rbsp_data() {
+            transform_8x8_mode_flag = reader.read_flag()?;
+
+            pic_scaling_matrix_present_flag = reader.read_flag()?;
+            if pic_scaling_matrix_present_flag {
+                let count = 6 + if sps.chroma_format_idc != 3 { 2 } else { 6 }
+                    * transform_8x8_mode_flag as usize;
+                scaling_lists = ScalingLists::parse(&mut reader, count, fallback)?;
+            }
+
+            second_chroma_qp_index_offset = reader.read_se()?;
+            if !(-12..=12).contains(&second_chroma_qp_index_offset) {
+                return Err(anyhow!(
+                    "Invalid second_chroma_qp_index_offset {}",
+                    second_chroma_qp_index_offset
+                ));
+            }
+        }
+
+        Ok(Self {
+            pic_parameter_set_id: pic_parameter_set_id as u8,
+            seq_parameter_set_id: seq_parameter_set_id as u8,
+            entropy_coding_mode_flag,
+            bottom_field_pic_order_in_frame_present_flag,
+            num_slice_groups_minus1,
+            slice_group_map,
+            num_ref_idx_l0_default_active_minus1: num_ref_idx_l0_default_active_minus1 as u8,
+            num_ref_idx_l1_default_active_minus1: num_ref_idx_l1_default_active_minus1 as u8,
+            weighted_pred_flag,
+            weighted_bipred_idc: weighted_bipred_idc as u8,
+            pic_init_qp_minus26: pic_init_qp_minus26 as i8,
+            pic_init_qs_minus26: pic_init_qs_minus26 as i8,
+            chroma_qp_index_offset: chroma_qp_index_offset as i8,
+            deblocking_filter_control_present_flag,
+            constrained_intra_pred_flag,
+            redundant_pic_cnt_present_flag,
+            transform_8x8_mode_flag,
+            pic_scaling_matrix_present_flag,
+            scaling_lists,
+            second_chroma_qp_index_offset: second_chroma_qp_index_offset as i8,
+        })
+    }
+
+    pub fn to_std(&self) -> StdPps {
+        let mut pps: StdVideoH264PictureParameterSet = unsafe { mem::zeroed() };
+
+        pps.flags
+            .set_transform_8x8_mode_flag(self.transform_8x8_mode_flag as u32);
+        pps.flags
+            .set_redundant_pic_cnt_present_flag(self.redundant_pic_cnt_present_flag as u32);
+        pps.flags
+            .set_constrained_intra_pred_flag(self.constrained_intra_pred_flag as u32);
+        pps.flags.set_deblocking_filter_control_present_flag(
+            self.deblocking_filter_control_present_flag as u32,
+        );
+        pps.flags
+            .set_weighted_pred_flag(self.weighted_pred_flag as u32);
+        pps.flags.set_bottom_field_pic_order_in_frame_present_flag(
+            self.bottom_field_pic_order_in_frame_present_flag as u32,
+        );
+        pps.flags
+            .set_entropy_coding_mode_flag(self.entropy_coding_mode_flag as u32);
+        pps.flags
+            .set_pic_scaling_matrix_present_flag(self.pic_scaling_matrix_present_flag as u32);
+
+        pps.seq_parameter_set_id = self.seq_parameter_set_id;
+        pps.pic_parameter_set_id = self.pic_parameter_set_id;
+        pps.num_ref_idx_l0_default_active_minus1 = self.num_ref_idx_l0_default_active_minus1;
+        pps.num_ref_idx_l1_default_active_minus1 = self.num_ref_idx_l1_default_active_minus1;
+        pps.weighted_bipred_idc = self.weighted_bipred_idc as u32;
+        pps.pic_init_qp_minus26 = self.pic_init_qp_minus26;
+        pps.pic_init_qs_minus26 = self.pic_init_qs_minus26;
+        pps.chroma_qp_index_offset = self.chroma_qp_index_offset;
+        pps.second_chroma_qp_index_offset = self.second_chroma_qp_index_offset;
+
+        let mut std = StdPps {
+            pps,
+            scaling_lists: Box::new(self.scaling_lists.to_std()),
+        };
+
+        if self.pic_scaling_matrix_present_flag {
+            std.pps.pScalingLists = &*std.scaling_lists;
+        }
+
+        std
+    }
+}
+
+/// `StdVideoH264PictureParameterSet` together with the scaling lists it references.
+pub struct StdPps {
+    pub pps: StdVideoH264PictureParameterSet,
+    scaling_lists: Box<StdVideoH264ScalingLists>,
+}
@@ -0,0 +1,509 @@
+use anyhow::{anyhow, Result};
+
+use crate::bitreader::{nal_to_rbsp, BitReader};
+use crate::h264::pps::SliceGroupMap;
+use crate::h264::{NalUnitHeader, NalUnitType, NalUnits, ParameterSets};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SliceType {
+    P,
+    B,
+    #[default]
+    I,
+    Sp,
+    Si,
+}
+
+impl SliceType {
+    fn from_raw(slice_type: u32) -> Result<Self> {
+        Ok(match slice_type % 5 {
+            0 => SliceType::P,
+            1 => SliceType::B,
+            2 => SliceType::I,
+            3 => SliceType::Sp,
+            4 => SliceType::Si,
+            _ => return Err(anyhow!("Invalid slice_type {}", slice_type)),
+        })
+    }
+
+    pub fn is_intra(self) -> bool {
+        matches!(self, SliceType::I | SliceType::Si)
+    }
+}
+
+/// One entry of ref_pic_list_modification()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefPicListModification {
+    ShortTermSubtract(u32),
+    ShortTermAdd(u32),
+    LongTerm(u32),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WeightEntry {
+    pub luma_weight: Option<(i32, i32)>,
+    pub chroma_weight: Option<[(i32, i32); 2]>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PredWeightTable {
+    pub luma_log2_weight_denom: u32,
+    pub chroma_log2_weight_denom: u32,
+    pub l0: Vec<WeightEntry>,
+    pub l1: Vec<WeightEntry>,
+}
+
+/// Memory management control operation of dec_ref_pic_marking()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mmco {
+    /// 1: mark a short-term picture as unused for reference
+    ForgetShortTerm { difference_of_pic_nums_minus1: u32 },
+    /// 2: mark a long-term picture as unused for reference
+    ForgetLongTerm { long_term_pic_num: u32 },
+    /// 3: turn a short-term picture into a long-term one
+    ShortTermToLongTerm {
+        difference_of_pic_nums_minus1: u32,
+        long_term_frame_idx: u32,
+    },
+    /// 4: limit the number of long-term frame indices
+    MaxLongTermFrameIdx { max_long_term_frame_idx_plus1: u32 },
+    /// 5: mark all reference pictures as unused
+    ForgetAll,
+    /// 6: mark the current picture as long-term
+    CurrentToLongTerm { long_term_frame_idx: u32 },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecRefPicMarking {
+    pub no_output_of_prior_pics_flag: bool,
+    pub long_term_reference_flag: bool,
+    pub adaptive_ref_pic_marking_mode_flag: bool,
+    pub mmco: Vec<Mmco>,
+}
+
+/// Slice header, 7.3.3
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SliceHeader {
+    pub nal_ref_idc: u8,
+    pub idr_pic_flag: bool,
+    pub first_mb_in_slice: u32,
+    pub slice_type: SliceType,
+    pub pic_parameter_set_id: u8,
+    pub colour_plane_id: u8,
+    pub frame_num: u32,
+    pub field_pic_flag: bool,
+    pub bottom_field_flag: bool,
+    pub idr_pic_id: u32,
+    pub pic_order_cnt_lsb: u32,
+    pub delta_pic_order_cnt_bottom: i32,
+    pub delta_pic_order_cnt: [i32; 2],
+    pub redundant_pic_cnt: u32,
+    pub direct_spatial_mv_pred_flag: bool,
+    pub num_ref_idx_active_override_flag: bool,
+    pub num_ref_idx_l0_active_minus1: u32,
+    pub num_ref_idx_l1_active_minus1: u32,
+    pub ref_pic_list_modification_l0: Vec<RefPicListModification>,
+    pub ref_pic_list_modification_l1: Vec<RefPicListModification>,
+    pub pred_weight_table: Option<PredWeightTable>,
+    pub dec_ref_pic_marking: Option<DecRefPicMarking>,
+    pub cabac_init_idc: u32,
+    pub slice_qp_delta: i32,
+    pub sp_for_switch_flag: bool,
+    pub slice_qs_delta: i32,
+    pub disable_deblocking_filter_idc: u32,
+    pub slice_alpha_c0_offset_div2: i32,
+    pub slice_beta_offset_div2: i32,
+    pub slice_group_change_cycle: u32,
+    /// Size of the header in bits, counted in the RBSP after the NAL unit header byte.
+    /// slice_data() starts right after it.
+    pub header_bits: usize,
+}
+
+impl SliceHeader {
+    /// Parses the header of a coded slice NAL unit (type 1 or 5).
+    pub fn parse(nal: &[u8], parameter_sets: &ParameterSets) -> Result<Self> {
+        let header = NalUnitHeader::parse(nal)?;
+        let idr_pic_flag = match header.nal_unit_type {
+            NalUnitType::Slice => false,
+            NalUnitType::IdrSlice => true,
+            nal_unit_type => {
+                return Err(anyhow!(
+                    "Expected a coded slice NAL unit, got {:?}",
+                    nal_unit_type
+                ))
+            }
+        };
+
+        let rbsp = nal_to_rbsp(&nal[1..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let first_mb_in_slice = reader.read_ue()?;
+        let raw_slice_type = reader.read_ue()?;
+        if raw_slice_type > 9 {
+            return Err(anyhow!("Invalid slice_type {}", raw_slice_type));
+        }
+        let slice_type = SliceType::from_raw(raw_slice_type)?;
+        if idr_pic_flag && !slice_type.is_intra() {
+            return Err(anyhow!("IDR picture with {:?} slice", slice_type));
+        }
+
+        let pic_parameter_set_id =
+            u8::try_from(reader.read_ue()?).map_err(|_| anyhow!("Invalid pic_parameter_set_id"))?;
+        let (pps, sps) = parameter_sets.active(pic_parameter_set_id)?;
+
+        let colour_plane_id = if sps.separate_colour_plane_flag {
+            reader.read_bits(2)? as u8
+        } else {
+            0
+        };
+
+        let frame_num = reader.read_bits(sps.log2_max_frame_num_minus4 as u32 + 4)?;
+
+        let mut field_pic_flag = false;
+        let mut bottom_field_flag = false;
+        if !sps.frame_mbs_only_flag {
+            field_pic_flag = reader.read_flag()?;
+            if field_pic_flag {
+                bottom_field_flag = reader.read_flag()?;
+            }
+        }
+
+        let idr_pic_id = if idr_pic_flag { reader.read_ue()? } else { 0 };
+
+        let mut pic_order_cnt_lsb = 0;
+        let mut delta_pic_order_cnt_bottom = 0;
+        let mut delta_pic_order_cnt = [0; 2];
+        if sps.pic_order_cnt_type == 0 {
+            pic_order_cnt_lsb =
+                reader.read_bits(sps.log2_max_pic_order_cnt_lsb_minus4 as u32 + 4)?;
+            if pps.bottom_field_pic_order_in_frame_present_flag && !field_pic_flag {
+                delta_pic_order_cnt_bottom = reader.read_se()?;
+            }
+        }
+        if sps.pic_order_cnt_type == 1 && !sps.delta_pic_order_always_zero_flag {
+            delta_pic_order_cnt[0] = reader.read_se()?;
+            if pps.bottom_field_pic_order_in_frame_present_flag && !field_pic_flag {
+                delta_pic_order_cnt[1] = reader.read_se()?;
+            }
+        }
+
+        let redundant_pic_cnt = if pps.redundant_pic_cnt_present_flag {
+            reader.read_ue()?
+        } else {
+            0
+        };
+
+        let direct_spatial_mv_pred_flag = slice_type == SliceType::B && reader.read_flag()?;
+
+        let mut num_ref_idx_active_override_flag = false;
+        let mut num_ref_idx_l0_active_minus1 = pps.num_ref_idx_l0_default_active_minus1 as u32;
+        let mut num_ref_idx_l1_active_minus1 = pps.num_ref_idx_l1_default_active_minus1 as u32;
+        if matches!(slice_type, SliceType::P | SliceType::Sp | SliceType::B) {
+            num_ref_idx_active_override_flag = reader.read_flag()?;
+            if num_ref_idx_active_override_flag {
+                num_ref_idx_l0_active_minus1 = reader.read_ue()?;
+                if slice_type == SliceType::B {
+                    num_ref_idx_l1_active_minus1 = reader.read_ue()?;
+                }
+            }
+        }
+        let max_num_ref_idx = if field_pic_flag { 31 } else { 15 };
+        if num_ref_idx_l0_active_minus1 > max_num_ref_idx
+            || num_ref_idx_l1_active_minus1 > max_num_ref_idx
+        {
+            return Err(anyhow!(
+                "Invalid num_ref_idx_active_minus1 l0 {} l1 {}",
+                num_ref_idx_l0_active_minus1,
+                num_ref_idx_l1_active_minus1
+            ));
+        }
+
+        let mut ref_pic_list_modification_l0 = Vec::new();
+        let mut ref_pic_list_modification_l1 = Vec::new();
+        if !slice_type.is_intra() {
+            ref_pic_list_modification_l0 = parse_ref_pic_list_modification(&mut reader)?;
+        }
+        if slice_type == SliceType::B {
+            ref_pic_list_modification_l1 = parse_ref_pic_list_modification(&mut reader)?;
+        }
+
+        let pred_weight_table = if (pps.weighted_pred_flag
+            && matches!(slice_type, SliceType::P | SliceType::Sp))
+            || (pps.weighted_bipred_idc == 1 && slice_type == SliceType::B)
+        {
+            let chroma_present = sps.chroma_array_type() != 0;
+            let mut table = PredWeightTable {
+                luma_log2_weight_denom: reader.read_ue()?,
+                chroma_log2_weight_denom: if chroma_present { reader.read_ue()? } else { 0 },
+                ..Default::default()
+            };
+            table.l0 = parse_weights(&mut reader, num_ref_idx_l0_active_minus1, chroma_present)?;
+            if slice_type == SliceType::B {
+                table.l1 =
+                    parse_weights(&mut reader, num_ref_idx_l1_active_minus1, chroma_present)?;
+            }
+            Some(table)
+        } else {
+            None
+        };
+
+        let dec_ref_pic_marking = if header.nal_ref_idc != 0 {
+            let mut marking = DecRefPicMarking::default();
+            if idr_pic_flag {
+                marking.no_output_of_prior_pics_flag = reader.read_flag()?;
+                marking.long_term_reference_flag = reader.read_flag()?;
+            } else {
+                marking.adaptive_ref_pic_marking_mode_flag = reader.read_flag()?;
+                if marking.adaptive_ref_pic_marking_mode_flag {
+                    marking.mmco = parse_mmco(&mut reader)?;
+                }
+            }
+            Some(marking)
+        } else {
+            None
+        };
+
+        let cabac_init_idc = if pps.entropy_coding_mode_flag && !slice_type.is_intra() {
+            let cabac_init_idc = reader.read_ue()?;
+            if cabac_init_idc > 2 {
+                return Err(anyhow!("Invalid cabac_init_idc {}", cabac_init_idc));
+            }
+            cabac_init_idc
+        } else {
+            0
+        };
+
+        let slice_qp_delta = reader.read_se()?;
+
+        let mut sp_for_switch_flag = false;
+        let mut slice_qs_delta = 0;
+        if matches!(slice_type, SliceType::Sp | SliceType::Si) {
+            if slice_type == SliceType::Sp {
+                sp_for_switch_flag = reader.read_flag()?;
+            }
+            slice_qs_delta = reader.read_se()?;
+        }
+
+        let mut disable_deblocking_filter_idc = 0;
+        let mut slice_alpha_c0_offset_div2 = 0;
+        let mut slice_beta_offset_div2 = 0;
+        if pps.deblocking_filter_control_present_flag {
+            disable_deblocking_filter_idc = reader.read_ue()?;
+            if disable_deblocking_filter_idc > 2 {
+                return Err(anyhow!(
+                    "Invalid disable_deblocking_filter_idc {}",
+                    disable_deblocking_filter_idc
+                ));
+            }
+            if disable_deblocking_filter_idc != 1 {
+                slice_alpha_c0_offset_div2 = reader.read_se()?;
+                slice_beta_offset_div2 = reader.read_se()?;
+            }
+        }
+
+        let mut slice_group_change_cycle = 0;
+        if let Some(SliceGroupMap::Changing {
+            slice_group_change_rate_minus1,
+            ..
+        }) = pps.slice_group_map
+        {
+            let pic_size_in_map_units =
+                sps.pic_width_in_mbs() * (sps.pic_height_in_map_units_minus1 + 1);
+            let slice_group_change_rate = slice_group_change_rate_minus1 + 1;
+            // Ceil(Log2(PicSizeInMapUnits ÷ SliceGroupChangeRate + 1))
+            let max =
+                (pic_size_in_map_units + slice_group_change_rate - 1) / slice_group_change_rate + 1;
+            let bits = 32 - (max - 1).leading_zeros();
+            slice_group_change_cycle = reader.read_bits(bits)?;
+        }
+
+        Ok(Self {
+            nal_ref_idc: header.nal_ref_idc,
+            idr_pic_flag,
+            first_mb_in_slice,
+            slice_type,
+            pic_parameter_set_id,
+            colour_plane_id,
+            frame_num,
+            field_pic_flag,
+            bottom_field_flag,
+            idr_pic_id,
+            pic_order_cnt_lsb,
+            delta_pic_order_cnt_bottom,
+            delta_pic_order_cnt,
+            redundant_pic_cnt,
+            direct_spatial_mv_pred_flag,
+            num_ref_idx_active_override_flag,
+            num_ref_idx_l0_active_minus1,
+            num_ref_idx_l1_active_minus1,
+            ref_pic_list_modification_l0,
+            ref_pic_list_modification_l1,
+            pred_weight_table,
+            dec_ref_pic_marking,
+            cabac_init_idc,
+            slice_qp_delta,
+            sp_for_switch_flag,
+            slice_qs_delta,
+            disable_deblocking_filter_idc,
+            slice_alpha_c0_offset_div2,
+            slice_beta_offset_div2,
+            slice_group_change_cycle,
+            header_bits: reader.position(),
+        })
+    }
+
+    pub fn is_reference(&self) -> bool {
+        self.nal_ref_idc != 0
+    }
+
+    /// True when the slice carries memory_management_control_operation 5.
+    pub fn has_mmco5(&self) -> bool {
+        self.dec_ref_pic_marking
+            .as_ref()
+            .map_or(false, |marking| marking.mmco.contains(&Mmco::ForgetAll))
+    }
+
+    /// Checks the conditions of 7.4.1.2.4 that separate the first VCL NAL unit of a new
+    /// primary coded picture from the previous one.
+    pub fn is_new_picture(&self, previous: &SliceHeader, poc_type: u8) -> bool {
+        self.frame_num != previous.frame_num
+            || self.pic_parameter_set_id != previous.pic_parameter_set_id
+            || self.field_pic_flag != previous.field_pic_flag
+            || self.bottom_field_flag != previous.bottom_field_flag
+            || (self.nal_ref_idc != previous.nal_ref_idc
+                && (self.nal_ref_idc == 0 || previous.nal_ref_idc == 0))
+            || (poc_type == 0
+                && (self.pic_order_cnt_lsb != previous.pic_order_cnt_lsb
+                    || self.delta_pic_order_cnt_bottom != previous.delta_pic_order_cnt_bottom))
+            || (poc_type == 1 && self.delta_pic_order_cnt != previous.delta_pic_order_cnt)
+            || self.idr_pic_flag != previous.idr_pic_flag
+            || (self.idr_pic_flag && self.idr_pic_id != previous.idr_pic_id)
+    }
+}
+
+fn parse_ref_pic_list_modification(reader: &mut BitReader) -> Result<Vec<RefPicListModification>> {
+    let mut modifications = Vec::new();
+
+    if reader.read_flag()? {
+        loop {
+            let modification = match reader.read_ue()? {
+                0 => RefPicListModification::ShortTermSubtract(reader.read_ue()?),
+                1 => RefPicListModification::ShortTermAdd(reader.read_ue()?),
+                2 => RefPicListModification::LongTerm(reader.read_ue()?),
+                3 => break,
+                idc => return Err(anyhow!("Invalid modification_of_pic_nums_idc {}", idc)),
+            };
+            if modifications.len() > 32 {
+                return Err(anyhow!("Too many reference picture list modifications"));
+            }
+            modifications.push(modification);
+        }
+    }
+
+    Ok(modifications)
+}
+
+fn parse_weights(
+    reader: &mut BitReader,
+    num_ref_idx_active_minus1: u32,
+    chroma_present: bool,
+) -> Result<Vec<WeightEntry>> {
+    let mut weights = Vec::new();
+
+    for _ in 0..=num_ref_idx_active_minus1 {
+        let mut entry = WeightEntry::default();
+        if reader.read_flag()? {
+            entry.luma_weight = Some((reader.read_se()?, reader.read_se()?));
+        }
+        if chroma_present && reader.read_flag()? {
+            entry.chroma_weight = Some([
+                (reader.read_se()?, reader.read_se()?),
+                (reader.read_se()?, reader.read_se()?),
+            ]);
+        }
+        weights.push(entry);
+    }
+
+    Ok(weights)
+}
+
+fn parse_mmco(reader: &mut BitReader) -> Result<Vec<Mmco>> {
+    let mut operations = Vec::new();
+
+    loop {
+        let operation = match reader.read_ue()? {
+            0 => break,
+            1 => Mmco::ForgetShortTerm {
+                difference_of_pic_nums_minus1: reader.read_ue()?,
+            },
+            2 => Mmco::ForgetLongTerm {
+                long_term_pic_num: reader.read_ue()?,
+            },
+            3 => Mmco::ShortTermToLongTerm {
+                difference_of_pic_nums_minus1: reader.read_ue()?,
+                long_term_frame_idx: reader.read_ue()?,
+            },
+            4 => Mmco::MaxLongTermFrameIdx {
+                max_long_term_frame_idx_plus1: reader.read_ue()?,
+            },
+            5 => Mmco::ForgetAll,
+            6 => Mmco::CurrentToLongTerm {
+                long_term_frame_idx: reader.read_ue()?,
+            },
+            operation => {
+                return Err(anyhow!(
+                    "Invalid memory_management_control_operation {}",
+                    operation
+                ))
+            }
+        };
+        if operations.len() > 66 {
+            return Err(anyhow!("Too many memory management control operations"));
+        }
+        operations.push(operation);
+    }
+
+    Ok(operations)
+}
+
+/// A coded slice located inside an Annex-B formatted access unit.
+#[derive(Clone, Debug)]
+pub struct Slice {
+    /// Offset of the slice's start code from the beginning of the access unit
+    pub offset: usize,
+    /// Size in bytes, start code included
+    pub size: usize,
+    pub header: SliceHeader,
+}
+
+/// Finds and parses every coded slice of an Annex-B formatted access unit.
+/// Parameter sets found along the way are added to `parameter_sets`.
+pub fn parse_slices(access_unit: &[u8], parameter_sets: &mut ParameterSets) -> Result<Vec<Slice>> {
+    let mut slices = Vec::new();
+
+    for nal in NalUnits::new(access_unit) {
+        match NalUnitHeader::parse(nal.data)?.nal_unit_type {
+            NalUnitType::Slice | NalUnitType::IdrSlice => slices.push(Slice {
+                offset: nal.offset,
+                size: nal.start_code_len + nal.data.len(),
+                header: SliceHeader::parse(nal.data, parameter_sets)?,
+            }),
+            NalUnitType::Sps | NalUnitType::Pps => {
+                parameter_sets.add_nal(nal.data)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(slices)
+}
+
+/// Offsets of the slices relative to the first one, as expected by
+/// `VkVideoDecodeH264PictureInfoKHR::pSliceOffsets`.
+pub fn slice_offsets(slices: &[Slice]) -> Vec<u32> {
+    let base = slices.first().map_or(0, |slice| slice.offset);
+    slices
+        .iter()
+        .map(|slice| (slice.offset - base) as u32)
+        .collect()
+}
@@ -0,0 +1,786 @@
+use anyhow::{anyhow, Result};
+
+use super::macroblock::BlockCategory;
+use crate::bitreader::BitReader;
+use crate::h264::slice::SliceType;
+
+// ctxIdxOffset of the syntax elements in Table 9-34, frame coded macroblocks only
+const MB_TYPE_I: usize = 3;
+const MB_SKIP_FLAG_P: usize = 11;
+const MB_TYPE_P_PREFIX: usize = 14;
+const MB_TYPE_P_SUFFIX: usize = 17;
+const SUB_MB_TYPE_P: usize = 21;
+const MB_SKIP_FLAG_B: usize = 24;
+const MB_TYPE_B_PREFIX: usize = 27;
+const MB_TYPE_B_SUFFIX: usize = 32;
+const SUB_MB_TYPE_B: usize = 36;
+const MVD: [usize; 2] = [40, 47];
+const REF_IDX: usize = 54;
+const MB_QP_DELTA: usize = 60;
+const INTRA_CHROMA_PRED_MODE: usize = 64;
+const PREV_INTRA_PRED_MODE_FLAG: usize = 68;
+const REM_INTRA_PRED_MODE: usize = 69;
+const CODED_BLOCK_PATTERN_LUMA: usize = 73;
+const CODED_BLOCK_PATTERN_CHROMA: usize = 77;
+const CODED_BLOCK_FLAG: usize = 85;
+const SIGNIFICANT_COEFF_FLAG: usize = 105;
+const LAST_SIGNIFICANT_COEFF_FLAG: usize = 166;
+const COEFF_ABS_LEVEL_MINUS1: usize = 227;
+const END_OF_SLICE_FLAG: usize = 276;
+const TRANSFORM_SIZE_8X8_FLAG: usize = 399;
+const SIGNIFICANT_COEFF_FLAG_8X8: usize = 402;
+const LAST_SIGNIFICANT_COEFF_FLAG_8X8: usize = 417;
+const COEFF_ABS_LEVEL_MINUS1_8X8: usize = 426;
+const CONTEXT_COUNT: usize = 436;
+
+// ctxBlockCatOffset of Table 9-40 for block categories 0 to 4
+const CODED_BLOCK_FLAG_CAT_OFFSET: [usize; 5] = [0, 4, 8, 12, 16];
+const SIGNIFICANT_CAT_OFFSET: [usize; 5] = [0, 15, 29, 44, 47];
+const COEFF_ABS_LEVEL_CAT_OFFSET: [usize; 5] = [0, 10, 20, 30, 39];
+
+// Table 9-43, ctxIdxInc of significant_coeff_flag and last_significant_coeff_flag in
+// frame coded 8x8 blocks
+const SIGNIFICANT_8X8_INC: [u8; 63] = [
+    0, 1, 2, 3, 4, 5, 5, 4, 4, 3, 3, 4, 4, 4, 5, 5, 4, 4, 4, 4, 3, 3, 6, 7, 7, 7, 8, 9, 10, 9, 8,
+    7, 7, 6, 11, 12, 13, 11, 6, 7, 8, 9, 14, 10, 9, 8, 6, 11, 12, 13, 11, 6, 9, 14, 10, 9, 11, 12,
+    13, 11, 14, 10, 12,
+];
+const LAST_8X8_INC: [u8; 63] = [
+    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8,
+];
+
+// Table 9-44, rangeTabLPS indexed by pStateIdx and qCodIRangeIdx
+#[rustfmt::skip]
+const RANGE_TAB_LPS: [[u8; 4]; 64] = [
+    [128, 176, 208, 240], [128, 167, 197, 227], [128, 158, 187, 216], [123, 150, 178, 205],
+    [116, 142, 169, 195], [111, 135, 160, 185], [105, 128, 152, 175], [100, 122, 144, 166],
+    [95, 116, 137, 158], [90, 110, 130, 150], [85, 104, 123, 142], [81, 99, 117, 135],
+    [77, 94, 111, 128], [73, 89, 105, 122], [69, 85, 100, 116], [66, 80, 95, 110],
+    [62, 76, 90, 104], [59, 72, 86, 99], [56, 69, 81, 94], [53, 65, 77, 89],
+    [51, 62, 73, 85], [48, 59, 69, 80], [46, 56, 66, 76], [43, 53, 63, 72],
+    [41, 50, 59, 69], [39, 48, 56, 65], [37, 45, 54, 62], [35, 43, 51, 59],
+    [33, 41, 48, 56], [32, 39, 46, 53], [30, 37, 43, 50], [29, 35, 41, 48],
+    [27, 33, 39, 45], [26, 31, 37, 43], [24, 30, 35, 41], [23, 28, 33, 39],
+    [22, 27, 32, 37], [21, 26, 30, 35], [20, 24, 29, 33], [19, 23, 27, 31],
+    [18, 22, 26, 30], [17, 21, 25, 28], [16, 20, 23, 27], [15, 19, 22, 25],
+    [14, 18, 21, 24], [14, 17, 20, 23], [13, 16, 19, 22], [12, 15, 18, 21],
+    [12, 14, 17, 20], [11, 14, 16, 19], [11, 13, 15, 18], [10, 12, 15, 17],
+    [10, 12, 14, 16], [9, 11, 13, 15], [9, 11, 12, 14], [8, 10, 12, 14],
+    [8, 9, 11, 13], [7, 9, 11, 12], [7, 9, 10, 12], [7, 8, 10, 11],
+    [6, 8, 9, 11], [6, 7, 9, 10], [6, 7, 8, 9], [2, 2, 2, 2],
+];
+
+// Table 9-45, transIdxLPS
+const TRANS_IDX_LPS: [u8; 64] = [
+    0, 0, 1, 2, 2, 4, 4, 5, 6, 7, 8, 9, 9, 11, 11, 12, 13, 13, 15, 15, 16, 16, 18, 18, 19, 19, 21,
+    21, 22, 22, 23, 24, 24, 25, 26, 26, 27, 27, 28, 29, 29, 30, 30, 30, 31, 32, 32, 33, 33, 33, 34,
+    34, 35, 35, 35, 36, 36, 36, 37, 37, 37, 38, 38, 63,
+];
+
+// Tables 9-12 to 9-33, (m, n) for ctxIdx 0 to 276 of I slices and of P and B slices per
+// cabac_init_idc. ctxIdx 0 to 10 and 60 to 69 are shared by all slice types.
+#[rustfmt::skip]
+const CONTEXT_INIT_I: [(i8, i8); 277] = [
+    // 0 - 10
+    (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104),
+    (-6, 53), (-1, 54), (7, 51),
+    // 11 - 59, unused in I slices
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0),
+    // 60 - 69
+    (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72),
+    (13, 41), (3, 62),
+    // 70 - 104
+    (0, 11), (1, 55), (0, 69), (-17, 127), (-13, 102), (0, 82), (-7, 74), (-21, 107),
+    (-27, 127), (-31, 127), (-24, 127), (-18, 95), (-27, 127), (-21, 114), (-30, 127), (-17, 123),
+    (-12, 115), (-16, 122), (-11, 115), (-12, 63), (-2, 68), (-15, 84), (-13, 104), (-3, 70),
+    (-8, 93), (-10, 90), (-30, 127), (-1, 74), (-6, 97), (-7, 91), (-20, 127), (-4, 56),
+    (-5, 82), (-7, 76), (-22, 125),
+    // 105 - 165
+    (-7, 93), (-11, 87), (-3, 77), (-5, 71), (-4, 63), (-4, 68), (-12, 84), (-7, 62),
+    (-7, 65), (8, 61), (5, 56), (-2, 66), (1, 64), (0, 61), (-2, 78), (1, 50),
+    (7, 52), (10, 35), (0, 44), (11, 38), (1, 45), (0, 46), (5, 44), (31, 17),
+    (1, 51), (7, 50), (28, 19), (16, 33), (14, 62), (-13, 108), (-15, 100), (-13, 101),
+    (-13, 91), (-12, 94), (-10, 88), (-16, 84), (-10, 86), (-7, 83), (-13, 87), (-19, 94),
+    (1, 70), (0, 72), (-5, 74), (18, 59), (-8, 102), (-15, 100), (0, 95), (-4, 75),
+    (2, 72), (-11, 75), (-3, 71), (15, 46), (-13, 69), (0, 62), (0, 65), (21, 37),
+    (-15, 72), (9, 57), (16, 54), (0, 62), (12, 72),
+    // 166 - 226
+    (24, 0), (15, 9), (8, 25), (13, 18), (15, 9), (13, 19), (10, 37), (12, 18),
+    (6, 29), (20, 33), (15, 30), (4, 45), (1, 58), (0, 62), (7, 61), (12, 38),
+    (11, 45), (15, 39), (11, 42), (13, 44), (16, 45), (12, 41), (10, 49), (30, 34),
+    (18, 42), (10, 55), (17, 51), (17, 46), (0, 89), (26, -19), (22, -17), (26, -17),
+    (30, -25), (28, -20), (33, -23), (37, -27), (33, -23), (40, -28), (38, -17), (33, -11),
+    (40, -15), (41, -6), (38, 1), (41, 17), (30, -6), (27, 3), (26, 22), (37, -16),
+    (35, -4), (38, -8), (38, -3), (37, 3), (38, 5), (42, 0), (35, 16), (39, 22),
+    (14, 48), (27, 37), (21, 60), (12, 68), (2, 97),
+    // 227 - 275
+    (-3, 71), (-6, 42), (-5, 50), (-3, 54), (-2, 62), (0, 58), (1, 63), (-2, 72),
+    (-1, 74), (-9, 91), (-5, 67), (-5, 27), (-3, 39), (-2, 44), (0, 46), (-16, 64),
+    (-8, 68), (-10, 78), (-6, 77), (-10, 86), (-12, 92), (-15, 55), (-10, 60), (-6, 62),
+    (-4, 65), (-12, 73), (-8, 76), (-7, 80), (-9, 88), (-17, 110), (-11, 97), (-20, 84),
+    (-11, 79), (-6, 73), (-4, 74), (-13, 86), (-13, 96), (-11, 97), (-19, 117), (-8, 78),
+    (-5, 33), (-4, 48), (-2, 53), (-3, 62), (-13, 71), (-10, 79), (-12, 86), (-13, 90),
+    (-14, 97),
+    // 276, end_of_slice_flag uses the non-adapting terminate bin
+    (0, 0),
+];
+
+#[rustfmt::skip]
+const CONTEXT_INIT_PB: [[(i8, i8); 277]; 3] = [
+    [
+        // 0 - 10
+        (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104),
+        (-6, 53), (-1, 54), (7, 51),
+        // 11 - 23
+        (23, 33), (23, 2), (21, 0), (1, 9), (0, 49), (-37, 118), (5, 57), (-13, 78),
+        (-11, 65), (1, 62), (12, 49), (-4, 73), (17, 50),
+        // 24 - 39
+        (18, 64), (9, 43), (29, 0), (26, 67), (16, 90), (9, 104), (-46, 127), (-20, 104),
+        (1, 67), (-13, 78), (-11, 65), (1, 62), (-6, 86), (-17, 95), (-6, 61), (9, 45),
+        // 40 - 53
+        (-3, 69), (-6, 81), (-11, 96), (6, 55), (7, 67), (-5, 86), (2, 88), (0, 58),
+        (-3, 76), (-10, 94), (5, 54), (4, 69), (-3, 81), (0, 88),
+        // 54 - 59
+        (-7, 67), (-5, 74), (-4, 74), (-5, 80), (-7, 72), (1, 58),
+        // 60 - 69
+        (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72),
+        (13, 41), (3, 62),
+        // 70 - 104
+        (0, 45), (-4, 78), (-3, 96), (-27, 126), (-28, 98), (-25, 101), (-23, 67), (-28, 82),
+        (-20, 94), (-16, 83), (-22, 110), (-21, 91), (-18, 102), (-13, 93), (-29, 127), (-7, 92),
+        (-5, 89), (-7, 96), (-13, 108), (-3, 46), (-1, 65), (-1, 57), (-9, 93), (-3, 74),
+        (-9, 92), (-8, 87), (-23, 126), (5, 54), (6, 60), (6, 59), (6, 69), (-1, 48),
+        (0, 68), (-4, 69), (-8, 88),
+        // 105 - 165
+        (-2, 85), (-6, 78), (-1, 75), (-7, 77), (2, 54), (5, 50), (-3, 68), (1, 50),
+        (6, 42), (-4, 81), (1, 63), (-4, 70), (0, 67), (2, 57), (-2, 76), (11, 35),
+        (4, 64), (1, 61), (11, 35), (18, 25), (12, 24), (13, 29), (13, 36), (-10, 93),
+        (-7, 73), (-2, 73), (13, 46), (9, 49), (-7, 100), (9, 53), (2, 53), (5, 53),
+        (-2, 61), (0, 56), (0, 56), (-13, 63), (-5, 60), (-1, 62), (4, 57), (-6, 69),
+        (4, 57), (14, 39), (4, 51), (13, 68), (3, 64), (1, 61), (9, 63), (7, 50),
+        (16, 39), (5, 44), (4, 52), (11, 48), (-5, 60), (-1, 59), (0, 59), (22, 33),
+        (5, 44), (14, 43), (-1, 78), (0, 60), (9, 69),
+        // 166 - 226
+        (11, 28), (2, 40), (3, 44), (0, 49), (0, 46), (2, 44), (2, 51), (0, 47),
+        (4, 39), (2, 62), (6, 46), (0, 54), (3, 54), (2, 58), (4, 63), (6, 51),
+        (6, 57), (7, 53), (6, 52), (6, 55), (11, 45), (14, 36), (8, 53), (-1, 82),
+        (7, 55), (-3, 78), (15, 46), (22, 31), (-1, 84), (25, 7), (30, -7), (28, 3),
+        (28, 4), (32, 0), (34, -1), (30, 6), (30, 6), (32, 9), (31, 19), (26, 27),
+        (26, 30), (37, 20), (28, 34), (17, 70), (1, 67), (5, 59), (9, 67), (16, 30),
+        (18, 32), (18, 35), (22, 29), (24, 31), (23, 38), (18, 43), (20, 41), (11, 63),
+        (9, 59), (9, 64), (-1, 94), (-2, 89), (-9, 108),
+        // 227 - 275
+        (-6, 76), (-2, 44), (0, 45), (0, 52), (-3, 64), (-2, 59), (-4, 70), (-4, 75),
+        (-8, 82), (-17, 102), (-9, 77), (3, 24), (0, 42), (0, 48), (0, 55), (-6, 59),
+        (-7, 71), (-12, 83), (-11, 87), (-30, 119), (1, 58), (-3, 29), (-1, 36), (1, 38),
+        (2, 43), (-6, 55), (0, 58), (0, 64), (-3, 74), (-10, 90), (0, 70), (-4, 29),
+        (5, 31), (7, 42), (1, 59), (-2, 58), (-3, 72), (-3, 81), (-11, 97), (0, 58),
+        (8, 5), (10, 14), (14, 18), (13, 27), (2, 40), (0, 58), (-3, 70), (-6, 79),
+        (-8, 85),
+        // 276
+        (0, 0),
+    ],
+    [
+        // 0 - 10
+        (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104),
+        (-6, 53), (-1, 54), (7, 51),
+        // 11 - 23
+        (22, 25), (34, 0), (16, 0), (-2, 9), (4, 41), (-29, 118), (2, 65), (-6, 71),
+        (-13, 79), (5, 52), (9, 50), (-3, 70), (10, 54),
+        // 24 - 39
+        (26, 34), (19, 22), (40, 0), (57, 2), (41, 36), (26, 69), (-45, 127), (-15, 101),
+        (-4, 76), (-6, 71), (-13, 79), (5, 52), (6, 69), (-13, 90), (0, 52), (8, 43),
+        // 40 - 53
+        (-2, 69), (-5, 82), (-10, 96), (2, 59), (2, 75), (-3, 87), (-3, 100), (1, 56),
+        (-3, 74), (-6, 85), (0, 59), (-3, 81), (-7, 86), (-5, 95),
+        // 54 - 59
+        (-1, 66), (-1, 77), (1, 70), (-2, 86), (-5, 72), (0, 61),
+        // 60 - 69
+        (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72),
+        (13, 41), (3, 62),
+        // 70 - 104
+        (13, 15), (7, 51), (2, 80), (-39, 127), (-18, 91), (-17, 96), (-26, 81), (-35, 98),
+        (-24, 102), (-23, 97), (-27, 119), (-24, 99), (-21, 110), (-18, 102), (-36, 127), (0, 80),
+        (-5, 89), (-7, 94), (-4, 92), (0, 39), (0, 65), (-15, 84), (-35, 127), (-2, 73),
+        (-12, 104), (-9, 91), (-31, 127), (3, 55), (7, 56), (7, 55), (8, 61), (-3, 53),
+        (0, 68), (-7, 74), (-9, 88),
+        // 105 - 165
+        (-13, 103), (-13, 91), (-9, 89), (-14, 92), (-8, 76), (-12, 87), (-23, 110), (-24, 105),
+        (-10, 78), (-20, 112), (-17, 99), (-78, 127), (-70, 127), (-50, 127), (-46, 127), (-4, 66),
+        (-5, 78), (-4, 71), (-8, 72), (2, 59), (-1, 55), (-7, 70), (-6, 75), (-8, 89),
+        (-34, 119), (-3, 75), (32, 20), (30, 22), (-44, 127), (0, 54), (-5, 61), (0, 58),
+        (-1, 60), (-3, 61), (-8, 67), (-25, 84), (-14, 74), (-5, 65), (5, 52), (2, 57),
+        (0, 61), (-9, 69), (-11, 70), (18, 55), (-4, 71), (0, 58), (7, 61), (9, 41),
+        (18, 25), (9, 32), (5, 43), (9, 47), (0, 44), (0, 51), (2, 46), (19, 38),
+        (-4, 66), (15, 38), (12, 42), (9, 34), (0, 89),
+        // 166 - 226
+        (4, 45), (10, 28), (10, 31), (33, -11), (52, -43), (18, 15), (28, 0), (35, -22),
+        (38, -25), (34, 0), (39, -18), (32, -12), (102, -94), (0, 0), (56, -15), (33, -4),
+        (29, 10), (37, -5), (51, -29), (39, -9), (52, -34), (69, -58), (67, -63), (44, -5),
+        (32, 7), (55, -29), (32, 1), (0, 0), (27, 36), (33, -25), (34, -30), (36, -28),
+        (38, -28), (38, -27), (34, -18), (35, -16), (34, -14), (32, -8), (37, -6), (35, 0),
+        (30, 10), (28, 18), (26, 25), (29, 41), (0, 75), (2, 72), (8, 77), (14, 35),
+        (18, 31), (17, 35), (21, 30), (17, 45), (20, 42), (18, 45), (27, 26), (16, 54),
+        (7, 66), (16, 56), (11, 73), (10, 67), (-10, 116),
+        // 227 - 275
+        (-23, 112), (-15, 71), (-7, 61), (0, 53), (-5, 66), (-11, 77), (-9, 80), (-9, 84),
+        (-10, 87), (-34, 127), (-21, 101), (-3, 39), (-5, 53), (-7, 61), (-11, 75), (-15, 77),
+        (-17, 91), (-25, 107), (-25, 111), (-28, 122), (-11, 76), (-10, 44), (-10, 52), (-10, 57),
+        (-9, 58), (-16, 72), (-7, 69), (-4, 69), (-5, 74), (-9, 86), (2, 66), (-9, 34),
+        (1, 32), (11, 31), (5, 52), (-2, 55), (-2, 67), (0, 73), (-8, 89), (3, 52),
+        (7, 4), (10, 8), (17, 8), (16, 19), (3, 37), (-1, 61), (-5, 73), (-1, 70),
+        (4, 78),
+        // 276
+        (0, 0),
+    ],
+    [
+        // 0 - 10
+        (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104),
+        (-6, 53), (-1, 54), (7, 51),
+        // 11 - 23
+        (29, 16), (25, 0), (14, 0), (-10, 51), (-3, 62), (-27, 99), (26, 16), (-4, 85),
+        (-24, 102), (5, 57), (6, 57), (-17, 73), (14, 57),
+        // 24 - 39
+        (20, 40), (20, 10), (29, 0), (54, 0), (37, 42), (12, 97), (-32, 127), (-22, 117),
+        (-2, 74), (-4, 85), (-24, 102), (5, 57), (-6, 93), (-14, 88), (-6, 44), (4, 55),
+        // 40 - 53
+        (-11, 89), (-15, 103), (-21, 116), (19, 57), (20, 58), (4, 84), (6, 96), (1, 63),
+        (-5, 85), (-13, 106), (5, 63), (6, 75), (-3, 90), (-1, 101),
+        // 54 - 59
+        (3, 55), (-4, 79), (-2, 75), (-12, 97), (-7, 50), (1, 60),
+        // 60 - 69
+        (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72),
+        (13, 41), (3, 62),
+        // 70 - 104
+        (7, 34), (-9, 88), (-20, 127), (-36, 127), (-17, 91), (-14, 95), (-25, 84), (-25, 86),
+        (-12, 89), (-17, 91), (-31, 127), (-14, 76), (-18, 103), (-13, 90), (-37, 127), (11, 80),
+        (5, 76), (2, 84), (5, 78), (-6, 55), (4, 61), (-14, 83), (-37, 127), (-5, 79),
+        (-11, 104), (-11, 91), (-30, 127), (0, 65), (-2, 79), (0, 72), (-4, 92), (-6, 56),
+        (3, 68), (-8, 71), (-13, 98),
+        // 105 - 165
+        (-4, 86), (-12, 88), (-5, 82), (-3, 72), (-4, 67), (-8, 72), (-16, 89), (-9, 69),
+        (-1, 59), (5, 66), (4, 57), (-4, 71), (-2, 71), (2, 58), (-1, 74), (-4, 44),
+        (-1, 69), (0, 62), (-7, 51), (-4, 47), (-6, 42), (-3, 41), (-6, 53), (8, 76),
+        (-9, 78), (-11, 83), (9, 52), (0, 67), (-5, 90), (1, 67), (-15, 72), (-5, 75),
+        (-8, 80), (-21, 83), (-21, 64), (-13, 31), (-25, 64), (-29, 94), (9, 75), (17, 63),
+        (-8, 74), (-5, 35), (-2, 27), (13, 91), (3, 65), (-7, 69), (8, 77), (-10, 66),
+        (3, 62), (-3, 68), (-20, 81), (0, 30), (1, 7), (-3, 23), (-21, 74), (16, 66),
+        (-23, 124), (17, 37), (44, -18), (50, -34), (-22, 127),
+        // 166 - 226
+        (4, 39), (0, 42), (7, 34), (11, 29), (8, 31), (6, 37), (7, 42), (3, 40),
+        (8, 33), (13, 43), (13, 36), (4, 47), (3, 55), (2, 58), (6, 60), (8, 44),
+        (11, 44), (14, 42), (7, 48), (4, 56), (4, 52), (13, 37), (9, 49), (19, 58),
+        (10, 48), (12, 45), (0, 69), (20, 33), (8, 63), (35, -18), (33, -25), (28, -3),
+        (24, 10), (27, 0), (34, -14), (52, -44), (39, -24), (19, 17), (31, 25), (36, 29),
+        (24, 33), (34, 15), (30, 20), (22, 73), (20, 34), (19, 31), (27, 44), (19, 16),
+        (15, 36), (15, 36), (21, 28), (25, 21), (30, 20), (31, 12), (27, 16), (24, 42),
+        (0, 93), (14, 56), (15, 57), (26, 38), (-24, 127),
+        // 227 - 275
+        (-24, 115), (-22, 82), (-9, 62), (0, 53), (0, 59), (-14, 85), (-13, 89), (-13, 94),
+        (-11, 92), (-29, 127), (-21, 100), (-14, 57), (-12, 67), (-11, 71), (-10, 77), (-21, 85),
+        (-16, 88), (-23, 104), (-15, 98), (-37, 127), (-10, 82), (-8, 48), (-8, 61), (-8, 66),
+        (-7, 70), (-14, 75), (-10, 79), (-9, 83), (-12, 92), (-18, 108), (-4, 79), (-22, 69),
+        (-16, 75), (-2, 58), (1, 58), (-13, 78), (-9, 83), (-4, 81), (-13, 99), (-13, 81),
+        (-6, 38), (-13, 62), (-6, 58), (-2, 59), (-16, 73), (-10, 76), (-13, 86), (-9, 83),
+        (-10, 87),
+        // 276
+        (0, 0),
+    ],
+];
+
+// (m, n) for ctxIdx 399 to 435, the 8x8 transform contexts of frame coded macroblocks
+#[rustfmt::skip]
+const CONTEXT_INIT_8X8_I: [(i8, i8); 37] = [
+    (31, 21), (31, 31), (25, 50), (-17, 120), (-20, 112), (-18, 114), (-11, 85), (-15, 92),
+    (-14, 89), (-26, 71), (-15, 81), (-14, 80), (0, 68), (-14, 70), (-24, 56), (-23, 68),
+    (-24, 50), (-11, 74), (23, -13), (26, -13), (40, -15), (49, -14), (44, 3), (45, 6),
+    (44, 34), (33, 54), (19, 82), (-3, 75), (-1, 23), (1, 34), (1, 43), (0, 54),
+    (-2, 55), (0, 61), (1, 64), (0, 68), (-9, 92),
+];
+
+#[rustfmt::skip]
+const CONTEXT_INIT_8X8_PB: [[(i8, i8); 37]; 3] = [
+    [
+        (12, 40), (11, 51), (14, 59), (-4, 79), (-7, 71), (-5, 69), (-9, 70), (-8, 66),
+        (-10, 68), (-19, 73), (-12, 69), (-16, 70), (-15, 67), (-20, 62), (-19, 70), (-16, 66),
+        (-22, 65), (-20, 63), (9, -2), (26, -9), (33, -9), (39, -7), (41, -2), (45, 3),
+        (49, 9), (45, 27), (36, 59), (-6, 66), (-7, 35), (-7, 42), (-8, 45), (-5, 48),
+        (-12, 56), (-6, 60), (-5, 62), (-8, 66), (-8, 76),
+    ],
+    [
+        (25, 32), (21, 49), (21, 54), (-5, 85), (-6, 81), (-10, 77), (-7, 81), (-17, 80),
+        (-18, 73), (-4, 74), (-10, 83), (-9, 71), (-9, 67), (-1, 61), (-8, 66), (-14, 66),
+        (0, 59), (2, 59), (21, -13), (33, -14), (39, -7), (46, -2), (51, 2), (60, 6),
+        (61, 17), (55, 34), (42, 62), (-6, 66), (-7, 35), (-7, 42), (-8, 45), (-5, 48),
+        (-12, 56), (-6, 60), (-5, 62), (-8, 66), (-8, 76),
+    ],
+    [
+        (21, 33), (19, 50), (17, 61), (-3, 78), (-8, 74), (-9, 72), (-10, 72), (-18, 75),
+        (-12, 71), (-11, 63), (-5, 70), (-17, 75), (-14, 72), (-16, 67), (-8, 53), (-14, 59),
+        (-9, 52), (-11, 68), (9, -2), (30, -10), (31, -4), (33, -1), (33, 7), (31, 12),
+        (37, 23), (31, 38), (20, 64), (-9, 71), (-7, 37), (-8, 44), (-11, 49), (-10, 56),
+        (-12, 59), (-8, 63), (-9, 67), (-6, 68), (-10, 79),
+    ],
+];
+
+/// CABAC parsing of slice data (9.3), for frame coded 4:2:0 pictures.
+///
+/// Context index increments that depend on neighbouring macroblocks are derived by the
+/// caller and passed in.
+pub struct Cabac<'a> {
+    reader: BitReader<'a>,
+    range: u32,
+    offset: u32,
+    /// pStateIdx << 1 | valMPS per ctxIdx
+    states: [u8; CONTEXT_COUNT],
+}
+
+impl<'a> Cabac<'a> {
+    /// Starts decoding at the byte aligned start of slice_data(). `reader` must be past the
+    /// slice header and the cabac_alignment_one_bits.
+    pub fn new(
+        reader: BitReader<'a>,
+        slice_type: SliceType,
+        cabac_init_idc: u32,
+        slice_qp: i32,
+    ) -> Result<Self> {
+        let (init, init_8x8) = if slice_type.is_intra() {
+            (&CONTEXT_INIT_I, &CONTEXT_INIT_8X8_I)
+        } else {
+            let idc = cabac_init_idc as usize;
+            if idc > 2 {
+                return Err(anyhow!("Invalid cabac_init_idc {}", cabac_init_idc));
+            }
+            (&CONTEXT_INIT_PB[idc], &CONTEXT_INIT_8X8_PB[idc])
+        };
+
+        // 9.3.1.1
+        let qp = slice_qp.clamp(0, 51);
+        let mut states = [0; CONTEXT_COUNT];
+        let (states_main, states_8x8) = states.split_at_mut(TRANSFORM_SIZE_8X8_FLAG);
+        let contexts = init
+            .iter()
+            .zip(&mut states_main[..=END_OF_SLICE_FLAG])
+            .chain(init_8x8.iter().zip(states_8x8));
+        for (&(m, n), state) in contexts {
+            let pre = (((m as i32 * qp) >> 4) + n as i32).clamp(1, 126);
+            *state = if pre <= 63 {
+                ((63 - pre) << 1) as u8
+            } else {
+                (((pre - 64) << 1) | 1) as u8
+            };
+        }
+
+        let mut cabac = Self {
+            reader,
+            range: 0,
+            offset: 0,
+            states,
+        };
+        cabac.init_engine()?;
+        Ok(cabac)
+    }
+
+    /// 9.3.1.2
+    fn init_engine(&mut self) -> Result<()> {
+        self.range = 510;
+        self.offset = self.reader.read_bits(9)?;
+        if self.offset >= 510 {
+            return Err(anyhow!("Invalid CABAC codIOffset {}", self.offset));
+        }
+        Ok(())
+    }
+
+    /// DecodeDecision (9.3.3.2.1)
+    fn decision(&mut self, ctx_idx: usize) -> Result<bool> {
+        let state = self.states[ctx_idx];
+        let p_state_idx = (state >> 1) as usize;
+        let mps = state & 1;
+
+        let lps_range = RANGE_TAB_LPS[p_state_idx][((self.range >> 6) & 3) as usize] as u32;
+        self.range -= lps_range;
+        let bin = if self.offset >= self.range {
+            self.offset -= self.range;
+            self.range = lps_range;
+            let mps = if p_state_idx == 0 { 1 - mps } else { mps };
+            self.states[ctx_idx] = (TRANS_IDX_LPS[p_state_idx] << 1) | mps;
+            1 - (state & 1)
+        } else {
+            self.states[ctx_idx] = ((p_state_idx as u8 + 1).min(62) << 1) | mps;
+            mps
+        };
+
+        while self.range < 256 {
+            self.range <<= 1;
+            self.offset = (self.offset << 1) | self.reader.read_bit()?;
+        }
+        Ok(bin == 1)
+    }
+
+    /// DecodeBypass (9.3.3.2.3)
+    fn bypass(&mut self) -> Result<bool> {
+        self.offset = (self.offset << 1) | self.reader.read_bit()?;
+        if self.offset >= self.range {
+            self.offset -= self.range;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// DecodeTerminate (9.3.3.2.2). After a 1 the last bit read is the rbsp_stop_one_bit,
+    /// or the bit before the pcm_alignment_zero_bits.
+    fn terminate(&mut self) -> Result<bool> {
+        self.range -= 2;
+        if self.offset >= self.range {
+            return Ok(true);
+        }
+        while self.range < 256 {
+            self.range <<= 1;
+            self.offset = (self.offset << 1) | self.reader.read_bit()?;
+        }
+        Ok(false)
+    }
+
+    /// Suffix of the UEGk binarization (9.3.2.3), read in bypass mode.
+    fn exp_golomb_bypass(&mut self, mut k: u32) -> Result<u32> {
+        let mut value = 0u32;
+        while self.bypass()? {
+            value += 1 << k;
+            k += 1;
+            if k >= 31 {
+                return Err(anyhow!("CABAC Exp-Golomb suffix exceeds 32 bits"));
+            }
+        }
+        while k > 0 {
+            k -= 1;
+            value += (self.bypass()? as u32) << k;
+        }
+        Ok(value)
+    }
+
+    pub fn end_of_slice_flag(&mut self) -> Result<bool> {
+        self.terminate()
+    }
+
+    /// Reads the pcm_sample_luma and pcm_sample_chroma bytes that follow an I_PCM mb_type
+    /// and restarts the decoding engine after them (9.3.1.2).
+    pub fn pcm_samples(&mut self, samples: &mut [u8]) -> Result<()> {
+        self.reader.align();
+        for sample in samples {
+            *sample = self.reader.read_bits(8)? as u8;
+        }
+        self.init_engine()
+    }
+
+    pub fn mb_skip_flag(&mut self, slice_type: SliceType, ctx_inc: usize) -> Result<bool> {
+        let offset = if slice_type == SliceType::B {
+            MB_SKIP_FLAG_B
+        } else {
+            MB_SKIP_FLAG_P
+        };
+        self.decision(offset + ctx_inc)
+    }
+
+    /// mb_type of an I macroblock (9.3.2.5), `ctx_inc` only applies to the first bin of
+    /// I slices.
+    fn mb_type_i(&mut self, offset: usize, in_i_slice: bool, ctx_inc: usize) -> Result<u32> {
+        let first = if in_i_slice { offset + ctx_inc } else { offset };
+        if !self.decision(first)? {
+            return Ok(0);
+        }
+        if self.terminate()? {
+            return Ok(25);
+        }
+
+        // The prefix state offsets of Table 9-39 differ between I slices and suffixes
+        let (luma, chroma_first, chroma_second, pred_first, pred_second) = if in_i_slice {
+            (offset + 3, offset + 4, offset + 5, offset + 6, offset + 7)
+        } else {
+            (offset + 1, offset + 2, offset + 2, offset + 3, offset + 3)
+        };
+        let mut mb_type = 1 + 12 * self.decision(luma)? as u32;
+        if self.decision(chroma_first)? {
+            mb_type += 4 + 4 * self.decision(chroma_second)? as u32;
+        }
+        mb_type += 2 * self.decision(pred_first)? as u32;
+        mb_type += self.decision(pred_second)? as u32;
+        Ok(mb_type)
+    }
+
+    /// mb_type with the numbering of the slice type, so intra macroblocks in P and B slices
+    /// are offset by 5 and 23 as in Tables 7-13 and 7-14.
+    pub fn mb_type(&mut self, slice_type: SliceType, ctx_inc: usize) -> Result<u32> {
+        match slice_type {
+            SliceType::I => self.mb_type_i(MB_TYPE_I, true, ctx_inc),
+            SliceType::P => {
+                if !self.decision(MB_TYPE_P_PREFIX)? {
+                    // P_L0_16x16, P_L0_L0_16x8, P_L0_L0_8x16 or P_8x8
+                    return Ok(if !self.decision(MB_TYPE_P_PREFIX + 1)? {
+                        3 * self.decision(MB_TYPE_P_PREFIX + 2)? as u32
+                    } else {
+                        2 - self.decision(MB_TYPE_P_PREFIX + 3)? as u32
+                    });
+                }
+                Ok(5 + self.mb_type_i(MB_TYPE_P_SUFFIX, false, 0)?)
+            }
+            SliceType::B => {
+                if !self.decision(MB_TYPE_B_PREFIX + ctx_inc)? {
+                    return Ok(0);
+                }
+                if !self.decision(MB_TYPE_B_PREFIX + 3)? {
+                    return Ok(1 + self.decision(MB_TYPE_B_PREFIX + 5)? as u32);
+                }
+                let mut bits = self.decision(MB_TYPE_B_PREFIX + 4)? as u32;
+                for _ in 0..3 {
+                    bits = (bits << 1) | self.decision(MB_TYPE_B_PREFIX + 5)? as u32;
+                }
+                match bits {
+                    0..=7 => Ok(bits + 3),
+                    13 => Ok(23 + self.mb_type_i(MB_TYPE_B_SUFFIX, false, 0)?),
+                    14 => Ok(11),
+                    15 => Ok(22),
+                    _ => {
+                        bits = (bits << 1) | self.decision(MB_TYPE_B_PREFIX + 5)? as u32;
+                        Ok(bits - 4)
+                    }
+                }
+            }
+            _ => Err(anyhow!("{:?} slices are not supported", slice_type)),
+        }
+    }
+
+    pub fn sub_mb_type(&mut self, slice_type: SliceType) -> Result<u32> {
+        if slice_type == SliceType::P {
+            // Table 9-37, P_L0_8x8 "1", P_L0_8x4 "00", P_L0_4x8 "011", P_L0_4x4 "010"
+            if self.decision(SUB_MB_TYPE_P)? {
+                return Ok(0);
+            }
+            if !self.decision(SUB_MB_TYPE_P + 1)? {
+                return Ok(1);
+            }
+            return Ok(if self.decision(SUB_MB_TYPE_P + 2)? {
+                2
+            } else {
+                3
+            });
+        }
+
+        if !self.decision(SUB_MB_TYPE_B)? {
+            return Ok(0);
+        }
+        if !self.decision(SUB_MB_TYPE_B + 1)? {
+            return Ok(1 + self.decision(SUB_MB_TYPE_B + 3)? as u32);
+        }
+        let mut sub_mb_type = 3;
+        if self.decision(SUB_MB_TYPE_B + 2)? {
+            if self.decision(SUB_MB_TYPE_B + 3)? {
+                return Ok(11 + self.decision(SUB_MB_TYPE_B + 3)? as u32);
+            }
+            sub_mb_type += 4;
+        }
+        sub_mb_type += 2 * self.decision(SUB_MB_TYPE_B + 3)? as u32;
+        sub_mb_type += self.decision(SUB_MB_TYPE_B + 3)? as u32;
+        Ok(sub_mb_type)
+    }
+
+    pub fn transform_size_8x8_flag(&mut self, ctx_inc: usize) -> Result<bool> {
+        self.decision(TRANSFORM_SIZE_8X8_FLAG + ctx_inc)
+    }
+
+    pub fn prev_intra_pred_mode_flag(&mut self) -> Result<bool> {
+        self.decision(PREV_INTRA_PRED_MODE_FLAG)
+    }
+
+    pub fn rem_intra_pred_mode(&mut self) -> Result<u8> {
+        let mut mode = 0;
+        for bit in 0..3 {
+            mode |= (self.decision(REM_INTRA_PRED_MODE)? as u8) << bit;
+        }
+        Ok(mode)
+    }
+
+    pub fn intra_chroma_pred_mode(&mut self, ctx_inc: usize) -> Result<u8> {
+        if !self.decision(INTRA_CHROMA_PRED_MODE + ctx_inc)? {
+            return Ok(0);
+        }
+        if !self.decision(INTRA_CHROMA_PRED_MODE + 3)? {
+            return Ok(1);
+        }
+        Ok(2 + self.decision(INTRA_CHROMA_PRED_MODE + 3)? as u8)
+    }
+
+    /// ref_idx_lX as a unary code, `ctx_inc` for its first bin.
+    pub fn ref_idx(&mut self, ctx_inc: usize) -> Result<u32> {
+        let mut ref_idx = 0;
+        let mut ctx_idx = REF_IDX + ctx_inc;
+        while self.decision(ctx_idx)? {
+            ref_idx += 1;
+            ctx_idx = REF_IDX + if ref_idx == 1 { 4 } else { 5 };
+            if ref_idx > 32 {
+                return Err(anyhow!("ref_idx exceeds 32"));
+            }
+        }
+        Ok(ref_idx)
+    }
+
+    /// One component of mvd_lX, UEG3 with uCoff 9. `abs_mvd_sum` is absMvdComp of the
+    /// neighbouring partitions A and B (9.3.3.1.1.7).
+    pub fn mvd(&mut self, component: usize, abs_mvd_sum: u32) -> Result<i32> {
+        let offset = MVD[component];
+        let ctx_inc = match abs_mvd_sum {
+            0..=2 => 0,
+            3..=32 => 1,
+            _ => 2,
+        };
+        if !self.decision(offset + ctx_inc)? {
+            return Ok(0);
+        }
+
+        let mut value = 1;
+        let mut ctx_inc = 3;
+        while value < 9 && self.decision(offset + ctx_inc)? {
+            value += 1;
+            ctx_inc = (ctx_inc + 1).min(6);
+        }
+        if value >= 9 {
+            value += self.exp_golomb_bypass(3)?;
+        }
+        Ok(if self.bypass()? {
+            -(value as i32)
+        } else {
+            value as i32
+        })
+    }
+
+    /// `previous_nonzero` tells whether the previous macroblock in decoding order has a
+    /// nonzero mb_qp_delta.
+    pub fn mb_qp_delta(&mut self, previous_nonzero: bool) -> Result<i32> {
+        if !self.decision(MB_QP_DELTA + previous_nonzero as usize)? {
+            return Ok(0);
+        }
+        let mut value = 1;
+        let mut ctx_inc = 2;
+        while self.decision(MB_QP_DELTA + ctx_inc)? {
+            value += 1;
+            ctx_inc = 3;
+            if value > 104 {
+                return Err(anyhow!("mb_qp_delta out of range"));
+            }
+        }
+        // Table 9-3
+        Ok(if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -(value / 2)
+        })
+    }
+
+    /// One bin of the coded_block_pattern prefix, for the 8x8 block with the given ctxIdxInc.
+    pub fn coded_block_pattern_luma_bit(&mut self, ctx_inc: usize) -> Result<bool> {
+        self.decision(CODED_BLOCK_PATTERN_LUMA + ctx_inc)
+    }
+
+    /// The coded_block_pattern suffix. `ctx_inc` gives the increments of both bins.
+    pub fn coded_block_pattern_chroma(&mut self, ctx_inc: [usize; 2]) -> Result<u8> {
+        if !self.decision(CODED_BLOCK_PATTERN_CHROMA + ctx_inc[0])? {
+            return Ok(0);
+        }
+        Ok(1 + self.decision(CODED_BLOCK_PATTERN_CHROMA + 4 + ctx_inc[1])? as u8)
+    }
+
+    pub fn coded_block_flag(&mut self, category: BlockCategory, ctx_inc: usize) -> Result<bool> {
+        let offset = CODED_BLOCK_FLAG_CAT_OFFSET[category as usize];
+        self.decision(CODED_BLOCK_FLAG + offset + ctx_inc)
+    }
+
+    /// significant_coeff_flag, last_significant_coeff_flag and coeff_abs_level_minus1 of a
+    /// block whose coded_block_flag is 1, into `coefficients` in scanning order.
+    pub fn residual_block(
+        &mut self,
+        category: BlockCategory,
+        coefficients: &mut [i32],
+    ) -> Result<()> {
+        let max = coefficients.len();
+        let (significant, last, abs_level) = if category == BlockCategory::Luma8x8 {
+            (
+                SIGNIFICANT_COEFF_FLAG_8X8,
+                LAST_SIGNIFICANT_COEFF_FLAG_8X8,
+                COEFF_ABS_LEVEL_MINUS1_8X8,
+            )
+        } else {
+            let cat = category as usize;
+            (
+                SIGNIFICANT_COEFF_FLAG + SIGNIFICANT_CAT_OFFSET[cat],
+                LAST_SIGNIFICANT_COEFF_FLAG + SIGNIFICANT_CAT_OFFSET[cat],
+                COEFF_ABS_LEVEL_MINUS1 + COEFF_ABS_LEVEL_CAT_OFFSET[cat],
+            )
+        };
+
+        let mut positions = [0u8; 64];
+        let mut count = 0;
+        let mut index = 0;
+        while index < max - 1 {
+            let (significant_inc, last_inc) = match category {
+                BlockCategory::Luma8x8 => (
+                    SIGNIFICANT_8X8_INC[index] as usize,
+                    LAST_8X8_INC[index] as usize,
+                ),
+                // NumC8x8 is 1 for 4:2:0
+                BlockCategory::ChromaDc => (index.min(2), index.min(2)),
+                _ => (index, index),
+            };
+            if self.decision(significant + significant_inc)? {
+                positions[count] = index as u8;
+                count += 1;
+                if self.decision(last + last_inc)? {
+                    break;
+                }
+            }
+            index += 1;
+        }
+        if index == max - 1 {
+            positions[count] = index as u8;
+            count += 1;
+        }
+
+        // Levels come in reverse scanning order
+        let max_gt1_inc = if category == BlockCategory::ChromaDc {
+            3
+        } else {
+            4
+        };
+        let mut num_eq1 = 0;
+        let mut num_gt1 = 0;
+        for &position in positions[..count].iter().rev() {
+            let first_inc = if num_gt1 != 0 {
+                0
+            } else {
+                (1 + num_eq1).min(4)
+            };
+            let mut level = 1;
+            if self.decision(abs_level + first_inc)? {
+                let inc = 5 + num_gt1.min(max_gt1_inc);
+                level += 1;
+                while level < 15 && self.decision(abs_level + inc)? {
+                    level += 1;
+                }
+                if level == 15 {
+                    level += self.exp_golomb_bypass(0)? as i32;
+                }
+                num_gt1 += 1;
+            } else {
+                num_eq1 += 1;
+            }
+            coefficients[position as usize] = if self.bypass()? { -level } else { level };
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+use anyhow::{anyhow, Result};
+
+use crate::bitreader::BitReader;
+
+// Table 9-5, coeff_token lengths and codes indexed by 4 * TotalCoeff + TrailingOnes for
+// 0 <= nC < 2, 2 <= nC < 4, 4 <= nC < 8 and 8 <= nC. A length of 0 marks an impossible
+// combination.
+#[rustfmt::skip]
+const COEFF_TOKEN_LENGTH: [[u8; 68]; 4] = [
+    [
+        1, 0, 0, 0, 6, 2, 0, 0, 8, 6, 3, 0, 9, 8, 7, 5,
+        10, 9, 8, 6, 11, 10, 9, 7, 13, 11, 10, 8, 13, 13, 11, 9,
+        13, 13, 13, 10, 14, 14, 13, 11, 14, 14, 14, 13, 15, 15, 14, 14,
+        15, 15, 15, 14, 16, 15, 15, 15, 16, 16, 16, 15, 16, 16, 16, 16,
+        16, 16, 16, 16,
+    ],
+    [
+        2, 0, 0, 0, 6, 2, 0, 0, 6, 5, 3, 0, 7, 6, 6, 4,
+        8, 6, 6, 4, 8, 7, 7, 5, 9, 8, 8, 6, 11, 9, 9, 6,
+        11, 11, 11, 7, 12, 11, 11, 9, 12, 12, 12, 11, 12, 12, 12, 11,
+        13, 13, 13, 12, 13, 13, 13, 13, 13, 14, 13, 13, 14, 14, 14, 13,
+        14, 14, 14, 14,
+    ],
+    [
+        4, 0, 0, 0, 6, 4, 0, 0, 6, 5, 4, 0, 6, 5, 5, 4,
+        7, 5, 5, 4, 7, 5, 5, 4, 7, 6, 6, 4, 7, 6, 6, 4,
+        8, 7, 7, 5, 8, 8, 7, 6, 9, 8, 8, 7, 9, 9, 8, 8,
+        9, 9, 9, 8, 10, 9, 9, 9, 10, 10, 10, 10, 10, 10, 10, 10,
+        10, 10, 10, 10,
+    ],
+    [
+        6, 0, 0, 0, 6, 6, 0, 0, 6, 6, 6, 0, 6, 6, 6, 6,
+        6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+        6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+        6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+        6, 6, 6, 6,
+    ],
+];
+
+#[rustfmt::skip]
+const COEFF_TOKEN_CODE: [[u8; 68]; 4] = [
+    [
+        1, 0, 0, 0, 5, 1, 0, 0, 7, 4, 1, 0, 7, 6, 5, 3,
+        7, 6, 5, 3, 7, 6, 5, 4, 15, 6, 5, 4, 11, 14, 5, 4,
+        8, 10, 13, 4, 15, 14, 9, 4, 11, 10, 13, 12, 15, 14, 9, 12,
+        11, 10, 13, 8, 15, 1, 9, 12, 11, 14, 13, 8, 7, 10, 9, 12,
+        4, 6, 5, 8,
+    ],
+    [
+        3, 0, 0, 0, 11, 2, 0, 0, 7, 7, 3, 0, 7, 10, 9, 5,
+        7, 6, 5, 4, 4, 6, 5, 6, 7, 6, 5, 8, 15, 6, 5, 4,
+        11, 14, 13, 4, 15, 10, 9, 4, 11, 14, 13, 12, 8, 10, 9, 8,
+        15, 14, 13, 12, 11, 10, 9, 12, 7, 11, 6, 8, 9, 8, 10, 1,
+        7, 6, 5, 4,
+    ],
+    [
+        15, 0, 0, 0, 15, 14, 0, 0, 11, 15, 13, 0, 8, 12, 14, 12,
+        15, 10, 11, 11, 11, 8, 9, 10, 9, 14, 13, 9, 8, 10, 9, 8,
+        15, 14, 13, 13, 11, 14, 10, 12, 15, 10, 13, 12, 11, 14, 9, 12,
+        8, 10, 13, 8, 13, 7, 9, 12, 9, 12, 11, 10, 5, 8, 7, 6,
+        1, 4, 3, 2,
+    ],
+    [
+        3, 0, 0, 0, 0, 1, 0, 0, 4, 5, 6, 0, 8, 9, 10, 11,
+        12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+        28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43,
+        44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59,
+        60, 61, 62, 63,
+    ],
+];
+
+// coeff_token of chroma DC blocks, nC equal to -1
+const CHROMA_DC_COEFF_TOKEN_LENGTH: [u8; 20] =
+    [2, 0, 0, 0, 6, 1, 0, 0, 6, 6, 3, 0, 6, 7, 7, 6, 6, 8, 8, 7];
+const CHROMA_DC_COEFF_TOKEN_CODE: [u8; 20] =
+    [1, 0, 0, 0, 7, 1, 0, 0, 4, 6, 1, 0, 3, 3, 2, 5, 2, 3, 2, 0];
+
+// Tables 9-7 and 9-8, total_zeros of 4x4 blocks indexed by TotalCoeff - 1
+#[rustfmt::skip]
+const TOTAL_ZEROS_LENGTH: [&[u8]; 15] = [
+    &[1, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 9],
+    &[3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 6, 6, 6, 6],
+    &[4, 3, 3, 3, 4, 4, 3, 3, 4, 5, 5, 6, 5, 6],
+    &[5, 3, 4, 4, 3, 3, 3, 4, 3, 4, 5, 5, 5],
+    &[4, 4, 4, 3, 3, 3, 3, 3, 4, 5, 4, 5],
+    &[6, 5, 3, 3, 3, 3, 3, 3, 4, 3, 6],
+    &[6, 5, 3, 3, 3, 2, 3, 4, 3, 6],
+    &[6, 4, 5, 3, 2, 2, 3, 3, 6],
+    &[6, 6, 4, 2, 2, 3, 2, 5],
+    &[5, 5, 3, 2, 2, 2, 4],
+    &[4, 4, 3, 3, 1, 3],
+    &[4, 4, 2, 1, 3],
+    &[3, 3, 1, 2],
+    &[2, 2, 1],
+    &[1, 1],
+];
+
+#[rustfmt::skip]
+const TOTAL_ZEROS_CODE: [&[u8]; 15] = [
+    &[1, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 1],
+    &[7, 6, 5, 4, 3, 5, 4, 3, 2, 3, 2, 3, 2, 1, 0],
+    &[5, 7, 6, 5, 4, 3, 4, 3, 2, 3, 2, 1, 1, 0],
+    &[3, 7, 5, 4, 6, 5, 4, 3, 3, 2, 2, 1, 0],
+    &[5, 4, 3, 7, 6, 5, 4, 3, 2, 1, 1, 0],
+    &[1, 1, 7, 6, 5, 4, 3, 2, 1, 1, 0],
+    &[1, 1, 5, 4, 3, 3, 2, 1, 1, 0],
+    &[1, 1, 1, 3, 3, 2, 2, 1, 0],
+    &[1, 0, 1, 3, 2, 1, 1, 1],
+    &[1, 0, 1, 3, 2, 1, 1],
+    &[0, 1, 1, 2, 1, 3],
+    &[0, 1, 1, 1, 1],
+    &[0, 1, 1, 1],
+    &[0, 1, 1],
+    &[0, 1],
+];
+
+// Table 9-9 (a), total_zeros of 2x2 chroma DC blocks
+const CHROMA_DC_TOTAL_ZEROS_LENGTH: [&[u8]; 3] = [&[1, 2, 3, 3], &[1, 2, 2], &[1, 1]];
+const CHROMA_DC_TOTAL_ZEROS_CODE: [&[u8]; 3] = [&[1, 1, 1, 0], &[1, 1, 0], &[1, 0]];
+
+// Table 9-10, run_before indexed by Min(zerosLeft, 7) - 1
+#[rustfmt::skip]
+const RUN_BEFORE_LENGTH: [&[u8]; 7] = [
+    &[1, 1],
+    &[1, 2, 2],
+    &[2, 2, 2, 2],
+    &[2, 2, 2, 3, 3],
+    &[2, 2, 3, 3, 3, 3],
+    &[2, 3, 3, 3, 3, 3, 3],
+    &[3, 3, 3, 3, 3, 3, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+];
+
+#[rustfmt::skip]
+const RUN_BEFORE_CODE: [&[u8]; 7] = [
+    &[1, 0],
+    &[1, 1, 0],
+    &[3, 2, 1, 0],
+    &[3, 2, 1, 1, 0],
+    &[3, 2, 3, 2, 1, 0],
+    &[3, 0, 1, 3, 2, 5, 4],
+    &[7, 6, 5, 4, 3, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+];
+
+// Table 9-4, coded_block_pattern of Intra_4x4/Intra_8x8 and Inter macroblocks by codeNum
+// for ChromaArrayType 1 and 2
+const INTRA_CODED_BLOCK_PATTERN: [u8; 48] = [
+    47, 31, 15, 0, 23, 27, 29, 30, 7, 11, 13, 14, 39, 43, 45, 46, 16, 3, 5, 10, 12, 19, 21, 26, 28,
+    35, 37, 42, 44, 1, 2, 4, 8, 17, 18, 20, 24, 6, 9, 22, 25, 32, 33, 34, 36, 40, 38, 41,
+];
+const INTER_CODED_BLOCK_PATTERN: [u8; 48] = [
+    0, 16, 1, 2, 4, 8, 32, 3, 5, 10, 12, 15, 47, 7, 11, 13, 14, 6, 9, 31, 35, 37, 42, 44, 33, 34,
+    36, 40, 39, 43, 45, 46, 17, 18, 20, 24, 19, 21, 26, 28, 23, 27, 29, 30, 22, 25, 38, 41,
+];
+
+/// Reads a variable length code and returns the index of the matching entry.
+fn read_vlc(reader: &mut BitReader, lengths: &[u8], codes: &[u8]) -> Result<usize> {
+    let max_length = *lengths.iter().max().unwrap_or(&0);
+    let mut code = 0;
+    for length in 1..=max_length {
+        code = (code << 1) | reader.read_bit()?;
+        if let Some(index) = lengths
+            .iter()
+            .zip(codes)
+            .position(|(&l, &c)| l == length && c as u32 == code)
+        {
+            return Ok(index);
+        }
+    }
+    Err(anyhow!("Invalid CAVLC code"))
+}
+
+/// coded_block_pattern me(v)
+pub fn coded_block_pattern(reader: &mut BitReader, intra: bool) -> Result<u8> {
+    let code_num = reader.read_ue()? as usize;
+    let table = if intra {
+        &INTRA_CODED_BLOCK_PATTERN
+    } else {
+        &INTER_CODED_BLOCK_PATTERN
+    };
+    table
+        .get(code_num)
+        .copied()
+        .ok_or_else(|| anyhow!("Invalid coded_block_pattern code {}", code_num))
+}
+
+/// residual_block_cavlc() (7.3.5.3.2) with its level and run decoding (9.2), for a block of
+/// `coefficients.len()` coefficients in scanning order. `n_c` is -1 for chroma DC blocks.
+/// Returns TotalCoeff.
+pub fn residual_block(reader: &mut BitReader, n_c: i32, coefficients: &mut [i32]) -> Result<u8> {
+    let max = coefficients.len();
+    let index = if n_c == -1 {
+        read_vlc(
+            reader,
+            &CHROMA_DC_COEFF_TOKEN_LENGTH,
+            &CHROMA_DC_COEFF_TOKEN_CODE,
+        )?
+    } else {
+        let table = match n_c {
+            0..=1 => 0,
+            2..=3 => 1,
+            4..=7 => 2,
+            _ => 3,
+        };
+        read_vlc(reader, &COEFF_TOKEN_LENGTH[table], &COEFF_TOKEN_CODE[table])?
+    };
+    let total_coeff = index / 4;
+    let trailing_ones = index % 4;
+    if total_coeff == 0 {
+        return Ok(0);
+    }
+    if total_coeff > max {
+        return Err(anyhow!(
+            "TotalCoeff {} exceeds {} coefficients",
+            total_coeff,
+            max
+        ));
+    }
+
+    let mut levels = [0i32; 16];
+    let mut suffix_length = if total_coeff > 10 && trailing_ones < 3 {
+        1
+    } else {
+        0
+    };
+    for (i, level) in levels[..total_coeff].iter_mut().enumerate() {
+        if i < trailing_ones {
+            *level = 1 - 2 * reader.read_bit()? as i32;
+            continue;
+        }
+
+        let mut level_prefix = 0;
+        while reader.read_bit()? == 0 {
+            level_prefix += 1;
+            if level_prefix > 31 {
+                return Err(anyhow!("Invalid level_prefix"));
+            }
+        }
+        let mut level_code = (level_prefix.min(15) << suffix_length) as i32;
+        if suffix_length > 0 || level_prefix >= 14 {
+            let level_suffix_size = if level_prefix == 14 && suffix_length == 0 {
+                4
+            } else if level_prefix >= 15 {
+                level_prefix - 3
+            } else {
+                suffix_length
+            };
+            level_code += reader.read_bits(level_suffix_size)? as i32;
+        }
+        if level_prefix >= 15 && suffix_length == 0 {
+            level_code += 15;
+        }
+        if level_prefix >= 16 {
+            level_code += (1 << (level_prefix - 3)) - 4096;
+        }
+        if i == trailing_ones && trailing_ones < 3 {
+            level_code += 2;
+        }
+        *level = if level_code % 2 == 0 {
+            (level_code + 2) >> 1
+        } else {
+            (-level_code - 1) >> 1
+        };
+
+        if suffix_length == 0 {
+            suffix_length = 1;
+        }
+        if level.abs() > (3 << (suffix_length - 1)) && suffix_length < 6 {
+            suffix_length += 1;
+        }
+    }
+
+    let mut zeros_left = if total_coeff < max {
+        let index = total_coeff - 1;
+        if max == 4 {
+            read_vlc(
+                reader,
+                CHROMA_DC_TOTAL_ZEROS_LENGTH[index],
+                CHROMA_DC_TOTAL_ZEROS_CODE[index],
+            )?
+        } else {
+            read_vlc(reader, TOTAL_ZEROS_LENGTH[index], TOTAL_ZEROS_CODE[index])?
+        }
+    } else {
+        0
+    };
+    if total_coeff + zeros_left > max {
+        return Err(anyhow!("total_zeros {} out of range", zeros_left));
+    }
+
+    // Levels are in reverse scanning order, each preceded by run_before zeros
+    let mut position = total_coeff + zeros_left;
+    for (i, &level) in levels[..total_coeff].iter().enumerate() {
+        position -= 1;
+        coefficients[position] = level;
+        if i + 1 < total_coeff && zeros_left > 0 {
+            let table = zeros_left.min(7) - 1;
+            let run_before = read_vlc(reader, RUN_BEFORE_LENGTH[table], RUN_BEFORE_CODE[table])?;
+            if run_before > zeros_left {
+                return Err(anyhow!("run_before {} out of range", run_before));
+            }
+            zeros_left -= run_before;
+            position -= run_before;
+        }
+    }
+    Ok(total_coeff as u8)
+}
@@ -0,0 +1,273 @@
+use super::macroblock::{MbInfo, PictureState};
+use super::picture::{Motion, Plane};
+
+// Table 8-16, alpha' and beta' by indexA and indexB
+#[rustfmt::skip]
+const ALPHA: [u8; 52] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    4, 4, 5, 6, 7, 8, 9, 10, 12, 13, 15, 17, 20, 22, 25, 28, 32, 36, 40, 45,
+    50, 56, 63, 71, 80, 90, 101, 113, 127, 144, 162, 182, 203, 226, 255, 255,
+];
+#[rustfmt::skip]
+const BETA: [u8; 52] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10,
+    11, 11, 12, 12, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18,
+];
+
+// Table 8-17, tC0' by indexA for bS 1 to 3
+#[rustfmt::skip]
+const TC0: [[u8; 3]; 52] = [
+    [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [0, 0, 0], [0, 0, 1], [0, 0, 1], [0, 0, 1], [0, 0, 1], [0, 1, 1], [0, 1, 1], [1, 1, 1],
+    [1, 1, 1], [1, 1, 1], [1, 1, 1], [1, 1, 2], [1, 1, 2], [1, 1, 2], [1, 1, 2], [1, 2, 3],
+    [1, 2, 3], [2, 2, 3], [2, 2, 4], [2, 3, 4], [2, 3, 4], [3, 3, 5], [3, 4, 6], [3, 4, 6],
+    [4, 5, 7], [4, 5, 8], [4, 6, 9], [5, 7, 10], [6, 8, 11], [6, 8, 13], [7, 10, 14],
+    [8, 11, 16], [9, 12, 18], [10, 13, 20], [11, 15, 23], [13, 17, 25],
+];
+
+/// Runs the deblocking filter process (8.7) over a completely decoded picture.
+pub fn deblock_picture(state: &mut PictureState) {
+    let width_in_mbs = state.width_in_mbs;
+    for mb_addr in 0..state.mbs.len() {
+        let mb = state.mbs[mb_addr];
+        if !mb.is_decoded() {
+            continue;
+        }
+        let filter = state.filters[mb.slice as usize];
+        if filter.disable_deblocking_filter_idc == 1 {
+            continue;
+        }
+        let (mb_x, mb_y) = (mb_addr % width_in_mbs, mb_addr / width_in_mbs);
+        // Macroblocks across a slice boundary are left alone with idc 2
+        let usable = |neighbour: &MbInfo| {
+            neighbour.is_decoded()
+                && (filter.disable_deblocking_filter_idc != 2 || neighbour.slice == mb.slice)
+        };
+        let left = (mb_x > 0 && usable(&state.mbs[mb_addr - 1])).then(|| mb_addr - 1);
+        let top = (mb_y > 0 && usable(&state.mbs[mb_addr - width_in_mbs]))
+            .then(|| mb_addr - width_in_mbs);
+
+        for vertical in [true, false] {
+            let neighbour = if vertical { left } else { top };
+            for edge in 0..4 {
+                if (edge == 0 && neighbour.is_none()) || (edge % 2 == 1 && mb.transform_8x8) {
+                    continue;
+                }
+                let p_mb = if edge == 0 {
+                    state.mbs[neighbour.unwrap()]
+                } else {
+                    mb
+                };
+                let bs: [u8; 4] = std::array::from_fn(|i| {
+                    let (x, y) = if vertical { (edge, i) } else { (i, edge) };
+                    boundary_strength(state, mb_addr, x, y, vertical, &p_mb, &mb)
+                });
+                if bs == [0; 4] {
+                    continue;
+                }
+
+                let edge_filter = EdgeFilter {
+                    vertical,
+                    alpha_offset: filter.alpha_offset,
+                    beta_offset: filter.beta_offset,
+                };
+                let luma_x = mb_x * 16 + if vertical { edge * 4 } else { 0 };
+                let luma_y = mb_y * 16 + if vertical { 0 } else { edge * 4 };
+                let qp = (p_mb.qp + mb.qp + 1) >> 1;
+                edge_filter.filter(
+                    &mut state.planes[0],
+                    luma_x,
+                    luma_y,
+                    16,
+                    qp,
+                    |i| bs[i / 4],
+                    false,
+                );
+
+                // 4:2:0 chroma edges are at every other luma edge
+                if edge % 2 == 0 {
+                    let (x, y) = (luma_x / 2, luma_y / 2);
+                    for c in 0..2 {
+                        let qp = (p_mb.chroma_qp[c] + mb.chroma_qp[c] + 1) >> 1;
+                        let plane = &mut state.planes[1 + c];
+                        edge_filter.filter(plane, x, y, 8, qp, |i| bs[i / 2], true);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// bS (8.7.2.1) of the edge left of or above the 4x4 luma block at (`x`, `y`) of the
+/// macroblock `mb_addr`, whose samples q0 are on.
+fn boundary_strength(
+    state: &PictureState,
+    mb_addr: usize,
+    x: usize,
+    y: usize,
+    vertical: bool,
+    p_mb: &MbInfo,
+    q_mb: &MbInfo,
+) -> u8 {
+    let mb_edge = if vertical { x == 0 } else { y == 0 };
+    if p_mb.kind.is_intra() || q_mb.kind.is_intra() {
+        return if mb_edge { 4 } else { 3 };
+    }
+
+    let (p_x, p_y) = if vertical {
+        ((x + 3) % 4, y)
+    } else {
+        (x, (y + 3) % 4)
+    };
+    if q_mb.nonzero & (1 << (y * 4 + x)) != 0 || p_mb.nonzero & (1 << (p_y * 4 + p_x)) != 0 {
+        return 2;
+    }
+
+    let width_in_mbs = state.width_in_mbs;
+    let stride = width_in_mbs * 4;
+    let (mb_x, mb_y) = (mb_addr % width_in_mbs, mb_addr / width_in_mbs);
+    let q = (mb_y * 4 + y) * stride + mb_x * 4 + x;
+    let p = if vertical { q - 1 } else { q - stride };
+    let motion = |index: usize| -> Vec<Motion> {
+        state
+            .motion
+            .iter()
+            .map(|motion| motion[index])
+            .filter(|motion| motion.ref_idx >= 0)
+            .collect()
+    };
+    different_motion(&motion(p), &motion(q)) as u8
+}
+
+/// Whether the prediction of two blocks differs enough for a bS of 1
+fn different_motion(p: &[Motion], q: &[Motion]) -> bool {
+    let far = |a: &Motion, b: &Motion| {
+        (a.mv[0] as i32 - b.mv[0] as i32).abs() >= 4 || (a.mv[1] as i32 - b.mv[1] as i32).abs() >= 4
+    };
+    match (p, q) {
+        ([p0], [q0]) => p0.ref_id != q0.ref_id || far(p0, q0),
+        ([p0, p1], [q0, q1]) => {
+            let (mut p_ids, mut q_ids) = ([p0.ref_id, p1.ref_id], [q0.ref_id, q1.ref_id]);
+            p_ids.sort_unstable();
+            q_ids.sort_unstable();
+            if p_ids != q_ids {
+                return true;
+            }
+            if p0.ref_id != p1.ref_id {
+                // Compare the motion vectors for the same reference picture
+                if p0.ref_id == q0.ref_id {
+                    far(p0, q0) || far(p1, q1)
+                } else {
+                    far(p0, q1) || far(p1, q0)
+                }
+            } else {
+                (far(p0, q0) || far(p1, q1)) && (far(p0, q1) || far(p1, q0))
+            }
+        }
+        _ => true,
+    }
+}
+
+struct EdgeFilter {
+    vertical: bool,
+    alpha_offset: i32,
+    beta_offset: i32,
+}
+
+impl EdgeFilter {
+    /// Filters `length` sample rows across the edge whose first q0 sample is at
+    /// (`x`, `y`) (8.7.2.3 and 8.7.2.4). `bs` gives bS by sample position along the edge.
+    #[allow(clippy::too_many_arguments)]
+    fn filter(
+        &self,
+        plane: &mut Plane,
+        x: usize,
+        y: usize,
+        length: usize,
+        qp: i32,
+        bs: impl Fn(usize) -> u8,
+        chroma: bool,
+    ) {
+        let index_a = (qp + self.alpha_offset).clamp(0, 51) as usize;
+        let index_b = (qp + self.beta_offset).clamp(0, 51) as usize;
+        let alpha = ALPHA[index_a] as i32;
+        let beta = BETA[index_b] as i32;
+        if alpha == 0 || beta == 0 {
+            return;
+        }
+
+        let stride = plane.width;
+        let (step, along) = if self.vertical {
+            (1, stride)
+        } else {
+            (stride, 1)
+        };
+        let data = &mut plane.data;
+        for i in 0..length {
+            let bs = bs(i);
+            if bs == 0 {
+                continue;
+            }
+            let q0_index = y * stride + x + i * along;
+            let at = |k: isize| (q0_index as isize + k * step as isize) as usize;
+            // p3 to p0 and q0 to q3
+            let samples: [i32; 8] = std::array::from_fn(|k| data[at(k as isize - 4)] as i32);
+            let [p3, p2, p1, p0, q0, q1, q2, q3] = samples;
+            if (p0 - q0).abs() >= alpha || (p1 - p0).abs() >= beta || (q1 - q0).abs() >= beta {
+                continue;
+            }
+
+            if chroma {
+                if bs < 4 {
+                    let tc = TC0[index_a][bs as usize - 1] as i32 + 1;
+                    let delta = ((((q0 - p0) << 2) + (p1 - q1) + 4) >> 3).clamp(-tc, tc);
+                    data[at(-1)] = (p0 + delta).clamp(0, 255) as u8;
+                    data[at(0)] = (q0 - delta).clamp(0, 255) as u8;
+                } else {
+                    data[at(-1)] = ((2 * p1 + p0 + q1 + 2) >> 2) as u8;
+                    data[at(0)] = ((2 * q1 + q0 + p1 + 2) >> 2) as u8;
+                }
+                continue;
+            }
+
+            let a_p = (p2 - p0).abs();
+            let a_q = (q2 - q0).abs();
+            if bs < 4 {
+                let tc0 = TC0[index_a][bs as usize - 1] as i32;
+                let tc = tc0 + (a_p < beta) as i32 + (a_q < beta) as i32;
+                let delta = ((((q0 - p0) << 2) + (p1 - q1) + 4) >> 3).clamp(-tc, tc);
+                data[at(-1)] = (p0 + delta).clamp(0, 255) as u8;
+                data[at(0)] = (q0 - delta).clamp(0, 255) as u8;
+                if a_p < beta {
+                    data[at(-2)] = (p1
+                        + ((p2 + ((p0 + q0 + 1) >> 1) - (p1 << 1)) >> 1).clamp(-tc0, tc0))
+                        as u8;
+                }
+                if a_q < beta {
+                    data[at(1)] = (q1
+                        + ((q2 + ((p0 + q0 + 1) >> 1) - (q1 << 1)) >> 1).clamp(-tc0, tc0))
+                        as u8;
+                }
+                continue;
+            }
+
+            let strong = (p0 - q0).abs() < (alpha >> 2) + 2;
+            if a_p < beta && strong {
+                data[at(-1)] = ((p2 + 2 * p1 + 2 * p0 + 2 * q0 + q1 + 4) >> 3) as u8;
+                data[at(-2)] = ((p2 + p1 + p0 + q0 + 2) >> 2) as u8;
+                data[at(-3)] = ((2 * p3 + 3 * p2 + p1 + p0 + q0 + 4) >> 3) as u8;
+            } else {
+                data[at(-1)] = ((2 * p1 + p0 + q1 + 2) >> 2) as u8;
+            }
+            if a_q < beta && strong {
+                data[at(0)] = ((p1 + 2 * p0 + 2 * q0 + 2 * q1 + q2 + 4) >> 3) as u8;
+                data[at(1)] = ((p0 + q0 + q1 + q2 + 2) >> 2) as u8;
+                data[at(2)] = ((2 * q3 + 3 * q2 + q1 + q0 + p0 + 4) >> 3) as u8;
+            } else {
+                data[at(0)] = ((2 * q1 + q0 + p1 + 2) >> 2) as u8;
+            }
+        }
+    }
+}
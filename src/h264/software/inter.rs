@@ -0,0 +1,168 @@
+use super::picture::Plane;
+
+/// Largest partition, the prediction buffers are 16 samples wide
+pub const PREDICTION_STRIDE: usize = 16;
+
+/// Luma sample interpolation (8.4.2.2.1) of a `width` x `height` block at (`x`, `y`)
+/// displaced by `mv` in quarter samples, into `dst` with [`PREDICTION_STRIDE`].
+#[allow(clippy::too_many_arguments)]
+pub fn predict_luma(
+    reference: &Plane,
+    x: i32,
+    y: i32,
+    mv: [i32; 2],
+    width: usize,
+    height: usize,
+    dst: &mut [u8],
+) {
+    let x_int = x + (mv[0] >> 2);
+    let y_int = y + (mv[1] >> 2);
+    let (x_frac, y_frac) = (mv[0] & 3, mv[1] & 3);
+
+    if x_frac == 0 && y_frac == 0 {
+        for row in 0..height {
+            for column in 0..width {
+                dst[row * PREDICTION_STRIDE + column] =
+                    reference.sample(x_int + column as i32, y_int + row as i32);
+            }
+        }
+        return;
+    }
+
+    // Integer samples from two to the left and above up to three past the block
+    const WINDOW: usize = 16 + 5;
+    let mut window = [0i32; WINDOW * WINDOW];
+    for row in 0..height + 5 {
+        for column in 0..width + 5 {
+            window[row * WINDOW + column] =
+                reference.sample(x_int - 2 + column as i32, y_int - 2 + row as i32) as i32;
+        }
+    }
+    let full = |column: i32, row: i32| window[(row + 2) as usize * WINDOW + (column + 2) as usize];
+    let tap = |s: [i32; 6]| s[0] - 5 * s[1] + 20 * s[2] + 20 * s[3] - 5 * s[4] + s[5];
+    // Intermediate b1 and h1 of 8-241 and 8-242, between (column, row) and the next sample
+    let b1 = |column: i32, row: i32| tap(std::array::from_fn(|i| full(column - 2 + i as i32, row)));
+    let h1 = |column: i32, row: i32| tap(std::array::from_fn(|i| full(column, row - 2 + i as i32)));
+    let clip = |value: i32| value.clamp(0, 255);
+    let b = |column: i32, row: i32| clip((b1(column, row) + 16) >> 5);
+    let h = |column: i32, row: i32| clip((h1(column, row) + 16) >> 5);
+    let j = |column: i32, row: i32| {
+        let j1 = tap(std::array::from_fn(|i| b1(column, row - 2 + i as i32)));
+        clip((j1 + 512) >> 10)
+    };
+    let average = |a: i32, b: i32| (a + b + 1) >> 1;
+
+    for row in 0..height as i32 {
+        for column in 0..width as i32 {
+            let (c, r) = (column, row);
+            let value = match (x_frac, y_frac) {
+                (0, 1) => average(full(c, r), h(c, r)),
+                (0, 2) => h(c, r),
+                (0, 3) => average(full(c, r + 1), h(c, r)),
+                (1, 0) => average(full(c, r), b(c, r)),
+                (2, 0) => b(c, r),
+                (3, 0) => average(full(c + 1, r), b(c, r)),
+                (1, 1) => average(b(c, r), h(c, r)),
+                (3, 1) => average(b(c, r), h(c + 1, r)),
+                (1, 3) => average(h(c, r), b(c, r + 1)),
+                (3, 3) => average(h(c + 1, r), b(c, r + 1)),
+                (2, 1) => average(b(c, r), j(c, r)),
+                (2, 3) => average(j(c, r), b(c, r + 1)),
+                (1, 2) => average(h(c, r), j(c, r)),
+                (3, 2) => average(j(c, r), h(c + 1, r)),
+                _ => j(c, r),
+            };
+            dst[row as usize * PREDICTION_STRIDE + column as usize] = value as u8;
+        }
+    }
+}
+
+/// Chroma sample interpolation (8.4.2.2.2) of 4:2:0 video, for a `width` x `height` block
+/// at chroma sample (`x`, `y`) displaced by the luma `mv`, which is in eighth chroma samples.
+#[allow(clippy::too_many_arguments)]
+pub fn predict_chroma(
+    reference: &Plane,
+    x: i32,
+    y: i32,
+    mv: [i32; 2],
+    width: usize,
+    height: usize,
+    dst: &mut [u8],
+) {
+    let x_int = x + (mv[0] >> 3);
+    let y_int = y + (mv[1] >> 3);
+    let (x_frac, y_frac) = (mv[0] & 7, mv[1] & 7);
+    for row in 0..height as i32 {
+        for column in 0..width as i32 {
+            let sample =
+                |dx: i32, dy: i32| reference.sample(x_int + column + dx, y_int + row + dy) as i32;
+            let value = ((8 - x_frac) * (8 - y_frac) * sample(0, 0)
+                + x_frac * (8 - y_frac) * sample(1, 0)
+                + (8 - x_frac) * y_frac * sample(0, 1)
+                + x_frac * y_frac * sample(1, 1)
+                + 32)
+                >> 6;
+            dst[row as usize * PREDICTION_STRIDE + column as usize] = value as u8;
+        }
+    }
+}
+
+/// Weight and offset of one reference picture for one colour component.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Weight {
+    pub weight: i32,
+    pub offset: i32,
+}
+
+/// Weighted sample prediction (8.4.2.3) of a block predicted from one list, in place.
+/// `None` is the default prediction, which leaves the samples as they are.
+pub fn weight_single(
+    pred: &mut [u8],
+    width: usize,
+    height: usize,
+    log2_denom: u32,
+    weight: Option<Weight>,
+) {
+    let Some(Weight { weight, offset }) = weight else {
+        return;
+    };
+    for row in pred.chunks_mut(PREDICTION_STRIDE).take(height) {
+        for sample in &mut row[..width] {
+            let value = if log2_denom >= 1 {
+                ((*sample as i32 * weight + (1 << (log2_denom - 1))) >> log2_denom) + offset
+            } else {
+                *sample as i32 * weight + offset
+            };
+            *sample = value.clamp(0, 255) as u8;
+        }
+    }
+}
+
+/// Weighted sample prediction of a bi-predicted block into `pred0`. `None` averages the
+/// two predictions.
+pub fn weight_bi(
+    pred0: &mut [u8],
+    pred1: &[u8],
+    width: usize,
+    height: usize,
+    log2_denom: u32,
+    weights: Option<[Weight; 2]>,
+) {
+    for (row0, row1) in pred0
+        .chunks_mut(PREDICTION_STRIDE)
+        .zip(pred1.chunks(PREDICTION_STRIDE))
+        .take(height)
+    {
+        for (sample0, &sample1) in row0[..width].iter_mut().zip(&row1[..width]) {
+            let (s0, s1) = (*sample0 as i32, sample1 as i32);
+            let value = match weights {
+                None => (s0 + s1 + 1) >> 1,
+                Some([w0, w1]) => {
+                    ((s0 * w0.weight + s1 * w1.weight + (1 << log2_denom)) >> (log2_denom + 1))
+                        + ((w0.offset + w1.offset + 1) >> 1)
+                }
+            };
+            *sample0 = value.clamp(0, 255) as u8;
+        }
+    }
+}
@@ -0,0 +1,334 @@
+/// Which neighbouring samples of a block can be used for intra prediction (8.3.1.2).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Neighbours {
+    pub left: bool,
+    pub top: bool,
+    pub top_right: bool,
+    pub top_left: bool,
+}
+
+/// Reference samples of an NxN luma block: `edge[..n]` holds p[-1, n-1] up to p[-1, 0],
+/// `edge[n]` p[-1, -1] and `edge[n + 1..]` p[0, -1] up to p[2n - 1, -1].
+struct Edge<const N: usize> {
+    samples: [i32; 64],
+}
+
+impl<const N: usize> Edge<N> {
+    fn load(plane: &[u8], stride: usize, x: usize, y: usize, available: Neighbours) -> Self {
+        let mut samples = [128; 64];
+        if available.left {
+            for i in 0..N {
+                samples[N - 1 - i] = plane[(y + i) * stride + x - 1] as i32;
+            }
+        }
+        if available.top_left {
+            samples[N] = plane[(y - 1) * stride + x - 1] as i32;
+        }
+        if available.top {
+            let top = &plane[(y - 1) * stride + x..];
+            for i in 0..N {
+                samples[N + 1 + i] = top[i] as i32;
+            }
+            for i in N..2 * N {
+                samples[N + 1 + i] = if available.top_right {
+                    top[i] as i32
+                } else {
+                    top[N - 1] as i32
+                };
+            }
+        }
+        Self { samples }
+    }
+
+    /// p[x, -1] for x from -1
+    fn top(&self, x: i32) -> i32 {
+        self.samples[(N as i32 + 1 + x) as usize]
+    }
+
+    /// p[-1, y] for y from -1
+    fn left(&self, y: i32) -> i32 {
+        self.samples[(N as i32 - 1 - y) as usize]
+    }
+
+    fn filter(&self, index: i32) -> i32 {
+        let index = index as usize;
+        (self.samples[index - 1] + 2 * self.samples[index] + self.samples[index + 1] + 2) >> 2
+    }
+
+    /// Reference sample filtering of Intra_8x8 prediction (8.3.2.2.1)
+    fn filter_8x8(&mut self, available: Neighbours) {
+        let p = self.samples;
+        let n = N;
+        if available.top {
+            self.samples[n + 1] = if available.top_left {
+                (p[n] + 2 * p[n + 1] + p[n + 2] + 2) >> 2
+            } else {
+                (3 * p[n + 1] + p[n + 2] + 2) >> 2
+            };
+            for i in n + 2..3 * n {
+                self.samples[i] = (p[i - 1] + 2 * p[i] + p[i + 1] + 2) >> 2;
+            }
+            self.samples[3 * n] = (p[3 * n - 1] + 3 * p[3 * n] + 2) >> 2;
+        }
+        if available.top_left {
+            self.samples[n] = match (available.top, available.left) {
+                (true, true) => (p[n + 1] + 2 * p[n] + p[n - 1] + 2) >> 2,
+                (true, false) => (3 * p[n] + p[n + 1] + 2) >> 2,
+                (false, true) => (3 * p[n] + p[n - 1] + 2) >> 2,
+                (false, false) => p[n],
+            };
+        }
+        if available.left {
+            self.samples[n - 1] = if available.top_left {
+                (p[n] + 2 * p[n - 1] + p[n - 2] + 2) >> 2
+            } else {
+                (3 * p[n - 1] + p[n - 2] + 2) >> 2
+            };
+            for i in 1..n - 1 {
+                self.samples[i] = (p[i + 1] + 2 * p[i] + p[i - 1] + 2) >> 2;
+            }
+            self.samples[0] = (p[1] + 3 * p[0] + 2) >> 2;
+        }
+    }
+
+    /// Intra_4x4 and Intra_8x8 prediction with one of the nine directional modes.
+    fn predict(&self, mode: u8, available: Neighbours, plane: &mut [u8], stride: usize, x0: usize) {
+        let n = N as i32;
+        let dc = {
+            let top: i32 = (0..n).map(|x| self.top(x)).sum();
+            let left: i32 = (0..n).map(|y| self.left(y)).sum();
+            let shift = N.trailing_zeros();
+            match (available.top, available.left) {
+                (true, true) => (top + left + n) >> (shift + 1),
+                (true, false) => (top + n / 2) >> shift,
+                (false, true) => (left + n / 2) >> shift,
+                (false, false) => 128,
+            }
+        };
+
+        for y in 0..n {
+            for x in 0..n {
+                let value = match mode {
+                    0 => self.top(x),
+                    1 => self.left(y),
+                    2 => dc,
+                    // Diagonal_Down_Left
+                    3 => {
+                        if x == n - 1 && y == n - 1 {
+                            (self.top(2 * n - 2) + 3 * self.top(2 * n - 1) + 2) >> 2
+                        } else {
+                            self.filter(n + 2 + x + y)
+                        }
+                    }
+                    // Diagonal_Down_Right
+                    4 => self.filter(n + x - y),
+                    // Vertical_Right
+                    5 => {
+                        let z = 2 * x - y;
+                        if z >= 0 && z % 2 == 0 {
+                            (self.top(x - (y >> 1) - 1) + self.top(x - (y >> 1)) + 1) >> 1
+                        } else if z >= 0 {
+                            self.filter(n + 1 + x - (y >> 1) - 1)
+                        } else if z == -1 {
+                            self.filter(n)
+                        } else {
+                            self.filter(n - 1 - (y - 2 * x - 2))
+                        }
+                    }
+                    // Horizontal_Down
+                    6 => {
+                        let z = 2 * y - x;
+                        if z >= 0 && z % 2 == 0 {
+                            (self.left(y - (x >> 1) - 1) + self.left(y - (x >> 1)) + 1) >> 1
+                        } else if z >= 0 {
+                            self.filter(n - 1 - (y - (x >> 1) - 1))
+                        } else if z == -1 {
+                            self.filter(n)
+                        } else {
+                            self.filter(n + 1 + x - 2 * y - 2)
+                        }
+                    }
+                    // Vertical_Left
+                    7 => {
+                        if y % 2 == 0 {
+                            (self.top(x + (y >> 1)) + self.top(x + (y >> 1) + 1) + 1) >> 1
+                        } else {
+                            self.filter(n + 1 + x + (y >> 1) + 1)
+                        }
+                    }
+                    // Horizontal_Up
+                    _ => {
+                        let z = x + 2 * y;
+                        if z < 2 * n - 3 && z % 2 == 0 {
+                            (self.left(y + (x >> 1)) + self.left(y + (x >> 1) + 1) + 1) >> 1
+                        } else if z < 2 * n - 3 {
+                            self.filter(n - 1 - (y + (x >> 1) + 1))
+                        } else if z == 2 * n - 3 {
+                            (self.left(n - 2) + 3 * self.left(n - 1) + 2) >> 2
+                        } else {
+                            self.left(n - 1)
+                        }
+                    }
+                };
+                plane[y as usize * stride + x0 + x as usize] = value as u8;
+            }
+        }
+    }
+}
+
+/// Intra_4x4 prediction (8.3.1.2) of the block at (`x`, `y`) into the plane.
+pub fn predict_4x4(
+    plane: &mut [u8],
+    stride: usize,
+    x: usize,
+    y: usize,
+    mode: u8,
+    available: Neighbours,
+) {
+    let edge = Edge::<4>::load(plane, stride, x, y, available);
+    edge.predict(mode, available, &mut plane[y * stride..], stride, x);
+}
+
+/// Intra_8x8 prediction (8.3.2.2) of the block at (`x`, `y`) into the plane.
+pub fn predict_8x8(
+    plane: &mut [u8],
+    stride: usize,
+    x: usize,
+    y: usize,
+    mode: u8,
+    available: Neighbours,
+) {
+    let mut edge = Edge::<8>::load(plane, stride, x, y, available);
+    edge.filter_8x8(available);
+    edge.predict(mode, available, &mut plane[y * stride..], stride, x);
+}
+
+/// Plane prediction shared by Intra_16x16 luma and 4:2:0 chroma (8.3.3.4 and 8.3.4.4),
+/// for a `size` x `size` block.
+fn predict_plane(plane: &mut [u8], stride: usize, x0: usize, y0: usize, size: usize) {
+    let top = |x: isize| plane[(y0 - 1) * stride + (x0 as isize + x) as usize] as i32;
+    let left = |y: isize| plane[(y0 as isize + y) as usize * stride + x0 - 1] as i32;
+    let half = size as isize / 2;
+    let mut h = 0;
+    let mut v = 0;
+    for i in 0..half {
+        h += (i as i32 + 1) * (top(half + i) - top(half - 2 - i));
+        v += (i as i32 + 1) * (left(half + i) - left(half - 2 - i));
+    }
+    let last = size as isize - 1;
+    let a = 16 * (left(last) + top(last));
+    let (b, c) = if size == 16 {
+        ((5 * h + 32) >> 6, (5 * v + 32) >> 6)
+    } else {
+        ((34 * h + 32) >> 6, (34 * v + 32) >> 6)
+    };
+    let center = half as i32 - 1;
+    for y in 0..size {
+        for x in 0..size {
+            let value = (a + b * (x as i32 - center) + c * (y as i32 - center) + 16) >> 5;
+            plane[(y0 + y) * stride + x0 + x] = value.clamp(0, 255) as u8;
+        }
+    }
+}
+
+fn fill(plane: &mut [u8], stride: usize, x0: usize, y0: usize, size: usize, value: u8) {
+    for y in 0..size {
+        plane[(y0 + y) * stride + x0..][..size].fill(value);
+    }
+}
+
+fn top_sum(plane: &[u8], stride: usize, x0: usize, y0: usize, size: usize) -> u32 {
+    plane[(y0 - 1) * stride + x0..][..size]
+        .iter()
+        .map(|&s| s as u32)
+        .sum()
+}
+
+fn left_sum(plane: &[u8], stride: usize, x0: usize, y0: usize, size: usize) -> u32 {
+    (0..size)
+        .map(|y| plane[(y0 + y) * stride + x0 - 1] as u32)
+        .sum()
+}
+
+/// Intra_16x16 prediction (8.3.3) of the macroblock at (`x`, `y`). Mode 0 is vertical,
+/// 1 horizontal, 2 DC and 3 plane.
+pub fn predict_16x16(
+    plane: &mut [u8],
+    stride: usize,
+    x: usize,
+    y: usize,
+    mode: u8,
+    available: Neighbours,
+) {
+    match mode {
+        0 => {
+            let mut top = [0; 16];
+            top.copy_from_slice(&plane[(y - 1) * stride + x..][..16]);
+            for row in 0..16 {
+                plane[(y + row) * stride + x..][..16].copy_from_slice(&top);
+            }
+        }
+        1 => {
+            for row in 0..16 {
+                let left = plane[(y + row) * stride + x - 1];
+                plane[(y + row) * stride + x..][..16].fill(left);
+            }
+        }
+        2 => {
+            let value = match (available.top, available.left) {
+                (true, true) => {
+                    (top_sum(plane, stride, x, y, 16) + left_sum(plane, stride, x, y, 16) + 16) >> 5
+                }
+                (true, false) => (top_sum(plane, stride, x, y, 16) + 8) >> 4,
+                (false, true) => (left_sum(plane, stride, x, y, 16) + 8) >> 4,
+                (false, false) => 128,
+            };
+            fill(plane, stride, x, y, 16, value as u8);
+        }
+        _ => predict_plane(plane, stride, x, y, 16),
+    }
+}
+
+/// Chroma intra prediction (8.3.4) of an 8x8 block of 4:2:0 video at (`x`, `y`). Mode 0
+/// is DC, 1 horizontal, 2 vertical and 3 plane.
+pub fn predict_chroma(
+    plane: &mut [u8],
+    stride: usize,
+    x: usize,
+    y: usize,
+    mode: u8,
+    available: Neighbours,
+) {
+    match mode {
+        0 => {
+            for (x_offset, y_offset) in [(0, 0), (4, 0), (0, 4), (4, 4)] {
+                let (bx, by) = (x + x_offset, y + y_offset);
+                let top = || top_sum(plane, stride, bx, y, 4);
+                let left = || left_sum(plane, stride, x, by, 4);
+                // Blocks on the diagonal use both neighbours, the others prefer their
+                // adjacent one
+                let value = match (x_offset, y_offset, available.top, available.left) {
+                    (4, 0, true, _) | (_, _, true, false) => (top() + 2) >> 2,
+                    (0, 4, _, true) | (_, _, false, true) => (left() + 2) >> 2,
+                    (_, _, true, true) => (top() + left() + 4) >> 3,
+                    (_, _, false, false) => 128,
+                };
+                fill(plane, stride, bx, by, 4, value as u8);
+            }
+        }
+        1 => {
+            for row in 0..8 {
+                let left = plane[(y + row) * stride + x - 1];
+                plane[(y + row) * stride + x..][..8].fill(left);
+            }
+        }
+        2 => {
+            let mut top = [0; 8];
+            top.copy_from_slice(&plane[(y - 1) * stride + x..][..8]);
+            for row in 0..8 {
+                plane[(y + row) * stride + x..][..8].copy_from_slice(&top);
+            }
+        }
+        _ => predict_plane(plane, stride, x, y, 8),
+    }
+}
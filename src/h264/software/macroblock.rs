@@ -0,0 +1,1462 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use super::cabac::Cabac;
+use super::cavlc;
+use super::intra::{self, Neighbours};
+use super::picture::{Motion, Picture, Plane};
+use super::transform::{self, LevelScale, ZIGZAG_4X4, ZIGZAG_8X8};
+use crate::bitreader::BitReader;
+use crate::h264::pps::Pps;
+use crate::h264::slice::{SliceHeader, SliceType};
+use crate::h264::sps::Sps;
+
+/// ctxBlockCat of Table 9-42 for 4:2:0 video, which also tells CAVLC blocks apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockCategory {
+    LumaDc = 0,
+    LumaAc = 1,
+    Luma4x4 = 2,
+    ChromaDc = 3,
+    ChromaAc = 4,
+    Luma8x8 = 5,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MbKind {
+    I4x4,
+    I8x8,
+    I16x16,
+    IPcm,
+    PSkip,
+    BSkip,
+    /// B_Direct_16x16
+    BDirect,
+    /// Every other P and B macroblock type
+    Inter,
+}
+
+impl MbKind {
+    pub fn is_intra(self) -> bool {
+        matches!(
+            self,
+            MbKind::I4x4 | MbKind::I8x8 | MbKind::I16x16 | MbKind::IPcm
+        )
+    }
+
+    pub fn is_skip(self) -> bool {
+        matches!(self, MbKind::PSkip | MbKind::BSkip)
+    }
+}
+
+/// What later macroblocks and the deblocking filter need to know about a decoded macroblock.
+#[derive(Clone, Copy, Debug)]
+pub struct MbInfo {
+    /// Index of the slice within the picture, [`MbInfo::NOT_DECODED`] until decoded
+    pub slice: u32,
+    pub kind: MbKind,
+    pub transform_8x8: bool,
+    /// CodedBlockPatternLuma in the low four bits and CodedBlockPatternChroma above,
+    /// all set for I_PCM
+    pub cbp: u8,
+    /// QPY, 0 for I_PCM as the deblocking filter wants it
+    pub qp: i32,
+    /// QPc of Cb and Cr
+    pub chroma_qp: [i32; 2],
+    pub chroma_pred_mode: u8,
+    /// coded_block_flag of the luma, Cb and Cr DC blocks
+    pub coded_dc: u8,
+    /// 8x8 blocks predicted in direct mode
+    pub direct: u8,
+    /// 4x4 luma blocks with non-zero coefficients, bit y * 4 + x
+    pub nonzero: u16,
+}
+
+impl MbInfo {
+    pub const NOT_DECODED: u32 = u32::MAX;
+
+    const EMPTY: MbInfo = MbInfo {
+        slice: MbInfo::NOT_DECODED,
+        kind: MbKind::Inter,
+        transform_8x8: false,
+        cbp: 0,
+        qp: 0,
+        chroma_qp: [0, 0],
+        chroma_pred_mode: 0,
+        coded_dc: 0,
+        direct: 0,
+        nonzero: 0,
+    };
+
+    pub fn is_decoded(&self) -> bool {
+        self.slice != MbInfo::NOT_DECODED
+    }
+}
+
+/// Deblocking filter parameters of one slice.
+#[derive(Clone, Copy, Debug)]
+pub struct FilterParams {
+    pub disable_deblocking_filter_idc: u32,
+    /// FilterOffsetA and FilterOffsetB
+    pub alpha_offset: i32,
+    pub beta_offset: i32,
+}
+
+/// A reference picture as it appears in RefPicList0 or RefPicList1.
+#[derive(Clone, Debug)]
+pub struct RefPicture {
+    pub picture: Arc<Picture>,
+    /// The POC it has as a reference, which differs from the one it was decoded with
+    /// after MMCO 5
+    pub pic_order_cnt: i32,
+    pub long_term: bool,
+}
+
+/// Everything about a slice that stays the same across its macroblocks.
+pub struct SliceContext<'a> {
+    pub header: &'a SliceHeader,
+    pub sps: &'a Sps,
+    pub pps: &'a Pps,
+    pub level_scale: &'a LevelScale,
+    pub pic_order_cnt: i32,
+    /// Entries are `None` where no reference picture is available
+    pub ref_lists: [Vec<Option<RefPicture>>; 2],
+}
+
+/// The picture being decoded along with the per-block state that the parsing and
+/// prediction of neighbouring macroblocks depends on.
+pub struct PictureState {
+    pub width_in_mbs: usize,
+    pub planes: [Plane; 3],
+    pub mbs: Vec<MbInfo>,
+    /// Per 4x4 luma block in raster order and per list
+    pub motion: [Vec<Motion>; 2],
+    /// Per slice in decoding order
+    pub filters: Vec<FilterParams>,
+    /// TotalCoeff with CAVLC or coded_block_flag with CABAC per 4x4 luma block
+    coded_luma: Vec<u8>,
+    /// The same per 4x4 Cb and Cr block
+    coded_chroma: [Vec<u8>; 2],
+    /// Intra4x4PredMode or Intra8x8PredMode per 4x4 luma block
+    intra_modes: Vec<u8>,
+    /// Absolute mvd components per 4x4 luma block, for the CABAC contexts
+    mvd: [Vec<[u8; 2]>; 2],
+}
+
+impl PictureState {
+    pub fn new(width_in_mbs: usize, height_in_mbs: usize) -> Self {
+        let blocks = width_in_mbs * height_in_mbs * 16;
+        let chroma_blocks = width_in_mbs * height_in_mbs * 4;
+        Self {
+            width_in_mbs,
+            planes: [
+                Plane::new(width_in_mbs * 16, height_in_mbs * 16),
+                Plane::new(width_in_mbs * 8, height_in_mbs * 8),
+                Plane::new(width_in_mbs * 8, height_in_mbs * 8),
+            ],
+            mbs: vec![MbInfo::EMPTY; width_in_mbs * height_in_mbs],
+            motion: [vec![Motion::UNUSED; blocks], vec![Motion::UNUSED; blocks]],
+            filters: Vec::new(),
+            coded_luma: vec![0; blocks],
+            coded_chroma: [vec![0; chroma_blocks], vec![0; chroma_blocks]],
+            intra_modes: vec![2; blocks],
+            mvd: [vec![[0; 2]; blocks], vec![[0; 2]; blocks]],
+        }
+    }
+
+    pub fn into_picture(self, id: u32) -> Picture {
+        Picture {
+            id,
+            planes: self.planes,
+            motion: self.motion,
+        }
+    }
+}
+
+/// Column and row of each luma4x4BlkIdx in 4x4 blocks (6.4.3)
+const BLOCK_X: [usize; 16] = [0, 1, 0, 1, 2, 3, 2, 3, 0, 1, 0, 1, 2, 3, 2, 3];
+const BLOCK_Y: [usize; 16] = [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 3, 3, 2, 2, 3, 3];
+
+/// luma4x4BlkIdx of each 4x4 block by row and column
+const BLOCK_INDEX: [[usize; 4]; 4] = [[0, 1, 4, 5], [2, 3, 6, 7], [8, 9, 12, 13], [10, 11, 14, 15]];
+
+/// Partitions of the P and B macroblock types with one to two of them, Tables 7-13 and
+/// 7-14, as their size in 4x4 blocks and the lists each one predicts from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Shape {
+    Whole,
+    Wide,
+    Tall,
+}
+
+const PRED_L0: u8 = 1;
+const PRED_L1: u8 = 2;
+const PRED_BI: u8 = 3;
+
+#[rustfmt::skip]
+const B_PARTITIONS: [(Shape, [u8; 2]); 22] = [
+    (Shape::Whole, [0, 0]),
+    (Shape::Whole, [PRED_L0, 0]), (Shape::Whole, [PRED_L1, 0]), (Shape::Whole, [PRED_BI, 0]),
+    (Shape::Wide, [PRED_L0, PRED_L0]), (Shape::Tall, [PRED_L0, PRED_L0]),
+    (Shape::Wide, [PRED_L1, PRED_L1]), (Shape::Tall, [PRED_L1, PRED_L1]),
+    (Shape::Wide, [PRED_L0, PRED_L1]), (Shape::Tall, [PRED_L0, PRED_L1]),
+    (Shape::Wide, [PRED_L1, PRED_L0]), (Shape::Tall, [PRED_L1, PRED_L0]),
+    (Shape::Wide, [PRED_L0, PRED_BI]), (Shape::Tall, [PRED_L0, PRED_BI]),
+    (Shape::Wide, [PRED_L1, PRED_BI]), (Shape::Tall, [PRED_L1, PRED_BI]),
+    (Shape::Wide, [PRED_BI, PRED_L0]), (Shape::Tall, [PRED_BI, PRED_L0]),
+    (Shape::Wide, [PRED_BI, PRED_L1]), (Shape::Tall, [PRED_BI, PRED_L1]),
+    (Shape::Wide, [PRED_BI, PRED_BI]), (Shape::Tall, [PRED_BI, PRED_BI]),
+];
+
+/// Sub-macroblock partition width and height in 4x4 blocks and prediction lists,
+/// Tables 7-17 and 7-18. B_Direct_8x8 has no lists.
+#[rustfmt::skip]
+const P_SUB_PARTITIONS: [(usize, usize, u8); 4] = [
+    (2, 2, PRED_L0), (2, 1, PRED_L0), (1, 2, PRED_L0), (1, 1, PRED_L0),
+];
+#[rustfmt::skip]
+const B_SUB_PARTITIONS: [(usize, usize, u8); 13] = [
+    (2, 2, 0),
+    (2, 2, PRED_L0), (2, 2, PRED_L1), (2, 2, PRED_BI),
+    (2, 1, PRED_L0), (1, 2, PRED_L0), (2, 1, PRED_L1), (1, 2, PRED_L1),
+    (2, 1, PRED_BI), (1, 2, PRED_BI),
+    (1, 1, PRED_L0), (1, 1, PRED_L1), (1, 1, PRED_BI),
+];
+
+/// A rectangle of 4x4 blocks inside the macroblock predicted with the same motion.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Partition {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Partition {
+    /// Bits of its 4x4 blocks in a y * 4 + x mask
+    fn mask(&self) -> u16 {
+        let mut mask = 0;
+        for y in self.y..self.y + self.height {
+            for x in self.x..self.x + self.width {
+                mask |= 1 << (y * 4 + x);
+            }
+        }
+        mask
+    }
+}
+
+/// Coefficient levels of a macroblock, in raster order within each block.
+struct Residual {
+    luma_dc: [i32; 16],
+    /// Per luma4x4BlkIdx
+    luma: [[i32; 16]; 16],
+    luma_8x8: [[i32; 64]; 4],
+    chroma_dc: [[i32; 4]; 2],
+    chroma_ac: [[[i32; 16]; 4]; 2],
+}
+
+impl Residual {
+    fn new() -> Self {
+        Self {
+            luma_dc: [0; 16],
+            luma: [[0; 16]; 16],
+            luma_8x8: [[0; 64]; 4],
+            chroma_dc: [[0; 4]; 2],
+            chroma_ac: [[[0; 16]; 4]; 2],
+        }
+    }
+}
+
+enum Entropy<'a> {
+    Cavlc(BitReader<'a>),
+    Cabac(Box<Cabac<'a>>),
+}
+
+/// Decodes slice_data() (7.3.4) of one slice into the picture. `rbsp` is the slice NAL
+/// unit without its header byte and emulation prevention bytes.
+pub fn decode_slice(state: &mut PictureState, context: &SliceContext, rbsp: &[u8]) -> Result<()> {
+    let header = context.header;
+    let mut reader = BitReader::new(rbsp);
+    reader.skip_bits(header.header_bits)?;
+
+    let slice_qp = 26 + context.pps.pic_init_qp_minus26 as i32 + header.slice_qp_delta;
+    if !(0..=51).contains(&slice_qp) {
+        return Err(anyhow!("SliceQPY {} out of range", slice_qp));
+    }
+    let entropy = if context.pps.entropy_coding_mode_flag {
+        // cabac_alignment_one_bit
+        reader.align();
+        Entropy::Cabac(Box::new(Cabac::new(
+            reader,
+            header.slice_type,
+            header.cabac_init_idc,
+            slice_qp,
+        )?))
+    } else {
+        Entropy::Cavlc(reader)
+    };
+
+    state.filters.push(FilterParams {
+        disable_deblocking_filter_idc: header.disable_deblocking_filter_idc,
+        alpha_offset: header.slice_alpha_c0_offset_div2 * 2,
+        beta_offset: header.slice_beta_offset_div2 * 2,
+    });
+    let slice = state.filters.len() as u32 - 1;
+
+    let mut decoder = SliceDecoder {
+        state,
+        context,
+        entropy,
+        slice,
+        qp: slice_qp,
+        previous_qp_delta_nonzero: false,
+        mb_addr: 0,
+        mb_x: 0,
+        mb_y: 0,
+        spatial_direct: None,
+    };
+    decoder.run()
+}
+
+pub(super) struct SliceDecoder<'s, 'c> {
+    pub(super) state: &'s mut PictureState,
+    pub(super) context: &'s SliceContext<'c>,
+    entropy: Entropy<'s>,
+    slice: u32,
+    /// QPY of the last macroblock, the prediction for the next one
+    qp: i32,
+    previous_qp_delta_nonzero: bool,
+    pub(super) mb_addr: usize,
+    pub(super) mb_x: usize,
+    pub(super) mb_y: usize,
+    /// Reference indices and motion vectors of spatial direct prediction, derived once
+    /// per macroblock
+    pub(super) spatial_direct: Option<([i8; 2], [[i32; 2]; 2])>,
+}
+
+impl<'s> SliceDecoder<'s, '_> {
+    fn run(&mut self) -> Result<()> {
+        let intra_slice = self.context.header.slice_type.is_intra();
+        let mut mb_addr = self.context.header.first_mb_in_slice as usize;
+
+        if let Entropy::Cabac(_) = self.entropy {
+            loop {
+                self.start_macroblock(mb_addr)?;
+                let skip = !intra_slice && {
+                    let ctx_inc = self.neighbour_count(|mb| !mb.kind.is_skip());
+                    let slice_type = self.context.header.slice_type;
+                    self.cabac().mb_skip_flag(slice_type, ctx_inc)?
+                };
+                if skip {
+                    self.decode_skip()?;
+                } else {
+                    self.decode_macroblock()?;
+                }
+                if self.cabac().end_of_slice_flag()? {
+                    return Ok(());
+                }
+                mb_addr += 1;
+            }
+        }
+
+        loop {
+            if !intra_slice {
+                let mb_skip_run = self.cavlc().read_ue()?;
+                for _ in 0..mb_skip_run {
+                    self.start_macroblock(mb_addr)?;
+                    self.decode_skip()?;
+                    mb_addr += 1;
+                }
+                if mb_skip_run > 0 && !self.cavlc().more_rbsp_data() {
+                    return Ok(());
+                }
+            }
+            self.start_macroblock(mb_addr)?;
+            self.decode_macroblock()?;
+            if !self.cavlc().more_rbsp_data() {
+                return Ok(());
+            }
+            mb_addr += 1;
+        }
+    }
+
+    fn cabac(&mut self) -> &mut Cabac<'s> {
+        match &mut self.entropy {
+            Entropy::Cabac(cabac) => cabac,
+            Entropy::Cavlc(_) => unreachable!(),
+        }
+    }
+
+    fn cavlc(&mut self) -> &mut BitReader<'s> {
+        match &mut self.entropy {
+            Entropy::Cavlc(reader) => reader,
+            Entropy::Cabac(_) => unreachable!(),
+        }
+    }
+
+    fn start_macroblock(&mut self, mb_addr: usize) -> Result<()> {
+        if mb_addr >= self.state.mbs.len() {
+            return Err(anyhow!("Slice data runs past the end of the picture"));
+        }
+        if self.state.mbs[mb_addr].is_decoded() {
+            return Err(anyhow!("Macroblock {} is decoded twice", mb_addr));
+        }
+        self.mb_addr = mb_addr;
+        self.mb_x = mb_addr % self.state.width_in_mbs;
+        self.mb_y = mb_addr / self.state.width_in_mbs;
+        self.spatial_direct = None;
+
+        let info = MbInfo {
+            slice: self.slice,
+            qp: self.qp,
+            chroma_qp: self.chroma_qp(self.qp),
+            ..MbInfo::EMPTY
+        };
+        let rows: [usize; 4] = std::array::from_fn(|y| self.block_index(0, y));
+        let chroma_rows: [usize; 2] = std::array::from_fn(|y| self.chroma_block_index(0, y));
+
+        let state = &mut *self.state;
+        state.mbs[mb_addr] = info;
+        for start in rows {
+            for list in 0..2 {
+                state.motion[list][start..start + 4].fill(Motion::UNUSED);
+                state.mvd[list][start..start + 4].fill([0; 2]);
+            }
+            state.coded_luma[start..start + 4].fill(0);
+            state.intra_modes[start..start + 4].fill(2);
+        }
+        for start in chroma_rows {
+            for coded in &mut state.coded_chroma {
+                coded[start..start + 2].fill(0);
+            }
+        }
+        Ok(())
+    }
+
+    fn chroma_qp(&self, qp: i32) -> [i32; 2] {
+        let pps = self.context.pps;
+        [
+            transform::chroma_qp(qp, pps.chroma_qp_index_offset as i32),
+            transform::chroma_qp(qp, pps.second_chroma_qp_index_offset as i32),
+        ]
+    }
+
+    fn mb(&self) -> &MbInfo {
+        &self.state.mbs[self.mb_addr]
+    }
+
+    fn mb_mut(&mut self) -> &mut MbInfo {
+        &mut self.state.mbs[self.mb_addr]
+    }
+
+    /// Index of the 4x4 luma block at (`x`, `y`) of the current macroblock in the
+    /// per-block arrays
+    pub(super) fn block_index(&self, x: usize, y: usize) -> usize {
+        (self.mb_y * 4 + y) * self.state.width_in_mbs * 4 + self.mb_x * 4 + x
+    }
+
+    fn chroma_block_index(&self, x: usize, y: usize) -> usize {
+        (self.mb_y * 2 + y) * self.state.width_in_mbs * 2 + self.mb_x * 2 + x
+    }
+
+    /// The macroblock covering the 4x4 block at (`x`, `y`) relative to the current one,
+    /// for blocks left of or above it (6.4.12). `None` when it is outside the picture or
+    /// slice, or not decoded yet.
+    pub(super) fn neighbour_mb(&self, x: i32, y: i32) -> Option<usize> {
+        if y >= 0 && x >= 4 {
+            return None;
+        }
+        let mb_x = self.mb_x as i32 + x.div_euclid(4);
+        let mb_y = self.mb_y as i32 + y.div_euclid(4);
+        if mb_x < 0 || mb_y < 0 || mb_x >= self.state.width_in_mbs as i32 {
+            return None;
+        }
+        let mb_addr = mb_y as usize * self.state.width_in_mbs + mb_x as usize;
+        (self.state.mbs[mb_addr].slice == self.slice).then_some(mb_addr)
+    }
+
+    /// The macroblock and block index of the 4x4 luma block at (`x`, `y`) relative to the
+    /// current macroblock, which may be inside it.
+    pub(super) fn locate(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let mb_addr = if (0..4).contains(&x) && (0..4).contains(&y) {
+            self.mb_addr
+        } else {
+            self.neighbour_mb(x, y)?
+        };
+        let stride = self.state.width_in_mbs as i32 * 4;
+        let index = (self.mb_y as i32 * 4 + y) * stride + self.mb_x as i32 * 4 + x;
+        Some((mb_addr, index as usize))
+    }
+
+    /// condTermFlagA + condTermFlagB of the macroblocks left and above
+    fn neighbour_count(&self, condition: impl Fn(&MbInfo) -> bool) -> usize {
+        [(-1, 0), (0, -1)]
+            .into_iter()
+            .filter(|&(x, y)| {
+                self.neighbour_mb(x, y)
+                    .is_some_and(|mb_addr| condition(&self.state.mbs[mb_addr]))
+            })
+            .count()
+    }
+
+    /// Whether the neighbouring macroblock may be used for intra prediction
+    fn intra_usable(&self, mb_addr: usize) -> bool {
+        !self.context.pps.constrained_intra_pred_flag || self.state.mbs[mb_addr].kind.is_intra()
+    }
+
+    fn decode_skip(&mut self) -> Result<()> {
+        self.previous_qp_delta_nonzero = false;
+        if self.context.header.slice_type == SliceType::B {
+            let mb = self.mb_mut();
+            mb.kind = MbKind::BSkip;
+            mb.direct = 0xf;
+            for sub in 0..4 {
+                self.direct_motion(sub)?;
+            }
+        } else {
+            self.mb_mut().kind = MbKind::PSkip;
+            self.p_skip_motion()?;
+        }
+        self.predict_inter()
+    }
+
+    fn decode_macroblock(&mut self) -> Result<()> {
+        let slice_type = self.context.header.slice_type;
+        let mb_type = match &mut self.entropy {
+            Entropy::Cavlc(reader) => reader.read_ue()?,
+            Entropy::Cabac(_) => {
+                let ctx_inc = match slice_type {
+                    SliceType::I => {
+                        self.neighbour_count(|mb| !matches!(mb.kind, MbKind::I4x4 | MbKind::I8x8))
+                    }
+                    SliceType::B => self
+                        .neighbour_count(|mb| !matches!(mb.kind, MbKind::BSkip | MbKind::BDirect)),
+                    _ => 0,
+                };
+                self.cabac().mb_type(slice_type, ctx_inc)?
+            }
+        };
+
+        let intra_type = match slice_type {
+            SliceType::I => Some(mb_type),
+            SliceType::P => mb_type.checked_sub(5),
+            SliceType::B => mb_type.checked_sub(23),
+            _ => return Err(anyhow!("{:?} slices are not supported", slice_type)),
+        };
+        match intra_type {
+            Some(25) => self.decode_pcm(),
+            Some(intra_type) if intra_type > 25 => Err(anyhow!(
+                "Invalid mb_type {} in a {:?} slice",
+                mb_type,
+                slice_type
+            )),
+            Some(intra_type) => self.decode_intra(intra_type),
+            None => self.decode_inter(mb_type),
+        }
+    }
+
+    fn decode_pcm(&mut self) -> Result<()> {
+        let mut samples = [0u8; 384];
+        match &mut self.entropy {
+            Entropy::Cavlc(reader) => {
+                reader.align();
+                for sample in &mut samples {
+                    *sample = reader.read_bits(8)? as u8;
+                }
+            }
+            Entropy::Cabac(cabac) => cabac.pcm_samples(&mut samples)?,
+        }
+
+        let (x, y) = (self.mb_x * 16, self.mb_y * 16);
+        let luma = &mut self.state.planes[0];
+        for (row, samples) in samples[..256].chunks(16).enumerate() {
+            let start = (y + row) * luma.width + x;
+            luma.data[start..start + 16].copy_from_slice(samples);
+        }
+        for (plane, samples) in self.state.planes[1..]
+            .iter_mut()
+            .zip(samples[256..].chunks(64))
+        {
+            for (row, samples) in samples.chunks(8).enumerate() {
+                let start = (y / 2 + row) * plane.width + x / 2;
+                plane.data[start..start + 8].copy_from_slice(samples);
+            }
+        }
+
+        // I_PCM counts as 16 coefficients for CAVLC and as coded for CABAC everywhere
+        let coded = match self.entropy {
+            Entropy::Cavlc(_) => 16,
+            Entropy::Cabac(_) => 1,
+        };
+        for y in 0..4 {
+            let start = self.block_index(0, y);
+            self.state.coded_luma[start..start + 4].fill(coded);
+        }
+        for y in 0..2 {
+            let start = self.chroma_block_index(0, y);
+            for plane in &mut self.state.coded_chroma {
+                plane[start..start + 2].fill(coded);
+            }
+        }
+        let chroma_qp = self.chroma_qp(0);
+        let mb = self.mb_mut();
+        mb.kind = MbKind::IPcm;
+        mb.cbp = 0x2f;
+        mb.qp = 0;
+        mb.chroma_qp = chroma_qp;
+        mb.coded_dc = 0b111;
+        mb.nonzero = 0xffff;
+        self.previous_qp_delta_nonzero = false;
+        Ok(())
+    }
+
+    fn decode_intra(&mut self, intra_type: u32) -> Result<()> {
+        let mut modes = [0u8; 16];
+        let cbp = if intra_type == 0 {
+            let transform_8x8 =
+                self.context.pps.transform_8x8_mode_flag && self.transform_size_8x8_flag()?;
+            let mb = self.mb_mut();
+            mb.transform_8x8 = transform_8x8;
+            mb.kind = if transform_8x8 {
+                MbKind::I8x8
+            } else {
+                MbKind::I4x4
+            };
+
+            let (count, size) = if transform_8x8 { (4, 2) } else { (16, 1) };
+            for (index, mode) in modes[..count].iter_mut().enumerate() {
+                let block = if transform_8x8 { index * 4 } else { index };
+                let (x, y) = (BLOCK_X[block], BLOCK_Y[block]);
+                let (prev_flag, rem) = match &mut self.entropy {
+                    Entropy::Cavlc(reader) => {
+                        let prev_flag = reader.read_flag()?;
+                        let rem = if prev_flag {
+                            0
+                        } else {
+                            reader.read_bits(3)? as u8
+                        };
+                        (prev_flag, rem)
+                    }
+                    Entropy::Cabac(cabac) => {
+                        let prev_flag = cabac.prev_intra_pred_mode_flag()?;
+                        let rem = if prev_flag {
+                            0
+                        } else {
+                            cabac.rem_intra_pred_mode()?
+                        };
+                        (prev_flag, rem)
+                    }
+                };
+                let predicted = self.predicted_intra_mode(x, y);
+                *mode = if prev_flag {
+                    predicted
+                } else if rem < predicted {
+                    rem
+                } else {
+                    rem + 1
+                };
+                for y in y..y + size {
+                    let start = self.block_index(x, y);
+                    self.state.intra_modes[start..start + size].fill(*mode);
+                }
+            }
+            self.intra_chroma_pred_mode()?;
+            self.coded_block_pattern(true)?
+        } else {
+            let mb = self.mb_mut();
+            mb.kind = MbKind::I16x16;
+            modes[0] = ((intra_type - 1) % 4) as u8;
+            self.intra_chroma_pred_mode()?;
+            let chroma = ((intra_type - 1) / 4 % 3) as u8;
+            let luma = if intra_type >= 13 { 15 } else { 0 };
+            chroma << 4 | luma
+        };
+        self.mb_mut().cbp = cbp;
+
+        let residual = self.residual()?;
+        self.reconstruct_intra(&modes, &residual);
+        self.add_chroma_residual(&residual);
+        Ok(())
+    }
+
+    /// Intra4x4PredMode or Intra8x8PredMode predicted from the blocks left and above the
+    /// block at (`x`, `y`) (8.3.1.1 and 8.3.2.1)
+    fn predicted_intra_mode(&self, x: usize, y: usize) -> u8 {
+        let mode = |x: i32, y: i32| {
+            let (mb_addr, index) = self.locate(x, y)?;
+            if !self.intra_usable(mb_addr) {
+                return None;
+            }
+            Some(match self.state.mbs[mb_addr].kind {
+                MbKind::I4x4 | MbKind::I8x8 => self.state.intra_modes[index],
+                _ => 2,
+            })
+        };
+        let (x, y) = (x as i32, y as i32);
+        match (mode(x - 1, y), mode(x, y - 1)) {
+            (Some(a), Some(b)) => a.min(b),
+            _ => 2,
+        }
+    }
+
+    fn transform_size_8x8_flag(&mut self) -> Result<bool> {
+        match &mut self.entropy {
+            Entropy::Cavlc(reader) => reader.read_flag(),
+            Entropy::Cabac(_) => {
+                let ctx_inc = self.neighbour_count(|mb| mb.transform_8x8);
+                self.cabac().transform_size_8x8_flag(ctx_inc)
+            }
+        }
+    }
+
+    fn intra_chroma_pred_mode(&mut self) -> Result<()> {
+        let mode = match &mut self.entropy {
+            Entropy::Cavlc(reader) => reader.read_ue()?,
+            Entropy::Cabac(_) => {
+                let ctx_inc = self.neighbour_count(|mb| mb.chroma_pred_mode != 0);
+                self.cabac().intra_chroma_pred_mode(ctx_inc)? as u32
+            }
+        };
+        if mode > 3 {
+            return Err(anyhow!("Invalid intra_chroma_pred_mode {}", mode));
+        }
+        self.mb_mut().chroma_pred_mode = mode as u8;
+        Ok(())
+    }
+
+    fn coded_block_pattern(&mut self, intra: bool) -> Result<u8> {
+        if let Entropy::Cavlc(reader) = &mut self.entropy {
+            let cbp = cavlc::coded_block_pattern(reader, intra)?;
+            // The tables hold CodedBlockPatternChroma * 16 + CodedBlockPatternLuma
+            return Ok(cbp);
+        }
+
+        // 9.3.3.1.1.4, the bits of the 8x8 blocks left and above
+        let mut luma = 0u8;
+        for b8 in 0..4 {
+            let (x, y) = ((b8 % 2) * 2, (b8 / 2) * 2);
+            let condition = |x: i32, y: i32| -> usize {
+                if (0..4).contains(&x) && (0..4).contains(&y) {
+                    let b8 = (y / 2 * 2 + x / 2) as usize;
+                    return ((luma >> b8) & 1 == 0) as usize;
+                }
+                match self.neighbour_mb(x, y) {
+                    Some(mb_addr) => {
+                        let b8 = (y.rem_euclid(4) / 2 * 2 + x.rem_euclid(4) / 2) as usize;
+                        ((self.state.mbs[mb_addr].cbp >> b8) & 1 == 0) as usize
+                    }
+                    None => 0,
+                }
+            };
+            let ctx_inc = condition(x - 1, y) + 2 * condition(x, y - 1);
+            luma |= (self.cabac().coded_block_pattern_luma_bit(ctx_inc)? as u8) << b8;
+        }
+
+        let chroma_condition = |bin: usize| -> usize {
+            [(-1, 0), (0, -1)]
+                .into_iter()
+                .enumerate()
+                .map(|(i, (x, y))| {
+                    let set = self.neighbour_mb(x, y).is_some_and(|mb_addr| {
+                        let chroma = self.state.mbs[mb_addr].cbp >> 4;
+                        if bin == 0 {
+                            chroma != 0
+                        } else {
+                            chroma == 2
+                        }
+                    });
+                    (set as usize) << i
+                })
+                .sum()
+        };
+        let ctx_inc = [chroma_condition(0), chroma_condition(1)];
+        let chroma = self.cabac().coded_block_pattern_chroma(ctx_inc)?;
+        Ok(chroma << 4 | luma)
+    }
+
+    fn decode_inter(&mut self, mb_type: u32) -> Result<()> {
+        let header = self.context.header;
+        let b_slice = header.slice_type == SliceType::B;
+        let direct_8x8_inference = self.context.sps.direct_8x8_inference_flag;
+        self.mb_mut().kind = MbKind::Inter;
+
+        // Whether the macroblock may use the 8x8 transform
+        let mut no_sub_8x8 = true;
+        if b_slice && mb_type == 0 {
+            let mb = self.mb_mut();
+            mb.kind = MbKind::BDirect;
+            mb.direct = 0xf;
+            for sub in 0..4 {
+                self.direct_motion(sub)?;
+            }
+            no_sub_8x8 = direct_8x8_inference;
+        } else if (!b_slice && mb_type >= 3) || (b_slice && mb_type == 22) {
+            no_sub_8x8 = self.sub_mb_pred(!b_slice && mb_type == 4)?;
+        } else {
+            let (shape, pred) = if b_slice {
+                B_PARTITIONS[mb_type as usize]
+            } else {
+                let shape = [Shape::Whole, Shape::Wide, Shape::Tall][mb_type as usize];
+                (shape, [PRED_L0, PRED_L0])
+            };
+            self.mb_pred(shape, pred)?;
+        }
+
+        let cbp = self.coded_block_pattern(false)?;
+        self.mb_mut().cbp = cbp;
+        if cbp & 15 != 0 && self.context.pps.transform_8x8_mode_flag && no_sub_8x8 {
+            let transform_8x8 = self.transform_size_8x8_flag()?;
+            self.mb_mut().transform_8x8 = transform_8x8;
+        }
+
+        let residual = self.residual()?;
+        self.predict_inter()?;
+        self.add_luma_residual(&residual, false);
+        self.add_chroma_residual(&residual);
+        Ok(())
+    }
+
+    /// mb_pred() of inter macroblocks with one or two partitions
+    fn mb_pred(&mut self, shape: Shape, pred: [u8; 2]) -> Result<()> {
+        let partitions: &[Partition] = match shape {
+            Shape::Whole => &[Partition {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 4,
+            }],
+            Shape::Wide => &[
+                Partition {
+                    x: 0,
+                    y: 0,
+                    width: 4,
+                    height: 2,
+                },
+                Partition {
+                    x: 0,
+                    y: 2,
+                    width: 4,
+                    height: 2,
+                },
+            ],
+            Shape::Tall => &[
+                Partition {
+                    x: 0,
+                    y: 0,
+                    width: 2,
+                    height: 4,
+                },
+                Partition {
+                    x: 2,
+                    y: 0,
+                    width: 2,
+                    height: 4,
+                },
+            ],
+        };
+
+        for list in 0..2 {
+            for (partition, pred) in partitions.iter().zip(pred) {
+                if pred & (1 << list) != 0 {
+                    let ref_idx = self.ref_idx(list, partition)?;
+                    self.set_ref_idx(list, partition, ref_idx)?;
+                }
+            }
+        }
+        for list in 0..2 {
+            let mut done = 0;
+            for (partition, pred) in partitions.iter().zip(pred) {
+                if pred & (1 << list) != 0 {
+                    self.motion_vector(list, partition, shape, done)?;
+                }
+                done |= partition.mask();
+            }
+        }
+        Ok(())
+    }
+
+    /// sub_mb_pred() of P_8x8, P_8x8ref0 and B_8x8. Returns whether no sub-macroblock
+    /// partition is smaller than 8x8.
+    fn sub_mb_pred(&mut self, ref0: bool) -> Result<bool> {
+        let slice_type = self.context.header.slice_type;
+        let mut sub_mb_types = [0u32; 4];
+        for sub_mb_type in &mut sub_mb_types {
+            *sub_mb_type = match &mut self.entropy {
+                Entropy::Cavlc(reader) => reader.read_ue()?,
+                Entropy::Cabac(cabac) => cabac.sub_mb_type(slice_type)?,
+            };
+        }
+        let sub_partitions = sub_mb_types.map(|sub_mb_type| {
+            let table: &[(usize, usize, u8)] = if slice_type == SliceType::B {
+                &B_SUB_PARTITIONS
+            } else {
+                &P_SUB_PARTITIONS
+            };
+            table.get(sub_mb_type as usize).copied()
+        });
+        if sub_partitions.contains(&None) {
+            return Err(anyhow!("Invalid sub_mb_type in {:?}", sub_mb_types));
+        }
+        let sub_partitions = sub_partitions.map(Option::unwrap);
+
+        let direct = |sub: usize| slice_type == SliceType::B && sub_mb_types[sub] == 0;
+        let mut no_sub_8x8 = true;
+        for (sub, &(width, height, _)) in sub_partitions.iter().enumerate() {
+            if direct(sub) {
+                self.mb_mut().direct |= 1 << sub;
+                no_sub_8x8 &= self.context.sps.direct_8x8_inference_flag;
+            } else {
+                no_sub_8x8 &= width == 2 && height == 2;
+            }
+        }
+        for sub in (0..4).filter(|&sub| direct(sub)) {
+            self.direct_motion(sub)?;
+        }
+
+        let sub_rect = |sub: usize| Partition {
+            x: (sub % 2) * 2,
+            y: (sub / 2) * 2,
+            width: 2,
+            height: 2,
+        };
+        for list in 0..2 {
+            for sub in (0..4).filter(|&sub| !direct(sub)) {
+                if sub_partitions[sub].2 & (1 << list) != 0 {
+                    let ref_idx = if ref0 {
+                        0
+                    } else {
+                        self.ref_idx(list, &sub_rect(sub))?
+                    };
+                    self.set_ref_idx(list, &sub_rect(sub), ref_idx)?;
+                }
+            }
+        }
+
+        for list in 0..2 {
+            // Direct sub-macroblocks count as decoded for the prediction of later ones
+            let mut done = 0;
+            for (sub, &(width, height, pred)) in sub_partitions.iter().enumerate() {
+                let rect = sub_rect(sub);
+                if !direct(sub) && pred & (1 << list) != 0 {
+                    let mut sub_done = done;
+                    for index in 0..4 / (width * height) {
+                        let columns = 2 / width;
+                        let partition = Partition {
+                            x: rect.x + index % columns * width,
+                            y: rect.y + index / columns * height,
+                            width,
+                            height,
+                        };
+                        self.motion_vector(list, &partition, Shape::Whole, sub_done)?;
+                        sub_done |= partition.mask();
+                    }
+                }
+                done |= rect.mask();
+            }
+        }
+        Ok(no_sub_8x8)
+    }
+
+    fn ref_idx(&mut self, list: usize, partition: &Partition) -> Result<u32> {
+        let header = self.context.header;
+        let num_ref_idx_active_minus1 = [
+            header.num_ref_idx_l0_active_minus1,
+            header.num_ref_idx_l1_active_minus1,
+        ][list];
+        if num_ref_idx_active_minus1 == 0 {
+            return Ok(0);
+        }
+        let ref_idx = match &mut self.entropy {
+            Entropy::Cavlc(reader) => reader.read_te(num_ref_idx_active_minus1)?,
+            Entropy::Cabac(_) => {
+                // 9.3.3.1.1.6, direct predicted neighbours count as using index 0
+                let condition = |x: i32, y: i32| -> usize {
+                    let Some((mb_addr, index)) = self.locate(x, y) else {
+                        return 0;
+                    };
+                    let mb = &self.state.mbs[mb_addr];
+                    let b8 = (y.rem_euclid(4) / 2 * 2 + x.rem_euclid(4) / 2) as usize;
+                    let direct = mb.direct & (1 << b8) != 0;
+                    (!mb.kind.is_skip() && !direct && self.state.motion[list][index].ref_idx > 0)
+                        as usize
+                };
+                let (x, y) = (partition.x as i32, partition.y as i32);
+                let ctx_inc = condition(x - 1, y) + 2 * condition(x, y - 1);
+                self.cabac().ref_idx(ctx_inc)?
+            }
+        };
+        if ref_idx > num_ref_idx_active_minus1 {
+            return Err(anyhow!("ref_idx_l{} {} out of range", list, ref_idx));
+        }
+        Ok(ref_idx)
+    }
+
+    fn set_ref_idx(&mut self, list: usize, partition: &Partition, ref_idx: u32) -> Result<()> {
+        let reference = self.reference(list, ref_idx as i8)?;
+        let motion = Motion {
+            mv: [0, 0],
+            ref_idx: ref_idx as i8,
+            ref_id: reference.picture.id,
+        };
+        self.fill_motion(list, partition, motion);
+        Ok(())
+    }
+
+    pub(super) fn reference(&self, list: usize, ref_idx: i8) -> Result<&RefPicture> {
+        self.context.ref_lists[list]
+            .get(ref_idx as usize)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| anyhow!("No reference picture for ref_idx_l{} {}", list, ref_idx))
+    }
+
+    pub(super) fn fill_motion(&mut self, list: usize, partition: &Partition, motion: Motion) {
+        for y in partition.y..partition.y + partition.height {
+            let start = self.block_index(partition.x, y);
+            self.state.motion[list][start..start + partition.width].fill(motion);
+        }
+    }
+
+    /// Parses mvd_lX of the partition and sets its motion vector to it plus the prediction
+    fn motion_vector(
+        &mut self,
+        list: usize,
+        partition: &Partition,
+        shape: Shape,
+        done: u16,
+    ) -> Result<()> {
+        let mut mvd = [0i32; 2];
+        match &mut self.entropy {
+            Entropy::Cavlc(reader) => {
+                mvd = [reader.read_se()?, reader.read_se()?];
+            }
+            Entropy::Cabac(_) => {
+                for (component, mvd) in mvd.iter_mut().enumerate() {
+                    let (x, y) = (partition.x as i32, partition.y as i32);
+                    let abs_mvd = |x: i32, y: i32| {
+                        self.locate(x, y).map_or(0, |(_, index)| {
+                            self.state.mvd[list][index][component] as u32
+                        })
+                    };
+                    let sum = abs_mvd(x - 1, y) + abs_mvd(x, y - 1);
+                    *mvd = self.cabac().mvd(component, sum)?;
+                }
+            }
+        }
+
+        let index = self.block_index(partition.x, partition.y);
+        let mut motion = self.state.motion[list][index];
+        let mvp = self.predict_mv(list, partition, motion.ref_idx, shape, done);
+        let mv = [mvp[0] + mvd[0], mvp[1] + mvd[1]];
+        motion.mv = mv.map(|component| component.clamp(i16::MIN as i32, i16::MAX as i32) as i16);
+        self.fill_motion(list, partition, motion);
+
+        let abs_mvd = mvd.map(|component| component.unsigned_abs().min(255) as u8);
+        for y in partition.y..partition.y + partition.height {
+            let start = self.block_index(partition.x, y);
+            self.state.mvd[list][start..start + partition.width].fill(abs_mvd);
+        }
+        Ok(())
+    }
+
+    /// residual() (7.3.5.3) preceded by mb_qp_delta, which also updates the macroblock's
+    /// QP and the per-block state used by later blocks' contexts
+    fn residual(&mut self) -> Result<Residual> {
+        let mut residual = Residual::new();
+        let mb = *self.mb();
+        let intra_16x16 = mb.kind == MbKind::I16x16;
+        let (cbp_luma, cbp_chroma) = (mb.cbp & 15, mb.cbp >> 4);
+        if cbp_luma == 0 && cbp_chroma == 0 && !intra_16x16 {
+            self.previous_qp_delta_nonzero = false;
+            return Ok(residual);
+        }
+
+        let mb_qp_delta = match &mut self.entropy {
+            Entropy::Cavlc(reader) => reader.read_se()?,
+            Entropy::Cabac(_) => {
+                let previous_nonzero = self.previous_qp_delta_nonzero;
+                self.cabac().mb_qp_delta(previous_nonzero)?
+            }
+        };
+        if !(-26..=25).contains(&mb_qp_delta) {
+            return Err(anyhow!("mb_qp_delta {} out of range", mb_qp_delta));
+        }
+        self.previous_qp_delta_nonzero = mb_qp_delta != 0;
+        self.qp = (self.qp + mb_qp_delta + 52) % 52;
+        let chroma_qp = self.chroma_qp(self.qp);
+        let qp = self.qp;
+        let mb = self.mb_mut();
+        mb.qp = qp;
+        mb.chroma_qp = chroma_qp;
+
+        let mut nonzero = 0u16;
+        if intra_16x16 {
+            let mut scan = [0; 16];
+            let coded = self.luma_block(BlockCategory::LumaDc, 0, 0, &mut scan)?;
+            self.mb_mut().coded_dc |= (coded != 0) as u8;
+            for (&position, level) in ZIGZAG_4X4.iter().zip(scan) {
+                residual.luma_dc[position as usize] = level;
+            }
+        }
+
+        let transform_8x8 = self.mb().transform_8x8;
+        for b8 in 0..4 {
+            if cbp_luma & (1 << b8) == 0 {
+                continue;
+            }
+            let (x8, y8) = ((b8 % 2) * 2, (b8 / 2) * 2);
+            if transform_8x8 {
+                let mut scan = [0; 64];
+                if let Entropy::Cabac(cabac) = &mut self.entropy {
+                    cabac.residual_block(BlockCategory::Luma8x8, &mut scan)?;
+                    for y in y8..y8 + 2 {
+                        let start = self.block_index(x8, y);
+                        self.state.coded_luma[start..start + 2].fill(1);
+                    }
+                } else {
+                    // Four interleaved 4x4 blocks, 7.3.5.3.2
+                    for b4 in 0..4 {
+                        let block = b8 * 4 + b4;
+                        let mut scan_4x4 = [0; 16];
+                        self.luma_block(
+                            BlockCategory::Luma4x4,
+                            BLOCK_X[block],
+                            BLOCK_Y[block],
+                            &mut scan_4x4,
+                        )?;
+                        for (k, level) in scan_4x4.into_iter().enumerate() {
+                            scan[4 * k + b4] = level;
+                        }
+                    }
+                }
+                for (&position, level) in ZIGZAG_8X8.iter().zip(scan) {
+                    residual.luma_8x8[b8][position as usize] = level;
+                }
+                if scan.iter().any(|&level| level != 0) {
+                    nonzero |= 0x33 << (y8 * 4 + x8);
+                }
+            } else {
+                for b4 in 0..4 {
+                    let block = b8 * 4 + b4;
+                    let (x, y) = (BLOCK_X[block], BLOCK_Y[block]);
+                    let mut scan = [0; 16];
+                    let coded = if intra_16x16 {
+                        self.luma_block(BlockCategory::LumaAc, x, y, &mut scan[1..])?
+                    } else {
+                        self.luma_block(BlockCategory::Luma4x4, x, y, &mut scan)?
+                    };
+                    if coded != 0 {
+                        nonzero |= 1 << (y * 4 + x);
+                        for (&position, level) in ZIGZAG_4X4.iter().zip(scan) {
+                            residual.luma[block][position as usize] = level;
+                        }
+                    }
+                }
+            }
+        }
+        self.mb_mut().nonzero = nonzero;
+
+        if cbp_chroma != 0 {
+            for plane in 0..2 {
+                let coded = self.chroma_block(plane, None, &mut residual.chroma_dc[plane])?;
+                self.mb_mut().coded_dc |= ((coded != 0) as u8) << (plane + 1);
+            }
+        }
+        if cbp_chroma == 2 {
+            for plane in 0..2 {
+                for block in 0..4 {
+                    let mut scan = [0; 16];
+                    let coded = self.chroma_block(plane, Some(block), &mut scan[1..])?;
+                    if coded != 0 {
+                        for (&position, level) in ZIGZAG_4X4.iter().zip(scan) {
+                            residual.chroma_ac[plane][block][position as usize] = level;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(residual)
+    }
+
+    /// Parses a luma block of the 4x4 block at (`x`, `y`) into `coefficients` in scanning
+    /// order and records its TotalCoeff or coded_block_flag. Returns that value.
+    fn luma_block(
+        &mut self,
+        category: BlockCategory,
+        x: usize,
+        y: usize,
+        coefficients: &mut [i32],
+    ) -> Result<u8> {
+        let intra = self.mb().kind.is_intra();
+        // CAVLC predicts the DC block's nC from the 4x4 blocks like the first AC block
+        let dc_flag =
+            category == BlockCategory::LumaDc && matches!(self.entropy, Entropy::Cabac(_));
+        let (x, y) = (x as i32, y as i32);
+        let neighbours = [(x - 1, y), (x, y - 1)].map(|(x, y)| {
+            let (mb_addr, index) = self.locate(x, y)?;
+            Some(if dc_flag {
+                self.state.mbs[mb_addr].coded_dc & 1
+            } else {
+                self.state.coded_luma[index]
+            })
+        });
+        let coded = self.block(category, neighbours, intra, coefficients)?;
+        if category != BlockCategory::LumaDc {
+            let index = self.block_index(x as usize, y as usize);
+            self.state.coded_luma[index] = coded;
+        }
+        Ok(coded)
+    }
+
+    /// Parses the DC block of a chroma plane, or with `block` one of its AC blocks.
+    fn chroma_block(
+        &mut self,
+        plane: usize,
+        block: Option<usize>,
+        coefficients: &mut [i32],
+    ) -> Result<u8> {
+        let intra = self.mb().kind.is_intra();
+        let Some(block) = block else {
+            let neighbours = [(-1, 0), (0, -1)].map(|(x, y)| {
+                let mb_addr = self.neighbour_mb(x, y)?;
+                Some((self.state.mbs[mb_addr].coded_dc >> (plane + 1)) & 1)
+            });
+            return self.block(BlockCategory::ChromaDc, neighbours, intra, coefficients);
+        };
+
+        let (x, y) = ((block % 2) as i32, (block / 2) as i32);
+        let neighbours = [(x - 1, y), (x, y - 1)].map(|(x, y)| {
+            // Chroma blocks of 4:2:0 cover two luma blocks in each direction
+            let (mb_addr, _) = self.locate(x * 2, y * 2)?;
+            let mb_x = mb_addr % self.state.width_in_mbs;
+            let mb_y = mb_addr / self.state.width_in_mbs;
+            let stride = self.state.width_in_mbs * 2;
+            let index = (mb_y * 2 + y.rem_euclid(2) as usize) * stride
+                + mb_x * 2
+                + x.rem_euclid(2) as usize;
+            Some(self.state.coded_chroma[plane][index])
+        });
+        let coded = self.block(BlockCategory::ChromaAc, neighbours, intra, coefficients)?;
+        let index = self.chroma_block_index(x as usize, y as usize);
+        self.state.coded_chroma[plane][index] = coded;
+        Ok(coded)
+    }
+
+    /// Parses one block given the values recorded for its neighbours A and B, `None` when
+    /// not available: nC for CAVLC (9.2.1) or the coded_block_flag context (9.3.3.1.1.9).
+    fn block(
+        &mut self,
+        category: BlockCategory,
+        neighbours: [Option<u8>; 2],
+        intra: bool,
+        coefficients: &mut [i32],
+    ) -> Result<u8> {
+        match &mut self.entropy {
+            Entropy::Cavlc(reader) => {
+                let n_c = match (category, neighbours) {
+                    (BlockCategory::ChromaDc, _) => -1,
+                    (_, [Some(a), Some(b)]) => (a as i32 + b as i32 + 1) >> 1,
+                    (_, [Some(n), None]) | (_, [None, Some(n)]) => n as i32,
+                    (_, [None, None]) => 0,
+                };
+                cavlc::residual_block(reader, n_c, coefficients)
+            }
+            Entropy::Cabac(cabac) => {
+                let [a, b] = neighbours.map(|n| n.map_or(intra as usize, |n| (n != 0) as usize));
+                if !cabac.coded_block_flag(category, a + 2 * b)? {
+                    return Ok(0);
+                }
+                cabac.residual_block(category, coefficients)?;
+                Ok(1)
+            }
+        }
+    }
+
+    /// Which neighbouring samples of the luma block at (`x`, `y`) with `size` 4x4 blocks
+    /// on a side can be used for intra prediction
+    fn block_neighbours(&self, x: usize, y: usize, size: usize) -> Neighbours {
+        let current = BLOCK_INDEX[y][x];
+        let available = |x: i32, y: i32| {
+            if (0..4).contains(&x) && (0..4).contains(&y) {
+                return BLOCK_INDEX[y as usize][x as usize] < current;
+            }
+            self.neighbour_mb(x, y)
+                .is_some_and(|mb_addr| self.intra_usable(mb_addr))
+        };
+        let (x, y, size) = (x as i32, y as i32, size as i32);
+        Neighbours {
+            left: available(x - 1, y),
+            top: available(x, y - 1),
+            top_right: available(x + size, y - 1),
+            top_left: available(x - 1, y - 1),
+        }
+    }
+
+    fn mb_neighbours(&self) -> Neighbours {
+        let available = |x: i32, y: i32| {
+            self.neighbour_mb(x, y)
+                .is_some_and(|mb_addr| self.intra_usable(mb_addr))
+        };
+        Neighbours {
+            left: available(-1, 0),
+            top: available(0, -1),
+            top_right: false,
+            top_left: available(-1, -1),
+        }
+    }
+
+    fn reconstruct_intra(&mut self, modes: &[u8; 16], residual: &Residual) {
+        let mb = *self.mb();
+        let level_scale = self.context.level_scale;
+        let (mb_x, mb_y) = (self.mb_x * 16, self.mb_y * 16);
+        match mb.kind {
+            MbKind::I4x4 => {
+                for (block, &mode) in modes.iter().enumerate() {
+                    let (x, y) = (BLOCK_X[block], BLOCK_Y[block]);
+                    let available = self.block_neighbours(x, y, 1);
+                    let plane = &mut self.state.planes[0];
+                    let (px, py) = (mb_x + x * 4, mb_y + y * 4);
+                    intra::predict_4x4(&mut plane.data, plane.width, px, py, mode, available);
+                    if mb.nonzero & (1 << (y * 4 + x)) != 0 {
+                        let mut block = residual.luma[block];
+                        transform::dequantize_4x4(
+                            &mut block,
+                            &level_scale.scale_4x4[0],
+                            mb.qp,
+                            true,
+                        );
+                        transform::add_idct_4x4(
+                            &block,
+                            &mut plane.data[py * plane.width + px..],
+                            plane.width,
+                        );
+                    }
+                }
+            }
+            MbKind::I8x8 => {
+                for (b8, &mode) in modes[..4].iter().enumerate() {
+                    let (x, y) = ((b8 % 2) * 2, (b8 / 2) * 2);
+                    let available = self.block_neighbours(x, y, 2);
+                    let plane = &mut self.state.planes[0];
+                    let (px, py) = (mb_x + x * 4, mb_y + y * 4);
+                    intra::predict_8x8(&mut plane.data, plane.width, px, py, mode, available);
+                    if mb.nonzero & (1 << (y * 4 + x)) != 0 {
+                        let mut block = residual.luma_8x8[b8];
+                        transform::dequantize_8x8(&mut block, &level_scale.scale_8x8[0], mb.qp);
+                        transform::add_idct_8x8(
+                            &block,
+                            &mut plane.data[py * plane.width + px..],
+                            plane.width,
+                        );
+                    }
+                }
+            }
+            _ => {
+                let available = self.mb_neighbours();
+                let plane = &mut self.state.planes[0];
+                intra::predict_16x16(
+                    &mut plane.data,
+                    plane.width,
+                    mb_x,
+                    mb_y,
+                    modes[0],
+                    available,
+                );
+                self.add_luma_residual(residual, true);
+            }
+        }
+
+        let available = self.mb_neighbours();
+        for plane in &mut self.state.planes[1..] {
+            let (x, y) = (mb_x / 2, mb_y / 2);
+            intra::predict_chroma(
+                &mut plane.data,
+                plane.width,
+                x,
+                y,
+                mb.chroma_pred_mode,
+                available,
+            );
+        }
+    }
+
+    /// Adds the luma residual of inter and Intra_16x16 macroblocks to the prediction
+    fn add_luma_residual(&mut self, residual: &Residual, intra: bool) {
+        let mb = *self.mb();
+        let level_scale = self.context.level_scale;
+        let (mb_x, mb_y) = (self.mb_x * 16, self.mb_y * 16);
+        let plane = &mut self.state.planes[0];
+        let stride = plane.width;
+        let list = if intra { 0 } else { 3 };
+
+        if mb.kind == MbKind::I16x16 {
+            let mut dc = residual.luma_dc;
+            if mb.coded_dc & 1 != 0 {
+                transform::luma_dc_transform(&mut dc, &level_scale.scale_4x4[0], mb.qp);
+            }
+            for (index, block) in residual.luma.iter().enumerate() {
+                let (x, y) = (BLOCK_X[index], BLOCK_Y[index]);
+                let mut block = *block;
+                transform::dequantize_4x4(&mut block, &level_scale.scale_4x4[0], mb.qp, false);
+                block[0] = dc[y * 4 + x];
+                if block.iter().any(|&c| c != 0) {
+                    let start = (mb_y + y * 4) * stride + mb_x + x * 4;
+                    transform::add_idct_4x4(&block, &mut plane.data[start..], stride);
+                }
+            }
+        } else if mb.transform_8x8 {
+            for (b8, block) in residual.luma_8x8.iter().enumerate() {
+                let (x, y) = ((b8 % 2) * 2, (b8 / 2) * 2);
+                if mb.nonzero & (1 << (y * 4 + x)) != 0 {
+                    let mut block = *block;
+                    transform::dequantize_8x8(&mut block, &level_scale.scale_8x8[1], mb.qp);
+                    let start = (mb_y + y * 4) * stride + mb_x + x * 4;
+                    transform::add_idct_8x8(&block, &mut plane.data[start..], stride);
+                }
+            }
+        } else {
+            for (index, block) in residual.luma.iter().enumerate() {
+                let (x, y) = (BLOCK_X[index], BLOCK_Y[index]);
+                if mb.nonzero & (1 << (y * 4 + x)) != 0 {
+                    let mut block = *block;
+                    transform::dequantize_4x4(
+                        &mut block,
+                        &level_scale.scale_4x4[list],
+                        mb.qp,
+                        true,
+                    );
+                    let start = (mb_y + y * 4) * stride + mb_x + x * 4;
+                    transform::add_idct_4x4(&block, &mut plane.data[start..], stride);
+                }
+            }
+        }
+    }
+
+    fn add_chroma_residual(&mut self, residual: &Residual) {
+        let mb = *self.mb();
+        if mb.cbp >> 4 == 0 {
+            return;
+        }
+        let level_scale = self.context.level_scale;
+        let list_offset = if mb.kind.is_intra() { 1 } else { 4 };
+        let (mb_x, mb_y) = (self.mb_x * 8, self.mb_y * 8);
+        for c in 0..2 {
+            let scale = &level_scale.scale_4x4[list_offset + c];
+            let qp = mb.chroma_qp[c];
+            let mut dc = residual.chroma_dc[c];
+            transform::chroma_dc_transform(&mut dc, scale, qp);
+
+            let plane = &mut self.state.planes[1 + c];
+            let stride = plane.width;
+            for (index, block) in residual.chroma_ac[c].iter().enumerate() {
+                let mut block = *block;
+                transform::dequantize_4x4(&mut block, scale, qp, false);
+                block[0] = dc[index];
+                if block.iter().any(|&coefficient| coefficient != 0) {
+                    let start = (mb_y + index / 2 * 4) * stride + mb_x + index % 2 * 4;
+                    transform::add_idct_4x4(&block, &mut plane.data[start..], stride);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! A CPU implementation of H.264 decoding for machines without a video decode queue.
+//!
+//! Covers progressive 8-bit 4:2:0 streams of the Constrained Baseline, Main and High
+//! profiles: CAVLC and CABAC, intra and inter prediction including B slices and weighted
+//! prediction, 8x8 transforms and the deblocking filter. Interlaced coding, slice groups
+//! and SP/SI slices are rejected.
+//!
+//! [`SoftwareBackend`] only decodes the pictures a [`Decoder`](crate::decoder::Decoder)
+//! hands it, reference picture marking, POCs and output order are the same as with
+//! Vulkan Video.
+
+mod cabac;
+mod cavlc;
+mod deblock;
+mod inter;
+mod intra;
+mod macroblock;
+mod motion;
+mod picture;
+mod transform;
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::StdVideoDecodeH264ReferenceInfo;
+
+use crate::bitreader::nal_to_rbsp;
+use crate::codec::{self, Codec};
+use crate::decoder::backend::{
+    BackendCapabilities, BeginCodingInfo, DecodeBackend, DecodeInfo, PictureInfo, ReferenceInfo,
+    SessionInfo,
+};
+use crate::h264::dpb::ReferenceFrame;
+use crate::h264::pps::Pps;
+use crate::h264::slice::{parse_slices, RefPicListModification, SliceHeader, SliceType};
+use crate::h264::sps::Sps;
+use crate::h264::{NalUnits, ParameterSets};
+use crate::timestamp::Timestamp;
+use crate::yuv::Nv12Frame;
+use macroblock::{PictureState, RefPicture, SliceContext};
+use picture::Picture;
+use transform::LevelScale;
+
+/// Up to 16 reference frames
+const MAX_REFERENCE_FRAMES: u32 = 16;
+
+/// The references and up to 16 frames waiting for output next to the one being decoded.
+/// Slots are only filled as the stream needs them.
+const DPB_SLOTS: u32 = 2 * MAX_REFERENCE_FRAMES + 1;
+
+/// The largest frames worth decoding on the CPU, those of Level 5.1 (Table A-1)
+pub const MAX_CODED_EXTENT: (u32, u32) = (4096, 2304);
+
+/// A decoded frame in host memory.
+#[derive(Clone, Debug)]
+pub struct SoftwareFrame {
+    /// Coded size, see [`StreamInfo::crop_rect`](crate::codec::StreamInfo::crop_rect) for
+    /// the visible area
+    pub picture: Nv12Frame,
+    pub pts: Timestamp,
+}
+
+/// Decodes H.264 pictures on the CPU, for a [`Decoder`](crate::decoder::Decoder) on
+/// machines without a video decode queue.
+#[derive(Default)]
+pub struct SoftwareBackend {
+    parameter_sets: ParameterSets,
+    /// Decoded pictures by DPB slot
+    pictures: Vec<Option<Arc<Picture>>>,
+    /// The picture decoded between begin and end of coding, with its slot
+    current: Option<(usize, Picture)>,
+    /// An IDR picture has been decoded since the session was reset
+    started: bool,
+    next_id: u32,
+}
+
+impl DecodeBackend for SoftwareBackend {
+    type Frame = SoftwareFrame;
+
+    fn capabilities(
+        &mut self,
+        parameter_sets: &codec::ParameterSets,
+    ) -> Result<BackendCapabilities> {
+        if parameter_sets.codec() != Codec::H264 {
+            return Err(anyhow!(
+                "The software decoder only decodes H.264, not {:?}",
+                parameter_sets.codec()
+            ));
+        }
+        Ok(BackendCapabilities {
+            max_dpb_slots: DPB_SLOTS,
+            max_active_reference_pictures: MAX_REFERENCE_FRAMES,
+        })
+    }
+
+    fn create_session(
+        &mut self,
+        parameter_sets: &codec::ParameterSets,
+        info: &SessionInfo,
+    ) -> Result<()> {
+        self.capabilities(parameter_sets)?;
+        let (width, height) = info.coded_extent;
+        if width > MAX_CODED_EXTENT.0 || height > MAX_CODED_EXTENT.1 {
+            return Err(anyhow!(
+                "Coded extent {}x{} exceeds the {}x{} of the software decoder",
+                width,
+                height,
+                MAX_CODED_EXTENT.0,
+                MAX_CODED_EXTENT.1
+            ));
+        }
+        self.pictures = vec![None; info.dpb_slots as usize];
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, parameter_sets: &codec::ParameterSets) -> Result<()> {
+        match parameter_sets {
+            codec::ParameterSets::H264(parameter_sets) => {
+                self.parameter_sets = parameter_sets.clone();
+                Ok(())
+            }
+            _ => Err(anyhow!("Not H.264 parameter sets")),
+        }
+    }
+
+    fn begin_coding(&mut self, info: &BeginCodingInfo) -> Result<()> {
+        if info.reset {
+            self.pictures.fill(None);
+            self.started = false;
+        }
+        self.current = None;
+        Ok(())
+    }
+
+    fn decode(&mut self, info: &DecodeInfo) -> Result<()> {
+        let PictureInfo::H264 {
+            std_picture_info, ..
+        } = info.picture
+        else {
+            return Err(anyhow!("Not an H.264 picture"));
+        };
+        let slices = parse_slices(info.bitstream, &mut self.parameter_sets)?;
+        let first = slices
+            .first()
+            .ok_or_else(|| anyhow!("Picture without slices"))?;
+        let (pps, sps) = self
+            .parameter_sets
+            .active(first.header.pic_parameter_set_id)?;
+        check_supported(pps, sps, slices.iter().map(|slice| slice.header.slice_type))?;
+
+        if first.header.idr_pic_flag {
+            self.started = true;
+        } else if !self.started {
+            return Err(anyhow!("The stream does not start with an IDR picture"));
+        }
+
+        let references = info
+            .reference_slots
+            .iter()
+            .map(|reference| match reference.info {
+                ReferenceInfo::H264(std_reference_info) => Ok(reference_frame(
+                    reference.slot,
+                    &std_reference_info,
+                    first.header.frame_num,
+                    sps,
+                )),
+                _ => Err(anyhow!("Not an H.264 reference picture")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let [top_field_order_cnt, bottom_field_order_cnt] = std_picture_info.PicOrderCnt;
+        let pic_order_cnt = top_field_order_cnt.min(bottom_field_order_cnt);
+
+        let mut state = PictureState::new(
+            sps.pic_width_in_mbs() as usize,
+            sps.frame_height_in_mbs() as usize,
+        );
+        for slice in &slices {
+            let header = &slice.header;
+            let (pps, sps) = self.parameter_sets.active(header.pic_parameter_set_id)?;
+            let nal = NalUnits::new(&info.bitstream[slice.offset..slice.offset + slice.size])
+                .next()
+                .ok_or_else(|| anyhow!("Slice NAL unit missing"))?;
+            let level_scale = LevelScale::new(&pps.scaling_lists);
+            let context = SliceContext {
+                header,
+                sps,
+                pps,
+                level_scale: &level_scale,
+                pic_order_cnt,
+                ref_lists: self.ref_pic_lists(header, sps, pic_order_cnt, &references)?,
+            };
+            macroblock::decode_slice(&mut state, &context, &nal_to_rbsp(&nal.data[1..]))?;
+        }
+        deblock::deblock_picture(&mut state);
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.current = Some((info.setup_slot.slot, state.into_picture(id)));
+        Ok(())
+    }
+
+    fn end_coding(&mut self) -> Result<()> {
+        let (slot, picture) = self
+            .current
+            .take()
+            .ok_or_else(|| anyhow!("No picture decoded"))?;
+        *self
+            .pictures
+            .get_mut(slot)
+            .ok_or_else(|| anyhow!("Slot {} out of the DPB", slot))? = Some(Arc::new(picture));
+        Ok(())
+    }
+
+    fn frame(&mut self, slot: usize, pts: Timestamp) -> Result<SoftwareFrame> {
+        let picture = self
+            .pictures
+            .get(slot)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| anyhow!("Slot {} holds no picture", slot))?;
+        Ok(SoftwareFrame {
+            picture: picture.to_nv12(),
+            pts,
+        })
+    }
+}
+
+/// The reference frame described by `std_reference_info`, with FrameNumWrap relative to
+/// the current picture's `frame_num` (8.2.4.1).
+fn reference_frame(
+    slot: usize,
+    std_reference_info: &StdVideoDecodeH264ReferenceInfo,
+    frame_num: u32,
+    sps: &Sps,
+) -> ReferenceFrame {
+    let long_term = std_reference_info.flags.used_for_long_term_reference() != 0;
+    let reference_frame_num = std_reference_info.FrameNum as u32;
+    let frame_num_wrap = if reference_frame_num > frame_num {
+        reference_frame_num as i32 - sps.max_frame_num() as i32
+    } else {
+        reference_frame_num as i32
+    };
+    let [top_field_order_cnt, bottom_field_order_cnt] = std_reference_info.PicOrderCnt;
+    ReferenceFrame {
+        slot: Some(slot),
+        frame_num: if long_term { 0 } else { reference_frame_num },
+        frame_num_wrap,
+        // FrameNum carries LongTermFrameIdx for long-term references
+        long_term_frame_idx: long_term.then_some(reference_frame_num),
+        top_field_order_cnt,
+        bottom_field_order_cnt,
+    }
+}
+
+impl SoftwareBackend {
+    /// RefPicList0 and RefPicList1 of a frame slice, initialised (8.2.4.2) and modified
+    /// (8.2.4.3).
+    fn ref_pic_lists(
+        &self,
+        header: &SliceHeader,
+        sps: &Sps,
+        pic_order_cnt: i32,
+        references: &[ReferenceFrame],
+    ) -> Result<[Vec<Option<RefPicture>>; 2]> {
+        let (short_term, long_term): (Vec<_>, Vec<_>) =
+            (0..references.len()).partition(|&i| !references[i].is_long_term());
+        let mut long_term = long_term;
+        long_term.sort_by_key(|&i| references[i].pic_num());
+
+        let lists = match header.slice_type {
+            SliceType::P => {
+                let mut list = short_term;
+                list.sort_by_key(|&i| std::cmp::Reverse(references[i].pic_num()));
+                list.extend(&long_term);
+                [list, Vec::new()]
+            }
+            SliceType::B => {
+                let poc = |i: usize| references[i].pic_order_cnt();
+                let (mut before, mut after): (Vec<_>, Vec<_>) =
+                    short_term.iter().partition(|&&i| poc(i) < pic_order_cnt);
+                before.sort_by_key(|&i| std::cmp::Reverse(poc(i)));
+                after.sort_by_key(|&i| poc(i));
+
+                let list0 = [&before[..], &after[..], &long_term[..]].concat();
+                let mut list1 = [&after[..], &before[..], &long_term[..]].concat();
+                if list1.len() > 1 && list0 == list1 {
+                    list1.swap(0, 1);
+                }
+                [list0, list1]
+            }
+            _ => [Vec::new(), Vec::new()],
+        };
+
+        let counts = [
+            header.num_ref_idx_l0_active_minus1 as usize + 1,
+            header.num_ref_idx_l1_active_minus1 as usize + 1,
+        ];
+        let modifications = [
+            &header.ref_pic_list_modification_l0,
+            &header.ref_pic_list_modification_l1,
+        ];
+        let mut ref_lists = [Vec::new(), Vec::new()];
+        for (list, initial) in lists.into_iter().enumerate() {
+            if list == 1 && header.slice_type != SliceType::B
+                || list == 0 && header.slice_type.is_intra()
+            {
+                continue;
+            }
+            let mut entries: Vec<Option<usize>> = initial.into_iter().map(Some).collect();
+            entries.resize(counts[list], None);
+            entries.truncate(counts[list]);
+            modify_ref_pic_list(&mut entries, modifications[list], references, header, sps)?;
+
+            ref_lists[list] = entries
+                .into_iter()
+                .map(|entry| {
+                    let frame = &references[entry?];
+                    Some(RefPicture {
+                        picture: self.pictures.get(frame.slot?)?.clone()?,
+                        pic_order_cnt: frame.pic_order_cnt(),
+                        long_term: frame.is_long_term(),
+                    })
+                })
+                .collect();
+        }
+        Ok(ref_lists)
+    }
+}
+
+/// Applies ref_pic_list_modification() to a list of indices into `references`.
+fn modify_ref_pic_list(
+    list: &mut Vec<Option<usize>>,
+    modifications: &[RefPicListModification],
+    references: &[ReferenceFrame],
+    header: &SliceHeader,
+    sps: &Sps,
+) -> Result<()> {
+    let max_pic_num = sps.max_frame_num() as i32;
+    let curr_pic_num = header.frame_num as i32;
+    let count = list.len();
+    let mut pic_num_pred = curr_pic_num;
+
+    for (ref_idx, &modification) in modifications.iter().enumerate() {
+        let target = match modification {
+            RefPicListModification::ShortTermSubtract(abs_diff_pic_num_minus1)
+            | RefPicListModification::ShortTermAdd(abs_diff_pic_num_minus1) => {
+                let diff = abs_diff_pic_num_minus1 as i32 + 1;
+                let mut pic_num_no_wrap =
+                    if matches!(modification, RefPicListModification::ShortTermSubtract(_)) {
+                        pic_num_pred - diff
+                    } else {
+                        pic_num_pred + diff
+                    };
+                if pic_num_no_wrap < 0 {
+                    pic_num_no_wrap += max_pic_num;
+                } else if pic_num_no_wrap >= max_pic_num {
+                    pic_num_no_wrap -= max_pic_num;
+                }
+                pic_num_pred = pic_num_no_wrap;
+                let pic_num = if pic_num_no_wrap > curr_pic_num {
+                    pic_num_no_wrap - max_pic_num
+                } else {
+                    pic_num_no_wrap
+                };
+                references
+                    .iter()
+                    .position(|frame| !frame.is_long_term() && frame.pic_num() == pic_num)
+                    .ok_or_else(|| anyhow!("No short-term reference with PicNum {}", pic_num))?
+            }
+            RefPicListModification::LongTerm(long_term_pic_num) => references
+                .iter()
+                .position(|frame| frame.long_term_frame_idx == Some(long_term_pic_num))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No long-term reference with LongTermPicNum {}",
+                        long_term_pic_num
+                    )
+                })?,
+        };
+        if ref_idx >= count {
+            return Err(anyhow!("Too many reference picture list modifications"));
+        }
+
+        // The picture moves to ref_idx, its later occurrence is removed
+        list.insert(ref_idx, Some(target));
+        let duplicate = (ref_idx + 1..list.len()).find(|&i| list[i] == Some(target));
+        match duplicate {
+            Some(i) => {
+                list.remove(i);
+            }
+            None => list.truncate(count),
+        }
+    }
+    Ok(())
+}
+
+/// Rejects the coding tools the software decoder does not implement.
+fn check_supported(
+    pps: &Pps,
+    sps: &Sps,
+    mut slice_types: impl Iterator<Item = SliceType>,
+) -> Result<()> {
+    let unsupported = if !sps.frame_mbs_only_flag {
+        Some("Interlaced coding")
+    } else if sps.chroma_format_idc != 1 {
+        Some("Chroma formats other than 4:2:0")
+    } else if sps.bit_depth_luma_minus8 != 0 || sps.bit_depth_chroma_minus8 != 0 {
+        Some("Bit depths other than 8")
+    } else if sps.qpprime_y_zero_transform_bypass_flag {
+        Some("Lossless coding")
+    } else if pps.num_slice_groups_minus1 != 0 {
+        Some("Slice groups")
+    } else if slice_types.any(|slice_type| matches!(slice_type, SliceType::Sp | SliceType::Si)) {
+        Some("SP and SI slices")
+    } else {
+        None
+    };
+    match unsupported {
+        Some(feature) => Err(anyhow!("{} not supported by the software decoder", feature)),
+        None => Ok(()),
+    }
+}
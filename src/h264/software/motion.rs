@@ -0,0 +1,463 @@
+use anyhow::{anyhow, Result};
+
+use super::inter::{self, Weight, PREDICTION_STRIDE};
+use super::macroblock::{Partition, RefPicture, Shape, SliceDecoder};
+use super::picture::Motion;
+use crate::h264::slice::SliceType;
+
+const WHOLE: Partition = Partition {
+    x: 0,
+    y: 0,
+    width: 4,
+    height: 4,
+};
+
+/// Median of three, 8-214
+fn median(a: i32, b: i32, c: i32) -> i32 {
+    a.max(b).min(a.min(b).max(c))
+}
+
+/// MinPositive of 8-184
+fn min_positive(x: i8, y: i8) -> i8 {
+    if x >= 0 && y >= 0 {
+        x.min(y)
+    } else {
+        x.max(y)
+    }
+}
+
+/// Whether a neighbour of a P_Skip macroblock makes its motion vector zero (8.4.1.1)
+fn skip_zero(neighbour: Option<Motion>) -> bool {
+    !matches!(neighbour, Some(motion) if motion.ref_idx != 0 || motion.mv != [0, 0])
+}
+
+/// DistScaleFactor of 8-195 to 8-197, `None` when td is 0.
+fn dist_scale_factor(current: i32, pic0: i32, pic1: i32) -> Option<i32> {
+    let tb = (current - pic0).clamp(-128, 127);
+    let td = (pic1 - pic0).clamp(-128, 127);
+    if td == 0 {
+        return None;
+    }
+    let tx = (16384 + (td / 2).abs()) / td;
+    Some(((tb * tx + 32) >> 6).clamp(-1024, 1023))
+}
+
+impl SliceDecoder<'_, '_> {
+    /// Motion of the neighbouring block at (`x`, `y`) relative to the current macroblock,
+    /// `None` when not available. Blocks inside the current macroblock are available
+    /// when set in `done`.
+    fn neighbour_motion(&self, list: usize, x: i32, y: i32, done: u16) -> Option<Motion> {
+        if (0..4).contains(&x) && (0..4).contains(&y) && done & (1 << (y * 4 + x)) == 0 {
+            return None;
+        }
+        let (_, index) = self.locate(x, y)?;
+        Some(self.state.motion[list][index])
+    }
+
+    /// Motion of the neighbours A, B and C of a partition (8.4.1.3.2), with D in place
+    /// of an unavailable C
+    fn neighbours(&self, list: usize, partition: &Partition, done: u16) -> [Option<Motion>; 3] {
+        let (x, y) = (partition.x as i32, partition.y as i32);
+        let a = self.neighbour_motion(list, x - 1, y, done);
+        let b = self.neighbour_motion(list, x, y - 1, done);
+        let c = self
+            .neighbour_motion(list, x + partition.width as i32, y - 1, done)
+            .or_else(|| self.neighbour_motion(list, x - 1, y - 1, done));
+        [a, b, c]
+    }
+
+    /// Luma motion vector prediction (8.4.1.3) of a partition using `ref_idx`.
+    pub(super) fn predict_mv(
+        &self,
+        list: usize,
+        partition: &Partition,
+        ref_idx: i8,
+        shape: Shape,
+        done: u16,
+    ) -> [i32; 2] {
+        let [a, mut b, mut c] = self.neighbours(list, partition, done);
+        if b.is_none() && c.is_none() && a.is_some() {
+            b = a;
+            c = a;
+        }
+        let [a, b, c] = [a, b, c].map(|n| n.unwrap_or(Motion::UNUSED));
+        let mv = |motion: Motion| motion.mv.map(i32::from);
+
+        match shape {
+            Shape::Wide if partition.y == 0 && b.ref_idx == ref_idx => return mv(b),
+            Shape::Wide if partition.y != 0 && a.ref_idx == ref_idx => return mv(a),
+            Shape::Tall if partition.x == 0 && a.ref_idx == ref_idx => return mv(a),
+            Shape::Tall if partition.x != 0 && c.ref_idx == ref_idx => return mv(c),
+            _ => {}
+        }
+
+        let matching: Vec<Motion> = [a, b, c]
+            .into_iter()
+            .filter(|n| n.ref_idx == ref_idx)
+            .collect();
+        if matching.len() == 1 {
+            return mv(matching[0]);
+        }
+        [0, 1].map(|i| median(a.mv[i] as i32, b.mv[i] as i32, c.mv[i] as i32))
+    }
+
+    /// Motion of a P_Skip macroblock (8.4.1.1)
+    pub(super) fn p_skip_motion(&mut self) -> Result<()> {
+        let reference = self.reference(0, 0)?;
+        let ref_id = reference.picture.id;
+        let a = self.neighbour_motion(0, -1, 0, 0);
+        let b = self.neighbour_motion(0, 0, -1, 0);
+        let mv = if skip_zero(a) || skip_zero(b) {
+            [0, 0]
+        } else {
+            self.predict_mv(0, &WHOLE, 0, Shape::Whole, 0)
+        };
+        let motion = Motion {
+            mv: mv.map(|component| component as i16),
+            ref_idx: 0,
+            ref_id,
+        };
+        self.fill_motion(0, &WHOLE, motion);
+        Ok(())
+    }
+
+    /// Reference indices and motion vectors of spatial direct prediction (8.4.1.2.2),
+    /// which are the same for the whole macroblock
+    fn spatial_direct(&mut self) -> ([i8; 2], [[i32; 2]; 2]) {
+        if let Some(spatial_direct) = self.spatial_direct {
+            return spatial_direct;
+        }
+        let mut ref_idx = [0i8; 2];
+        for (list, ref_idx) in ref_idx.iter_mut().enumerate() {
+            let [a, b, c] = self
+                .neighbours(list, &WHOLE, 0)
+                .map(|n| n.unwrap_or(Motion::UNUSED).ref_idx);
+            *ref_idx = min_positive(a, min_positive(b, c));
+        }
+
+        let spatial_direct = if ref_idx[0] < 0 && ref_idx[1] < 0 {
+            // directZeroPredictionFlag
+            ([0, 0], [[0; 2]; 2])
+        } else {
+            let mv = [0, 1].map(|list| {
+                if ref_idx[list] < 0 {
+                    [0, 0]
+                } else {
+                    self.predict_mv(list, &WHOLE, ref_idx[list], Shape::Whole, 0)
+                }
+            });
+            (ref_idx, mv)
+        };
+        self.spatial_direct = Some(spatial_direct);
+        spatial_direct
+    }
+
+    /// Motion of the 8x8 block `sub` predicted in direct mode (8.4.1.2)
+    pub(super) fn direct_motion(&mut self, sub: usize) -> Result<()> {
+        let context = self.context;
+        let colocated = self.reference(1, 0)?.clone();
+        let inference = context.sps.direct_8x8_inference_flag;
+        let spatial = context.header.direct_spatial_mv_pred_flag;
+        let direct = spatial.then(|| self.spatial_direct());
+
+        let (x8, y8) = ((sub % 2) * 2, (sub / 2) * 2);
+        let stride = self.state.width_in_mbs * 4;
+        for y in y8..y8 + 2 {
+            for x in x8..x8 + 2 {
+                // The corner blocks with direct_8x8_inference_flag, 8.4.1.2.1
+                let (col_x, col_y) = if inference {
+                    (if x < 2 { 0 } else { 3 }, if y < 2 { 0 } else { 3 })
+                } else {
+                    (x, y)
+                };
+                let col_index = (self.mb_y * 4 + col_y) * stride + self.mb_x * 4 + col_x;
+                let col = match colocated.picture.motion[0][col_index] {
+                    motion if motion.ref_idx >= 0 => motion,
+                    _ => colocated.picture.motion[1][col_index],
+                };
+
+                let motion = match direct {
+                    Some((ref_idx, mv)) => {
+                        let col_zero = !colocated.long_term
+                            && col.ref_idx == 0
+                            && col.mv.iter().all(|component| (-1..=1).contains(component));
+                        let mut motion = [Motion::UNUSED; 2];
+                        for list in 0..2 {
+                            if ref_idx[list] < 0 {
+                                continue;
+                            }
+                            let zero = ref_idx[list] == 0 && col_zero;
+                            motion[list] = Motion {
+                                mv: if zero {
+                                    [0, 0]
+                                } else {
+                                    mv[list].map(|component| component as i16)
+                                },
+                                ref_idx: ref_idx[list],
+                                ref_id: self.reference(list, ref_idx[list])?.picture.id,
+                            };
+                        }
+                        motion
+                    }
+                    None => self.temporal_direct(&colocated, col)?,
+                };
+                let index = self.block_index(x, y);
+                for (list, motion) in motion.into_iter().enumerate() {
+                    self.state.motion[list][index] = motion;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Temporal direct prediction (8.4.1.2.3) of one block from the co-located motion
+    fn temporal_direct(&self, colocated: &RefPicture, col: Motion) -> Result<[Motion; 2]> {
+        let ref_idx = if col.ref_idx < 0 {
+            0
+        } else {
+            self.context.ref_lists[0]
+                .iter()
+                .position(|reference| {
+                    reference
+                        .as_ref()
+                        .is_some_and(|reference| reference.picture.id == col.ref_id)
+                })
+                .ok_or_else(|| anyhow!("Co-located reference picture is not in RefPicList0"))?
+        };
+        let reference = self.reference(0, ref_idx as i8)?;
+        let mv_col = col.mv.map(i32::from);
+        let scale = if reference.long_term {
+            None
+        } else {
+            dist_scale_factor(
+                self.context.pic_order_cnt,
+                reference.pic_order_cnt,
+                colocated.pic_order_cnt,
+            )
+        };
+        let (mv0, mv1) = match scale {
+            Some(scale) => {
+                let mv0 = mv_col.map(|component| (scale * component + 128) >> 8);
+                (mv0, [mv0[0] - mv_col[0], mv0[1] - mv_col[1]])
+            }
+            None => (mv_col, [0, 0]),
+        };
+        Ok([
+            Motion {
+                mv: mv0.map(|component| component as i16),
+                ref_idx: ref_idx as i8,
+                ref_id: reference.picture.id,
+            },
+            Motion {
+                mv: mv1.map(|component| component as i16),
+                ref_idx: 0,
+                ref_id: colocated.picture.id,
+            },
+        ])
+    }
+
+    /// Inter prediction of the whole macroblock from its motion, in the largest blocks
+    /// that share the same motion.
+    pub(super) fn predict_inter(&mut self) -> Result<()> {
+        let motion_at = |decoder: &Self, x: usize, y: usize| {
+            let index = decoder.block_index(x, y);
+            [
+                decoder.state.motion[0][index],
+                decoder.state.motion[1][index],
+            ]
+        };
+        let uniform = |decoder: &Self, partition: &Partition| {
+            let first = motion_at(decoder, partition.x, partition.y);
+            (partition.y..partition.y + partition.height).all(|y| {
+                (partition.x..partition.x + partition.width)
+                    .all(|x| motion_at(decoder, x, y) == first)
+            })
+        };
+
+        if uniform(self, &WHOLE) {
+            return self.predict_partition(&WHOLE);
+        }
+        for sub in 0..4 {
+            let partition = Partition {
+                x: (sub % 2) * 2,
+                y: (sub / 2) * 2,
+                width: 2,
+                height: 2,
+            };
+            if uniform(self, &partition) {
+                self.predict_partition(&partition)?;
+                continue;
+            }
+            for block in 0..4 {
+                self.predict_partition(&Partition {
+                    x: partition.x + block % 2,
+                    y: partition.y + block / 2,
+                    width: 1,
+                    height: 1,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn predict_partition(&mut self, partition: &Partition) -> Result<()> {
+        let index = self.block_index(partition.x, partition.y);
+        let motion = [self.state.motion[0][index], self.state.motion[1][index]];
+        let mut references: [Option<RefPicture>; 2] = [None, None];
+        for list in 0..2 {
+            if motion[list].ref_idx >= 0 {
+                references[list] = Some(self.reference(list, motion[list].ref_idx)?.clone());
+            }
+        }
+        if references.iter().all(Option::is_none) {
+            return Err(anyhow!("Inter partition without motion"));
+        }
+
+        let luma_x = self.mb_x * 16 + partition.x * 4;
+        let luma_y = self.mb_y * 16 + partition.y * 4;
+        let weights = self.weights(&motion, &references);
+        let mut predictions = [[0u8; PREDICTION_STRIDE * 16]; 2];
+        for plane in 0..3 {
+            let (x, y, width, height) = if plane == 0 {
+                (luma_x, luma_y, partition.width * 4, partition.height * 4)
+            } else {
+                (
+                    luma_x / 2,
+                    luma_y / 2,
+                    partition.width * 2,
+                    partition.height * 2,
+                )
+            };
+            for list in 0..2 {
+                let Some(reference) = &references[list] else {
+                    continue;
+                };
+                let source = &reference.picture.planes[plane];
+                let mv = motion[list].mv.map(i32::from);
+                let dst = &mut predictions[list];
+                if plane == 0 {
+                    inter::predict_luma(source, x as i32, y as i32, mv, width, height, dst);
+                } else {
+                    inter::predict_chroma(source, x as i32, y as i32, mv, width, height, dst);
+                }
+            }
+
+            let (log2_denom, weights) = weights[plane.min(1)];
+            let [prediction0, prediction1] = &mut predictions;
+            let prediction = match references {
+                [Some(_), Some(_)] => {
+                    let weights = weights.map(|w| [w[0][plane], w[1][plane]]);
+                    inter::weight_bi(prediction0, prediction1, width, height, log2_denom, weights);
+                    &*prediction0
+                }
+                [Some(_), None] => {
+                    let weight = weights.map(|w| w[0][plane]);
+                    inter::weight_single(prediction0, width, height, log2_denom, weight);
+                    &*prediction0
+                }
+                _ => {
+                    let weight = weights.map(|w| w[1][plane]);
+                    inter::weight_single(prediction1, width, height, log2_denom, weight);
+                    &*prediction1
+                }
+            };
+
+            let target = &mut self.state.planes[plane];
+            for (row, samples) in prediction
+                .chunks(PREDICTION_STRIDE)
+                .take(height)
+                .enumerate()
+            {
+                let start = (y + row) * target.width + x;
+                target.data[start..start + width].copy_from_slice(&samples[..width]);
+            }
+        }
+        Ok(())
+    }
+
+    /// logWD and the weights of both lists per colour component for luma and for chroma,
+    /// `None` for the default prediction (8.4.2.3)
+    #[allow(clippy::type_complexity)]
+    fn weights(
+        &self,
+        motion: &[Motion; 2],
+        references: &[Option<RefPicture>; 2],
+    ) -> [(u32, Option<[[Weight; 3]; 2]>); 2] {
+        let header = self.context.header;
+        let pps = self.context.pps;
+        let default = [(0, None); 2];
+        let explicit = match header.slice_type {
+            SliceType::P => pps.weighted_pred_flag,
+            SliceType::B => pps.weighted_bipred_idc == 1,
+            _ => false,
+        };
+
+        if explicit {
+            let Some(table) = &header.pred_weight_table else {
+                return default;
+            };
+            let entry = |list: usize| -> [Weight; 3] {
+                let entries = if list == 0 { &table.l0 } else { &table.l1 };
+                let entry = (motion[list].ref_idx >= 0)
+                    .then(|| entries.get(motion[list].ref_idx as usize))
+                    .flatten()
+                    .copied()
+                    .unwrap_or_default();
+                let (weight, offset) = entry
+                    .luma_weight
+                    .unwrap_or((1 << table.luma_log2_weight_denom, 0));
+                let chroma = entry
+                    .chroma_weight
+                    .unwrap_or([(1 << table.chroma_log2_weight_denom, 0); 2]);
+                [
+                    Weight { weight, offset },
+                    Weight {
+                        weight: chroma[0].0,
+                        offset: chroma[0].1,
+                    },
+                    Weight {
+                        weight: chroma[1].0,
+                        offset: chroma[1].1,
+                    },
+                ]
+            };
+            let weights = Some([entry(0), entry(1)]);
+            return [
+                (table.luma_log2_weight_denom, weights),
+                (table.chroma_log2_weight_denom, weights),
+            ];
+        }
+
+        // Implicit weights only apply to bi-prediction, 8.4.2.3.1
+        let [Some(reference0), Some(reference1)] = references else {
+            return default;
+        };
+        if header.slice_type != SliceType::B || pps.weighted_bipred_idc != 2 {
+            return default;
+        }
+        let scale = if reference0.long_term || reference1.long_term {
+            None
+        } else {
+            dist_scale_factor(
+                self.context.pic_order_cnt,
+                reference0.pic_order_cnt,
+                reference1.pic_order_cnt,
+            )
+        };
+        let (w0, w1) = match scale.map(|scale| scale >> 2) {
+            Some(w1) if (-64..=128).contains(&w1) => (64 - w1, w1),
+            _ => (32, 32),
+        };
+        let weights = Some([
+            [Weight {
+                weight: w0,
+                offset: 0,
+            }; 3],
+            [Weight {
+                weight: w1,
+                offset: 0,
+            }; 3],
+        ]);
+        [(5, weights), (5, weights)]
+    }
+}
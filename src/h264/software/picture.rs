@@ -0,0 +1,73 @@
+use crate::yuv::Nv12Frame;
+
+/// One colour component of a decoded picture.
+#[derive(Clone, Debug)]
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Plane {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width * height],
+        }
+    }
+
+    /// The sample at (`x`, `y`), with coordinates outside the plane clamped to its edges
+    /// as reference pictures are extended in 8.4.2.2.
+    pub fn sample(&self, x: i32, y: i32) -> u8 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.data[y * self.width + x]
+    }
+}
+
+/// Motion of one 4x4 luma block for one reference picture list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Motion {
+    pub mv: [i16; 2],
+    /// -1 when the list is not used
+    pub ref_idx: i8,
+    /// [`Picture::id`] of the reference picture, for comparisons across slices and lists
+    pub ref_id: u32,
+}
+
+impl Motion {
+    pub const UNUSED: Motion = Motion {
+        mv: [0, 0],
+        ref_idx: -1,
+        ref_id: u32::MAX,
+    };
+}
+
+/// A decoded frame with the motion kept for the direct prediction of later pictures.
+#[derive(Clone, Debug)]
+pub struct Picture {
+    /// Unique among the pictures of a decoder
+    pub id: u32,
+    /// Y, Cb and Cr
+    pub planes: [Plane; 3],
+    /// Per 4x4 luma block in raster order and per list
+    pub motion: [Vec<Motion>; 2],
+}
+
+impl Picture {
+    pub fn to_nv12(&self) -> Nv12Frame {
+        let [y, cb, cr] = &self.planes;
+        Nv12Frame {
+            width: y.width as u32,
+            height: y.height as u32,
+            y: y.data.clone(),
+            uv: cb
+                .data
+                .iter()
+                .zip(&cr.data)
+                .flat_map(|(&cb, &cr)| [cb, cr])
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::h264::sps::ScalingLists;
+
+/// Raster position of each coefficient of a 4x4 block in frame zig-zag order (8.5.6)
+pub const ZIGZAG_4X4: [u8; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+/// Raster position of each coefficient of an 8x8 block in frame zig-zag order (8.5.7)
+pub const ZIGZAG_8X8: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// normAdjust4x4 (8-315) and normAdjust8x8 (8-318) per qP % 6
+const NORM_ADJUST_4X4: [[i32; 3]; 6] = [
+    [10, 16, 13],
+    [11, 18, 14],
+    [13, 20, 16],
+    [14, 23, 18],
+    [16, 25, 20],
+    [18, 29, 23],
+];
+const NORM_ADJUST_8X8: [[i32; 6]; 6] = [
+    [20, 18, 32, 19, 25, 24],
+    [22, 19, 35, 21, 28, 26],
+    [26, 23, 42, 24, 33, 31],
+    [28, 25, 45, 26, 35, 33],
+    [32, 28, 51, 30, 40, 38],
+    [36, 32, 58, 34, 46, 43],
+];
+
+// Table 8-15, QPc for qPI from 30 to 51
+const CHROMA_QP: [i32; 22] = [
+    29, 30, 31, 32, 32, 33, 34, 34, 35, 35, 36, 36, 37, 37, 37, 38, 38, 38, 39, 39, 39, 39,
+];
+
+/// QPc of 8-bit video for the luma QP and chroma_qp_index_offset or
+/// second_chroma_qp_index_offset.
+pub fn chroma_qp(qp_y: i32, offset: i32) -> i32 {
+    let qp_i = (qp_y + offset).clamp(0, 51);
+    if qp_i < 30 {
+        qp_i
+    } else {
+        CHROMA_QP[(qp_i - 30) as usize]
+    }
+}
+
+/// LevelScale4x4 and LevelScale8x8 (8.5.9) of the active scaling matrices, in raster order.
+pub struct LevelScale {
+    /// Intra Y, Cb, Cr and Inter Y, Cb, Cr, per qP % 6
+    pub scale_4x4: [[[i32; 16]; 6]; 6],
+    /// Intra Y and Inter Y, per qP % 6
+    pub scale_8x8: [[[i32; 64]; 6]; 2],
+}
+
+impl LevelScale {
+    pub fn new(lists: &ScalingLists) -> Self {
+        let mut scale_4x4 = [[[0; 16]; 6]; 6];
+        for (list, scale) in lists.list_4x4.iter().zip(&mut scale_4x4) {
+            for (m, scale) in scale.iter_mut().enumerate() {
+                for (k, &position) in ZIGZAG_4X4.iter().enumerate() {
+                    let (i, j) = (position / 4, position % 4);
+                    let norm = match (i % 2, j % 2) {
+                        (0, 0) => NORM_ADJUST_4X4[m][0],
+                        (1, 1) => NORM_ADJUST_4X4[m][1],
+                        _ => NORM_ADJUST_4X4[m][2],
+                    };
+                    scale[position as usize] = list[k] as i32 * norm;
+                }
+            }
+        }
+
+        let mut scale_8x8 = [[[0; 64]; 6]; 2];
+        for (list, scale) in lists.list_8x8.iter().zip(&mut scale_8x8) {
+            for (m, scale) in scale.iter_mut().enumerate() {
+                for (k, &position) in ZIGZAG_8X8.iter().enumerate() {
+                    let (i, j) = (position / 8, position % 8);
+                    let v = if i % 4 == 0 && j % 4 == 0 {
+                        0
+                    } else if i % 2 == 1 && j % 2 == 1 {
+                        1
+                    } else if i % 4 == 2 && j % 4 == 2 {
+                        2
+                    } else if (i % 4 == 0 && j % 2 == 1) || (i % 2 == 1 && j % 4 == 0) {
+                        3
+                    } else if (i % 4 == 0 && j % 4 == 2) || (i % 4 == 2 && j % 4 == 0) {
+                        4
+                    } else {
+                        5
+                    };
+                    scale[position as usize] = list[k] as i32 * NORM_ADJUST_8X8[m][v];
+                }
+            }
+        }
+
+        Self {
+            scale_4x4,
+            scale_8x8,
+        }
+    }
+}
+
+/// Scaling of a 4x4 block in raster order (8.5.12.1). The DC coefficient is left alone
+/// when `has_dc` is false, as it comes from the separate DC transform.
+pub fn dequantize_4x4(block: &mut [i32; 16], scale: &[[i32; 16]; 6], qp: i32, has_dc: bool) {
+    let scale = &scale[(qp % 6) as usize];
+    let start = if has_dc { 0 } else { 1 };
+    for (c, &s) in block.iter_mut().zip(scale).skip(start) {
+        *c = if qp >= 24 {
+            (*c * s) << (qp / 6 - 4)
+        } else {
+            (*c * s + (1 << (3 - qp / 6))) >> (4 - qp / 6)
+        };
+    }
+}
+
+/// Scaling of an 8x8 block in raster order (8.5.13.1).
+pub fn dequantize_8x8(block: &mut [i32; 64], scale: &[[i32; 64]; 6], qp: i32) {
+    let scale = &scale[(qp % 6) as usize];
+    for (c, &s) in block.iter_mut().zip(scale) {
+        *c = if qp >= 36 {
+            (*c * s) << (qp / 6 - 6)
+        } else {
+            (*c * s + (1 << (5 - qp / 6))) >> (6 - qp / 6)
+        };
+    }
+}
+
+/// Inverse Hadamard transform and scaling of the Intra_16x16 luma DC coefficients
+/// (8.5.10), in raster order of the 4x4 blocks.
+pub fn luma_dc_transform(dc: &mut [i32; 16], scale: &[[i32; 16]; 6], qp: i32) {
+    let mut f = [0; 16];
+    for i in 0..4 {
+        let row = &dc[i * 4..i * 4 + 4];
+        let e = [
+            row[0] + row[1] + row[2] + row[3],
+            row[0] + row[1] - row[2] - row[3],
+            row[0] - row[1] - row[2] + row[3],
+            row[0] - row[1] + row[2] - row[3],
+        ];
+        f[i * 4..i * 4 + 4].copy_from_slice(&e);
+    }
+    let s = scale[(qp % 6) as usize][0];
+    for j in 0..4 {
+        let column = [f[j], f[4 + j], f[8 + j], f[12 + j]];
+        let g = [
+            column[0] + column[1] + column[2] + column[3],
+            column[0] + column[1] - column[2] - column[3],
+            column[0] - column[1] - column[2] + column[3],
+            column[0] - column[1] + column[2] - column[3],
+        ];
+        for (i, g) in g.into_iter().enumerate() {
+            dc[i * 4 + j] = if qp >= 36 {
+                (g * s) << (qp / 6 - 6)
+            } else {
+                (g * s + (1 << (5 - qp / 6))) >> (6 - qp / 6)
+            };
+        }
+    }
+}
+
+/// Inverse transform and scaling of the 2x2 chroma DC coefficients of 4:2:0 video (8.5.11).
+pub fn chroma_dc_transform(dc: &mut [i32; 4], scale: &[[i32; 16]; 6], qp: i32) {
+    let f = [
+        dc[0] + dc[1] + dc[2] + dc[3],
+        dc[0] - dc[1] + dc[2] - dc[3],
+        dc[0] + dc[1] - dc[2] - dc[3],
+        dc[0] - dc[1] - dc[2] + dc[3],
+    ];
+    let s = scale[(qp % 6) as usize][0];
+    for (dc, f) in dc.iter_mut().zip(f) {
+        *dc = ((f * s) << (qp / 6)) >> 5;
+    }
+}
+
+/// Adds the inverse transform (8.5.12.2) of the scaled 4x4 block to the prediction in `dst`.
+pub fn add_idct_4x4(block: &[i32; 16], dst: &mut [u8], stride: usize) {
+    let mut f = [0; 16];
+    for i in 0..4 {
+        let d = &block[i * 4..i * 4 + 4];
+        let e = [
+            d[0] + d[2],
+            d[0] - d[2],
+            (d[1] >> 1) - d[3],
+            d[1] + (d[3] >> 1),
+        ];
+        f[i * 4] = e[0] + e[3];
+        f[i * 4 + 1] = e[1] + e[2];
+        f[i * 4 + 2] = e[1] - e[2];
+        f[i * 4 + 3] = e[0] - e[3];
+    }
+    for j in 0..4 {
+        let d = [f[j], f[4 + j], f[8 + j], f[12 + j]];
+        let g = [
+            d[0] + d[2],
+            d[0] - d[2],
+            (d[1] >> 1) - d[3],
+            d[1] + (d[3] >> 1),
+        ];
+        let h = [g[0] + g[3], g[1] + g[2], g[1] - g[2], g[0] - g[3]];
+        for (i, h) in h.into_iter().enumerate() {
+            let sample = &mut dst[i * stride + j];
+            *sample = (*sample as i32 + ((h + 32) >> 6)).clamp(0, 255) as u8;
+        }
+    }
+}
+
+/// One dimensional 8 point inverse transform of 8.5.13.2.
+fn idct_8(d: [i32; 8]) -> [i32; 8] {
+    let a0 = d[0] + d[4];
+    let a4 = d[0] - d[4];
+    let a2 = (d[2] >> 1) - d[6];
+    let a6 = d[2] + (d[6] >> 1);
+
+    let b0 = a0 + a6;
+    let b2 = a4 + a2;
+    let b4 = a4 - a2;
+    let b6 = a0 - a6;
+
+    let a1 = -d[3] + d[5] - d[7] - (d[7] >> 1);
+    let a3 = d[1] + d[7] - d[3] - (d[3] >> 1);
+    let a5 = -d[1] + d[7] + d[5] + (d[5] >> 1);
+    let a7 = d[3] + d[5] + d[1] + (d[1] >> 1);
+
+    let b1 = a1 + (a7 >> 2);
+    let b7 = a7 - (a1 >> 2);
+    let b3 = a3 + (a5 >> 2);
+    let b5 = (a3 >> 2) - a5;
+
+    [
+        b0 + b7,
+        b2 + b5,
+        b4 + b3,
+        b6 + b1,
+        b6 - b1,
+        b4 - b3,
+        b2 - b5,
+        b0 - b7,
+    ]
+}
+
+/// Adds the inverse transform (8.5.13.2) of the scaled 8x8 block to the prediction in `dst`.
+pub fn add_idct_8x8(block: &[i32; 64], dst: &mut [u8], stride: usize) {
+    let mut f = [0; 64];
+    for i in 0..8 {
+        let mut row = [0; 8];
+        row.copy_from_slice(&block[i * 8..i * 8 + 8]);
+        f[i * 8..i * 8 + 8].copy_from_slice(&idct_8(row));
+    }
+    for j in 0..8 {
+        let column = idct_8(std::array::from_fn(|i| f[i * 8 + j]));
+        for (i, h) in column.into_iter().enumerate() {
+            let sample = &mut dst[i * stride + j];
+            *sample = (*sample as i32 + ((h + 32) >> 6)).clamp(0, 255) as u8;
+        }
+    }
+}
@@ -0,0 +1,909 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoH264HrdParameters, StdVideoH264ScalingLists, StdVideoH264SequenceParameterSet,
+    StdVideoH264SequenceParameterSetVui,
+};
+
+use crate::bitreader::{nal_to_rbsp, BitReader};
+use crate::h264::{NalUnitHeader, NalUnitType};
+use crate::timestamp::Timestamp;
+
+pub const MAX_SPS_COUNT: usize = 32;
+
+/// Neither dimension of a frame exceeds Sqrt(MaxFS * 8) macroblocks of level 6.2, A.3.1
+const MAX_FRAME_SIZE_IN_MBS: u32 = 1055;
+
+// Table 7-3 and 7-4, in zig-zag scan order
+pub const DEFAULT_4X4_INTRA: [u8; 16] = [
+    6, 13, 13, 20, 20, 20, 28, 28, 28, 28, 32, 32, 32, 37, 37, 42,
+];
+pub const DEFAULT_4X4_INTER: [u8; 16] = [
+    10, 14, 14, 20, 20, 20, 24, 24, 24, 24, 27, 27, 27, 30, 30, 34,
+];
+pub const DEFAULT_8X8_INTRA: [u8; 64] = [
+    6, 10, 10, 13, 11, 13, 16, 16, 16, 16, 18, 18, 18, 18, 18, 23, 23, 23, 23, 23, 23, 25, 25, 25,
+    25, 25, 25, 25, 27, 27, 27, 27, 27, 27, 27, 27, 29, 29, 29, 29, 29, 29, 29, 31, 31, 31, 31, 31,
+    31, 33, 33, 33, 33, 33, 36, 36, 36, 36, 38, 38, 38, 40, 40, 42,
+];
+pub const DEFAULT_8X8_INTER: [u8; 64] = [
+    9, 13, 13, 15, 13, 15, 17, 17, 17, 17, 19, 19, 19, 19, 19, 21, 21, 21, 21, 21, 21, 22, 22, 22,
+    22, 22, 22, 22, 24, 24, 24, 24, 24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27, 27,
+    27, 28, 28, 28, 28, 28, 30, 30, 30, 30, 32, 32, 32, 33, 33, 35,
+];
+
+/// Scaling matrices in zig-zag order with the fall-back rules of Table 7-2 already applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScalingLists {
+    pub present_mask: u16,
+    pub use_default_mask: u16,
+    pub list_4x4: [[u8; 16]; 6],
+    pub list_8x8: [[u8; 64]; 6],
+}
+
+impl Default for ScalingLists {
+    /// Flat_4x4_16 and Flat_8x8_16
+    fn default() -> Self {
+        Self {
+            present_mask: 0,
+            use_default_mask: 0,
+            list_4x4: [[16; 16]; 6],
+            list_8x8: [[16; 64]; 6],
+        }
+    }
+}
+
+impl ScalingLists {
+    fn parse_list(reader: &mut BitReader, list: &mut [u8]) -> Result<bool> {
+        let mut last_scale = 8;
+        let mut next_scale = 8;
+        let mut use_default = false;
+
+        for j in 0..list.len() {
+            if next_scale != 0 {
+                let delta_scale = reader.read_se()?;
+                if !(-128..=127).contains(&delta_scale) {
+                    return Err(anyhow!("Invalid delta_scale {}", delta_scale));
+                }
+                next_scale = (last_scale + delta_scale + 256) % 256;
+                use_default = j == 0 && next_scale == 0;
+            }
+            list[j] = if next_scale == 0 {
+                last_scale
+            } else {
+                next_scale
+            } as u8;
+            last_scale = list[j] as i32;
+        }
+
+        Ok(use_default)
+    }
+
+    /// Parses `count` scaling lists. Lists missing from the bitstream are inferred from
+    /// `fallback` (fall-back rule B) or, when it is `None`, from the default tables (rule A).
+    pub(crate) fn parse(
+        reader: &mut BitReader,
+        count: usize,
+        fallback: Option<&ScalingLists>,
+    ) -> Result<Self> {
+        let mut lists = ScalingLists::default();
+
+        for i in 0..12 {
+            let present = i < count && reader.read_flag()?;
+
+            if i < 6 {
+                if present {
+                    lists.present_mask |= 1 << i;
+                    if Self::parse_list(reader, &mut lists.list_4x4[i])? {
+                        lists.use_default_mask |= 1 << i;
+                    }
+                }
+                if !present || lists.use_default_mask & (1 << i) != 0 {
+                    lists.list_4x4[i] = match (i, fallback) {
+                        _ if present => {
+                            if i < 3 {
+                                DEFAULT_4X4_INTRA
+                            } else {
+                                DEFAULT_4X4_INTER
+                            }
+                        }
+                        (0 | 3, Some(fallback)) => fallback.list_4x4[i],
+                        (0, None) => DEFAULT_4X4_INTRA,
+                        (3, None) => DEFAULT_4X4_INTER,
+                        _ => lists.list_4x4[i - 1],
+                    };
+                }
+            } else {
+                let j = i - 6;
+                if present {
+                    lists.present_mask |= 1 << i;
+                    if Self::parse_list(reader, &mut lists.list_8x8[j])? {
+                        lists.use_default_mask |= 1 << i;
+                    }
+                }
+                if !present || lists.use_default_mask & (1 << i) != 0 {
+                    lists.list_8x8[j] = match (j, fallback) {
+                        _ if present => {
+                            if j % 2 == 0 {
+                                DEFAULT_8X8_INTRA
+                            } else {
+                                DEFAULT_8X8_INTER
+                            }
+                        }
+                        (0 | 1, Some(fallback)) => fallback.list_8x8[j],
+                        (0, None) => DEFAULT_8X8_INTRA,
+                        (1, None) => DEFAULT_8X8_INTER,
+                        _ => lists.list_8x8[j - 2],
+                    };
+                }
+            }
+        }
+
+        Ok(lists)
+    }
+
+    pub fn to_std(&self) -> StdVideoH264ScalingLists {
+        StdVideoH264ScalingLists {
+            scaling_list_present_mask: self.present_mask,
+            use_default_scaling_matrix_mask: self.use_default_mask,
+            ScalingList4x4: self.list_4x4,
+            ScalingList8x8: self.list_8x8,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HrdParameters {
+    pub cpb_cnt_minus1: u8,
+    pub bit_rate_scale: u8,
+    pub cpb_size_scale: u8,
+    pub bit_rate_value_minus1: Vec<u32>,
+    pub cpb_size_value_minus1: Vec<u32>,
+    pub cbr_flag: Vec<bool>,
+    pub initial_cpb_removal_delay_length_minus1: u8,
+    pub cpb_removal_delay_length_minus1: u8,
+    pub dpb_output_delay_length_minus1: u8,
+    pub time_offset_length: u8,
+}
+
+impl HrdParameters {
+    fn parse(reader: &mut BitReader) -> Result<Self> {
+        let cpb_cnt_minus1 = reader.read_ue()?;
+        if cpb_cnt_minus1 > 31 {
+            return Err(anyhow!("Invalid cpb_cnt_minus1 {}", cpb_cnt_minus1));
+        }
+
+        let mut hrd = HrdParameters {
+            cpb_cnt_minus1: cpb_cnt_minus1 as u8,
+            bit_rate_scale: reader.read_bits(4)? as u8,
+            cpb_size_scale: reader.read_bits(4)? as u8,
+            ..Default::default()
+        };
+
+        for _ in 0..=cpb_cnt_minus1 {
+            hrd.bit_rate_value_minus1.push(reader.read_ue()?);
+            hrd.cpb_size_value_minus1.push(reader.read_ue()?);
+            hrd.cbr_flag.push(reader.read_flag()?);
+        }
+
+        hrd.initial_cpb_removal_delay_length_minus1 = reader.read_bits(5)? as u8;
+        hrd.cpb_removal_delay_length_minus1 = reader.read_bits(5)? as u8;
+        hrd.dpb_output_delay_length_minus1 = reader.read_bits(5)? as u8;
+        hrd.time_offset_length = reader.read_bits(5)? as u8;
+
+        Ok(hrd)
+    }
+
+    pub fn to_std(&self) -> StdVideoH264HrdParameters {
+        let mut hrd = StdVideoH264HrdParameters {
+            cpb_cnt_minus1: self.cpb_cnt_minus1,
+            bit_rate_scale: self.bit_rate_scale,
+            cpb_size_scale: self.cpb_size_scale,
+            reserved1: 0,
+            bit_rate_value_minus1: [0; 32],
+            cpb_size_value_minus1: [0; 32],
+            cbr_flag: [0; 32],
+            initial_cpb_removal_delay_length_minus1: self.initial_cpb_removal_delay_length_minus1
+                as u32,
+            cpb_removal_delay_length_minus1: self.cpb_removal_delay_length_minus1 as u32,
+            dpb_output_delay_length_minus1: self.dpb_output_delay_length_minus1 as u32,
+            time_offset_length: self.time_offset_length as u32,
+        };
+
+        for i in 0..self.bit_rate_value_minus1.len() {
+            hrd.bit_rate_value_minus1[i] = self.bit_rate_value_minus1[i];
+            hrd.cpb_size_value_minus1[i] = self.cpb_size_value_minus1[i];
+            hrd.cbr_flag[i] = self.cbr_flag[i] as u8;
+        }
+
+        hrd
+    }
+}
+
+/// Video usability information, Annex E.1.1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vui {
+    pub aspect_ratio_info_present_flag: bool,
+    pub aspect_ratio_idc: u8,
+    pub sar_width: u16,
+    pub sar_height: u16,
+    pub overscan_info_present_flag: bool,
+    pub overscan_appropriate_flag: bool,
+    pub video_signal_type_present_flag: bool,
+    pub video_format: u8,
+    pub video_full_range_flag: bool,
+    pub colour_description_present_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub chroma_loc_info_present_flag: bool,
+    pub chroma_sample_loc_type_top_field: u8,
+    pub chroma_sample_loc_type_bottom_field: u8,
+    pub timing_info_present_flag: bool,
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate_flag: bool,
+    pub nal_hrd_parameters: Option<HrdParameters>,
+    pub vcl_hrd_parameters: Option<HrdParameters>,
+    pub low_delay_hrd_flag: bool,
+    pub pic_struct_present_flag: bool,
+    pub bitstream_restriction_flag: bool,
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    pub max_bytes_per_pic_denom: u32,
+    pub max_bits_per_mb_denom: u32,
+    pub log2_max_mv_length_horizontal: u32,
+    pub log2_max_mv_length_vertical: u32,
+    pub max_num_reorder_frames: u32,
+    pub max_dec_frame_buffering: u32,
+}
+
+impl Default for Vui {
+    /// Values inferred when the corresponding syntax elements are absent
+    fn default() -> Self {
+        Self {
+            aspect_ratio_info_present_flag: false,
+            aspect_ratio_idc: 0,
+            sar_width: 0,
+            sar_height: 0,
+            overscan_info_present_flag: false,
+            overscan_appropriate_flag: false,
+            video_signal_type_present_flag: false,
+            video_format: 5,
+            video_full_range_flag: false,
+            colour_description_present_flag: false,
+            colour_primaries: 2,
+            transfer_characteristics: 2,
+            matrix_coefficients: 2,
+            chroma_loc_info_present_flag: false,
+            chroma_sample_loc_type_top_field: 0,
+            chroma_sample_loc_type_bottom_field: 0,
+            timing_info_present_flag: false,
+            num_units_in_tick: 0,
+            time_scale: 0,
+            fixed_frame_rate_flag: false,
+            nal_hrd_parameters: None,
+            vcl_hrd_parameters: None,
+            low_delay_hrd_flag: false,
+            pic_struct_present_flag: false,
+            bitstream_restriction_flag: false,
+            motion_vectors_over_pic_boundaries_flag: true,
+            max_bytes_per_pic_denom: 2,
+            max_bits_per_mb_denom: 1,
+            log2_max_mv_length_horizontal: 15,
+            log2_max_mv_length_vertical: 15,
+            max_num_reorder_frames: 16,
+            max_dec_frame_buffering: 16,
+        }
+    }
+}
+
+impl Vui {
+    fn parse(reader: &mut BitReader) -> Result<Self> {
+        let mut vui = Vui::default();
+
+        vui.aspect_ratio_info_present_flag = reader.read_flag()?;
+        if vui.aspect_ratio_info_present_flag {
+            vui.aspect_ratio_idc = reader.read_bits(8)? as u8;
+            // Extended_SAR
+            if vui.aspect_ratio_idc == 255 {
+                vui.sar_width = reader.read_bits(16)? as u16;
+                vui.sar_height = reader.read_bits(16)? as u16;
+            }
+        }
+
+        vui.overscan_info_present_flag = reader.read_flag()?;
+        if vui.overscan_info_present_flag {
+            vui.overscan_appropriate_flag = reader.read_flag()?;
+        }
+
+        vui.video_signal_type_present_flag = reader.read_flag()?;
+        if vui.video_signal_type_present_flag {
+            vui.video_format = reader.read_bits(3)? as u8;
+            vui.video_full_range_flag = reader.read_flag()?;
+            vui.colour_description_present_flag = reader.read_flag()?;
+            if vui.colour_description_present_flag {
+                vui.colour_primaries = reader.read_bits(8)? as u8;
+                vui.transfer_characteristics = reader.read_bits(8)? as u8;
+                vui.matrix_coefficients = reader.read_bits(8)? as u8;
+            }
+        }
+
+        vui.chroma_loc_info_present_flag = reader.read_flag()?;
+        if vui.chroma_loc_info_present_flag {
+            vui.chroma_sample_loc_type_top_field = reader.read_ue()?.min(5) as u8;
+            vui.chroma_sample_loc_type_bottom_field = reader.read_ue()?.min(5) as u8;
+        }
+
+        vui.timing_info_present_flag = reader.read_flag()?;
+        if vui.timing_info_present_flag {
+            vui.num_units_in_tick = reader.read_bits(32)?;
+            vui.time_scale = reader.read_bits(32)?;
+            vui.fixed_frame_rate_flag = reader.read_flag()?;
+        }
+
+        if reader.read_flag()? {
+            vui.nal_hrd_parameters = Some(HrdParameters::parse(reader)?);
+        }
+        if reader.read_flag()? {
+            vui.vcl_hrd_parameters = Some(HrdParameters::parse(reader)?);
+        }
+        if vui.nal_hrd_parameters.is_some() || vui.vcl_hrd_parameters.is_some() {
+            vui.low_delay_hrd_flag = reader.read_flag()?;
+        }
+
+        vui.pic_struct_present_flag = reader.read_flag()?;
+
+        vui.bitstream_restriction_flag = reader.read_flag()?;
+        if vui.bitstream_restriction_flag {
+            vui.motion_vectors_over_pic_boundaries_flag = reader.read_flag()?;
+            vui.max_bytes_per_pic_denom = reader.read_ue()?;
+            vui.max_bits_per_mb_denom = reader.read_ue()?;
+            vui.log2_max_mv_length_horizontal = reader.read_ue()?;
+            vui.log2_max_mv_length_vertical = reader.read_ue()?;
+            vui.max_num_reorder_frames = reader.read_ue()?;
+            vui.max_dec_frame_buffering = reader.read_ue()?;
+        }
+
+        Ok(vui)
+    }
+
+    /// The returned struct does not reference the HRD parameters; `pHrdParameters` is left null.
+    pub fn to_std(&self) -> StdVideoH264SequenceParameterSetVui {
+        let mut vui: StdVideoH264SequenceParameterSetVui = unsafe { mem::zeroed() };
+
+        vui.flags
+            .set_aspect_ratio_info_present_flag(self.aspect_ratio_info_present_flag as u32);
+        vui.flags
+            .set_overscan_info_present_flag(self.overscan_info_present_flag as u32);
+        vui.flags
+            .set_overscan_appropriate_flag(self.overscan_appropriate_flag as u32);
+        vui.flags
+            .set_video_signal_type_present_flag(self.video_signal_type_present_flag as u32);
+        vui.flags
+            .set_video_full_range_flag(self.video_full_range_flag as u32);
+        vui.flags
+            .set_color_description_present_flag(self.colour_description_present_flag as u32);
+        vui.flags
+            .set_chroma_loc_info_present_flag(self.chroma_loc_info_present_flag as u32);
+        vui.flags
+            .set_timing_info_present_flag(self.timing_info_present_flag as u32);
+        vui.flags
+            .set_fixed_frame_rate_flag(self.fixed_frame_rate_flag as u32);
+        vui.flags
+            .set_bitstream_restriction_flag(self.bitstream_restriction_flag as u32);
+        vui.flags
+            .set_nal_hrd_parameters_present_flag(self.nal_hrd_parameters.is_some() as u32);
+        vui.flags
+            .set_vcl_hrd_parameters_present_flag(self.vcl_hrd_parameters.is_some() as u32);
+
+        vui.aspect_ratio_idc = self.aspect_ratio_idc as u32;
+        vui.sar_width = self.sar_width;
+        vui.sar_height = self.sar_height;
+        vui.video_format = self.video_format;
+        vui.colour_primaries = self.colour_primaries;
+        vui.transfer_characteristics = self.transfer_characteristics;
+        vui.matrix_coefficients = self.matrix_coefficients;
+        vui.num_units_in_tick = self.num_units_in_tick;
+        vui.time_scale = self.time_scale;
+        vui.max_num_reorder_frames = self.max_num_reorder_frames.min(u8::MAX as u32) as u8;
+        vui.max_dec_frame_buffering = self.max_dec_frame_buffering.min(u8::MAX as u32) as u8;
+        vui.chroma_sample_loc_type_top_field = self.chroma_sample_loc_type_top_field;
+        vui.chroma_sample_loc_type_bottom_field = self.chroma_sample_loc_type_bottom_field;
+
+        vui
+    }
+}
+
+/// Sequence parameter set, 7.3.2.1.1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sps {
+    pub profile_idc: u8,
+    pub constraint_set_flags: u8,
+    pub level_idc: u8,
+    pub seq_parameter_set_id: u8,
+    pub chroma_format_idc: u8,
+    pub separate_colour_plane_flag: bool,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub qpprime_y_zero_transform_bypass_flag: bool,
+    pub seq_scaling_matrix_present_flag: bool,
+    pub scaling_lists: ScalingLists,
+    pub log2_max_frame_num_minus4: u8,
+    pub pic_order_cnt_type: u8,
+    pub log2_max_pic_order_cnt_lsb_minus4: u8,
+    pub delta_pic_order_always_zero_flag: bool,
+    pub offset_for_non_ref_pic: i32,
+    pub offset_for_top_to_bottom_field: i32,
+    pub offset_for_ref_frame: Vec<i32>,
+    pub max_num_ref_frames: u8,
+    pub gaps_in_frame_num_value_allowed_flag: bool,
+    pub pic_width_in_mbs_minus1: u32,
+    pub pic_height_in_map_units_minus1: u32,
+    pub frame_mbs_only_flag: bool,
+    pub mb_adaptive_frame_field_flag: bool,
+    pub direct_8x8_inference_flag: bool,
+    pub frame_cropping_flag: bool,
+    pub frame_crop_left_offset: u32,
+    pub frame_crop_right_offset: u32,
+    pub frame_crop_top_offset: u32,
+    pub frame_crop_bottom_offset: u32,
+    pub vui: Option<Vui>,
+}
+
+impl Sps {
+    /// Parses an SPS NAL unit, including its one byte header and any emulation prevention bytes.
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let header = NalUnitHeader::parse(nal)?;
+        if header.nal_unit_type != NalUnitType::Sps {
+            return Err(anyhow!(
+                "Expected an SPS NAL unit, got {:?}",
+                header.nal_unit_type
+            ));
+        }
+
+        let rbsp = nal_to_rbsp(&nal[1..]);
+        Self::parse_rbsp(&mut BitReader::new(&rbsp))
+    }
+
+    pub fn parse_rbsp(reader: &mut BitReader) -> Result<Self> {
+        let profile_idc = reader.read_bits(8)? as u8;
+        let constraint_set_flags = reader.read_bits(8)? as u8;
+        let level_idc = reader.read_bits(8)? as u8;
+
+        let seq_parameter_set_id = reader.read_ue()?;
+        if seq_parameter_set_id as usize >= MAX_SPS_COUNT {
+            return Err(anyhow!(
+                "Invalid seq_parameter_set_id {}",
+                seq_parameter_set_id
+            ));
+        }
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane_flag = false;
+        let mut bit_depth_luma_minus8 = 0;
+        let mut bit_depth_chroma_minus8 = 0;
+        let mut qpprime_y_zero_transform_bypass_flag = false;
+        let mut seq_scaling_matrix_present_flag = false;
+        let mut scaling_lists = ScalingLists::default();
+
+        if matches!(
+            profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            chroma_format_idc = reader.read_ue()?;
+            if chroma_format_idc > 3 {
+                return Err(anyhow!("Invalid chroma_format_idc {}", chroma_format_idc));
+            }
+            if chroma_format_idc == 3 {
+                separate_colour_plane_flag = reader.read_flag()?;
+            }
+
+            bit_depth_luma_minus8 = reader.read_ue()?;
+            bit_depth_chroma_minus8 = reader.read_ue()?;
+            if bit_depth_luma_minus8 > 6 || bit_depth_chroma_minus8 > 6 {
+                return Err(anyhow!(
+                    "Invalid bit depth luma {} chroma {}",
+                    bit_depth_luma_minus8 + 8,
+                    bit_depth_chroma_minus8 + 8
+                ));
+            }
+
+            qpprime_y_zero_transform_bypass_flag = reader.read_flag()?;
+
+            seq_scaling_matrix_present_flag = reader.read_flag()?;
+            if seq_scaling_matrix_present_flag {
+                let count = if chroma_format_idc != 3 { 8 } else { 12 };
+                scaling_lists = ScalingLists::parse(reader, count, None)?;
+            }
+        }
+
+        let log2_max_frame_num_minus4 = reader.read_ue()?;
+        if log2_max_frame_num_minus4 > 12 {
+            return Err(anyhow!(
+                "Invalid log2_max_frame_num_minus4 {}",
+                log2_max_frame_num_minus4
+            ));
+        }
+
+        let pic_order_cnt_type = reader.read_ue()?;
+        let mut log2_max_pic_order_cnt_lsb_minus4 = 0;
+        let mut delta_pic_order_always_zero_flag = false;
+        let mut offset_for_non_ref_pic = 0;
+        let mut offset_for_top_to_bottom_field = 0;
+        let mut offset_for_ref_frame = Vec::new();
+
+        match pic_order_cnt_type {
+            0 => {
+                log2_max_pic_order_cnt_lsb_minus4 = reader.read_ue()?;
+                if log2_max_pic_order_cnt_lsb_minus4 > 12 {
+                    return Err(anyhow!(
+                        "Invalid log2_max_pic_order_cnt_lsb_minus4 {}",
+                        log2_max_pic_order_cnt_lsb_minus4
+                    ));
+                }
+            }
+            1 => {
+                delta_pic_order_always_zero_flag = reader.read_flag()?;
+                offset_for_non_ref_pic = reader.read_se()?;
+                offset_for_top_to_bottom_field = reader.read_se()?;
+
+                let num_ref_frames_in_pic_order_cnt_cycle = reader.read_ue()?;
+                if num_ref_frames_in_pic_order_cnt_cycle > 255 {
+                    return Err(anyhow!(
+                        "Invalid num_ref_frames_in_pic_order_cnt_cycle {}",
+                        num_ref_frames_in_pic_order_cnt_cycle
+                    ));
+                }
+                for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
+                    offset_for_ref_frame.push(reader.read_se()?);
+                }
+            }
+            2 => {}
+            _ => return Err(anyhow!("Invalid pic_order_cnt_type {}", pic_order_cnt_type)),
+        }
+
+        let max_num_ref_frames = reader.read_ue()?;
+        if max_num_ref_frames > 16 {
+            return Err(anyhow!("Invalid max_num_ref_frames {}", max_num_ref_frames));
+        }
+        let gaps_in_frame_num_value_allowed_flag = reader.read_flag()?;
+
+        let pic_width_in_mbs_minus1 = reader.read_ue()?;
+        let pic_height_in_map_units_minus1 = reader.read_ue()?;
+
+        let frame_mbs_only_flag = reader.read_flag()?;
+        let mb_adaptive_frame_field_flag = !frame_mbs_only_flag && reader.read_flag()?;
+        let direct_8x8_inference_flag = reader.read_flag()?;
+
+        let width_in_mbs = pic_width_in_mbs_minus1 as u64 + 1;
+        let height_in_mbs =
+            (2 - frame_mbs_only_flag as u64) * (pic_height_in_map_units_minus1 as u64 + 1);
+        if width_in_mbs > MAX_FRAME_SIZE_IN_MBS as u64
+            || height_in_mbs > MAX_FRAME_SIZE_IN_MBS as u64
+        {
+            return Err(anyhow!(
+                "Frame of {}x{} macroblocks exceeds the level limits",
+                width_in_mbs,
+                height_in_mbs
+            ));
+        }
+
+        let frame_cropping_flag = reader.read_flag()?;
+        let mut frame_crop = [0; 4];
+        if frame_cropping_flag {
+            for offset in frame_crop.iter_mut() {
+                *offset = reader.read_ue()?;
+            }
+        }
+        // The cropping window keeps at least one sample in each direction, 7.4.2.1.1
+        let (crop_unit_x, crop_unit_y) = match (chroma_format_idc, separate_colour_plane_flag) {
+            (1, false) => (2, 2 * (2 - frame_mbs_only_flag as u64)),
+            (2, false) => (2, 2 - frame_mbs_only_flag as u64),
+            _ => (1, 2 - frame_mbs_only_flag as u64),
+        };
+        let [left, right, top, bottom] = frame_crop.map(|offset| offset as u64);
+        if crop_unit_x * (left + right) >= width_in_mbs * 16
+            || crop_unit_y * (top + bottom) >= height_in_mbs * 16
+        {
+            return Err(anyhow!(
+                "Cropping offsets {:?} exceed the frame of {}x{} macroblocks",
+                frame_crop,
+                width_in_mbs,
+                height_in_mbs
+            ));
+        }
+
+        let vui = if reader.read_flag()? {
+            Some(Vui::parse(reader)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            profile_idc,
+            constraint_set_flags,
+            level_idc,
+            seq_parameter_set_id: seq_parameter_set_id as u8,
+            chroma_format_idc: chroma_format_idc as u8,
+            separate_colour_plane_flag,
+            bit_depth_luma_minus8: bit_depth_luma_minus8 as u8,
+            bit_depth_chroma_minus8: bit_depth_chroma_minus8 as u8,
+            qpprime_y_zero_transform_bypass_flag,
+            seq_scaling_matrix_present_flag,
+            scaling_lists,
+            log2_max_frame_num_minus4: log2_max_frame_num_minus4 as u8,
+            pic_order_cnt_type: pic_order_cnt_type as u8,
+            log2_max_pic_order_cnt_lsb_minus4: log2_max_pic_order_cnt_lsb_minus4 as u8,
+            delta_pic_order_always_zero_flag,
+            offset_for_non_ref_pic,
+            offset_for_top_to_bottom_field,
+            offset_for_ref_frame,
+            max_num_ref_frames: max_num_ref_frames as u8,
+            gaps_in_frame_num_value_allowed_flag,
+            pic_width_in_mbs_minus1,
+            pic_height_in_map_units_minus1,
+            frame_mbs_only_flag,
+            mb_adaptive_frame_field_flag,
+            direct_8x8_inference_flag,
+            frame_cropping_flag,
+            frame_crop_left_offset: frame_crop[0],
+            frame_crop_right_offset: frame_crop[1],
+            frame_crop_top_offset: frame_crop[2],
+            frame_crop_bottom_offset: frame_crop[3],
+            vui,
+        })
+    }
+
+    /// constraint_set0_flag .. constraint_set5_flag
+    pub fn constraint_set_flag(&self, index: u32) -> bool {
+        self.constraint_set_flags & (0x80 >> index) != 0
+    }
+
+    pub fn chroma_array_type(&self) -> u8 {
+        if self.separate_colour_plane_flag {
+            0
+        } else {
+            self.chroma_format_idc
+        }
+    }
+
+    pub fn max_frame_num(&self) -> u32 {
+        1 << (self.log2_max_frame_num_minus4 + 4)
+    }
+
+    pub fn max_pic_order_cnt_lsb(&self) -> u32 {
+        1 << (self.log2_max_pic_order_cnt_lsb_minus4 + 4)
+    }
+
+    pub fn pic_width_in_mbs(&self) -> u32 {
+        self.pic_width_in_mbs_minus1 + 1
+    }
+
+    /// FrameHeightInMbs
+    pub fn frame_height_in_mbs(&self) -> u32 {
+        (2 - self.frame_mbs_only_flag as u32) * (self.pic_height_in_map_units_minus1 + 1)
+    }
+
+    /// Width and height of the decoded frame in luma samples before cropping.
+    pub fn coded_extent(&self) -> (u32, u32) {
+        (
+            self.pic_width_in_mbs() * 16,
+            self.frame_height_in_mbs() * 16,
+        )
+    }
+
+    /// Frame cropping rectangle as (x, y, width, height) in luma samples.
+    pub fn crop_rect(&self) -> (u32, u32, u32, u32) {
+        let (width, height) = self.coded_extent();
+
+        let (sub_width_c, sub_height_c) = match self.chroma_array_type() {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let crop_unit_x = sub_width_c;
+        let crop_unit_y = sub_height_c * (2 - self.frame_mbs_only_flag as u32);
+
+        let left = self.frame_crop_left_offset * crop_unit_x;
+        let right = self.frame_crop_right_offset * crop_unit_x;
+        let top = self.frame_crop_top_offset * crop_unit_y;
+        let bottom = self.frame_crop_bottom_offset * crop_unit_y;
+
+        (
+            left.min(width),
+            top.min(height),
+            width.saturating_sub(left + right),
+            height.saturating_sub(top + bottom),
+        )
+    }
+
+    /// Duration of a frame from the VUI timing information, a tick being a field period.
+    pub fn frame_duration(&self) -> Option<Timestamp> {
+        let vui = self.vui.as_ref()?;
+        if !vui.timing_info_present_flag || vui.num_units_in_tick == 0 || vui.time_scale == 0 {
+            return None;
+        }
+        Some(Timestamp::new(
+            2 * vui.num_units_in_tick as i64,
+            vui.time_scale,
+        ))
+    }
+
+    /// MaxDpbFrames, from MaxDpbMbs of the level in Table A-1.
+    pub fn max_dpb_frames(&self) -> u32 {
+        let max_dpb_mbs = match self.level_idc {
+            9 | 10 => 396,
+            11 => 900,
+            12 | 13 | 20 => 2376,
+            21 => 4752,
+            22 | 30 => 8100,
+            31 => 18000,
+            32 => 20480,
+            40 | 41 => 32768,
+            42 => 34816,
+            50 => 110400,
+            51 | 52 => 184320,
+            _ => 696320,
+        };
+        (max_dpb_mbs / (self.pic_width_in_mbs() * self.frame_height_in_mbs())).clamp(1, 16)
+    }
+
+    /// Whether max_num_reorder_frames and max_dec_frame_buffering are inferred to be 0
+    /// in the absence of bitstream restrictions, E.2.1.
+    fn is_intra_profile(&self) -> bool {
+        matches!(self.profile_idc, 44 | 86 | 100 | 110 | 122 | 244) && self.constraint_set_flag(3)
+    }
+
+    /// max_num_reorder_frames from the VUI, or its inferred value.
+    pub fn max_num_reorder_frames(&self) -> u32 {
+        match &self.vui {
+            Some(vui) if vui.bitstream_restriction_flag => vui.max_num_reorder_frames,
+            _ if self.is_intra_profile() => 0,
+            _ => self.max_dpb_frames(),
+        }
+    }
+
+    /// max_dec_frame_buffering from the VUI, or its inferred value.
+    pub fn max_dec_frame_buffering(&self) -> u32 {
+        match &self.vui {
+            Some(vui) if vui.bitstream_restriction_flag => vui.max_dec_frame_buffering,
+            _ if self.is_intra_profile() => 0,
+            _ => self.max_dpb_frames(),
+        }
+    }
+
+    /// Maps level_idc onto the StdVideoH264LevelIdc enumeration.
+    pub fn std_level_idc(&self) -> u32 {
+        match self.level_idc {
+            10 => 0,
+            // There is no value for level 1b, which is signalled either as 9 or as 11 together
+            // with constraint_set3_flag, so it is promoted to 1.1.
+            9 | 11 => 1,
+            12 => 2,
+            13 => 3,
+            20 => 4,
+            21 => 5,
+            22 => 6,
+            30 => 7,
+            31 => 8,
+            32 => 9,
+            40 => 10,
+            41 => 11,
+            42 => 12,
+            50 => 13,
+            51 => 14,
+            52 => 15,
+            60 => 16,
+            61 => 17,
+            62 => 18,
+            _ => ash::vk::native::StdVideoH264LevelIdc_STD_VIDEO_H264_LEVEL_IDC_INVALID,
+        }
+    }
+
+    pub fn to_std(&self) -> StdSps {
+        let mut sps: StdVideoH264SequenceParameterSet = unsafe { mem::zeroed() };
+
+        for i in 0..6 {
+            let flag = self.constraint_set_flag(i) as u32;
+            match i {
+                0 => sps.flags.set_constraint_set0_flag(flag),
+                1 => sps.flags.set_constraint_set1_flag(flag),
+                2 => sps.flags.set_constraint_set2_flag(flag),
+                3 => sps.flags.set_constraint_set3_flag(flag),
+                4 => sps.flags.set_constraint_set4_flag(flag),
+                _ => sps.flags.set_constraint_set5_flag(flag),
+            }
+        }
+        sps.flags
+            .set_direct_8x8_inference_flag(self.direct_8x8_inference_flag as u32);
+        sps.flags
+            .set_mb_adaptive_frame_field_flag(self.mb_adaptive_frame_field_flag as u32);
+        sps.flags
+            .set_frame_mbs_only_flag(self.frame_mbs_only_flag as u32);
+        sps.flags
+            .set_delta_pic_order_always_zero_flag(self.delta_pic_order_always_zero_flag as u32);
+        sps.flags
+            .set_separate_colour_plane_flag(self.separate_colour_plane_flag as u32);
+        sps.flags.set_gaps_in_frame_num_value_allowed_flag(
+            self.gaps_in_frame_num_value_allowed_flag as u32,
+        );
+        sps.flags.set_qpprime_y_zero_transform_bypass_flag(
+            self.qpprime_y_zero_transform_bypass_flag as u32,
+        );
+        sps.flags
+            .set_frame_cropping_flag(self.frame_cropping_flag as u32);
+        sps.flags
+            .set_seq_scaling_matrix_present_flag(self.seq_scaling_matrix_present_flag as u32);
+        sps.flags
+            .set_vui_parameters_present_flag(self.vui.is_some() as u32);
+
+        sps.profile_idc = self.profile_idc as u32;
+        sps.level_idc = self.std_level_idc();
+        sps.chroma_format_idc = self.chroma_format_idc as u32;
+        sps.seq_parameter_set_id = self.seq_parameter_set_id;
+        sps.bit_depth_luma_minus8 = self.bit_depth_luma_minus8;
+        sps.bit_depth_chroma_minus8 = self.bit_depth_chroma_minus8;
+        sps.log2_max_frame_num_minus4 = self.log2_max_frame_num_minus4;
+        sps.pic_order_cnt_type = self.pic_order_cnt_type as u32;
+        sps.offset_for_non_ref_pic = self.offset_for_non_ref_pic;
+        sps.offset_for_top_to_bottom_field = self.offset_for_top_to_bottom_field;
+        sps.log2_max_pic_order_cnt_lsb_minus4 = self.log2_max_pic_order_cnt_lsb_minus4;
+        sps.num_ref_frames_in_pic_order_cnt_cycle = self.offset_for_ref_frame.len() as u8;
+        sps.max_num_ref_frames = self.max_num_ref_frames;
+        sps.pic_width_in_mbs_minus1 = self.pic_width_in_mbs_minus1;
+        sps.pic_height_in_map_units_minus1 = self.pic_height_in_map_units_minus1;
+        sps.frame_crop_left_offset = self.frame_crop_left_offset;
+        sps.frame_crop_right_offset = self.frame_crop_right_offset;
+        sps.frame_crop_top_offset = self.frame_crop_top_offset;
+        sps.frame_crop_bottom_offset = self.frame_crop_bottom_offset;
+
+        let mut std = StdSps {
+            sps,
+            scaling_lists: Box::new(self.scaling_lists.to_std()),
+            vui: Box::new(
+                self.vui
+                    .as_ref()
+                    .map(Vui::to_std)
+                    .unwrap_or_else(|| unsafe { mem::zeroed() }),
+            ),
+            hrd: Box::new(
+                self.vui
+                    .as_ref()
+                    .and_then(|vui| {
+                        vui.nal_hrd_parameters
+                            .as_ref()
+                            .or(vui.vcl_hrd_parameters.as_ref())
+                    })
+                    .map(HrdParameters::to_std)
+                    .unwrap_or_else(|| unsafe { mem::zeroed() }),
+            ),
+            offset_for_ref_frame: self.offset_for_ref_frame.clone(),
+        };
+
+        if !std.offset_for_ref_frame.is_empty() {
+            std.sps.pOffsetForRefFrame = std.offset_for_ref_frame.as_ptr();
+        }
+        if self.seq_scaling_matrix_present_flag {
+            std.sps.pScalingLists = &*std.scaling_lists;
+        }
+        if let Some(vui) = &self.vui {
+            if vui.nal_hrd_parameters.is_some() || vui.vcl_hrd_parameters.is_some() {
+                std.vui.pHrdParameters = &*std.hrd;
+            }
+            std.sps.pSequenceParameterSetVui = &*std.vui;
+        }
+
+        std
+    }
+}
+
+/// `StdVideoH264SequenceParameterSet` together with the structures its pointers reference.
+/// The pointers stay valid for as long as this value is alive, moving it is fine.
+pub struct StdSps {
+    pub sps: StdVideoH264SequenceParameterSet,
+    scaling_lists: Box<StdVideoH264ScalingLists>,
+    vui: Box<StdVideoH264SequenceParameterSetVui>,
+    hrd: Box<StdVideoH264HrdParameters>,
+    offset_for_ref_frame: Vec<i32>,
+}
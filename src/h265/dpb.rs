@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Result};
+
+use crate::h265::slice::SliceSegmentHeader;
+use crate::h265::sps::Sps;
+
+/// A picture marked as used for reference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReferencePicture {
+    /// DPB slot holding the reconstructed picture
+    pub slot: usize,
+    /// PicOrderCntVal
+    pub pic_order_cnt: i32,
+    pub long_term: bool,
+}
+
+/// The picture being decoded, returned by [`Dpb::start_picture`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CurrentPicture {
+    /// DPB slot the picture is reconstructed into
+    pub slot: usize,
+    /// PicOrderCntVal
+    pub pic_order_cnt: i32,
+    pub irap: bool,
+    pub idr: bool,
+    /// NoRaslOutputFlag, set for IRAP pictures that start a coded video sequence
+    pub no_rasl_output_flag: bool,
+    /// Cleared for sub-layer non-reference pictures
+    pub is_reference: bool,
+    /// PicOutputFlag
+    pub pic_output_flag: bool,
+    /// DPB slots of RefPicSetStCurrBefore, RefPicSetStCurrAfter and RefPicSetLtCurr.
+    /// `None` stands for "no reference picture", a picture missing from the DPB.
+    pub st_curr_before: Vec<Option<usize>>,
+    pub st_curr_after: Vec<Option<usize>>,
+    pub lt_curr: Vec<Option<usize>>,
+}
+
+/// Picture order count derivation (8.3.1) and the reference picture set (8.3.2) of
+/// H.265, together with the assignment of DPB slots.
+///
+/// Each picture goes through [`Dpb::start_picture`], which computes the POC, applies its
+/// RPS to the reference pictures and picks a free slot, and [`Dpb::finish_picture`] once
+/// it is decoded, which stores it as a short-term reference.
+#[derive(Clone, Debug)]
+pub struct Dpb {
+    slot_count: usize,
+    pictures: Vec<ReferencePicture>,
+    /// Slots of pictures still in use outside the DPB, e.g. waiting for output
+    held_slots: Vec<usize>,
+    /// PicOrderCntVal of prevTid0Pic
+    prev_tid0_pic_order_cnt: i32,
+    /// The next picture is the first of the bitstream or follows an end of sequence
+    first_picture: bool,
+    /// The associated IRAP picture has NoRaslOutputFlag set, its RASL pictures refer to
+    /// pictures that were never decoded
+    skip_rasl: bool,
+}
+
+impl Dpb {
+    /// `slot_count` must leave room for the current picture next to
+    /// sps_max_dec_pic_buffering_minus1 references.
+    pub fn new(slot_count: usize) -> Self {
+        Self {
+            slot_count,
+            pictures: Vec::new(),
+            held_slots: Vec::new(),
+            prev_tid0_pic_order_cnt: 0,
+            first_picture: true,
+            skip_rasl: false,
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    /// Pictures currently marked as used for reference.
+    pub fn references(&self) -> &[ReferencePicture] {
+        &self.pictures
+    }
+
+    /// Marks every picture as unused for reference, e.g. before seeking. Decoding resumes
+    /// at the next IRAP picture. Held slots stay held.
+    pub fn clear(&mut self) {
+        let held_slots = std::mem::take(&mut self.held_slots);
+        *self = Self::new(self.slot_count);
+        self.held_slots = held_slots;
+    }
+
+    /// The picture after an end of sequence NAL unit starts a new coded video sequence.
+    pub fn end_sequence(&mut self) {
+        self.first_picture = true;
+    }
+
+    /// Keeps `slot` from being picked for new pictures after its picture stops being a
+    /// reference, until [`Dpb::release_slot`].
+    pub fn hold_slot(&mut self, slot: usize) {
+        self.held_slots.push(slot);
+    }
+
+    pub fn release_slot(&mut self, slot: usize) {
+        if let Some(index) = self.held_slots.iter().position(|&held| held == slot) {
+            self.held_slots.swap_remove(index);
+        }
+    }
+
+    /// Prepares the decoding of the picture the slice segment belongs to. Returns `None`
+    /// for pictures that cannot be decoded and are skipped: anything before the first
+    /// IRAP picture and the RASL pictures of an IRAP picture that starts decoding.
+    pub fn start_picture(
+        &mut self,
+        header: &SliceSegmentHeader,
+        sps: &Sps,
+    ) -> Result<Option<CurrentPicture>> {
+        let nal_unit_type = header.nal_unit_type;
+        let irap = nal_unit_type.is_irap();
+        let idr = nal_unit_type.is_idr();
+
+        // A CRA picture is handled like a BLA picture when decoding starts at it
+        let no_rasl_output_flag = irap && (idr || nal_unit_type.is_bla() || self.first_picture);
+        if irap {
+            self.skip_rasl = no_rasl_output_flag;
+        } else if self.first_picture || (nal_unit_type.is_rasl() && self.skip_rasl) {
+            return Ok(None);
+        }
+        self.first_picture = false;
+
+        // 8.3.1
+        let max_pic_order_cnt_lsb = sps.max_pic_order_cnt_lsb() as i32;
+        let pic_order_cnt_lsb = header.slice_pic_order_cnt_lsb as i32;
+        let pic_order_cnt_msb = if no_rasl_output_flag {
+            0
+        } else {
+            let prev_pic_order_cnt_lsb = self.prev_tid0_pic_order_cnt & (max_pic_order_cnt_lsb - 1);
+            let prev_pic_order_cnt_msb = self.prev_tid0_pic_order_cnt - prev_pic_order_cnt_lsb;
+            if pic_order_cnt_lsb < prev_pic_order_cnt_lsb
+                && prev_pic_order_cnt_lsb - pic_order_cnt_lsb >= max_pic_order_cnt_lsb / 2
+            {
+                prev_pic_order_cnt_msb + max_pic_order_cnt_lsb
+            } else if pic_order_cnt_lsb > prev_pic_order_cnt_lsb
+                && pic_order_cnt_lsb - prev_pic_order_cnt_lsb > max_pic_order_cnt_lsb / 2
+            {
+                prev_pic_order_cnt_msb - max_pic_order_cnt_lsb
+            } else {
+                prev_pic_order_cnt_msb
+            }
+        };
+        let pic_order_cnt = pic_order_cnt_msb + pic_order_cnt_lsb;
+
+        if header.temporal_id == 0
+            && !nal_unit_type.is_rasl()
+            && !nal_unit_type.is_radl()
+            && !nal_unit_type.is_sub_layer_non_reference()
+        {
+            self.prev_tid0_pic_order_cnt = pic_order_cnt;
+        }
+
+        // 8.3.2
+        if irap && no_rasl_output_flag {
+            self.pictures.clear();
+        }
+
+        let mut poc_st_curr_before = Vec::new();
+        let mut poc_st_curr_after = Vec::new();
+        let mut poc_st_foll = Vec::new();
+        if let Some(set) = header.short_term_ref_pic_set(sps) {
+            for (&delta_poc, &used) in set.delta_poc_s0.iter().zip(&set.used_by_curr_pic_s0) {
+                if used {
+                    poc_st_curr_before.push(pic_order_cnt + delta_poc);
+                } else {
+                    poc_st_foll.push(pic_order_cnt + delta_poc);
+                }
+            }
+            for (&delta_poc, &used) in set.delta_poc_s1.iter().zip(&set.used_by_curr_pic_s1) {
+                if used {
+                    poc_st_curr_after.push(pic_order_cnt + delta_poc);
+                } else {
+                    poc_st_foll.push(pic_order_cnt + delta_poc);
+                }
+            }
+        }
+
+        // (PocLt, delta_poc_msb_present_flag)
+        let mut poc_lt_curr = Vec::new();
+        let mut poc_lt_foll = Vec::new();
+        if !idr {
+            for picture in header.long_term_pics.iter() {
+                let mut poc_lt = picture.poc_lsb_lt as i32;
+                if picture.delta_poc_msb_present_flag {
+                    poc_lt += pic_order_cnt
+                        - picture.delta_poc_msb_cycle_lt as i32 * max_pic_order_cnt_lsb
+                        - (pic_order_cnt & (max_pic_order_cnt_lsb - 1));
+                }
+                if picture.used_by_curr_pic_lt_flag {
+                    poc_lt_curr.push((poc_lt, picture.delta_poc_msb_present_flag));
+                } else {
+                    poc_lt_foll.push((poc_lt, picture.delta_poc_msb_present_flag));
+                }
+            }
+        }
+
+        // Long-term pictures are identified first, among all reference pictures, and only
+        // the remaining short-term ones are candidates for the short-term sets
+        let mut in_rps = vec![false; self.pictures.len()];
+        let mut find_long_term = |pictures: &mut [ReferencePicture], (poc_lt, msb_present)| {
+            let index = pictures.iter().position(|picture| {
+                if msb_present {
+                    picture.pic_order_cnt == poc_lt
+                } else {
+                    picture.pic_order_cnt & (max_pic_order_cnt_lsb - 1) == poc_lt
+                }
+            })?;
+            pictures[index].long_term = true;
+            in_rps[index] = true;
+            Some(pictures[index].slot)
+        };
+        let lt_curr: Vec<_> = poc_lt_curr
+            .into_iter()
+            .map(|poc| find_long_term(&mut self.pictures, poc))
+            .collect();
+        for poc in poc_lt_foll {
+            find_long_term(&mut self.pictures, poc);
+        }
+
+        let mut find_short_term = |pictures: &[ReferencePicture], poc: i32| {
+            let index = pictures
+                .iter()
+                .position(|picture| !picture.long_term && picture.pic_order_cnt == poc)?;
+            in_rps[index] = true;
+            Some(pictures[index].slot)
+        };
+        let st_curr_before: Vec<_> = poc_st_curr_before
+            .into_iter()
+            .map(|poc| find_short_term(&self.pictures, poc))
+            .collect();
+        let st_curr_after: Vec<_> = poc_st_curr_after
+            .into_iter()
+            .map(|poc| find_short_term(&self.pictures, poc))
+            .collect();
+        for poc in poc_st_foll {
+            find_short_term(&self.pictures, poc);
+        }
+
+        // Pictures outside of the RPS are marked as unused for reference
+        let mut in_rps = in_rps.into_iter();
+        self.pictures.retain(|_| in_rps.next().unwrap_or(false));
+
+        let slot = (0..self.slot_count)
+            .find(|&slot| {
+                !self.held_slots.contains(&slot)
+                    && !self.pictures.iter().any(|picture| picture.slot == slot)
+            })
+            .ok_or_else(|| anyhow!("No free DPB slot out of {}", self.slot_count))?;
+
+        Ok(Some(CurrentPicture {
+            slot,
+            pic_order_cnt,
+            irap,
+            idr,
+            no_rasl_output_flag,
+            is_reference: !nal_unit_type.is_sub_layer_non_reference(),
+            // RASL pictures of a CRA picture starting decoding are skipped above
+            pic_output_flag: header.pic_output_flag,
+            st_curr_before,
+            st_curr_after,
+            lt_curr,
+        }))
+    }
+
+    /// Stores the decoded picture, which is marked as used for short-term reference
+    /// until the RPS of a following picture drops it.
+    pub fn finish_picture(&mut self, picture: &CurrentPicture) {
+        self.pictures.push(ReferencePicture {
+            slot: picture.slot,
+            pic_order_cnt: picture.pic_order_cnt,
+            long_term: false,
+        });
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+
+use crate::h265::ParameterSets;
+
+/// HEVCDecoderConfigurationRecord, the payload of the `hvcC` box of `hvc1` and `hev1`
+/// sample entries (ISO/IEC 14496-15 8.3.3.1).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HevcDecoderConfiguration {
+    pub general_profile_idc: u8,
+    pub general_tier_flag: bool,
+    pub general_level_idc: u8,
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    /// Size of the NAL unit length fields of the samples, lengthSizeMinusOne + 1
+    pub length_size: usize,
+    /// The NAL units of all arrays, usually VPS, SPS, PPS and SEI, in record order
+    pub nal_units: Vec<Vec<u8>>,
+}
+
+impl HevcDecoderConfiguration {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        // Fixed part up to and including numOfArrays
+        if data.len() < 23 {
+            return Err(anyhow!("Truncated hvcC of {} bytes", data.len()));
+        }
+        if data[0] != 1 {
+            return Err(anyhow!("Unsupported hvcC version {}", data[0]));
+        }
+
+        let mut nal_units = Vec::new();
+        let mut offset = 23;
+        let read_u16 = |offset: &mut usize| -> Result<usize> {
+            let bytes = data
+                .get(*offset..*offset + 2)
+                .ok_or_else(|| anyhow!("Truncated hvcC NAL unit array"))?;
+            *offset += 2;
+            Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+        };
+        for _ in 0..data[22] {
+            // array_completeness, reserved and NAL_unit_type, the NAL units carry their type
+            offset += 1;
+            let num_nalus = read_u16(&mut offset)?;
+            for _ in 0..num_nalus {
+                let length = read_u16(&mut offset)?;
+                let nal = data
+                    .get(offset..offset + length)
+                    .ok_or_else(|| anyhow!("hvcC NAL unit of {} bytes is truncated", length))?;
+                nal_units.push(nal.to_vec());
+                offset += length;
+            }
+        }
+
+        Ok(Self {
+            general_profile_idc: data[1] & 0x1f,
+            general_tier_flag: data[1] & 0x20 != 0,
+            general_level_idc: data[12],
+            chroma_format_idc: data[16] & 0b11,
+            bit_depth_luma: (data[17] & 0b111) + 8,
+            bit_depth_chroma: (data[18] & 0b111) + 8,
+            length_size: (data[21] & 0b11) as usize + 1,
+            nal_units,
+        })
+    }
+
+    /// The parameter sets of the record. Other NAL units, such as SEI, are ignored.
+    pub fn parameter_sets(&self) -> Result<ParameterSets> {
+        let mut parameter_sets = ParameterSets::default();
+        for nal in self.nal_units.iter() {
+            parameter_sets.add_nal(nal)?;
+        }
+        Ok(parameter_sets)
+    }
+}
@@ -0,0 +1,315 @@
+pub mod dpb;
+pub mod hvcc;
+pub mod pps;
+pub mod slice;
+pub mod sps;
+pub mod vps;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use ash::vk::native::{
+    StdVideoH265PictureParameterSet, StdVideoH265SequenceParameterSet,
+    StdVideoH265VideoParameterSet,
+};
+
+use pps::{Pps, StdPps, MAX_PPS_COUNT};
+use sps::{Sps, StdSps, MAX_SPS_COUNT};
+use vps::{StdVps, Vps, MAX_VPS_COUNT};
+
+/// Table 7-1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NalUnitType {
+    TrailN,
+    TrailR,
+    TsaN,
+    TsaR,
+    StsaN,
+    StsaR,
+    RadlN,
+    RadlR,
+    RaslN,
+    RaslR,
+    BlaWLp,
+    BlaWRadl,
+    BlaNLp,
+    IdrWRadl,
+    IdrNLp,
+    Cra,
+    Vps,
+    Sps,
+    Pps,
+    AccessUnitDelimiter,
+    EndOfSequence,
+    EndOfBitstream,
+    FillerData,
+    PrefixSei,
+    SuffixSei,
+    Reserved(u8),
+    Unspecified(u8),
+}
+
+impl From<u8> for NalUnitType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => NalUnitType::TrailN,
+            1 => NalUnitType::TrailR,
+            2 => NalUnitType::TsaN,
+            3 => NalUnitType::TsaR,
+            4 => NalUnitType::StsaN,
+            5 => NalUnitType::StsaR,
+            6 => NalUnitType::RadlN,
+            7 => NalUnitType::RadlR,
+            8 => NalUnitType::RaslN,
+            9 => NalUnitType::RaslR,
+            16 => NalUnitType::BlaWLp,
+            17 => NalUnitType::BlaWRadl,
+            18 => NalUnitType::BlaNLp,
+            19 => NalUnitType::IdrWRadl,
+            20 => NalUnitType::IdrNLp,
+            21 => NalUnitType::Cra,
+            32 => NalUnitType::Vps,
+            33 => NalUnitType::Sps,
+            34 => NalUnitType::Pps,
+            35 => NalUnitType::AccessUnitDelimiter,
+            36 => NalUnitType::EndOfSequence,
+            37 => NalUnitType::EndOfBitstream,
+            38 => NalUnitType::FillerData,
+            39 => NalUnitType::PrefixSei,
+            40 => NalUnitType::SuffixSei,
+            48..=63 => NalUnitType::Unspecified(value),
+            _ => NalUnitType::Reserved(value),
+        }
+    }
+}
+
+impl NalUnitType {
+    /// Slice segments of a coded picture, including the reserved VCL types.
+    pub fn is_vcl(self) -> bool {
+        match self {
+            NalUnitType::Reserved(value) => value < 32,
+            NalUnitType::Vps
+            | NalUnitType::Sps
+            | NalUnitType::Pps
+            | NalUnitType::AccessUnitDelimiter
+            | NalUnitType::EndOfSequence
+            | NalUnitType::EndOfBitstream
+            | NalUnitType::FillerData
+            | NalUnitType::PrefixSei
+            | NalUnitType::SuffixSei
+            | NalUnitType::Unspecified(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Intra random access point, BLA, IDR or CRA.
+    pub fn is_irap(self) -> bool {
+        matches!(
+            self,
+            NalUnitType::BlaWLp
+                | NalUnitType::BlaWRadl
+                | NalUnitType::BlaNLp
+                | NalUnitType::IdrWRadl
+                | NalUnitType::IdrNLp
+                | NalUnitType::Cra
+                | NalUnitType::Reserved(22 | 23)
+        )
+    }
+
+    pub fn is_idr(self) -> bool {
+        matches!(self, NalUnitType::IdrWRadl | NalUnitType::IdrNLp)
+    }
+
+    pub fn is_bla(self) -> bool {
+        matches!(
+            self,
+            NalUnitType::BlaWLp | NalUnitType::BlaWRadl | NalUnitType::BlaNLp
+        )
+    }
+
+    pub fn is_rasl(self) -> bool {
+        matches!(self, NalUnitType::RaslN | NalUnitType::RaslR)
+    }
+
+    pub fn is_radl(self) -> bool {
+        matches!(self, NalUnitType::RadlN | NalUnitType::RadlR)
+    }
+
+    /// Sub-layer non-reference picture, not used for inter prediction within its sub-layer.
+    pub fn is_sub_layer_non_reference(self) -> bool {
+        matches!(
+            self,
+            NalUnitType::TrailN
+                | NalUnitType::TsaN
+                | NalUnitType::StsaN
+                | NalUnitType::RadlN
+                | NalUnitType::RaslN
+                | NalUnitType::Reserved(10 | 12 | 14)
+        )
+    }
+}
+
+/// The two byte NAL unit header, 7.3.1.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NalUnitHeader {
+    pub nal_unit_type: NalUnitType,
+    pub nuh_layer_id: u8,
+    pub nuh_temporal_id_plus1: u8,
+}
+
+impl NalUnitHeader {
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let bytes = nal
+            .get(..2)
+            .ok_or_else(|| anyhow!("NAL unit too short for its header"))?;
+
+        if bytes[0] & 0x80 != 0 {
+            return Err(anyhow!("forbidden_zero_bit is set"));
+        }
+        let nuh_temporal_id_plus1 = bytes[1] & 0b111;
+        if nuh_temporal_id_plus1 == 0 {
+            return Err(anyhow!("nuh_temporal_id_plus1 is 0"));
+        }
+
+        Ok(Self {
+            nal_unit_type: NalUnitType::from((bytes[0] >> 1) & 0x3f),
+            nuh_layer_id: ((bytes[0] & 1) << 5) | (bytes[1] >> 3),
+            nuh_temporal_id_plus1,
+        })
+    }
+
+    /// TemporalId
+    pub fn temporal_id(&self) -> u8 {
+        self.nuh_temporal_id_plus1 - 1
+    }
+}
+
+/// Active VPS, SPS and PPS tables, indexed by their ids.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParameterSets {
+    vps: Vec<Option<Vps>>,
+    sps: Vec<Option<Sps>>,
+    pps: Vec<Option<Pps>>,
+}
+
+impl Default for ParameterSets {
+    fn default() -> Self {
+        Self {
+            vps: vec![None; MAX_VPS_COUNT],
+            sps: vec![None; MAX_SPS_COUNT],
+            pps: vec![None; MAX_PPS_COUNT],
+        }
+    }
+}
+
+impl ParameterSets {
+    /// Parses a VPS, SPS or PPS NAL unit and stores it under its id, replacing any previous
+    /// one. Returns false for NAL units of any other type and for those of enhancement layers.
+    pub fn add_nal(&mut self, nal: &[u8]) -> Result<bool> {
+        let header = NalUnitHeader::parse(nal)?;
+        if header.nuh_layer_id != 0 {
+            return Ok(false);
+        }
+
+        match header.nal_unit_type {
+            NalUnitType::Vps => {
+                let vps = Vps::parse(nal)?;
+                let id = vps.vps_video_parameter_set_id as usize;
+                self.vps[id] = Some(vps);
+                Ok(true)
+            }
+            NalUnitType::Sps => {
+                let sps = Sps::parse(nal)?;
+                let id = sps.sps_seq_parameter_set_id as usize;
+                self.sps[id] = Some(sps);
+                Ok(true)
+            }
+            NalUnitType::Pps => {
+                let pps = Pps::parse(nal)?;
+                let id = pps.pps_pic_parameter_set_id as usize;
+                self.pps[id] = Some(pps);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn vps(&self, id: u8) -> Option<&Vps> {
+        self.vps.get(id as usize)?.as_ref()
+    }
+
+    pub fn sps(&self, id: u8) -> Option<&Sps> {
+        self.sps.get(id as usize)?.as_ref()
+    }
+
+    pub fn pps(&self, id: u8) -> Option<&Pps> {
+        self.pps.get(id as usize)?.as_ref()
+    }
+
+    /// Looks up a PPS together with the SPS it depends on.
+    pub fn active(&self, pps_pic_parameter_set_id: u8) -> Result<(&Pps, &Sps)> {
+        let pps = self
+            .pps(pps_pic_parameter_set_id)
+            .ok_or_else(|| anyhow!("Unknown PPS {}", pps_pic_parameter_set_id))?;
+        let sps = self.sps(pps.pps_seq_parameter_set_id).ok_or_else(|| {
+            anyhow!(
+                "PPS {} references unknown SPS {}",
+                pps_pic_parameter_set_id,
+                pps.pps_seq_parameter_set_id
+            )
+        })?;
+        Ok((pps, sps))
+    }
+
+    pub fn vps_count(&self) -> usize {
+        self.vps.iter().flatten().count()
+    }
+
+    pub fn sps_count(&self) -> usize {
+        self.sps.iter().flatten().count()
+    }
+
+    pub fn pps_count(&self) -> usize {
+        self.pps.iter().flatten().count()
+    }
+
+    /// PPS whose SPS is missing are left out, they cannot be used before it arrives.
+    pub fn to_std(&self) -> StdParameterSets {
+        let vps_backing: Vec<StdVps> = self.vps.iter().flatten().map(Vps::to_std).collect();
+        let sps_backing: Vec<StdSps> = self.sps.iter().flatten().map(Sps::to_std).collect();
+        let pps_backing: Vec<StdPps> = self
+            .pps
+            .iter()
+            .flatten()
+            .filter_map(|pps| Some(pps.to_std(self.sps(pps.pps_seq_parameter_set_id)?)))
+            .collect();
+
+        StdParameterSets {
+            vps: vps_backing.iter().map(|vps| vps.vps).collect(),
+            sps: sps_backing.iter().map(|sps| sps.sps).collect(),
+            pps: pps_backing.iter().map(|pps| pps.pps).collect(),
+            _vps_backing: vps_backing,
+            _sps_backing: sps_backing,
+            _pps_backing: pps_backing,
+        }
+    }
+}
+
+/// Contiguous arrays of std parameter sets, as expected by
+/// `VkVideoDecodeH265SessionParametersAddInfoKHR`.
+pub struct StdParameterSets {
+    pub vps: Vec<StdVideoH265VideoParameterSet>,
+    pub sps: Vec<StdVideoH265SequenceParameterSet>,
+    pub pps: Vec<StdVideoH265PictureParameterSet>,
+    _vps_backing: Vec<StdVps>,
+    _sps_backing: Vec<StdSps>,
+    _pps_backing: Vec<StdPps>,
+}
+
+impl StdParameterSets {
+    pub fn add_info(&self) -> vk::VideoDecodeH265SessionParametersAddInfoKHR<'_> {
+        vk::VideoDecodeH265SessionParametersAddInfoKHR::default()
+            .std_vp_ss(&self.vps)
+            .std_sp_ss(&self.sps)
+            .std_pp_ss(&self.pps)
+    }
+}
@@ -0,0 +1,395 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{StdVideoH265PictureParameterSet, StdVideoH265ScalingLists};
+
+use crate::bitreader::{nal_to_rbsp, BitReader};
+use crate::h265::sps::{ScalingLists, Sps};
+use crate::h265::{NalUnitHeader, NalUnitType};
+
+pub const MAX_PPS_COUNT: usize = 64;
+
+/// The pps_range_extension(), 7.3.2.3.2
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RangeExtension {
+    pub log2_max_transform_skip_block_size_minus2: u8,
+    pub cross_component_prediction_enabled_flag: bool,
+    pub chroma_qp_offset_list_enabled_flag: bool,
+    pub diff_cu_chroma_qp_offset_depth: u8,
+    pub cb_qp_offset_list: Vec<i8>,
+    pub cr_qp_offset_list: Vec<i8>,
+    pub log2_sao_offset_scale_luma: u8,
+    pub log2_sao_offset_scale_chroma: u8,
+}
+
+/// Picture parameter set, 7.3.2.3. The multilayer, 3D and SCC extensions are not parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pps {
+    pub pps_pic_parameter_set_id: u8,
+    pub pps_seq_parameter_set_id: u8,
+    pub dependent_slice_segments_enabled_flag: bool,
+    pub output_flag_present_flag: bool,
+    pub num_extra_slice_header_bits: u8,
+    pub sign_data_hiding_enabled_flag: bool,
+    pub cabac_init_present_flag: bool,
+    pub num_ref_idx_l0_default_active_minus1: u8,
+    pub num_ref_idx_l1_default_active_minus1: u8,
+    pub init_qp_minus26: i8,
+    pub constrained_intra_pred_flag: bool,
+    pub transform_skip_enabled_flag: bool,
+    pub cu_qp_delta_enabled_flag: bool,
+    pub diff_cu_qp_delta_depth: u8,
+    pub pps_cb_qp_offset: i8,
+    pub pps_cr_qp_offset: i8,
+    pub pps_slice_chroma_qp_offsets_present_flag: bool,
+    pub weighted_pred_flag: bool,
+    pub weighted_bipred_flag: bool,
+    pub transquant_bypass_enabled_flag: bool,
+    pub tiles_enabled_flag: bool,
+    pub entropy_coding_sync_enabled_flag: bool,
+    pub num_tile_columns_minus1: u8,
+    pub num_tile_rows_minus1: u8,
+    pub uniform_spacing_flag: bool,
+    pub column_width_minus1: Vec<u16>,
+    pub row_height_minus1: Vec<u16>,
+    pub loop_filter_across_tiles_enabled_flag: bool,
+    pub pps_loop_filter_across_slices_enabled_flag: bool,
+    pub deblocking_filter_control_present_flag: bool,
+    pub deblocking_filter_override_enabled_flag: bool,
+    pub pps_deblocking_filter_disabled_flag: bool,
+    pub pps_beta_offset_div2: i8,
+    pub pps_tc_offset_div2: i8,
+    pub pps_scaling_list_data_present_flag: bool,
+    pub scaling_lists: ScalingLists,
+    pub lists_modification_present_flag: bool,
+    pub log2_parallel_merge_level_minus2: u8,
+    pub slice_segment_header_extension_present_flag: bool,
+    pub pps_extension_present_flag: bool,
+    pub range_extension: Option<RangeExtension>,
+}
+
+fn read_se_in(reader: &mut BitReader, name: &str, min: i32, max: i32) -> Result<i8> {
+    let value = reader.read_se()?;
+    if !(min..=max).contains(&value) {
+        return Err(anyhow!("Invalid {} {}", name, value));
+    }
+    Ok(value as i8)
+}
+
+fn read_ue_max(reader: &mut BitReader, name: &str, max: u32) -> Result<u8> {
+    let value = reader.read_ue()?;
+    if value > max {
+        return Err(anyhow!("Invalid {} {}", name, value));
+    }
+    Ok(value as u8)
+}
+
+impl Pps {
+    /// Parses a PPS NAL unit, including its two byte header and any emulation prevention
+    /// bytes. Unlike in H.264 the syntax does not depend on the SPS.
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let header = NalUnitHeader::parse(nal)?;
+        if header.nal_unit_type != NalUnitType::Pps {
+            return Err(anyhow!(
+                "Expected a PPS NAL unit, got {:?}",
+                header.nal_unit_type
+            ));
+        }
+
+        let rbsp = nal_to_rbsp(&nal[2..]);
+        Self::parse_rbsp(&mut BitReader::new(&rbsp))
+    }
+
+    pub fn parse_rbsp(reader: &mut BitReader) -> Result<Self> {
+        let pps_pic_parameter_set_id =
+            read_ue_max(reader, "pps_pic_parameter_set_id", MAX_PPS_COUNT as u32 - 1)?;
+        let pps_seq_parameter_set_id = read_ue_max(reader, "pps_seq_parameter_set_id", 15)?;
+        let dependent_slice_segments_enabled_flag = reader.read_flag()?;
+        let output_flag_present_flag = reader.read_flag()?;
+        let num_extra_slice_header_bits = reader.read_bits(3)? as u8;
+        let sign_data_hiding_enabled_flag = reader.read_flag()?;
+        let cabac_init_present_flag = reader.read_flag()?;
+        let num_ref_idx_l0_default_active_minus1 =
+            read_ue_max(reader, "num_ref_idx_l0_default_active_minus1", 14)?;
+        let num_ref_idx_l1_default_active_minus1 =
+            read_ue_max(reader, "num_ref_idx_l1_default_active_minus1", 14)?;
+        // The lower bound depends on the bit depth, -(26 + QpBdOffsetY)
+        let init_qp_minus26 = read_se_in(reader, "init_qp_minus26", -74, 25)?;
+        let constrained_intra_pred_flag = reader.read_flag()?;
+        let transform_skip_enabled_flag = reader.read_flag()?;
+
+        let cu_qp_delta_enabled_flag = reader.read_flag()?;
+        let diff_cu_qp_delta_depth = if cu_qp_delta_enabled_flag {
+            read_ue_max(reader, "diff_cu_qp_delta_depth", 6)?
+        } else {
+            0
+        };
+
+        let pps_cb_qp_offset = read_se_in(reader, "pps_cb_qp_offset", -12, 12)?;
+        let pps_cr_qp_offset = read_se_in(reader, "pps_cr_qp_offset", -12, 12)?;
+        let pps_slice_chroma_qp_offsets_present_flag = reader.read_flag()?;
+        let weighted_pred_flag = reader.read_flag()?;
+        let weighted_bipred_flag = reader.read_flag()?;
+        let transquant_bypass_enabled_flag = reader.read_flag()?;
+        let tiles_enabled_flag = reader.read_flag()?;
+        let entropy_coding_sync_enabled_flag = reader.read_flag()?;
+
+        let mut num_tile_columns_minus1 = 0;
+        let mut num_tile_rows_minus1 = 0;
+        let mut uniform_spacing_flag = true;
+        let mut column_width_minus1 = Vec::new();
+        let mut row_height_minus1 = Vec::new();
+        let mut loop_filter_across_tiles_enabled_flag = true;
+        if tiles_enabled_flag {
+            // Limited by the std structure, levels allow at most 20 columns and 22 rows
+            num_tile_columns_minus1 = read_ue_max(reader, "num_tile_columns_minus1", 19)?;
+            num_tile_rows_minus1 = read_ue_max(reader, "num_tile_rows_minus1", 21)?;
+            uniform_spacing_flag = reader.read_flag()?;
+            if !uniform_spacing_flag {
+                for _ in 0..num_tile_columns_minus1 {
+                    column_width_minus1.push(reader.read_ue()?.min(u16::MAX as u32) as u16);
+                }
+                for _ in 0..num_tile_rows_minus1 {
+                    row_height_minus1.push(reader.read_ue()?.min(u16::MAX as u32) as u16);
+                }
+            }
+            loop_filter_across_tiles_enabled_flag = reader.read_flag()?;
+        }
+        let pps_loop_filter_across_slices_enabled_flag = reader.read_flag()?;
+
+        let deblocking_filter_control_present_flag = reader.read_flag()?;
+        let mut deblocking_filter_override_enabled_flag = false;
+        let mut pps_deblocking_filter_disabled_flag = false;
+        let mut pps_beta_offset_div2 = 0;
+        let mut pps_tc_offset_div2 = 0;
+        if deblocking_filter_control_present_flag {
+            deblocking_filter_override_enabled_flag = reader.read_flag()?;
+            pps_deblocking_filter_disabled_flag = reader.read_flag()?;
+            if !pps_deblocking_filter_disabled_flag {
+                pps_beta_offset_div2 = read_se_in(reader, "pps_beta_offset_div2", -6, 6)?;
+                pps_tc_offset_div2 = read_se_in(reader, "pps_tc_offset_div2", -6, 6)?;
+            }
+        }
+
+        let pps_scaling_list_data_present_flag = reader.read_flag()?;
+        let scaling_lists = if pps_scaling_list_data_present_flag {
+            ScalingLists::parse(reader)?
+        } else {
+            ScalingLists::default()
+        };
+
+        let lists_modification_present_flag = reader.read_flag()?;
+        let log2_parallel_merge_level_minus2 = reader.read_ue()?.min(u8::MAX as u32) as u8;
+        let slice_segment_header_extension_present_flag = reader.read_flag()?;
+
+        let pps_extension_present_flag = reader.read_flag()?;
+        let mut range_extension = None;
+        if pps_extension_present_flag {
+            let pps_range_extension_flag = reader.read_flag()?;
+            // pps_multilayer_extension_flag, pps_3d_extension_flag, pps_scc_extension_flag
+            // and pps_extension_4bits
+            reader.skip_bits(7)?;
+            if pps_range_extension_flag {
+                let mut range = RangeExtension::default();
+                if transform_skip_enabled_flag {
+                    range.log2_max_transform_skip_block_size_minus2 =
+                        read_ue_max(reader, "log2_max_transform_skip_block_size_minus2", 3)?;
+                }
+                range.cross_component_prediction_enabled_flag = reader.read_flag()?;
+                range.chroma_qp_offset_list_enabled_flag = reader.read_flag()?;
+                if range.chroma_qp_offset_list_enabled_flag {
+                    range.diff_cu_chroma_qp_offset_depth =
+                        read_ue_max(reader, "diff_cu_chroma_qp_offset_depth", 6)?;
+                    let chroma_qp_offset_list_len_minus1 =
+                        read_ue_max(reader, "chroma_qp_offset_list_len_minus1", 5)?;
+                    for _ in 0..=chroma_qp_offset_list_len_minus1 {
+                        range.cb_qp_offset_list.push(read_se_in(
+                            reader,
+                            "cb_qp_offset_list",
+                            -12,
+                            12,
+                        )?);
+                        range.cr_qp_offset_list.push(read_se_in(
+                            reader,
+                            "cr_qp_offset_list",
+                            -12,
+                            12,
+                        )?);
+                    }
+                }
+                range.log2_sao_offset_scale_luma =
+                    read_ue_max(reader, "log2_sao_offset_scale_luma", 10)?;
+                range.log2_sao_offset_scale_chroma =
+                    read_ue_max(reader, "log2_sao_offset_scale_chroma", 10)?;
+                range_extension = Some(range);
+            }
+        }
+
+        Ok(Self {
+            pps_pic_parameter_set_id,
+            pps_seq_parameter_set_id,
+            dependent_slice_segments_enabled_flag,
+            output_flag_present_flag,
+            num_extra_slice_header_bits,
+            sign_data_hiding_enabled_flag,
+            cabac_init_present_flag,
+            num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_default_active_minus1,
+            init_qp_minus26,
+            constrained_intra_pred_flag,
+            transform_skip_enabled_flag,
+            cu_qp_delta_enabled_flag,
+            diff_cu_qp_delta_depth,
+            pps_cb_qp_offset,
+            pps_cr_qp_offset,
+            pps_slice_chroma_qp_offsets_present_flag,
+            weighted_pred_flag,
+            weighted_bipred_flag,
+            transquant_bypass_enabled_flag,
+            tiles_enabled_flag,
+            entropy_coding_sync_enabled_flag,
+            num_tile_columns_minus1,
+            num_tile_rows_minus1,
+            uniform_spacing_flag,
+            column_width_minus1,
+            row_height_minus1,
+            loop_filter_across_tiles_enabled_flag,
+            pps_loop_filter_across_slices_enabled_flag,
+            deblocking_filter_control_present_flag,
+            deblocking_filter_override_enabled_flag,
+            pps_deblocking_filter_disabled_flag,
+            pps_beta_offset_div2,
+            pps_tc_offset_div2,
+            pps_scaling_list_data_present_flag,
+            scaling_lists,
+            lists_modification_present_flag,
+            log2_parallel_merge_level_minus2,
+            slice_segment_header_extension_present_flag,
+            pps_extension_present_flag,
+            range_extension,
+        })
+    }
+
+    /// `sps` is the SPS the PPS refers to, which supplies the VPS id.
+    pub fn to_std(&self, sps: &Sps) -> StdPps {
+        let mut pps: StdVideoH265PictureParameterSet = unsafe { mem::zeroed() };
+
+        pps.flags.set_dependent_slice_segments_enabled_flag(
+            self.dependent_slice_segments_enabled_flag as u32,
+        );
+        pps.flags
+            .set_output_flag_present_flag(self.output_flag_present_flag as u32);
+        pps.flags
+            .set_sign_data_hiding_enabled_flag(self.sign_data_hiding_enabled_flag as u32);
+        pps.flags
+            .set_cabac_init_present_flag(self.cabac_init_present_flag as u32);
+        pps.flags
+            .set_constrained_intra_pred_flag(self.constrained_intra_pred_flag as u32);
+        pps.flags
+            .set_transform_skip_enabled_flag(self.transform_skip_enabled_flag as u32);
+        pps.flags
+            .set_cu_qp_delta_enabled_flag(self.cu_qp_delta_enabled_flag as u32);
+        pps.flags.set_pps_slice_chroma_qp_offsets_present_flag(
+            self.pps_slice_chroma_qp_offsets_present_flag as u32,
+        );
+        pps.flags
+            .set_weighted_pred_flag(self.weighted_pred_flag as u32);
+        pps.flags
+            .set_weighted_bipred_flag(self.weighted_bipred_flag as u32);
+        pps.flags
+            .set_transquant_bypass_enabled_flag(self.transquant_bypass_enabled_flag as u32);
+        pps.flags
+            .set_tiles_enabled_flag(self.tiles_enabled_flag as u32);
+        pps.flags
+            .set_entropy_coding_sync_enabled_flag(self.entropy_coding_sync_enabled_flag as u32);
+        pps.flags
+            .set_uniform_spacing_flag(self.uniform_spacing_flag as u32);
+        pps.flags.set_loop_filter_across_tiles_enabled_flag(
+            self.loop_filter_across_tiles_enabled_flag as u32,
+        );
+        pps.flags.set_pps_loop_filter_across_slices_enabled_flag(
+            self.pps_loop_filter_across_slices_enabled_flag as u32,
+        );
+        pps.flags.set_deblocking_filter_control_present_flag(
+            self.deblocking_filter_control_present_flag as u32,
+        );
+        pps.flags.set_deblocking_filter_override_enabled_flag(
+            self.deblocking_filter_override_enabled_flag as u32,
+        );
+        pps.flags.set_pps_deblocking_filter_disabled_flag(
+            self.pps_deblocking_filter_disabled_flag as u32,
+        );
+        pps.flags
+            .set_pps_scaling_list_data_present_flag(self.pps_scaling_list_data_present_flag as u32);
+        pps.flags
+            .set_lists_modification_present_flag(self.lists_modification_present_flag as u32);
+        pps.flags.set_slice_segment_header_extension_present_flag(
+            self.slice_segment_header_extension_present_flag as u32,
+        );
+        pps.flags
+            .set_pps_extension_present_flag(self.pps_extension_present_flag as u32);
+        pps.flags
+            .set_pps_range_extension_flag(self.range_extension.is_some() as u32);
+
+        pps.pps_pic_parameter_set_id = self.pps_pic_parameter_set_id;
+        pps.pps_seq_parameter_set_id = self.pps_seq_parameter_set_id;
+        pps.sps_video_parameter_set_id = sps.sps_video_parameter_set_id;
+        pps.num_extra_slice_header_bits = self.num_extra_slice_header_bits;
+        pps.num_ref_idx_l0_default_active_minus1 = self.num_ref_idx_l0_default_active_minus1;
+        pps.num_ref_idx_l1_default_active_minus1 = self.num_ref_idx_l1_default_active_minus1;
+        pps.init_qp_minus26 = self.init_qp_minus26;
+        pps.diff_cu_qp_delta_depth = self.diff_cu_qp_delta_depth;
+        pps.pps_cb_qp_offset = self.pps_cb_qp_offset;
+        pps.pps_cr_qp_offset = self.pps_cr_qp_offset;
+        pps.pps_beta_offset_div2 = self.pps_beta_offset_div2;
+        pps.pps_tc_offset_div2 = self.pps_tc_offset_div2;
+        pps.log2_parallel_merge_level_minus2 = self.log2_parallel_merge_level_minus2;
+        pps.num_tile_columns_minus1 = self.num_tile_columns_minus1;
+        pps.num_tile_rows_minus1 = self.num_tile_rows_minus1;
+        for (i, &width) in self.column_width_minus1.iter().enumerate() {
+            pps.column_width_minus1[i] = width;
+        }
+        for (i, &height) in self.row_height_minus1.iter().enumerate() {
+            pps.row_height_minus1[i] = height;
+        }
+
+        if let Some(range) = &self.range_extension {
+            pps.flags.set_cross_component_prediction_enabled_flag(
+                range.cross_component_prediction_enabled_flag as u32,
+            );
+            pps.flags.set_chroma_qp_offset_list_enabled_flag(
+                range.chroma_qp_offset_list_enabled_flag as u32,
+            );
+            pps.log2_max_transform_skip_block_size_minus2 =
+                range.log2_max_transform_skip_block_size_minus2;
+            pps.diff_cu_chroma_qp_offset_depth = range.diff_cu_chroma_qp_offset_depth;
+            pps.chroma_qp_offset_list_len_minus1 =
+                range.cb_qp_offset_list.len().saturating_sub(1) as u8;
+            for (i, &offset) in range.cb_qp_offset_list.iter().enumerate() {
+                pps.cb_qp_offset_list[i] = offset;
+            }
+            for (i, &offset) in range.cr_qp_offset_list.iter().enumerate() {
+                pps.cr_qp_offset_list[i] = offset;
+            }
+            pps.log2_sao_offset_scale_luma = range.log2_sao_offset_scale_luma;
+            pps.log2_sao_offset_scale_chroma = range.log2_sao_offset_scale_chroma;
+        }
+
+        let mut std = StdPps {
+            pps,
+            scaling_lists: Box::new(self.scaling_lists.to_std()),
+        };
+        if self.pps_scaling_list_data_present_flag {
+            std.pps.pScalingLists = &*std.scaling_lists;
+        }
+
+        std
+    }
+}
+
+/// `StdVideoH265PictureParameterSet` together with the scaling lists it references.
+/// The pointer stays valid for as long as this value is alive, moving it is fine.
+pub struct StdPps {
+    pub pps: StdVideoH265PictureParameterSet,
+    scaling_lists: Box<StdVideoH265ScalingLists>,
+}
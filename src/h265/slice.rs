@@ -0,0 +1,313 @@
+use anyhow::{anyhow, Result};
+
+use crate::bitreader::{nal_to_rbsp, BitReader};
+use crate::h264::NalUnits;
+use crate::h265::sps::{ShortTermRefPicSet, Sps};
+use crate::h265::{NalUnitHeader, NalUnitType, ParameterSets};
+
+/// Table 7-7
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceType {
+    B,
+    P,
+    I,
+}
+
+impl SliceType {
+    fn from_u32(value: u32) -> Result<Self> {
+        match value {
+            0 => Ok(SliceType::B),
+            1 => Ok(SliceType::P),
+            2 => Ok(SliceType::I),
+            _ => Err(anyhow!("Invalid slice_type {}", value)),
+        }
+    }
+}
+
+/// A long-term reference picture signalled in the slice header, with the values taken from
+/// the SPS candidates already resolved.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LongTermPicture {
+    /// PocLsbLt
+    pub poc_lsb_lt: u32,
+    /// UsedByCurrPicLt
+    pub used_by_curr_pic_lt_flag: bool,
+    pub delta_poc_msb_present_flag: bool,
+    /// DeltaPocMsbCycleLt, accumulated over the entries
+    pub delta_poc_msb_cycle_lt: u32,
+}
+
+/// Slice segment header, 7.3.6.1, up to slice_temporal_mvp_enabled_flag. The remaining
+/// syntax elements only matter for decoding the slice data. Dependent slice segments
+/// inherit the rest of their header from the preceding independent one, only the fields
+/// before `dependent_slice_segment_flag` and the address are set for them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SliceSegmentHeader {
+    pub nal_unit_type: NalUnitType,
+    pub temporal_id: u8,
+    pub first_slice_segment_in_pic_flag: bool,
+    pub no_output_of_prior_pics_flag: bool,
+    pub slice_pic_parameter_set_id: u8,
+    pub dependent_slice_segment_flag: bool,
+    pub slice_segment_address: u32,
+    pub slice_type: SliceType,
+    pub pic_output_flag: bool,
+    pub colour_plane_id: u8,
+    pub slice_pic_order_cnt_lsb: u32,
+    pub short_term_ref_pic_set_sps_flag: bool,
+    /// The set coded in the slice header when short_term_ref_pic_set_sps_flag is 0
+    pub short_term_ref_pic_set: Option<ShortTermRefPicSet>,
+    pub short_term_ref_pic_set_idx: u8,
+    /// Size of the short-term set coded in the slice header, NumBitsForSTRefPicSetInSlice
+    pub st_ref_pic_set_bits: usize,
+    pub long_term_pics: Vec<LongTermPicture>,
+    pub slice_temporal_mvp_enabled_flag: bool,
+}
+
+impl SliceSegmentHeader {
+    /// Parses the header of a coded slice segment NAL unit.
+    pub fn parse(nal: &[u8], parameter_sets: &ParameterSets) -> Result<Self> {
+        let nal_header = NalUnitHeader::parse(nal)?;
+        let nal_unit_type = nal_header.nal_unit_type;
+        if !nal_unit_type.is_vcl() {
+            return Err(anyhow!(
+                "Expected a coded slice segment NAL unit, got {:?}",
+                nal_unit_type
+            ));
+        }
+
+        let rbsp = nal_to_rbsp(&nal[2..]);
+        let mut reader = BitReader::new(&rbsp);
+
+        let mut header = SliceSegmentHeader {
+            nal_unit_type,
+            temporal_id: nal_header.temporal_id(),
+            first_slice_segment_in_pic_flag: reader.read_flag()?,
+            no_output_of_prior_pics_flag: false,
+            slice_pic_parameter_set_id: 0,
+            dependent_slice_segment_flag: false,
+            slice_segment_address: 0,
+            slice_type: SliceType::I,
+            pic_output_flag: true,
+            colour_plane_id: 0,
+            slice_pic_order_cnt_lsb: 0,
+            short_term_ref_pic_set_sps_flag: false,
+            short_term_ref_pic_set: None,
+            short_term_ref_pic_set_idx: 0,
+            st_ref_pic_set_bits: 0,
+            long_term_pics: Vec::new(),
+            slice_temporal_mvp_enabled_flag: false,
+        };
+        if nal_unit_type.is_irap() {
+            header.no_output_of_prior_pics_flag = reader.read_flag()?;
+        }
+        let slice_pic_parameter_set_id = reader.read_ue()?;
+        if slice_pic_parameter_set_id > 63 {
+            return Err(anyhow!(
+                "Invalid slice_pic_parameter_set_id {}",
+                slice_pic_parameter_set_id
+            ));
+        }
+        header.slice_pic_parameter_set_id = slice_pic_parameter_set_id as u8;
+
+        let (pps, sps) = parameter_sets.active(header.slice_pic_parameter_set_id)?;
+
+        if !header.first_slice_segment_in_pic_flag {
+            if pps.dependent_slice_segments_enabled_flag {
+                header.dependent_slice_segment_flag = reader.read_flag()?;
+            }
+            let bits = ceil_log2(sps.pic_size_in_ctbs());
+            header.slice_segment_address = reader.read_bits(bits)?;
+        }
+        if header.dependent_slice_segment_flag {
+            return Ok(header);
+        }
+
+        // slice_reserved_flag
+        reader.skip_bits(pps.num_extra_slice_header_bits as usize)?;
+        header.slice_type = SliceType::from_u32(reader.read_ue()?)?;
+        if pps.output_flag_present_flag {
+            header.pic_output_flag = reader.read_flag()?;
+        }
+        if sps.separate_colour_plane_flag {
+            header.colour_plane_id = reader.read_bits(2)? as u8;
+        }
+
+        if !nal_unit_type.is_idr() {
+            header.slice_pic_order_cnt_lsb =
+                reader.read_bits(sps.log2_max_pic_order_cnt_lsb_minus4 as u32 + 4)?;
+            header.short_term_ref_pic_set_sps_flag = reader.read_flag()?;
+            let num_short_term_ref_pic_sets = sps.short_term_ref_pic_sets.len();
+            if !header.short_term_ref_pic_set_sps_flag {
+                let start = reader.position();
+                header.short_term_ref_pic_set = Some(ShortTermRefPicSet::parse(
+                    &mut reader,
+                    num_short_term_ref_pic_sets,
+                    &sps.short_term_ref_pic_sets,
+                    num_short_term_ref_pic_sets,
+                )?);
+                header.st_ref_pic_set_bits = reader.position() - start;
+            } else {
+                if num_short_term_ref_pic_sets == 0 {
+                    return Err(anyhow!(
+                        "Slice refers to a short-term RPS the SPS does not have"
+                    ));
+                }
+                if num_short_term_ref_pic_sets > 1 {
+                    let bits = ceil_log2(num_short_term_ref_pic_sets as u32);
+                    header.short_term_ref_pic_set_idx = reader.read_bits(bits)? as u8;
+                }
+                if header.short_term_ref_pic_set_idx as usize >= num_short_term_ref_pic_sets {
+                    return Err(anyhow!(
+                        "Invalid short_term_ref_pic_set_idx {}",
+                        header.short_term_ref_pic_set_idx
+                    ));
+                }
+            }
+
+            if sps.long_term_ref_pics_present_flag {
+                header.long_term_pics = parse_long_term_pics(&mut reader, sps)?;
+            }
+            if sps.sps_temporal_mvp_enabled_flag {
+                header.slice_temporal_mvp_enabled_flag = reader.read_flag()?;
+            }
+        }
+
+        Ok(header)
+    }
+
+    /// The short-term reference picture set of the picture, `None` for IDR pictures.
+    pub fn short_term_ref_pic_set<'a>(&'a self, sps: &'a Sps) -> Option<&'a ShortTermRefPicSet> {
+        if self.nal_unit_type.is_idr() {
+            return None;
+        }
+        match &self.short_term_ref_pic_set {
+            Some(set) => Some(set),
+            None => sps
+                .short_term_ref_pic_sets
+                .get(self.short_term_ref_pic_set_idx as usize),
+        }
+    }
+
+    /// NumDeltaPocs of the set the slice header's short-term set is predicted from, as
+    /// `StdVideoDecodeH265PictureInfo::NumDeltaPocsOfRefRpsIdx` wants it.
+    pub fn num_delta_pocs_of_ref_rps_idx(&self, sps: &Sps) -> usize {
+        match &self.short_term_ref_pic_set {
+            Some(set) if set.inter_ref_pic_set_prediction_flag => {
+                let num_short_term_ref_pic_sets = sps.short_term_ref_pic_sets.len();
+                num_short_term_ref_pic_sets
+                    .checked_sub(set.delta_idx_minus1 as usize + 1)
+                    .and_then(|idx| sps.short_term_ref_pic_sets.get(idx))
+                    .map_or(0, ShortTermRefPicSet::num_delta_pocs)
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Ceil(Log2(value))
+fn ceil_log2(value: u32) -> u32 {
+    u32::BITS - value.saturating_sub(1).leading_zeros()
+}
+
+fn parse_long_term_pics(reader: &mut BitReader, sps: &Sps) -> Result<Vec<LongTermPicture>> {
+    let num_long_term_ref_pics_sps = sps.long_term_ref_pics_sps.len();
+    let num_long_term_sps = if num_long_term_ref_pics_sps > 0 {
+        reader.read_ue()? as usize
+    } else {
+        0
+    };
+    if num_long_term_sps > num_long_term_ref_pics_sps {
+        return Err(anyhow!("Invalid num_long_term_sps {}", num_long_term_sps));
+    }
+    let num_long_term_pics = reader.read_ue()? as usize;
+    if num_long_term_sps + num_long_term_pics > 32 {
+        return Err(anyhow!("Invalid num_long_term_pics {}", num_long_term_pics));
+    }
+
+    let mut pictures: Vec<LongTermPicture> = Vec::new();
+    for i in 0..num_long_term_sps + num_long_term_pics {
+        let mut picture = LongTermPicture::default();
+        if i < num_long_term_sps {
+            let lt_idx_sps = if num_long_term_ref_pics_sps > 1 {
+                reader.read_bits(ceil_log2(num_long_term_ref_pics_sps as u32))? as usize
+            } else {
+                0
+            };
+            let candidate = sps
+                .long_term_ref_pics_sps
+                .get(lt_idx_sps)
+                .ok_or_else(|| anyhow!("Invalid lt_idx_sps {}", lt_idx_sps))?;
+            picture.poc_lsb_lt = candidate.lt_ref_pic_poc_lsb_sps;
+            picture.used_by_curr_pic_lt_flag = candidate.used_by_curr_pic_lt_sps_flag;
+        } else {
+            picture.poc_lsb_lt =
+                reader.read_bits(sps.log2_max_pic_order_cnt_lsb_minus4 as u32 + 4)?;
+            picture.used_by_curr_pic_lt_flag = reader.read_flag()?;
+        }
+
+        picture.delta_poc_msb_present_flag = reader.read_flag()?;
+        if picture.delta_poc_msb_present_flag {
+            picture.delta_poc_msb_cycle_lt = reader.read_ue()?;
+        }
+        // 7-52, the cycles add up within the SPS candidates and within the explicit ones
+        if i != 0 && i != num_long_term_sps {
+            picture.delta_poc_msb_cycle_lt += pictures[i - 1].delta_poc_msb_cycle_lt;
+        }
+        pictures.push(picture);
+    }
+
+    Ok(pictures)
+}
+
+/// A coded slice segment located inside an Annex-B formatted access unit.
+#[derive(Clone, Debug)]
+pub struct SliceSegment {
+    /// Offset of the segment's start code from the beginning of the access unit
+    pub offset: usize,
+    /// Size in bytes, start code included
+    pub size: usize,
+    pub header: SliceSegmentHeader,
+}
+
+/// Finds and parses every coded slice segment of an Annex-B formatted access unit.
+/// Parameter sets found along the way are added to `parameter_sets`.
+pub fn parse_slice_segments(
+    access_unit: &[u8],
+    parameter_sets: &mut ParameterSets,
+) -> Result<Vec<SliceSegment>> {
+    let mut segments = Vec::new();
+
+    for nal in NalUnits::new(access_unit) {
+        let header = NalUnitHeader::parse(nal.data)?;
+        // Enhancement layers are not decoded
+        if header.nuh_layer_id != 0 {
+            continue;
+        }
+        match header.nal_unit_type {
+            // Reserved types are ignored by decoders
+            NalUnitType::Reserved(_) => {}
+            nal_unit_type if nal_unit_type.is_vcl() => segments.push(SliceSegment {
+                offset: nal.offset,
+                size: nal.start_code_len + nal.data.len(),
+                header: SliceSegmentHeader::parse(nal.data, parameter_sets)?,
+            }),
+            NalUnitType::Vps | NalUnitType::Sps | NalUnitType::Pps => {
+                parameter_sets.add_nal(nal.data)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Offsets of the slice segments relative to the first one, as expected by
+/// `VkVideoDecodeH265PictureInfoKHR::pSliceSegmentOffsets`.
+pub fn slice_segment_offsets(segments: &[SliceSegment]) -> Vec<u32> {
+    let base = segments.first().map_or(0, |segment| segment.offset);
+    segments
+        .iter()
+        .map(|segment| (segment.offset - base) as u32)
+        .collect()
+}
@@ -0,0 +1,1121 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoH265ChromaFormatIdc, StdVideoH265DecPicBufMgr, StdVideoH265LongTermRefPicsSps,
+    StdVideoH265ProfileTierLevel, StdVideoH265ScalingLists, StdVideoH265SequenceParameterSet,
+    StdVideoH265SequenceParameterSetVui, StdVideoH265ShortTermRefPicSet,
+};
+
+use crate::bitreader::{nal_to_rbsp, BitReader};
+use crate::h265::vps::{DecPicBufMgr, HrdParameters, ProfileTierLevel, StdHrd, MAX_SUB_LAYERS};
+use crate::h265::{NalUnitHeader, NalUnitType};
+use crate::timestamp::Timestamp;
+
+pub const MAX_SPS_COUNT: usize = 16;
+pub const MAX_SHORT_TERM_REF_PIC_SETS: usize = 64;
+pub const MAX_LONG_TERM_REF_PICS_SPS: usize = 32;
+
+// Table 7-6, in up-right diagonal scan order
+pub const DEFAULT_8X8_INTRA: [u8; 64] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 17, 16, 17, 16, 17, 18, 17, 18, 18, 17, 18, 21, 19, 20,
+    21, 20, 19, 21, 24, 22, 22, 24, 24, 22, 22, 24, 25, 25, 27, 30, 27, 25, 25, 29, 31, 35, 35, 31,
+    29, 36, 41, 44, 41, 36, 47, 54, 54, 47, 65, 70, 65, 88, 88, 115,
+];
+pub const DEFAULT_8X8_INTER: [u8; 64] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 17, 17, 17, 17, 17, 18, 18, 18, 18, 18, 18, 20, 20, 20,
+    20, 20, 20, 20, 24, 24, 24, 24, 24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 28, 28, 28, 28, 28,
+    28, 33, 33, 33, 33, 33, 41, 41, 41, 41, 54, 54, 54, 71, 71, 91,
+];
+
+/// Scaling lists in coefficient scan order, as signalled by scaling_list_data(), 7.3.4.
+/// Lists predicted from others or from the defaults are already resolved. 32x32 lists only
+/// exist for matrixId 0 and 3, stored at index 0 and 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScalingLists {
+    pub list_4x4: [[u8; 16]; 6],
+    pub list_8x8: [[u8; 64]; 6],
+    pub list_16x16: [[u8; 64]; 6],
+    pub list_32x32: [[u8; 64]; 2],
+    pub dc_16x16: [u8; 6],
+    pub dc_32x32: [u8; 2],
+}
+
+impl Default for ScalingLists {
+    /// The default lists of Table 7-5 and 7-6, used when scaling lists are enabled but not sent
+    fn default() -> Self {
+        let default_8x8 = |matrix_id| {
+            if matrix_id < 3 {
+                DEFAULT_8X8_INTRA
+            } else {
+                DEFAULT_8X8_INTER
+            }
+        };
+        Self {
+            list_4x4: [[16; 16]; 6],
+            list_8x8: std::array::from_fn(default_8x8),
+            list_16x16: std::array::from_fn(default_8x8),
+            list_32x32: [DEFAULT_8X8_INTRA, DEFAULT_8X8_INTER],
+            dc_16x16: [16; 6],
+            dc_32x32: [16; 2],
+        }
+    }
+}
+
+impl ScalingLists {
+    /// The list for sizeId and matrixId, with matrixId 0 and 3 for the 32x32 lists.
+    fn list_mut(&mut self, size_id: usize, matrix_id: usize) -> &mut [u8] {
+        match size_id {
+            0 => &mut self.list_4x4[matrix_id],
+            1 => &mut self.list_8x8[matrix_id],
+            2 => &mut self.list_16x16[matrix_id],
+            _ => &mut self.list_32x32[matrix_id / 3],
+        }
+    }
+
+    fn dc_mut(&mut self, size_id: usize, matrix_id: usize) -> Option<&mut u8> {
+        match size_id {
+            2 => Some(&mut self.dc_16x16[matrix_id]),
+            3 => Some(&mut self.dc_32x32[matrix_id / 3]),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn parse(reader: &mut BitReader) -> Result<Self> {
+        let mut lists = ScalingLists::default();
+
+        for size_id in 0..4 {
+            let step = if size_id == 3 { 3 } else { 1 };
+            for matrix_id in (0..6).step_by(step) {
+                let scaling_list_pred_mode_flag = reader.read_flag()?;
+                if !scaling_list_pred_mode_flag {
+                    let scaling_list_pred_matrix_id_delta = reader.read_ue()? as usize;
+                    if scaling_list_pred_matrix_id_delta * step > matrix_id {
+                        return Err(anyhow!(
+                            "Invalid scaling_list_pred_matrix_id_delta {}",
+                            scaling_list_pred_matrix_id_delta
+                        ));
+                    }
+                    // A delta of 0 infers the default list, which is already in place
+                    if scaling_list_pred_matrix_id_delta != 0 {
+                        let ref_matrix_id = matrix_id - scaling_list_pred_matrix_id_delta * step;
+                        let list = lists.list_mut(size_id, ref_matrix_id).to_vec();
+                        lists.list_mut(size_id, matrix_id).copy_from_slice(&list);
+                        if let Some(dc) = lists.dc_mut(size_id, ref_matrix_id).copied() {
+                            *lists.dc_mut(size_id, matrix_id).unwrap() = dc;
+                        }
+                    }
+                    continue;
+                }
+
+                let mut next_coef = 8;
+                if size_id > 1 {
+                    let scaling_list_dc_coef_minus8 = reader.read_se()?;
+                    if !(-7..=247).contains(&scaling_list_dc_coef_minus8) {
+                        return Err(anyhow!(
+                            "Invalid scaling_list_dc_coef_minus8 {}",
+                            scaling_list_dc_coef_minus8
+                        ));
+                    }
+                    next_coef = scaling_list_dc_coef_minus8 + 8;
+                    *lists.dc_mut(size_id, matrix_id).unwrap() = next_coef as u8;
+                }
+                for coef in lists.list_mut(size_id, matrix_id).iter_mut() {
+                    let scaling_list_delta_coef = reader.read_se()?;
+                    if !(-128..=127).contains(&scaling_list_delta_coef) {
+                        return Err(anyhow!(
+                            "Invalid scaling_list_delta_coef {}",
+                            scaling_list_delta_coef
+                        ));
+                    }
+                    next_coef = (next_coef + scaling_list_delta_coef + 256) % 256;
+                    *coef = next_coef as u8;
+                }
+            }
+        }
+
+        Ok(lists)
+    }
+
+    pub fn to_std(&self) -> StdVideoH265ScalingLists {
+        StdVideoH265ScalingLists {
+            ScalingList4x4: self.list_4x4,
+            ScalingList8x8: self.list_8x8,
+            ScalingList16x16: self.list_16x16,
+            ScalingList32x32: self.list_32x32,
+            ScalingListDCCoef16x16: self.dc_16x16,
+            ScalingListDCCoef32x32: self.dc_32x32,
+        }
+    }
+}
+
+/// st_ref_pic_set(), 7.3.7, with the delta POCs of 7.4.8 derived. Sets predicted from
+/// another one keep their syntax elements next to the derived values.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShortTermRefPicSet {
+    pub inter_ref_pic_set_prediction_flag: bool,
+    pub delta_idx_minus1: u32,
+    pub delta_rps_sign: bool,
+    pub abs_delta_rps_minus1: u32,
+    /// used_by_curr_pic_flag[j] in bit j
+    pub used_by_curr_pic_flag: u32,
+    /// use_delta_flag[j] in bit j
+    pub use_delta_flag: u32,
+    /// DeltaPocS0, negative and decreasing
+    pub delta_poc_s0: Vec<i32>,
+    pub used_by_curr_pic_s0: Vec<bool>,
+    /// DeltaPocS1, positive and increasing
+    pub delta_poc_s1: Vec<i32>,
+    pub used_by_curr_pic_s1: Vec<bool>,
+}
+
+impl ShortTermRefPicSet {
+    /// Parses set `idx` given the sets before it. `idx` equals num_short_term_ref_pic_sets
+    /// for the set in a slice header.
+    pub(crate) fn parse(
+        reader: &mut BitReader,
+        idx: usize,
+        sets: &[ShortTermRefPicSet],
+        num_short_term_ref_pic_sets: usize,
+    ) -> Result<Self> {
+        let mut set = ShortTermRefPicSet::default();
+
+        if idx != 0 {
+            set.inter_ref_pic_set_prediction_flag = reader.read_flag()?;
+        }
+
+        if set.inter_ref_pic_set_prediction_flag {
+            if idx == num_short_term_ref_pic_sets {
+                set.delta_idx_minus1 = reader.read_ue()?;
+                if set.delta_idx_minus1 as usize >= idx {
+                    return Err(anyhow!("Invalid delta_idx_minus1 {}", set.delta_idx_minus1));
+                }
+            }
+            set.delta_rps_sign = reader.read_flag()?;
+            set.abs_delta_rps_minus1 = reader.read_ue()?;
+            if set.abs_delta_rps_minus1 > 0x7fff {
+                return Err(anyhow!(
+                    "Invalid abs_delta_rps_minus1 {}",
+                    set.abs_delta_rps_minus1
+                ));
+            }
+
+            let reference = &sets[idx - (set.delta_idx_minus1 as usize + 1)];
+            let num_delta_pocs = reference.num_delta_pocs();
+            for j in 0..=num_delta_pocs {
+                let used_by_curr_pic_flag = reader.read_flag()?;
+                let use_delta_flag = used_by_curr_pic_flag || reader.read_flag()?;
+                set.used_by_curr_pic_flag |= (used_by_curr_pic_flag as u32) << j;
+                set.use_delta_flag |= (use_delta_flag as u32) << j;
+            }
+            set.predict(reference);
+        } else {
+            let num_negative_pics = reader.read_ue()?;
+            let num_positive_pics = reader.read_ue()?;
+            if num_negative_pics > 16 || num_positive_pics > 16 - num_negative_pics {
+                return Err(anyhow!(
+                    "Invalid num_negative_pics {} num_positive_pics {}",
+                    num_negative_pics,
+                    num_positive_pics
+                ));
+            }
+
+            let mut delta_poc = 0;
+            for _ in 0..num_negative_pics {
+                delta_poc -= reader.read_ue()? as i32 + 1;
+                set.delta_poc_s0.push(delta_poc);
+                set.used_by_curr_pic_s0.push(reader.read_flag()?);
+            }
+            delta_poc = 0;
+            for _ in 0..num_positive_pics {
+                delta_poc += reader.read_ue()? as i32 + 1;
+                set.delta_poc_s1.push(delta_poc);
+                set.used_by_curr_pic_s1.push(reader.read_flag()?);
+            }
+        }
+
+        Ok(set)
+    }
+
+    /// Derives the delta POCs of a set predicted from `reference`, equations 7-61 and 7-62.
+    fn predict(&mut self, reference: &ShortTermRefPicSet) {
+        let delta_rps =
+            (1 - 2 * self.delta_rps_sign as i32) * (self.abs_delta_rps_minus1 as i32 + 1);
+        let num_negative = reference.delta_poc_s0.len();
+        let num_delta_pocs = reference.num_delta_pocs();
+        let used = |j: usize| self.used_by_curr_pic_flag & (1 << j) != 0;
+        let use_delta = |j: usize| self.use_delta_flag & (1 << j) != 0;
+
+        let mut s0 = Vec::new();
+        for (j, &delta_poc) in reference.delta_poc_s1.iter().enumerate().rev() {
+            let d_poc = delta_poc + delta_rps;
+            if d_poc < 0 && use_delta(num_negative + j) {
+                s0.push((d_poc, used(num_negative + j)));
+            }
+        }
+        if delta_rps < 0 && use_delta(num_delta_pocs) {
+            s0.push((delta_rps, used(num_delta_pocs)));
+        }
+        for (j, &delta_poc) in reference.delta_poc_s0.iter().enumerate() {
+            let d_poc = delta_poc + delta_rps;
+            if d_poc < 0 && use_delta(j) {
+                s0.push((d_poc, used(j)));
+            }
+        }
+
+        let mut s1 = Vec::new();
+        for (j, &delta_poc) in reference.delta_poc_s0.iter().enumerate().rev() {
+            let d_poc = delta_poc + delta_rps;
+            if d_poc > 0 && use_delta(j) {
+                s1.push((d_poc, used(j)));
+            }
+        }
+        if delta_rps > 0 && use_delta(num_delta_pocs) {
+            s1.push((delta_rps, used(num_delta_pocs)));
+        }
+        for (j, &delta_poc) in reference.delta_poc_s1.iter().enumerate() {
+            let d_poc = delta_poc + delta_rps;
+            if d_poc > 0 && use_delta(num_negative + j) {
+                s1.push((d_poc, used(num_negative + j)));
+            }
+        }
+
+        // A conforming set has at most 16 pictures
+        s0.truncate(16);
+        s1.truncate(16 - s0.len());
+        (self.delta_poc_s0, self.used_by_curr_pic_s0) = s0.into_iter().unzip();
+        (self.delta_poc_s1, self.used_by_curr_pic_s1) = s1.into_iter().unzip();
+    }
+
+    /// NumDeltaPocs
+    pub fn num_delta_pocs(&self) -> usize {
+        self.delta_poc_s0.len() + self.delta_poc_s1.len()
+    }
+
+    pub fn to_std(&self) -> StdVideoH265ShortTermRefPicSet {
+        let mut set: StdVideoH265ShortTermRefPicSet = unsafe { mem::zeroed() };
+
+        set.flags
+            .set_inter_ref_pic_set_prediction_flag(self.inter_ref_pic_set_prediction_flag as u32);
+        set.flags.set_delta_rps_sign(self.delta_rps_sign as u32);
+        set.delta_idx_minus1 = self.delta_idx_minus1;
+        set.use_delta_flag = self.use_delta_flag as u16;
+        set.abs_delta_rps_minus1 = self.abs_delta_rps_minus1 as u16;
+        set.used_by_curr_pic_flag = self.used_by_curr_pic_flag as u16;
+        set.num_negative_pics = self.delta_poc_s0.len() as u8;
+        set.num_positive_pics = self.delta_poc_s1.len() as u8;
+
+        // The derived values, also for predicted sets
+        let mut previous = 0;
+        for (i, (&delta_poc, &used)) in self
+            .delta_poc_s0
+            .iter()
+            .zip(&self.used_by_curr_pic_s0)
+            .enumerate()
+        {
+            set.delta_poc_s0_minus1[i] = (previous - delta_poc - 1) as u16;
+            set.used_by_curr_pic_s0_flag |= (used as u16) << i;
+            previous = delta_poc;
+        }
+        previous = 0;
+        for (i, (&delta_poc, &used)) in self
+            .delta_poc_s1
+            .iter()
+            .zip(&self.used_by_curr_pic_s1)
+            .enumerate()
+        {
+            set.delta_poc_s1_minus1[i] = (delta_poc - previous - 1) as u16;
+            set.used_by_curr_pic_s1_flag |= (used as u16) << i;
+            previous = delta_poc;
+        }
+
+        set
+    }
+}
+
+/// Video usability information, Annex E.2.1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vui {
+    pub aspect_ratio_info_present_flag: bool,
+    pub aspect_ratio_idc: u8,
+    pub sar_width: u16,
+    pub sar_height: u16,
+    pub overscan_info_present_flag: bool,
+    pub overscan_appropriate_flag: bool,
+    pub video_signal_type_present_flag: bool,
+    pub video_format: u8,
+    pub video_full_range_flag: bool,
+    pub colour_description_present_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coeffs: u8,
+    pub chroma_loc_info_present_flag: bool,
+    pub chroma_sample_loc_type_top_field: u8,
+    pub chroma_sample_loc_type_bottom_field: u8,
+    pub neutral_chroma_indication_flag: bool,
+    pub field_seq_flag: bool,
+    pub frame_field_info_present_flag: bool,
+    pub default_display_window_flag: bool,
+    pub def_disp_win_left_offset: u32,
+    pub def_disp_win_right_offset: u32,
+    pub def_disp_win_top_offset: u32,
+    pub def_disp_win_bottom_offset: u32,
+    pub vui_timing_info_present_flag: bool,
+    pub vui_num_units_in_tick: u32,
+    pub vui_time_scale: u32,
+    pub vui_poc_proportional_to_timing_flag: bool,
+    pub vui_num_ticks_poc_diff_one_minus1: u32,
+    pub hrd_parameters: Option<HrdParameters>,
+    pub bitstream_restriction_flag: bool,
+    pub tiles_fixed_structure_flag: bool,
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    pub restricted_ref_pic_lists_flag: bool,
+    pub min_spatial_segmentation_idc: u32,
+    pub max_bytes_per_pic_denom: u32,
+    pub max_bits_per_min_cu_denom: u32,
+    pub log2_max_mv_length_horizontal: u32,
+    pub log2_max_mv_length_vertical: u32,
+}
+
+impl Default for Vui {
+    /// Values inferred when the corresponding syntax elements are absent
+    fn default() -> Self {
+        Self {
+            aspect_ratio_info_present_flag: false,
+            aspect_ratio_idc: 0,
+            sar_width: 0,
+            sar_height: 0,
+            overscan_info_present_flag: false,
+            overscan_appropriate_flag: false,
+            video_signal_type_present_flag: false,
+            video_format: 5,
+            video_full_range_flag: false,
+            colour_description_present_flag: false,
+            colour_primaries: 2,
+            transfer_characteristics: 2,
+            matrix_coeffs: 2,
+            chroma_loc_info_present_flag: false,
+            chroma_sample_loc_type_top_field: 0,
+            chroma_sample_loc_type_bottom_field: 0,
+            neutral_chroma_indication_flag: false,
+            field_seq_flag: false,
+            frame_field_info_present_flag: false,
+            default_display_window_flag: false,
+            def_disp_win_left_offset: 0,
+            def_disp_win_right_offset: 0,
+            def_disp_win_top_offset: 0,
+            def_disp_win_bottom_offset: 0,
+            vui_timing_info_present_flag: false,
+            vui_num_units_in_tick: 0,
+            vui_time_scale: 0,
+            vui_poc_proportional_to_timing_flag: false,
+            vui_num_ticks_poc_diff_one_minus1: 0,
+            hrd_parameters: None,
+            bitstream_restriction_flag: false,
+            tiles_fixed_structure_flag: false,
+            motion_vectors_over_pic_boundaries_flag: true,
+            restricted_ref_pic_lists_flag: false,
+            min_spatial_segmentation_idc: 0,
+            max_bytes_per_pic_denom: 2,
+            max_bits_per_min_cu_denom: 1,
+            log2_max_mv_length_horizontal: 15,
+            log2_max_mv_length_vertical: 15,
+        }
+    }
+}
+
+impl Vui {
+    fn parse(reader: &mut BitReader, sps_max_sub_layers_minus1: u8) -> Result<Self> {
+        let mut vui = Vui {
+            aspect_ratio_info_present_flag: reader.read_flag()?,
+            ..Vui::default()
+        };
+        if vui.aspect_ratio_info_present_flag {
+            vui.aspect_ratio_idc = reader.read_bits(8)? as u8;
+            // EXTENDED_SAR
+            if vui.aspect_ratio_idc == 255 {
+                vui.sar_width = reader.read_bits(16)? as u16;
+                vui.sar_height = reader.read_bits(16)? as u16;
+            }
+        }
+
+        vui.overscan_info_present_flag = reader.read_flag()?;
+        if vui.overscan_info_present_flag {
+            vui.overscan_appropriate_flag = reader.read_flag()?;
+        }
+
+        vui.video_signal_type_present_flag = reader.read_flag()?;
+        if vui.video_signal_type_present_flag {
+            vui.video_format = reader.read_bits(3)? as u8;
+            vui.video_full_range_flag = reader.read_flag()?;
+            vui.colour_description_present_flag = reader.read_flag()?;
+            if vui.colour_description_present_flag {
+                vui.colour_primaries = reader.read_bits(8)? as u8;
+                vui.transfer_characteristics = reader.read_bits(8)? as u8;
+                vui.matrix_coeffs = reader.read_bits(8)? as u8;
+            }
+        }
+
+        vui.chroma_loc_info_present_flag = reader.read_flag()?;
+        if vui.chroma_loc_info_present_flag {
+            vui.chroma_sample_loc_type_top_field = reader.read_ue()?.min(5) as u8;
+            vui.chroma_sample_loc_type_bottom_field = reader.read_ue()?.min(5) as u8;
+        }
+
+        vui.neutral_chroma_indication_flag = reader.read_flag()?;
+        vui.field_seq_flag = reader.read_flag()?;
+        vui.frame_field_info_present_flag = reader.read_flag()?;
+
+        vui.default_display_window_flag = reader.read_flag()?;
+        if vui.default_display_window_flag {
+            vui.def_disp_win_left_offset = reader.read_ue()?;
+            vui.def_disp_win_right_offset = reader.read_ue()?;
+            vui.def_disp_win_top_offset = reader.read_ue()?;
+            vui.def_disp_win_bottom_offset = reader.read_ue()?;
+        }
+
+        vui.vui_timing_info_present_flag = reader.read_flag()?;
+        if vui.vui_timing_info_present_flag {
+            vui.vui_num_units_in_tick = reader.read_bits(32)?;
+            vui.vui_time_scale = reader.read_bits(32)?;
+            vui.vui_poc_proportional_to_timing_flag = reader.read_flag()?;
+            if vui.vui_poc_proportional_to_timing_flag {
+                vui.vui_num_ticks_poc_diff_one_minus1 = reader.read_ue()?;
+            }
+            if reader.read_flag()? {
+                vui.hrd_parameters = Some(HrdParameters::parse(
+                    reader,
+                    true,
+                    sps_max_sub_layers_minus1,
+                )?);
+            }
+        }
+
+        vui.bitstream_restriction_flag = reader.read_flag()?;
+        if vui.bitstream_restriction_flag {
+            vui.tiles_fixed_structure_flag = reader.read_flag()?;
+            vui.motion_vectors_over_pic_boundaries_flag = reader.read_flag()?;
+            vui.restricted_ref_pic_lists_flag = reader.read_flag()?;
+            vui.min_spatial_segmentation_idc = reader.read_ue()?;
+            vui.max_bytes_per_pic_denom = reader.read_ue()?;
+            vui.max_bits_per_min_cu_denom = reader.read_ue()?;
+            vui.log2_max_mv_length_horizontal = reader.read_ue()?;
+            vui.log2_max_mv_length_vertical = reader.read_ue()?;
+        }
+
+        Ok(vui)
+    }
+
+    /// The returned struct does not reference the HRD parameters; `pHrdParameters` is left null.
+    pub fn to_std(&self) -> StdVideoH265SequenceParameterSetVui {
+        let mut vui: StdVideoH265SequenceParameterSetVui = unsafe { mem::zeroed() };
+
+        vui.flags
+            .set_aspect_ratio_info_present_flag(self.aspect_ratio_info_present_flag as u32);
+        vui.flags
+            .set_overscan_info_present_flag(self.overscan_info_present_flag as u32);
+        vui.flags
+            .set_overscan_appropriate_flag(self.overscan_appropriate_flag as u32);
+        vui.flags
+            .set_video_signal_type_present_flag(self.video_signal_type_present_flag as u32);
+        vui.flags
+            .set_video_full_range_flag(self.video_full_range_flag as u32);
+        vui.flags
+            .set_colour_description_present_flag(self.colour_description_present_flag as u32);
+        vui.flags
+            .set_chroma_loc_info_present_flag(self.chroma_loc_info_present_flag as u32);
+        vui.flags
+            .set_neutral_chroma_indication_flag(self.neutral_chroma_indication_flag as u32);
+        vui.flags.set_field_seq_flag(self.field_seq_flag as u32);
+        vui.flags
+            .set_frame_field_info_present_flag(self.frame_field_info_present_flag as u32);
+        vui.flags
+            .set_default_display_window_flag(self.default_display_window_flag as u32);
+        vui.flags
+            .set_vui_timing_info_present_flag(self.vui_timing_info_present_flag as u32);
+        vui.flags.set_vui_poc_proportional_to_timing_flag(
+            self.vui_poc_proportional_to_timing_flag as u32,
+        );
+        vui.flags
+            .set_vui_hrd_parameters_present_flag(self.hrd_parameters.is_some() as u32);
+        vui.flags
+            .set_bitstream_restriction_flag(self.bitstream_restriction_flag as u32);
+        vui.flags
+            .set_tiles_fixed_structure_flag(self.tiles_fixed_structure_flag as u32);
+        vui.flags.set_motion_vectors_over_pic_boundaries_flag(
+            self.motion_vectors_over_pic_boundaries_flag as u32,
+        );
+        vui.flags
+            .set_restricted_ref_pic_lists_flag(self.restricted_ref_pic_lists_flag as u32);
+
+        vui.aspect_ratio_idc = self.aspect_ratio_idc as u32;
+        vui.sar_width = self.sar_width;
+        vui.sar_height = self.sar_height;
+        vui.video_format = self.video_format;
+        vui.colour_primaries = self.colour_primaries;
+        vui.transfer_characteristics = self.transfer_characteristics;
+        vui.matrix_coeffs = self.matrix_coeffs;
+        vui.chroma_sample_loc_type_top_field = self.chroma_sample_loc_type_top_field;
+        vui.chroma_sample_loc_type_bottom_field = self.chroma_sample_loc_type_bottom_field;
+        vui.def_disp_win_left_offset = self.def_disp_win_left_offset as u16;
+        vui.def_disp_win_right_offset = self.def_disp_win_right_offset as u16;
+        vui.def_disp_win_top_offset = self.def_disp_win_top_offset as u16;
+        vui.def_disp_win_bottom_offset = self.def_disp_win_bottom_offset as u16;
+        vui.vui_num_units_in_tick = self.vui_num_units_in_tick;
+        vui.vui_time_scale = self.vui_time_scale;
+        vui.vui_num_ticks_poc_diff_one_minus1 = self.vui_num_ticks_poc_diff_one_minus1;
+        vui.min_spatial_segmentation_idc = self.min_spatial_segmentation_idc as u16;
+        vui.max_bytes_per_pic_denom = self.max_bytes_per_pic_denom as u8;
+        vui.max_bits_per_min_cu_denom = self.max_bits_per_min_cu_denom as u8;
+        vui.log2_max_mv_length_horizontal = self.log2_max_mv_length_horizontal as u8;
+        vui.log2_max_mv_length_vertical = self.log2_max_mv_length_vertical as u8;
+
+        vui
+    }
+}
+
+/// Long-term reference picture candidates of the SPS
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LongTermRefPicSps {
+    pub lt_ref_pic_poc_lsb_sps: u32,
+    pub used_by_curr_pic_lt_sps_flag: bool,
+}
+
+/// The sps_range_extension() flags, 7.3.2.2.2
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RangeExtension {
+    pub transform_skip_rotation_enabled_flag: bool,
+    pub transform_skip_context_enabled_flag: bool,
+    pub implicit_rdpcm_enabled_flag: bool,
+    pub explicit_rdpcm_enabled_flag: bool,
+    pub extended_precision_processing_flag: bool,
+    pub intra_smoothing_disabled_flag: bool,
+    pub high_precision_offsets_enabled_flag: bool,
+    pub persistent_rice_adaptation_enabled_flag: bool,
+    pub cabac_bypass_alignment_enabled_flag: bool,
+}
+
+/// Sequence parameter set, 7.3.2.2. The multilayer, 3D and SCC extensions are not parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sps {
+    pub sps_video_parameter_set_id: u8,
+    pub sps_max_sub_layers_minus1: u8,
+    pub sps_temporal_id_nesting_flag: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub sps_seq_parameter_set_id: u8,
+    pub chroma_format_idc: u8,
+    pub separate_colour_plane_flag: bool,
+    pub pic_width_in_luma_samples: u32,
+    pub pic_height_in_luma_samples: u32,
+    pub conformance_window_flag: bool,
+    pub conf_win_left_offset: u32,
+    pub conf_win_right_offset: u32,
+    pub conf_win_top_offset: u32,
+    pub conf_win_bottom_offset: u32,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub log2_max_pic_order_cnt_lsb_minus4: u8,
+    pub sps_sub_layer_ordering_info_present_flag: bool,
+    pub dec_pic_buf_mgr: DecPicBufMgr,
+    pub log2_min_luma_coding_block_size_minus3: u8,
+    pub log2_diff_max_min_luma_coding_block_size: u8,
+    pub log2_min_luma_transform_block_size_minus2: u8,
+    pub log2_diff_max_min_luma_transform_block_size: u8,
+    pub max_transform_hierarchy_depth_inter: u8,
+    pub max_transform_hierarchy_depth_intra: u8,
+    pub scaling_list_enabled_flag: bool,
+    pub sps_scaling_list_data_present_flag: bool,
+    pub scaling_lists: ScalingLists,
+    pub amp_enabled_flag: bool,
+    pub sample_adaptive_offset_enabled_flag: bool,
+    pub pcm_enabled_flag: bool,
+    pub pcm_sample_bit_depth_luma_minus1: u8,
+    pub pcm_sample_bit_depth_chroma_minus1: u8,
+    pub log2_min_pcm_luma_coding_block_size_minus3: u8,
+    pub log2_diff_max_min_pcm_luma_coding_block_size: u8,
+    pub pcm_loop_filter_disabled_flag: bool,
+    pub short_term_ref_pic_sets: Vec<ShortTermRefPicSet>,
+    pub long_term_ref_pics_present_flag: bool,
+    pub long_term_ref_pics_sps: Vec<LongTermRefPicSps>,
+    pub sps_temporal_mvp_enabled_flag: bool,
+    pub strong_intra_smoothing_enabled_flag: bool,
+    pub vui: Option<Vui>,
+    pub sps_extension_present_flag: bool,
+    pub range_extension: Option<RangeExtension>,
+}
+
+impl Sps {
+    /// Parses an SPS NAL unit, including its two byte header and any emulation prevention bytes.
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let header = NalUnitHeader::parse(nal)?;
+        if header.nal_unit_type != NalUnitType::Sps {
+            return Err(anyhow!(
+                "Expected an SPS NAL unit, got {:?}",
+                header.nal_unit_type
+            ));
+        }
+
+        let rbsp = nal_to_rbsp(&nal[2..]);
+        Self::parse_rbsp(&mut BitReader::new(&rbsp))
+    }
+
+    pub fn parse_rbsp(reader: &mut BitReader) -> Result<Self> {
+        let sps_video_parameter_set_id = reader.read_bits(4)? as u8;
+        let sps_max_sub_layers_minus1 = reader.read_bits(3)? as u8;
+        if sps_max_sub_layers_minus1 as usize >= MAX_SUB_LAYERS {
+            return Err(anyhow!(
+                "Invalid sps_max_sub_layers_minus1 {}",
+                sps_max_sub_layers_minus1
+            ));
+        }
+        let sps_temporal_id_nesting_flag = reader.read_flag()?;
+        let profile_tier_level = ProfileTierLevel::parse(reader, sps_max_sub_layers_minus1)?;
+
+        let sps_seq_parameter_set_id = reader.read_ue()?;
+        if sps_seq_parameter_set_id as usize >= MAX_SPS_COUNT {
+            return Err(anyhow!(
+                "Invalid sps_seq_parameter_set_id {}",
+                sps_seq_parameter_set_id
+            ));
+        }
+
+        let chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc > 3 {
+            return Err(anyhow!("Invalid chroma_format_idc {}", chroma_format_idc));
+        }
+        let separate_colour_plane_flag = chroma_format_idc == 3 && reader.read_flag()?;
+
+        let pic_width_in_luma_samples = reader.read_ue()?;
+        let pic_height_in_luma_samples = reader.read_ue()?;
+        if pic_width_in_luma_samples == 0 || pic_height_in_luma_samples == 0 {
+            return Err(anyhow!(
+                "Invalid picture size {}x{}",
+                pic_width_in_luma_samples,
+                pic_height_in_luma_samples
+            ));
+        }
+
+        let conformance_window_flag = reader.read_flag()?;
+        let mut conf_win = [0; 4];
+        if conformance_window_flag {
+            for offset in conf_win.iter_mut() {
+                *offset = reader.read_ue()?;
+            }
+        }
+
+        let bit_depth_luma_minus8 = reader.read_ue()?;
+        let bit_depth_chroma_minus8 = reader.read_ue()?;
+        if bit_depth_luma_minus8 > 8 || bit_depth_chroma_minus8 > 8 {
+            return Err(anyhow!(
+                "Invalid bit depth luma {} chroma {}",
+                bit_depth_luma_minus8 + 8,
+                bit_depth_chroma_minus8 + 8
+            ));
+        }
+
+        let log2_max_pic_order_cnt_lsb_minus4 = reader.read_ue()?;
+        if log2_max_pic_order_cnt_lsb_minus4 > 12 {
+            return Err(anyhow!(
+                "Invalid log2_max_pic_order_cnt_lsb_minus4 {}",
+                log2_max_pic_order_cnt_lsb_minus4
+            ));
+        }
+
+        let sps_sub_layer_ordering_info_present_flag = reader.read_flag()?;
+        let dec_pic_buf_mgr = DecPicBufMgr::parse(
+            reader,
+            sps_max_sub_layers_minus1,
+            sps_sub_layer_ordering_info_present_flag,
+        )?;
+
+        let mut block_sizes = [0; 6];
+        for size in block_sizes.iter_mut() {
+            let value = reader.read_ue()?;
+            if value > 31 {
+                return Err(anyhow!("Invalid block size syntax element {}", value));
+            }
+            *size = value as u8;
+        }
+
+        let scaling_list_enabled_flag = reader.read_flag()?;
+        let sps_scaling_list_data_present_flag = scaling_list_enabled_flag && reader.read_flag()?;
+        let scaling_lists = if sps_scaling_list_data_present_flag {
+            ScalingLists::parse(reader)?
+        } else {
+            ScalingLists::default()
+        };
+
+        let amp_enabled_flag = reader.read_flag()?;
+        let sample_adaptive_offset_enabled_flag = reader.read_flag()?;
+
+        let pcm_enabled_flag = reader.read_flag()?;
+        let mut pcm = [0; 4];
+        let mut pcm_loop_filter_disabled_flag = false;
+        if pcm_enabled_flag {
+            pcm[0] = reader.read_bits(4)? as u8;
+            pcm[1] = reader.read_bits(4)? as u8;
+            pcm[2] = reader.read_ue()?.min(u8::MAX as u32) as u8;
+            pcm[3] = reader.read_ue()?.min(u8::MAX as u32) as u8;
+            pcm_loop_filter_disabled_flag = reader.read_flag()?;
+        }
+
+        let num_short_term_ref_pic_sets = reader.read_ue()? as usize;
+        if num_short_term_ref_pic_sets > MAX_SHORT_TERM_REF_PIC_SETS {
+            return Err(anyhow!(
+                "Invalid num_short_term_ref_pic_sets {}",
+                num_short_term_ref_pic_sets
+            ));
+        }
+        let mut short_term_ref_pic_sets = Vec::with_capacity(num_short_term_ref_pic_sets);
+        for i in 0..num_short_term_ref_pic_sets {
+            let set = ShortTermRefPicSet::parse(
+                reader,
+                i,
+                &short_term_ref_pic_sets,
+                num_short_term_ref_pic_sets,
+            )?;
+            short_term_ref_pic_sets.push(set);
+        }
+
+        let long_term_ref_pics_present_flag = reader.read_flag()?;
+        let mut long_term_ref_pics_sps = Vec::new();
+        if long_term_ref_pics_present_flag {
+            let num_long_term_ref_pics_sps = reader.read_ue()? as usize;
+            if num_long_term_ref_pics_sps > MAX_LONG_TERM_REF_PICS_SPS {
+                return Err(anyhow!(
+                    "Invalid num_long_term_ref_pics_sps {}",
+                    num_long_term_ref_pics_sps
+                ));
+            }
+            for _ in 0..num_long_term_ref_pics_sps {
+                long_term_ref_pics_sps.push(LongTermRefPicSps {
+                    lt_ref_pic_poc_lsb_sps: reader
+                        .read_bits(log2_max_pic_order_cnt_lsb_minus4 + 4)?,
+                    used_by_curr_pic_lt_sps_flag: reader.read_flag()?,
+                });
+            }
+        }
+
+        let sps_temporal_mvp_enabled_flag = reader.read_flag()?;
+        let strong_intra_smoothing_enabled_flag = reader.read_flag()?;
+
+        let vui = if reader.read_flag()? {
+            Some(Vui::parse(reader, sps_max_sub_layers_minus1)?)
+        } else {
+            None
+        };
+
+        let sps_extension_present_flag = reader.read_flag()?;
+        let mut range_extension = None;
+        if sps_extension_present_flag {
+            let sps_range_extension_flag = reader.read_flag()?;
+            // sps_multilayer_extension_flag, sps_3d_extension_flag, sps_scc_extension_flag
+            // and sps_extension_4bits
+            reader.skip_bits(7)?;
+            if sps_range_extension_flag {
+                range_extension = Some(RangeExtension {
+                    transform_skip_rotation_enabled_flag: reader.read_flag()?,
+                    transform_skip_context_enabled_flag: reader.read_flag()?,
+                    implicit_rdpcm_enabled_flag: reader.read_flag()?,
+                    explicit_rdpcm_enabled_flag: reader.read_flag()?,
+                    extended_precision_processing_flag: reader.read_flag()?,
+                    intra_smoothing_disabled_flag: reader.read_flag()?,
+                    high_precision_offsets_enabled_flag: reader.read_flag()?,
+                    persistent_rice_adaptation_enabled_flag: reader.read_flag()?,
+                    cabac_bypass_alignment_enabled_flag: reader.read_flag()?,
+                });
+            }
+        }
+
+        Ok(Self {
+            sps_video_parameter_set_id,
+            sps_max_sub_layers_minus1,
+            sps_temporal_id_nesting_flag,
+            profile_tier_level,
+            sps_seq_parameter_set_id: sps_seq_parameter_set_id as u8,
+            chroma_format_idc: chroma_format_idc as u8,
+            separate_colour_plane_flag,
+            pic_width_in_luma_samples,
+            pic_height_in_luma_samples,
+            conformance_window_flag,
+            conf_win_left_offset: conf_win[0],
+            conf_win_right_offset: conf_win[1],
+            conf_win_top_offset: conf_win[2],
+            conf_win_bottom_offset: conf_win[3],
+            bit_depth_luma_minus8: bit_depth_luma_minus8 as u8,
+            bit_depth_chroma_minus8: bit_depth_chroma_minus8 as u8,
+            log2_max_pic_order_cnt_lsb_minus4: log2_max_pic_order_cnt_lsb_minus4 as u8,
+            sps_sub_layer_ordering_info_present_flag,
+            dec_pic_buf_mgr,
+            log2_min_luma_coding_block_size_minus3: block_sizes[0],
+            log2_diff_max_min_luma_coding_block_size: block_sizes[1],
+            log2_min_luma_transform_block_size_minus2: block_sizes[2],
+            log2_diff_max_min_luma_transform_block_size: block_sizes[3],
+            max_transform_hierarchy_depth_inter: block_sizes[4],
+            max_transform_hierarchy_depth_intra: block_sizes[5],
+            scaling_list_enabled_flag,
+            sps_scaling_list_data_present_flag,
+            scaling_lists,
+            amp_enabled_flag,
+            sample_adaptive_offset_enabled_flag,
+            pcm_enabled_flag,
+            pcm_sample_bit_depth_luma_minus1: pcm[0],
+            pcm_sample_bit_depth_chroma_minus1: pcm[1],
+            log2_min_pcm_luma_coding_block_size_minus3: pcm[2],
+            log2_diff_max_min_pcm_luma_coding_block_size: pcm[3],
+            pcm_loop_filter_disabled_flag,
+            short_term_ref_pic_sets,
+            long_term_ref_pics_present_flag,
+            long_term_ref_pics_sps,
+            sps_temporal_mvp_enabled_flag,
+            strong_intra_smoothing_enabled_flag,
+            vui,
+            sps_extension_present_flag,
+            range_extension,
+        })
+    }
+
+    pub fn chroma_array_type(&self) -> u8 {
+        if self.separate_colour_plane_flag {
+            0
+        } else {
+            self.chroma_format_idc
+        }
+    }
+
+    pub fn max_pic_order_cnt_lsb(&self) -> u32 {
+        1 << (self.log2_max_pic_order_cnt_lsb_minus4 + 4)
+    }
+
+    /// CtbSizeY
+    pub fn ctb_size(&self) -> u32 {
+        1 << (self.log2_min_luma_coding_block_size_minus3
+            + 3
+            + self.log2_diff_max_min_luma_coding_block_size)
+    }
+
+    /// PicSizeInCtbsY
+    pub fn pic_size_in_ctbs(&self) -> u32 {
+        let ctb_size = self.ctb_size();
+        self.pic_width_in_luma_samples.div_ceil(ctb_size)
+            * self.pic_height_in_luma_samples.div_ceil(ctb_size)
+    }
+
+    /// Width and height of the decoded picture in luma samples before cropping.
+    pub fn coded_extent(&self) -> (u32, u32) {
+        (
+            self.pic_width_in_luma_samples,
+            self.pic_height_in_luma_samples,
+        )
+    }
+
+    /// Conformance cropping window as (x, y, width, height) in luma samples.
+    pub fn crop_rect(&self) -> (u32, u32, u32, u32) {
+        let (width, height) = self.coded_extent();
+
+        let (sub_width_c, sub_height_c) = match self.chroma_array_type() {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+
+        let left = self.conf_win_left_offset * sub_width_c;
+        let right = self.conf_win_right_offset * sub_width_c;
+        let top = self.conf_win_top_offset * sub_height_c;
+        let bottom = self.conf_win_bottom_offset * sub_height_c;
+
+        (
+            left.min(width),
+            top.min(height),
+            width.saturating_sub(left + right),
+            height.saturating_sub(top + bottom),
+        )
+    }
+
+    /// Duration of a picture from the VUI timing information, a tick being a picture period.
+    pub fn frame_duration(&self) -> Option<Timestamp> {
+        let vui = self.vui.as_ref()?;
+        if !vui.vui_timing_info_present_flag
+            || vui.vui_num_units_in_tick == 0
+            || vui.vui_time_scale == 0
+        {
+            return None;
+        }
+        Some(Timestamp::new(
+            vui.vui_num_units_in_tick as i64,
+            vui.vui_time_scale,
+        ))
+    }
+
+    /// sps_max_dec_pic_buffering_minus1 + 1 of the highest sub-layer, the DPB size needed
+    /// including the current picture.
+    pub fn max_dec_pic_buffering(&self) -> u32 {
+        let highest = self.sps_max_sub_layers_minus1 as usize;
+        self.dec_pic_buf_mgr.max_dec_pic_buffering_minus1[highest] as u32 + 1
+    }
+
+    /// sps_max_num_reorder_pics of the highest sub-layer.
+    pub fn max_num_reorder_pics(&self) -> u32 {
+        let highest = self.sps_max_sub_layers_minus1 as usize;
+        self.dec_pic_buf_mgr.max_num_reorder_pics[highest] as u32
+    }
+
+    pub fn to_std(&self) -> StdSps {
+        let mut sps: StdVideoH265SequenceParameterSet = unsafe { mem::zeroed() };
+
+        sps.flags
+            .set_sps_temporal_id_nesting_flag(self.sps_temporal_id_nesting_flag as u32);
+        sps.flags
+            .set_separate_colour_plane_flag(self.separate_colour_plane_flag as u32);
+        sps.flags
+            .set_conformance_window_flag(self.conformance_window_flag as u32);
+        sps.flags.set_sps_sub_layer_ordering_info_present_flag(
+            self.sps_sub_layer_ordering_info_present_flag as u32,
+        );
+        sps.flags
+            .set_scaling_list_enabled_flag(self.scaling_list_enabled_flag as u32);
+        sps.flags
+            .set_sps_scaling_list_data_present_flag(self.sps_scaling_list_data_present_flag as u32);
+        sps.flags.set_amp_enabled_flag(self.amp_enabled_flag as u32);
+        sps.flags.set_sample_adaptive_offset_enabled_flag(
+            self.sample_adaptive_offset_enabled_flag as u32,
+        );
+        sps.flags.set_pcm_enabled_flag(self.pcm_enabled_flag as u32);
+        sps.flags
+            .set_pcm_loop_filter_disabled_flag(self.pcm_loop_filter_disabled_flag as u32);
+        sps.flags
+            .set_long_term_ref_pics_present_flag(self.long_term_ref_pics_present_flag as u32);
+        sps.flags
+            .set_sps_temporal_mvp_enabled_flag(self.sps_temporal_mvp_enabled_flag as u32);
+        sps.flags.set_strong_intra_smoothing_enabled_flag(
+            self.strong_intra_smoothing_enabled_flag as u32,
+        );
+        sps.flags
+            .set_vui_parameters_present_flag(self.vui.is_some() as u32);
+        sps.flags
+            .set_sps_extension_present_flag(self.sps_extension_present_flag as u32);
+        sps.flags
+            .set_sps_range_extension_flag(self.range_extension.is_some() as u32);
+        if let Some(range) = &self.range_extension {
+            sps.flags.set_transform_skip_rotation_enabled_flag(
+                range.transform_skip_rotation_enabled_flag as u32,
+            );
+            sps.flags.set_transform_skip_context_enabled_flag(
+                range.transform_skip_context_enabled_flag as u32,
+            );
+            sps.flags
+                .set_implicit_rdpcm_enabled_flag(range.implicit_rdpcm_enabled_flag as u32);
+            sps.flags
+                .set_explicit_rdpcm_enabled_flag(range.explicit_rdpcm_enabled_flag as u32);
+            sps.flags.set_extended_precision_processing_flag(
+                range.extended_precision_processing_flag as u32,
+            );
+            sps.flags
+                .set_intra_smoothing_disabled_flag(range.intra_smoothing_disabled_flag as u32);
+            sps.flags.set_high_precision_offsets_enabled_flag(
+                range.high_precision_offsets_enabled_flag as u32,
+            );
+            sps.flags.set_persistent_rice_adaptation_enabled_flag(
+                range.persistent_rice_adaptation_enabled_flag as u32,
+            );
+            sps.flags.set_cabac_bypass_alignment_enabled_flag(
+                range.cabac_bypass_alignment_enabled_flag as u32,
+            );
+        }
+
+        sps.chroma_format_idc = self.chroma_format_idc as StdVideoH265ChromaFormatIdc;
+        sps.pic_width_in_luma_samples = self.pic_width_in_luma_samples;
+        sps.pic_height_in_luma_samples = self.pic_height_in_luma_samples;
+        sps.sps_video_parameter_set_id = self.sps_video_parameter_set_id;
+        sps.sps_max_sub_layers_minus1 = self.sps_max_sub_layers_minus1;
+        sps.sps_seq_parameter_set_id = self.sps_seq_parameter_set_id;
+        sps.bit_depth_luma_minus8 = self.bit_depth_luma_minus8;
+        sps.bit_depth_chroma_minus8 = self.bit_depth_chroma_minus8;
+        sps.log2_max_pic_order_cnt_lsb_minus4 = self.log2_max_pic_order_cnt_lsb_minus4;
+        sps.log2_min_luma_coding_block_size_minus3 = self.log2_min_luma_coding_block_size_minus3;
+        sps.log2_diff_max_min_luma_coding_block_size =
+            self.log2_diff_max_min_luma_coding_block_size;
+        sps.log2_min_luma_transform_block_size_minus2 =
+            self.log2_min_luma_transform_block_size_minus2;
+        sps.log2_diff_max_min_luma_transform_block_size =
+            self.log2_diff_max_min_luma_transform_block_size;
+        sps.max_transform_hierarchy_depth_inter = self.max_transform_hierarchy_depth_inter;
+        sps.max_transform_hierarchy_depth_intra = self.max_transform_hierarchy_depth_intra;
+        sps.num_short_term_ref_pic_sets = self.short_term_ref_pic_sets.len() as u8;
+        sps.num_long_term_ref_pics_sps = self.long_term_ref_pics_sps.len() as u8;
+        sps.pcm_sample_bit_depth_luma_minus1 = self.pcm_sample_bit_depth_luma_minus1;
+        sps.pcm_sample_bit_depth_chroma_minus1 = self.pcm_sample_bit_depth_chroma_minus1;
+        sps.log2_min_pcm_luma_coding_block_size_minus3 =
+            self.log2_min_pcm_luma_coding_block_size_minus3;
+        sps.log2_diff_max_min_pcm_luma_coding_block_size =
+            self.log2_diff_max_min_pcm_luma_coding_block_size;
+        sps.conf_win_left_offset = self.conf_win_left_offset;
+        sps.conf_win_right_offset = self.conf_win_right_offset;
+        sps.conf_win_top_offset = self.conf_win_top_offset;
+        sps.conf_win_bottom_offset = self.conf_win_bottom_offset;
+
+        let mut long_term_ref_pics: StdVideoH265LongTermRefPicsSps = unsafe { mem::zeroed() };
+        for (i, long_term) in self.long_term_ref_pics_sps.iter().enumerate() {
+            long_term_ref_pics.lt_ref_pic_poc_lsb_sps[i] = long_term.lt_ref_pic_poc_lsb_sps;
+            long_term_ref_pics.used_by_curr_pic_lt_sps_flag |=
+                (long_term.used_by_curr_pic_lt_sps_flag as u32) << i;
+        }
+
+        let mut std = StdSps {
+            sps,
+            profile_tier_level: Box::new(self.profile_tier_level.to_std()),
+            dec_pic_buf_mgr: Box::new(self.dec_pic_buf_mgr.to_std()),
+            scaling_lists: Box::new(self.scaling_lists.to_std()),
+            short_term_ref_pic_sets: self
+                .short_term_ref_pic_sets
+                .iter()
+                .map(ShortTermRefPicSet::to_std)
+                .collect(),
+            long_term_ref_pics: Box::new(long_term_ref_pics),
+            vui: Box::new(
+                self.vui
+                    .as_ref()
+                    .map(Vui::to_std)
+                    .unwrap_or_else(|| unsafe { mem::zeroed() }),
+            ),
+            hrd: self
+                .vui
+                .as_ref()
+                .and_then(|vui| vui.hrd_parameters.as_ref())
+                .map(|hrd| Box::new(hrd.to_std())),
+        };
+
+        std.sps.pProfileTierLevel = &*std.profile_tier_level;
+        std.sps.pDecPicBufMgr = &*std.dec_pic_buf_mgr;
+        if self.sps_scaling_list_data_present_flag {
+            std.sps.pScalingLists = &*std.scaling_lists;
+        }
+        if !std.short_term_ref_pic_sets.is_empty() {
+            std.sps.pShortTermRefPicSet = std.short_term_ref_pic_sets.as_ptr();
+        }
+        if self.long_term_ref_pics_present_flag {
+            std.sps.pLongTermRefPicsSps = &*std.long_term_ref_pics;
+        }
+        if self.vui.is_some() {
+            if let Some(hrd) = &std.hrd {
+                std.vui.pHrdParameters = &hrd.hrd;
+            }
+            std.sps.pSequenceParameterSetVui = &*std.vui;
+        }
+
+        std
+    }
+}
+
+/// `StdVideoH265SequenceParameterSet` together with the structures its pointers reference.
+/// The pointers stay valid for as long as this value is alive, moving it is fine.
+pub struct StdSps {
+    pub sps: StdVideoH265SequenceParameterSet,
+    profile_tier_level: Box<StdVideoH265ProfileTierLevel>,
+    dec_pic_buf_mgr: Box<StdVideoH265DecPicBufMgr>,
+    scaling_lists: Box<StdVideoH265ScalingLists>,
+    short_term_ref_pic_sets: Vec<StdVideoH265ShortTermRefPicSet>,
+    long_term_ref_pics: Box<StdVideoH265LongTermRefPicsSps>,
+    vui: Box<StdVideoH265SequenceParameterSetVui>,
+    hrd: Option<Box<StdHrd>>,
+}
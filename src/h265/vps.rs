@@ -0,0 +1,586 @@
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use ash::vk::native::{
+    StdVideoH265DecPicBufMgr, StdVideoH265HrdParameters, StdVideoH265LevelIdc,
+    StdVideoH265ProfileIdc, StdVideoH265ProfileTierLevel, StdVideoH265SubLayerHrdParameters,
+    StdVideoH265VideoParameterSet,
+};
+
+use crate::bitreader::{nal_to_rbsp, BitReader};
+use crate::h265::{NalUnitHeader, NalUnitType};
+
+pub const MAX_VPS_COUNT: usize = 16;
+/// Up to 7 temporal sub-layers
+pub const MAX_SUB_LAYERS: usize = 7;
+
+/// The general part of profile_tier_level(), 7.3.3. Sub-layer profiles and levels are skipped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileTierLevel {
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    /// general_profile_compatibility_flag[j] in bit j
+    pub general_profile_compatibility_flags: u32,
+    pub general_progressive_source_flag: bool,
+    pub general_interlaced_source_flag: bool,
+    pub general_non_packed_constraint_flag: bool,
+    pub general_frame_only_constraint_flag: bool,
+    pub general_level_idc: u8,
+}
+
+impl ProfileTierLevel {
+    pub(crate) fn parse(reader: &mut BitReader, max_sub_layers_minus1: u8) -> Result<Self> {
+        let general_profile_space = reader.read_bits(2)? as u8;
+        let general_tier_flag = reader.read_flag()?;
+        let general_profile_idc = reader.read_bits(5)? as u8;
+        let general_profile_compatibility_flags = reader.read_bits(32)?.reverse_bits();
+        let general_progressive_source_flag = reader.read_flag()?;
+        let general_interlaced_source_flag = reader.read_flag()?;
+        let general_non_packed_constraint_flag = reader.read_flag()?;
+        let general_frame_only_constraint_flag = reader.read_flag()?;
+        // Constraint flags and general_inbld_flag
+        reader.skip_bits(44)?;
+        let general_level_idc = reader.read_bits(8)? as u8;
+
+        let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1 as usize);
+        for _ in 0..max_sub_layers_minus1 {
+            let profile_present = reader.read_flag()?;
+            let level_present = reader.read_flag()?;
+            sub_layer_flags.push((profile_present, level_present));
+        }
+        if max_sub_layers_minus1 > 0 {
+            // reserved_zero_2bits
+            reader.skip_bits(2 * (8 - max_sub_layers_minus1 as usize))?;
+        }
+        for (profile_present, level_present) in sub_layer_flags {
+            if profile_present {
+                reader.skip_bits(88)?;
+            }
+            if level_present {
+                reader.skip_bits(8)?;
+            }
+        }
+
+        Ok(Self {
+            general_profile_space,
+            general_tier_flag,
+            general_profile_idc,
+            general_profile_compatibility_flags,
+            general_progressive_source_flag,
+            general_interlaced_source_flag,
+            general_non_packed_constraint_flag,
+            general_frame_only_constraint_flag,
+            general_level_idc,
+        })
+    }
+
+    /// general_profile_idc, or the first profile the stream claims compatibility with when
+    /// it is 0.
+    pub fn profile_idc(&self) -> u8 {
+        match self.general_profile_idc {
+            0 if self.general_profile_compatibility_flags != 0 => {
+                self.general_profile_compatibility_flags.trailing_zeros() as u8
+            }
+            profile_idc => profile_idc,
+        }
+    }
+
+    /// Maps general_level_idc, 30 times the level number, onto StdVideoH265LevelIdc.
+    pub fn std_level_idc(&self) -> StdVideoH265LevelIdc {
+        match self.general_level_idc {
+            30 => 0,
+            60 => 1,
+            63 => 2,
+            90 => 3,
+            93 => 4,
+            120 => 5,
+            123 => 6,
+            150 => 7,
+            153 => 8,
+            156 => 9,
+            180 => 10,
+            183 => 11,
+            186 => 12,
+            _ => ash::vk::native::StdVideoH265LevelIdc_STD_VIDEO_H265_LEVEL_IDC_INVALID,
+        }
+    }
+
+    pub fn to_std(&self) -> StdVideoH265ProfileTierLevel {
+        let mut ptl: StdVideoH265ProfileTierLevel = unsafe { mem::zeroed() };
+
+        ptl.flags
+            .set_general_tier_flag(self.general_tier_flag as u32);
+        ptl.flags
+            .set_general_progressive_source_flag(self.general_progressive_source_flag as u32);
+        ptl.flags
+            .set_general_interlaced_source_flag(self.general_interlaced_source_flag as u32);
+        ptl.flags
+            .set_general_non_packed_constraint_flag(self.general_non_packed_constraint_flag as u32);
+        ptl.flags
+            .set_general_frame_only_constraint_flag(self.general_frame_only_constraint_flag as u32);
+        ptl.general_profile_idc = self.profile_idc() as StdVideoH265ProfileIdc;
+        ptl.general_level_idc = self.std_level_idc();
+
+        ptl
+    }
+}
+
+/// Per sub-layer DPB sizes, the sub_layer_ordering_info of the VPS and SPS.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecPicBufMgr {
+    pub max_dec_pic_buffering_minus1: [u8; MAX_SUB_LAYERS],
+    pub max_num_reorder_pics: [u8; MAX_SUB_LAYERS],
+    pub max_latency_increase_plus1: [u32; MAX_SUB_LAYERS],
+}
+
+impl DecPicBufMgr {
+    /// Values of sub-layers that are not signalled are inferred from the highest one.
+    pub(crate) fn parse(
+        reader: &mut BitReader,
+        max_sub_layers_minus1: u8,
+        sub_layer_ordering_info_present_flag: bool,
+    ) -> Result<Self> {
+        let highest = max_sub_layers_minus1 as usize;
+        let first = if sub_layer_ordering_info_present_flag {
+            0
+        } else {
+            highest
+        };
+
+        let mut buf_mgr = DecPicBufMgr::default();
+        for i in first..=highest {
+            let max_dec_pic_buffering_minus1 = reader.read_ue()?;
+            let max_num_reorder_pics = reader.read_ue()?;
+            if max_dec_pic_buffering_minus1 > 15
+                || max_num_reorder_pics > max_dec_pic_buffering_minus1
+            {
+                return Err(anyhow!(
+                    "Invalid max_dec_pic_buffering_minus1 {} max_num_reorder_pics {}",
+                    max_dec_pic_buffering_minus1,
+                    max_num_reorder_pics
+                ));
+            }
+            buf_mgr.max_dec_pic_buffering_minus1[i] = max_dec_pic_buffering_minus1 as u8;
+            buf_mgr.max_num_reorder_pics[i] = max_num_reorder_pics as u8;
+            buf_mgr.max_latency_increase_plus1[i] = reader.read_ue()?;
+        }
+        for i in 0..first {
+            buf_mgr.max_dec_pic_buffering_minus1[i] = buf_mgr.max_dec_pic_buffering_minus1[highest];
+            buf_mgr.max_num_reorder_pics[i] = buf_mgr.max_num_reorder_pics[highest];
+            buf_mgr.max_latency_increase_plus1[i] = buf_mgr.max_latency_increase_plus1[highest];
+        }
+
+        Ok(buf_mgr)
+    }
+
+    pub fn to_std(&self) -> StdVideoH265DecPicBufMgr {
+        StdVideoH265DecPicBufMgr {
+            max_latency_increase_plus1: self.max_latency_increase_plus1,
+            max_dec_pic_buffering_minus1: self.max_dec_pic_buffering_minus1,
+            max_num_reorder_pics: self.max_num_reorder_pics,
+        }
+    }
+}
+
+/// sub_layer_hrd_parameters(), E.2.3
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubLayerHrdParameters {
+    pub bit_rate_value_minus1: Vec<u32>,
+    pub cpb_size_value_minus1: Vec<u32>,
+    pub cpb_size_du_value_minus1: Vec<u32>,
+    pub bit_rate_du_value_minus1: Vec<u32>,
+    pub cbr_flag: Vec<bool>,
+}
+
+impl SubLayerHrdParameters {
+    fn parse(
+        reader: &mut BitReader,
+        cpb_cnt_minus1: u8,
+        sub_pic_hrd_params_present_flag: bool,
+    ) -> Result<Self> {
+        let mut sub_layer = SubLayerHrdParameters::default();
+        for _ in 0..=cpb_cnt_minus1 {
+            sub_layer.bit_rate_value_minus1.push(reader.read_ue()?);
+            sub_layer.cpb_size_value_minus1.push(reader.read_ue()?);
+            if sub_pic_hrd_params_present_flag {
+                sub_layer.cpb_size_du_value_minus1.push(reader.read_ue()?);
+                sub_layer.bit_rate_du_value_minus1.push(reader.read_ue()?);
+            }
+            sub_layer.cbr_flag.push(reader.read_flag()?);
+        }
+        Ok(sub_layer)
+    }
+
+    fn to_std(&self) -> StdVideoH265SubLayerHrdParameters {
+        let mut sub_layer: StdVideoH265SubLayerHrdParameters = unsafe { mem::zeroed() };
+        for (i, &value) in self.bit_rate_value_minus1.iter().enumerate() {
+            sub_layer.bit_rate_value_minus1[i] = value;
+        }
+        for (i, &value) in self.cpb_size_value_minus1.iter().enumerate() {
+            sub_layer.cpb_size_value_minus1[i] = value;
+        }
+        for (i, &value) in self.cpb_size_du_value_minus1.iter().enumerate() {
+            sub_layer.cpb_size_du_value_minus1[i] = value;
+        }
+        for (i, &value) in self.bit_rate_du_value_minus1.iter().enumerate() {
+            sub_layer.bit_rate_du_value_minus1[i] = value;
+        }
+        for (i, &cbr) in self.cbr_flag.iter().enumerate() {
+            sub_layer.cbr_flag |= (cbr as u32) << i;
+        }
+        sub_layer
+    }
+}
+
+/// Timing information of one temporal sub-layer in hrd_parameters()
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubLayerHrd {
+    pub fixed_pic_rate_general_flag: bool,
+    pub fixed_pic_rate_within_cvs_flag: bool,
+    pub elemental_duration_in_tc_minus1: u16,
+    pub low_delay_hrd_flag: bool,
+    pub cpb_cnt_minus1: u8,
+    pub nal: Option<SubLayerHrdParameters>,
+    pub vcl: Option<SubLayerHrdParameters>,
+}
+
+/// hrd_parameters(), E.2.2
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HrdParameters {
+    pub nal_hrd_parameters_present_flag: bool,
+    pub vcl_hrd_parameters_present_flag: bool,
+    pub sub_pic_hrd_params_present_flag: bool,
+    pub tick_divisor_minus2: u8,
+    pub du_cpb_removal_delay_increment_length_minus1: u8,
+    pub sub_pic_cpb_params_in_pic_timing_sei_flag: bool,
+    pub dpb_output_delay_du_length_minus1: u8,
+    pub bit_rate_scale: u8,
+    pub cpb_size_scale: u8,
+    pub cpb_size_du_scale: u8,
+    pub initial_cpb_removal_delay_length_minus1: u8,
+    pub au_cpb_removal_delay_length_minus1: u8,
+    pub dpb_output_delay_length_minus1: u8,
+    pub sub_layers: Vec<SubLayerHrd>,
+}
+
+impl HrdParameters {
+    pub(crate) fn parse(
+        reader: &mut BitReader,
+        common_inf_present_flag: bool,
+        max_sub_layers_minus1: u8,
+    ) -> Result<Self> {
+        let mut hrd = HrdParameters::default();
+
+        if common_inf_present_flag {
+            hrd.nal_hrd_parameters_present_flag = reader.read_flag()?;
+            hrd.vcl_hrd_parameters_present_flag = reader.read_flag()?;
+            if hrd.nal_hrd_parameters_present_flag || hrd.vcl_hrd_parameters_present_flag {
+                hrd.sub_pic_hrd_params_present_flag = reader.read_flag()?;
+                if hrd.sub_pic_hrd_params_present_flag {
+                    hrd.tick_divisor_minus2 = reader.read_bits(8)? as u8;
+                    hrd.du_cpb_removal_delay_increment_length_minus1 = reader.read_bits(5)? as u8;
+                    hrd.sub_pic_cpb_params_in_pic_timing_sei_flag = reader.read_flag()?;
+                    hrd.dpb_output_delay_du_length_minus1 = reader.read_bits(5)? as u8;
+                }
+                hrd.bit_rate_scale = reader.read_bits(4)? as u8;
+                hrd.cpb_size_scale = reader.read_bits(4)? as u8;
+                if hrd.sub_pic_hrd_params_present_flag {
+                    hrd.cpb_size_du_scale = reader.read_bits(4)? as u8;
+                }
+                hrd.initial_cpb_removal_delay_length_minus1 = reader.read_bits(5)? as u8;
+                hrd.au_cpb_removal_delay_length_minus1 = reader.read_bits(5)? as u8;
+                hrd.dpb_output_delay_length_minus1 = reader.read_bits(5)? as u8;
+            }
+        }
+
+        for _ in 0..=max_sub_layers_minus1 {
+            let mut sub_layer = SubLayerHrd {
+                fixed_pic_rate_general_flag: reader.read_flag()?,
+                ..Default::default()
+            };
+            sub_layer.fixed_pic_rate_within_cvs_flag =
+                sub_layer.fixed_pic_rate_general_flag || reader.read_flag()?;
+            if sub_layer.fixed_pic_rate_within_cvs_flag {
+                let elemental_duration_in_tc_minus1 = reader.read_ue()?;
+                if elemental_duration_in_tc_minus1 > 2047 {
+                    return Err(anyhow!(
+                        "Invalid elemental_duration_in_tc_minus1 {}",
+                        elemental_duration_in_tc_minus1
+                    ));
+                }
+                sub_layer.elemental_duration_in_tc_minus1 = elemental_duration_in_tc_minus1 as u16;
+            } else {
+                sub_layer.low_delay_hrd_flag = reader.read_flag()?;
+            }
+            if !sub_layer.low_delay_hrd_flag {
+                let cpb_cnt_minus1 = reader.read_ue()?;
+                if cpb_cnt_minus1 > 31 {
+                    return Err(anyhow!("Invalid cpb_cnt_minus1 {}", cpb_cnt_minus1));
+                }
+                sub_layer.cpb_cnt_minus1 = cpb_cnt_minus1 as u8;
+            }
+
+            if hrd.nal_hrd_parameters_present_flag {
+                sub_layer.nal = Some(SubLayerHrdParameters::parse(
+                    reader,
+                    sub_layer.cpb_cnt_minus1,
+                    hrd.sub_pic_hrd_params_present_flag,
+                )?);
+            }
+            if hrd.vcl_hrd_parameters_present_flag {
+                sub_layer.vcl = Some(SubLayerHrdParameters::parse(
+                    reader,
+                    sub_layer.cpb_cnt_minus1,
+                    hrd.sub_pic_hrd_params_present_flag,
+                )?);
+            }
+            hrd.sub_layers.push(sub_layer);
+        }
+
+        Ok(hrd)
+    }
+
+    pub fn to_std(&self) -> StdHrd {
+        let mut hrd: StdVideoH265HrdParameters = unsafe { mem::zeroed() };
+
+        hrd.flags
+            .set_nal_hrd_parameters_present_flag(self.nal_hrd_parameters_present_flag as u32);
+        hrd.flags
+            .set_vcl_hrd_parameters_present_flag(self.vcl_hrd_parameters_present_flag as u32);
+        hrd.flags
+            .set_sub_pic_hrd_params_present_flag(self.sub_pic_hrd_params_present_flag as u32);
+        hrd.flags.set_sub_pic_cpb_params_in_pic_timing_sei_flag(
+            self.sub_pic_cpb_params_in_pic_timing_sei_flag as u32,
+        );
+
+        // The per sub-layer flags are bit masks
+        let mask = |flag: fn(&SubLayerHrd) -> bool| {
+            self.sub_layers
+                .iter()
+                .enumerate()
+                .fold(0, |mask, (i, sub_layer)| {
+                    mask | ((flag(sub_layer) as u32) << i)
+                })
+        };
+        hrd.flags.set_fixed_pic_rate_general_flag(mask(|sub_layer| {
+            sub_layer.fixed_pic_rate_general_flag
+        }));
+        hrd.flags
+            .set_fixed_pic_rate_within_cvs_flag(mask(|sub_layer| {
+                sub_layer.fixed_pic_rate_within_cvs_flag
+            }));
+        hrd.flags
+            .set_low_delay_hrd_flag(mask(|sub_layer| sub_layer.low_delay_hrd_flag));
+
+        hrd.tick_divisor_minus2 = self.tick_divisor_minus2;
+        hrd.du_cpb_removal_delay_increment_length_minus1 =
+            self.du_cpb_removal_delay_increment_length_minus1;
+        hrd.dpb_output_delay_du_length_minus1 = self.dpb_output_delay_du_length_minus1;
+        hrd.bit_rate_scale = self.bit_rate_scale;
+        hrd.cpb_size_scale = self.cpb_size_scale;
+        hrd.cpb_size_du_scale = self.cpb_size_du_scale;
+        hrd.initial_cpb_removal_delay_length_minus1 = self.initial_cpb_removal_delay_length_minus1;
+        hrd.au_cpb_removal_delay_length_minus1 = self.au_cpb_removal_delay_length_minus1;
+        hrd.dpb_output_delay_length_minus1 = self.dpb_output_delay_length_minus1;
+        for (i, sub_layer) in self.sub_layers.iter().enumerate() {
+            hrd.cpb_cnt_minus1[i] = sub_layer.cpb_cnt_minus1;
+            hrd.elemental_duration_in_tc_minus1[i] = sub_layer.elemental_duration_in_tc_minus1;
+        }
+
+        let mut std = StdHrd {
+            hrd,
+            nal: self
+                .sub_layers
+                .iter()
+                .map(|sub_layer| sub_layer.nal.as_ref().map(SubLayerHrdParameters::to_std))
+                .collect::<Option<Vec<_>>>()
+                .unwrap_or_default(),
+            vcl: self
+                .sub_layers
+                .iter()
+                .map(|sub_layer| sub_layer.vcl.as_ref().map(SubLayerHrdParameters::to_std))
+                .collect::<Option<Vec<_>>>()
+                .unwrap_or_default(),
+        };
+        if !std.nal.is_empty() {
+            std.hrd.pSubLayerHrdParametersNal = std.nal.as_ptr();
+        }
+        if !std.vcl.is_empty() {
+            std.hrd.pSubLayerHrdParametersVcl = std.vcl.as_ptr();
+        }
+
+        std
+    }
+}
+
+/// `StdVideoH265HrdParameters` together with the sub-layer parameters it points at.
+/// Moving it is fine, the sub-layer parameters live on the heap.
+pub struct StdHrd {
+    pub hrd: StdVideoH265HrdParameters,
+    nal: Vec<StdVideoH265SubLayerHrdParameters>,
+    vcl: Vec<StdVideoH265SubLayerHrdParameters>,
+}
+
+/// Video parameter set, 7.3.2.1. Only the base layer is of interest, layer sets and the
+/// extension are skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vps {
+    pub vps_video_parameter_set_id: u8,
+    pub vps_max_sub_layers_minus1: u8,
+    pub vps_temporal_id_nesting_flag: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub vps_sub_layer_ordering_info_present_flag: bool,
+    pub dec_pic_buf_mgr: DecPicBufMgr,
+    pub vps_timing_info_present_flag: bool,
+    pub vps_num_units_in_tick: u32,
+    pub vps_time_scale: u32,
+    pub vps_poc_proportional_to_timing_flag: bool,
+    pub vps_num_ticks_poc_diff_one_minus1: u32,
+    /// The first of the signalled HRD parameters, the one `StdVideoH265VideoParameterSet` has room for
+    pub hrd_parameters: Option<HrdParameters>,
+}
+
+impl Vps {
+    /// Parses a VPS NAL unit, including its two byte header and any emulation prevention bytes.
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let header = NalUnitHeader::parse(nal)?;
+        if header.nal_unit_type != NalUnitType::Vps {
+            return Err(anyhow!(
+                "Expected a VPS NAL unit, got {:?}",
+                header.nal_unit_type
+            ));
+        }
+
+        let rbsp = nal_to_rbsp(&nal[2..]);
+        Self::parse_rbsp(&mut BitReader::new(&rbsp))
+    }
+
+    pub fn parse_rbsp(reader: &mut BitReader) -> Result<Self> {
+        let vps_video_parameter_set_id = reader.read_bits(4)? as u8;
+        // vps_base_layer_internal_flag, vps_base_layer_available_flag, vps_max_layers_minus1
+        reader.skip_bits(8)?;
+        let vps_max_sub_layers_minus1 = reader.read_bits(3)? as u8;
+        if vps_max_sub_layers_minus1 as usize >= MAX_SUB_LAYERS {
+            return Err(anyhow!(
+                "Invalid vps_max_sub_layers_minus1 {}",
+                vps_max_sub_layers_minus1
+            ));
+        }
+        let vps_temporal_id_nesting_flag = reader.read_flag()?;
+        // vps_reserved_0xffff_16bits
+        reader.skip_bits(16)?;
+
+        let profile_tier_level = ProfileTierLevel::parse(reader, vps_max_sub_layers_minus1)?;
+        let vps_sub_layer_ordering_info_present_flag = reader.read_flag()?;
+        let dec_pic_buf_mgr = DecPicBufMgr::parse(
+            reader,
+            vps_max_sub_layers_minus1,
+            vps_sub_layer_ordering_info_present_flag,
+        )?;
+
+        let vps_max_layer_id = reader.read_bits(6)?;
+        let vps_num_layer_sets_minus1 = reader.read_ue()?;
+        if vps_num_layer_sets_minus1 > 1023 {
+            return Err(anyhow!(
+                "Invalid vps_num_layer_sets_minus1 {}",
+                vps_num_layer_sets_minus1
+            ));
+        }
+        // layer_id_included_flag
+        reader.skip_bits(vps_num_layer_sets_minus1 as usize * (vps_max_layer_id as usize + 1))?;
+
+        let mut vps = Self {
+            vps_video_parameter_set_id,
+            vps_max_sub_layers_minus1,
+            vps_temporal_id_nesting_flag,
+            profile_tier_level,
+            vps_sub_layer_ordering_info_present_flag,
+            dec_pic_buf_mgr,
+            vps_timing_info_present_flag: reader.read_flag()?,
+            vps_num_units_in_tick: 0,
+            vps_time_scale: 0,
+            vps_poc_proportional_to_timing_flag: false,
+            vps_num_ticks_poc_diff_one_minus1: 0,
+            hrd_parameters: None,
+        };
+
+        if vps.vps_timing_info_present_flag {
+            vps.vps_num_units_in_tick = reader.read_bits(32)?;
+            vps.vps_time_scale = reader.read_bits(32)?;
+            vps.vps_poc_proportional_to_timing_flag = reader.read_flag()?;
+            if vps.vps_poc_proportional_to_timing_flag {
+                vps.vps_num_ticks_poc_diff_one_minus1 = reader.read_ue()?;
+            }
+
+            let vps_num_hrd_parameters = reader.read_ue()?;
+            if vps_num_hrd_parameters > vps_num_layer_sets_minus1 + 1 {
+                return Err(anyhow!(
+                    "Invalid vps_num_hrd_parameters {}",
+                    vps_num_hrd_parameters
+                ));
+            }
+            for i in 0..vps_num_hrd_parameters {
+                // hrd_layer_set_idx
+                reader.read_ue()?;
+                let cprms_present_flag = i == 0 || reader.read_flag()?;
+                let hrd = HrdParameters::parse(
+                    reader,
+                    cprms_present_flag,
+                    vps.vps_max_sub_layers_minus1,
+                )?;
+                vps.hrd_parameters.get_or_insert(hrd);
+            }
+        }
+
+        Ok(vps)
+    }
+
+    pub fn to_std(&self) -> StdVps {
+        let mut vps: StdVideoH265VideoParameterSet = unsafe { mem::zeroed() };
+
+        vps.flags
+            .set_vps_temporal_id_nesting_flag(self.vps_temporal_id_nesting_flag as u32);
+        vps.flags.set_vps_sub_layer_ordering_info_present_flag(
+            self.vps_sub_layer_ordering_info_present_flag as u32,
+        );
+        vps.flags
+            .set_vps_timing_info_present_flag(self.vps_timing_info_present_flag as u32);
+        vps.flags.set_vps_poc_proportional_to_timing_flag(
+            self.vps_poc_proportional_to_timing_flag as u32,
+        );
+
+        vps.vps_video_parameter_set_id = self.vps_video_parameter_set_id;
+        vps.vps_max_sub_layers_minus1 = self.vps_max_sub_layers_minus1;
+        vps.vps_num_units_in_tick = self.vps_num_units_in_tick;
+        vps.vps_time_scale = self.vps_time_scale;
+        vps.vps_num_ticks_poc_diff_one_minus1 = self.vps_num_ticks_poc_diff_one_minus1;
+
+        let mut std = StdVps {
+            vps,
+            profile_tier_level: Box::new(self.profile_tier_level.to_std()),
+            dec_pic_buf_mgr: Box::new(self.dec_pic_buf_mgr.to_std()),
+            hrd: self
+                .hrd_parameters
+                .as_ref()
+                .map(|hrd| Box::new(hrd.to_std())),
+        };
+
+        std.vps.pProfileTierLevel = &*std.profile_tier_level;
+        std.vps.pDecPicBufMgr = &*std.dec_pic_buf_mgr;
+        if let Some(hrd) = &std.hrd {
+            std.vps.pHrdParameters = &hrd.hrd;
+        }
+
+        std
+    }
+}
+
+/// `StdVideoH265VideoParameterSet` together with the structures its pointers reference.
+/// The pointers stay valid for as long as this value is alive, moving it is fine.
+pub struct StdVps {
+    pub vps: StdVideoH265VideoParameterSet,
+    profile_tier_level: Box<StdVideoH265ProfileTierLevel>,
+    dec_pic_buf_mgr: Box<StdVideoH265DecPicBufMgr>,
+    hrd: Option<Box<StdHrd>>,
+}
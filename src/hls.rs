@@ -0,0 +1,403 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use crate::codec::Codec;
+use crate::http;
+
+/// Segments from the end of a live playlist to start at (RFC 8216 6.3.3)
+const LIVE_START_SEGMENTS: usize = 3;
+
+/// Splits an attribute list (RFC 8216 4.2) into names and values, without the quotes of
+/// quoted strings.
+fn attributes(list: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = list;
+    while let Some((name, value)) = rest.split_once('=') {
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let next = quoted[end..].trim_start_matches('"');
+                (
+                    &quoted[..end],
+                    next.split_once(',').map_or("", |(_, next)| next),
+                )
+            }
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        attributes.push((name.trim(), value));
+        rest = next;
+    }
+    attributes
+}
+
+/// A byte range of `EXT-X-BYTERANGE` or of the `BYTERANGE` attribute, `<length>[@<offset>]`.
+/// Without an offset, the range follows `previous`.
+fn byte_range(value: &str, previous: Option<&Range<u64>>) -> Result<Range<u64>> {
+    let invalid = || anyhow!("Invalid byte range {:?}", value);
+    let (length, offset) = match value.split_once('@') {
+        Some((length, offset)) => (length, Some(offset.parse().map_err(|_| invalid())?)),
+        None => (value, None),
+    };
+    let length: u64 = length.parse().map_err(|_| invalid())?;
+    let offset = offset
+        .or_else(|| previous.map(|range| range.end))
+        .ok_or_else(invalid)?;
+    let end = offset.checked_add(length).ok_or_else(invalid)?;
+    Ok(offset..end)
+}
+
+/// A variant stream of a master playlist (RFC 8216 4.3.4.2).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant {
+    pub uri: String,
+    /// Peak bit rate in bits per second
+    pub bandwidth: u64,
+    pub resolution: Option<(u32, u32)>,
+    /// The formats of the `CODECS` attribute, such as `avc1.64001f`
+    pub codecs: Vec<String>,
+}
+
+impl Variant {
+    /// The video codec of the variant, H.264 when it leaves out `CODECS`. None for audio
+    /// only variants and video codecs we do not decode.
+    pub fn codec(&self) -> Option<Codec> {
+        if self.codecs.is_empty() {
+            return Some(Codec::H264);
+        }
+        self.codecs.iter().find_map(
+            |format| match format.split('.').next().unwrap_or_default() {
+                "avc1" | "avc3" => Some(Codec::H264),
+                "hvc1" | "hev1" => Some(Codec::H265),
+                "av01" => Some(Codec::Av1),
+                _ => None,
+            },
+        )
+    }
+}
+
+/// The `EXT-X-MAP` of fragmented MP4 segments, holding their `moov`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaInitialization {
+    pub uri: String,
+    pub byte_range: Option<Range<u64>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub uri: String,
+    /// Seconds, from `EXTINF`
+    pub duration: f64,
+    pub byte_range: Option<Range<u64>>,
+    pub map: Option<MediaInitialization>,
+    /// `EXT-X-DISCONTINUITY` before the segment
+    pub discontinuity: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaPlaylist {
+    /// Seconds, from `EXT-X-TARGETDURATION`
+    pub target_duration: u64,
+    /// Sequence number of the first segment
+    pub media_sequence: u64,
+    pub segments: Vec<Segment>,
+    /// `EXT-X-ENDLIST`, no segments will be added
+    pub end_list: bool,
+}
+
+/// A master playlist listing variant streams, or a media playlist listing segments.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Playlist {
+    Master(Vec<Variant>),
+    Media(MediaPlaylist),
+}
+
+impl Playlist {
+    /// Parses an M3U8 playlist (RFC 8216 4), leaving the URIs as they are written.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(anyhow!("Not an M3U8 playlist"));
+        }
+
+        let mut variants = Vec::new();
+        let mut media = MediaPlaylist {
+            target_duration: 0,
+            media_sequence: 0,
+            segments: Vec::new(),
+            end_list: false,
+        };
+        // Tags applying to the next URI
+        let mut variant: Option<Variant> = None;
+        let mut duration = None;
+        let mut range = None;
+        let mut discontinuity = false;
+        let mut map = None;
+        // The previous byte range of each URI, where ranges without an offset continue
+        let mut previous_ranges: Vec<(String, Range<u64>)> = Vec::new();
+
+        for line in lines {
+            let (tag, value) = line.split_once(':').unwrap_or((line, ""));
+            match tag {
+                "#EXT-X-STREAM-INF" => {
+                    let mut stream = Variant {
+                        uri: String::new(),
+                        bandwidth: 0,
+                        resolution: None,
+                        codecs: Vec::new(),
+                    };
+                    for (name, value) in attributes(value) {
+                        match name {
+                            "BANDWIDTH" => {
+                                stream.bandwidth = value
+                                    .parse()
+                                    .map_err(|_| anyhow!("Invalid BANDWIDTH {:?}", value))?
+                            }
+                            "RESOLUTION" => {
+                                stream.resolution = value
+                                    .split_once('x')
+                                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                            }
+                            "CODECS" => {
+                                stream.codecs = value.split(',').map(|c| c.trim().into()).collect()
+                            }
+                            _ => {}
+                        }
+                    }
+                    variant = Some(stream);
+                }
+                "#EXTINF" => {
+                    let seconds = value.split(',').next().unwrap_or_default();
+                    duration = Some(
+                        seconds
+                            .trim()
+                            .parse::<f64>()
+                            .map_err(|_| anyhow!("Invalid EXTINF duration {:?}", seconds))?,
+                    );
+                }
+                "#EXT-X-TARGETDURATION" => {
+                    media.target_duration = value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid target duration {:?}", value))?;
+                }
+                "#EXT-X-MEDIA-SEQUENCE" => {
+                    media.media_sequence = value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid media sequence {:?}", value))?;
+                }
+                "#EXT-X-BYTERANGE" => range = Some(value.to_string()),
+                "#EXT-X-DISCONTINUITY" => discontinuity = true,
+                "#EXT-X-ENDLIST" => media.end_list = true,
+                "#EXT-X-MAP" => {
+                    let attributes = attributes(value);
+                    let attribute = |name: &str| {
+                        attributes
+                            .iter()
+                            .find(|&&(n, _)| n == name)
+                            .map(|&(_, value)| value)
+                    };
+                    map = Some(MediaInitialization {
+                        uri: attribute("URI")
+                            .ok_or_else(|| anyhow!("EXT-X-MAP without a URI"))?
+                            .to_string(),
+                        byte_range: attribute("BYTERANGE")
+                            .map(|range| byte_range(range, None))
+                            .transpose()?,
+                    });
+                }
+                "#EXT-X-KEY" => {
+                    let method = attributes(value)
+                        .into_iter()
+                        .find(|&(name, _)| name == "METHOD")
+                        .map_or("NONE", |(_, method)| method);
+                    if method != "NONE" {
+                        return Err(anyhow!("Encrypted HLS segments are not supported"));
+                    }
+                }
+                // Other tags and comments
+                _ if line.starts_with('#') => {}
+                _ => {
+                    let uri = line.to_string();
+                    if let Some(mut variant) = variant.take() {
+                        variant.uri = uri;
+                        variants.push(variant);
+                        continue;
+                    }
+
+                    let duration = duration
+                        .take()
+                        .ok_or_else(|| anyhow!("Segment {} without EXTINF", uri))?;
+                    let byte_range = match range.take() {
+                        Some(range) => {
+                            let previous = previous_ranges.iter().find(|(u, _)| *u == uri);
+                            let range = byte_range(&range, previous.map(|(_, range)| range))?;
+                            previous_ranges.retain(|(u, _)| *u != uri);
+                            previous_ranges.push((uri.clone(), range.clone()));
+                            Some(range)
+                        }
+                        None => None,
+                    };
+                    media.segments.push(Segment {
+                        uri,
+                        duration,
+                        byte_range,
+                        map: map.clone(),
+                        discontinuity: std::mem::take(&mut discontinuity),
+                    });
+                }
+            }
+        }
+
+        match (variants.is_empty(), media.segments.is_empty()) {
+            (false, true) => Ok(Self::Master(variants)),
+            (true, _) => Ok(Self::Media(media)),
+            (false, false) => Err(anyhow!("Playlist with both variants and segments")),
+        }
+    }
+}
+
+/// Picks the variant to play among those with a video codec we decode: the largest picture
+/// that fits in the `max_coded_extent` of its codec, then the highest bandwidth. With
+/// `max_bandwidth`, variants above it are left out unless none is below it, in which case
+/// the one with the lowest bandwidth is taken.
+///
+/// Variants without `RESOLUTION` are assumed to fit and rank below those with one. A codec
+/// `max_coded_extent` fails for, such as one the device does not decode, is left out.
+pub fn select_variant(
+    variants: &[Variant],
+    max_bandwidth: Option<u64>,
+    mut max_coded_extent: impl FnMut(Codec) -> Result<(u32, u32)>,
+) -> Result<&Variant> {
+    // One query per codec
+    let mut extents: Vec<(Codec, Option<(u32, u32)>)> = Vec::new();
+    let mut error = None;
+    let mut candidates = Vec::new();
+    for variant in variants {
+        let codec = match variant.codec() {
+            Some(codec) => codec,
+            None => continue,
+        };
+        let extent = match extents.iter().find(|(c, _)| *c == codec) {
+            Some(&(_, extent)) => extent,
+            None => {
+                let extent = max_coded_extent(codec)
+                    .map_err(|err| error = Some(err))
+                    .ok();
+                extents.push((codec, extent));
+                extent
+            }
+        };
+        let (max_width, max_height) = match extent {
+            Some(extent) => extent,
+            None => continue,
+        };
+        let too_large = matches!(
+            variant.resolution,
+            Some((width, height)) if width > max_width || height > max_height
+        );
+        if !too_large {
+            candidates.push(variant);
+        }
+    }
+
+    let within_bandwidth = candidates
+        .iter()
+        .copied()
+        .filter(|variant| variant.bandwidth <= max_bandwidth.unwrap_or(u64::MAX))
+        .max_by_key(|variant| {
+            let (width, height) = variant.resolution.unwrap_or_default();
+            (width as u64 * height as u64, variant.bandwidth)
+        });
+    within_bandwidth
+        .or_else(|| {
+            candidates
+                .iter()
+                .copied()
+                .min_by_key(|variant| variant.bandwidth)
+        })
+        .ok_or_else(|| match error {
+            Some(err) => err.context("No variant stream the decoder supports"),
+            None => anyhow!("No variant stream the decoder supports"),
+        })
+}
+
+/// Fetches the segments of a media playlist in order, reloading live playlists as they
+/// grow. Live playback starts a few segments from the end.
+pub struct SegmentFetcher {
+    url: String,
+    playlist: MediaPlaylist,
+    /// Media sequence number of the next segment
+    next: u64,
+    /// The current `EXT-X-MAP` and its bytes
+    map: Option<(MediaInitialization, Vec<u8>)>,
+}
+
+impl SegmentFetcher {
+    /// `url` is that of the media playlist, the segment URIs are relative to it.
+    pub fn new(url: &str, playlist: MediaPlaylist) -> Self {
+        let next = if playlist.end_list {
+            playlist.media_sequence
+        } else {
+            let start = playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS);
+            playlist.media_sequence + start as u64
+        };
+        Self {
+            url: url.to_string(),
+            playlist,
+            next,
+            map: None,
+        }
+    }
+
+    /// The `EXT-X-MAP` section of the segment returned last, for fragmented MP4 segments.
+    pub fn init_section(&self) -> Option<&[u8]> {
+        self.map.as_ref().map(|(_, data)| data.as_slice())
+    }
+
+    /// The next segment, or None at the end of the playlist. Blocks on live playlists until
+    /// the server adds a segment.
+    pub fn next_segment(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            // Behind the start of a live playlist that moved on, segments got lost
+            self.next = self.next.max(self.playlist.media_sequence);
+            let index = (self.next - self.playlist.media_sequence) as usize;
+            if let Some(segment) = self.playlist.segments.get(index) {
+                if let Some(map) = &segment.map {
+                    if self.map.as_ref().map(|(current, _)| current) != Some(map) {
+                        let data =
+                            http::get(&http::resolve(&self.url, &map.uri), map.byte_range.clone())?;
+                        self.map = Some((map.clone(), data));
+                    }
+                }
+                let data = http::get(
+                    &http::resolve(&self.url, &segment.uri),
+                    segment.byte_range.clone(),
+                )?;
+                self.next += 1;
+                return Ok(Some(data));
+            }
+            if self.playlist.end_list {
+                return Ok(None);
+            }
+
+            // Half the target duration when the playlist did not change (RFC 8216 6.3.4)
+            let interval = self
+                .playlist
+                .target_duration
+                .checked_mul(500)
+                .ok_or_else(|| anyhow!("Target duration of {} s", self.playlist.target_duration))?;
+            std::thread::sleep(Duration::from_millis(interval));
+            match Playlist::parse(&String::from_utf8_lossy(&http::get(&self.url, None)?))? {
+                Playlist::Media(playlist) => self.playlist = playlist,
+                Playlist::Master(_) => {
+                    return Err(anyhow!("Media playlist became a master playlist"))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::ops::Range;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+const DEFAULT_PORT: u16 = 80;
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The authority, host, port and path with query of an `http://host[:port][/path]` URL.
+fn parse_url(url: &str) -> Result<(&str, String, u16, String)> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None if url.starts_with("https://") => return Err(anyhow!("HTTPS is not supported")),
+        None => return Err(anyhow!("Not an http:// URL: {}", url)),
+    };
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(end) => (&rest[..end], &rest[end..]),
+        None => (rest, "/"),
+    };
+    let path = match path.strip_prefix('?') {
+        Some(query) => format!("/?{}", query),
+        None => path.to_string(),
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (
+            host,
+            port.parse()
+                .map_err(|_| anyhow!("Invalid port in URL {}", url))?,
+        ),
+        _ => (authority, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return Err(anyhow!("URL without a host: {}", url));
+    }
+    Ok((
+        authority,
+        host.trim_matches(['[', ']']).to_string(),
+        port,
+        path,
+    ))
+}
+
+/// Resolves a reference, as found in a playlist or a `Location` header, against the URL
+/// of the document it came from.
+pub fn resolve(base: &str, reference: &str) -> String {
+    if reference.contains("://") {
+        return reference.to_string();
+    }
+    let scheme_end = base.find("://").map_or(0, |end| end + 3);
+    if let Some(reference) = reference.strip_prefix("//") {
+        return format!("{}{}", &base[..scheme_end], reference);
+    }
+    let authority_end = base[scheme_end..]
+        .find(['/', '?'])
+        .map_or(base.len(), |end| scheme_end + end);
+    if reference.starts_with('/') {
+        return format!("{}{}", &base[..authority_end], reference);
+    }
+
+    // Relative to the directory of the base path, without its query
+    let path = &base[authority_end..];
+    let path = &path[..path.find('?').unwrap_or(path.len())];
+    let directory = &path[..path.rfind('/').map_or(0, |end| end + 1)];
+    let directory = if directory.is_empty() { "/" } else { directory };
+    format!("{}{}{}", &base[..authority_end], directory, reference)
+}
+
+/// Reads a chunked body (RFC 9112 7.1).
+fn read_chunked(reader: &mut impl BufRead) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let size = line.trim_end().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size.trim(), 16)
+            .map_err(|_| anyhow!("Invalid chunk size {:?}", line.trim_end()))?;
+        if size == 0 {
+            // Trailers up to the empty line
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+        // Read as the data arrives rather than trusting the size with an allocation
+        let read = reader.by_ref().take(size as u64).read_to_end(&mut body)?;
+        if read < size {
+            return Err(anyhow!(
+                "Connection closed after {} bytes of a {} byte chunk",
+                read,
+                size
+            ));
+        }
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+/// Fetches `url`, or the `range` of its bytes, following redirects. Each request is made
+/// on a new connection.
+pub fn get(url: &str, range: Option<Range<u64>>) -> Result<Vec<u8>> {
+    if let Some(range) = range.as_ref().filter(|range| range.is_empty()) {
+        return Err(anyhow!("Empty byte range {:?} of {}", range, url));
+    }
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let (authority, host, port, path) = parse_url(&url)?;
+        let stream = TcpStream::connect((host.as_str(), port))
+            .with_context(|| format!("Failed to connect to {}", url))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            path, authority
+        );
+        if let Some(range) = &range {
+            request += &format!("Range: bytes={}-{}\r\n", range.start, range.end - 1);
+        }
+        request += "\r\n";
+        (&stream).write_all(request.as_bytes())?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line)?;
+        let status_line = status_line.trim_end().to_string();
+        let status: u16 = match status_line.split_whitespace().collect::<Vec<_>>()[..] {
+            [version, status, ..] if version.starts_with("HTTP/1.") => status.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("Invalid HTTP response {:?}", status_line))?;
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(anyhow!("Connection closed in the HTTP response to {}", url));
+            }
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => {
+                    headers.push((name.to_ascii_lowercase(), value.trim().to_string()))
+                }
+                None => break,
+            }
+        }
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        match status {
+            200 | 206 => {}
+            301 | 302 | 303 | 307 | 308 => {
+                let location = header("location")
+                    .ok_or_else(|| anyhow!("HTTP redirect without a location from {}", url))?;
+                url = resolve(&url, location);
+                continue;
+            }
+            _ => return Err(anyhow!("HTTP GET {} failed: {}", url, status_line)),
+        }
+
+        let mut body = Vec::new();
+        if header("transfer-encoding").is_some_and(|coding| coding.contains("chunked")) {
+            body = read_chunked(&mut reader)?;
+        } else if let Some(length) = header("content-length") {
+            let length: u64 = length
+                .parse()
+                .map_err(|_| anyhow!("Invalid Content-Length {:?}", length))?;
+            let read = reader.by_ref().take(length).read_to_end(&mut body)?;
+            if (read as u64) < length {
+                return Err(anyhow!(
+                    "Connection closed after {} of {} bytes from {}",
+                    read,
+                    length,
+                    url
+                ));
+            }
+        } else {
+            reader.read_to_end(&mut body)?;
+        }
+
+        // A server without range requests sends the whole resource
+        if let (Some(range), 200) = (range, status) {
+            body = usize::try_from(range.start)
+                .ok()
+                .zip(usize::try_from(range.end).ok())
+                .and_then(|(start, end)| body.get(start..end))
+                .ok_or_else(|| anyhow!("Byte range {:?} outside of {}", range, url))?
+                .to_vec();
+        }
+        return Ok(body);
+    }
+    Err(anyhow!("Too many HTTP redirects from {}", url))
+}
@@ -1,9 +1,40 @@
+pub mod annexb;
+pub mod av1;
+pub mod bitreader;
+pub mod codec;
+pub mod color;
+pub mod decoder;
+pub mod demux;
+pub mod encoder;
+pub mod h264;
+pub mod h265;
+pub mod hls;
+pub mod http;
+pub mod mkv;
+pub mod mp4;
+pub mod readback;
+pub mod rtp;
+pub mod rtsp;
+pub mod stream;
+pub mod timestamp;
+pub mod ts;
+pub mod upload;
+pub mod ycbcr;
+pub mod yuv;
+
+pub use codec::Codec;
+pub use decoder::{DecodedFrame, Decoder};
+pub use encoder::{EncodedPicture, Encoder};
+pub use timestamp::Timestamp;
+
 use ash::{
     extensions::{
         ext::DebugUtils,
         khr::{Surface, Swapchain, VideoQueue},
     },
     vk::KhrVideoDecodeQueueFn,
+    vk::KhrVideoEncodeH264Fn,
+    vk::KhrVideoEncodeQueueFn,
     vk::KhrVideoQueueFn,
 };
 
@@ -173,6 +204,162 @@ pub fn find_video_format(
     // TODO more conscious decision
 }
 
+/// Creates the instance with `extension_names` plus debug utils, and the validation layer
+/// in debug builds.
+unsafe fn create_instance(
+    entry: &Entry,
+    mut extension_names: Vec<*const c_char>,
+) -> Result<Instance> {
+    let app_name = CStr::from_bytes_with_nul_unchecked(b"VulkanTriangle\0");
+
+    let layer_names = [CStr::from_bytes_with_nul_unchecked(
+        b"VK_LAYER_KHRONOS_validation\0",
+    )];
+    let layers_names_raw: Vec<*const c_char> = if DEBUG_ENABLED {
+        layer_names
+            .iter()
+            .map(|raw_name| raw_name.as_ptr())
+            .collect()
+    } else {
+        vec![]
+    };
+
+    extension_names.push(DebugUtils::NAME.as_ptr());
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        extension_names.push(KhrPortabilityEnumerationFn::name().as_ptr());
+        // Enabling this extension is a requirement when using `VK_KHR_portability_subset`
+        extension_names.push(KhrGetPhysicalDeviceProperties2Fn::name().as_ptr());
+    }
+
+    let appinfo = vk::ApplicationInfo::default()
+        .application_name(app_name)
+        .application_version(0)
+        .engine_name(app_name)
+        .engine_version(0)
+        .api_version(vk::make_api_version(0, 1, 3, 0));
+
+    let create_flags = if cfg!(any(target_os = "macos", target_os = "ios")) {
+        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    } else {
+        vk::InstanceCreateFlags::default()
+    };
+
+    let create_info = vk::InstanceCreateInfo::default()
+        .application_info(&appinfo)
+        .enabled_layer_names(&layers_names_raw)
+        .enabled_extension_names(&extension_names)
+        .flags(create_flags);
+
+    Ok(entry.create_instance(&create_info, None)?)
+}
+
+unsafe fn create_debug_messenger(
+    entry: &Entry,
+    instance: &Instance,
+) -> Result<(DebugUtils, vk::DebugUtilsMessengerEXT)> {
+    let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
+        .message_severity(
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+        )
+        .message_type(
+            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        )
+        .pfn_user_callback(Some(vulkan_debug_callback));
+
+    let debug_utils_loader = DebugUtils::new(entry, instance);
+    let debug_call_back = debug_utils_loader.create_debug_utils_messenger(&debug_info, None)?;
+
+    Ok((debug_utils_loader, debug_call_back))
+}
+
+/// Queue family properties of `pdevice` along with their video properties.
+unsafe fn queue_families(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+) -> (
+    Vec<vk::QueueFamilyProperties2<'static>>,
+    Vec<vk::QueueFamilyVideoPropertiesKHR<'static>>,
+) {
+    let queue_family_properties_count =
+        instance.get_physical_device_queue_family_properties2_len(pdevice);
+
+    let mut video_queue_family_properties =
+        vec![vk::QueueFamilyVideoPropertiesKHR::default(); queue_family_properties_count];
+    let mut queue_family_properties =
+        vec![vk::QueueFamilyProperties2::default(); queue_family_properties_count];
+
+    for j in 0..queue_family_properties_count {
+        //push_next only implemented for struct builders
+        queue_family_properties[j].p_next = &mut video_queue_family_properties[j] as *mut _ as _;
+    }
+
+    instance.get_physical_device_queue_family_properties2(pdevice, &mut queue_family_properties);
+
+    // The chained structs are filled in, don't leave pointers into them behind
+    for queue_family_property in queue_family_properties.iter_mut() {
+        queue_family_property.p_next = std::ptr::null_mut();
+    }
+
+    (queue_family_properties, video_queue_family_properties)
+}
+
+/// Whether the queue family can decode `codec`.
+fn supports_decode(
+    queue_family_property: &vk::QueueFamilyProperties2,
+    video_queue_family_property: &vk::QueueFamilyVideoPropertiesKHR,
+    codec: Codec,
+) -> bool {
+    queue_family_property
+        .queue_family_properties
+        .queue_flags
+        .contains(vk::QueueFlags::VIDEO_DECODE_KHR)
+        && video_queue_family_property
+            .video_codec_operations
+            .contains(codec.decode_operation())
+}
+
+/// Whether the queue family can encode H.264.
+fn supports_encode(
+    queue_family_property: &vk::QueueFamilyProperties2,
+    video_queue_family_property: &vk::QueueFamilyVideoPropertiesKHR,
+) -> bool {
+    queue_family_property
+        .queue_family_properties
+        .queue_flags
+        .contains(vk::QueueFlags::VIDEO_ENCODE_KHR)
+        && video_queue_family_property
+            .video_codec_operations
+            .contains(vk::VideoCodecOperationFlagsKHR::ENCODE_H264)
+}
+
+/// The index of the first queue family that can encode H.264.
+fn encode_queue_family(
+    queue_family_properties: &[vk::QueueFamilyProperties2],
+    video_queue_family_properties: &[vk::QueueFamilyVideoPropertiesKHR],
+) -> Option<u32> {
+    queue_family_properties
+        .iter()
+        .zip(video_queue_family_properties.iter())
+        .position(|(queue_family_property, video_queue_family_property)| {
+            supports_encode(queue_family_property, video_queue_family_property)
+        })
+        .map(|index| index as u32)
+}
+
+/// The device extensions for H.264 encoding.
+fn encode_extension_names() -> [*const c_char; 2] {
+    [
+        KhrVideoEncodeQueueFn::NAME.as_ptr(),
+        KhrVideoEncodeH264Fn::NAME.as_ptr(),
+    ]
+}
+
 pub struct ExampleBase {
     pub entry: Entry,
     pub instance: Instance,
@@ -187,8 +374,14 @@ pub struct ExampleBase {
     pub pdevice: vk::PhysicalDevice,
     pub device_memory_properties: vk::PhysicalDeviceMemoryProperties,
     pub graphics_queue_family_index: u32,
-    pub decode_queue_family_index: u32,
+    /// `None` on devices without a video decode queue for the codec, H.264 is then
+    /// decoded on the CPU
+    pub decode_queue_family_index: Option<u32>,
+    /// `None` on devices without a video encode queue for H.264
+    pub encode_queue_family_index: Option<u32>,
     pub present_queue: vk::Queue,
+    pub decode_queue: Option<vk::Queue>,
+    pub encode_queue: Option<vk::Queue>,
 
     //pub video_profiles: Vec<vk::VideoProfileInfoKHR>,
     //pub profile_list_info: VideoProfileInfoKHR,
@@ -203,10 +396,10 @@ pub struct ExampleBase {
     pub present_image_views: Vec<vk::ImageView>,
 
     pub graphics_pool: vk::CommandPool,
-    pub decode_pool: vk::CommandPool,
+    pub decode_pool: Option<vk::CommandPool>,
     pub draw_command_buffer: vk::CommandBuffer,
     pub setup_command_buffer: vk::CommandBuffer,
-    pub decode_command_buffer: vk::CommandBuffer,
+    pub decode_command_buffer: Option<vk::CommandBuffer>,
 
     pub depth_image: vk::Image,
     pub depth_image_view: vk::ImageView,
@@ -220,7 +413,10 @@ pub struct ExampleBase {
 }
 
 impl ExampleBase {
-    pub fn render_loop<F: Fn()>(&self, f: F) {
+    /// Calls `f` once per frame until the window is closed or `f` fails, returning the
+    /// error it failed with.
+    pub fn render_loop<F: FnMut() -> Result<()>>(&self, mut f: F) -> Result<()> {
+        let mut result = Ok(());
         self.event_loop
             .borrow_mut()
             .run_return(|event, _, control_flow| {
@@ -240,13 +436,22 @@ impl ExampleBase {
                             },
                         ..
                     } => *control_flow = ControlFlow::Exit,
-                    Event::MainEventsCleared => f(),
+                    Event::MainEventsCleared if result.is_ok() => {
+                        if let Err(err) = f() {
+                            result = Err(err);
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
                     _ => (),
                 }
             });
+        result
     }
 
-    pub fn new(window_width: u32, window_height: u32) -> Result<Self> {
+    /// Opens a window and picks a device that can present to it, preferring one that can
+    /// decode `codec`, and among those one that can also encode H.264. Without a decode
+    /// queue only H.264 is supported, through the software decoder.
+    pub fn new(codec: Codec, window_width: u32, window_height: u32) -> Result<Self> {
         unsafe {
             let event_loop = EventLoop::new();
             let window = WindowBuilder::new()
@@ -258,73 +463,12 @@ impl ExampleBase {
                 .build(&event_loop)
                 .unwrap();
             let entry = Entry::linked();
-            let app_name = CStr::from_bytes_with_nul_unchecked(b"VulkanTriangle\0");
-
-            let layer_names = [CStr::from_bytes_with_nul_unchecked(
-                b"VK_LAYER_KHRONOS_validation\0",
-            )];
-            let layers_names_raw: Vec<*const c_char> = if DEBUG_ENABLED {
-                layer_names
-                    .iter()
-                    .map(|raw_name| raw_name.as_ptr())
-                    .collect()
-            } else {
-                vec![]
-            };
-
-            let mut extension_names =
+            let extension_names =
                 ash_window::enumerate_required_extensions(window.raw_display_handle())
                     .unwrap()
                     .to_vec();
-            extension_names.push(DebugUtils::NAME.as_ptr());
-
-            #[cfg(any(target_os = "macos", target_os = "ios"))]
-            {
-                extension_names.push(KhrPortabilityEnumerationFn::name().as_ptr());
-                // Enabling this extension is a requirement when using `VK_KHR_portability_subset`
-                extension_names.push(KhrGetPhysicalDeviceProperties2Fn::name().as_ptr());
-            }
-
-            let appinfo = vk::ApplicationInfo::default()
-                .application_name(app_name)
-                .application_version(0)
-                .engine_name(app_name)
-                .engine_version(0)
-                .api_version(vk::make_api_version(0, 1, 3, 0));
-
-            let create_flags = if cfg!(any(target_os = "macos", target_os = "ios")) {
-                vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
-            } else {
-                vk::InstanceCreateFlags::default()
-            };
-
-            let create_info = vk::InstanceCreateInfo::default()
-                .application_info(&appinfo)
-                .enabled_layer_names(&layers_names_raw)
-                .enabled_extension_names(&extension_names)
-                .flags(create_flags);
-
-            let instance: Instance = entry
-                .create_instance(&create_info, None)
-                .expect("Instance creation error");
-
-            let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
-                .message_severity(
-                    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-                )
-                .message_type(
-                    vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                        | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                        | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-                )
-                .pfn_user_callback(Some(vulkan_debug_callback));
-
-            let debug_utils_loader = DebugUtils::new(&entry, &instance);
-            let debug_call_back = debug_utils_loader
-                .create_debug_utils_messenger(&debug_info, None)
-                .unwrap();
+            let instance = create_instance(&entry, extension_names)?;
+            let (debug_utils_loader, debug_call_back) = create_debug_messenger(&entry, &instance)?;
 
             let surface = ash_window::create_surface(
                 &entry,
@@ -341,89 +485,88 @@ impl ExampleBase {
 
             let surface_loader = Surface::new(&entry, &instance);
 
-            let mut found_graphics_queue = false;
-            let mut found_decode_queue = false;
-            let mut pdevice = vk::PhysicalDevice::null();
-            let mut graphics_queue_family_index = 0;
-            let mut decode_queue_family_index = 0;
-
-            for i in 0..pdevices.len() {
-                let device = pdevices[i];
-
-                found_graphics_queue = false;
-                found_decode_queue = false;
-
-                let queue_family_properties_count =
-                    instance.get_physical_device_queue_family_properties2_len(device);
-
-                let mut video_queue_family_properties = vec![
-                        vk::QueueFamilyVideoPropertiesKHR::default();
-                        queue_family_properties_count
-                    ];
-                let mut queue_family_properties =
-                    vec![vk::QueueFamilyProperties2::default(); queue_family_properties_count];
-
-                for j in 0..queue_family_properties_count {
-                    //push_next only implemented for struct builders
-                    queue_family_properties[j].p_next =
-                        &mut video_queue_family_properties[j] as *mut _ as _;
-                }
-
-                instance.get_physical_device_queue_family_properties2(
-                    device,
-                    &mut queue_family_properties,
-                );
-
-                for k in 0..queue_family_properties.len() {
-                    let queue_family_property = queue_family_properties[k];
-                    let video_queue_family_property = video_queue_family_properties[k];
-
-                    if queue_family_property
-                        .queue_family_properties
-                        .queue_flags
-                        .contains(vk::QueueFlags::VIDEO_DECODE_KHR)
-                    {
-                        if video_queue_family_property
-                            .video_codec_operations
-                            .contains(vk::VideoCodecOperationFlagsKHR::DECODE_H264)
-                        {
-                            found_decode_queue = true;
-                            decode_queue_family_index = k as u32;
-                        }
-                    }
-
-                    if queue_family_property
-                        .queue_family_properties
-                        .queue_flags
-                        .contains(vk::QueueFlags::GRAPHICS)
-                        && surface_loader
-                            .get_physical_device_surface_support(device, k as u32, surface)
-                            .unwrap()
-                    {
-                        found_graphics_queue = true;
-                        graphics_queue_family_index = k as u32;
-                    }
-                }
+            // Devices that decode `codec` come first, H.264 can also be decoded on the CPU
+            // and shown on any device that presents
+            let mut candidates = Vec::new();
+            for pdevice in pdevices {
+                let (queue_family_properties, video_queue_family_properties) =
+                    queue_families(&instance, pdevice);
 
-                if found_decode_queue && found_graphics_queue {
-                    pdevice = device;
-                    break;
+                let decode_queue_family_index = queue_family_properties
+                    .iter()
+                    .zip(video_queue_family_properties.iter())
+                    .position(|(queue_family_property, video_queue_family_property)| {
+                        supports_decode(queue_family_property, video_queue_family_property, codec)
+                    })
+                    .map(|index| index as u32);
+                let encode_queue_family_index =
+                    encode_queue_family(&queue_family_properties, &video_queue_family_properties);
+
+                let graphics_queue_family_index =
+                    (0..queue_family_properties.len() as u32).find(|&index| {
+                        queue_family_properties[index as usize]
+                            .queue_family_properties
+                            .queue_flags
+                            .contains(vk::QueueFlags::GRAPHICS)
+                            && surface_loader
+                                .get_physical_device_surface_support(pdevice, index, surface)
+                                .unwrap()
+                    });
+
+                if let Some(graphics_queue_family_index) = graphics_queue_family_index {
+                    candidates.push((
+                        pdevice,
+                        graphics_queue_family_index,
+                        decode_queue_family_index,
+                        encode_queue_family_index,
+                    ));
                 }
             }
 
-            if !found_decode_queue {
-                return Err(anyhow!(
-                    "H264 video decode is not supported on this platform"
-                ));
-            }
-            if !found_graphics_queue {
+            let (
+                pdevice,
+                graphics_queue_family_index,
+                decode_queue_family_index,
+                encode_queue_family_index,
+            ) = candidates
+                .iter()
+                .find(
+                    |(_, _, decode_queue_family_index, encode_queue_family_index)| {
+                        decode_queue_family_index.is_some() && encode_queue_family_index.is_some()
+                    },
+                )
+                .or_else(|| {
+                    candidates
+                        .iter()
+                        .find(|(_, _, decode_queue_family_index, _)| {
+                            decode_queue_family_index.is_some()
+                        })
+                })
+                .or_else(|| candidates.first())
+                .copied()
+                .ok_or_else(|| anyhow!("Graphics display is not supported on this platform"))?;
+
+            if decode_queue_family_index.is_none() && codec != Codec::H264 {
                 return Err(anyhow!(
-                    "Graphics display is not supported on this platform"
+                    "{:?} video decode is not supported on this platform",
+                    codec
                 ));
             }
 
-            let device_extension_names_raw =
-                [Swapchain::NAME.as_ptr(), KhrVideoQueueFn::NAME.as_ptr()];
+            let mut device_extension_names_raw = vec![Swapchain::NAME.as_ptr()];
+            if decode_queue_family_index.is_some() {
+                device_extension_names_raw.extend([
+                    KhrVideoQueueFn::NAME.as_ptr(),
+                    KhrVideoDecodeQueueFn::NAME.as_ptr(),
+                    codec.decode_extension_name().as_ptr(),
+                ]);
+            }
+            if encode_queue_family_index.is_some() {
+                if decode_queue_family_index.is_none() {
+                    device_extension_names_raw.push(KhrVideoQueueFn::NAME.as_ptr());
+                }
+                device_extension_names_raw.extend(encode_extension_names());
+            }
             let features = vk::PhysicalDeviceFeatures {
                 shader_clip_distance: 1,
                 ..Default::default()
@@ -434,13 +577,35 @@ impl ExampleBase {
                 .queue_family_index(graphics_queue_family_index)
                 .queue_priorities(&priorities);
 
-            let decode_queue_info = vk::DeviceQueueCreateInfo::default()
-                .queue_family_index(decode_queue_family_index)
-                .queue_priorities(&priorities);
+            let mut queue_infos = vec![graphics_queue_info];
+            if let Some(decode_queue_family_index) = decode_queue_family_index {
+                if decode_queue_family_index != graphics_queue_family_index {
+                    queue_infos.push(
+                        vk::DeviceQueueCreateInfo::default()
+                            .queue_family_index(decode_queue_family_index)
+                            .queue_priorities(&priorities),
+                    );
+                }
+            }
+            if let Some(encode_queue_family_index) = encode_queue_family_index {
+                if queue_infos
+                    .iter()
+                    .all(|queue_info| queue_info.queue_family_index != encode_queue_family_index)
+                {
+                    queue_infos.push(
+                        vk::DeviceQueueCreateInfo::default()
+                            .queue_family_index(encode_queue_family_index)
+                            .queue_priorities(&priorities),
+                    );
+                }
+            }
 
-            let queue_infos = [graphics_queue_info, decode_queue_info];
+            // Decoded frames are sampled through a YCbCr conversion
+            let mut vulkan_11_features =
+                vk::PhysicalDeviceVulkan11Features::default().sampler_ycbcr_conversion(true);
 
             let device_create_info = vk::DeviceCreateInfo::default()
+                .push_next(&mut vulkan_11_features)
                 .queue_create_infos(&queue_infos)
                 .enabled_extension_names(&device_extension_names_raw)
                 .enabled_features(&features);
@@ -450,6 +615,10 @@ impl ExampleBase {
                 .unwrap();
 
             let present_queue = device.get_device_queue(graphics_queue_family_index, 0);
+            let decode_queue = decode_queue_family_index
+                .map(|queue_family_index| device.get_device_queue(queue_family_index, 0));
+            let encode_queue = encode_queue_family_index
+                .map(|queue_family_index| device.get_device_queue(queue_family_index, 0));
 
             let surface_format = surface_loader
                 .get_physical_device_surface_formats(pdevice, surface)
@@ -529,24 +698,30 @@ impl ExampleBase {
             let draw_command_buffer = graphics_command_buffers[1];
 
             // Video decode command pool
-            let decode_pool_create_info = vk::CommandPoolCreateInfo::default()
-                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-                .queue_family_index(decode_queue_family_index);
-
-            let decode_pool = device
-                .create_command_pool(&decode_pool_create_info, None)
-                .unwrap();
-
-            let deocde_command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
-                .command_buffer_count(1)
-                .command_pool(decode_pool)
-                .level(vk::CommandBufferLevel::PRIMARY);
-
-            let decode_command_buffers = device
-                .allocate_command_buffers(&deocde_command_buffer_allocate_info)
-                .unwrap();
-
-            let decode_command_buffer = decode_command_buffers[0];
+            let (decode_pool, decode_command_buffer) = match decode_queue_family_index {
+                Some(decode_queue_family_index) => {
+                    let decode_pool_create_info = vk::CommandPoolCreateInfo::default()
+                        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                        .queue_family_index(decode_queue_family_index);
+
+                    let decode_pool = device
+                        .create_command_pool(&decode_pool_create_info, None)
+                        .unwrap();
+
+                    let deocde_command_buffer_allocate_info =
+                        vk::CommandBufferAllocateInfo::default()
+                            .command_buffer_count(1)
+                            .command_pool(decode_pool)
+                            .level(vk::CommandBufferLevel::PRIMARY);
+
+                    let decode_command_buffers = device
+                        .allocate_command_buffers(&deocde_command_buffer_allocate_info)
+                        .unwrap();
+
+                    (Some(decode_pool), Some(decode_command_buffers[0]))
+                }
+                None => (None, None),
+            };
 
             // Presentation images
             let present_images = swapchain_loader.get_swapchain_images(swapchain).unwrap();
@@ -683,12 +858,15 @@ impl ExampleBase {
                 device,
                 graphics_queue_family_index,
                 decode_queue_family_index,
+                encode_queue_family_index,
                 pdevice,
                 device_memory_properties,
                 window,
                 surface_loader,
                 surface_format,
                 present_queue,
+                decode_queue,
+                encode_queue,
                 //video_profiles,
                 //dst_video_format,
                 //dpb_video_format,
@@ -738,7 +916,9 @@ impl Drop for ExampleBase {
                 self.device.destroy_image_view(image_view, None);
             }
             self.device.destroy_command_pool(self.graphics_pool, None);
-            self.device.destroy_command_pool(self.decode_pool, None);
+            if let Some(decode_pool) = self.decode_pool {
+                self.device.destroy_command_pool(decode_pool, None);
+            }
 
             self.swapchain_loader
                 .destroy_swapchain(self.swapchain, None);
@@ -750,3 +930,163 @@ impl Drop for ExampleBase {
         }
     }
 }
+
+/// Vulkan setup without a window, surface or swapchain, for decoding on machines without
+/// a display.
+pub struct HeadlessBase {
+    pub entry: Entry,
+    pub instance: Instance,
+    pub device: Device,
+    pub debug_utils_loader: DebugUtils,
+    pub debug_call_back: vk::DebugUtilsMessengerEXT,
+
+    pub pdevice: vk::PhysicalDevice,
+    pub device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub decode_queue_family_index: u32,
+    pub decode_queue: vk::Queue,
+    /// A compute, or else transfer capable queue for working with decoded frames, when
+    /// the device has one
+    pub transfer_queue_family_index: Option<u32>,
+    pub transfer_queue: Option<vk::Queue>,
+    /// `None` on devices without a video encode queue for H.264
+    pub encode_queue_family_index: Option<u32>,
+    pub encode_queue: Option<vk::Queue>,
+}
+
+impl HeadlessBase {
+    /// Picks the first device that can decode `codec`, preferring one that can also encode
+    /// H.264.
+    pub fn new(codec: Codec) -> Result<Self> {
+        unsafe {
+            let entry = Entry::linked();
+            let instance = create_instance(&entry, Vec::new())?;
+            let (debug_utils_loader, debug_call_back) = create_debug_messenger(&entry, &instance)?;
+
+            let mut candidates = Vec::new();
+            for pdevice in instance.enumerate_physical_devices()? {
+                let (queue_family_properties, video_queue_family_properties) =
+                    queue_families(&instance, pdevice);
+
+                let decode_queue_family_index = queue_family_properties
+                    .iter()
+                    .zip(video_queue_family_properties.iter())
+                    .position(|(queue_family_property, video_queue_family_property)| {
+                        supports_decode(queue_family_property, video_queue_family_property, codec)
+                    });
+
+                let find_family = |flags: vk::QueueFlags| {
+                    queue_family_properties.iter().position(|queue_family_property| {
+                        queue_family_property
+                            .queue_family_properties
+                            .queue_flags
+                            .contains(flags)
+                    })
+                };
+                let transfer_queue_family_index = find_family(vk::QueueFlags::COMPUTE)
+                    .or_else(|| find_family(vk::QueueFlags::TRANSFER));
+
+                let encode_queue_family_index =
+                    encode_queue_family(&queue_family_properties, &video_queue_family_properties);
+
+                if let Some(decode_queue_family_index) = decode_queue_family_index {
+                    candidates.push((
+                        pdevice,
+                        decode_queue_family_index as u32,
+                        transfer_queue_family_index.map(|index| index as u32),
+                        encode_queue_family_index,
+                    ));
+                }
+            }
+
+            let (
+                pdevice,
+                decode_queue_family_index,
+                transfer_queue_family_index,
+                encode_queue_family_index,
+            ) = candidates
+                .iter()
+                .find(|(_, _, _, encode_queue_family_index)| encode_queue_family_index.is_some())
+                .or_else(|| candidates.first())
+                .copied()
+                .ok_or_else(|| {
+                    anyhow!("{:?} video decode is not supported on this platform", codec)
+                })?;
+
+            let mut device_extension_names_raw = vec![
+                KhrVideoQueueFn::NAME.as_ptr(),
+                KhrVideoDecodeQueueFn::NAME.as_ptr(),
+                codec.decode_extension_name().as_ptr(),
+            ];
+            if encode_queue_family_index.is_some() {
+                device_extension_names_raw.extend(encode_extension_names());
+            }
+            let priorities = [0.0];
+
+            let mut queue_infos = vec![vk::DeviceQueueCreateInfo::default()
+                .queue_family_index(decode_queue_family_index)
+                .queue_priorities(&priorities)];
+            if let Some(transfer_queue_family_index) = transfer_queue_family_index {
+                if transfer_queue_family_index != decode_queue_family_index {
+                    queue_infos.push(
+                        vk::DeviceQueueCreateInfo::default()
+                            .queue_family_index(transfer_queue_family_index)
+                            .queue_priorities(&priorities),
+                    );
+                }
+            }
+            if let Some(encode_queue_family_index) = encode_queue_family_index {
+                if queue_infos
+                    .iter()
+                    .all(|queue_info| queue_info.queue_family_index != encode_queue_family_index)
+                {
+                    queue_infos.push(
+                        vk::DeviceQueueCreateInfo::default()
+                            .queue_family_index(encode_queue_family_index)
+                            .queue_priorities(&priorities),
+                    );
+                }
+            }
+
+            let device_create_info = vk::DeviceCreateInfo::default()
+                .queue_create_infos(&queue_infos)
+                .enabled_extension_names(&device_extension_names_raw);
+
+            let device: Device = instance.create_device(pdevice, &device_create_info, None)?;
+
+            let decode_queue = device.get_device_queue(decode_queue_family_index, 0);
+            let transfer_queue = transfer_queue_family_index
+                .map(|queue_family_index| device.get_device_queue(queue_family_index, 0));
+            let encode_queue = encode_queue_family_index
+                .map(|queue_family_index| device.get_device_queue(queue_family_index, 0));
+            let device_memory_properties = instance.get_physical_device_memory_properties(pdevice);
+
+            Ok(HeadlessBase {
+                entry,
+                instance,
+                device,
+                debug_utils_loader,
+                debug_call_back,
+                pdevice,
+                device_memory_properties,
+                decode_queue_family_index,
+                decode_queue,
+                transfer_queue_family_index,
+                transfer_queue,
+                encode_queue_family_index,
+                encode_queue,
+            })
+        }
+    }
+}
+
+impl Drop for HeadlessBase {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            self.device.destroy_device(None);
+            self.debug_utils_loader
+                .destroy_debug_utils_messenger(self.debug_call_back, None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
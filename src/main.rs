@@ -1,17 +1,17 @@
+use std::cell::RefCell;
 use std::default::Default;
 use std::env;
-use std::ffi::{CStr, CString};
-use std::io::{Cursor, Read};
+use std::ffi::CStr;
+use std::fs;
+use std::io::{BufReader, BufWriter, Cursor, Write};
 use std::mem::{self, align_of};
 use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
 
-use ash::extensions::khr::{VideoDecodeQueue, VideoQueue};
 use ash::util::*;
-use ash::vk::native::StdVideoH264ProfileIdc_STD_VIDEO_H264_PROFILE_IDC_MAIN;
-use ash::vk::{self, DeviceMemory, VideoSessionCreateInfoKHR};
+use ash::vk;
 
-use anyhow::Result;
-use mp4parse;
+use anyhow::{anyhow, Result};
 
 use ash_video::*;
 
@@ -19,8 +19,6 @@ use ash_video::*;
 struct VideoSpec {
     width: u16,
     height: u16,
-    max_sps_count: u32,
-    max_pps_count: u32,
 }
 
 // impl Default for VideoSpec {
@@ -38,556 +36,416 @@ struct Vertex {
     uv: [f32; 2],
 }
 
-#[derive(Clone, Debug, Copy)]
-pub struct Vector3 {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
-    pub _pad: f32,
+/// Where the headless mode writes the decoded frames.
+enum FrameOutput {
+    /// All frames appended to one planar I420 file
+    Yuv(PathBuf),
+    /// One numbered PNG per frame in a directory
+    Png(PathBuf),
+    /// All frames encoded again as an Annex-B H.264 stream
+    H264(PathBuf),
 }
 
-#[derive(Debug)]
-struct AVCVideoConfiguration {
-    version: u8,
-    profile: u8,
-    compatibility: u8,
-    level: u8,
-    // indicates the length in bytes of the length field in an AVC video access unit used indicate the length of each NAL unit.
-    length_size_minus_one: u8,
-    sps: Vec<Vec<u8>>,
-    pps: Vec<Vec<u8>>,
+/// Where the player's frames come from: the device's video decode queue, or else H.264
+/// decoded on the CPU and uploaded before each draw.
+enum FrameSource {
+    Vulkan(Decoder),
+    Software(
+        Decoder<h264::software::SoftwareBackend>,
+        upload::FrameUploader,
+    ),
 }
 
-fn parse_avc_config(data: &[u8]) -> AVCVideoConfiguration {
-    let version = data[0];
-    let avc_profile = data[1];
-    let avc_compatibility = data[2];
-    let avc_level = data[3];
-    let nalulength_size_minus_one = data[4] & 0b00000011;
-    let number_of_sps_nalus = data[5] & 0b00011111;
-    let mut i: usize = 6;
-
-    let sps_elems = (0..number_of_sps_nalus)
-        .map(|_| {
-            let sps_size = u16::from_be_bytes([data[i], data[i + 1]]) as usize;
-            i += 2;
-            let sps: Vec<u8> = data[i..i + sps_size].to_vec();
-            i += sps_size;
-            sps
-        })
-        .collect::<Vec<Vec<u8>>>();
-
-    let number_of_pps_nalus = data[i];
-    i += 1;
-
-    let pps_elems = (0..number_of_pps_nalus)
-        .map(|_| {
-            let pps_size = u16::from_be_bytes([data[i], data[i + 1]]) as usize;
-            i += 2;
-            let sps: Vec<u8> = data[i..i + pps_size].to_vec();
-            i += pps_size;
-            sps
-        })
-        .collect::<Vec<Vec<u8>>>();
-
-    assert_eq!(version, 1);
-
-    AVCVideoConfiguration {
-        version,
-        profile: avc_profile,
-        compatibility: avc_compatibility,
-        level: avc_level,
-        length_size_minus_one: nalulength_size_minus_one,
-        sps: sps_elems,
-        pps: pps_elems,
-    }
+/// A frame due for display, in device memory or still in host memory.
+enum SourceFrame {
+    Device(DecodedFrame),
+    Host(h264::software::SoftwareFrame),
 }
 
-pub fn vk_make_video_std_version(major: u32, minor: u32, patch: u32) -> u32 {
-    (major << 22) | (minor << 12) | patch
-}
-
-fn vk_make_extension_name(text: &str) -> [i8; 256] {
-    let mut array: [i8; 256] = [0; 256];
-    let bytes = CString::new(text).unwrap().into_bytes();
-
-    for (i, &b) in bytes.iter().enumerate() {
-        array[i] = b as i8;
+impl FrameSource {
+    fn format(&self) -> vk::Format {
+        match self {
+            FrameSource::Vulkan(decoder) => decoder.format(),
+            FrameSource::Software(_, uploader) => uploader.format(),
+        }
     }
 
-    array
-}
-
-fn vk_find_bit_index(num: u32) -> u32 {
-    let mut index = 0;
-    let mut bits = num;
-    while bits != 0 {
-        if bits & 1 == 1 {
-            return index;
+    fn extent(&self) -> vk::Extent2D {
+        match self {
+            FrameSource::Vulkan(decoder) => decoder.extent(),
+            FrameSource::Software(_, uploader) => uploader.extent(),
         }
-        index += 1;
-        bits >>= 1;
     }
-    return index;
-}
 
-fn main() -> Result<()> {
-    unsafe {
-        let args: Vec<String> = env::args().collect();
-
-        let mut file = std::fs::File::open({
-            if DEBUG_ENABLED {
-                //"./samples/Big_Buck_Bunny_360_10s_1MB.mp4"
-                "./samples/a.mp4"
-            } else {
-                &args[1]
+    /// Decodes `access_unit`, or returns the remaining frames at the end of the stream.
+    unsafe fn decode(
+        &mut self,
+        access_unit: Option<(Vec<u8>, Timestamp)>,
+    ) -> Result<Vec<SourceFrame>> {
+        let frames = match (self, access_unit) {
+            (FrameSource::Vulkan(decoder), Some((access_unit, pts))) => decoder
+                .decode(&access_unit, pts)?
+                .into_iter()
+                .map(SourceFrame::Device)
+                .collect(),
+            (FrameSource::Vulkan(decoder), None) => decoder
+                .flush()
+                .into_iter()
+                .map(SourceFrame::Device)
+                .collect(),
+            (FrameSource::Software(decoder, _), Some((access_unit, pts))) => decoder
+                .decode(&access_unit, pts)?
+                .into_iter()
+                .map(SourceFrame::Host)
+                .collect(),
+            (FrameSource::Software(decoder, _), None) => {
+                decoder.flush().into_iter().map(SourceFrame::Host).collect()
             }
-        })?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-
-        let mut c = Cursor::new(&buf);
-        let video_context = mp4parse::read_mp4(&mut c)?;
-
-        let mut video_spec = VideoSpec::default();
-
-        assert_eq!(
-            video_context.timescale,
-            Some(mp4parse::MediaTimeScale(1000))
-        );
-
-        let mut _config: AVCVideoConfiguration;
-
-        for track in video_context.tracks {
-            match track.track_type {
-                mp4parse::TrackType::Video => {
-                    //let ctts = track.ctts.unwrap();
-                    let stco = track.stco.unwrap();
-                    let stsc = track.stsc.unwrap();
-                    let stss = track.stss.unwrap();
-                    let stsz = track.stsz.unwrap();
-                    let stts = track.stts.unwrap();
-
-                    for offset in stco.offsets {
-                        println!("{}", offset);
-                    }
-
-                    //offset 48
-                    //size 90
-                    let stsd = track.stsd.expect("expected an stsd");
-
-                    let v = match stsd.descriptions.first().expect("expected a SampleEntry") {
-                        mp4parse::SampleEntry::Video(v) => v,
-                        _ => panic!("expected a VideoSampleEntry"),
-                    };
-
-                    video_spec.width = v.width;
-                    video_spec.height = v.height;
+        };
+        Ok(frames)
+    }
 
-                    if let mp4parse::VideoCodecSpecific::AVCConfig(ref avc) = v.codec_specific {
-                        _config = parse_avc_config(avc);
-                    }
-                }
-                _ => {}
+    /// The image holding `frame`, uploading it when it was decoded on the CPU. An
+    /// uploaded frame is only valid until the next one.
+    unsafe fn device_frame(&mut self, frame: SourceFrame) -> Result<DecodedFrame> {
+        match (self, frame) {
+            (_, SourceFrame::Device(frame)) => Ok(frame),
+            (FrameSource::Software(_, uploader), SourceFrame::Host(frame)) => {
+                uploader.upload(&frame.picture, frame.pts)
+            }
+            (FrameSource::Vulkan(_), SourceFrame::Host(_)) => {
+                Err(anyhow!("Host frames only come from the software decoder"))
             }
         }
+    }
 
-        let base = ExampleBase::new(video_spec.width as u32, video_spec.height as u32)?;
-
-        // let mut video_decode_usage_info = vk::VideoDecodeUsageInfoKHR::default()
-        //     .video_usage_hints(vk::VideoDecodeUsageFlagsKHR::OFFLINE);
-
-        let mut video_profile_operation = vk::VideoDecodeH264ProfileInfoKHR::default()
-            .std_profile_idc(StdVideoH264ProfileIdc_STD_VIDEO_H264_PROFILE_IDC_MAIN)
-            .picture_layout(vk::VideoDecodeH264PictureLayoutFlagsKHR::PROGRESSIVE);
-
-        //video_profile_operation.p_next = &mut video_decode_usage_info as *mut _ as _;
-
-        let profile_info = vk::VideoProfileInfoKHR::default()
-            .push_next(&mut video_profile_operation)
-            .video_codec_operation(vk::VideoCodecOperationFlagsKHR::DECODE_H264)
-            .chroma_subsampling(vk::VideoChromaSubsamplingFlagsKHR::TYPE_420)
-            .luma_bit_depth(vk::VideoComponentBitDepthFlagsKHR::TYPE_8)
-            .chroma_bit_depth(vk::VideoComponentBitDepthFlagsKHR::TYPE_8);
-
-        let mut h264_decode_capibilities = vk::VideoDecodeH264CapabilitiesKHR::default();
-
-        let mut decode_capabilities = vk::VideoDecodeCapabilitiesKHR::default();
-
-        // TODO no p_next or push_next motheods yet this is failing when not passed
-        decode_capabilities.p_next = &mut h264_decode_capibilities as *mut _ as _;
-
-        let mut capabilities =
-            vk::VideoCapabilitiesKHR::default().push_next(&mut decode_capabilities);
-
-        let video_queue_loader = VideoQueue::new(&base.entry, &base.instance, &base.device);
-
-        video_queue_loader
-            .get_physical_device_video_capabilities(base.pdevice, &profile_info, &mut capabilities).unwrap();
-
-        let video_profiles = vec![profile_info];
-
-        let mut profile_list_info =
-            vk::VideoProfileListInfoKHR::default().profiles(&video_profiles);
-
-        let mut dst_video_format = find_video_format(
-            base.pdevice,
-            &video_queue_loader,
-            vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR,
-            &mut profile_list_info,
-        )?;
-        let mut dpb_video_format = find_video_format(
-            base.pdevice,
-            &video_queue_loader,
-            vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR,
-            &mut profile_list_info,
-        )?;
-
-        //TODO experimental
-        let prefer_coincide_mode = false;
-
-        if decode_capabilities
-            .flags
-            .contains(vk::VideoDecodeCapabilityFlagsKHR::DPB_AND_OUTPUT_DISTINCT)
-            && prefer_coincide_mode
-        {
-            dst_video_format = find_video_format(
-                base.pdevice,
-                &video_queue_loader,
-                vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR
-                    | vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR,
-                &mut profile_list_info,
-            )?;
-
-            dpb_video_format = dst_video_format;
+    /// Submits `record` to the present queue of `base`, with `frame` in
+    /// `SHADER_READ_ONLY_OPTIMAL`, waiting for the swapchain image and signalling the end
+    /// of rendering.
+    unsafe fn draw<F: FnOnce(&Device, vk::CommandBuffer)>(
+        &mut self,
+        base: &ExampleBase,
+        frame: &DecodedFrame,
+        record: F,
+    ) -> Result<()> {
+        match self {
+            // The frame is handed over from the decode queue family for the draw
+            FrameSource::Vulkan(decoder) => decoder.use_frame(
+                frame,
+                base.graphics_queue_family_index,
+                base.present_queue,
+                base.draw_command_buffer,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
+                &[base.present_complete_semaphore],
+                &[base.rendering_complete_semaphore],
+                record,
+            ),
+            // Uploads already leave the frame on the present queue, ready for sampling
+            FrameSource::Software(..) => {
+                record_submit_commandbuffer(
+                    &base.device,
+                    base.draw_command_buffer,
+                    base.draw_commands_reuse_fence,
+                    base.present_queue,
+                    &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
+                    &[base.present_complete_semaphore],
+                    &[base.rendering_complete_semaphore],
+                    record,
+                );
+                Ok(())
+            }
         }
+    }
+}
 
-        // Bitstream buffer
-        
-        //let bitstream_buffer_data = [0u32, 1, 2, 2, 3, 0];
-        let bitstream_buffer_data = buf;
-
-        let bitstream_buffer_info = vk::BufferCreateInfo {
-            p_next: &mut profile_list_info as *mut _ as _,
-            //size: buf.len() as u64,
-            size: bitstream_buffer_data.len() as u64,
-            usage: vk::BufferUsageFlags::VIDEO_DECODE_DST_KHR,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
+/// Creates an H.264 encoder on the encode queue of `base`, uploading through its transfer
+/// queue when it has one.
+unsafe fn create_encoder(base: &HeadlessBase, width: u32, height: u32) -> Result<Encoder> {
+    let (encode_queue_family_index, encode_queue) =
+        match (base.encode_queue_family_index, base.encode_queue) {
+            (Some(queue_family_index), Some(queue)) => (queue_family_index, queue),
+            _ => return Err(anyhow!("H.264 encode is not supported on this platform")),
         };
-
-        let bitstream_buffer = base
-            .device
-            .create_buffer(&bitstream_buffer_info, None)
-            .unwrap();
-
-        let bitstream_buffer_memory_req = base.device.get_buffer_memory_requirements(bitstream_buffer);
-        let bitstream_buffer_memory_index = find_memorytype_index(
-            &bitstream_buffer_memory_req,
-            &base.device_memory_properties,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )
-        .expect("Unable to find suitable memorytype for the index buffer.");
-        let bitstream_allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: bitstream_buffer_memory_req.size,
-            memory_type_index: bitstream_buffer_memory_index,
-            ..Default::default()
+    let (transfer_queue_family_index, transfer_queue) =
+        match (base.transfer_queue_family_index, base.transfer_queue) {
+            (Some(queue_family_index), Some(queue)) => (queue_family_index, queue),
+            _ => (encode_queue_family_index, encode_queue),
         };
-        let bitstream_buffer_memory = base
-            .device
-            .allocate_memory(&bitstream_allocate_info, None)
-            .unwrap();
-        let bitstream_ptr: *mut c_void = base
-            .device
-            .map_memory(
-                bitstream_buffer_memory,
-                0,
-                bitstream_buffer_memory_req.size,
-                vk::MemoryMapFlags::empty(),
-            )
-            .unwrap();
-        let mut bitstream_slice = Align::new(
-            bitstream_ptr,
-            align_of::<u32>() as u64,
-            bitstream_buffer_memory_req.size,
-        );
-        bitstream_slice.copy_from_slice(&bitstream_buffer_data);
-        base.device.unmap_memory(bitstream_buffer_memory);
-        base.device
-            .bind_buffer_memory(bitstream_buffer, bitstream_buffer_memory, 0)
-            .unwrap(); 
+    Encoder::new(
+        &base.entry,
+        &base.instance,
+        &base.device,
+        base.pdevice,
+        encode_queue_family_index,
+        encode_queue,
+        transfer_queue_family_index,
+        transfer_queue,
+        encoder::EncoderConfig::new(width, height),
+    )
+}
 
-        // DST
-        let video_extent = vk::Extent2D {
-            width: video_spec.width as u32,
-            height: video_spec.height as u32,
-        };
+/// Decodes without a window and writes every frame, in display order and cropped to the
+/// visible area. H.264 is decoded on the CPU when no device can decode it, but only
+/// encoded on a device that can.
+unsafe fn decode_to_files(
+    access_units: stream::AccessUnits,
+    parameter_sets: &codec::ParameterSets,
+    output: &FrameOutput,
+) -> Result<()> {
+    let stream_info = parameter_sets.stream_info()?;
+    let (crop_x, crop_y, crop_width, crop_height) = stream_info.crop_rect;
+    let color_space = stream_info.color_space;
+
+    let mut writer = match output {
+        FrameOutput::Yuv(path) | FrameOutput::H264(path) => {
+            Some(BufWriter::new(fs::File::create(path)?))
+        }
+        FrameOutput::Png(dir) => {
+            fs::create_dir_all(dir)?;
+            None
+        }
+    };
 
-        let dst_image_create_info = vk::ImageCreateInfo {
-            p_next: &mut profile_list_info as *mut _ as _,
-            image_type: vk::ImageType::TYPE_2D,
-            format: dst_video_format,
-            extent: video_extent.into(),
-            mip_levels: 1,
-            array_layers: 1,
-            samples: vk::SampleCountFlags::TYPE_1,
-            tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR,
-            ..Default::default()
-        };
+    // The encoder works on whole chroma samples, an odd last row or column is dropped
+    let base = HeadlessBase::new(parameter_sets.codec());
+    let mut encoder = match (output, &base) {
+        (FrameOutput::H264(_), Ok(base)) => {
+            Some(create_encoder(base, crop_width & !1, crop_height & !1)?)
+        }
+        (FrameOutput::H264(_), Err(err)) => {
+            return Err(anyhow!("H.264 output needs a video encode queue: {}", err))
+        }
+        _ => None,
+    };
 
-        let dst_image = base
-            .device
-            .create_image(&dst_image_create_info, None)
-            .unwrap();
+    let mut index = 0;
+    let mut write_picture = |picture: yuv::Nv12Frame, pts: Timestamp| -> Result<()> {
+        let picture = picture.crop(crop_x, crop_y, crop_width, crop_height);
+        match (&mut writer, &mut encoder, output) {
+            (Some(writer), Some(encoder), _) => {
+                let picture = picture.crop(0, 0, picture.width & !1, picture.height & !1);
+                for encoded in encoder.encode(picture, pts)? {
+                    writer.write_all(&encoded.data)?;
+                }
+            }
+            (Some(writer), None, _) => picture.write_i420(writer)?,
+            (None, _, FrameOutput::Png(dir)) => write_png(
+                &picture,
+                &color_space,
+                &dir.join(format!("{:05}.png", index)),
+            )?,
+            (None, _, _) => unreachable!(),
+        }
+        if DEBUG_ENABLED {
+            println!("wrote frame {} pts {}", index, pts);
+        }
+        index += 1;
+        Ok(())
+    };
+
+    let base = match (&base, parameter_sets) {
+        (Ok(base), _) => base,
+        (Err(_), codec::ParameterSets::H264(_)) => {
+            let mut decoder =
+                Decoder::with_backend(h264::software::SoftwareBackend::default(), parameter_sets)?;
+            for access_unit in access_units {
+                let (access_unit, pts) = access_unit?;
+                for frame in decoder.decode(&access_unit, pts)? {
+                    write_picture(frame.picture, frame.pts)?;
+                }
+            }
+            for frame in decoder.flush() {
+                write_picture(frame.picture, frame.pts)?;
+            }
+            return Ok(());
+        }
+        (Err(err), _) => return Err(anyhow!("{}", err)),
+    };
+    let (transfer_queue_family_index, transfer_queue) =
+        match (base.transfer_queue_family_index, base.transfer_queue) {
+            (Some(queue_family_index), Some(queue)) => (queue_family_index, queue),
+            _ => return Err(anyhow!("No queue to copy the decoded frames with")),
+        };
 
-        let dst_memory_req = base.device.get_image_memory_requirements(dst_image);
-        let dst_memory_index = find_memorytype_index(
-            &dst_memory_req,
-            &base.device_memory_properties,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        )
-        .expect("Unable to find suitable memory index for dst image.");
+    let mut decoder = Decoder::new(
+        &base.entry,
+        &base.instance,
+        &base.device,
+        base.pdevice,
+        base.decode_queue_family_index,
+        base.decode_queue,
+        parameter_sets,
+    )?;
+    let mut reader = readback::FrameReader::new(
+        &base.device,
+        &base.device_memory_properties,
+        transfer_queue_family_index,
+        transfer_queue,
+        decoder.extent(),
+    )?;
+
+    let mut write_frames = |decoder: &mut Decoder, frames: Vec<DecodedFrame>| -> Result<()> {
+        for frame in frames {
+            write_picture(reader.read(decoder, &frame)?, frame.pts)?;
+        }
+        Ok(())
+    };
 
-        let dst_allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: dst_memory_req.size,
-            memory_type_index: dst_memory_index,
-            ..Default::default()
-        };
-        let dst_memory = base
-            .device
-            .allocate_memory(&dst_allocate_info, None)
-            .unwrap();
-        base.device
-            .bind_image_memory(dst_image, dst_memory, 0)
-            .expect("Unable to bind dst image memory");
+    for access_unit in access_units {
+        let (access_unit, pts) = access_unit?;
+        let frames = decoder.decode(&access_unit, pts)?;
+        write_frames(&mut decoder, frames)?;
+    }
+    let frames = decoder.flush();
+    write_frames(&mut decoder, frames)?;
 
-        let mut dst_image_view_usage_create_info = vk::ImageViewUsageCreateInfo {
-            usage: vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR,
-            ..Default::default()
-        };
+    // B frames wait for the picture after them
+    if let (Some(writer), Some(encoder)) = (&mut writer, &mut encoder) {
+        for encoded in encoder.flush()? {
+            writer.write_all(&encoded.data)?;
+        }
+    }
 
-        let dst_image_view_info = vk::ImageViewCreateInfo {
-            p_next: &mut dst_image_view_usage_create_info as *mut _ as _,
-            view_type: vk::ImageViewType::TYPE_2D,
-            format: dst_video_format,
-            image: dst_image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
+    Ok(())
+}
 
-        let dst_image_view = base
-            .device
-            .create_image_view(&dst_image_view_info, None)
-            .unwrap();
+fn write_png(picture: &yuv::Nv12Frame, color_space: &color::ColorSpace, path: &Path) -> Result<()> {
+    let image =
+        image::RgbImage::from_raw(picture.width, picture.height, picture.to_rgb8(color_space))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Picture does not fit a {}x{} image",
+                    picture.width,
+                    picture.height
+                )
+            })?;
+    image.save(path)?;
+    Ok(())
+}
 
-        // DPB
-        let dpb_image_create_info = vk::ImageCreateInfo {
-            p_next: &mut profile_list_info as *mut _ as _,
-            image_type: vk::ImageType::TYPE_2D,
-            format: dpb_video_format,
-            extent: video_extent.into(),
-            mip_levels: 1,
-            array_layers: 1,
-            samples: vk::SampleCountFlags::TYPE_1,
-            tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR,
-            ..Default::default()
+fn main() -> Result<()> {
+    unsafe {
+        let args: Vec<String> = env::args().collect();
+        let usage = || {
+            anyhow!(
+                "Usage: {} [--yuv <file> | --png <dir> | --h264 <file>] <file | rtsp://url | http://playlist>",
+                args[0]
+            )
         };
 
-        let dpb_image = base
-            .device
-            .create_image(&dpb_image_create_info, None)
-            .unwrap();
-
-        let dpb_memory_req = base.device.get_image_memory_requirements(dpb_image);
-        let dpb_memory_index = find_memorytype_index(
-            &dpb_memory_req,
-            &base.device_memory_properties,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        )
-        .expect("Unable to find suitable memory index for dpb image.");
+        let mut output = None;
+        let mut input = None;
+        let mut rest = args.iter().skip(1);
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--yuv" => output = Some(FrameOutput::Yuv(rest.next().ok_or_else(usage)?.into())),
+                "--png" => output = Some(FrameOutput::Png(rest.next().ok_or_else(usage)?.into())),
+                "--h264" => output = Some(FrameOutput::H264(rest.next().ok_or_else(usage)?.into())),
+                _ if input.is_none() => input = Some(arg.as_str()),
+                _ => return Err(usage()),
+            }
+        }
 
-        let dpb_allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: dpb_memory_req.size,
-            memory_type_index: dpb_memory_index,
-            ..Default::default()
+        let input = match input {
+            Some(input) => input,
+            //"./samples/Big_Buck_Bunny_360_10s_1MB.mp4"
+            None if DEBUG_ENABLED => "./samples/a.mp4",
+            None => return Err(usage()),
         };
-        let dpb_memory = base
-            .device
-            .allocate_memory(&dpb_allocate_info, None)
-            .unwrap();
-        base.device
-            .bind_image_memory(dpb_image, dpb_memory, 0)
-            .expect("Unable to bind dpb image memory");
 
-        let mut dpb_image_view_usage_create_info = vk::ImageViewUsageCreateInfo {
-            usage: vk::ImageUsageFlags::VIDEO_DECODE_DPB_KHR,
-            ..Default::default()
+        // Access units are read as they are decoded rather than the whole file up front
+        let stream = if input.starts_with("rtsp://") {
+            stream::open_rtsp(input)?
+        } else if input.starts_with("http://") {
+            // Variants are picked within what the device decodes
+            stream::open_hls(input, None, |codec| {
+                let base = match HeadlessBase::new(codec) {
+                    Ok(base) => base,
+                    Err(_) if codec == Codec::H264 => {
+                        return Ok(h264::software::MAX_CODED_EXTENT);
+                    }
+                    Err(err) => return Err(err),
+                };
+                let extent = Decoder::max_coded_extent(
+                    &base.entry,
+                    &base.instance,
+                    &base.device,
+                    base.pdevice,
+                    codec,
+                )?;
+                Ok((extent.width, extent.height))
+            })?
+        } else {
+            stream::open(BufReader::new(std::fs::File::open(input)?))?
         };
-
-        let dpb_image_view_info = vk::ImageViewCreateInfo {
-            p_next: &mut dpb_image_view_usage_create_info as *mut _ as _,
-            view_type: vk::ImageViewType::TYPE_2D,
-            format: dpb_video_format,
-            image: dpb_image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
+        if DEBUG_ENABLED {
+            println!(
+                "{:?} {:?} stream, {}x{}",
+                stream.format,
+                stream.parameter_sets.codec(),
+                stream.width,
+                stream.height
+            );
+        }
+        let parameter_sets = stream.parameter_sets;
+        let video_spec = VideoSpec {
+            width: stream.width,
+            height: stream.height,
         };
+        let access_units = stream.access_units;
 
-        let dpb_image_view = base
-            .device
-            .create_image_view(&dpb_image_view_info, None)
-            .unwrap();
-
-        // VideoSession
-
-        let extension_properties = vk::ExtensionProperties::default()
-            .extension_name(vk_make_extension_name(
-                "VK_STD_vulkan_video_codec_h264_decode",
-            ))
-            //TODO header version update
-            .spec_version(vk_make_video_std_version(1, 0, 0));
-            //.spec_version(vk_make_video_std_version(0, 9, 8));
-
-        let video_session_info = vk::VideoSessionCreateInfoKHR::default()
-            .queue_family_index(base.decode_queue_family_index)
-            .video_profile(&video_profiles[0])
-            .picture_format(dst_video_format)
-            .std_header_version(&extension_properties)
-            .max_coded_extent(video_extent);
-
-        let video_session = video_queue_loader
-            .create_video_session(base.device.handle(),&video_session_info, None)
-            .unwrap();
-
-        let video_session_memory_requirements_count =
-            video_queue_loader.get_video_session_memory_requirements_len(video_session);
-
-        let mut video_session_memory_requirements = vec![
-                vk::VideoSessionMemoryRequirementsKHR::default();
-                video_session_memory_requirements_count
-            ];
+        if let Some(output) = &output {
+            return decode_to_files(access_units, &parameter_sets, output);
+        }
 
-        video_queue_loader.get_video_session_memory_requirements(
-            video_session,
-            &mut video_session_memory_requirements,
+        let base = ExampleBase::new(
+            parameter_sets.codec(),
+            video_spec.width as u32,
+            video_spec.height as u32,
         )?;
 
-
-        let mut video_session_memory =
-            vec![vk::DeviceMemory::default(); video_session_memory_requirements_count];
-        let mut video_session_bind_memory = Vec::new();
-
-        // TODO single allocation + offset
-        for i in 0..video_session_memory_requirements_count {
-            let memory_type_index = vk_find_bit_index(
-                video_session_memory_requirements[i]
-                    .memory_requirements
-                    .memory_type_bits,
-            );
-
-            let video_session_memory_allocate_info = vk::MemoryAllocateInfo {
-                allocation_size: video_session_memory_requirements[i]
-                    .memory_requirements
-                    .size,
-                memory_type_index: memory_type_index,
-                ..Default::default()
-            };
-
-            video_session_memory[i] = base
-                .device
-                .allocate_memory(&video_session_memory_allocate_info, None)
-                .unwrap();
-
-            let video_session_bind_memory_info = vk::BindVideoSessionMemoryInfoKHR::default()
-                .memory_bind_index(video_session_memory_requirements[i].memory_bind_index)
-                .memory_size(
-                    video_session_memory_requirements[i]
-                        .memory_requirements
-                        .size,
+        let stream_info = parameter_sets.stream_info()?;
+        let source = match (
+            base.decode_queue_family_index,
+            base.decode_queue,
+            &parameter_sets,
+        ) {
+            (Some(decode_queue_family_index), Some(decode_queue), _) => {
+                FrameSource::Vulkan(Decoder::new(
+                    &base.entry,
+                    &base.instance,
+                    &base.device,
+                    base.pdevice,
+                    decode_queue_family_index,
+                    decode_queue,
+                    &parameter_sets,
+                )?)
+            }
+            (_, _, codec::ParameterSets::H264(_)) => {
+                let (width, height) = stream_info.coded_extent;
+                FrameSource::Software(
+                    Decoder::with_backend(
+                        h264::software::SoftwareBackend::default(),
+                        &parameter_sets,
+                    )?,
+                    upload::FrameUploader::new(
+                        &base.device,
+                        &base.device_memory_properties,
+                        base.graphics_queue_family_index,
+                        base.present_queue,
+                        vk::Extent2D { width, height },
+                    )?,
                 )
-                .memory(video_session_memory[i])
-                .memory_offset(0)
-                .memory_size(
-                    video_session_memory_requirements[i]
-                        .memory_requirements
-                        .size,
-                );
-
-            video_session_bind_memory.push(video_session_bind_memory_info);
-        }
-
-        video_queue_loader
-            .bind_video_session_memory(video_session, &mut video_session_bind_memory)?;
-
-        // Video session parameters
-        let video_decode_session_paramteres_add_info =
-            vk::VideoDecodeH264SessionParametersAddInfoKHR::default();
-
-        let mut video_decode_session_paramteres_create_info =
-            vk::VideoDecodeH264SessionParametersCreateInfoKHR::default()
-                .parameters_add_info(&video_decode_session_paramteres_add_info);
-
-        let video_session_parameters_info = vk::VideoSessionParametersCreateInfoKHR::default()
-            .push_next(&mut video_decode_session_paramteres_create_info)
-            .video_session(video_session);
-
-        let video_session_paramaters = video_queue_loader
-            .create_video_session_parameters(&video_session_parameters_info, None)?;
-
-		/* 
-        // Record and submit
-        record_submit_commandbuffer(
-            &base.device,
-            base.decode_command_buffer,
-            base.draw_commands_reuse_fence,
-            base.present_queue,
-            &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
-            &[base.present_complete_semaphore],
-            &[base.rendering_complete_semaphore],
-            |device, decode_command_buffer| {
-
-                let begin_info =
-                vk::VideoBeginCodingInfoKHR::default().video_session(video_session)
-                .video_session_parameters(video_session_paramaters);
-
-                video_queue_loader.cmd_begin_video_coding(decode_command_buffer, &begin_info);
-
-                let decode_output_picture_resource = vk::VideoPictureResourceInfoKHR {
-                    base_array_layer: 0,
-                    image_view_binding: dst_image_view,
-                    ..Default::default()
-                };
-
-                let decode_info = vk::VideoDecodeInfoKHR {
-                    src_buffer: bitstream_buffer,
-                    src_buffer_offset: 1859,
-                    src_buffer_range: 26,
-                    dst_picture_resource: decode_output_picture_resource,
-                    reference_slot_count: 0,
-                    ..Default::default()
-                };
-
-                video_decode_queue_loader.cmd_decode_video(decode_command_buffer, &decode_info);
+            }
+            _ => {
+                return Err(anyhow!(
+                    "{:?} video decode is not supported on this platform",
+                    parameter_sets.codec()
+                ))
+            }
+        };
 
-                video_queue_loader.cmd_end_video_coding(
-                    decode_command_buffer,
-                    &vk::VideoEndCodingInfoKHR::default(),
-                );
-            },
-        );
-        */
         // Render pass
 
         let renderpass_attachments = [
@@ -701,22 +559,29 @@ fn main() -> Result<()> {
             .bind_buffer_memory(index_buffer, index_buffer_memory, 0)
             .unwrap();
 
+        // Only the cropping window of the coded picture is shown
+        let (crop_x, crop_y, crop_width, crop_height) = stream_info.crop_rect;
+        let coded_extent = source.extent();
+        let u0 = crop_x as f32 / coded_extent.width as f32;
+        let v0 = crop_y as f32 / coded_extent.height as f32;
+        let u1 = (crop_x + crop_width) as f32 / coded_extent.width as f32;
+        let v1 = (crop_y + crop_height) as f32 / coded_extent.height as f32;
         let vertices = [
             Vertex {
                 pos: [-1.0, -1.0, 0.0, 1.0],
-                uv: [0.0, 0.0],
+                uv: [u0, v0],
             },
             Vertex {
                 pos: [-1.0, 1.0, 0.0, 1.0],
-                uv: [0.0, 1.0],
+                uv: [u0, v1],
             },
             Vertex {
                 pos: [1.0, 1.0, 0.0, 1.0],
-                uv: [1.0, 1.0],
+                uv: [u1, v1],
             },
             Vertex {
                 pos: [1.0, -1.0, 0.0, 1.0],
-                uv: [1.0, 0.0],
+                uv: [u1, v0],
             },
         ];
         let vertex_input_buffer_info = vk::BufferCreateInfo {
@@ -769,12 +634,13 @@ fn main() -> Result<()> {
             .bind_buffer_memory(vertex_input_buffer, vertex_input_buffer_memory, 0)
             .unwrap();
 
-        let uniform_color_buffer_data = Vector3 {
-            x: 1.0,
-            y: 1.0,
-            z: 1.0,
-            _pad: 0.0,
-        };
+        let srgb_output = matches!(
+            base.surface_format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        );
+        let uniform_color_buffer_data = stream_info.color_space.uniforms(srgb_output);
         let uniform_color_buffer_info = vk::BufferCreateInfo {
             size: std::mem::size_of_val(&uniform_color_buffer_data) as u64,
             usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
@@ -815,7 +681,7 @@ fn main() -> Result<()> {
             .unwrap();
         let mut uniform_aligned_slice = Align::new(
             uniform_ptr,
-            align_of::<Vector3>() as u64,
+            align_of::<color::ColorUniforms>() as u64,
             uniform_color_buffer_memory_req.size,
         );
         uniform_aligned_slice.copy_from_slice(&[uniform_color_buffer_data]);
@@ -824,201 +690,15 @@ fn main() -> Result<()> {
             .bind_buffer_memory(uniform_color_buffer, uniform_color_buffer_memory, 0)
             .unwrap();
 
-        let image = image::load_from_memory(include_bytes!("../assets/rust.png"))
-            .unwrap()
-            .to_rgba8();
-        let (width, height) = image.dimensions();
-        let image_extent = vk::Extent2D { width, height };
-        let image_data = image.into_raw();
-        let image_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<u8>() * image_data.len()) as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_SRC,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-        let image_buffer = base.device.create_buffer(&image_buffer_info, None).unwrap();
-        let image_buffer_memory_req = base.device.get_buffer_memory_requirements(image_buffer);
-        let image_buffer_memory_index = find_memorytype_index(
-            &image_buffer_memory_req,
-            &base.device_memory_properties,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )
-        .expect("Unable to find suitable memorytype for the image buffer.");
-
-        let image_buffer_allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: image_buffer_memory_req.size,
-            memory_type_index: image_buffer_memory_index,
-            ..Default::default()
-        };
-        let image_buffer_memory = base
-            .device
-            .allocate_memory(&image_buffer_allocate_info, None)
-            .unwrap();
-        let image_ptr = base
-            .device
-            .map_memory(
-                image_buffer_memory,
-                0,
-                image_buffer_memory_req.size,
-                vk::MemoryMapFlags::empty(),
-            )
-            .unwrap();
-        let mut image_slice = Align::new(
-            image_ptr,
-            std::mem::align_of::<u8>() as u64,
-            image_buffer_memory_req.size,
-        );
-        image_slice.copy_from_slice(&image_data);
-        base.device.unmap_memory(image_buffer_memory);
-        base.device
-            .bind_buffer_memory(image_buffer, image_buffer_memory, 0)
-            .unwrap();
-
-        let texture_create_info = vk::ImageCreateInfo {
-            image_type: vk::ImageType::TYPE_2D,
-            format: vk::Format::R8G8B8A8_UNORM,
-            extent: image_extent.into(),
-            mip_levels: 1,
-            array_layers: 1,
-            samples: vk::SampleCountFlags::TYPE_1,
-            tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-        let texture_image = base
-            .device
-            .create_image(&texture_create_info, None)
-            .unwrap();
-        let texture_memory_req = base.device.get_image_memory_requirements(texture_image);
-        let texture_memory_index = find_memorytype_index(
-            &texture_memory_req,
-            &base.device_memory_properties,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        )
-        .expect("Unable to find suitable memory index for depth image.");
-
-        let texture_allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: texture_memory_req.size,
-            memory_type_index: texture_memory_index,
-            ..Default::default()
-        };
-        let texture_memory = base
-            .device
-            .allocate_memory(&texture_allocate_info, None)
-            .unwrap();
-        base.device
-            .bind_image_memory(texture_image, texture_memory, 0)
-            .expect("Unable to bind depth image memory");
-
-        record_submit_commandbuffer(
+        // Decoded frames are sampled straight from the decoder's images
+        let ycbcr_sampler = ycbcr::YcbcrSampler::new(
+            &base.instance,
             &base.device,
-            base.setup_command_buffer,
-            base.setup_commands_reuse_fence,
-            base.present_queue,
-            &[],
-            &[],
-            &[],
-            |device, texture_command_buffer| {
-                let texture_barrier = vk::ImageMemoryBarrier {
-                    dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                    new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    image: texture_image,
-                    subresource_range: vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        level_count: 1,
-                        layer_count: 1,
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                };
-                device.cmd_pipeline_barrier(
-                    texture_command_buffer,
-                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[texture_barrier],
-                );
-                let buffer_copy_regions = vk::BufferImageCopy::default()
-                    .image_subresource(
-                        vk::ImageSubresourceLayers::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .layer_count(1),
-                    )
-                    .image_extent(image_extent.into());
-
-                device.cmd_copy_buffer_to_image(
-                    texture_command_buffer,
-                    image_buffer,
-                    texture_image,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    &[buffer_copy_regions],
-                );
-                let texture_barrier_end = vk::ImageMemoryBarrier {
-                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                    dst_access_mask: vk::AccessFlags::SHADER_READ,
-                    old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                    image: texture_image,
-                    subresource_range: vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        level_count: 1,
-                        layer_count: 1,
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                };
-                device.cmd_pipeline_barrier(
-                    texture_command_buffer,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::PipelineStageFlags::FRAGMENT_SHADER,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[texture_barrier_end],
-                );
-            },
-        );
-
-        let sampler_info = vk::SamplerCreateInfo {
-            mag_filter: vk::Filter::LINEAR,
-            min_filter: vk::Filter::LINEAR,
-            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
-            address_mode_u: vk::SamplerAddressMode::MIRRORED_REPEAT,
-            address_mode_v: vk::SamplerAddressMode::MIRRORED_REPEAT,
-            address_mode_w: vk::SamplerAddressMode::MIRRORED_REPEAT,
-            max_anisotropy: 1.0,
-            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
-            compare_op: vk::CompareOp::NEVER,
-            ..Default::default()
-        };
-
-        let sampler = base.device.create_sampler(&sampler_info, None).unwrap();
+            base.pdevice,
+            source.format(),
+            &stream_info,
+        )?;
 
-        let tex_image_view_info = vk::ImageViewCreateInfo {
-            view_type: vk::ImageViewType::TYPE_2D,
-            format: texture_create_info.format,
-            components: vk::ComponentMapping {
-                r: vk::ComponentSwizzle::R,
-                g: vk::ComponentSwizzle::G,
-                b: vk::ComponentSwizzle::B,
-                a: vk::ComponentSwizzle::A,
-            },
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            image: texture_image,
-            ..Default::default()
-        };
-        let tex_image_view = base
-            .device
-            .create_image_view(&tex_image_view_info, None)
-            .unwrap();
         let descriptor_sizes = [
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER,
@@ -1049,6 +729,8 @@ fn main() -> Result<()> {
                 descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
                 descriptor_count: 1,
                 stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                // A YCbCr conversion sampler can only be used as an immutable sampler
+                p_immutable_samplers: &ycbcr_sampler.sampler,
                 ..Default::default()
             },
         ];
@@ -1074,29 +756,13 @@ fn main() -> Result<()> {
             range: mem::size_of_val(&uniform_color_buffer_data) as u64,
         };
 
-        let tex_descriptor = vk::DescriptorImageInfo {
-            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            image_view: tex_image_view,
-            sampler,
-        };
-
-        let write_desc_sets = [
-            vk::WriteDescriptorSet {
-                dst_set: descriptor_sets[0],
-                descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-                p_buffer_info: &uniform_color_buffer_descriptor,
-                ..Default::default()
-            },
-            vk::WriteDescriptorSet {
-                dst_set: descriptor_sets[0],
-                dst_binding: 1,
-                descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                p_image_info: &tex_descriptor,
-                ..Default::default()
-            },
-        ];
+        let write_desc_sets = [vk::WriteDescriptorSet {
+            dst_set: descriptor_sets[0],
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            p_buffer_info: &uniform_color_buffer_descriptor,
+            ..Default::default()
+        }];
         base.device.update_descriptor_sets(&write_desc_sets, &[]);
 
         let mut vertex_spv_file = Cursor::new(&include_bytes!("../shader/texture/vert.spv")[..]);
@@ -1248,46 +914,65 @@ fn main() -> Result<()> {
 
         let graphic_pipeline = graphics_pipelines[0];
 
-        base.render_loop(|| {
-            let (present_index, _) = base
-                .swapchain_loader
-                .acquire_next_image(
+        let source = RefCell::new(source);
+        let ycbcr_sampler = RefCell::new(ycbcr_sampler);
+        let access_units = RefCell::new(access_units);
+
+        // A demuxing, decoding or presentation error ends playback and is returned from main
+        // once the device is idle and its objects destroyed
+        let result = base.render_loop(|| {
+            // One access unit per drawn frame, then what is left waiting for display
+            let mut source = source.borrow_mut();
+            let access_unit = access_units.borrow_mut().next().transpose()?;
+            for frame in source.decode(access_unit)? {
+                let frame = source.device_frame(frame)?;
+                if DEBUG_ENABLED {
+                    println!("decoded frame pts {}", frame.pts);
+                }
+
+                let image_descriptor = vk::DescriptorImageInfo {
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    image_view: ycbcr_sampler.borrow_mut().view(&frame)?,
+                    sampler: vk::Sampler::null(),
+                };
+                let write_desc_sets = [vk::WriteDescriptorSet {
+                    dst_set: descriptor_sets[0],
+                    dst_binding: 1,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: &image_descriptor,
+                    ..Default::default()
+                }];
+                base.device.update_descriptor_sets(&write_desc_sets, &[]);
+
+                let (present_index, _) = base.swapchain_loader.acquire_next_image(
                     base.swapchain,
                     std::u64::MAX,
                     base.present_complete_semaphore,
                     vk::Fence::null(),
-                )
-                .unwrap();
+                )?;
 
-            let clear_values = [
-                vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 0.0],
+                let clear_values = [
+                    vk::ClearValue {
+                        color: vk::ClearColorValue {
+                            float32: [0.0, 0.0, 0.0, 0.0],
+                        },
                     },
-                },
-                vk::ClearValue {
-                    depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
-                        stencil: 0,
+                    vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
                     },
-                },
-            ];
+                ];
 
-            let render_pass_begin_info = vk::RenderPassBeginInfo::default()
-                .render_pass(renderpass)
-                .framebuffer(framebuffers[present_index as usize])
-                .render_area(base.surface_resolution.into())
-                .clear_values(&clear_values);
+                let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+                    .render_pass(renderpass)
+                    .framebuffer(framebuffers[present_index as usize])
+                    .render_area(base.surface_resolution.into())
+                    .clear_values(&clear_values);
 
-            record_submit_commandbuffer(
-                &base.device,
-                base.draw_command_buffer,
-                base.draw_commands_reuse_fence,
-                base.present_queue,
-                &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
-                &[base.present_complete_semaphore],
-                &[base.rendering_complete_semaphore],
-                |device, draw_command_buffer| {
+                source.draw(&base, &frame, |device, draw_command_buffer| {
                     device.cmd_begin_render_pass(
                         draw_command_buffer,
                         &render_pass_begin_info,
@@ -1331,28 +1016,27 @@ fn main() -> Result<()> {
                     // Or draw without the index buffer
                     // device.cmd_draw(draw_command_buffer, 3, 1, 0, 0);
                     device.cmd_end_render_pass(draw_command_buffer);
-                },
-            );
-
-            //let mut present_info_err = mem::zeroed();
-            let present_info = vk::PresentInfoKHR {
-                wait_semaphore_count: 1,
-                p_wait_semaphores: &base.rendering_complete_semaphore,
-                swapchain_count: 1,
-                p_swapchains: &base.swapchain,
-                p_image_indices: &present_index,
-                ..Default::default()
-            };
-            base.swapchain_loader
-                .queue_present(base.present_queue, &present_info)
-                .unwrap();
+                })?;
+
+                //let mut present_info_err = mem::zeroed();
+                let present_info = vk::PresentInfoKHR {
+                    wait_semaphore_count: 1,
+                    p_wait_semaphores: &base.rendering_complete_semaphore,
+                    swapchain_count: 1,
+                    p_swapchains: &base.swapchain,
+                    p_image_indices: &present_index,
+                    ..Default::default()
+                };
+                base.swapchain_loader
+                    .queue_present(base.present_queue, &present_info)?;
+            }
+            Ok(())
         });
         base.device.device_wait_idle().unwrap();
 
-        //Destroy video
-        video_queue_loader.destroy_video_session_parameters(video_session_paramaters, None);
-        video_queue_loader.destroy_video_session(video_session, None);
-        
+        // The sampler's views go before the decoder's images
+        drop(ycbcr_sampler);
+        drop(source);
 
         for pipeline in graphics_pipelines {
             base.device.destroy_pipeline(pipeline, None);
@@ -1363,24 +1047,6 @@ fn main() -> Result<()> {
         base.device
             .destroy_shader_module(fragment_shader_module, None);
 
-        // Video resources
-        base.device.destroy_buffer(bitstream_buffer, None);
-        base.device.free_memory(dpb_memory, None);
-        base.device.destroy_image(dpb_image, None);
-        base.device.destroy_image_view(dpb_image_view, None);
-        base.device.free_memory(dst_memory, None);
-        base.device.destroy_image(dst_image, None);
-        base.device.destroy_image_view(dst_image_view, None);
-        base.device.free_memory(image_buffer_memory, None);
-        base.device.destroy_buffer(image_buffer, None);
-        for video_memory in video_session_memory {
-            base.device.free_memory(video_memory, None);
-        }
-        base.device.free_memory(bitstream_buffer_memory, None);
-
-        base.device.free_memory(texture_memory, None);
-        base.device.destroy_image_view(tex_image_view, None);
-        base.device.destroy_image(texture_image, None);
         base.device.free_memory(index_buffer_memory, None);
         base.device.destroy_buffer(index_buffer, None);
         base.device.free_memory(uniform_color_buffer_memory, None);
@@ -1392,12 +1058,11 @@ fn main() -> Result<()> {
                 .destroy_descriptor_set_layout(descriptor_set_layout, None);
         }
         base.device.destroy_descriptor_pool(descriptor_pool, None);
-        base.device.destroy_sampler(sampler, None);
         for framebuffer in framebuffers {
             base.device.destroy_framebuffer(framebuffer, None);
         }
         base.device.destroy_render_pass(renderpass, None);
-    }
 
-    Ok(())
+        result
+    }
 }
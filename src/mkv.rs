@@ -0,0 +1,927 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+use anyhow::{anyhow, Result};
+
+use crate::mp4::LengthPrefixedNalUnits;
+use crate::timestamp::{Timestamp, NANOS_PER_SECOND};
+
+/// EBML and Matroska element IDs, RFC 8794 and RFC 9559, with their marker bits.
+pub mod id {
+    pub const EBML: u32 = 0x1A45_DFA3;
+    pub const DOC_TYPE: u32 = 0x4282;
+    pub const SEGMENT: u32 = 0x1853_8067;
+    pub const SEEK_HEAD: u32 = 0x114D_9B74;
+    pub const INFO: u32 = 0x1549_A966;
+    pub const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+    pub const DURATION: u32 = 0x4489;
+    pub const TRACKS: u32 = 0x1654_AE6B;
+    pub const TRACK_ENTRY: u32 = 0xAE;
+    pub const TRACK_NUMBER: u32 = 0xD7;
+    pub const TRACK_TYPE: u32 = 0x83;
+    pub const CODEC_ID: u32 = 0x86;
+    pub const CODEC_PRIVATE: u32 = 0x63A2;
+    pub const DEFAULT_DURATION: u32 = 0x23_E383;
+    pub const VIDEO: u32 = 0xE0;
+    pub const PIXEL_WIDTH: u32 = 0xB0;
+    pub const PIXEL_HEIGHT: u32 = 0xBA;
+    pub const CONTENT_ENCODINGS: u32 = 0x6D80;
+    pub const CLUSTER: u32 = 0x1F43_B675;
+    pub const TIMESTAMP: u32 = 0xE7;
+    pub const SIMPLE_BLOCK: u32 = 0xA3;
+    pub const BLOCK_GROUP: u32 = 0xA0;
+    pub const BLOCK: u32 = 0xA1;
+    pub const REFERENCE_BLOCK: u32 = 0xFB;
+    pub const CUES: u32 = 0x1C53_BB6B;
+    pub const CUE_POINT: u32 = 0xBB;
+    pub const CUE_TIME: u32 = 0xB3;
+    pub const CUE_TRACK_POSITIONS: u32 = 0xB7;
+    pub const CUE_TRACK: u32 = 0xF7;
+    pub const CUE_CLUSTER_POSITION: u32 = 0xF1;
+    pub const CHAPTERS: u32 = 0x1043_A770;
+    pub const TAGS: u32 = 0x1254_C367;
+    pub const ATTACHMENTS: u32 = 0x1941_A469;
+}
+
+/// TrackType of video tracks
+pub const TRACK_TYPE_VIDEO: u64 = 1;
+
+/// Matroska TimestampScale default, one millisecond per tick
+const DEFAULT_TIMESTAMP_SCALE: u64 = 1_000_000;
+
+/// Reads a variable size integer, returning its value with the length marker cleared
+/// and its length in bytes.
+fn read_vint(data: &[u8], max_length: usize) -> Result<(u64, usize)> {
+    let first = *data
+        .first()
+        .ok_or_else(|| anyhow!("Truncated EBML integer"))?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > max_length {
+        return Err(anyhow!("Invalid EBML integer length {}", length));
+    }
+    let bytes = data
+        .get(..length)
+        .ok_or_else(|| anyhow!("Truncated EBML integer of {} bytes", length))?;
+    let value = bytes[1..]
+        .iter()
+        .fold((first as u64) & (0xff >> length), |value, &byte| {
+            value << 8 | byte as u64
+        });
+    Ok((value, length))
+}
+
+/// An element header: the ID including its marker bits, the payload size, `None` when
+/// unknown, and the header length.
+fn read_element_header(data: &[u8]) -> Result<(u32, Option<u64>, usize)> {
+    let (_, id_length) = read_vint(data, 4)?;
+    let id = data[..id_length]
+        .iter()
+        .fold(0u32, |id, &byte| id << 8 | byte as u32);
+    let (size, size_length) = read_vint(&data[id_length..], 8)?;
+    // All value bits set marks an unknown size
+    let unknown = size == (1u64 << (7 * size_length)) - 1;
+    Ok((id, (!unknown).then_some(size), id_length + size_length))
+}
+
+/// Iterates over the elements in `data`, yielding their ID and payload. An element of
+/// unknown size extends to the end of `data`.
+pub struct Elements<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Elements<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Result<(u32, &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let (id, size, header_length) = match read_element_header(self.data) {
+            Ok(header) => header,
+            Err(err) => {
+                self.data = &[];
+                return Some(Err(err));
+            }
+        };
+
+        let remaining = self.data.len() - header_length;
+        let size = match size {
+            Some(size) if size > remaining as u64 => {
+                self.data = &[];
+                return Some(Err(anyhow!(
+                    "Element {:#x} of {} bytes does not fit the remaining {} bytes",
+                    id,
+                    size,
+                    remaining
+                )));
+            }
+            Some(size) => size as usize,
+            None => remaining,
+        };
+
+        let (current, rest) = self.data[header_length..].split_at(size);
+        self.data = rest;
+        Some(Ok((id, current)))
+    }
+}
+
+/// Unsigned integer element payload of up to 8 bytes.
+fn read_uint(payload: &[u8]) -> Result<u64> {
+    if payload.len() > 8 {
+        return Err(anyhow!("Unsigned integer of {} bytes", payload.len()));
+    }
+    Ok(payload
+        .iter()
+        .fold(0u64, |value, &byte| value << 8 | byte as u64))
+}
+
+/// Float element payload, empty, 4 or 8 bytes.
+fn read_float(payload: &[u8]) -> Result<f64> {
+    match payload.len() {
+        0 => Ok(0.0),
+        4 => Ok(f32::from_be_bytes(payload.try_into().unwrap()) as f64),
+        8 => Ok(f64::from_be_bytes(payload.try_into().unwrap())),
+        len => Err(anyhow!("Float of {} bytes", len)),
+    }
+}
+
+/// String element payload, without the zero padding.
+fn read_string(payload: &[u8]) -> String {
+    let end = payload
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(payload.len());
+    String::from_utf8_lossy(&payload[..end]).into_owned()
+}
+
+/// A `TrackEntry` of the `Tracks` element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackEntry<'a> {
+    pub number: u64,
+    pub track_type: u64,
+    /// e.g. `V_MPEG4/ISO/AVC` or `V_AV1`
+    pub codec_id: String,
+    /// The decoder configuration, `avcC`, `hvcC` or `av1C` for the video codecs we decode
+    pub codec_private: &'a [u8],
+    /// Frame duration in nanoseconds
+    pub default_duration: Option<u64>,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl<'a> TrackEntry<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        let mut track = Self {
+            number: 0,
+            track_type: 0,
+            codec_id: String::new(),
+            codec_private: &[],
+            default_duration: None,
+            width: 0,
+            height: 0,
+        };
+
+        for element in Elements::new(data) {
+            let (element_id, payload) = element?;
+            match element_id {
+                id::TRACK_NUMBER => track.number = read_uint(payload)?,
+                id::TRACK_TYPE => track.track_type = read_uint(payload)?,
+                id::CODEC_ID => track.codec_id = read_string(payload),
+                id::CODEC_PRIVATE => track.codec_private = payload,
+                id::DEFAULT_DURATION => track.default_duration = Some(read_uint(payload)?),
+                id::VIDEO => {
+                    for element in Elements::new(payload) {
+                        let (element_id, payload) = element?;
+                        match element_id {
+                            id::PIXEL_WIDTH => track.width = read_uint(payload)? as u16,
+                            id::PIXEL_HEIGHT => track.height = read_uint(payload)? as u16,
+                            _ => {}
+                        }
+                    }
+                }
+                // Header stripping and zlib are hardly used for video, leave them out
+                id::CONTENT_ENCODINGS => {
+                    return Err(anyhow!("Track with content encodings is not supported"))
+                }
+                _ => {}
+            }
+        }
+
+        if track.number == 0 {
+            return Err(anyhow!("TrackEntry without a TrackNumber"));
+        }
+        Ok(track)
+    }
+}
+
+/// A cue of the `Cues` element, times in TimestampScale ticks and positions relative to
+/// the start of the Segment payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CuePoint {
+    pub time: u64,
+    pub track: u64,
+    pub cluster_position: u64,
+}
+
+/// A cluster with its position relative to the start of the Segment payload.
+#[derive(Clone, Copy, Debug)]
+struct Cluster<'a> {
+    position: u64,
+    timestamp: u64,
+    payload: &'a [u8],
+}
+
+/// A frame read from a SimpleBlock or Block. For AVC and HEVC tracks one access unit of
+/// length-prefixed NAL units, for AV1 a temporal unit of OBUs, as in MP4 samples.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame<'a> {
+    pub track: u64,
+    pub pts: Timestamp,
+    pub is_keyframe: bool,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Rewrites the frame as an Annex-B byte stream, see [`crate::mp4::Sample::to_annexb`].
+    pub fn to_annexb(&self, length_size: usize) -> Result<Vec<u8>> {
+        let mut annexb = Vec::with_capacity(self.bytes.len() + 16);
+        for nal in LengthPrefixedNalUnits::new(self.bytes, length_size) {
+            annexb.extend_from_slice(&[0, 0, 1]);
+            annexb.extend_from_slice(nal?);
+        }
+        Ok(annexb)
+    }
+}
+
+/// A Matroska or WebM file read into memory: the segment info, the tracks, the cues and
+/// an index of the clusters.
+#[derive(Clone, Debug)]
+pub struct Matroska<'a> {
+    pub doc_type: String,
+    /// Nanoseconds per timestamp tick
+    pub timestamp_scale: u64,
+    pub duration: Option<Timestamp>,
+    pub tracks: Vec<TrackEntry<'a>>,
+    pub cues: Vec<CuePoint>,
+    clusters: Vec<Cluster<'a>>,
+}
+
+/// Converts `ticks` of `timestamp_scale` nanoseconds, see [`Matroska::timestamp`].
+fn timestamp(timestamp_scale: u64, ticks: i64) -> Timestamp {
+    match u32::try_from(timestamp_scale) {
+        Ok(scale) if NANOS_PER_SECOND % scale == 0 => {
+            Timestamp::new(ticks, NANOS_PER_SECOND / scale)
+        }
+        _ => Timestamp::from_nanos(ticks.saturating_mul(timestamp_scale as i64)),
+    }
+}
+
+/// The Timestamp of a Cluster, `None` when it has none.
+fn cluster_timestamp(payload: &[u8]) -> Option<Result<u64>> {
+    Elements::new(payload).find_map(|element| match element {
+        Ok((id::TIMESTAMP, timestamp)) => Some(read_uint(timestamp)),
+        Ok(_) => None,
+        Err(err) => Some(Err(err)),
+    })
+}
+
+/// Elements that end a Cluster of unknown size.
+fn is_top_level(element_id: u32) -> bool {
+    matches!(
+        element_id,
+        id::SEEK_HEAD
+            | id::INFO
+            | id::TRACKS
+            | id::CLUSTER
+            | id::CUES
+            | id::CHAPTERS
+            | id::TAGS
+            | id::ATTACHMENTS
+    )
+}
+
+impl<'a> Matroska<'a> {
+    /// Parses the EBML header and the first Segment of `data`, the whole file.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let mut elements = Elements::new(data);
+        let header = match elements.next() {
+            Some(Ok((id::EBML, header))) => header,
+            Some(Err(err)) => return Err(err),
+            _ => return Err(anyhow!("Missing EBML header")),
+        };
+        let mut doc_type = String::from("matroska");
+        for element in Elements::new(header) {
+            if let (id::DOC_TYPE, payload) = element? {
+                doc_type = read_string(payload);
+            }
+        }
+        if !matches!(doc_type.as_str(), "matroska" | "webm") {
+            return Err(anyhow!("Unsupported EBML document type '{}'", doc_type));
+        }
+
+        let segment = elements
+            .find_map(|element| match element {
+                Ok((id::SEGMENT, segment)) => Some(Ok(segment)),
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .ok_or_else(|| anyhow!("Missing Segment"))??;
+
+        let mut matroska = Self {
+            doc_type,
+            timestamp_scale: DEFAULT_TIMESTAMP_SCALE,
+            duration: None,
+            tracks: Vec::new(),
+            cues: Vec::new(),
+            clusters: Vec::new(),
+        };
+        let mut duration = None;
+
+        // Walked by hand rather than with Elements, clusters of unknown size are common
+        // in live captures and cluster positions are needed for the cues
+        let mut position = 0;
+        while position < segment.len() {
+            let (element_id, size, header_length) = read_element_header(&segment[position..])?;
+            let payload_start = position + header_length;
+            let payload_end = match size {
+                Some(size) => usize::try_from(size)
+                    .ok()
+                    .and_then(|size| payload_start.checked_add(size))
+                    .filter(|&end| end <= segment.len())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Element {:#x} of {} bytes does not fit the Segment",
+                            element_id,
+                            size
+                        )
+                    })?,
+                None if element_id == id::CLUSTER => {
+                    let mut end = payload_start;
+                    while end < segment.len() {
+                        let (child_id, child_size, child_header_length) =
+                            read_element_header(&segment[end..])?;
+                        if is_top_level(child_id) {
+                            break;
+                        }
+                        let child_size = child_size.ok_or_else(|| {
+                            anyhow!("Cluster child {:#x} of unknown size", child_id)
+                        })?;
+                        end = usize::try_from(child_size)
+                            .ok()
+                            .and_then(|size| (end + child_header_length).checked_add(size))
+                            .filter(|&end| end <= segment.len())
+                            .ok_or_else(|| {
+                                anyhow!("Cluster child {:#x} exceeds the Segment", child_id)
+                            })?;
+                    }
+                    end
+                }
+                None => segment.len(),
+            };
+            let payload = &segment[payload_start..payload_end];
+
+            match element_id {
+                id::INFO => {
+                    for element in Elements::new(payload) {
+                        let (element_id, payload) = element?;
+                        match element_id {
+                            id::TIMESTAMP_SCALE => matroska.timestamp_scale = read_uint(payload)?,
+                            id::DURATION => duration = Some(read_float(payload)?),
+                            _ => {}
+                        }
+                    }
+                }
+                id::TRACKS => {
+                    for element in Elements::new(payload) {
+                        if let (id::TRACK_ENTRY, entry) = element? {
+                            matroska.tracks.push(TrackEntry::parse(entry)?);
+                        }
+                    }
+                }
+                id::CUES => matroska.cues = parse_cues(payload)?,
+                id::CLUSTER => {
+                    let timestamp = cluster_timestamp(payload)
+                        .ok_or_else(|| anyhow!("Cluster at {} without a Timestamp", position))??;
+                    matroska.clusters.push(Cluster {
+                        position: position as u64,
+                        timestamp,
+                        payload,
+                    });
+                }
+                _ => {}
+            }
+
+            position = payload_end;
+        }
+
+        if matroska.timestamp_scale == 0 {
+            return Err(anyhow!("Invalid TimestampScale 0"));
+        }
+        // Duration is a float in ticks
+        matroska.duration = duration.map(|duration| matroska.timestamp(duration.round() as i64));
+
+        Ok(matroska)
+    }
+
+    /// Converts `ticks` of TimestampScale to a timestamp, exact when the scale divides a
+    /// second as the usual 1 ms does.
+    pub fn timestamp(&self, ticks: i64) -> Timestamp {
+        timestamp(self.timestamp_scale, ticks)
+    }
+
+    /// The first video track.
+    pub fn video_track(&self) -> Option<&TrackEntry<'a>> {
+        self.tracks
+            .iter()
+            .find(|track| track.track_type == TRACK_TYPE_VIDEO)
+    }
+
+    /// Iterates over the frames of the track `track_number` in storage order.
+    pub fn frames(&self, track_number: u64) -> Frames<'_> {
+        self.frames_from(0, track_number)
+    }
+
+    /// Iterates over the frames of the track `track_number` from the last keyframe at or
+    /// before `time`. The cluster to start at comes from the cues, or from the cluster
+    /// timestamps for files without cues.
+    pub fn seek(&self, track_number: u64, time: Timestamp) -> Result<Frames<'_>> {
+        let target = u64::try_from(time.as_nanos()).unwrap_or(0) / self.timestamp_scale;
+
+        let index = if self.cues.is_empty() {
+            self.clusters
+                .iter()
+                .rposition(|cluster| cluster.timestamp <= target)
+                .unwrap_or(0)
+        } else {
+            let position = self
+                .cues
+                .iter()
+                .filter(|cue| cue.track == track_number && cue.time <= target)
+                .max_by_key(|cue| cue.time)
+                .or_else(|| self.cues.iter().find(|cue| cue.track == track_number))
+                .ok_or_else(|| anyhow!("No cues for track {}", track_number))?
+                .cluster_position;
+            self.clusters
+                .binary_search_by_key(&position, |cluster| cluster.position)
+                .map_err(|_| anyhow!("No cluster at cue position {}", position))?
+        };
+
+        let mut frames = self.frames_from(index, track_number);
+        frames.skip_to_keyframe = true;
+        Ok(frames)
+    }
+
+    fn frames_from(&self, cluster_index: usize, track_number: u64) -> Frames<'_> {
+        Frames {
+            timestamp_scale: self.timestamp_scale,
+            track_number,
+            default_duration: self
+                .tracks
+                .iter()
+                .find(|track| track.number == track_number)
+                .and_then(|track| track.default_duration),
+            clusters: self.clusters[cluster_index.min(self.clusters.len())..].iter(),
+            cluster_timestamp: 0,
+            elements: Elements::new(&[]),
+            pending: VecDeque::new(),
+            skip_to_keyframe: false,
+        }
+    }
+}
+
+fn parse_cues(data: &[u8]) -> Result<Vec<CuePoint>> {
+    let mut cues = Vec::new();
+    for element in Elements::new(data) {
+        let cue_point = match element? {
+            (id::CUE_POINT, cue_point) => cue_point,
+            _ => continue,
+        };
+
+        let mut time = None;
+        let mut positions = Vec::new();
+        for element in Elements::new(cue_point) {
+            let (element_id, payload) = element?;
+            match element_id {
+                id::CUE_TIME => time = Some(read_uint(payload)?),
+                id::CUE_TRACK_POSITIONS => {
+                    let mut track = None;
+                    let mut cluster_position = None;
+                    for element in Elements::new(payload) {
+                        let (element_id, payload) = element?;
+                        match element_id {
+                            id::CUE_TRACK => track = Some(read_uint(payload)?),
+                            id::CUE_CLUSTER_POSITION => {
+                                cluster_position = Some(read_uint(payload)?)
+                            }
+                            _ => {}
+                        }
+                    }
+                    if let (Some(track), Some(cluster_position)) = (track, cluster_position) {
+                        positions.push((track, cluster_position));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let time = time.ok_or_else(|| anyhow!("CuePoint without a CueTime"))?;
+        cues.extend(
+            positions
+                .into_iter()
+                .map(|(track, cluster_position)| CuePoint {
+                    time,
+                    track,
+                    cluster_position,
+                }),
+        );
+    }
+    Ok(cues)
+}
+
+/// Splits a laced block payload into its frames (RFC 9559 10.3).
+fn split_lace(flags: u8, data: &[u8]) -> Result<Vec<&[u8]>> {
+    let lacing = (flags >> 1) & 0b11;
+    if lacing == 0 {
+        return Ok(vec![data]);
+    }
+
+    let count = *data.first().ok_or_else(|| anyhow!("Truncated lace"))? as usize + 1;
+    let mut offset = 1;
+    let mut sizes = Vec::with_capacity(count);
+    match lacing {
+        // Xiph
+        1 => {
+            for _ in 0..count - 1 {
+                let mut size = 0;
+                loop {
+                    let byte = *data
+                        .get(offset)
+                        .ok_or_else(|| anyhow!("Truncated Xiph lace size"))?;
+                    offset += 1;
+                    size += byte as usize;
+                    if byte != 255 {
+                        break;
+                    }
+                }
+                sizes.push(size);
+            }
+        }
+        // Fixed size
+        2 => {
+            let remaining = data.len() - offset;
+            if remaining % count != 0 {
+                return Err(anyhow!(
+                    "{} bytes do not split into {} equal frames",
+                    remaining,
+                    count
+                ));
+            }
+            sizes.resize(count - 1, remaining / count);
+        }
+        // EBML, the first size followed by signed differences
+        _ => {
+            let mut size = 0i64;
+            for index in 0..count - 1 {
+                let (value, length) = read_vint(&data[offset.min(data.len())..], 8)?;
+                offset += length;
+                size = if index == 0 {
+                    value as i64
+                } else {
+                    size + value as i64 - ((1i64 << (7 * length - 1)) - 1)
+                };
+                // Bounded by the block so that the differences cannot overflow
+                if size < 0 || size as usize > data.len() {
+                    return Err(anyhow!("Invalid EBML lace size {}", size));
+                }
+                sizes.push(size as usize);
+            }
+        }
+    }
+
+    let mut frames = Vec::with_capacity(count);
+    for size in sizes {
+        let frame = offset
+            .checked_add(size)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| anyhow!("Laced frame of {} bytes exceeds the block", size))?;
+        frames.push(frame);
+        offset += size;
+    }
+    frames.push(data.get(offset..).unwrap_or(&[]));
+    Ok(frames)
+}
+
+/// Iterates over the frames of one track, see [`Matroska::frames`] and [`Matroska::seek`].
+pub struct Frames<'a> {
+    timestamp_scale: u64,
+    track_number: u64,
+    default_duration: Option<u64>,
+    clusters: std::slice::Iter<'a, Cluster<'a>>,
+    cluster_timestamp: u64,
+    elements: Elements<'a>,
+    pending: VecDeque<Frame<'a>>,
+    skip_to_keyframe: bool,
+}
+
+impl<'a> Frames<'a> {
+    /// Iterates over the frames of the track `track_number` in `cluster`, the payload of a
+    /// single Cluster as read by [`MatroskaReader`]. `timestamp_scale` and
+    /// `default_duration` come from the [`Matroska`] parsed from the header.
+    pub fn from_cluster(
+        cluster: &'a [u8],
+        timestamp_scale: u64,
+        track_number: u64,
+        default_duration: Option<u64>,
+    ) -> Result<Self> {
+        let cluster_timestamp =
+            cluster_timestamp(cluster).ok_or_else(|| anyhow!("Cluster without a Timestamp"))??;
+        Ok(Frames {
+            timestamp_scale,
+            track_number,
+            default_duration,
+            clusters: [].iter(),
+            cluster_timestamp,
+            elements: Elements::new(cluster),
+            pending: VecDeque::new(),
+            skip_to_keyframe: false,
+        })
+    }
+
+    /// Queues the frames of a SimpleBlock or Block. A Block is a keyframe when its
+    /// BlockGroup has no ReferenceBlock.
+    fn read_block(&mut self, block: &'a [u8], is_keyframe: Option<bool>) -> Result<()> {
+        let (track, length) = read_vint(block, 8)?;
+        if track != self.track_number {
+            return Ok(());
+        }
+        let header = block
+            .get(length..length + 3)
+            .ok_or_else(|| anyhow!("Truncated block header"))?;
+        let relative = i16::from_be_bytes([header[0], header[1]]);
+        let flags = header[2];
+        let is_keyframe = is_keyframe.unwrap_or(flags & 0x80 != 0);
+
+        let pts = timestamp(
+            self.timestamp_scale,
+            (self.cluster_timestamp as i64).saturating_add(relative as i64),
+        );
+        for (index, bytes) in split_lace(flags, &block[length + 3..])?
+            .into_iter()
+            .enumerate()
+        {
+            // Laced frames after the first are spaced by the default duration if known
+            let offset = self.default_duration.map_or(Timestamp::ZERO, |duration| {
+                Timestamp::from_nanos((duration as i64).saturating_mul(index as i64))
+            });
+            self.pending.push_back(Frame {
+                track,
+                pts: if index == 0 { pts } else { pts + offset },
+                is_keyframe: is_keyframe && index == 0,
+                bytes,
+            });
+        }
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame<'a>>> {
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Ok(Some(frame));
+            }
+
+            let (element_id, payload) = match self.elements.next() {
+                Some(element) => element?,
+                None => match self.clusters.next() {
+                    Some(cluster) => {
+                        self.cluster_timestamp = cluster.timestamp;
+                        self.elements = Elements::new(cluster.payload);
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+
+            match element_id {
+                id::SIMPLE_BLOCK => self.read_block(payload, None)?,
+                id::BLOCK_GROUP => {
+                    let mut block = None;
+                    let mut has_reference = false;
+                    for element in Elements::new(payload) {
+                        match element? {
+                            (id::BLOCK, payload) => block = Some(payload),
+                            (id::REFERENCE_BLOCK, _) => has_reference = true,
+                            _ => {}
+                        }
+                    }
+                    let block = block.ok_or_else(|| anyhow!("BlockGroup without a Block"))?;
+                    self.read_block(block, Some(!has_reference))?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_frame() {
+                Ok(Some(frame)) if self.skip_to_keyframe && !frame.is_keyframe => continue,
+                Ok(Some(frame)) => {
+                    self.skip_to_keyframe = false;
+                    return Some(Ok(frame));
+                }
+                Ok(None) => return None,
+                Err(err) => {
+                    // Stop after the first error like the other demuxers
+                    self.clusters = [].iter();
+                    self.elements = Elements::new(&[]);
+                    self.pending.clear();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Reads an element header from `reader`, see [`read_element_header`], appending its
+/// bytes to `buffer`. `None` at the end of the stream.
+fn read_element_header_from<R: Read>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+) -> Result<Option<(u32, Option<u64>)>> {
+    let mut header = [0; 12];
+    match reader.read_exact(&mut header[..1]) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let id_length = header[0].leading_zeros() as usize + 1;
+    if id_length > 4 {
+        return Err(anyhow!("Invalid EBML integer length {}", id_length));
+    }
+    reader.read_exact(&mut header[1..id_length + 1])?;
+    let size_length = header[id_length].leading_zeros() as usize + 1;
+    if size_length > 8 {
+        return Err(anyhow!("Invalid EBML integer length {}", size_length));
+    }
+    let header = &mut header[..id_length + size_length];
+    reader.read_exact(&mut header[id_length + 1..])?;
+
+    let (element_id, size, _) = read_element_header(header)?;
+    buffer.extend_from_slice(header);
+    Ok(Some((element_id, size)))
+}
+
+/// Reads a Matroska file from a stream one Cluster at a time, so that only the cluster
+/// being demuxed is held in memory. The Info and Tracks before the first Cluster are read
+/// up front into [`MatroskaReader::header`].
+pub struct MatroskaReader<R> {
+    reader: R,
+    /// Where the Segment ends, the end of the stream for a Segment of unknown size
+    segment_end: u64,
+    header: Vec<u8>,
+}
+
+impl<R: Read + Seek> MatroskaReader<R> {
+    /// Reads the EBML header and the Segment up to its first Cluster from the start of
+    /// `reader`.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut header = Vec::new();
+        match read_element_header_from(&mut reader, &mut header)? {
+            Some((id::EBML, Some(size))) => read_payload(&mut reader, size, &mut header)?,
+            _ => return Err(anyhow!("Missing EBML header")),
+        }
+
+        let segment_size = loop {
+            match read_element_header_from(&mut reader, &mut Vec::new())? {
+                Some((id::SEGMENT, size)) => break size,
+                Some((_, Some(size))) => skip(&mut reader, size)?,
+                _ => return Err(anyhow!("Missing Segment")),
+            }
+        };
+        let segment_start = reader.stream_position()?;
+        let segment_end = segment_size.map_or(end, |size| segment_start.saturating_add(size));
+
+        // A Segment of unknown size around Info and Tracks, for Matroska::parse
+        header.extend_from_slice(&id::SEGMENT.to_be_bytes());
+        header.extend_from_slice(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        loop {
+            let position = reader.stream_position()?;
+            if position >= segment_end {
+                break;
+            }
+            let header_end = header.len();
+            match read_element_header_from(&mut reader, &mut header)? {
+                Some((element_id @ (id::INFO | id::TRACKS), Some(size))) => {
+                    read_payload(&mut reader, size, &mut header)
+                        .map_err(|err| anyhow!("Element {:#x}: {}", element_id, err))?;
+                    continue;
+                }
+                Some((id::CLUSTER, _)) | None => {
+                    header.truncate(header_end);
+                    reader.seek(SeekFrom::Start(position))?;
+                    break;
+                }
+                Some((_, Some(size))) => skip(&mut reader, size)?,
+                Some((element_id, None)) => {
+                    return Err(anyhow!("Element {:#x} of unknown size", element_id))
+                }
+            }
+            header.truncate(header_end);
+        }
+
+        Ok(Self {
+            reader,
+            segment_end,
+            header,
+        })
+    }
+
+    /// The EBML header and a Segment with the Info and Tracks of the file, to be read
+    /// with [`Matroska::parse`]. It holds no clusters.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// Reads the payload of the next Cluster into `cluster`, skipping other elements
+    /// such as the Cues. Returns `false` at the end of the Segment.
+    pub fn read_cluster(&mut self, cluster: &mut Vec<u8>) -> Result<bool> {
+        loop {
+            cluster.clear();
+            if self.reader.stream_position()? >= self.segment_end {
+                return Ok(false);
+            }
+            match read_element_header_from(&mut self.reader, cluster)? {
+                Some((id::CLUSTER, size)) => {
+                    cluster.clear();
+                    match size {
+                        Some(size) => read_payload(&mut self.reader, size, cluster)?,
+                        None => self.read_unknown_size_cluster(cluster)?,
+                    }
+                    return Ok(true);
+                }
+                Some((_, Some(size))) => skip(&mut self.reader, size)?,
+                Some((element_id, None)) => {
+                    return Err(anyhow!("Element {:#x} of unknown size", element_id))
+                }
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Reads the children of a Cluster of unknown size, which ends at the next top level
+    /// element.
+    fn read_unknown_size_cluster(&mut self, cluster: &mut Vec<u8>) -> Result<()> {
+        loop {
+            let position = self.reader.stream_position()?;
+            if position >= self.segment_end {
+                return Ok(());
+            }
+            let cluster_end = cluster.len();
+            match read_element_header_from(&mut self.reader, cluster)? {
+                Some((child_id, _)) if is_top_level(child_id) => {
+                    cluster.truncate(cluster_end);
+                    self.reader.seek(SeekFrom::Start(position))?;
+                    return Ok(());
+                }
+                Some((_, Some(size))) => read_payload(&mut self.reader, size, cluster)?,
+                Some((child_id, None)) => {
+                    return Err(anyhow!("Cluster child {:#x} of unknown size", child_id))
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Appends `size` bytes from `reader` to `buffer`.
+fn read_payload<R: Read>(reader: &mut R, size: u64, buffer: &mut Vec<u8>) -> Result<()> {
+    let read = reader.take(size).read_to_end(buffer)?;
+    if read as u64 != size {
+        return Err(anyhow!(
+            "Element of {} bytes cut short after {} bytes",
+            size,
+            read
+        ));
+    }
+    Ok(())
+}
+
+fn skip<R: Seek>(reader: &mut R, size: u64) -> Result<()> {
+    let offset = i64::try_from(size).map_err(|_| anyhow!("Element of {} bytes", size))?;
+    reader.seek(SeekFrom::Current(offset))?;
+    Ok(())
+}
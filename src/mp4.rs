@@ -0,0 +1,871 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::{anyhow, Result};
+use mp4parse::{MediaTimeScale, TimeOffsetVersion, Track};
+
+use crate::timestamp::Timestamp;
+
+/// Location and timing of a single sample, in track timescale units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleInfo {
+    pub offset: u64,
+    pub size: u32,
+    pub dts: u64,
+    pub pts: i64,
+    pub is_keyframe: bool,
+}
+
+/// A sample read from the file, for H.264 one access unit of length-prefixed NAL units.
+/// Timestamps are on the presentation timeline, i.e. with the edit list applied.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample<'a> {
+    pub dts: Timestamp,
+    pub pts: Timestamp,
+    pub is_keyframe: bool,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> Sample<'a> {
+    /// Splits the sample into NAL units using the length field size from the
+    /// decoder configuration record (`length_size_minus_one + 1`).
+    pub fn nal_units(&self, length_size: usize) -> LengthPrefixedNalUnits<'a> {
+        LengthPrefixedNalUnits::new(self.bytes, length_size)
+    }
+
+    /// Rewrites the sample as an Annex-B byte stream, which is what the decoder consumes.
+    pub fn to_annexb(&self, length_size: usize) -> Result<Vec<u8>> {
+        let mut annexb = Vec::with_capacity(self.bytes.len() + 16);
+        for nal in self.nal_units(length_size) {
+            annexb.extend_from_slice(&[0, 0, 1]);
+            annexb.extend_from_slice(nal?);
+        }
+        Ok(annexb)
+    }
+}
+
+/// Iterates over NAL units prefixed by a 1, 2 or 4 byte big-endian length.
+pub struct LengthPrefixedNalUnits<'a> {
+    data: &'a [u8],
+    length_size: usize,
+}
+
+impl<'a> LengthPrefixedNalUnits<'a> {
+    pub fn new(data: &'a [u8], length_size: usize) -> Self {
+        Self { data, length_size }
+    }
+}
+
+impl<'a> Iterator for LengthPrefixedNalUnits<'a> {
+    type Item = Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        if !matches!(self.length_size, 1 | 2 | 4) || self.data.len() < self.length_size {
+            self.data = &[];
+            return Some(Err(anyhow!("Truncated NAL unit length")));
+        }
+
+        let (length, rest) = self.data.split_at(self.length_size);
+        let length = length
+            .iter()
+            .fold(0usize, |length, &byte| length << 8 | byte as usize);
+
+        if rest.len() < length {
+            self.data = &[];
+            return Some(Err(anyhow!(
+                "NAL unit length {} exceeds the remaining {} bytes",
+                length,
+                rest.len()
+            )));
+        }
+
+        let (nal, rest) = rest.split_at(length);
+        self.data = rest;
+        Some(Ok(nal))
+    }
+}
+
+/// Maps track media time onto the movie presentation timeline.
+///
+/// Only the leading empty edit and the media time of the first edit of `elst` are
+/// honoured, which covers the usual B-frame delay compensation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackTiming {
+    /// Track (`mdhd`) timescale
+    pub timescale: u32,
+    /// Media time of the first edit, in track units
+    pub media_time: i64,
+    /// Duration of the leading empty edit
+    pub empty_duration: Timestamp,
+    /// Media duration from `mdhd`
+    pub duration: Option<Timestamp>,
+}
+
+impl TrackTiming {
+    /// `movie_timescale` is the `mvhd` timescale the edit list durations are expressed in.
+    pub fn new(track: &Track, movie_timescale: Option<MediaTimeScale>) -> Result<Self> {
+        let timescale = track
+            .timescale
+            .and_then(|timescale| u32::try_from(timescale.0).ok())
+            .filter(|&timescale| timescale != 0)
+            .ok_or_else(|| anyhow!("Missing or invalid track timescale"))?;
+
+        let empty_duration = match (track.empty_duration, movie_timescale) {
+            (Some(empty_duration), Some(movie_timescale)) if empty_duration.0 != 0 => {
+                let movie_timescale = u32::try_from(movie_timescale.0)
+                    .ok()
+                    .filter(|&timescale| timescale != 0)
+                    .ok_or_else(|| anyhow!("Invalid movie timescale {}", movie_timescale.0))?;
+                Timestamp::new(i64::try_from(empty_duration.0)?, movie_timescale)
+            }
+            _ => Timestamp::ZERO,
+        };
+
+        Ok(Self {
+            timescale,
+            media_time: track
+                .media_time
+                .map_or(Ok(0), |media_time| i64::try_from(media_time.0))?,
+            empty_duration,
+            duration: track
+                .duration
+                .and_then(|duration| i64::try_from(duration.0).ok())
+                .map(|duration| Timestamp::new(duration, timescale)),
+        })
+    }
+
+    /// Converts a media time in track units to the presentation timeline.
+    pub fn presentation_time(&self, media_time: i64) -> Timestamp {
+        Timestamp::new(media_time.saturating_sub(self.media_time), self.timescale)
+            + self.empty_duration
+    }
+}
+
+/// Flattened sample table of a track, combining the chunk offsets (`stco`/`co64`),
+/// samples per chunk (`stsc`), sample sizes (`stsz`), decode deltas (`stts`),
+/// composition offsets (`ctts`) and sync samples (`stss`).
+#[derive(Clone, Debug)]
+pub struct SampleTable {
+    pub samples: Vec<SampleInfo>,
+    pub timing: TrackTiming,
+    /// Decode time after the last sample, where the next fragment continues without `tfdt`
+    pub end_dts: u64,
+}
+
+impl SampleTable {
+    pub fn new(track: &Track, movie_timescale: Option<MediaTimeScale>) -> Result<Self> {
+        let timing = TrackTiming::new(track, movie_timescale)?;
+
+        let stco = track.stco.as_ref().ok_or_else(|| anyhow!("Missing stco"))?;
+        let stsc = track.stsc.as_ref().ok_or_else(|| anyhow!("Missing stsc"))?;
+        let stsz = track.stsz.as_ref().ok_or_else(|| anyhow!("Missing stsz"))?;
+        let stts = track.stts.as_ref().ok_or_else(|| anyhow!("Missing stts"))?;
+
+        let sample_count = if stsz.sample_size == 0 {
+            stsz.sample_sizes.len()
+        } else {
+            stts.samples
+                .iter()
+                .map(|entry| entry.sample_count as usize)
+                .sum()
+        };
+        let sample_size = |index: usize| {
+            if stsz.sample_size == 0 {
+                stsz.sample_sizes[index]
+            } else {
+                stsz.sample_size
+            }
+        };
+
+        // Chunk offsets and sizes
+        let mut samples = Vec::with_capacity(sample_count);
+        for (index, entry) in stsc.samples.iter().enumerate() {
+            let first_chunk = entry
+                .first_chunk
+                .checked_sub(1)
+                .ok_or_else(|| anyhow!("Invalid first_chunk 0 in stsc entry {}", index))?
+                as usize;
+            let last_chunk = stsc
+                .samples
+                .get(index + 1)
+                .map_or(stco.offsets.len(), |next| {
+                    (next.first_chunk as usize).saturating_sub(1)
+                })
+                .min(stco.offsets.len());
+
+            for chunk in first_chunk..last_chunk {
+                let mut offset = stco.offsets[chunk];
+                for _ in 0..entry.samples_per_chunk {
+                    if samples.len() == sample_count {
+                        break;
+                    }
+                    let size = sample_size(samples.len());
+                    samples.push(SampleInfo {
+                        offset,
+                        size,
+                        dts: 0,
+                        pts: 0,
+                        is_keyframe: false,
+                    });
+                    offset = offset.checked_add(size as u64).ok_or_else(|| {
+                        anyhow!(
+                            "Sample {} of chunk {} ends past 2^64",
+                            samples.len() - 1,
+                            chunk
+                        )
+                    })?;
+                }
+            }
+        }
+
+        if samples.len() != sample_count {
+            return Err(anyhow!(
+                "Chunks describe {} samples, stsz {}",
+                samples.len(),
+                sample_count
+            ));
+        }
+
+        // Decode timestamps
+        let deltas = stts
+            .samples
+            .iter()
+            .flat_map(|entry| (0..entry.sample_count).map(|_| entry.sample_delta));
+        let mut dts = 0u64;
+        let mut count = 0;
+        for (sample, delta) in samples.iter_mut().zip(deltas) {
+            sample.dts = dts;
+            sample.pts = dts as i64;
+            dts += delta as u64;
+            count += 1;
+        }
+        if count != sample_count {
+            return Err(anyhow!(
+                "stts describes {} samples, stsz {}",
+                count,
+                sample_count
+            ));
+        }
+
+        // Composition offsets, without ctts the presentation order is the decode order
+        if let Some(ctts) = &track.ctts {
+            let offsets = ctts.samples.iter().flat_map(|entry| {
+                let offset = match entry.time_offset {
+                    TimeOffsetVersion::Version0(offset) => offset as i64,
+                    TimeOffsetVersion::Version1(offset) => offset as i64,
+                };
+                (0..entry.sample_count).map(move |_| offset)
+            });
+            for (sample, offset) in samples.iter_mut().zip(offsets) {
+                sample.pts = sample.dts as i64 + offset;
+            }
+        }
+
+        // Without stss every sample is a sync sample
+        match &track.stss {
+            Some(stss) => {
+                for &number in stss.samples.iter() {
+                    if let Some(sample) = (number as usize)
+                        .checked_sub(1)
+                        .and_then(|index| samples.get_mut(index))
+                    {
+                        sample.is_keyframe = true;
+                    }
+                }
+            }
+            None => samples
+                .iter_mut()
+                .for_each(|sample| sample.is_keyframe = true),
+        }
+
+        Ok(Self {
+            samples,
+            timing,
+            end_dts: dts,
+        })
+    }
+
+    /// Appends the samples of the track `track_id` from the movie fragments (`moof`) in
+    /// `data`, the whole file, which may be an init segment followed by media segments.
+    pub fn add_fragments(&mut self, data: &[u8], track_id: u32) -> Result<()> {
+        let mut boxes = Boxes::new(data);
+        loop {
+            let moof_offset = boxes.offset() as u64;
+            match boxes.next() {
+                Some(Ok((box_type, moof))) if &box_type == b"moof" => {
+                    self.add_fragment(data, moof, moof_offset, track_id)?
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Appends the samples of the track `track_id` from one movie fragment, the payload
+    /// `moof` of the box at `moof_offset` in the file. `header` holds the `moov` of the
+    /// file. Fields a `trun` leaves out come from the `tfhd`, then from the `trex` defaults.
+    pub fn add_fragment(
+        &mut self,
+        header: &[u8],
+        moof: &[u8],
+        moof_offset: u64,
+        track_id: u32,
+    ) -> Result<()> {
+        // Without an explicit base, the data of a track fragment follows that of the
+        // previous one, starting at the moof
+        let mut traf_data_end = moof_offset;
+        for traf in Boxes::new(moof) {
+            let traf = match traf? {
+                (box_type, traf) if &box_type == b"traf" => traf,
+                _ => continue,
+            };
+
+            let tfhd = TrackFragmentHeader::parse(child_box(traf, b"tfhd")?)?;
+            let is_track = tfhd.track_id == track_id;
+            // The data of other tracks is skipped over using their own defaults
+            let trex = TrackExtends::find(header, tfhd.track_id)?;
+            let base_data_offset = match tfhd.base_data_offset {
+                Some(offset) => offset,
+                None if tfhd.default_base_is_moof => moof_offset,
+                None => traf_data_end,
+            };
+            if is_track {
+                if let Ok(tfdt) = child_box(traf, b"tfdt") {
+                    self.end_dts = parse_tfdt(tfdt)?;
+                }
+            }
+
+            let mut offset = base_data_offset;
+            for trun in Boxes::new(traf) {
+                let trun = match trun? {
+                    (box_type, trun) if &box_type == b"trun" => TrackRun::parse(trun)?,
+                    _ => continue,
+                };
+                if let Some(data_offset) = trun.data_offset {
+                    offset = base_data_offset
+                        .checked_add_signed(data_offset as i64)
+                        .ok_or_else(|| anyhow!("Invalid trun data_offset {}", data_offset))?;
+                }
+
+                for (index, sample) in trun.samples.iter().enumerate() {
+                    let size = sample
+                        .size
+                        .or(tfhd.default_sample_size)
+                        .unwrap_or(trex.default_sample_size);
+                    if is_track {
+                        let duration = sample
+                            .duration
+                            .or(tfhd.default_sample_duration)
+                            .unwrap_or(trex.default_sample_duration);
+                        let flags = sample
+                            .flags
+                            .or(trun.first_sample_flags.filter(|_| index == 0))
+                            .or(tfhd.default_sample_flags)
+                            .unwrap_or(trex.default_sample_flags);
+                        self.samples.push(SampleInfo {
+                            offset,
+                            size,
+                            dts: self.end_dts,
+                            pts: self.end_dts as i64 + sample.composition_offset,
+                            is_keyframe: flags & SAMPLE_IS_NON_SYNC_SAMPLE == 0,
+                        });
+                        self.end_dts += duration as u64;
+                    }
+                    offset += size as u64;
+                }
+            }
+            traf_data_end = offset;
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Media duration, falling back to the decode time after the last sample when
+    /// `mdhd` carries none, as in fragmented files where it is zero.
+    pub fn duration(&self) -> Timestamp {
+        let duration = self.timing.duration.filter(|duration| duration.value != 0);
+        duration.unwrap_or_else(|| Timestamp::new(self.end_dts as i64, self.timing.timescale))
+    }
+
+    /// Iterates over the samples in decode order, reading their bytes from `data`,
+    /// the whole file the track was parsed from.
+    pub fn iter<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = Result<Sample<'a>>> + 'a {
+        self.samples.iter().map(move |info| {
+            let bytes = usize::try_from(info.offset)
+                .ok()
+                .and_then(|offset| data.get(offset..offset.checked_add(info.size as usize)?))
+                .ok_or_else(|| outside_file(info))?;
+            Ok(self.sample(info, bytes))
+        })
+    }
+
+    /// Reads the sample `index` from `reader`, the file the track was parsed from, into
+    /// `buffer`, so only one sample at a time is held in memory.
+    pub fn read_sample<'a, R: Read + Seek>(
+        &self,
+        index: usize,
+        reader: &mut R,
+        buffer: &'a mut Vec<u8>,
+    ) -> Result<Sample<'a>> {
+        let info = self
+            .samples
+            .get(index)
+            .ok_or_else(|| anyhow!("No sample {} in a table of {}", index, self.len()))?;
+
+        reader.seek(SeekFrom::Start(info.offset))?;
+        buffer.clear();
+        reader.take(info.size as u64).read_to_end(buffer)?;
+        if buffer.len() != info.size as usize {
+            return Err(outside_file(info));
+        }
+        Ok(self.sample(info, buffer))
+    }
+
+    fn sample<'a>(&self, info: &SampleInfo, bytes: &'a [u8]) -> Sample<'a> {
+        Sample {
+            dts: self.timing.presentation_time(info.dts as i64),
+            pts: self.timing.presentation_time(info.pts),
+            is_keyframe: info.is_keyframe,
+            bytes,
+        }
+    }
+}
+
+fn outside_file(info: &SampleInfo) -> anyhow::Error {
+    anyhow!(
+        "Sample at offset {} with size {} lies outside the file",
+        info.offset,
+        info.size
+    )
+}
+
+/// The top level boxes of a file read from a stream, without the media data. The boxes
+/// describing the movie are kept in memory, the movie fragments only by position so they
+/// can be read one at a time.
+#[derive(Clone, Debug, Default)]
+pub struct FileIndex {
+    /// The top level boxes other than `mdat`, `moof` and free space, `ftyp` and `moov`
+    /// among them, to be parsed like a whole file
+    pub header: Vec<u8>,
+    /// Offset and size of the `moof` boxes
+    pub fragments: Vec<(u64, u64)>,
+}
+
+impl FileIndex {
+    /// Walks the top level boxes of `reader` from the start, seeking over the media data.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let mut index = Self::default();
+        let end = reader.seek(SeekFrom::End(0))?;
+        let mut offset = reader.seek(SeekFrom::Start(0))?;
+
+        while offset < end {
+            let remaining = end - offset;
+            let mut header = [0; 16];
+            reader
+                .read_exact(&mut header[..8])
+                .map_err(|_| anyhow!("Truncated box header of {} bytes", remaining))?;
+            let box_type: [u8; 4] = header[4..8].try_into().unwrap();
+            let (header_size, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+                // Up to the end of the file
+                0 => (8, remaining),
+                // 64 bit largesize
+                1 => {
+                    reader.read_exact(&mut header[8..16])?;
+                    (16, u64::from_be_bytes(header[8..16].try_into().unwrap()))
+                }
+                size => (8, size as u64),
+            };
+            if size < header_size as u64 || size > remaining {
+                return Err(anyhow!(
+                    "Box '{}' of {} bytes does not fit the remaining {} bytes",
+                    String::from_utf8_lossy(&box_type),
+                    size,
+                    remaining
+                ));
+            }
+
+            match &box_type {
+                b"moof" => index.fragments.push((offset, size)),
+                b"mdat" | b"free" | b"skip" | b"wide" => {}
+                _ => {
+                    index.header.extend_from_slice(&header[..header_size]);
+                    reader
+                        .by_ref()
+                        .take(size - header_size as u64)
+                        .read_to_end(&mut index.header)?;
+                }
+            }
+
+            offset = reader.seek(SeekFrom::Start(offset + size))?;
+        }
+
+        Ok(index)
+    }
+
+    /// Reads the `moof` box at `offset` with `size` bytes, one of `fragments`, into
+    /// `buffer`. Returns its payload.
+    pub fn read_fragment<'a, R: Read + Seek>(
+        reader: &mut R,
+        (offset, size): (u64, u64),
+        buffer: &'a mut Vec<u8>,
+    ) -> Result<&'a [u8]> {
+        reader.seek(SeekFrom::Start(offset))?;
+        buffer.clear();
+        reader.take(size).read_to_end(buffer)?;
+        match Boxes::new(buffer).next() {
+            Some(Ok((_, moof))) => Ok(moof),
+            Some(Err(err)) => Err(err),
+            None => Err(anyhow!("Missing moof at offset {}", offset)),
+        }
+    }
+}
+
+/// Iterates over the boxes in `data`, yielding their type and payload.
+pub struct Boxes<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Boxes<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    /// Offset of the next box from the start of `data`.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Result<([u8; 4], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let remaining = self.data.len();
+        if remaining < 8 {
+            self.data = &[];
+            return Some(Err(anyhow!("Truncated box header of {} bytes", remaining)));
+        }
+
+        let box_type = [self.data[4], self.data[5], self.data[6], self.data[7]];
+        let (header_size, size) = match u32::from_be_bytes(self.data[..4].try_into().unwrap()) {
+            // Up to the end of the file
+            0 => (8, remaining as u64),
+            // 64 bit largesize
+            1 => (
+                16,
+                self.data
+                    .get(8..16)
+                    .map_or(0, |size| u64::from_be_bytes(size.try_into().unwrap())),
+            ),
+            size => (8, size as u64),
+        };
+
+        if size < header_size as u64 || size > remaining as u64 {
+            self.data = &[];
+            return Some(Err(anyhow!(
+                "Box '{}' of {} bytes does not fit the remaining {} bytes",
+                String::from_utf8_lossy(&box_type),
+                size,
+                remaining
+            )));
+        }
+
+        let (current, rest) = self.data.split_at(size as usize);
+        self.data = rest;
+        self.offset += size as usize;
+        Some(Ok((box_type, &current[header_size..])))
+    }
+}
+
+/// The payload of the first box of type `box_type` in `data`.
+fn child_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Result<&'a [u8]> {
+    for child in Boxes::new(data) {
+        let (child_type, payload) = child?;
+        if &child_type == box_type {
+            return Ok(payload);
+        }
+    }
+    Err(anyhow!(
+        "Missing '{}' box",
+        String::from_utf8_lossy(box_type)
+    ))
+}
+
+/// sample_is_non_sync_sample of the sample flags (ISO/IEC 14496-12 8.8.3.1)
+const SAMPLE_IS_NON_SYNC_SAMPLE: u32 = 0x1_0000;
+
+/// Upper bound for the sample_count of a `trun` without per sample fields
+const MAX_TRUN_SAMPLES: usize = 1 << 20;
+
+/// Reads the fields of a full box front to back, bounds checked.
+struct FullBoxReader<'a> {
+    box_type: &'static str,
+    data: &'a [u8],
+    version: u8,
+    flags: u32,
+}
+
+impl<'a> FullBoxReader<'a> {
+    fn new(box_type: &'static str, data: &'a [u8]) -> Result<Self> {
+        let header = data
+            .get(..4)
+            .ok_or_else(|| anyhow!("Truncated '{}' box", box_type))?;
+        Ok(Self {
+            box_type,
+            data: &data[4..],
+            version: header[0],
+            flags: u32::from_be_bytes([0, header[1], header[2], header[3]]),
+        })
+    }
+
+    fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.data.len() < N {
+            return Err(anyhow!("Truncated '{}' box", self.box_type));
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes()?))
+    }
+
+    /// The field if `flag` is set.
+    fn optional_u32(&mut self, flag: u32) -> Result<Option<u32>> {
+        self.has_flag(flag).then(|| self.u32()).transpose()
+    }
+}
+
+/// Sample defaults of a track in fragmented files, the `trex` box in `mvex`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrackExtends {
+    pub default_sample_description_index: u32,
+    pub default_sample_duration: u32,
+    pub default_sample_size: u32,
+    pub default_sample_flags: u32,
+}
+
+impl TrackExtends {
+    /// The defaults of the track with `track_id` in `data`, the whole file, all zero when
+    /// the movie has none.
+    pub fn find(data: &[u8], track_id: u32) -> Result<Self> {
+        let moov = child_box(data, b"moov")?;
+        let mvex = match child_box(moov, b"mvex") {
+            Ok(mvex) => mvex,
+            Err(_) => return Ok(Self::default()),
+        };
+        for trex in Boxes::new(mvex) {
+            let trex = match trex? {
+                (box_type, trex) if &box_type == b"trex" => trex,
+                _ => continue,
+            };
+            let mut reader = FullBoxReader::new("trex", trex)?;
+            if reader.u32()? != track_id {
+                continue;
+            }
+            return Ok(Self {
+                default_sample_description_index: reader.u32()?,
+                default_sample_duration: reader.u32()?,
+                default_sample_size: reader.u32()?,
+                default_sample_flags: reader.u32()?,
+            });
+        }
+        Ok(Self::default())
+    }
+}
+
+/// Track fragment header, `tfhd`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackFragmentHeader {
+    pub track_id: u32,
+    pub base_data_offset: Option<u64>,
+    pub sample_description_index: Option<u32>,
+    pub default_sample_duration: Option<u32>,
+    pub default_sample_size: Option<u32>,
+    pub default_sample_flags: Option<u32>,
+    pub default_base_is_moof: bool,
+}
+
+impl TrackFragmentHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = FullBoxReader::new("tfhd", data)?;
+        Ok(Self {
+            track_id: reader.u32()?,
+            base_data_offset: reader.has_flag(0x01).then(|| reader.u64()).transpose()?,
+            sample_description_index: reader.optional_u32(0x02)?,
+            default_sample_duration: reader.optional_u32(0x08)?,
+            default_sample_size: reader.optional_u32(0x10)?,
+            default_sample_flags: reader.optional_u32(0x20)?,
+            default_base_is_moof: reader.has_flag(0x02_0000),
+        })
+    }
+}
+
+/// baseMediaDecodeTime of a `tfdt` box.
+fn parse_tfdt(data: &[u8]) -> Result<u64> {
+    let mut reader = FullBoxReader::new("tfdt", data)?;
+    if reader.version == 1 {
+        reader.u64()
+    } else {
+        Ok(reader.u32()? as u64)
+    }
+}
+
+/// A sample of a `trun`, fields missing from the run are `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackRunSample {
+    pub duration: Option<u32>,
+    pub size: Option<u32>,
+    pub flags: Option<u32>,
+    pub composition_offset: i64,
+}
+
+/// Track fragment run, `trun`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackRun {
+    /// Relative to the base data offset of the track fragment
+    pub data_offset: Option<i32>,
+    pub first_sample_flags: Option<u32>,
+    pub samples: Vec<TrackRunSample>,
+}
+
+impl TrackRun {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = FullBoxReader::new("trun", data)?;
+        let sample_count = reader.u32()? as usize;
+        let data_offset = reader.optional_u32(0x01)?.map(|offset| offset as i32);
+        let first_sample_flags = reader.optional_u32(0x04)?;
+
+        // Bound what a corrupt sample_count can allocate by the bytes left, or for runs
+        // taking everything from the defaults by a generous limit
+        let sample_fields_size = [0x100, 0x200, 0x400, 0x800]
+            .iter()
+            .filter(|&&flag| reader.has_flag(flag))
+            .count()
+            * 4;
+        let fits = if sample_fields_size == 0 {
+            sample_count <= MAX_TRUN_SAMPLES
+        } else {
+            sample_count
+                .checked_mul(sample_fields_size)
+                .is_some_and(|size| size <= reader.data.len())
+        };
+        if !fits {
+            return Err(anyhow!("Truncated trun of {} samples", sample_count));
+        }
+
+        let mut samples = Vec::with_capacity(sample_count);
+        for _ in 0..sample_count {
+            samples.push(TrackRunSample {
+                duration: reader.optional_u32(0x100)?,
+                size: reader.optional_u32(0x200)?,
+                flags: reader.optional_u32(0x400)?,
+                composition_offset: match reader.optional_u32(0x800)? {
+                    Some(offset) if reader.version == 0 => offset as i64,
+                    Some(offset) => offset as i32 as i64,
+                    None => 0,
+                },
+            });
+        }
+
+        Ok(Self {
+            data_offset,
+            first_sample_flags,
+            samples,
+        })
+    }
+}
+
+/// The first sample entry of a video track, read straight from the file for the codecs
+/// mp4parse leaves as [`mp4parse::SampleEntry::Unknown`], such as `hvc1` and `hev1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VideoSampleEntry<'a> {
+    /// The sample entry type, e.g. `hvc1`
+    pub format: [u8; 4],
+    pub width: u16,
+    pub height: u16,
+    /// The child boxes following the VisualSampleEntry fields
+    pub boxes: &'a [u8],
+}
+
+impl<'a> VideoSampleEntry<'a> {
+    /// Finds the entry of the track with `track_id` (`tkhd`) in `data`, the whole file.
+    pub fn find(data: &'a [u8], track_id: u32) -> Result<Self> {
+        let moov = child_box(data, b"moov")?;
+        for trak in Boxes::new(moov) {
+            let trak = match trak? {
+                (box_type, trak) if &box_type == b"trak" => trak,
+                _ => continue,
+            };
+
+            // track_ID follows the full box header and the creation and modification
+            // times, 64 bit in version 1
+            let tkhd = child_box(trak, b"tkhd")?;
+            let offset = if tkhd.first() == Some(&1) { 20 } else { 12 };
+            if tkhd
+                .get(offset..offset + 4)
+                .map(|id| u32::from_be_bytes(id.try_into().unwrap()))
+                != Some(track_id)
+            {
+                continue;
+            }
+
+            let mdia = child_box(trak, b"mdia")?;
+            let minf = child_box(mdia, b"minf")?;
+            let stbl = child_box(minf, b"stbl")?;
+            let stsd = child_box(stbl, b"stsd")?;
+            // Full box header and entry_count
+            let entries = stsd.get(8..).ok_or_else(|| anyhow!("Truncated stsd"))?;
+            let (format, entry) = Boxes::new(entries)
+                .next()
+                .ok_or_else(|| anyhow!("No sample entry in stsd"))??;
+
+            // SampleEntry and VisualSampleEntry fields, ISO/IEC 14496-12 12.1.3
+            if entry.len() < 78 {
+                return Err(anyhow!(
+                    "Truncated '{}' sample entry",
+                    String::from_utf8_lossy(&format)
+                ));
+            }
+            return Ok(Self {
+                format,
+                width: u16::from_be_bytes([entry[24], entry[25]]),
+                height: u16::from_be_bytes([entry[26], entry[27]]),
+                boxes: &entry[78..],
+            });
+        }
+
+        Err(anyhow!("No track with ID {}", track_id))
+    }
+
+    /// The payload of the child box of type `box_type`, e.g. `hvcC`.
+    pub fn child(&self, box_type: &[u8; 4]) -> Option<&'a [u8]> {
+        child_box(self.boxes, box_type).ok()
+    }
+}
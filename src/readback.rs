@@ -0,0 +1,198 @@
+use std::slice;
+
+use anyhow::{anyhow, Result};
+use ash::{vk, Device};
+
+use crate::decoder::{DecodedFrame, Decoder};
+use crate::find_memorytype_index;
+use crate::yuv::Nv12Frame;
+
+/// Copies decoded frames into host memory, through a queue that supports transfers.
+/// Video decode queues usually can not copy images themselves.
+pub struct FrameReader {
+    device: Device,
+    queue_family_index: u32,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    ptr: *const u8,
+    extent: vk::Extent2D,
+}
+
+impl FrameReader {
+    /// Creates a reader for NV12 frames of `extent`, the coded size of the decoder.
+    ///
+    /// # Safety
+    ///
+    /// `queue` must come from `queue_family_index` of `device`, and the device must
+    /// outlive the reader.
+    pub unsafe fn new(
+        device: &Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let size = extent.width as u64 * extent.height as u64 * 3 / 2;
+        let buffer_info = vk::BufferCreateInfo {
+            size,
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let buffer = device.create_buffer(&buffer_info, None)?;
+
+        let memory_req = device.get_buffer_memory_requirements(buffer);
+        let memory_index = find_memorytype_index(
+            &memory_req,
+            device_memory_properties,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+        .ok_or_else(|| anyhow!("Unable to find suitable memorytype for the readback buffer"))?;
+
+        let allocate_info = vk::MemoryAllocateInfo {
+            allocation_size: memory_req.size,
+            memory_type_index: memory_index,
+            ..Default::default()
+        };
+        let memory = device.allocate_memory(&allocate_info, None)?;
+        device.bind_buffer_memory(buffer, memory, 0)?;
+        let ptr = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *const u8;
+
+        let pool_create_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(queue_family_index);
+        let command_pool = device.create_command_pool(&pool_create_info, None)?;
+
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_buffer_count(1)
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let command_buffer = device.allocate_command_buffers(&command_buffer_allocate_info)?[0];
+
+        Ok(Self {
+            device: device.clone(),
+            queue_family_index,
+            queue,
+            command_pool,
+            command_buffer,
+            buffer,
+            memory,
+            ptr,
+            extent,
+        })
+    }
+
+    /// Copies the coded area of `frame`, as returned by `decoder`, into host memory.
+    ///
+    /// # Safety
+    ///
+    /// `decoder` must use the same device as the reader.
+    pub unsafe fn read(
+        &mut self,
+        decoder: &mut Decoder,
+        frame: &DecodedFrame,
+    ) -> Result<Nv12Frame> {
+        if frame.format != vk::Format::G8_B8R8_2PLANE_420_UNORM {
+            return Err(anyhow!("Can not read back frames in {:?}", frame.format));
+        }
+        if frame.extent != self.extent {
+            return Err(anyhow!(
+                "Frame of {}x{} does not fit the readback buffer for {}x{}",
+                frame.extent.width,
+                frame.extent.height,
+                self.extent.width,
+                self.extent.height
+            ));
+        }
+
+        let vk::Extent2D { width, height } = self.extent;
+        let plane = |aspect_mask, buffer_offset, width, height| vk::BufferImageCopy {
+            buffer_offset,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask,
+                mip_level: 0,
+                base_array_layer: frame.array_layer,
+                layer_count: 1,
+            },
+            image_extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            ..Default::default()
+        };
+        let regions = [
+            plane(vk::ImageAspectFlags::PLANE_0, 0, width, height),
+            plane(
+                vk::ImageAspectFlags::PLANE_1,
+                width as u64 * height as u64,
+                width / 2,
+                height / 2,
+            ),
+        ];
+        let buffer = self.buffer;
+        let buffer_barrier = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+
+        decoder.use_frame(
+            frame,
+            self.queue_family_index,
+            self.queue,
+            self.command_buffer,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| {
+                device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    frame.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer,
+                    &regions,
+                );
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[buffer_barrier],
+                    &[],
+                );
+            },
+        )?;
+
+        let luma_size = width as usize * height as usize;
+        let data = slice::from_raw_parts(self.ptr, luma_size * 3 / 2);
+        Ok(Nv12Frame {
+            width,
+            height,
+            y: data[..luma_size].to_vec(),
+            uv: data[luma_size..].to_vec(),
+        })
+    }
+}
+
+impl Drop for FrameReader {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.queue_wait_idle(self.queue).ok();
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device.unmap_memory(self.memory);
+            self.device.destroy_buffer(self.buffer, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
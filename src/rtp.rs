@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::{anyhow, Result};
+
+use crate::timestamp::Timestamp;
+
+/// H.264 RTP timestamps run at 90 kHz (RFC 6184 8.2.1)
+pub const CLOCK_RATE: u32 = 90_000;
+
+const RTP_VERSION: u8 = 2;
+
+/// NAL unit types of the RTP payload format beyond those of H.264 (RFC 6184 5.2)
+const STAP_A: u8 = 24;
+const FU_A: u8 = 28;
+
+const NAL_UNIT_TYPE_IDR_SLICE: u8 = 5;
+
+/// An RTP packet (RFC 3550 5.1), with the CSRC list, header extension and padding
+/// stripped from the payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtpPacket<'a> {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload: &'a [u8],
+}
+
+impl<'a> RtpPacket<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < 12 {
+            return Err(anyhow!("Truncated RTP header of {} bytes", data.len()));
+        }
+        let version = data[0] >> 6;
+        if version != RTP_VERSION {
+            return Err(anyhow!("Unsupported RTP version {}", version));
+        }
+        let padding = data[0] & 0x20 != 0;
+        let extension = data[0] & 0x10 != 0;
+        let csrc_count = (data[0] & 0xf) as usize;
+
+        let mut payload_start = 12 + 4 * csrc_count;
+        if extension {
+            let length = data
+                .get(payload_start + 2..payload_start + 4)
+                .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+                .ok_or_else(|| anyhow!("Truncated RTP header extension"))?;
+            payload_start += 4 + 4 * length;
+        }
+        let mut payload_end = data.len();
+        if padding {
+            let padding_length = data[data.len() - 1] as usize;
+            payload_end = payload_end
+                .checked_sub(padding_length)
+                .ok_or_else(|| anyhow!("RTP padding of {} bytes", padding_length))?;
+        }
+
+        let payload = data
+            .get(payload_start..payload_end)
+            .ok_or_else(|| anyhow!("RTP packet of {} bytes without a payload", data.len()))?;
+        Ok(Self {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7f,
+            sequence_number: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            ssrc: u32::from_be_bytes(data[8..12].try_into().unwrap()),
+            payload,
+        })
+    }
+}
+
+/// A packet held by the [`JitterBuffer`], with its sequence number extended so it does
+/// not wrap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub sequence_number: u64,
+    pub timestamp: u32,
+    pub marker: bool,
+    pub payload: Vec<u8>,
+}
+
+/// Puts RTP packets back into sequence order. A packet is released as soon as all packets
+/// before it are; once more than `capacity` packets wait behind a gap the missing ones are
+/// given up on. Late and duplicate packets are dropped.
+#[derive(Clone, Debug)]
+pub struct JitterBuffer {
+    capacity: usize,
+    packets: BTreeMap<u64, Packet>,
+    /// Extended sequence number of the next packet to release
+    next: Option<u64>,
+    highest: Option<u64>,
+    ended: bool,
+    /// Packets given up on
+    pub lost_packets: u64,
+}
+
+impl JitterBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            packets: BTreeMap::new(),
+            next: None,
+            highest: None,
+            ended: false,
+            lost_packets: 0,
+        }
+    }
+
+    /// Extends `sequence_number` to the value closest to the highest one so far. The
+    /// first packet starts at 2^32 so that reordered packets before it stay positive.
+    fn extend(&self, sequence_number: u16) -> u64 {
+        match self.highest {
+            Some(highest) => {
+                let delta = sequence_number.wrapping_sub(highest as u16) as i16;
+                highest.wrapping_add_signed(delta as i64)
+            }
+            None => (1 << 32) | sequence_number as u64,
+        }
+    }
+
+    pub fn push(&mut self, packet: &RtpPacket) {
+        let sequence_number = self.extend(packet.sequence_number);
+        if self.next.is_some_and(|next| sequence_number < next)
+            || self.packets.contains_key(&sequence_number)
+        {
+            return;
+        }
+
+        self.next.get_or_insert(sequence_number);
+        self.highest = Some(
+            self.highest
+                .map_or(sequence_number, |highest| highest.max(sequence_number)),
+        );
+        self.packets.insert(
+            sequence_number,
+            Packet {
+                sequence_number,
+                timestamp: packet.timestamp,
+                marker: packet.marker,
+                payload: packet.payload.to_vec(),
+            },
+        );
+    }
+
+    /// Releases the packets still waiting regardless of gaps, at the end of the stream.
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+
+    /// The next packet in sequence order, and whether packets were lost before it.
+    pub fn pop(&mut self) -> Option<(Packet, bool)> {
+        let next = self.next?;
+        let first = *self.packets.keys().next()?;
+        if first != next && self.packets.len() <= self.capacity && !self.ended {
+            return None;
+        }
+
+        let packet = self.packets.remove(&first)?;
+        let lost = first - next;
+        self.lost_packets += lost;
+        self.next = Some(first + 1);
+        Some((packet, lost > 0))
+    }
+}
+
+/// Reassembles H.264 access units from RTP payloads in sequence order, for single NAL
+/// unit packets, STAP-A and FU-A of the non-interleaved mode of RFC 6184. The access units
+/// come out in Annex-B format, timed from the first packet.
+///
+/// An access unit that lost packets is dropped, and so is everything up to the next IDR
+/// picture since the pictures in between may reference the lost one. The stream also
+/// starts at an IDR picture.
+#[derive(Clone, Debug)]
+pub struct H264Depacketizer {
+    access_unit: Vec<u8>,
+    /// Extended RTP timestamp of the access unit being reassembled
+    timestamp: Option<i64>,
+    first_timestamp: Option<i64>,
+    previous_timestamp: Option<i64>,
+    /// Inside a NAL unit fragmented with FU-A
+    in_fragment: bool,
+    has_idr: bool,
+    damaged: bool,
+    waiting_for_idr: bool,
+    ready: VecDeque<(Vec<u8>, Timestamp)>,
+    /// Access units dropped for lost packets or while waiting for an IDR picture
+    pub dropped_access_units: usize,
+}
+
+impl Default for H264Depacketizer {
+    fn default() -> Self {
+        Self {
+            access_unit: Vec::new(),
+            timestamp: None,
+            first_timestamp: None,
+            previous_timestamp: None,
+            in_fragment: false,
+            has_idr: false,
+            damaged: false,
+            waiting_for_idr: true,
+            ready: VecDeque::new(),
+            dropped_access_units: 0,
+        }
+    }
+}
+
+impl H264Depacketizer {
+    /// Adds the next packet in sequence order, `lost` when packets went missing before it.
+    /// Packets of one access unit share a timestamp, the last one has the marker bit set.
+    pub fn push(&mut self, packet: &Packet, lost: bool) {
+        // 32 bit RTP timestamps wrap after 13 hours at 90 kHz
+        let timestamp = match self.previous_timestamp {
+            Some(previous) => {
+                previous + packet.timestamp.wrapping_sub(previous as u32) as i32 as i64
+            }
+            None => packet.timestamp as i64,
+        };
+        self.previous_timestamp = Some(timestamp);
+
+        // The lost packets may have belonged to either access unit
+        if lost {
+            self.damaged = true;
+        }
+        if self.timestamp.is_some_and(|current| current != timestamp) {
+            self.finish_access_unit();
+            self.damaged = lost;
+        }
+        self.timestamp = Some(timestamp);
+
+        if self.add_payload(&packet.payload).is_err() {
+            self.damaged = true;
+        }
+        if packet.marker {
+            self.finish_access_unit();
+        }
+    }
+
+    /// Completes the access unit being reassembled, at the end of the stream.
+    pub fn flush(&mut self) {
+        self.finish_access_unit();
+    }
+
+    /// The next reassembled access unit with its presentation time.
+    pub fn pop(&mut self) -> Option<(Vec<u8>, Timestamp)> {
+        self.ready.pop_front()
+    }
+
+    fn add_nal(&mut self, nal: &[u8]) -> Result<()> {
+        let header = nal.first().ok_or_else(|| anyhow!("Empty NAL unit"))?;
+        if header & 0x1f == NAL_UNIT_TYPE_IDR_SLICE {
+            self.has_idr = true;
+        }
+        self.access_unit.extend_from_slice(&[0, 0, 1]);
+        self.access_unit.extend_from_slice(nal);
+        Ok(())
+    }
+
+    fn add_payload(&mut self, payload: &[u8]) -> Result<()> {
+        let header = *payload
+            .first()
+            .ok_or_else(|| anyhow!("Empty RTP payload"))?;
+        let nal_unit_type = header & 0x1f;
+        if nal_unit_type != FU_A && self.in_fragment {
+            self.in_fragment = false;
+            return Err(anyhow!("FU-A fragments without an end"));
+        }
+
+        match nal_unit_type {
+            1..=23 => self.add_nal(payload),
+            STAP_A => {
+                let mut rest = &payload[1..];
+                while !rest.is_empty() {
+                    let size = rest
+                        .get(..2)
+                        .map(|size| u16::from_be_bytes([size[0], size[1]]) as usize)
+                        .ok_or_else(|| anyhow!("Truncated STAP-A NAL unit size"))?;
+                    let nal = rest
+                        .get(2..2 + size)
+                        .ok_or_else(|| anyhow!("STAP-A NAL unit of {} bytes", size))?;
+                    self.add_nal(nal)?;
+                    rest = &rest[2 + size..];
+                }
+                Ok(())
+            }
+            FU_A => {
+                let fu_header = *payload
+                    .get(1)
+                    .ok_or_else(|| anyhow!("Truncated FU-A header"))?;
+                let start = fu_header & 0x80 != 0;
+                let end = fu_header & 0x40 != 0;
+                if start == self.in_fragment {
+                    self.in_fragment = false;
+                    return Err(anyhow!("FU-A fragment out of order"));
+                }
+                if start {
+                    // The NAL unit header is rebuilt from the FU indicator and header
+                    self.add_nal(&[header & 0xe0 | fu_header & 0x1f])?;
+                }
+                self.access_unit.extend_from_slice(&payload[2..]);
+                self.in_fragment = !end;
+                Ok(())
+            }
+            _ => Err(anyhow!(
+                "Unsupported NAL unit type {} in an RTP payload",
+                nal_unit_type
+            )),
+        }
+    }
+
+    fn finish_access_unit(&mut self) {
+        let access_unit = std::mem::take(&mut self.access_unit);
+        let timestamp = self.timestamp.take();
+        let damaged = self.damaged || self.in_fragment;
+        let has_idr = self.has_idr;
+        self.damaged = false;
+        self.in_fragment = false;
+        self.has_idr = false;
+
+        let timestamp = match timestamp {
+            Some(timestamp) if !access_unit.is_empty() => timestamp,
+            _ => return,
+        };
+        if damaged {
+            self.waiting_for_idr = true;
+        } else if has_idr {
+            self.waiting_for_idr = false;
+        }
+        if damaged || self.waiting_for_idr {
+            self.dropped_access_units += 1;
+            return;
+        }
+
+        let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+        self.ready.push_back((
+            access_unit,
+            Timestamp::new(timestamp - first_timestamp, CLOCK_RATE),
+        ));
+    }
+}
@@ -0,0 +1,474 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::h264;
+use crate::rtp::{H264Depacketizer, JitterBuffer, RtpPacket};
+use crate::timestamp::Timestamp;
+
+const DEFAULT_PORT: u16 = 554;
+
+/// A camera that stops sending for this long is treated as gone
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Session timeout when the server does not give one (RFC 2326 12.37)
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Packets held back to wait for a missing one. Over TCP they arrive in order, so this only
+/// bounds the delay for servers that drop packets before sending them.
+const JITTER_BUFFER_PACKETS: usize = 64;
+
+/// The host and port of an `rtsp://host[:port][/path]` URL.
+fn parse_url(url: &str) -> Result<(String, u16)> {
+    let rest = url
+        .strip_prefix("rtsp://")
+        .ok_or_else(|| anyhow!("Not an rtsp:// URL: {}", url))?;
+    let authority = rest.split('/').next().unwrap_or_default();
+    if authority.contains('@') {
+        return Err(anyhow!("RTSP authentication is not supported"));
+    }
+
+    // IPv6 addresses are enclosed in brackets
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, port) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow!("Invalid RTSP URL {}", url))?;
+            (host, port.strip_prefix(':'))
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return Err(anyhow!("RTSP URL without a host: {}", url));
+    }
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| anyhow!("Invalid port in RTSP URL {}", url))?,
+        None => DEFAULT_PORT,
+    };
+    Ok((host.to_string(), port))
+}
+
+/// Resolves an SDP `a=control` attribute against the base URL (RFC 2326 C.1.1).
+fn resolve_control(base: &str, control: Option<&str>) -> String {
+    match control {
+        None | Some("*") => base.to_string(),
+        Some(control) if control.starts_with("rtsp://") => control.to_string(),
+        Some(control) => format!("{}/{}", base.trim_end_matches('/'), control),
+    }
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(anyhow!("Invalid base64 {:?}", text)),
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            data.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(data)
+}
+
+/// A video stream of a session description, before picking the H.264 one
+struct Media {
+    control: Option<String>,
+    payload_types: Vec<u8>,
+    /// Attributes other than `control`, as name and value
+    attributes: Vec<(String, String)>,
+}
+
+/// The H.264 video stream of a session description (RFC 4566, RFC 6184 8.2).
+#[derive(Clone, Debug)]
+pub struct SessionDescription {
+    /// Session level `a=control`
+    pub control: Option<String>,
+    /// Media level `a=control` of the video stream
+    pub media_control: Option<String>,
+    pub payload_type: u8,
+    /// From `sprop-parameter-sets`, empty when the server leaves them to the stream
+    pub parameter_sets: h264::ParameterSets,
+}
+
+impl SessionDescription {
+    /// Parses the first H.264 video stream of `sdp`.
+    pub fn parse(sdp: &str) -> Result<Self> {
+        let mut control = None;
+        let mut media: Vec<Media> = Vec::new();
+        let mut in_session = true;
+        let mut in_video = false;
+
+        for line in sdp.lines() {
+            let (kind, value) = match line.trim_end().split_once('=') {
+                Some(line) => line,
+                None => continue,
+            };
+            match kind {
+                "m" => {
+                    in_session = false;
+                    let mut fields = value.split_whitespace();
+                    in_video = fields.next() == Some("video");
+                    if in_video {
+                        let payload_types = fields.skip(2).filter_map(|pt| pt.parse().ok());
+                        media.push(Media {
+                            control: None,
+                            payload_types: payload_types.collect(),
+                            attributes: Vec::new(),
+                        });
+                    }
+                }
+                "a" => {
+                    let (name, value) = value.split_once(':').unwrap_or((value, ""));
+                    if in_session && name == "control" {
+                        control = Some(value.to_string());
+                    } else if let Some(media) = media.last_mut().filter(|_| in_video) {
+                        if name == "control" {
+                            media.control = Some(value.to_string());
+                        } else {
+                            media.attributes.push((name.to_string(), value.to_string()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for Media {
+            control: media_control,
+            payload_types,
+            attributes,
+        } in media
+        {
+            // `a=rtpmap:96 H264/90000` and `a=fmtp:96 <parameters>`
+            let attribute = |name: &str, payload_type: u8| {
+                attributes.iter().find_map(|(n, value)| {
+                    let (pt, value) = value.split_once(' ')?;
+                    (n == name && pt.parse() == Ok(payload_type)).then_some(value.trim())
+                })
+            };
+            let payload_type = match payload_types.into_iter().find(|&pt| {
+                attribute("rtpmap", pt)
+                    .is_some_and(|encoding| encoding.to_ascii_uppercase().starts_with("H264/"))
+            }) {
+                Some(payload_type) => payload_type,
+                None => continue,
+            };
+
+            let mut parameter_sets = h264::ParameterSets::default();
+            let fmtp = attribute("fmtp", payload_type).unwrap_or_default();
+            for parameter in fmtp.split(';') {
+                match parameter.trim().split_once('=') {
+                    Some(("packetization-mode", mode)) if mode.trim() == "2" => {
+                        return Err(anyhow!("Interleaved H.264 packetization is not supported"));
+                    }
+                    Some(("sprop-parameter-sets", sets)) => {
+                        for set in sets.split(',').filter(|set| !set.is_empty()) {
+                            parameter_sets
+                                .add_nal(&decode_base64(set)?)
+                                .context("Invalid sprop-parameter-sets")?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            return Ok(Self {
+                control,
+                media_control,
+                payload_type,
+                parameter_sets,
+            });
+        }
+        Err(anyhow!("No H.264 video stream in the session description"))
+    }
+}
+
+struct Response {
+    status: u16,
+    status_line: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The H.264 video of an RTSP server as Annex-B access units with their presentation time,
+/// starting at an IDR picture. RTP is interleaved on the RTSP connection (RFC 2326 10.12)
+/// so it gets through the same firewalls and NATs.
+///
+/// Access units that lost packets are dropped up to the next IDR picture, the stream ends
+/// when the server closes the connection.
+pub struct RtspStream {
+    reader: BufReader<TcpStream>,
+    url: String,
+    cseq: u32,
+    session: Option<String>,
+    session_timeout: Duration,
+    last_request: Instant,
+    rtp_channel: u8,
+    pub description: SessionDescription,
+    jitter_buffer: JitterBuffer,
+    depacketizer: H264Depacketizer,
+    ended: bool,
+    buffer: Vec<u8>,
+}
+
+impl RtspStream {
+    /// Sets up and plays the first H.264 video stream of `url`.
+    pub fn connect(url: &str) -> Result<Self> {
+        let (host, port) = parse_url(url)?;
+        let stream = TcpStream::connect((host.as_str(), port))
+            .with_context(|| format!("Failed to connect to {}", url))?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let mut rtsp = Self {
+            reader: BufReader::new(stream),
+            url: url.to_string(),
+            cseq: 0,
+            session: None,
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+            last_request: Instant::now(),
+            rtp_channel: 0,
+            description: SessionDescription {
+                control: None,
+                media_control: None,
+                payload_type: 0,
+                parameter_sets: h264::ParameterSets::default(),
+            },
+            jitter_buffer: JitterBuffer::new(JITTER_BUFFER_PACKETS),
+            depacketizer: H264Depacketizer::default(),
+            ended: false,
+            buffer: Vec::new(),
+        };
+
+        let response = rtsp.request("DESCRIBE", url, &[("Accept", "application/sdp")])?;
+        let base = response
+            .header("Content-Base")
+            .or_else(|| response.header("Content-Location"))
+            .unwrap_or(url)
+            .to_string();
+        rtsp.description = SessionDescription::parse(&String::from_utf8_lossy(&response.body))?;
+
+        let setup_url = resolve_control(&base, rtsp.description.media_control.as_deref());
+        let response = rtsp.request(
+            "SETUP",
+            &setup_url,
+            &[("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1")],
+        )?;
+        // The server may pick other channels
+        let transport = response.header("Transport").unwrap_or_default();
+        rtsp.rtp_channel = transport
+            .split(';')
+            .find_map(|parameter| parameter.trim().strip_prefix("interleaved="))
+            .and_then(|channels| channels.split('-').next()?.parse().ok())
+            .ok_or_else(|| anyhow!("RTSP server refused RTP over TCP: {:?}", transport))?;
+        let session = response
+            .header("Session")
+            .ok_or_else(|| anyhow!("RTSP SETUP response without a session"))?;
+        let mut session = session.split(';');
+        rtsp.session = session.next().map(|id| id.trim().to_string());
+        if let Some(timeout) = session
+            .find_map(|parameter| parameter.trim().strip_prefix("timeout="))
+            .and_then(|timeout| timeout.parse().ok())
+        {
+            rtsp.session_timeout = Duration::from_secs(timeout);
+        }
+
+        rtsp.url = resolve_control(&base, rtsp.description.control.as_deref());
+        let play_url = rtsp.url.clone();
+        rtsp.request("PLAY", &play_url, &[("Range", "npt=0.000-")])?;
+        Ok(rtsp)
+    }
+
+    fn send(&mut self, method: &str, url: &str, headers: &[(&str, &str)]) -> Result<()> {
+        self.cseq += 1;
+        let mut request = format!("{} {} RTSP/1.0\r\nCSeq: {}\r\n", method, url, self.cseq);
+        if let Some(session) = &self.session {
+            request += &format!("Session: {}\r\n", session);
+        }
+        for (name, value) in headers {
+            request += &format!("{}: {}\r\n", name, value);
+        }
+        request += "\r\n";
+        self.reader.get_ref().write_all(request.as_bytes())?;
+        self.last_request = Instant::now();
+        Ok(())
+    }
+
+    fn request(&mut self, method: &str, url: &str, headers: &[(&str, &str)]) -> Result<Response> {
+        self.send(method, url, headers)?;
+        let response = self.read_response(None)?;
+        match response.status {
+            200..=299 => Ok(response),
+            401 => Err(anyhow!(
+                "RTSP server requires authentication, which is not supported"
+            )),
+            _ => Err(anyhow!("RTSP {} failed: {}", method, response.status_line)),
+        }
+    }
+
+    /// Reads a response, `first` being its first byte when already read.
+    fn read_response(&mut self, first: Option<u8>) -> Result<Response> {
+        let mut status_line = String::new();
+        self.reader.read_line(&mut status_line)?;
+        if let Some(first) = first {
+            status_line.insert(0, first as char);
+        }
+        let status_line = status_line.trim_end().to_string();
+        let status = match status_line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["RTSP/1.0", status, ..] => status.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("Invalid RTSP response {:?}", status_line))?;
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(anyhow!("RTSP connection closed in a response"));
+            }
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => headers.push((name.to_string(), value.trim().to_string())),
+                None => break,
+            }
+        }
+
+        let mut response = Response {
+            status,
+            status_line,
+            headers,
+            body: Vec::new(),
+        };
+        let length = response
+            .header("Content-Length")
+            .map_or(Ok(0), str::parse::<u64>)?;
+        // Read as the data arrives rather than trusting the size with an allocation
+        let read = self
+            .reader
+            .by_ref()
+            .take(length)
+            .read_to_end(&mut response.body)?;
+        if (read as u64) < length {
+            return Err(anyhow!(
+                "RTSP connection closed after {} of {} bytes of a response",
+                read,
+                length
+            ));
+        }
+        Ok(response)
+    }
+
+    /// Reads the next RTP packet into the jitter buffer, `false` when the server closed the
+    /// connection.
+    fn read_packet(&mut self) -> Result<bool> {
+        loop {
+            // Servers without RTCP from us count the session as alive on requests
+            if self.last_request.elapsed() > self.session_timeout / 2 {
+                let url = self.url.clone();
+                self.send("GET_PARAMETER", &url, &[])?;
+            }
+
+            let mut first = [0];
+            match self.reader.read(&mut first) {
+                Ok(0) => return Ok(false),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err).context("No data from the RTSP server"),
+            }
+            // Responses to the keepalives come between the interleaved frames
+            if first[0] != b'$' {
+                self.read_response(Some(first[0]))?;
+                continue;
+            }
+
+            let mut header = [0; 3];
+            self.reader.read_exact(&mut header)?;
+            self.buffer
+                .resize(u16::from_be_bytes([header[1], header[2]]) as usize, 0);
+            self.reader.read_exact(&mut self.buffer)?;
+            // The other channel carries RTCP
+            if header[0] != self.rtp_channel {
+                continue;
+            }
+            // A malformed packet is dropped, the jitter buffer counts it as lost like one
+            // that never arrived
+            let Ok(packet) = RtpPacket::parse(&self.buffer) else {
+                continue;
+            };
+            if packet.payload_type == self.description.payload_type {
+                self.jitter_buffer.push(&packet);
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Feeds the packets released by the jitter buffer to the depacketizer.
+    fn depacketize(&mut self) {
+        while let Some((packet, lost)) = self.jitter_buffer.pop() {
+            self.depacketizer.push(&packet, lost);
+        }
+    }
+}
+
+impl Iterator for RtspStream {
+    type Item = Result<(Vec<u8>, Timestamp)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.depacketize();
+            if let Some(access_unit) = self.depacketizer.pop() {
+                return Some(Ok(access_unit));
+            }
+            if self.ended {
+                return None;
+            }
+            match self.read_packet() {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.ended = true;
+                    self.jitter_buffer.end();
+                    self.depacketize();
+                    self.depacketizer.flush();
+                }
+                Err(err) => {
+                    self.ended = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RtspStream {
+    fn drop(&mut self) {
+        // Best effort, the server also ends the session when the connection closes
+        if !self.ended && self.session.is_some() {
+            let url = self.url.clone();
+            let _ = self.send("TEARDOWN", &url, &[]);
+        }
+    }
+}
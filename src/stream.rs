@@ -0,0 +1,732 @@
+use std::collections::VecDeque;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use anyhow::{anyhow, Result};
+
+use crate::demux::{self, ContainerFormat};
+use crate::timestamp::Timestamp;
+use crate::{annexb, av1, codec, h264, h265, hls, http, mkv, mp4, rtsp, ts};
+
+/// Bytes read from the start of a file to guess its format
+const PROBE_SIZE: u64 = 4096;
+/// Bytes read from a raw elementary stream at a time
+pub const READ_SIZE: u64 = 4096;
+
+/// Access units with their presentation time, read as they are consumed.
+pub type AccessUnits<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Timestamp)>> + 'a>;
+
+/// The video track of a file opened with [`open`].
+pub struct VideoStream<'a> {
+    pub format: ContainerFormat,
+    pub parameter_sets: codec::ParameterSets,
+    /// Size from the container, from the SPS for streams without one
+    pub width: u16,
+    pub height: u16,
+    /// Annex-B access units for H.264 and H.265, temporal units of OBUs for AV1
+    pub access_units: AccessUnits<'a>,
+}
+
+/// Opens the first AV1, H.264 or H.265 video track of `reader`, picking the demuxer from
+/// the content rather than the file extension.
+///
+/// MP4 and Matroska files are read a sample or a cluster at a time, transport streams a
+/// TS packet at a time and raw elementary streams in [`READ_SIZE`] pieces, so memory use
+/// does not grow with the length of the file.
+pub fn open<'a, R: Read + Seek + 'a>(mut reader: R) -> Result<VideoStream<'a>> {
+    let mut head = Vec::new();
+    reader.seek(SeekFrom::Start(0))?;
+    reader.by_ref().take(PROBE_SIZE).read_to_end(&mut head)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    match demux::probe(&head) {
+        Some(ContainerFormat::Mp4) => open_mp4(reader),
+        Some(ContainerFormat::Matroska) => open_matroska(reader),
+        Some(format @ (ContainerFormat::MpegTs | ContainerFormat::AnnexB)) => {
+            open_elementary_stream(format, reader)
+        }
+        Some(ContainerFormat::Rtp) | None => Err(anyhow!("Unrecognized file format")),
+    }
+}
+
+/// Plays the H.264 video of an `rtsp://` URL. The parameter sets come from the session
+/// description or, when the server leaves them out of it, from the first access units.
+pub fn open_rtsp(url: &str) -> Result<VideoStream<'static>> {
+    let mut stream = rtsp::RtspStream::connect(url)?;
+    let mut parameter_sets = stream.description.parameter_sets.clone();
+
+    let mut buffered = Vec::new();
+    while parameter_sets.sps_count() == 0 || parameter_sets.pps_count() == 0 {
+        let (access_unit, pts) = stream
+            .next()
+            .ok_or_else(|| anyhow!("RTSP stream ended before its parameter sets"))??;
+        for nal in h264::NalUnits::new(&access_unit) {
+            parameter_sets.add_nal(nal.data)?;
+        }
+        buffered.push((access_unit, pts));
+    }
+
+    let parameter_sets = codec::ParameterSets::from(parameter_sets);
+    let (width, height) = parameter_sets.stream_info()?.coded_extent;
+    Ok(VideoStream {
+        format: ContainerFormat::Rtp,
+        parameter_sets,
+        width: width as u16,
+        height: height as u16,
+        access_units: Box::new(buffered.into_iter().map(Ok).chain(stream)),
+    })
+}
+
+/// Parses the decoder configuration record of an MP4 sample entry or of a Matroska
+/// CodecPrivate, which are the same. Returns the parameter sets and, for H.264 and H.265,
+/// the NAL unit length size of the samples.
+fn parse_configuration(
+    codec: codec::Codec,
+    record: &[u8],
+) -> Result<(codec::ParameterSets, Option<usize>)> {
+    Ok(match codec {
+        codec::Codec::H264 => {
+            let config = h264::avcc::AvcDecoderConfiguration::parse(record)?;
+            (config.parameter_sets()?.into(), Some(config.length_size))
+        }
+        codec::Codec::H265 => {
+            let config = h265::hvcc::HevcDecoderConfiguration::parse(record)?;
+            (config.parameter_sets()?.into(), Some(config.length_size))
+        }
+        // AV1 samples are OBUs as they are, without a length size
+        codec::Codec::Av1 => {
+            let config = av1::av1c::Av1CodecConfiguration::parse(record)?;
+            (config.sequence_header()?.into(), None)
+        }
+    })
+}
+
+/// The first AV1, H.264 or H.265 video track of an MP4 file or init segment.
+struct Mp4Track {
+    track_id: Option<u32>,
+    width: u16,
+    height: u16,
+    parameter_sets: codec::ParameterSets,
+    length_size: Option<usize>,
+    sample_table: mp4::SampleTable,
+}
+
+/// Finds the video track in `header`, the boxes of the file other than the media data.
+fn mp4_video_track(header: &[u8]) -> Result<Mp4Track> {
+    let context = mp4parse::read_mp4(&mut Cursor::new(header))?;
+
+    for track in &context.tracks {
+        if track.track_type != mp4parse::TrackType::Video {
+            continue;
+        }
+        let stsd = track
+            .stsd
+            .as_ref()
+            .ok_or_else(|| anyhow!("Video track without an stsd"))?;
+
+        // mp4parse leaves the HEVC sample entries to us
+        let (width, height, (parameter_sets, length_size)) = match stsd
+            .descriptions
+            .first()
+            .ok_or_else(|| anyhow!("Video track without a sample entry"))?
+        {
+            mp4parse::SampleEntry::Video(v) => match &v.codec_specific {
+                mp4parse::VideoCodecSpecific::AVCConfig(avcc) => (
+                    v.width,
+                    v.height,
+                    parse_configuration(codec::Codec::H264, avcc)?,
+                ),
+                mp4parse::VideoCodecSpecific::AV1Config(av1c) => (
+                    v.width,
+                    v.height,
+                    parse_configuration(codec::Codec::Av1, &av1c.raw_config)?,
+                ),
+                _ => continue,
+            },
+            mp4parse::SampleEntry::Unknown => {
+                let track_id = track
+                    .track_id
+                    .ok_or_else(|| anyhow!("Video track without an ID"))?;
+                let entry = mp4::VideoSampleEntry::find(header, track_id)?;
+                if !matches!(&entry.format, b"hvc1" | b"hev1") {
+                    continue;
+                }
+                let hvcc = entry
+                    .child(b"hvcC")
+                    .ok_or_else(|| anyhow!("Missing hvcC box"))?;
+                (
+                    entry.width,
+                    entry.height,
+                    parse_configuration(codec::Codec::H265, hvcc)?,
+                )
+            }
+            _ => return Err(anyhow!("Video track without a video sample entry")),
+        };
+
+        return Ok(Mp4Track {
+            track_id: track.track_id,
+            width,
+            height,
+            parameter_sets,
+            length_size,
+            sample_table: mp4::SampleTable::new(track, context.timescale)?,
+        });
+    }
+
+    Err(anyhow!("No AV1, H.264 or H.265 video track"))
+}
+
+fn open_mp4<'a, R: Read + Seek + 'a>(mut reader: R) -> Result<VideoStream<'a>> {
+    let index = mp4::FileIndex::read(&mut reader)?;
+    let mut track = mp4_video_track(&index.header)?;
+
+    // Fragmented files keep their samples in moof boxes, after an empty sample table
+    if let Some(track_id) = track.track_id {
+        let mut buffer = Vec::new();
+        for &fragment in &index.fragments {
+            let moof = mp4::FileIndex::read_fragment(&mut reader, fragment, &mut buffer)?;
+            track
+                .sample_table
+                .add_fragment(&index.header, moof, fragment.0, track_id)?;
+        }
+    }
+
+    Ok(VideoStream {
+        format: ContainerFormat::Mp4,
+        parameter_sets: track.parameter_sets,
+        width: track.width,
+        height: track.height,
+        access_units: Box::new(Mp4Samples {
+            reader,
+            sample_table: track.sample_table,
+            length_size: track.length_size,
+            next: 0,
+            buffer: Vec::new(),
+        }),
+    })
+}
+
+/// The samples of an MP4 track, read from the file one at a time into a reused buffer.
+struct Mp4Samples<R> {
+    reader: R,
+    sample_table: mp4::SampleTable,
+    length_size: Option<usize>,
+    next: usize,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read + Seek> Mp4Samples<R> {
+    fn read(&mut self, index: usize) -> Result<(Vec<u8>, Timestamp)> {
+        let sample = self
+            .sample_table
+            .read_sample(index, &mut self.reader, &mut self.buffer)?;
+        let access_unit = match self.length_size {
+            Some(length_size) => sample.to_annexb(length_size)?,
+            None => sample.bytes.to_vec(),
+        };
+        Ok((access_unit, sample.pts))
+    }
+}
+
+impl<R: Read + Seek> Iterator for Mp4Samples<R> {
+    type Item = Result<(Vec<u8>, Timestamp)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.sample_table.len() {
+            return None;
+        }
+        let access_unit = self.read(self.next);
+        // Stop after the first error like the other demuxers
+        self.next = match access_unit {
+            Ok(_) => self.next + 1,
+            Err(_) => self.sample_table.len(),
+        };
+        Some(access_unit)
+    }
+}
+
+fn open_matroska<'a, R: Read + Seek + 'a>(reader: R) -> Result<VideoStream<'a>> {
+    let reader = mkv::MatroskaReader::new(reader)?;
+    let matroska = mkv::Matroska::parse(reader.header())?;
+
+    // CodecPrivate holds the same decoder configuration record as the MP4 sample entry,
+    // and the frames are laid out as MP4 samples
+    let (track, codec) = matroska
+        .tracks
+        .iter()
+        .filter(|track| track.track_type == mkv::TRACK_TYPE_VIDEO)
+        .find_map(|track| match track.codec_id.as_str() {
+            "V_MPEG4/ISO/AVC" => Some((track, codec::Codec::H264)),
+            "V_MPEGH/ISO/HEVC" => Some((track, codec::Codec::H265)),
+            "V_AV1" => Some((track, codec::Codec::Av1)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("No AV1, H.264 or H.265 video track"))?;
+    let (parameter_sets, length_size) = parse_configuration(codec, track.codec_private)?;
+
+    Ok(VideoStream {
+        format: ContainerFormat::Matroska,
+        parameter_sets,
+        width: track.width,
+        height: track.height,
+        access_units: Box::new(MatroskaFrames {
+            timestamp_scale: matroska.timestamp_scale,
+            track_number: track.number,
+            default_duration: track.default_duration,
+            length_size,
+            reader,
+            cluster: Vec::new(),
+            pending: VecDeque::new(),
+            done: false,
+        }),
+    })
+}
+
+/// The frames of a Matroska track, demuxed one cluster at a time.
+struct MatroskaFrames<R> {
+    timestamp_scale: u64,
+    track_number: u64,
+    default_duration: Option<u64>,
+    length_size: Option<usize>,
+    reader: mkv::MatroskaReader<R>,
+    cluster: Vec<u8>,
+    pending: VecDeque<(Vec<u8>, Timestamp)>,
+    done: bool,
+}
+
+impl<R: Read + Seek> MatroskaFrames<R> {
+    /// Queues the frames of the next cluster, `false` at the end of the file.
+    fn read_cluster(&mut self) -> Result<bool> {
+        if !self.reader.read_cluster(&mut self.cluster)? {
+            return Ok(false);
+        }
+        for frame in mkv::Frames::from_cluster(
+            &self.cluster,
+            self.timestamp_scale,
+            self.track_number,
+            self.default_duration,
+        )? {
+            let frame = frame?;
+            let access_unit = match self.length_size {
+                Some(length_size) => frame.to_annexb(length_size)?,
+                None => frame.bytes.to_vec(),
+            };
+            self.pending.push_back((access_unit, frame.pts));
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> Iterator for MatroskaFrames<R> {
+    type Item = Result<(Vec<u8>, Timestamp)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(access_unit) = self.pending.pop_front() {
+                return Some(Ok(access_unit));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_cluster() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    self.pending.clear();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Access units of an elementary stream, with their PTS where the container has one.
+type ElementaryAccessUnits<'a> =
+    Box<dyn Iterator<Item = Result<(Vec<u8>, Option<Timestamp>)>> + 'a>;
+
+fn open_elementary_stream<'a, R: Read + Seek + 'a>(
+    format: ContainerFormat,
+    reader: R,
+) -> Result<VideoStream<'a>> {
+    let (stream_type, mut access_units): (_, ElementaryAccessUnits<'a>) = match format {
+        ContainerFormat::MpegTs => {
+            let pes_reader = ts::PesReader::new(reader)?;
+            let stream_type = pes_reader.stream_type;
+            let splitter =
+                (stream_type == ts::VideoStreamType::H264).then(annexb::AnnexBSplitter::default);
+            let access_units = TsAccessUnits {
+                pes_reader,
+                splitter,
+                packets: VecDeque::new(),
+                size: 0,
+                pending_pts: None,
+                decodable: false,
+                end_of_stream: false,
+                done: false,
+            };
+            (stream_type, Box::new(access_units))
+        }
+        _ => {
+            let access_units = AnnexBAccessUnits {
+                reader,
+                splitter: annexb::AnnexBSplitter::default(),
+                buffer: Vec::new(),
+                end_of_stream: false,
+                done: false,
+            };
+            (ts::VideoStreamType::H264, Box::new(access_units))
+        }
+    };
+
+    // The parameter sets come in band, with the access unit decoding starts at
+    let first = access_units
+        .next()
+        .ok_or_else(|| anyhow!("No parameter sets in the elementary stream"))??;
+    let parameter_sets = stream_type.parameter_sets(&first.0)?;
+    let stream_info = parameter_sets.stream_info()?;
+
+    // For the access units that come without a PTS, all of them in raw streams
+    let frame_duration = stream_info.frame_duration.unwrap_or(Timestamp::new(1, 25));
+    let mut previous_pts = None;
+    let access_units = std::iter::once(Ok(first))
+        .chain(access_units)
+        .map(move |access_unit| {
+            let (data, pts) = access_unit?;
+            let pts = pts.unwrap_or_else(|| {
+                previous_pts.map_or(Timestamp::ZERO, |pts: Timestamp| pts + frame_duration)
+            });
+            previous_pts = Some(pts);
+            Ok((data, pts))
+        });
+
+    let (width, height) = stream_info.coded_extent;
+    Ok(VideoStream {
+        format,
+        parameter_sets,
+        width: width as u16,
+        height: height as u16,
+        access_units: Box::new(access_units),
+    })
+}
+
+/// The access units of a raw H.264 stream, split off the data as it is read.
+struct AnnexBAccessUnits<R> {
+    reader: R,
+    splitter: annexb::AnnexBSplitter,
+    buffer: Vec<u8>,
+    end_of_stream: bool,
+    done: bool,
+}
+
+impl<R: Read> AnnexBAccessUnits<R> {
+    /// Pushes the next [`READ_SIZE`] bytes to the splitter, `false` at the end of the
+    /// stream.
+    fn read(&mut self) -> Result<bool> {
+        self.buffer.clear();
+        self.reader
+            .by_ref()
+            .take(READ_SIZE)
+            .read_to_end(&mut self.buffer)?;
+        self.splitter.push(&self.buffer);
+        Ok(!self.buffer.is_empty())
+    }
+}
+
+impl<R: Read> Iterator for AnnexBAccessUnits<R> {
+    type Item = Result<(Vec<u8>, Option<Timestamp>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let result = match self.splitter.next_access_unit(self.end_of_stream) {
+                Some(access_unit) => access_unit.map(|(_, access_unit)| Some(access_unit)),
+                None if self.end_of_stream => {
+                    self.done = true;
+                    Ok(None)
+                }
+                None => self.read().map(|more| {
+                    self.end_of_stream = !more;
+                    None
+                }),
+            };
+            // Stop after the first error like the other demuxers
+            match result {
+                Ok(Some(access_unit)) => return Some(Ok((access_unit, None))),
+                Ok(None) => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The access units of a transport stream, demuxed a PES packet at a time the way
+/// [`ts::ElementaryStream::access_units`] splits them.
+struct TsAccessUnits<R> {
+    pes_reader: ts::PesReader<R>,
+    /// H.264 PES packets are split into access units again, H.265 ones are one each
+    splitter: Option<annexb::AnnexBSplitter>,
+    /// Offset in the elementary stream and PTS of the PES packets pushed to `splitter`
+    /// that no access unit has started in yet
+    packets: VecDeque<(usize, Option<Timestamp>)>,
+    /// Bytes pushed to `splitter`
+    size: usize,
+    pending_pts: Option<Timestamp>,
+    /// Whether a PES packet with parameter sets was read, captures seldom start at one
+    decodable: bool,
+    end_of_stream: bool,
+    done: bool,
+}
+
+impl<R: Read + Seek> TsAccessUnits<R> {
+    /// The next access unit, `None` when another PES packet is needed for it.
+    fn split(&mut self) -> Result<Option<(Vec<u8>, Option<Timestamp>)>> {
+        if let Some(splitter) = &mut self.splitter {
+            if let Some(access_unit) = splitter.next_access_unit(self.end_of_stream) {
+                let (offset, access_unit) = access_unit?;
+                while let Some(&(packet_offset, pts)) = self.packets.front() {
+                    if packet_offset > offset {
+                        break;
+                    }
+                    self.pending_pts = pts.or(self.pending_pts);
+                    self.packets.pop_front();
+                }
+                return Ok(Some((access_unit, self.pending_pts.take())));
+            }
+        }
+        if self.end_of_stream {
+            self.done = true;
+            return Ok(None);
+        }
+
+        let pes = match self.pes_reader.next() {
+            Some(pes) => pes?,
+            None => {
+                self.end_of_stream = true;
+                return Ok(None);
+            }
+        };
+        if !self.decodable {
+            if !self.pes_reader.stream_type.has_parameter_sets(&pes.payload) {
+                return Ok(None);
+            }
+            self.decodable = true;
+        }
+        match &mut self.splitter {
+            Some(splitter) => {
+                self.packets.push_back((self.size, pes.pts));
+                self.size += pes.payload.len();
+                splitter.push(&pes.payload);
+            }
+            None => {
+                self.pending_pts = pes.pts.or(self.pending_pts);
+                if !pes.payload.is_empty() {
+                    return Ok(Some((pes.payload, self.pending_pts.take())));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read + Seek> Iterator for TsAccessUnits<R> {
+    type Item = Result<(Vec<u8>, Option<Timestamp>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.split() {
+                Ok(Some(access_unit)) => return Some(Ok(access_unit)),
+                Ok(None) => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Plays an HLS stream from the `http://` URL of a master or media playlist, picking the
+/// variant of a master playlist with [`hls::select_variant`]. Transport stream and
+/// fragmented MP4 segments are fetched as the access units are consumed and demuxed one
+/// at a time; `format` is that of the segments.
+pub fn open_hls(
+    url: &str,
+    max_bandwidth: Option<u64>,
+    max_coded_extent: impl FnMut(codec::Codec) -> Result<(u32, u32)>,
+) -> Result<VideoStream<'static>> {
+    let fetch_playlist = |url: &str| -> Result<hls::Playlist> {
+        hls::Playlist::parse(&String::from_utf8_lossy(&http::get(url, None)?))
+    };
+    let mut url = url.to_string();
+    let mut playlist = fetch_playlist(&url)?;
+    if let hls::Playlist::Master(variants) = &playlist {
+        let variant = hls::select_variant(variants, max_bandwidth, max_coded_extent)?;
+        url = http::resolve(&url, &variant.uri);
+        playlist = fetch_playlist(&url)?;
+    }
+    let playlist = match playlist {
+        hls::Playlist::Media(playlist) => playlist,
+        hls::Playlist::Master(_) => {
+            return Err(anyhow!("Variant stream {} is a master playlist", url))
+        }
+    };
+
+    let mut fetcher = hls::SegmentFetcher::new(&url, playlist);
+    let first = fetcher
+        .next_segment()?
+        .ok_or_else(|| anyhow!("HLS playlist without segments"))?;
+
+    let (format, segment_format, parameter_sets, width, height) = match fetcher.init_section() {
+        Some(init) => {
+            let track = mp4_video_track(init)?;
+            let segment_format = HlsSegmentFormat::Mp4 {
+                track_id: track
+                    .track_id
+                    .ok_or_else(|| anyhow!("Video track without an ID"))?,
+                length_size: track.length_size,
+                sample_table: track.sample_table,
+            };
+            let (width, height) = (track.width, track.height);
+            (
+                ContainerFormat::Mp4,
+                segment_format,
+                track.parameter_sets,
+                width,
+                height,
+            )
+        }
+        None if demux::probe(&first) == Some(ContainerFormat::MpegTs) => {
+            let parameter_sets = ts::demux(&first)?.parameter_sets()?;
+            let stream_info = parameter_sets.stream_info()?;
+            let frame_duration = stream_info.frame_duration.unwrap_or(Timestamp::new(1, 25));
+            let (width, height) = stream_info.coded_extent;
+            (
+                ContainerFormat::MpegTs,
+                HlsSegmentFormat::MpegTs { frame_duration },
+                parameter_sets,
+                width as u16,
+                height as u16,
+            )
+        }
+        None => return Err(anyhow!("HLS segments are neither TS nor fragmented MP4")),
+    };
+
+    let mut access_units = HlsAccessUnits {
+        fetcher,
+        format: segment_format,
+        pending: VecDeque::new(),
+        done: false,
+    };
+    access_units.demux(&first)?;
+    Ok(VideoStream {
+        format,
+        parameter_sets,
+        width,
+        height,
+        access_units: Box::new(access_units),
+    })
+}
+
+enum HlsSegmentFormat {
+    MpegTs {
+        /// For the access units without a PTS
+        frame_duration: Timestamp,
+    },
+    Mp4 {
+        track_id: u32,
+        length_size: Option<usize>,
+        /// The samples of the current segment, after the empty table of the init section
+        sample_table: mp4::SampleTable,
+    },
+}
+
+/// The access units of an HLS stream, fetched and demuxed a segment at a time.
+struct HlsAccessUnits {
+    fetcher: hls::SegmentFetcher,
+    format: HlsSegmentFormat,
+    pending: VecDeque<(Vec<u8>, Timestamp)>,
+    done: bool,
+}
+
+impl HlsAccessUnits {
+    /// Queues the access units of `segment`.
+    fn demux(&mut self, segment: &[u8]) -> Result<()> {
+        match &mut self.format {
+            HlsSegmentFormat::MpegTs { frame_duration } => {
+                let stream = ts::demux(segment)?;
+                self.pending.extend(stream.access_units(*frame_duration)?);
+            }
+            HlsSegmentFormat::Mp4 {
+                track_id,
+                length_size,
+                sample_table,
+            } => {
+                let header = self
+                    .fetcher
+                    .init_section()
+                    .ok_or_else(|| anyhow!("Fragmented MP4 segment without EXT-X-MAP"))?;
+                // Decode times continue from the previous segment when it has no tfdt
+                sample_table.samples.clear();
+                let mut boxes = mp4::Boxes::new(segment);
+                loop {
+                    let moof_offset = boxes.offset() as u64;
+                    match boxes.next().transpose()? {
+                        Some((box_type, moof)) if &box_type == b"moof" => {
+                            sample_table.add_fragment(header, moof, moof_offset, *track_id)?
+                        }
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+                for sample in sample_table.iter(segment) {
+                    let sample = sample?;
+                    let access_unit = match length_size {
+                        Some(length_size) => sample.to_annexb(*length_size)?,
+                        None => sample.bytes.to_vec(),
+                    };
+                    self.pending.push_back((access_unit, sample.pts));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues the access units of the next segment, `false` at the end of the playlist.
+    fn read_segment(&mut self) -> Result<bool> {
+        match self.fetcher.next_segment()? {
+            Some(segment) => {
+                self.demux(&segment)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Iterator for HlsAccessUnits {
+    type Item = Result<(Vec<u8>, Timestamp)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(access_unit) = self.pending.pop_front() {
+                return Some(Ok(access_unit));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_segment() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    self.pending.clear();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Neg, Sub};
+use std::time::Duration;
+
+pub const NANOS_PER_SECOND: u32 = 1_000_000_000;
+
+/// A point in time or a duration of `value / timescale` seconds. Containers pick their
+/// own timescales (1000, 600, 90 kHz, the sample rate, ...), so values are kept exact
+/// and only converted when they are compared, combined or shown.
+#[derive(Clone, Copy, Debug)]
+pub struct Timestamp {
+    pub value: i64,
+    pub timescale: u32,
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Rounds `value * to / from` to the nearest integer, ties away from zero.
+fn rescale_value(value: i64, from: u32, to: u32) -> i64 {
+    let numerator = value as i128 * to as i128;
+    let half = from as i128 / 2;
+    let rounded = if numerator < 0 {
+        (numerator - half) / from as i128
+    } else {
+        (numerator + half) / from as i128
+    };
+    rounded.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+impl Timestamp {
+    pub const ZERO: Self = Self {
+        value: 0,
+        timescale: 1,
+    };
+
+    pub fn new(value: i64, timescale: u32) -> Self {
+        assert!(timescale != 0, "timescale must not be zero");
+        Self { value, timescale }
+    }
+
+    pub fn from_nanos(nanos: i64) -> Self {
+        Self::new(nanos, NANOS_PER_SECOND)
+    }
+
+    /// Converts to another timescale, rounding to the nearest unit.
+    pub fn rescale(self, timescale: u32) -> Self {
+        Self::new(
+            rescale_value(self.value, self.timescale, timescale),
+            timescale,
+        )
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        self.value as f64 / self.timescale as f64
+    }
+
+    pub fn as_nanos(self) -> i64 {
+        rescale_value(self.value, self.timescale, NANOS_PER_SECOND)
+    }
+
+    /// The timestamp as a `Duration`, or `None` when it is negative.
+    pub fn as_duration(self) -> Option<Duration> {
+        u64::try_from(self.as_nanos())
+            .ok()
+            .map(Duration::from_nanos)
+    }
+
+    /// The smallest timescale both operands can be represented in exactly, falling back
+    /// to nanoseconds when that does not fit.
+    fn common_timescale(self, other: Self) -> u32 {
+        let (a, b) = (self.timescale as u64, other.timescale as u64);
+        u32::try_from(a / gcd(a, b) * b).unwrap_or(NANOS_PER_SECOND)
+    }
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl From<Duration> for Timestamp {
+    fn from(duration: Duration) -> Self {
+        Self::from_nanos(duration.as_nanos().min(i64::MAX as u128) as i64)
+    }
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timestamp {}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.value as i128 * other.timescale as i128)
+            .cmp(&(other.value as i128 * self.timescale as i128))
+    }
+}
+
+impl Add for Timestamp {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let timescale = self.common_timescale(rhs);
+        Self::new(
+            self.rescale(timescale)
+                .value
+                .saturating_add(rhs.rescale(timescale).value),
+            timescale,
+        )
+    }
+}
+
+impl Sub for Timestamp {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl Neg for Timestamp {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(self.value.saturating_neg(), self.timescale)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.3}s", self.as_secs_f64())
+    }
+}
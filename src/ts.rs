@@ -0,0 +1,576 @@
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom};
+
+use anyhow::{anyhow, Result};
+
+use crate::annexb::AnnexBReader;
+use crate::codec;
+use crate::timestamp::Timestamp;
+use crate::{h264, h265};
+
+pub const PACKET_SIZE: usize = 188;
+/// Blu-ray and AVCHD `.m2ts` packets carry a 4 byte arrival timestamp in front
+pub const M2TS_PACKET_SIZE: usize = 192;
+pub const SYNC_BYTE: u8 = 0x47;
+
+const PAT_PID: u16 = 0;
+
+/// PTS and DTS are 33 bit values of a 90 kHz clock
+pub const CLOCK_RATE: u32 = 90_000;
+const TIMESTAMP_WRAP: i64 = 1 << 33;
+
+/// The video stream types of the PMT we decode (ISO/IEC 13818-1 Table 2-34).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoStreamType {
+    H264,
+    H265,
+}
+
+impl VideoStreamType {
+    pub fn from_stream_type(stream_type: u8) -> Option<Self> {
+        match stream_type {
+            0x1b => Some(VideoStreamType::H264),
+            0x24 => Some(VideoStreamType::H265),
+            _ => None,
+        }
+    }
+
+    /// Whether the Annex-B `data` has an SPS for H.264 or a VPS for H.265, where decoding
+    /// can start.
+    pub fn has_parameter_sets(self, data: &[u8]) -> bool {
+        h264::NalUnits::new(data).any(|nal| match self {
+            VideoStreamType::H264 => h264::NalUnitHeader::parse(nal.data)
+                .is_ok_and(|header| header.nal_unit_type == h264::NalUnitType::Sps),
+            VideoStreamType::H265 => h265::NalUnitHeader::parse(nal.data)
+                .is_ok_and(|header| header.nal_unit_type == h265::NalUnitType::Vps),
+        })
+    }
+
+    /// The parameter sets at the start of the Annex-B `data`.
+    pub fn parameter_sets(self, data: &[u8]) -> Result<codec::ParameterSets> {
+        match self {
+            VideoStreamType::H264 => Ok(AnnexBReader::read_parameter_sets(data)?.into()),
+            VideoStreamType::H265 => {
+                let mut parameter_sets = h265::ParameterSets::default();
+                for nal in h264::NalUnits::new(data) {
+                    parameter_sets.add_nal(nal.data)?;
+                    if parameter_sets.vps_count() > 0
+                        && parameter_sets.sps_count() > 0
+                        && parameter_sets.pps_count() > 0
+                    {
+                        break;
+                    }
+                }
+                Ok(parameter_sets.into())
+            }
+        }
+    }
+}
+
+/// Where a PES packet landed in [`ElementaryStream::data`], with its timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PesPacket {
+    pub offset: usize,
+    pub size: usize,
+    pub pts: Option<Timestamp>,
+    pub dts: Option<Timestamp>,
+    /// random_access_indicator of the adaptation field of the first TS packet
+    pub random_access: bool,
+}
+
+/// The payloads of the PES packets of one elementary stream, concatenated into the
+/// Annex-B byte stream they carry.
+#[derive(Clone, Debug)]
+pub struct ElementaryStream {
+    pub pid: u16,
+    pub stream_type: VideoStreamType,
+    pub data: Vec<u8>,
+    pub packets: Vec<PesPacket>,
+    /// PES packets dropped because TS packets went missing
+    pub lost_packets: usize,
+}
+
+/// A TS packet header with the adaptation field flags we use.
+#[derive(Clone, Copy, Debug)]
+struct PacketHeader {
+    payload_unit_start: bool,
+    pid: u16,
+    continuity_counter: u8,
+    has_payload: bool,
+    discontinuity: bool,
+    random_access: bool,
+}
+
+/// Splits a TS packet into its header and payload.
+fn parse_packet(packet: &[u8]) -> Result<(PacketHeader, &[u8])> {
+    if packet[0] != SYNC_BYTE {
+        return Err(anyhow!("Lost TS sync, found {:#04x}", packet[0]));
+    }
+
+    let adaptation_field_control = (packet[3] >> 4) & 0b11;
+    // Packets with the transport_error_indicator set are ignored, for PES packets the gap
+    // then shows in the continuity counter
+    let transport_error = packet[1] & 0x80 != 0;
+    let mut header = PacketHeader {
+        payload_unit_start: packet[1] & 0x40 != 0,
+        pid: u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff,
+        continuity_counter: packet[3] & 0xf,
+        has_payload: adaptation_field_control & 0b01 != 0 && !transport_error,
+        discontinuity: false,
+        random_access: false,
+    };
+
+    let mut payload_start = 4;
+    if adaptation_field_control & 0b10 != 0 {
+        let length = packet[4] as usize;
+        if 5 + length > PACKET_SIZE {
+            return Err(anyhow!("Adaptation field of {} bytes", length));
+        }
+        if length > 0 {
+            header.discontinuity = packet[5] & 0x80 != 0;
+            header.random_access = packet[5] & 0x40 != 0;
+        }
+        payload_start = 5 + length;
+    }
+
+    let payload = if header.has_payload {
+        &packet[payload_start..]
+    } else {
+        &[]
+    };
+    Ok((header, payload))
+}
+
+/// The size of the packets in `data`, checking the first few sync bytes.
+pub fn packet_size(data: &[u8]) -> Option<usize> {
+    [PACKET_SIZE, M2TS_PACKET_SIZE]
+        .into_iter()
+        .find(|&packet_size| {
+            let prefix = packet_size - PACKET_SIZE;
+            let count = (data.len() / packet_size).min(3);
+            count > 0 && (0..count).all(|index| data[index * packet_size + prefix] == SYNC_BYTE)
+        })
+}
+
+/// CRC-32/MPEG-2 of PSI sections, zero over a section including its CRC_32.
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0xffff_ffff, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u32) << 24, |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Reads the next TS packet into `packet`, `false` at the end of the stream. Only whole
+/// packets are read, a capture cut short loses its last partial packet.
+fn read_packet(reader: &mut impl Read, packet: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(packet) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Collects the first complete PSI section with `table_id` on `pid`, reading from the
+/// current position of `reader`.
+fn read_section(
+    reader: &mut impl Read,
+    packet_size: usize,
+    pid: u16,
+    table_id: u8,
+) -> Result<Vec<u8>> {
+    let mut packet = [0; M2TS_PACKET_SIZE];
+    let mut section = Vec::new();
+    let mut started = false;
+
+    while read_packet(reader, &mut packet[..packet_size])? {
+        let (header, payload) = parse_packet(&packet[packet_size - PACKET_SIZE..packet_size])?;
+        if header.pid != pid || payload.is_empty() {
+            continue;
+        }
+
+        if header.payload_unit_start {
+            // pointer_field, the bytes before it finish the previous section
+            let pointer = payload[0] as usize;
+            section.clear();
+            section.extend_from_slice(payload.get(1 + pointer..).unwrap_or(&[]));
+            started = true;
+        } else if started {
+            section.extend_from_slice(payload);
+        }
+
+        if started && section.len() >= 3 {
+            let length = 3 + (u16::from_be_bytes([section[1], section[2]]) & 0xfff) as usize;
+            if section.len() >= length {
+                section.truncate(length);
+                if section[0] == table_id && length >= 12 && crc32(&section) == 0 {
+                    return Ok(section);
+                }
+                // Another table on the PID or a corrupt section, wait for the next
+                started = false;
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "No section with table_id {:#04x} on PID {:#x}",
+        table_id,
+        pid
+    ))
+}
+
+/// Extends a 33 bit timestamp to the one closest to `previous`, so that it keeps counting
+/// when the clock wraps after about 26.5 hours.
+fn unwrap_timestamp(value: i64, previous: Option<i64>) -> i64 {
+    match previous {
+        Some(previous) => {
+            let base = previous - previous.rem_euclid(TIMESTAMP_WRAP);
+            [base - TIMESTAMP_WRAP, base, base + TIMESTAMP_WRAP]
+                .into_iter()
+                .map(|base| base + value)
+                .min_by_key(|candidate| (candidate - previous).abs())
+                .unwrap()
+        }
+        None => value,
+    }
+}
+
+/// PTS or DTS field, five bytes with marker bits.
+fn read_timestamp(bytes: &[u8]) -> i64 {
+    ((bytes[0] as i64 >> 1) & 0b111) << 30
+        | (bytes[1] as i64) << 22
+        | (bytes[2] as i64 >> 1) << 15
+        | (bytes[3] as i64) << 7
+        | bytes[4] as i64 >> 1
+}
+
+/// The payload and the PTS and DTS of a complete PES packet (ISO/IEC 13818-1 2.4.3.6).
+fn parse_pes(pes: &[u8]) -> Result<(&[u8], Option<i64>, Option<i64>)> {
+    if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+        return Err(anyhow!("Invalid PES packet start"));
+    }
+
+    let stream_id = pes[3];
+    // Video PES packets may leave the length unbounded
+    let end = match u16::from_be_bytes([pes[4], pes[5]]) as usize {
+        0 => pes.len(),
+        length => (6 + length).min(pes.len()),
+    };
+    if !(0xe0..=0xef).contains(&stream_id) {
+        return Err(anyhow!(
+            "PES stream_id {:#04x} is not a video stream",
+            stream_id
+        ));
+    }
+
+    let pts_dts_flags = pes[7] >> 6;
+    let payload_start = 9 + pes[8] as usize;
+    if payload_start > end {
+        return Err(anyhow!("PES header of {} bytes", payload_start));
+    }
+
+    let field = |offset: usize| pes.get(offset..offset + 5).map(read_timestamp);
+    let pts = if pts_dts_flags & 0b10 != 0 {
+        field(9)
+    } else {
+        None
+    };
+    let dts = if pts_dts_flags == 0b11 {
+        field(14)
+    } else {
+        None
+    };
+    Ok((&pes[payload_start..end], pts, dts))
+}
+
+/// A complete PES packet of the video stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pes {
+    pub payload: Vec<u8>,
+    pub pts: Option<Timestamp>,
+    pub dts: Option<Timestamp>,
+    /// random_access_indicator of the adaptation field of the first TS packet
+    pub random_access: bool,
+}
+
+/// Collects the payloads of the TS packets of one PID into PES packets.
+#[derive(Default)]
+struct PesAssembler {
+    pes: Vec<u8>,
+    random_access: bool,
+    // No PES is collected before the first payload_unit_start
+    collecting: bool,
+    continuity_counter: Option<u8>,
+    previous_pts: Option<i64>,
+    previous_dts: Option<i64>,
+    lost_packets: usize,
+}
+
+impl PesAssembler {
+    /// Takes the next TS packet of the PID, returns the PES packet it completes by starting
+    /// the next one.
+    fn push(&mut self, header: &PacketHeader, payload: &[u8]) -> Option<Pes> {
+        if let Some(previous) = self.continuity_counter {
+            let expected = (previous + 1) & 0xf;
+            if header.continuity_counter == previous && !header.discontinuity {
+                // A duplicate packet, sent at most once
+                return None;
+            }
+            if header.continuity_counter != expected && !header.discontinuity {
+                if self.collecting {
+                    self.lost_packets += 1;
+                }
+                self.pes.clear();
+                self.collecting = false;
+            }
+        }
+        self.continuity_counter = Some(header.continuity_counter);
+
+        let mut pes = None;
+        if header.payload_unit_start {
+            pes = self.finish();
+            self.random_access = header.random_access;
+            self.collecting = true;
+        }
+        if self.collecting {
+            self.pes.extend_from_slice(payload);
+        }
+        pes
+    }
+
+    /// The PES packet collected so far. A corrupt PES header counts as a lost packet rather
+    /// than failing the whole stream.
+    fn finish(&mut self) -> Option<Pes> {
+        if self.pes.is_empty() {
+            return None;
+        }
+        let parsed = parse_pes(&self.pes).map(|(payload, pts, dts)| (payload.to_vec(), pts, dts));
+        self.pes.clear();
+        let (payload, pts, dts) = match parsed {
+            Ok(pes) => pes,
+            Err(_) => {
+                self.lost_packets += 1;
+                return None;
+            }
+        };
+
+        let pts = pts.map(|pts| unwrap_timestamp(pts, self.previous_pts));
+        let dts = dts.map(|dts| unwrap_timestamp(dts, self.previous_dts.or(pts)));
+        self.previous_pts = pts.or(self.previous_pts);
+        self.previous_dts = dts.or(self.previous_dts);
+        Some(Pes {
+            payload,
+            pts: pts.map(|pts| Timestamp::new(pts, CLOCK_RATE)),
+            dts: dts.map(|dts| Timestamp::new(dts, CLOCK_RATE)),
+            random_access: self.random_access,
+        })
+    }
+}
+
+/// Reads the PES packets of the first H.264 or H.265 stream of the first program a TS
+/// packet at a time. A PES packet some of whose TS packets are missing, noticed by the
+/// continuity counter, is dropped unless the adaptation field signals the discontinuity.
+pub struct PesReader<R> {
+    reader: R,
+    packet_size: usize,
+    packet: [u8; M2TS_PACKET_SIZE],
+    pub pid: u16,
+    pub stream_type: VideoStreamType,
+    assembler: PesAssembler,
+    done: bool,
+}
+
+impl<R: Read + Seek> PesReader<R> {
+    /// Reads the PAT and the PMT of the first program from the current position of
+    /// `reader`, then returns to it for the PES packets.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let start = reader.stream_position()?;
+        let mut head = Vec::new();
+        reader
+            .by_ref()
+            .take(3 * M2TS_PACKET_SIZE as u64)
+            .read_to_end(&mut head)?;
+        let packet_size = packet_size(&head).ok_or_else(|| anyhow!("No TS sync byte"))?;
+
+        // Program association table, the first program other than the network PID
+        reader.seek(SeekFrom::Start(start))?;
+        let pat = read_section(&mut reader, packet_size, PAT_PID, 0x00)?;
+        let pmt_pid = pat[8..pat.len() - 4]
+            .chunks_exact(4)
+            .find(|program| u16::from_be_bytes([program[0], program[1]]) != 0)
+            .map(|program| u16::from_be_bytes([program[2], program[3]]) & 0x1fff)
+            .ok_or_else(|| anyhow!("No program in the PAT"))?;
+
+        // Program map table
+        reader.seek(SeekFrom::Start(start))?;
+        let pmt = read_section(&mut reader, packet_size, pmt_pid, 0x02)?;
+        let program_info_length = (u16::from_be_bytes([pmt[10], pmt[11]]) & 0xfff) as usize;
+        let mut streams = pmt
+            .get(12 + program_info_length..pmt.len() - 4)
+            .ok_or_else(|| anyhow!("Truncated PMT"))?;
+        let (pid, stream_type) = loop {
+            if streams.len() < 5 {
+                return Err(anyhow!("No H.264 or H.265 stream in the PMT"));
+            }
+            let es_info_length = (u16::from_be_bytes([streams[3], streams[4]]) & 0xfff) as usize;
+            let pid = u16::from_be_bytes([streams[1], streams[2]]) & 0x1fff;
+            if let Some(stream_type) = VideoStreamType::from_stream_type(streams[0]) {
+                break (pid, stream_type);
+            }
+            streams = streams.get(5 + es_info_length..).unwrap_or(&[]);
+        };
+
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            reader,
+            packet_size,
+            packet: [0; M2TS_PACKET_SIZE],
+            pid,
+            stream_type,
+            assembler: PesAssembler::default(),
+            done: false,
+        })
+    }
+
+    /// PES packets dropped so far because TS packets went missing.
+    pub fn lost_packets(&self) -> usize {
+        self.assembler.lost_packets
+    }
+
+    /// Reads TS packets up to the end of the next PES packet.
+    fn read_pes(&mut self) -> Result<Option<Pes>> {
+        while read_packet(&mut self.reader, &mut self.packet[..self.packet_size])? {
+            let packet = &self.packet[self.packet_size - PACKET_SIZE..self.packet_size];
+            let (header, payload) = parse_packet(packet)?;
+            if header.pid != self.pid || !header.has_payload {
+                continue;
+            }
+            if let Some(pes) = self.assembler.push(&header, payload) {
+                return Ok(Some(pes));
+            }
+        }
+        self.done = true;
+        Ok(self.assembler.finish())
+    }
+}
+
+impl<R: Read + Seek> Iterator for PesReader<R> {
+    type Item = Result<Pes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.read_pes() {
+                Ok(Some(pes)) => return Some(Ok(pes)),
+                Ok(None) => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Reads the PAT, the PMT of the first program and the first H.264 or H.265 stream of that
+/// program with a [`PesReader`].
+pub fn demux(data: &[u8]) -> Result<ElementaryStream> {
+    let mut reader = PesReader::new(Cursor::new(data))?;
+    let mut stream = ElementaryStream {
+        pid: reader.pid,
+        stream_type: reader.stream_type,
+        data: Vec::new(),
+        packets: Vec::new(),
+        lost_packets: 0,
+    };
+
+    for pes in reader.by_ref() {
+        let pes = pes?;
+        stream.packets.push(PesPacket {
+            offset: stream.data.len(),
+            size: pes.payload.len(),
+            pts: pes.pts,
+            dts: pes.dts,
+            random_access: pes.random_access,
+        });
+        stream.data.extend_from_slice(&pes.payload);
+    }
+    stream.lost_packets = reader.lost_packets();
+
+    Ok(stream)
+}
+
+impl ElementaryStream {
+    /// The index of the first PES packet with the parameter sets needed to start decoding,
+    /// captures seldom start at one.
+    fn first_decodable_packet(&self) -> Option<usize> {
+        self.packets.iter().position(|packet| {
+            self.stream_type
+                .has_parameter_sets(&self.data[packet.offset..packet.offset + packet.size])
+        })
+    }
+
+    fn decodable_data(&self) -> Result<&[u8]> {
+        let first = self
+            .first_decodable_packet()
+            .ok_or_else(|| anyhow!("No parameter sets in the elementary stream"))?;
+        Ok(&self.data[self.packets[first].offset..])
+    }
+
+    /// The in-band parameter sets the stream starts with.
+    pub fn parameter_sets(&self) -> Result<codec::ParameterSets> {
+        self.stream_type.parameter_sets(self.decodable_data()?)
+    }
+
+    /// Splits the stream into access units with their presentation time, starting at the
+    /// first parameter sets. H.264 goes through the same [`AnnexBReader`] as raw `.h264`
+    /// files, so PES packets need not be aligned to access units; a PTS belongs to the
+    /// first access unit starting in its PES packet. H.265 PES packets are taken as one
+    /// access unit each, which is how broadcast streams carry them. Access units without a
+    /// PTS follow the previous one by `frame_duration`.
+    pub fn access_units(&self, frame_duration: Timestamp) -> Result<Vec<(Vec<u8>, Timestamp)>> {
+        let first = self
+            .first_decodable_packet()
+            .ok_or_else(|| anyhow!("No parameter sets in the elementary stream"))?;
+        let start = self.packets[first].offset;
+        let packets = &self.packets[first..];
+
+        let mut access_units: Vec<(Vec<u8>, Timestamp)> = Vec::new();
+        let mut next_packet = 0;
+        let mut pending_pts = None;
+        let mut push = |offset: usize, data: &[u8]| {
+            while next_packet < packets.len() && packets[next_packet].offset <= offset {
+                pending_pts = packets[next_packet].pts.or(pending_pts);
+                next_packet += 1;
+            }
+            let pts = pending_pts.take().unwrap_or_else(|| {
+                access_units
+                    .last()
+                    .map_or(Timestamp::ZERO, |&(_, pts)| pts + frame_duration)
+            });
+            access_units.push((data.to_vec(), pts));
+        };
+
+        match self.stream_type {
+            VideoStreamType::H264 => {
+                for access_unit in AnnexBReader::new(&self.data[start..]) {
+                    let access_unit = access_unit?;
+                    push(start + access_unit.offset, access_unit.data);
+                }
+            }
+            VideoStreamType::H265 => {
+                for packet in packets.iter().filter(|packet| packet.size > 0) {
+                    push(
+                        packet.offset,
+                        &self.data[packet.offset..packet.offset + packet.size],
+                    );
+                }
+            }
+        }
+
+        Ok(access_units)
+    }
+}
@@ -0,0 +1,277 @@
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use ash::{vk, Device};
+
+use crate::decoder::DecodedFrame;
+use crate::find_memorytype_index;
+use crate::timestamp::Timestamp;
+use crate::yuv::Nv12Frame;
+
+/// The format of uploaded frames, the same NV12 layout hardware decoders write
+const FORMAT: vk::Format = vk::Format::G8_B8R8_2PLANE_420_UNORM;
+
+/// Copies frames decoded on the CPU into an image that can be sampled like the frames of
+/// a [`Decoder`](crate::decoder::Decoder), for devices without a video decode queue.
+pub struct FrameUploader {
+    device: Device,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+
+    image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    ptr: *mut u8,
+    extent: vk::Extent2D,
+}
+
+impl FrameUploader {
+    /// Creates an uploader for NV12 frames of `extent`, the coded size of the stream.
+    ///
+    /// # Safety
+    ///
+    /// `queue` must come from `queue_family_index` of `device`, the queue family has to
+    /// support transfers, and the device must outlive the uploader.
+    pub unsafe fn new(
+        device: &Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(FORMAT)
+            .extent(extent.into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let image = device.create_image(&image_info, None)?;
+
+        let image_memory_req = device.get_image_memory_requirements(image);
+        let image_memory_index = find_memorytype_index(
+            &image_memory_req,
+            device_memory_properties,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .ok_or_else(|| anyhow!("Unable to find suitable memorytype for the frame image"))?;
+        let image_allocate_info = vk::MemoryAllocateInfo {
+            allocation_size: image_memory_req.size,
+            memory_type_index: image_memory_index,
+            ..Default::default()
+        };
+        let image_memory = device.allocate_memory(&image_allocate_info, None)?;
+        device.bind_image_memory(image, image_memory, 0)?;
+
+        let size = extent.width as u64 * extent.height as u64 * 3 / 2;
+        let buffer_info = vk::BufferCreateInfo {
+            size,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let buffer = device.create_buffer(&buffer_info, None)?;
+
+        let memory_req = device.get_buffer_memory_requirements(buffer);
+        let memory_index = find_memorytype_index(
+            &memory_req,
+            device_memory_properties,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+        .ok_or_else(|| anyhow!("Unable to find suitable memorytype for the upload buffer"))?;
+        let allocate_info = vk::MemoryAllocateInfo {
+            allocation_size: memory_req.size,
+            memory_type_index: memory_index,
+            ..Default::default()
+        };
+        let memory = device.allocate_memory(&allocate_info, None)?;
+        device.bind_buffer_memory(buffer, memory, 0)?;
+        let ptr = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u8;
+
+        let pool_create_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(queue_family_index);
+        let command_pool = device.create_command_pool(&pool_create_info, None)?;
+
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_buffer_count(1)
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let command_buffer = device.allocate_command_buffers(&command_buffer_allocate_info)?[0];
+        let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+        Ok(Self {
+            device: device.clone(),
+            queue,
+            command_pool,
+            command_buffer,
+            fence,
+            image,
+            image_memory,
+            buffer,
+            memory,
+            ptr,
+            extent,
+        })
+    }
+
+    pub fn format(&self) -> vk::Format {
+        FORMAT
+    }
+
+    /// Coded size of the frames
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Copies `picture` into the image and returns it in
+    /// `SHADER_READ_ONLY_OPTIMAL`, owned by the uploader's queue family. The image is
+    /// reused by the next upload, which waits for earlier work on the same queue to stop
+    /// sampling it.
+    ///
+    /// # Safety
+    ///
+    /// Work sampling the previous frame must have been submitted to the uploader's queue.
+    pub unsafe fn upload(&mut self, picture: &Nv12Frame, pts: Timestamp) -> Result<DecodedFrame> {
+        let vk::Extent2D { width, height } = self.extent;
+        if picture.width != width || picture.height != height {
+            return Err(anyhow!(
+                "Frame of {}x{} does not fit the upload image for {}x{}",
+                picture.width,
+                picture.height,
+                width,
+                height
+            ));
+        }
+
+        let luma_size = width as usize * height as usize;
+        ptr::copy_nonoverlapping(picture.y.as_ptr(), self.ptr, luma_size);
+        ptr::copy_nonoverlapping(picture.uv.as_ptr(), self.ptr.add(luma_size), luma_size / 2);
+
+        let plane = |aspect_mask, buffer_offset, width, height| vk::BufferImageCopy {
+            buffer_offset,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            ..Default::default()
+        };
+        let regions = [
+            plane(vk::ImageAspectFlags::PLANE_0, 0, width, height),
+            plane(
+                vk::ImageAspectFlags::PLANE_1,
+                luma_size as u64,
+                width / 2,
+                height / 2,
+            ),
+        ];
+        let barrier =
+            |src_access_mask, dst_access_mask, old_layout, new_layout| vk::ImageMemoryBarrier {
+                src_access_mask,
+                dst_access_mask,
+                old_layout,
+                new_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: self.image,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    level_count: 1,
+                    layer_count: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+        // The previous contents are overwritten as a whole
+        let to_transfer = barrier(
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        let to_shader_read = barrier(
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        let device = &self.device;
+        let command_buffer = self.command_buffer;
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer],
+        );
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            self.buffer,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_shader_read],
+        );
+        device.end_command_buffer(command_buffer)?;
+
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+        device.queue_submit(self.queue, &[submit_info], self.fence)?;
+        // The staging buffer is free for the next frame once the copy is done
+        device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+        device.reset_fences(&[self.fence])?;
+
+        Ok(DecodedFrame {
+            image: self.image,
+            // Frames are sampled through views of the YCbCr sampler
+            image_view: vk::ImageView::null(),
+            array_layer: 0,
+            format: FORMAT,
+            extent: self.extent,
+            pts,
+        })
+    }
+}
+
+impl Drop for FrameUploader {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.queue_wait_idle(self.queue).ok();
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device.unmap_memory(self.memory);
+            self.device.destroy_buffer(self.buffer, None);
+            self.device.free_memory(self.memory, None);
+            self.device.destroy_image(self.image, None);
+            self.device.free_memory(self.image_memory, None);
+        }
+    }
+}
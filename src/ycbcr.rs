@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use ash::{vk, Device, Instance};
+
+use crate::codec::StreamInfo;
+use crate::color::{ColorSpace, MatrixCoefficients};
+use crate::decoder::DecodedFrame;
+
+/// The conversion only expands the sample range, the matrix is applied in the shader
+/// from [`ColorSpace::uniforms`]. G, B and R samples are passed through as they are.
+pub fn model_conversion(color_space: &ColorSpace) -> vk::SamplerYcbcrModelConversion {
+    match color_space.matrix {
+        MatrixCoefficients::Identity => vk::SamplerYcbcrModelConversion::RGB_IDENTITY,
+        _ => vk::SamplerYcbcrModelConversion::YCBCR_IDENTITY,
+    }
+}
+
+/// Full or limited sample range from video_full_range_flag.
+pub fn range(color_space: &ColorSpace) -> vk::SamplerYcbcrRange {
+    if color_space.full_range {
+        vk::SamplerYcbcrRange::ITU_FULL
+    } else {
+        vk::SamplerYcbcrRange::ITU_NARROW
+    }
+}
+
+/// Chroma sample position from chroma_sample_loc_type, Figure E-1. Vulkan has no
+/// position below the luma samples, those streams get the closest one.
+fn chroma_location(chroma_sample_loc_type: u8) -> (vk::ChromaLocation, vk::ChromaLocation) {
+    let x = match chroma_sample_loc_type {
+        0 | 2 | 4 => vk::ChromaLocation::COSITED_EVEN,
+        _ => vk::ChromaLocation::MIDPOINT,
+    };
+    let y = match chroma_sample_loc_type {
+        2 | 3 => vk::ChromaLocation::COSITED_EVEN,
+        _ => vk::ChromaLocation::MIDPOINT,
+    };
+    (x, y)
+}
+
+/// A sampler converting decoded YCbCr frames to RGB, with the image views it can be
+/// combined with. The sampler has to be an immutable sampler of the descriptor set
+/// layout, and every view sampled through it needs the same conversion.
+pub struct YcbcrSampler {
+    device: Device,
+    pub conversion: vk::SamplerYcbcrConversion,
+    pub sampler: vk::Sampler,
+    format: vk::Format,
+    /// One view per array layer of the decoder's images, created on first use
+    views: HashMap<(vk::Image, u32), vk::ImageView>,
+}
+
+impl YcbcrSampler {
+    /// Creates the conversion for frames in `format` of the stream described by `stream_info`.
+    ///
+    /// # Safety
+    ///
+    /// `device` must have been created from `pdevice` with the samplerYcbcrConversion
+    /// feature enabled, and must outlive the sampler.
+    pub unsafe fn new(
+        instance: &Instance,
+        device: &Device,
+        pdevice: vk::PhysicalDevice,
+        format: vk::Format,
+        stream_info: &StreamInfo,
+    ) -> Result<Self> {
+        let format_features = instance
+            .get_physical_device_format_properties(pdevice, format)
+            .optimal_tiling_features;
+        if !format_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+            return Err(anyhow!("Decoded frames in {:?} can not be sampled", format));
+        }
+
+        let filter = if format_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_YCBCR_CONVERSION_LINEAR_FILTER)
+        {
+            vk::Filter::LINEAR
+        } else {
+            vk::Filter::NEAREST
+        };
+        let supported = |location| match location {
+            vk::ChromaLocation::COSITED_EVEN => {
+                format_features.contains(vk::FormatFeatureFlags::COSITED_CHROMA_SAMPLES)
+            }
+            _ => format_features.contains(vk::FormatFeatureFlags::MIDPOINT_CHROMA_SAMPLES),
+        };
+        let fallback = |location| match location {
+            vk::ChromaLocation::COSITED_EVEN => vk::ChromaLocation::MIDPOINT,
+            _ => vk::ChromaLocation::COSITED_EVEN,
+        };
+        let (x_chroma_offset, y_chroma_offset) =
+            chroma_location(stream_info.chroma_sample_loc_type);
+        let x_chroma_offset = if supported(x_chroma_offset) {
+            x_chroma_offset
+        } else {
+            fallback(x_chroma_offset)
+        };
+        let y_chroma_offset = if supported(y_chroma_offset) {
+            y_chroma_offset
+        } else {
+            fallback(y_chroma_offset)
+        };
+
+        let color_space = stream_info.color_space;
+        let conversion_info = vk::SamplerYcbcrConversionCreateInfo::default()
+            .format(format)
+            .ycbcr_model(model_conversion(&color_space))
+            .ycbcr_range(range(&color_space))
+            .components(vk::ComponentMapping::default())
+            .x_chroma_offset(x_chroma_offset)
+            .y_chroma_offset(y_chroma_offset)
+            .chroma_filter(filter)
+            .force_explicit_reconstruction(false);
+        let conversion = device.create_sampler_ycbcr_conversion(&conversion_info, None)?;
+
+        // Without separate reconstruction filters the sampler filters must match the
+        // chroma filter
+        let mut sampler_conversion_info =
+            vk::SamplerYcbcrConversionInfo::default().conversion(conversion);
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .push_next(&mut sampler_conversion_info)
+            .mag_filter(filter)
+            .min_filter(filter)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_anisotropy(1.0)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK)
+            .compare_op(vk::CompareOp::NEVER);
+        let sampler = device.create_sampler(&sampler_info, None)?;
+
+        Ok(Self {
+            device: device.clone(),
+            conversion,
+            sampler,
+            format,
+            views: HashMap::new(),
+        })
+    }
+
+    /// A view of the layer holding `frame`, to be sampled through [`Self::sampler`].
+    ///
+    /// # Safety
+    ///
+    /// The views live as long as the sampler, which has to be dropped before the decoder
+    /// that owns the images.
+    pub unsafe fn view(&mut self, frame: &DecodedFrame) -> Result<vk::ImageView> {
+        if frame.format != self.format {
+            return Err(anyhow!(
+                "Frame in {:?} does not match the conversion for {:?}",
+                frame.format,
+                self.format
+            ));
+        }
+        if let Some(&view) = self.views.get(&(frame.image, frame.array_layer)) {
+            return Ok(view);
+        }
+
+        let mut sampler_conversion_info =
+            vk::SamplerYcbcrConversionInfo::default().conversion(self.conversion);
+        let mut usage_info =
+            vk::ImageViewUsageCreateInfo::default().usage(vk::ImageUsageFlags::SAMPLED);
+        let view_info = vk::ImageViewCreateInfo::default()
+            .push_next(&mut sampler_conversion_info)
+            .push_next(&mut usage_info)
+            .image(frame.image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(frame.format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                level_count: 1,
+                base_array_layer: frame.array_layer,
+                layer_count: 1,
+                ..Default::default()
+            });
+        let view = self.device.create_image_view(&view_info, None)?;
+        self.views.insert((frame.image, frame.array_layer), view);
+
+        Ok(view)
+    }
+}
+
+impl Drop for YcbcrSampler {
+    fn drop(&mut self) {
+        unsafe {
+            for &view in self.views.values() {
+                self.device.destroy_image_view(view, None);
+            }
+            self.device.destroy_sampler(self.sampler, None);
+            self.device
+                .destroy_sampler_ycbcr_conversion(self.conversion, None);
+        }
+    }
+}
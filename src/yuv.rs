@@ -0,0 +1,133 @@
+use std::io::{self, Write};
+
+use crate::color::ColorSpace;
+
+/// An 8 bit 4:2:0 picture in host memory, with a full resolution luma plane and a half
+/// resolution plane of interleaved Cb and Cr samples.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nv12Frame {
+    pub width: u32,
+    pub height: u32,
+    /// `width * height` luma samples
+    pub y: Vec<u8>,
+    /// `(width + 1) / 2 * (height + 1) / 2` Cb, Cr pairs
+    pub uv: Vec<u8>,
+}
+
+impl Nv12Frame {
+    fn chroma_width(&self) -> usize {
+        (self.width as usize).div_ceil(2)
+    }
+
+    fn chroma_height(&self) -> usize {
+        (self.height as usize).div_ceil(2)
+    }
+
+    /// Cuts out the rectangle at `x`, `y`, for instance the SPS cropping window. The
+    /// offsets are rounded down to even values so the chroma samples stay aligned.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Nv12Frame {
+        let x = (x & !1).min(self.width);
+        let y = (y & !1).min(self.height);
+        let width = width.min(self.width - x);
+        let height = height.min(self.height - y);
+
+        let mut cropped = Nv12Frame {
+            width,
+            height,
+            y: Vec::with_capacity(width as usize * height as usize),
+            uv: Vec::new(),
+        };
+        for row in y..y + height {
+            let start = (row * self.width + x) as usize;
+            cropped
+                .y
+                .extend_from_slice(&self.y[start..start + width as usize]);
+        }
+
+        let chroma_width = cropped.chroma_width();
+        cropped
+            .uv
+            .reserve(chroma_width * cropped.chroma_height() * 2);
+        for row in y as usize / 2..y as usize / 2 + cropped.chroma_height() {
+            let start = (row * self.chroma_width() + x as usize / 2) * 2;
+            cropped
+                .uv
+                .extend_from_slice(&self.uv[start..start + chroma_width * 2]);
+        }
+
+        cropped
+    }
+
+    /// Extends the picture to `width` x `height` by repeating its last column and row, for
+    /// instance up to the macroblock aligned size of an encoder. Larger pictures are
+    /// returned as they are.
+    pub fn pad(&self, width: u32, height: u32) -> Nv12Frame {
+        let width = width.max(self.width);
+        let height = height.max(self.height);
+
+        let mut padded = Nv12Frame {
+            width,
+            height,
+            y: Vec::with_capacity(width as usize * height as usize),
+            uv: Vec::new(),
+        };
+        for row in 0..height.min(self.height) as usize {
+            let line = &self.y[row * self.width as usize..(row + 1) * self.width as usize];
+            padded.y.extend_from_slice(line);
+            let last = line.last().copied().unwrap_or(0);
+            padded
+                .y
+                .resize(padded.y.len() + (width - self.width) as usize, last);
+        }
+        let width = width as usize;
+        for _ in self.height..height {
+            let start = padded.y.len() - width;
+            padded.y.extend_from_within(start..start + width);
+        }
+
+        let chroma_width = padded.chroma_width();
+        padded.uv.reserve(chroma_width * padded.chroma_height() * 2);
+        for row in 0..self.chroma_height() {
+            let line = &self.uv[row * self.chroma_width() * 2..(row + 1) * self.chroma_width() * 2];
+            padded.uv.extend_from_slice(line);
+            let last = match line {
+                [.., cb, cr] => [*cb, *cr],
+                _ => [128, 128],
+            };
+            for _ in self.chroma_width()..chroma_width {
+                padded.uv.extend_from_slice(&last);
+            }
+        }
+        for _ in self.chroma_height()..padded.chroma_height() {
+            let start = padded.uv.len() - chroma_width * 2;
+            padded
+                .uv
+                .extend_from_within(start..start + chroma_width * 2);
+        }
+
+        padded
+    }
+
+    /// Writes the picture as planar I420, the Y plane followed by the Cb and Cr planes.
+    pub fn write_i420<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.y)?;
+        let cb: Vec<u8> = self.uv.iter().step_by(2).copied().collect();
+        let cr: Vec<u8> = self.uv.iter().skip(1).step_by(2).copied().collect();
+        writer.write_all(&cb)?;
+        writer.write_all(&cr)
+    }
+
+    /// Converts to packed BT.709 R'G'B' as described by `color_space`.
+    pub fn to_rgb8(&self, color_space: &ColorSpace) -> Vec<u8> {
+        let converter = color_space.converter();
+        let mut rgb = Vec::with_capacity(self.y.len() * 3);
+        for row in 0..self.height as usize {
+            for column in 0..self.width as usize {
+                let luma = self.y[row * self.width as usize + column];
+                let chroma = ((row / 2) * self.chroma_width() + column / 2) * 2;
+                rgb.extend(converter.to_rgb8(luma, self.uv[chroma], self.uv[chroma + 1]));
+            }
+        }
+        rgb
+    }
+}
@@ -0,0 +1,167 @@
+use ash_video::annexb::{AnnexBReader, AnnexBSplitter};
+use ash_video::h264::{NalUnitHeader, NalUnitType, NalUnits};
+
+mod common;
+
+fn split(data: &[u8]) -> Vec<(usize, usize, &[u8])> {
+    NalUnits::new(data)
+        .map(|nal| (nal.offset, nal.start_code_len, nal.data))
+        .collect()
+}
+
+#[test]
+fn start_codes() {
+    let data = [
+        0, 0, 0, 1, 0x09, 0xf0, // 4 byte start code
+        0, 0, 1, 0x67, 0x42, // 3 byte start code
+        0, 0, 0, 1, 0x68, 0xce, 0x38, 0x80, // 4 byte start code after a NAL unit
+        0, 0, 1, 0x65, 0x88, 0x84,
+    ];
+    assert_eq!(
+        split(&data),
+        [
+            (0, 4, &[0x09, 0xf0][..]),
+            (6, 3, &[0x67, 0x42][..]),
+            (11, 4, &[0x68, 0xce, 0x38, 0x80][..]),
+            (19, 3, &[0x65, 0x88, 0x84][..]),
+        ]
+    );
+}
+
+#[test]
+fn trailing_zeros() {
+    // The last of the zeros after a NAL unit is the zero_byte of a 4 byte start code, the
+    // others are trailing_zero_8bits
+    let data = [
+        0, 0, 1, 0x06, 0x05, 0x80, 0, 0, 0, 0, 1, 0x67, 0x00, 0x0a, 0, 0, 0, 0,
+    ];
+    assert_eq!(
+        split(&data),
+        [
+            (0, 3, &[0x06, 0x05, 0x80][..]),
+            (7, 4, &[0x67, 0x00, 0x0a][..])
+        ]
+    );
+}
+
+#[test]
+fn leading_and_empty() {
+    // Bytes before the first start code are not a NAL unit, neither is an empty one
+    let data = [0x12, 0x34, 0, 0, 1, 0, 0, 1, 0x09, 0x10, 0, 0, 0, 1];
+    assert_eq!(split(&data), [(5, 3, &[0x09, 0x10][..])]);
+
+    assert!(split(&[]).is_empty());
+    assert!(split(&[0, 0, 0, 0]).is_empty());
+    assert!(split(&[0x65, 0x88, 0x84]).is_empty());
+}
+
+#[test]
+fn emulation_prevention() {
+    // 0x000003 is not a start code and the NAL unit keeps its emulation prevention bytes
+    let data = [0, 0, 1, 0x67, 0, 0, 3, 1, 0, 0, 3, 0, 0, 0, 1, 0x68];
+    let nal_units = split(&data);
+    assert_eq!(
+        nal_units,
+        [
+            (0, 3, &[0x67, 0, 0, 3, 1, 0, 0, 3][..]),
+            (11, 4, &[0x68][..]),
+        ]
+    );
+
+    let nal = NalUnits::new(&data).next().unwrap();
+    assert_eq!(nal.rbsp(), [0x67, 0, 0, 1, 0, 0]);
+}
+
+#[test]
+fn sample_stream() {
+    let data = std::fs::read(common::ANNEXB_STREAM).unwrap();
+
+    let nal_units: Vec<_> = NalUnits::new(&data).collect();
+    assert_eq!(nal_units.len(), 37);
+    let types: Vec<_> = nal_units[..5]
+        .iter()
+        .map(|nal| NalUnitHeader::parse(nal.data).unwrap().nal_unit_type)
+        .collect();
+    assert_eq!(
+        types,
+        [
+            NalUnitType::Sei,
+            NalUnitType::Sps,
+            NalUnitType::Pps,
+            NalUnitType::IdrSlice,
+            NalUnitType::Slice,
+        ]
+    );
+    // x264 writes 4 byte start codes in front of the parameter sets and the first slice of
+    // a picture that does not follow them
+    assert_eq!((nal_units[0].offset, nal_units[0].start_code_len), (0, 4));
+    assert_eq!((nal_units[1].offset, nal_units[1].start_code_len), (566, 4));
+    assert_eq!((nal_units[3].offset, nal_units[3].start_code_len), (602, 3));
+    assert_eq!((nal_units[4].offset, nal_units[4].start_code_len), (659, 4));
+    for pair in nal_units.windows(2) {
+        let (nal, next) = (&pair[0], &pair[1]);
+        let end = nal.offset + nal.start_code_len + nal.data.len();
+        assert!(
+            end <= next.offset,
+            "{} overlaps {}",
+            nal.offset,
+            next.offset
+        );
+        assert!(data[end..next.offset].iter().all(|&byte| byte == 0));
+    }
+
+    let access_units: Vec<_> = AnnexBReader::new(&data).collect::<Result<_, _>>().unwrap();
+    assert_eq!(access_units.len(), 30);
+    let mut offset = 0;
+    for (index, access_unit) in access_units.iter().enumerate() {
+        assert_eq!(access_unit.offset, offset);
+        assert_eq!(access_unit.is_idr(), index % 10 == 0);
+        assert_eq!(access_unit.slices.len(), 1);
+        let slice = &access_unit.slices[0];
+        let start_code: &[u8] = if index % 10 == 0 {
+            &[0, 0, 1]
+        } else {
+            &[0, 0, 0, 1]
+        };
+        assert!(access_unit.data[slice.offset..].starts_with(start_code));
+        assert_eq!(slice.offset + slice.size, access_unit.data.len());
+        assert_eq!(slice.header.frame_num, index as u32 % 10);
+        offset += access_unit.data.len();
+    }
+    assert_eq!(offset, data.len());
+}
+
+#[test]
+fn splitter_pieces() {
+    // The sample stream with 1 MB of filler data in the first access unit
+    let data = std::fs::read(common::ANNEXB_STREAM).unwrap();
+    let first = AnnexBReader::new(&data).next().unwrap().unwrap().data.len();
+    let mut filler = vec![0, 0, 0, 1, 0x0c];
+    filler.resize(filler.len() + (1 << 20), 0xff);
+    filler.push(0x80);
+    let data = [&data[..first], &filler, &data[first..]].concat();
+    let expected: Vec<_> = AnnexBReader::new(&data)
+        .map(|access_unit| {
+            let access_unit = access_unit.unwrap();
+            (access_unit.offset, access_unit.data.to_vec())
+        })
+        .collect();
+    assert_eq!(expected[0].1.len(), first + filler.len());
+
+    // Start codes split between pieces, and the filler data searched once rather than
+    // again for every piece
+    for size in [1, 2, 3, 4096] {
+        let mut splitter = AnnexBSplitter::default();
+        let mut access_units = Vec::new();
+        for piece in data.chunks(size) {
+            splitter.push(piece);
+            while let Some(access_unit) = splitter.next_access_unit(false) {
+                access_units.push(access_unit.unwrap());
+            }
+        }
+        while let Some(access_unit) = splitter.next_access_unit(true) {
+            access_units.push(access_unit.unwrap());
+        }
+        assert!(access_units == expected, "pieces of {} bytes", size);
+    }
+}
@@ -0,0 +1,450 @@
+use ash_video::av1::av1c::Av1CodecConfiguration;
+use ash_video::av1::dpb::Dpb;
+use ash_video::av1::frame::{FrameHeader, FrameType, SWITCHABLE, TX_MODE_SELECT};
+use ash_video::av1::obu::{ObuHeader, ObuType, Obus};
+use ash_video::av1::sequence::SequenceHeader;
+use ash_video::av1::tile_group::{Tile, TileGroup};
+use ash_video::av1::{read_leb128, PRIMARY_REF_NONE};
+use ash_video::codec::{self, Codec};
+use ash_video::color::MatrixCoefficients;
+use ash_video::timestamp::Timestamp;
+
+/// Writes OBU syntax elements, there is no AV1 sample stream to take them from.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u64, count: usize) -> &mut Self {
+        for i in (0..count).rev() {
+            if self.bits & 7 == 0 {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+        self
+    }
+
+    fn flag(&mut self, value: bool) -> &mut Self {
+        self.bits(value as u64, 1)
+    }
+
+    /// byte_alignment()
+    fn align(&mut self) -> &mut Self {
+        while self.bits & 7 != 0 {
+            self.flag(false);
+        }
+        self
+    }
+
+    /// trailing_bits()
+    fn trailing(&mut self) -> &mut Self {
+        self.flag(true).align()
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        assert_eq!(self.bits & 7, 0);
+        self.bytes.extend_from_slice(bytes);
+        self.bits += 8 * bytes.len();
+        self
+    }
+}
+
+/// An OBU with obu_has_size_field set.
+fn obu(obu_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut obu = vec![obu_type << 3 | 0b10];
+    let mut size = payload.len();
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            obu.push(byte);
+            break;
+        }
+        obu.push(byte | 0x80);
+    }
+    obu.extend_from_slice(payload);
+    obu
+}
+
+/// Main profile 640x360 at 30 fps, BT.709, 7 bit order hints, screen content tools and
+/// integer motion vectors selected per frame.
+fn sequence_header() -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer.bits(0, 3).flag(false).flag(false);
+    // timing_info() with num_ticks_per_picture_minus_1 0, no decoder model
+    writer
+        .flag(true)
+        .bits(1, 32)
+        .bits(30, 32)
+        .flag(true)
+        .flag(true)
+        .flag(false);
+    // One operating point at level 4.0, main tier
+    writer
+        .flag(false)
+        .bits(0, 5)
+        .bits(0, 12)
+        .bits(8, 5)
+        .flag(false);
+    writer.bits(9, 4).bits(8, 4).bits(639, 10).bits(359, 9);
+    // frame_id_numbers_present_flag, use_128x128_superblock, filter intra, intra edge
+    writer.flag(false).flag(false).flag(true).flag(true);
+    // interintra, masked, warped, dual filter, order hint, jnt comp, ref frame mvs
+    writer.bits(0b0000100, 7);
+    writer.flag(true).flag(true).bits(6, 3);
+    // superres, cdef, restoration
+    writer.bits(0, 3);
+    // color_config(): 8 bit, BT.709, limited range, unknown chroma sample position
+    writer
+        .flag(false)
+        .flag(false)
+        .flag(true)
+        .bits(1, 8)
+        .bits(1, 8)
+        .bits(1, 8)
+        .flag(false)
+        .bits(0, 2)
+        .flag(false);
+    writer.flag(false).trailing();
+    obu(1, &writer.bytes)
+}
+
+fn parse_sequence_header() -> SequenceHeader {
+    let obu = sequence_header();
+    SequenceHeader::parse(&obu[2..]).unwrap()
+}
+
+fn obu_header(obu_type: u8) -> ObuHeader {
+    ObuHeader::parse(&[obu_type << 3 | 0b10]).unwrap().0
+}
+
+/// A shown key frame as a frame OBU, split into two tile columns. The tiles hold 3 and 4
+/// bytes.
+fn key_frame_obu() -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // show_existing_frame, frame_type, show_frame, disable_cdf_update,
+    // allow_screen_content_tools, frame_size_override_flag, order_hint
+    writer
+        .flag(false)
+        .bits(0, 2)
+        .flag(true)
+        .flag(false)
+        .flag(false)
+        .flag(false)
+        .bits(0, 7);
+    // render_and_frame_size_different, disable_frame_end_update_cdf
+    writer.flag(false).flag(true);
+    // Uniform tiles, one more column, context_update_tile_id 0, two byte tile sizes
+    writer.flag(true).flag(true).flag(false).flag(false);
+    writer.bits(0, 1).bits(1, 2);
+    // base_q_idx 100, no delta q, no qmatrix, no segmentation or delta q
+    writer.bits(100, 8).bits(0, 4).flag(false).flag(false);
+    // Loop filter levels, sharpness, deltas enabled without update
+    writer.bits(10, 6).bits(10, 6).bits(5, 6).bits(5, 6);
+    writer.bits(0, 3).flag(true).flag(false);
+    // tx_mode_select, reduced_tx_set
+    writer.flag(true).flag(false);
+    writer.align();
+
+    // Tile group without tile_start_and_end_present_flag
+    writer.flag(false).align();
+    writer.bytes(&[2, 0]).bytes(&[1, 2, 3]).bytes(&[4, 5, 6, 7]);
+    obu(6, &writer.bytes)
+}
+
+/// A shown inter frame header with order hint 1, predicting everything from reference
+/// slot 0 and refreshing slot 1.
+fn inter_frame_header_obu() -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // show_existing_frame, frame_type, show_frame, error_resilient_mode,
+    // disable_cdf_update, allow_screen_content_tools, frame_size_override_flag,
+    // order_hint, primary_ref_frame, refresh_frame_flags
+    writer
+        .flag(false)
+        .bits(1, 2)
+        .flag(true)
+        .flag(false)
+        .flag(false)
+        .flag(false)
+        .flag(false)
+        .bits(1, 7)
+        .bits(0, 3)
+        .bits(0b10, 8);
+    // frame_refs_short_signaling, ref_frame_idx
+    writer.flag(false).bits(0, 21);
+    // render_and_frame_size_different, allow_high_precision_mv, switchable filter,
+    // is_motion_mode_switchable, disable_frame_end_update_cdf
+    writer
+        .flag(false)
+        .flag(true)
+        .flag(true)
+        .flag(false)
+        .flag(false);
+    // A single tile
+    writer.flag(true).flag(false).flag(false);
+    writer.bits(120, 8).bits(0, 4).flag(false).flag(false);
+    // Loop filter off, largest transforms, single references, no global motion
+    writer.bits(0, 6).bits(0, 6).bits(0, 3).flag(false);
+    writer.flag(false).flag(false).flag(false).bits(0, 7);
+    writer.trailing();
+    obu(3, &writer.bytes)
+}
+
+fn show_existing_frame_obu(frame_to_show_map_idx: u64) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer.flag(true).bits(frame_to_show_map_idx, 3).trailing();
+    obu(3, &writer.bytes)
+}
+
+fn parse_frame_header(obu: &[u8], sequence: &SequenceHeader, dpb: &Dpb) -> FrameHeader {
+    FrameHeader::parse(
+        &obu[2..],
+        sequence,
+        &obu_header(obu[0] >> 3),
+        dpb.references(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn leb128() {
+    assert_eq!(read_leb128(&[0x05]).unwrap(), (5, 1));
+    assert_eq!(read_leb128(&[0xe5, 0x8e, 0x26, 0xff]).unwrap(), (624485, 3));
+    assert!(read_leb128(&[0x80, 0x80]).is_err());
+}
+
+#[test]
+fn obus() {
+    let mut temporal_unit = obu(2, &[]);
+    temporal_unit.extend(sequence_header());
+    let sequence_header_size = temporal_unit.len() - 2;
+    temporal_unit.extend(key_frame_obu());
+
+    let obus: Vec<_> = Obus::new(&temporal_unit).map(Result::unwrap).collect();
+    let types: Vec<_> = obus.iter().map(|obu| obu.header.obu_type).collect();
+    assert_eq!(
+        types,
+        [
+            ObuType::TemporalDelimiter,
+            ObuType::SequenceHeader,
+            ObuType::Frame
+        ]
+    );
+    assert_eq!(obus[1].offset, 2);
+    assert_eq!(obus[1].size, sequence_header_size);
+    assert_eq!(obus[1].data.len(), sequence_header_size - 2);
+    assert_eq!(obus[2].offset, 2 + sequence_header_size);
+    assert!(obus[2].header.obu_has_size_field);
+
+    // The size field runs past the end of the data
+    let mut truncated = sequence_header();
+    truncated.pop();
+    let mut iter = Obus::new(&truncated);
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn sequence_header_obu() {
+    let sequence = parse_sequence_header();
+    assert_eq!(sequence.seq_profile, 0);
+    assert_eq!(sequence.coded_extent(), (640, 360));
+    assert_eq!(sequence.operating_points.len(), 1);
+    assert_eq!(sequence.operating_points[0].seq_level_idx, 8);
+    assert!(sequence.enable_order_hint);
+    assert_eq!(sequence.order_hint_bits, 7);
+    assert_eq!(sequence.frame_duration(), Some(Timestamp::new(1, 30)));
+    assert_eq!(sequence.relative_dist(1, 127), 2);
+    assert_eq!(sequence.relative_dist(127, 1), -2);
+
+    let color_config = &sequence.color_config;
+    assert_eq!(color_config.bit_depth, 8);
+    assert!(color_config.subsampling_x && color_config.subsampling_y);
+    assert_eq!(color_config.matrix_coefficients, 1);
+    assert_eq!(color_config.num_planes(), 3);
+
+    let std = sequence.to_std();
+    assert_eq!(std.header.max_frame_width_minus_1, 639);
+    assert_eq!(std.header.order_hint_bits_minus_1, 6);
+    assert_eq!(unsafe { (*std.header.pColorConfig).BitDepth }, 8);
+
+    let info = codec::ParameterSets::from(sequence).stream_info().unwrap();
+    assert_eq!(info.codec, Codec::Av1);
+    assert_eq!(info.coded_extent, (640, 360));
+    assert_eq!(info.crop_rect, (0, 0, 640, 360));
+    assert_eq!(info.color_space.matrix, MatrixCoefficients::Bt709);
+    assert!(!info.color_space.full_range);
+}
+
+#[test]
+fn codec_configuration() {
+    let mut av1c = vec![0x81, 0x08, 0x0c, 0x00];
+    av1c.extend(sequence_header());
+    let config = Av1CodecConfiguration::parse(&av1c).unwrap();
+    assert_eq!(config.seq_profile, 0);
+    assert_eq!(config.seq_level_idx_0, 8);
+    assert_eq!(config.bit_depth, 8);
+    assert!(config.chroma_subsampling_x && config.chroma_subsampling_y);
+    assert_eq!(config.initial_presentation_delay_minus_one, None);
+    assert_eq!(config.sequence_header().unwrap(), parse_sequence_header());
+
+    assert!(Av1CodecConfiguration::parse(&[0x82, 0x08, 0x0c, 0x00]).is_err());
+    assert!(Av1CodecConfiguration::parse(&av1c[..3]).is_err());
+    assert!(Av1CodecConfiguration::parse(&av1c[..4])
+        .unwrap()
+        .sequence_header()
+        .is_err());
+}
+
+#[test]
+fn key_frame_header() {
+    let sequence = parse_sequence_header();
+    let dpb = Dpb::new(10);
+    let obu = key_frame_obu();
+    let header = parse_frame_header(&obu, &sequence, &dpb);
+
+    assert_eq!(header.frame_type, FrameType::Key);
+    assert!(header.show_frame && !header.showable_frame);
+    assert!(header.error_resilient_mode);
+    assert_eq!(header.primary_ref_frame, PRIMARY_REF_NONE);
+    assert_eq!(header.refresh_frame_flags, 0xff);
+    assert_eq!((header.frame_width, header.frame_height), (640, 360));
+    assert_eq!((header.render_width, header.render_height), (640, 360));
+    assert_eq!(header.quantization.base_q_idx, 100);
+    assert_eq!(header.loop_filter.loop_filter_level, [10, 10, 5, 5]);
+    assert_eq!(header.tx_mode, TX_MODE_SELECT);
+    assert!(!header.coded_lossless);
+
+    // 640x360 in 64x64 superblocks is 10x6, split into two columns of five
+    let tile_info = &header.tile_info;
+    assert_eq!(tile_info.tile_cols_log2, 1);
+    assert_eq!(tile_info.mi_col_starts, [0, 80, 160]);
+    assert_eq!(tile_info.mi_row_starts, [0, 90]);
+    assert_eq!(tile_info.width_in_sbs_minus_1, [4, 4]);
+    assert_eq!(tile_info.height_in_sbs_minus_1, [5]);
+    assert_eq!(tile_info.num_tiles(), 2);
+    assert_eq!(tile_info.tile_size_bytes(), 2);
+
+    let std = header.to_std();
+    assert_eq!(std.info.flags.error_resilient_mode(), 1);
+    assert_eq!(std.info.refresh_frame_flags, 0xff);
+    assert_eq!(unsafe { (*std.info.pTileInfo).TileCols }, 2);
+
+    // The tile group follows the byte aligned header
+    let tile_group = TileGroup::parse(&obu[2 + header.header_bytes..], tile_info).unwrap();
+    assert_eq!((tile_group.tg_start, tile_group.tg_end), (0, 1));
+    assert_eq!(
+        tile_group.tiles,
+        [Tile { offset: 3, size: 3 }, Tile { offset: 6, size: 4 }]
+    );
+    assert!(tile_group.is_last(tile_info));
+
+    // The last tile takes the rest, earlier ones must fit
+    let data = &obu[2 + header.header_bytes..];
+    assert!(TileGroup::parse(&data[..4], tile_info).is_err());
+}
+
+#[test]
+fn inter_frame_header() {
+    let sequence = parse_sequence_header();
+    let mut dpb = Dpb::new(10);
+
+    // Inter frames need their references
+    let inter = inter_frame_header_obu();
+    assert!(FrameHeader::parse(&inter[2..], &sequence, &obu_header(3), dpb.references()).is_err());
+
+    let key = parse_frame_header(&key_frame_obu(), &sequence, &dpb);
+    let key_slot = dpb.start_frame(&key).unwrap();
+    dpb.finish_frame(&key, key_slot);
+
+    let header = parse_frame_header(&inter, &sequence, &dpb);
+    assert_eq!(header.frame_type, FrameType::Inter);
+    assert!(header.show_frame && header.showable_frame);
+    assert_eq!(header.order_hint, 1);
+    assert_eq!(header.primary_ref_frame, 0);
+    assert_eq!(header.refresh_frame_flags, 0b10);
+    assert_eq!(header.ref_frame_idx, [0; 7]);
+    assert_eq!(header.order_hints, [0; 8]);
+    assert_eq!(header.ref_frame_sign_bias, 0);
+    assert_eq!((header.frame_width, header.frame_height), (640, 360));
+    assert!(header.allow_high_precision_mv);
+    assert_eq!(header.interpolation_filter, SWITCHABLE);
+    assert_eq!(header.tile_info.num_tiles(), 1);
+    assert_eq!(header.quantization.base_q_idx, 120);
+    // Loop filter deltas carry over from the primary reference frame
+    assert_eq!(
+        header.loop_filter.loop_filter_ref_deltas,
+        key.loop_filter.loop_filter_ref_deltas
+    );
+
+    // A single tile takes the whole tile group
+    let tile_group = TileGroup::parse(&[9, 8, 7], &header.tile_info).unwrap();
+    assert_eq!(tile_group.tiles, [Tile { offset: 0, size: 3 }]);
+}
+
+#[test]
+fn reference_slots() {
+    let sequence = parse_sequence_header();
+    let mut dpb = Dpb::new(10);
+    assert!(dpb.references().iter().all(Option::is_none));
+
+    let key = parse_frame_header(&key_frame_obu(), &sequence, &dpb);
+    assert_eq!(dpb.start_frame(&key).unwrap(), 0);
+    dpb.finish_frame(&key, 0);
+    assert!(dpb
+        .references()
+        .iter()
+        .all(|reference| reference.as_ref().unwrap().slot == 0));
+
+    // Slot 0 is referenced, the inter frame goes into slot 1 and replaces reference 1
+    let inter = parse_frame_header(&inter_frame_header_obu(), &sequence, &dpb);
+    assert_eq!(dpb.start_frame(&inter).unwrap(), 1);
+    dpb.finish_frame(&inter, 1);
+    let slots: Vec<_> = dpb
+        .references()
+        .iter()
+        .map(|reference| reference.as_ref().unwrap().slot)
+        .collect();
+    assert_eq!(slots, [0, 1, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(dpb.references()[1].as_ref().unwrap().order_hint, 1);
+
+    let std_reference_info = dpb.references()[1]
+        .as_ref()
+        .unwrap()
+        .to_std_reference_info();
+    assert_eq!(std_reference_info.OrderHint, 1);
+    assert_eq!(
+        std_reference_info.frame_type,
+        FrameType::Inter.to_std() as u8
+    );
+
+    // Held slots are not reused while they wait for output
+    dpb.hold_slot(2);
+    let next = parse_frame_header(&inter_frame_header_obu(), &sequence, &dpb);
+    assert_eq!(dpb.start_frame(&next).unwrap(), 3);
+    dpb.release_slot(2);
+    assert_eq!(dpb.start_frame(&next).unwrap(), 2);
+
+    // Showing the inter frame again leaves the references alone
+    let shown = parse_frame_header(&show_existing_frame_obu(1), &sequence, &dpb);
+    assert!(shown.show_existing_frame);
+    assert_eq!(shown.frame_type, FrameType::Inter);
+    assert_eq!(shown.order_hint, 1);
+    assert_eq!(dpb.show_existing_frame(&shown).unwrap(), 1);
+    assert_eq!(dpb.references()[2].as_ref().unwrap().slot, 0);
+
+    dpb.clear();
+    assert!(FrameHeader::parse(
+        &show_existing_frame_obu(1)[2..],
+        &sequence,
+        &obu_header(3),
+        dpb.references()
+    )
+    .is_err());
+}
@@ -0,0 +1,285 @@
+use ash_video::annexb::AnnexBReader;
+use ash_video::color::{
+    ColorSpace, ColorUniforms, ColourPrimaries, MatrixCoefficients, TransferCharacteristics,
+};
+use ash_video::h264::sps::Sps;
+use ash_video::yuv::Nv12Frame;
+
+mod common;
+use common::ANNEXB_STREAM;
+
+const BT709: ColorSpace = ColorSpace {
+    primaries: ColourPrimaries::Bt709,
+    transfer: TransferCharacteristics::Bt709,
+    matrix: MatrixCoefficients::Bt709,
+    full_range: false,
+};
+
+/// The BT.601 matrix alone, without the gamut conversion of SMPTE 170M primaries.
+const BT601: ColorSpace = ColorSpace {
+    primaries: ColourPrimaries::Bt709,
+    transfer: TransferCharacteristics::Bt709,
+    matrix: MatrixCoefficients::Bt601,
+    full_range: false,
+};
+
+const BT2020: ColorSpace = ColorSpace {
+    primaries: ColourPrimaries::Bt2020,
+    transfer: TransferCharacteristics::Bt709,
+    matrix: MatrixCoefficients::Bt2020,
+    full_range: false,
+};
+
+/// 100% colour bars as Y, Cb, Cr and the R'G'B' they encode.
+const BARS: [[u8; 3]; 8] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 255, 255],
+    [0, 255, 0],
+    [255, 0, 255],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 0, 0],
+];
+
+fn assert_pixels(color_space: &ColorSpace, pixels: &[([u8; 3], [u8; 3])]) {
+    for &([y, cb, cr], expected) in pixels {
+        let rgb = color_space.to_rgb8(y, cb, cr);
+        assert!(
+            rgb.iter()
+                .zip(expected)
+                .all(|(&value, expected)| value.abs_diff(expected) <= 1),
+            "{:?} {}/{}/{} gave {:?}, expected {:?}",
+            color_space.matrix,
+            y,
+            cb,
+            cr,
+            rgb,
+            expected
+        );
+    }
+}
+
+#[test]
+fn bt709_bars() {
+    let ycbcr = [
+        [235, 128, 128],
+        [219, 16, 138],
+        [188, 154, 16],
+        [173, 42, 26],
+        [78, 214, 230],
+        [63, 102, 240],
+        [32, 240, 118],
+        [16, 128, 128],
+    ];
+    let pixels: Vec<_> = ycbcr.into_iter().zip(BARS).collect();
+    assert_pixels(&BT709, &pixels);
+}
+
+#[test]
+fn bt601_bars() {
+    let ycbcr = [
+        [235, 128, 128],
+        [210, 16, 146],
+        [170, 166, 16],
+        [145, 54, 34],
+        [106, 202, 222],
+        [81, 90, 240],
+        [41, 240, 110],
+        [16, 128, 128],
+    ];
+    let pixels: Vec<_> = ycbcr.into_iter().zip(BARS).collect();
+    assert_pixels(&BT601, &pixels);
+}
+
+#[test]
+fn intermediate_values() {
+    assert_pixels(
+        &BT709,
+        &[
+            ([126, 128, 128], [128, 128, 128]),
+            ([100, 150, 100], [48, 108, 144]),
+            ([180, 90, 160], [248, 182, 111]),
+        ],
+    );
+    assert_pixels(
+        &BT601,
+        &[
+            ([100, 150, 100], [53, 112, 142]),
+            ([180, 90, 160], [242, 180, 114]),
+        ],
+    );
+}
+
+#[test]
+fn smpte170m_primaries() {
+    let smpte170m = ColorSpace {
+        primaries: ColourPrimaries::Smpte170M,
+        ..BT601
+    };
+    assert!(smpte170m.needs_linearization());
+    // SMPTE C primaries are slightly inside of BT.709
+    assert_pixels(
+        &smpte170m,
+        &[
+            ([81, 90, 240], [247, 20, 0]),
+            ([145, 54, 34], [48, 251, 0]),
+            ([41, 240, 110], [12, 19, 255]),
+            ([126, 128, 128], [128, 128, 128]),
+        ],
+    );
+}
+
+#[test]
+fn full_range() {
+    // JFIF red, green and blue
+    let jpeg = ColorSpace {
+        full_range: true,
+        ..BT601
+    };
+    assert_pixels(
+        &jpeg,
+        &[
+            ([76, 85, 255], [255, 0, 0]),
+            ([150, 44, 21], [0, 255, 0]),
+            ([29, 255, 107], [0, 0, 255]),
+            ([128, 128, 128], [128, 128, 128]),
+        ],
+    );
+}
+
+#[test]
+fn identity_matrix() {
+    // G, B and R samples, no range expansion
+    let gbr = ColorSpace {
+        matrix: MatrixCoefficients::Identity,
+        ..BT709
+    };
+    assert_pixels(&gbr, &[([10, 20, 30], [30, 10, 20])]);
+}
+
+#[test]
+fn bt2020_to_bt709() {
+    assert!(BT2020.needs_linearization());
+    assert_pixels(
+        &BT2020,
+        &[
+            ([235, 128, 128], [255, 255, 255]),
+            ([126, 128, 128], [128, 128, 128]),
+            ([16, 128, 128], [0, 0, 0]),
+            // BT.2020 red is outside of BT.709 and clips to its red
+            ([74, 97, 240], [255, 0, 0]),
+        ],
+    );
+
+    // A white point shared by both gamuts maps to itself
+    let gamut = BT2020.gamut();
+    for row in gamut {
+        assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn uniforms() {
+    assert_eq!(std::mem::size_of::<ColorUniforms>(), 112);
+
+    let uniforms = BT709.uniforms(false);
+    assert_eq!(uniforms.transfer, 0);
+    assert_eq!(uniforms.srgb_output, 0);
+    // First column holds the Y coefficients
+    assert_eq!(uniforms.ycbcr_to_rgb[0], [1.0, 1.0, 1.0, 0.0]);
+    assert!((uniforms.ycbcr_to_rgb[2][0] - 1.5748).abs() < 1e-4);
+    for (index, column) in uniforms.gamut.iter().enumerate() {
+        for (row, &value) in column.iter().take(3).enumerate() {
+            let expected = if row == index { 1.0 } else { 0.0 };
+            assert!((value - expected).abs() < 1e-4);
+        }
+    }
+
+    let hlg = ColorSpace {
+        transfer: TransferCharacteristics::Hlg,
+        ..BT2020
+    };
+    let uniforms = hlg.uniforms(true);
+    assert_eq!(uniforms.transfer, TransferCharacteristics::Hlg as i32 + 1);
+    assert_eq!(uniforms.srgb_output, 1);
+}
+
+fn sps() -> Sps {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    let parameter_sets = AnnexBReader::read_parameter_sets(&data).unwrap();
+    parameter_sets.sps(0).unwrap().clone()
+}
+
+#[test]
+fn from_sps() {
+    let mut sps = sps();
+    sps.vui = None;
+
+    // Unspecified streams are BT.601 below 720 lines and BT.709 above
+    sps.frame_mbs_only_flag = true;
+    sps.frame_cropping_flag = false;
+    sps.pic_height_in_map_units_minus1 = 29;
+    assert_eq!(
+        ColorSpace::from_sps(&sps),
+        ColorSpace {
+            primaries: ColourPrimaries::Smpte170M,
+            ..BT601
+        }
+    );
+    sps.pic_height_in_map_units_minus1 = 44;
+    assert_eq!(ColorSpace::from_sps(&sps), BT709);
+
+    let vui = sps.vui.get_or_insert_with(Default::default);
+    vui.video_signal_type_present_flag = true;
+    vui.video_full_range_flag = true;
+    vui.colour_description_present_flag = true;
+    vui.colour_primaries = 9;
+    vui.transfer_characteristics = 16;
+    vui.matrix_coefficients = 9;
+    assert_eq!(
+        ColorSpace::from_sps(&sps),
+        ColorSpace {
+            primaries: ColourPrimaries::Bt2020,
+            transfer: TransferCharacteristics::Pq,
+            matrix: MatrixCoefficients::Bt2020,
+            full_range: true,
+        }
+    );
+
+    // Unspecified and reserved codes keep the guess
+    let vui = sps.vui.as_mut().unwrap();
+    vui.colour_primaries = 2;
+    vui.transfer_characteristics = 2;
+    vui.matrix_coefficients = 3;
+    assert_eq!(
+        ColorSpace::from_sps(&sps),
+        ColorSpace {
+            full_range: true,
+            ..BT709
+        }
+    );
+}
+
+#[test]
+fn nv12_crop_to_rgb() {
+    // 4x2 frame, left half red and right half blue in BT.709
+    let frame = Nv12Frame {
+        width: 4,
+        height: 2,
+        y: vec![63, 63, 32, 32, 63, 63, 32, 32],
+        uv: vec![102, 240, 240, 118],
+    };
+    let rgb = frame.to_rgb8(&BT709);
+    assert_eq!(rgb.len(), 4 * 2 * 3);
+    assert_pixels(&BT709, &[([63, 102, 240], [255, 0, 0])]);
+    assert_eq!(&rgb[..3], &BT709.to_rgb8(63, 102, 240));
+    assert_eq!(&rgb[6..9], &BT709.to_rgb8(32, 240, 118));
+
+    let right = frame.crop(2, 0, 2, 2);
+    assert_eq!(right.y, [32, 32, 32, 32]);
+    assert_eq!(right.uv, [240, 118]);
+    let mut i420 = Vec::new();
+    right.write_i420(&mut i420).unwrap();
+    assert_eq!(i420, [32, 32, 32, 32, 240, 118]);
+}
@@ -0,0 +1,62 @@
+//! Writes H.264 RBSP syntax elements for headers the sample streams do not contain.
+
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    pub fn bits(&mut self, value: u64, count: usize) -> &mut Self {
+        for i in (0..count).rev() {
+            if self.bits & 7 == 0 {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+        self
+    }
+
+    /// Number of bits written so far.
+    pub fn position(&self) -> usize {
+        self.bits
+    }
+
+    pub fn flag(&mut self, value: bool) -> &mut Self {
+        self.bits(value as u64, 1)
+    }
+
+    pub fn ue(&mut self, value: u32) -> &mut Self {
+        let value = value as u64 + 1;
+        let length = 64 - value.leading_zeros() as usize;
+        self.bits(0, length - 1).bits(value, length)
+    }
+
+    pub fn se(&mut self, value: i32) -> &mut Self {
+        let code = if value > 0 { 2 * value - 1 } else { -2 * value };
+        self.ue(code as u32)
+    }
+
+    /// The NAL unit with its header, rbsp_trailing_bits and emulation prevention bytes.
+    pub fn nal(&mut self, nal_ref_idc: u8, nal_unit_type: u8) -> Vec<u8> {
+        self.flag(true);
+        while self.bits & 7 != 0 {
+            self.flag(false);
+        }
+
+        let mut nal = vec![nal_ref_idc << 5 | nal_unit_type];
+        let mut zeros = 0;
+        for &byte in self.bytes.iter() {
+            if zeros == 2 && byte <= 3 {
+                nal.push(3);
+                zeros = 0;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            nal.push(byte);
+        }
+        nal
+    }
+}
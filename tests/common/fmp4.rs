@@ -0,0 +1,159 @@
+//! Fragmented MP4 packaging of progressive files, as HLS and DASH packagers do.
+
+use ash_video::mp4::{Boxes, SampleInfo, SampleTable};
+
+pub const NON_SYNC: u32 = 0x1_0000;
+
+pub fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = ((8 + payload.len()) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(box_type);
+    data.extend_from_slice(payload);
+    data
+}
+
+pub fn full_box(box_type: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = flags.to_be_bytes();
+    data[0] = version;
+    mp4_box(box_type, &[&data[..], payload].concat())
+}
+
+pub fn words(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+pub fn trex(track_id: u32, duration: u32, size: u32, flags: u32) -> Vec<u8> {
+    full_box(b"trex", 0, 0, &words(&[track_id, 1, duration, size, flags]))
+}
+
+/// The `moov` of a progressive file with its sample tables emptied and `mvex` added, as
+/// packagers write init segments.
+pub fn init_segment(data: &[u8], mvex: &[u8]) -> Vec<u8> {
+    fn rewrite(data: &[u8], mvex: &[u8]) -> Vec<u8> {
+        let mut rewritten = Vec::new();
+        for child in Boxes::new(data) {
+            let (box_type, payload) = child.unwrap();
+            match &box_type {
+                b"moov" => {
+                    let mut children = rewrite(payload, mvex);
+                    children.extend_from_slice(mvex);
+                    rewritten.extend(mp4_box(&box_type, &children));
+                }
+                b"trak" | b"mdia" | b"minf" | b"stbl" => {
+                    rewritten.extend(mp4_box(&box_type, &rewrite(payload, mvex)))
+                }
+                b"stts" | b"stsc" | b"stco" => {
+                    rewritten.extend(full_box(&box_type, 0, 0, &words(&[0])))
+                }
+                b"stsz" => rewritten.extend(full_box(&box_type, 0, 0, &words(&[0, 0]))),
+                b"stss" | b"ctts" | b"mdat" | b"free" => {}
+                _ => rewritten.extend(mp4_box(&box_type, payload)),
+            }
+        }
+        rewritten
+    }
+    rewrite(data, mvex)
+}
+
+/// Rebuilds `data`, descending into the boxes that lead to the edit and sample tables.
+/// `replace` returns the boxes taking the place of a box, or `None` to keep it.
+pub fn rewrite_boxes<F>(data: &[u8], replace: &mut F) -> Vec<u8>
+where
+    F: FnMut(&[u8; 4], &[u8]) -> Option<Vec<u8>>,
+{
+    let mut rewritten = Vec::new();
+    for child in Boxes::new(data) {
+        let (box_type, payload) = child.unwrap();
+        match (replace(&box_type, payload), &box_type) {
+            (Some(boxes), _) => rewritten.extend(boxes),
+            (None, b"moov" | b"trak" | b"edts" | b"mdia" | b"minf" | b"stbl") => {
+                let children = rewrite_boxes(payload, replace);
+                rewritten.extend(mp4_box(&box_type, &children))
+            }
+            (None, _) => rewritten.extend(mp4_box(&box_type, payload)),
+        }
+    }
+    rewritten
+}
+
+/// A media segment holding `samples` in one `trun` with every field present.
+pub fn media_segment(
+    sequence_number: u32,
+    track_id: u32,
+    samples: &[(SampleInfo, u32)],
+    data: &[u8],
+) -> Vec<u8> {
+    let moof = |data_offset: u32| {
+        let mut run = words(&[samples.len() as u32, data_offset]);
+        for (sample, duration) in samples {
+            let flags = if sample.is_keyframe { 0 } else { NON_SYNC };
+            let composition_offset = (sample.pts - sample.dts as i64) as i32 as u32;
+            run.extend(words(&[*duration, sample.size, flags, composition_offset]));
+        }
+        let traf = [
+            // default-base-is-moof
+            full_box(b"tfhd", 0, 0x02_0000, &words(&[track_id])),
+            full_box(b"tfdt", 1, 0, &samples[0].0.dts.to_be_bytes()),
+            full_box(b"trun", 1, 0xf01, &run),
+        ]
+        .concat();
+        mp4_box(
+            b"moof",
+            &[
+                full_box(b"mfhd", 0, 0, &words(&[sequence_number])),
+                mp4_box(b"traf", &traf),
+            ]
+            .concat(),
+        )
+    };
+
+    let moof = moof(moof(0).len() as u32 + 8);
+    let mdat: Vec<u8> = samples
+        .iter()
+        .flat_map(|(sample, _)| {
+            let offset = sample.offset as usize;
+            data[offset..offset + sample.size as usize].iter().copied()
+        })
+        .collect();
+    [
+        mp4_box(b"styp", b"msdh\0\0\0\0msdhmsix"),
+        moof,
+        mp4_box(b"mdat", &mdat),
+    ]
+    .concat()
+}
+
+/// Splits the video track of a progressive file into an init segment and media segments of
+/// `samples_per_fragment` samples.
+pub fn fragment(data: &[u8], samples_per_fragment: usize) -> (Vec<u8>, Vec<Vec<u8>>) {
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(data)).unwrap();
+    let track = context
+        .tracks
+        .iter()
+        .find(|track| track.track_type == mp4parse::TrackType::Video)
+        .unwrap();
+    let track_id = track.track_id.unwrap();
+    let sample_table = SampleTable::new(track, context.timescale).unwrap();
+
+    // Sample durations from the decode times, the last one repeating the one before
+    let samples = &sample_table.samples;
+    let durations: Vec<u32> = samples
+        .windows(2)
+        .map(|pair| (pair[1].dts - pair[0].dts) as u32)
+        .collect();
+    let samples: Vec<(SampleInfo, u32)> = samples
+        .iter()
+        .zip(durations.iter().chain(durations.last()))
+        .map(|(sample, &duration)| (*sample, duration))
+        .collect();
+
+    let init = init_segment(data, &mp4_box(b"mvex", &trex(track_id, 0, 0, NON_SYNC)));
+    let segments = samples
+        .chunks(samples_per_fragment)
+        .enumerate()
+        .map(|(index, fragment)| media_segment(index as u32 + 1, track_id, fragment, data))
+        .collect();
+    (init, segments)
+}
@@ -0,0 +1,52 @@
+//! Stream readers shared by the integration tests.
+#![allow(dead_code)]
+
+pub mod bitwriter;
+pub mod fmp4;
+pub mod mpegts;
+
+use ash_video::h264::avcc::AvcDecoderConfiguration;
+use ash_video::h264::ParameterSets;
+use ash_video::{mp4, Timestamp};
+
+pub const ANNEXB_STREAM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/a.h264");
+pub const MP4_STREAM: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/samples/Big_Buck_Bunny_360_10s_1MB.mp4"
+);
+
+/// Reads the parameter sets from the avcC box and the samples of the first video track
+/// as Annex-B access units with their presentation time.
+pub fn read_mp4(path: &str) -> (ParameterSets, Vec<(Vec<u8>, Timestamp)>) {
+    let data = std::fs::read(path).unwrap();
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(&data)).unwrap();
+    let track = context
+        .tracks
+        .iter()
+        .find(|track| track.track_type == mp4parse::TrackType::Video)
+        .unwrap();
+
+    let entry = match &track.stsd.as_ref().unwrap().descriptions[0] {
+        mp4parse::SampleEntry::Video(entry) => entry,
+        _ => panic!("expected a video sample entry"),
+    };
+    let avcc = match &entry.codec_specific {
+        mp4parse::VideoCodecSpecific::AVCConfig(avcc) => avcc,
+        _ => panic!("expected avcC"),
+    };
+
+    let config = AvcDecoderConfiguration::parse(avcc).unwrap();
+    let parameter_sets = config.parameter_sets().unwrap();
+    let length_size = config.length_size;
+
+    let sample_table = mp4::SampleTable::new(track, context.timescale).unwrap();
+    let access_units = sample_table
+        .iter(&data)
+        .map(|sample| {
+            let sample = sample.unwrap();
+            (sample.to_annexb(length_size).unwrap(), sample.pts)
+        })
+        .collect();
+
+    (parameter_sets, access_units)
+}
@@ -0,0 +1,133 @@
+//! An MPEG-2 transport stream muxer for video PES packets.
+
+use ash_video::ts;
+
+pub const PMT_PID: u16 = 0x1000;
+pub const VIDEO_PID: u16 = 0x100;
+pub const AUDIO_PID: u16 = 0x101;
+
+pub fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0xffff_ffff, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u32) << 24, |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// A PSI section with its length and CRC filled in.
+pub fn section(table_id: u8, table_id_extension: u16, body: &[u8]) -> Vec<u8> {
+    let length = 5 + body.len() + 4;
+    let mut section = vec![table_id, 0xb0 | (length >> 8) as u8, length as u8];
+    section.extend_from_slice(&table_id_extension.to_be_bytes());
+    section.extend_from_slice(&[0xc1, 0, 0]);
+    section.extend_from_slice(body);
+    let crc = crc32(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+    section
+}
+
+pub fn pes_timestamp(prefix: u8, timestamp: i64) -> [u8; 5] {
+    [
+        (prefix << 4) | (((timestamp >> 29) & 0b1110) as u8) | 1,
+        (timestamp >> 22) as u8,
+        (((timestamp >> 14) & 0xfe) as u8) | 1,
+        (timestamp >> 7) as u8,
+        (((timestamp << 1) & 0xfe) as u8) | 1,
+    ]
+}
+
+/// A video PES packet of unbounded length.
+pub fn pes(pts: Option<i64>, dts: Option<i64>, payload: &[u8]) -> Vec<u8> {
+    let mut header = Vec::new();
+    let flags = match (pts, dts) {
+        (Some(pts), Some(dts)) => {
+            header.extend_from_slice(&pes_timestamp(0b0011, pts));
+            header.extend_from_slice(&pes_timestamp(0b0001, dts));
+            0xc0
+        }
+        (Some(pts), None) => {
+            header.extend_from_slice(&pes_timestamp(0b0010, pts));
+            0x80
+        }
+        _ => 0,
+    };
+    let mut pes = vec![0, 0, 1, 0xe0, 0, 0, 0x80, flags, header.len() as u8];
+    pes.extend(header);
+    pes.extend_from_slice(payload);
+    pes
+}
+
+/// Writes TS packets, keeping a continuity counter per PID.
+pub struct Muxer {
+    pub packets: Vec<Vec<u8>>,
+    continuity_counters: [u8; 0x2000],
+}
+
+impl Muxer {
+    pub fn new() -> Self {
+        Self {
+            packets: Vec::new(),
+            continuity_counters: [0; 0x2000],
+        }
+    }
+
+    /// Splits `payload` into packets, the first one flagged as a unit start and the last
+    /// one filled up with adaptation field stuffing.
+    pub fn write(&mut self, pid: u16, mut payload: &[u8], random_access: bool) {
+        let mut first = true;
+        while first || !payload.is_empty() {
+            let flags = if first && random_access { 0x40 } else { 0 };
+            let size = payload.len().min(if flags != 0 { 182 } else { 184 });
+            // Length byte, flags and stuffing
+            let adaptation_field = 184 - size;
+
+            let counter = &mut self.continuity_counters[pid as usize];
+            let control = if adaptation_field > 0 { 0x30 } else { 0x10 };
+            let mut packet = vec![
+                0x47,
+                (first as u8) << 6 | (pid >> 8) as u8,
+                pid as u8,
+                control | *counter,
+            ];
+            *counter = (*counter + 1) & 0xf;
+
+            if adaptation_field > 0 {
+                packet.push(adaptation_field as u8 - 1);
+                if adaptation_field > 1 {
+                    packet.push(flags);
+                    packet.resize(4 + adaptation_field, 0xff);
+                }
+            }
+            packet.extend_from_slice(&payload[..size]);
+            assert_eq!(packet.len(), ts::PACKET_SIZE);
+            self.packets.push(packet);
+
+            payload = &payload[size..];
+            first = false;
+        }
+    }
+
+    pub fn write_tables(&mut self, stream_type: u8) {
+        let pat = section(0x00, 1, &[0, 1, 0xe0 | (PMT_PID >> 8) as u8, PMT_PID as u8]);
+        let mut pointer_field = vec![0];
+        pointer_field.extend(pat);
+        self.write(0, &pointer_field, false);
+
+        let mut body = vec![0xe0 | (VIDEO_PID >> 8) as u8, VIDEO_PID as u8, 0xf0, 0];
+        // An audio stream ahead of the video one
+        for (stream_type, pid) in [(0x0f, AUDIO_PID), (stream_type, VIDEO_PID)] {
+            body.extend_from_slice(&[stream_type, 0xe0 | (pid >> 8) as u8, pid as u8, 0xf0, 0]);
+        }
+        let mut pointer_field = vec![0];
+        pointer_field.extend(section(0x02, 1, &body));
+        self.write(PMT_PID, &pointer_field, false);
+    }
+
+    pub fn finish(&self) -> Vec<u8> {
+        self.packets.concat()
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use ash_video::h264::software::SoftwareBackend;
+use ash_video::{codec, Codec, Decoder, HeadlessBase};
+
+mod common;
+
+/// Decodes every `samples/*.mp4` on the first device with H.264 decode support, or on the
+/// CPU on machines without one.
+#[test]
+fn decode_all_samples() {
+    let base = HeadlessBase::new(Codec::H264);
+    if let Err(error) = &base {
+        eprintln!("no video decode device, decoding on the CPU: {}", error);
+    }
+
+    let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+    let mut paths: Vec<_> = std::fs::read_dir(samples)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "mp4"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let (parameter_sets, access_units) = common::read_mp4(path.to_str().unwrap());
+
+        let parameter_sets = codec::ParameterSets::from(parameter_sets);
+        let mut pts = Vec::new();
+        match &base {
+            Ok(base) => {
+                let mut decoder = unsafe {
+                    Decoder::new(
+                        &base.entry,
+                        &base.instance,
+                        &base.device,
+                        base.pdevice,
+                        base.decode_queue_family_index,
+                        base.decode_queue,
+                        &parameter_sets,
+                    )
+                    .unwrap()
+                };
+                for (access_unit, sample_pts) in access_units.iter() {
+                    let frames = decoder.decode(access_unit, *sample_pts).unwrap();
+                    pts.extend(frames.iter().map(|frame| frame.pts));
+                }
+                pts.extend(decoder.flush().iter().map(|frame| frame.pts));
+            }
+            Err(_) => {
+                let mut decoder =
+                    Decoder::with_backend(SoftwareBackend::default(), &parameter_sets).unwrap();
+                for (access_unit, sample_pts) in access_units.iter() {
+                    let frames = decoder.decode(access_unit, *sample_pts).unwrap();
+                    pts.extend(frames.iter().map(|frame| frame.pts));
+                }
+                pts.extend(decoder.flush().iter().map(|frame| frame.pts));
+            }
+        }
+
+        assert_eq!(pts.len(), access_units.len(), "{}", path.display());
+        assert!(
+            pts.windows(2).all(|pair| pair[0] < pair[1]),
+            "{} not in display order",
+            path.display()
+        );
+    }
+}
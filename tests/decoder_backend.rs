@@ -0,0 +1,348 @@
+use ash_video::annexb::AnnexBReader;
+use ash_video::codec::{self, Codec};
+use ash_video::decoder::backend::{BackendCapabilities, PictureInfo, ReferenceInfo};
+use ash_video::decoder::mock::{Call, MockBackend, MockFrame};
+use ash_video::h264::{NalUnitHeader, NalUnitType, NalUnits, ParameterSets};
+use ash_video::{Decoder, Timestamp};
+
+mod common;
+use common::bitwriter::BitWriter;
+use common::{ANNEXB_STREAM, MP4_STREAM};
+
+fn annexb_access_units() -> (ParameterSets, Vec<(Vec<u8>, Timestamp)>) {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    let parameter_sets = AnnexBReader::read_parameter_sets(&data).unwrap();
+    let access_units = AnnexBReader::new(&data)
+        .enumerate()
+        .map(|(index, access_unit)| {
+            (
+                access_unit.unwrap().data.to_vec(),
+                Timestamp::new(index as i64, 25),
+            )
+        })
+        .collect();
+    (parameter_sets, access_units)
+}
+
+/// Decodes all access units, checking that every returned frame is still in its slot.
+fn decode_all(
+    decoder: &mut Decoder<MockBackend>,
+    access_units: &[(Vec<u8>, Timestamp)],
+) -> Vec<MockFrame> {
+    let mut frames = Vec::new();
+    for (access_unit, pts) in access_units {
+        let output = decoder.decode(access_unit, *pts).unwrap();
+        for frame in &output {
+            assert_eq!(decoder.backend().slots[frame.slot], Some(frame.picture));
+        }
+        frames.extend(output);
+    }
+    let output = decoder.flush();
+    for frame in &output {
+        assert_eq!(decoder.backend().slots[frame.slot], Some(frame.picture));
+    }
+    frames.extend(output);
+    frames
+}
+
+/// An SPS like that of the Annex-B sample: High profile 4:2:0 with pic_order_cnt_type 2
+/// and as many frames buffered as referenced, here at level 3.0.
+fn annexb_like_sps(
+    seq_parameter_set_id: u32,
+    width_in_mbs: u32,
+    height_in_mbs: u32,
+    max_num_ref_frames: u32,
+) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer
+        .bits(100, 8)
+        .bits(0, 8)
+        .bits(30, 8)
+        .ue(seq_parameter_set_id)
+        .ue(1)
+        .ue(0)
+        .ue(0)
+        .flag(false)
+        .flag(false)
+        .ue(0)
+        .ue(2)
+        .ue(max_num_ref_frames)
+        .flag(false)
+        .ue(width_in_mbs - 1)
+        .ue(height_in_mbs - 1)
+        .flag(true)
+        .flag(true)
+        .flag(false)
+        .flag(true);
+    // VUI with only the bitstream restrictions
+    for _ in 0..8 {
+        writer.flag(false);
+    }
+    writer
+        .flag(true)
+        .flag(true)
+        .ue(0)
+        .ue(0)
+        .ue(9)
+        .ue(9)
+        .ue(0)
+        .ue(max_num_ref_frames);
+    writer.nal(3, 7)
+}
+
+/// `access_unit` with its SPS replaced by `sps`.
+fn replace_sps(access_unit: &[u8], sps: &[u8]) -> Vec<u8> {
+    let mut replaced = Vec::new();
+    for nal in NalUnits::new(access_unit) {
+        replaced.extend_from_slice(&[0, 0, 0, 1]);
+        match NalUnitHeader::parse(nal.data).unwrap().nal_unit_type {
+            NalUnitType::Sps => replaced.extend_from_slice(sps),
+            _ => replaced.extend_from_slice(nal.data),
+        }
+    }
+    replaced
+}
+
+fn h264_picture(picture: &PictureInfo) -> &ash::vk::native::StdVideoDecodeH264PictureInfo {
+    match picture {
+        PictureInfo::H264 {
+            std_picture_info, ..
+        } => std_picture_info,
+        _ => panic!("expected an H.264 picture, got {:?}", picture),
+    }
+}
+
+#[test]
+fn annexb_stream_calls() {
+    let (parameter_sets, access_units) = annexb_access_units();
+    let sps = parameter_sets.sps(0).unwrap().clone();
+    let parameter_sets = codec::ParameterSets::from(parameter_sets);
+    let mut decoder = Decoder::with_backend(MockBackend::default(), &parameter_sets).unwrap();
+    let frames = decode_all(&mut decoder, &access_units);
+
+    // The session is created for the SPS and gets the parameter sets once, the ones
+    // repeated in-band are the same
+    let calls = &decoder.backend().calls;
+    let max_num_ref_frames = (sps.max_num_ref_frames as u32).max(1);
+    match &calls[0] {
+        Call::CreateSession(info) => {
+            assert_eq!(info.codec, Codec::H264);
+            assert_eq!(info.coded_extent, (176, 144));
+            assert_eq!(
+                info.dpb_slots,
+                max_num_ref_frames + sps.max_num_reorder_frames() + 1
+            );
+            assert_eq!(info.max_active_reference_pictures, max_num_ref_frames);
+        }
+        call => panic!("expected the session to be created first, got {:?}", call),
+    }
+    assert!(matches!(&calls[1], Call::UpdateParameters(update) if *update == parameter_sets));
+
+    // Every picture is decoded between begin and end of coding, the first one resets
+    // the session
+    assert_eq!(calls.len(), 2 + 3 * access_units.len());
+    for (index, chunk) in calls[2..].chunks(3).enumerate() {
+        match chunk {
+            [Call::BeginCoding {
+                reset,
+                reference_slots,
+                setup_slot,
+            }, Call::Decode {
+                setup_slot: decode_setup_slot,
+                reference_slots: decode_reference_slots,
+                bitstream,
+                ..
+            }, Call::EndCoding] => {
+                assert_eq!(*reset, index == 0);
+                assert_eq!(*setup_slot, decode_setup_slot.slot);
+                let slots = |references: &[_]| {
+                    references
+                        .iter()
+                        .map(|reference: &ash_video::decoder::backend::ReferenceSlot| {
+                            reference.slot
+                        })
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(slots(reference_slots), slots(decode_reference_slots));
+                // Only the slices are passed on
+                assert!(bitstream.starts_with(&[0, 0, 1]) || bitstream.starts_with(&[0, 0, 0, 1]));
+                assert!(access_units[index]
+                    .0
+                    .windows(bitstream.len())
+                    .any(|window| window == &bitstream[..]));
+            }
+            calls => panic!("picture {} decoded with {:?}", index, calls),
+        }
+    }
+
+    // Groups of an IDR picture without references, then P pictures each referring to the
+    // one before
+    let decodes: Vec<_> = decoder.backend().decodes().collect();
+    assert_eq!(decodes.len(), 30);
+    for (index, (picture, _, references)) in decodes.iter().enumerate() {
+        let idr = h264_picture(picture).flags.IdrPicFlag() == 1;
+        assert_eq!(idr, index % 10 == 0);
+        assert_eq!(idr, references.is_empty());
+    }
+    for pair in decodes.windows(2) {
+        let (_, previous_setup_slot, _) = pair[0];
+        let (picture, _, references) = pair[1];
+        let picture = h264_picture(picture);
+        if picture.flags.IdrPicFlag() == 1 {
+            continue;
+        }
+
+        let previous_reference = references
+            .iter()
+            .find(|reference| reference.slot == previous_setup_slot.slot)
+            .expect("the previous picture is a reference");
+        let (ReferenceInfo::H264(previous), ReferenceInfo::H264(reference)) =
+            (previous_setup_slot.info, previous_reference.info)
+        else {
+            panic!("expected H.264 reference infos");
+        };
+        assert_eq!(reference.FrameNum, previous.FrameNum);
+        assert_eq!(reference.PicOrderCnt, previous.PicOrderCnt);
+        assert_eq!(
+            picture.frame_num as u32,
+            (previous.FrameNum as u32 + 1) % sps.max_frame_num()
+        );
+    }
+
+    // No reordering, frames come out as they are decoded
+    assert_eq!(frames.len(), 30);
+    for (index, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.picture, index);
+        assert_eq!(frame.pts, Timestamp::new(index as i64, 25));
+    }
+}
+
+#[test]
+fn mp4_sample_output_order() {
+    let (parameter_sets, access_units) = common::read_mp4(MP4_STREAM);
+    let parameter_sets = codec::ParameterSets::from(parameter_sets);
+    let mut decoder = Decoder::with_backend(MockBackend::default(), &parameter_sets).unwrap();
+    let frames = decode_all(&mut decoder, &access_units);
+
+    assert_eq!(frames.len(), access_units.len());
+    assert!(frames.windows(2).all(|pair| pair[0].pts < pair[1].pts));
+
+    // B pictures are decoded after the P pictures they refer to and output before them
+    assert!(frames
+        .iter()
+        .enumerate()
+        .any(|(index, frame)| frame.picture != index));
+}
+
+#[test]
+fn dpb_slots_follow_capabilities() {
+    let (parameter_sets, access_units) = common::read_mp4(MP4_STREAM);
+    let parameter_sets = codec::ParameterSets::from(parameter_sets);
+    let backend = MockBackend::new(BackendCapabilities {
+        max_dpb_slots: 17,
+        max_active_reference_pictures: 16,
+    });
+    let mut decoder = Decoder::with_backend(backend, &parameter_sets).unwrap();
+    match &decoder.backend().calls[0] {
+        Call::CreateSession(info) => {
+            assert_eq!(info.dpb_slots, 17);
+            assert_eq!(info.max_active_reference_pictures, 16);
+        }
+        call => panic!("expected the session to be created first, got {:?}", call),
+    }
+
+    // The 16 reference frames leave no slot to reorder in, frames come out as they are
+    // decoded
+    let frames = decode_all(&mut decoder, &access_units);
+    assert_eq!(frames.len(), access_units.len());
+    for (index, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.picture, index);
+    }
+}
+
+#[test]
+fn in_band_parameter_sets_update_the_session() {
+    let (_, access_units) = annexb_access_units();
+
+    // Start out with the SPS only, the PPS comes with the first access unit
+    let mut parameter_sets = ParameterSets::default();
+    for nal in NalUnits::new(&access_units[0].0) {
+        if NalUnitHeader::parse(nal.data).unwrap().nal_unit_type == NalUnitType::Sps {
+            parameter_sets.add_nal(nal.data).unwrap();
+        }
+    }
+    assert_eq!(parameter_sets.pps_count(), 0);
+    let parameter_sets = codec::ParameterSets::from(parameter_sets);
+    let mut decoder = Decoder::with_backend(MockBackend::default(), &parameter_sets).unwrap();
+    decode_all(&mut decoder, &access_units[..2]);
+
+    let calls = &decoder.backend().calls;
+    assert!(matches!(&calls[1], Call::UpdateParameters(update) if *update == parameter_sets));
+    match &calls[2] {
+        Call::UpdateParameters(codec::ParameterSets::H264(update)) => {
+            assert_eq!(update.pps_count(), 1)
+        }
+        call => panic!("expected the parameter sets to be updated, got {:?}", call),
+    }
+    assert!(matches!(calls[3], Call::BeginCoding { reset: true, .. }));
+    // The second access unit repeats them unchanged
+    assert_eq!(
+        calls
+            .iter()
+            .filter(|call| matches!(call, Call::UpdateParameters(_)))
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn in_band_sps_must_fit_the_session() {
+    let (parameter_sets, access_units) = annexb_access_units();
+    let parameter_sets = codec::ParameterSets::from(parameter_sets);
+    let mut decoder = Decoder::with_backend(MockBackend::default(), &parameter_sets).unwrap();
+    for (access_unit, pts) in &access_units[..10] {
+        decoder.decode(access_unit, *pts).unwrap();
+    }
+
+    // The second IDR picture, whose SPS and PPS are repeated in band
+    let (idr, pts) = &access_units[10];
+    let mut pts = *pts;
+    let mut decode = |access_unit: Vec<u8>| {
+        pts = pts + Timestamp::new(1, 25);
+        decoder.decode(&access_unit, pts)
+    };
+    // The same size and reference frames as the sample
+    decode(replace_sps(idr, &annexb_like_sps(0, 11, 9, 3))).unwrap();
+    // A larger SPS that no picture refers to
+    decode([&[0, 0, 0, 1], &annexb_like_sps(1, 40, 23, 3)[..], idr].concat()).unwrap();
+
+    // The pictures would no longer fit the images of the session
+    let error = decode(replace_sps(idr, &annexb_like_sps(0, 40, 23, 3))).unwrap_err();
+    assert!(
+        error.to_string().contains("176x144 to 640x368"),
+        "{}",
+        error
+    );
+    // Nor their references in its DPB
+    assert!(decode(replace_sps(idr, &annexb_like_sps(0, 11, 9, 4))).is_err());
+
+    // Neither SPS reached the backend
+    let calls = &decoder.backend().calls;
+    assert_eq!(
+        calls
+            .iter()
+            .filter(|call| matches!(call, Call::CreateSession(_)))
+            .count(),
+        1
+    );
+    let last_update = calls
+        .iter()
+        .rev()
+        .find_map(|call| match call {
+            Call::UpdateParameters(codec::ParameterSets::H264(update)) => Some(update),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(last_update.sps(0).unwrap().coded_extent(), (176, 144));
+    assert_eq!(last_update.sps(0).unwrap().max_num_ref_frames, 3);
+}
@@ -0,0 +1,137 @@
+use ash_video::h264::avcc::{AvcConfigError, AvcDecoderConfiguration, HighProfileExtension};
+
+mod common;
+use common::MP4_STREAM;
+
+const CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/corpus/avcc");
+
+/// The avcC box payload of the first video track.
+fn read_avcc(path: &str) -> Vec<u8> {
+    let data = std::fs::read(path).unwrap();
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(&data)).unwrap();
+    let track = context
+        .tracks
+        .iter()
+        .find(|track| track.track_type == mp4parse::TrackType::Video)
+        .unwrap();
+    match &track.stsd.as_ref().unwrap().descriptions[0] {
+        mp4parse::SampleEntry::Video(entry) => match &entry.codec_specific {
+            mp4parse::VideoCodecSpecific::AVCConfig(avcc) => avcc.to_vec(),
+            _ => panic!("expected avcC"),
+        },
+        _ => panic!("expected a video sample entry"),
+    }
+}
+
+fn parse(name: &str) -> Result<AvcDecoderConfiguration, AvcConfigError> {
+    let data = std::fs::read(format!("{}/{}", CORPUS, name)).unwrap();
+    AvcDecoderConfiguration::parse(&data)
+}
+
+#[test]
+fn sample_record() {
+    let avcc = read_avcc(MP4_STREAM);
+    let config = AvcDecoderConfiguration::parse(&avcc).unwrap();
+    assert_eq!(config.profile_indication, 100);
+    assert_eq!(config.level_indication, 31);
+    assert_eq!(config.length_size, 4);
+    assert_eq!(config.sps.len(), 1);
+    assert_eq!(config.pps.len(), 1);
+    // Left out by the muxer although the stream is High profile
+    assert_eq!(config.high_profile, None);
+
+    let parameter_sets = config.parameter_sets().unwrap();
+    let sps = parameter_sets.sps(0).unwrap();
+    assert_eq!(sps.profile_idc, 100);
+    assert!(parameter_sets.pps(0).is_some());
+
+    // The corpus holds the same record
+    assert_eq!(parse("big_buck_bunny_360").unwrap(), config);
+}
+
+#[test]
+fn high_profile_extension() {
+    let config = parse("high_extension").unwrap();
+    assert_eq!(
+        config.high_profile,
+        Some(HighProfileExtension {
+            chroma_format: 2,
+            bit_depth_luma: 10,
+            bit_depth_chroma: 10,
+            sps_ext: Vec::new(),
+        })
+    );
+    assert_eq!(config.sps, parse("big_buck_bunny_360").unwrap().sps);
+
+    assert!(matches!(
+        parse("high_extension_truncated"),
+        Err(AvcConfigError::Truncated {
+            offset: 47,
+            len: 47
+        })
+    ));
+    assert!(matches!(
+        parse("high_extension_sps_ext_oversized"),
+        Err(AvcConfigError::OversizedNalUnit {
+            length: 16,
+            remaining: 1
+        })
+    ));
+}
+
+#[test]
+fn invalid_records() {
+    assert_eq!(
+        parse("empty"),
+        Err(AvcConfigError::Truncated { offset: 0, len: 0 })
+    );
+    assert_eq!(
+        parse("fixed_part_only"),
+        Err(AvcConfigError::Truncated { offset: 6, len: 6 })
+    );
+    assert!(matches!(
+        parse("sps_truncated"),
+        Err(AvcConfigError::OversizedNalUnit { length: 28, .. })
+    ));
+    assert!(matches!(
+        parse("pps_count_missing"),
+        Err(AvcConfigError::Truncated { offset: 36, .. })
+    ));
+    assert!(matches!(
+        parse("pps_truncated"),
+        Err(AvcConfigError::OversizedNalUnit { length: 6, .. })
+    ));
+    assert_eq!(
+        parse("version_0"),
+        Err(AvcConfigError::UnsupportedVersion(0))
+    );
+    assert_eq!(
+        parse("length_size_3"),
+        Err(AvcConfigError::InvalidLengthSize(2))
+    );
+    assert!(matches!(
+        parse("sps_length_oversized"),
+        Err(AvcConfigError::OversizedNalUnit { length: 0xffff, .. })
+    ));
+    assert_eq!(parse("sps_length_zero"), Err(AvcConfigError::EmptyNalUnit));
+
+    // Errors convert into anyhow like the other parsers'
+    let error = anyhow::Error::from(parse("version_0").unwrap_err());
+    assert_eq!(error.to_string(), "Unsupported avcC version 0");
+}
+
+/// Every corpus entry and every prefix of it parses or fails without panicking.
+#[test]
+fn corpus_replay() {
+    let mut entries = 0;
+    for entry in std::fs::read_dir(CORPUS).unwrap() {
+        let data = std::fs::read(entry.unwrap().path()).unwrap();
+        for end in 0..=data.len() {
+            if let Ok(config) = AvcDecoderConfiguration::parse(&data[..end]) {
+                let _ = config.parameter_sets();
+            }
+        }
+        entries += 1;
+    }
+    assert!(entries >= 2);
+}
@@ -0,0 +1,759 @@
+use ash_video::annexb::{AccessUnit, AnnexBReader};
+use ash_video::h264::dpb::{CurrentPicture, Dpb};
+use ash_video::h264::slice::{self, DecRefPicMarking, Mmco, SliceHeader, SliceType};
+use ash_video::h264::sps::Sps;
+
+mod common;
+use common::{ANNEXB_STREAM, MP4_STREAM};
+
+/// The SPS of the Annex-B stream, for tests that tweak the POC or reference settings.
+fn sps() -> Sps {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    let parameter_sets = AnnexBReader::read_parameter_sets(&data).unwrap();
+    parameter_sets.sps(0).unwrap().clone()
+}
+
+fn sps_with(pic_order_cnt_type: u8, max_num_ref_frames: u8) -> Sps {
+    Sps {
+        pic_order_cnt_type,
+        max_num_ref_frames,
+        // MaxFrameNum 16, MaxPicOrderCntLsb 16
+        log2_max_frame_num_minus4: 0,
+        log2_max_pic_order_cnt_lsb_minus4: 0,
+        ..sps()
+    }
+}
+
+fn idr(pic_order_cnt_lsb: u32) -> SliceHeader {
+    SliceHeader {
+        nal_ref_idc: 3,
+        idr_pic_flag: true,
+        pic_order_cnt_lsb,
+        dec_ref_pic_marking: Some(DecRefPicMarking::default()),
+        ..Default::default()
+    }
+}
+
+fn reference(frame_num: u32, pic_order_cnt_lsb: u32) -> SliceHeader {
+    SliceHeader {
+        nal_ref_idc: 2,
+        slice_type: SliceType::P,
+        frame_num,
+        pic_order_cnt_lsb,
+        dec_ref_pic_marking: Some(DecRefPicMarking::default()),
+        ..Default::default()
+    }
+}
+
+fn non_reference(frame_num: u32, pic_order_cnt_lsb: u32) -> SliceHeader {
+    SliceHeader {
+        nal_ref_idc: 0,
+        slice_type: SliceType::B,
+        frame_num,
+        pic_order_cnt_lsb,
+        ..Default::default()
+    }
+}
+
+fn with_mmco(mut header: SliceHeader, mmco: Vec<Mmco>) -> SliceHeader {
+    header.dec_ref_pic_marking = Some(DecRefPicMarking {
+        adaptive_ref_pic_marking_mode_flag: true,
+        mmco,
+        ..Default::default()
+    });
+    header
+}
+
+fn decode(dpb: &mut Dpb, header: &SliceHeader, sps: &Sps) -> CurrentPicture {
+    let picture = dpb.start_picture(header, sps).unwrap();
+    assert!(
+        dpb.references()
+            .iter()
+            .all(|frame| frame.slot != Some(picture.slot)),
+        "slot {} of the current picture is still referenced",
+        picture.slot
+    );
+    dpb.finish_picture(header, sps, picture).unwrap()
+}
+
+/// (frame_num, long_term_frame_idx) of the reference frames, in DPB order
+fn references(dpb: &Dpb) -> Vec<(u32, Option<u32>)> {
+    dpb.references()
+        .iter()
+        .map(|frame| (frame.frame_num, frame.long_term_frame_idx))
+        .collect()
+}
+
+fn check_slots(dpb: &Dpb) {
+    let mut slots: Vec<_> = dpb
+        .references()
+        .iter()
+        .filter_map(|frame| frame.slot)
+        .collect();
+    let count = slots.len();
+    slots.sort_unstable();
+    slots.dedup();
+    assert_eq!(slots.len(), count, "slot used twice");
+    assert!(slots.iter().all(|&slot| slot < dpb.slot_count()));
+}
+
+#[test]
+fn annexb_stream() {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    let mut reader = AnnexBReader::new(&data);
+    let mut dpb = Dpb::new(17);
+    let mut count = 0;
+
+    while let Some(access_unit) = reader.next() {
+        let AccessUnit { slices, .. } = access_unit.unwrap();
+        let header = &slices[0].header;
+        let (_, sps) = reader
+            .parameter_sets()
+            .active(header.pic_parameter_set_id)
+            .unwrap();
+        assert_eq!(sps.pic_order_cnt_type, 2);
+
+        let picture = decode(&mut dpb, header, sps);
+        check_slots(&dpb);
+        assert!(dpb.references().len() <= sps.max_num_ref_frames.max(1) as usize);
+
+        // No reordering and every frame is a reference, with an IDR picture every 10 frames
+        assert_eq!(picture.idr, count % 10 == 0);
+        assert_eq!(picture.pic_order_cnt(), 2 * (count % 10));
+        count += 1;
+    }
+    assert_eq!(count, 30);
+}
+
+#[test]
+fn mp4_stream_poc_matches_presentation_order() {
+    let (mut parameter_sets, access_units) = common::read_mp4(MP4_STREAM);
+    let sps = parameter_sets.sps(0).unwrap().clone();
+    assert_eq!(sps.pic_order_cnt_type, 0);
+    let mut dpb = Dpb::new(sps.max_num_ref_frames as usize + 1);
+
+    // (IDR period, POC) and the presentation time of every sample
+    let mut idr_period = 0;
+    let mut pictures = Vec::new();
+    for (access_unit, pts) in access_units {
+        let slices = slice::parse_slices(&access_unit, &mut parameter_sets).unwrap();
+        let header = &slices[0].header;
+
+        let picture = decode(&mut dpb, header, &sps);
+        check_slots(&dpb);
+        assert!(dpb.references().len() <= sps.max_num_ref_frames as usize);
+        if picture.idr {
+            idr_period += 1;
+        }
+        pictures.push(((idr_period, picture.pic_order_cnt()), pts));
+    }
+    assert_eq!(pictures.len(), 300);
+
+    let mut by_poc = pictures.clone();
+    by_poc.sort_by_key(|&(poc, _)| poc);
+    let mut by_pts = pictures;
+    by_pts.sort_by_key(|&(_, pts)| pts);
+    assert_eq!(by_poc, by_pts);
+}
+
+#[test]
+fn sliding_window() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    assert_eq!(references(&dpb), [(0, None), (1, None)]);
+
+    // Non-reference pictures leave the DPB alone
+    decode(&mut dpb, &non_reference(2, 3), &sps);
+    assert_eq!(references(&dpb), [(0, None), (1, None)]);
+
+    decode(&mut dpb, &reference(2, 4), &sps);
+    assert_eq!(references(&dpb), [(1, None), (2, None)]);
+    decode(&mut dpb, &reference(3, 6), &sps);
+    assert_eq!(references(&dpb), [(2, None), (3, None)]);
+    check_slots(&dpb);
+}
+
+#[test]
+fn sliding_window_frame_num_wrap() {
+    let sps = sps_with(0, 3);
+    let mut dpb = Dpb::new(4);
+
+    decode(&mut dpb, &idr(0), &sps);
+    for frame_num in 1..16 {
+        decode(&mut dpb, &reference(frame_num, (2 * frame_num) % 16), &sps);
+    }
+    // frame_num wraps at MaxFrameNum 16, 15 is older than 0 now
+    decode(&mut dpb, &reference(0, 0), &sps);
+    assert_eq!(references(&dpb), [(14, None), (15, None), (0, None)]);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    assert_eq!(references(&dpb), [(15, None), (0, None), (1, None)]);
+
+    let wraps: Vec<_> = dpb
+        .references()
+        .iter()
+        .map(|frame| frame.frame_num_wrap)
+        .collect();
+    assert_eq!(wraps, [-1, 0, 1]);
+}
+
+#[test]
+fn sliding_window_keeps_long_term() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(1, 2),
+            vec![
+                Mmco::MaxLongTermFrameIdx {
+                    max_long_term_frame_idx_plus1: 1,
+                },
+                Mmco::ShortTermToLongTerm {
+                    difference_of_pic_nums_minus1: 0,
+                    long_term_frame_idx: 0,
+                },
+            ],
+        ),
+        &sps,
+    );
+    assert_eq!(references(&dpb), [(0, Some(0)), (1, None)]);
+
+    decode(&mut dpb, &reference(2, 4), &sps);
+    assert_eq!(references(&dpb), [(0, Some(0)), (2, None)]);
+}
+
+#[test]
+fn mmco_forget_short_term() {
+    let sps = sps_with(0, 4);
+    let mut dpb = Dpb::new(5);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    decode(&mut dpb, &reference(2, 4), &sps);
+
+    // picNumX = 3 - (1 + 1) = 1
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(3, 6),
+            vec![Mmco::ForgetShortTerm {
+                difference_of_pic_nums_minus1: 1,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(references(&dpb), [(0, None), (2, None), (3, None)]);
+}
+
+#[test]
+fn mmco_forget_short_term_across_wrap() {
+    let sps = sps_with(0, 4);
+    let mut dpb = Dpb::new(5);
+
+    decode(&mut dpb, &idr(0), &sps);
+    for frame_num in 1..16 {
+        decode(&mut dpb, &reference(frame_num, (2 * frame_num) % 16), &sps);
+    }
+    // picNumX = 0 - (1 + 1) = -2, the frame with frame_num 14
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(0, 0),
+            vec![Mmco::ForgetShortTerm {
+                difference_of_pic_nums_minus1: 1,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(
+        references(&dpb),
+        [(12, None), (13, None), (15, None), (0, None)]
+    );
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(1, 2),
+            vec![Mmco::ForgetShortTerm {
+                difference_of_pic_nums_minus1: 1,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(
+        references(&dpb),
+        [(12, None), (13, None), (0, None), (1, None)]
+    );
+}
+
+#[test]
+fn mmco_long_term() {
+    let sps = sps_with(0, 4);
+    let mut dpb = Dpb::new(5);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    decode(&mut dpb, &reference(2, 4), &sps);
+
+    // MMCO 4 then MMCO 3 on frame_num 0 and 2
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(3, 6),
+            vec![
+                Mmco::MaxLongTermFrameIdx {
+                    max_long_term_frame_idx_plus1: 2,
+                },
+                Mmco::ShortTermToLongTerm {
+                    difference_of_pic_nums_minus1: 2,
+                    long_term_frame_idx: 0,
+                },
+                Mmco::ShortTermToLongTerm {
+                    difference_of_pic_nums_minus1: 0,
+                    long_term_frame_idx: 1,
+                },
+            ],
+        ),
+        &sps,
+    );
+    assert_eq!(
+        references(&dpb),
+        [(0, Some(0)), (1, None), (2, Some(1)), (3, None)]
+    );
+    let long_term_pic_nums: Vec<_> = dpb
+        .references()
+        .iter()
+        .filter(|frame| frame.is_long_term())
+        .map(|frame| frame.pic_num())
+        .collect();
+    assert_eq!(long_term_pic_nums, [0, 1]);
+
+    // MMCO 3 onto an index in use replaces the long-term frame holding it
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(4, 8),
+            vec![Mmco::ShortTermToLongTerm {
+                difference_of_pic_nums_minus1: 2,
+                long_term_frame_idx: 0,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(
+        references(&dpb),
+        [(1, Some(0)), (2, Some(1)), (3, None), (4, None)]
+    );
+
+    // MMCO 2
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(5, 10),
+            vec![Mmco::ForgetLongTerm {
+                long_term_pic_num: 1,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(
+        references(&dpb),
+        [(1, Some(0)), (3, None), (4, None), (5, None)]
+    );
+
+    // MMCO 4 to "no long-term frame indices" drops the rest
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(6, 12),
+            vec![Mmco::MaxLongTermFrameIdx {
+                max_long_term_frame_idx_plus1: 0,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(
+        references(&dpb),
+        [(3, None), (4, None), (5, None), (6, None)]
+    );
+    check_slots(&dpb);
+}
+
+#[test]
+fn mmco_max_long_term_frame_idx() {
+    let sps = sps_with(0, 4);
+    let mut dpb = Dpb::new(5);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(2, 4),
+            vec![
+                Mmco::MaxLongTermFrameIdx {
+                    max_long_term_frame_idx_plus1: 3,
+                },
+                Mmco::ShortTermToLongTerm {
+                    difference_of_pic_nums_minus1: 1,
+                    long_term_frame_idx: 0,
+                },
+                Mmco::ShortTermToLongTerm {
+                    difference_of_pic_nums_minus1: 0,
+                    long_term_frame_idx: 2,
+                },
+            ],
+        ),
+        &sps,
+    );
+    assert_eq!(references(&dpb), [(0, Some(0)), (1, Some(2)), (2, None)]);
+
+    // Lowering MaxLongTermFrameIdx to 1 drops LongTermFrameIdx 2
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(3, 6),
+            vec![Mmco::MaxLongTermFrameIdx {
+                max_long_term_frame_idx_plus1: 2,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(references(&dpb), [(0, Some(0)), (2, None), (3, None)]);
+}
+
+#[test]
+fn mmco_current_to_long_term() {
+    let sps = sps_with(0, 3);
+    let mut dpb = Dpb::new(4);
+
+    decode(&mut dpb, &idr(0), &sps);
+    let picture = decode(
+        &mut dpb,
+        &with_mmco(
+            reference(1, 2),
+            vec![
+                Mmco::MaxLongTermFrameIdx {
+                    max_long_term_frame_idx_plus1: 1,
+                },
+                Mmco::CurrentToLongTerm {
+                    long_term_frame_idx: 0,
+                },
+            ],
+        ),
+        &sps,
+    );
+    assert_eq!(picture.long_term_frame_idx, Some(0));
+    assert_eq!(references(&dpb), [(0, None), (1, Some(0))]);
+
+    // Assigning the same index again replaces the previous long-term frame
+    decode(
+        &mut dpb,
+        &with_mmco(
+            reference(2, 4),
+            vec![Mmco::CurrentToLongTerm {
+                long_term_frame_idx: 0,
+            }],
+        ),
+        &sps,
+    );
+    assert_eq!(references(&dpb), [(0, None), (2, Some(0))]);
+
+    // The long-term frame survives the sliding window
+    decode(&mut dpb, &reference(3, 6), &sps);
+    decode(&mut dpb, &reference(4, 8), &sps);
+    assert_eq!(references(&dpb), [(2, Some(0)), (3, None), (4, None)]);
+}
+
+#[test]
+fn mmco_forget_all() {
+    let sps = sps_with(0, 4);
+    let mut dpb = Dpb::new(5);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    decode(&mut dpb, &reference(2, 4), &sps);
+
+    let picture = decode(
+        &mut dpb,
+        &with_mmco(reference(3, 10), vec![Mmco::ForgetAll]),
+        &sps,
+    );
+    // The picture gets frame_num 0 and a POC relative to itself
+    assert!(picture.has_mmco5);
+    assert_eq!(picture.frame_num, 0);
+    assert_eq!(picture.pic_order_cnt(), 0);
+    assert_eq!(references(&dpb), [(0, None)]);
+
+    // POC continues from the MMCO 5 picture with prevPicOrderCntMsb 0 and
+    // prevPicOrderCntLsb 0, frame_num from 0
+    let picture = decode(&mut dpb, &reference(1, 2), &sps);
+    assert_eq!(picture.pic_order_cnt(), 2);
+    assert_eq!(references(&dpb), [(0, None), (1, None)]);
+}
+
+#[test]
+fn mmco_forget_all_poc_type_2() {
+    let sps = sps_with(2, 4);
+    let mut dpb = Dpb::new(5);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 0), &sps);
+    let picture = decode(
+        &mut dpb,
+        &with_mmco(reference(2, 0), vec![Mmco::ForgetAll]),
+        &sps,
+    );
+    assert_eq!(picture.pic_order_cnt(), 0);
+
+    // FrameNumOffset and prevFrameNum restart at 0
+    let picture = decode(&mut dpb, &reference(1, 0), &sps);
+    assert_eq!(picture.pic_order_cnt(), 2);
+}
+
+#[test]
+fn idr_long_term_reference() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+
+    let mut header = idr(0);
+    header.dec_ref_pic_marking = Some(DecRefPicMarking {
+        long_term_reference_flag: true,
+        ..Default::default()
+    });
+    let picture = decode(&mut dpb, &header, &sps);
+    assert_eq!(picture.long_term_frame_idx, Some(0));
+    assert_eq!(references(&dpb), [(0, Some(0))]);
+
+    decode(&mut dpb, &reference(1, 2), &sps);
+    decode(&mut dpb, &reference(2, 4), &sps);
+    assert_eq!(references(&dpb), [(0, Some(0)), (2, None)]);
+}
+
+#[test]
+fn idr_clears_references() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+
+    let picture = dpb.start_picture(&idr(0), &sps).unwrap();
+    assert!(dpb.references().is_empty());
+    assert_eq!(picture.slot, 0);
+    dpb.finish_picture(&idr(0), &sps, picture).unwrap();
+    assert_eq!(references(&dpb), [(0, None)]);
+}
+
+#[test]
+fn frame_num_gap() {
+    let sps = Sps {
+        gaps_in_frame_num_value_allowed_flag: true,
+        ..sps_with(2, 3)
+    };
+    let mut dpb = Dpb::new(4);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 0), &sps);
+
+    // frame_num 2 to 4 are missing, the sliding window runs for each of them
+    let picture = decode(&mut dpb, &reference(5, 0), &sps);
+    assert_eq!(picture.pic_order_cnt(), 10);
+    assert_eq!(references(&dpb), [(3, None), (4, None), (5, None)]);
+
+    let non_existing: Vec<_> = dpb
+        .references()
+        .iter()
+        .map(|frame| (frame.is_non_existing(), frame.pic_order_cnt()))
+        .collect();
+    assert_eq!(non_existing, [(true, 6), (true, 8), (false, 10)]);
+
+    // Non-existing frames hold no slot
+    check_slots(&dpb);
+    assert_eq!(dpb.references()[2].slot, Some(0));
+    let picture = dpb.start_picture(&reference(6, 0), &sps).unwrap();
+    assert_eq!(picture.slot, 1);
+}
+
+#[test]
+fn frame_num_gap_across_wrap() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    for frame_num in 1..15 {
+        decode(&mut dpb, &reference(frame_num, (2 * frame_num) % 16), &sps);
+    }
+    decode(&mut dpb, &reference(1, 2), &sps);
+    assert_eq!(references(&dpb), [(0, None), (1, None)]);
+    assert!(dpb.references()[0].is_non_existing());
+}
+
+#[test]
+fn repeated_frame_num_is_no_gap() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    // Non-reference pictures share frame_num with the next reference picture
+    decode(&mut dpb, &non_reference(1, 2), &sps);
+    decode(&mut dpb, &non_reference(1, 4), &sps);
+    decode(&mut dpb, &reference(1, 6), &sps);
+    assert_eq!(references(&dpb), [(0, None), (1, None)]);
+}
+
+#[test]
+fn poc_type_0() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    let picture = decode(&mut dpb, &idr(0), &sps);
+    assert_eq!(picture.pic_order_cnt(), 0);
+
+    // pic_order_cnt_lsb wraps at 16, PicOrderCntMsb follows
+    let mut pocs = Vec::new();
+    for frame_num in 1..12u32 {
+        let picture = decode(&mut dpb, &reference(frame_num, (frame_num * 4) % 16), &sps);
+        pocs.push(picture.pic_order_cnt());
+    }
+    assert_eq!(pocs, (1..12).map(|n| n * 4).collect::<Vec<_>>());
+
+    // A non-reference B picture before its reference, prevPicOrderCntLsb is not updated
+    let picture = decode(&mut dpb, &non_reference(12, 14), &sps);
+    assert_eq!(picture.pic_order_cnt(), 46);
+    let picture = decode(&mut dpb, &reference(12, 0), &sps);
+    assert_eq!(picture.pic_order_cnt(), 48);
+
+    // delta_pic_order_cnt_bottom
+    let mut header = reference(13, 2);
+    header.delta_pic_order_cnt_bottom = -1;
+    let picture = decode(&mut dpb, &header, &sps);
+    assert_eq!(picture.top_field_order_cnt, 50);
+    assert_eq!(picture.bottom_field_order_cnt, 49);
+    assert_eq!(picture.pic_order_cnt(), 49);
+}
+
+#[test]
+fn poc_type_0_negative() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    // A leading picture more than half the range below wraps down
+    let picture = decode(&mut dpb, &non_reference(1, 14), &sps);
+    assert_eq!(picture.pic_order_cnt(), -2);
+}
+
+#[test]
+fn poc_type_1() {
+    let sps = Sps {
+        offset_for_non_ref_pic: -3,
+        offset_for_top_to_bottom_field: 1,
+        offset_for_ref_frame: vec![4, 2],
+        ..sps_with(1, 2)
+    };
+    let mut dpb = Dpb::new(3);
+
+    let picture = decode(&mut dpb, &idr(0), &sps);
+    assert_eq!(
+        (picture.top_field_order_cnt, picture.bottom_field_order_cnt),
+        (0, 1)
+    );
+
+    // absFrameNum 1..=5 cycles through the offsets 4, 2
+    let pocs: Vec<_> = (1..6)
+        .map(|frame_num| decode(&mut dpb, &reference(frame_num, 0), &sps).top_field_order_cnt)
+        .collect();
+    assert_eq!(pocs, [4, 6, 10, 12, 16]);
+
+    // Non-reference: absFrameNum - 1 plus offset_for_non_ref_pic, and delta_pic_order_cnt
+    let mut header = non_reference(6, 0);
+    header.delta_pic_order_cnt = [1, -2];
+    let picture = decode(&mut dpb, &header, &sps);
+    assert_eq!(
+        (picture.top_field_order_cnt, picture.bottom_field_order_cnt),
+        (16 - 3 + 1, 16 - 3 + 1 + 1 - 2)
+    );
+
+    // FrameNumOffset grows by MaxFrameNum when frame_num wraps
+    for frame_num in 6..16 {
+        decode(&mut dpb, &reference(frame_num, 0), &sps);
+    }
+    let picture = decode(&mut dpb, &reference(0, 0), &sps);
+    // absFrameNum 16: 7 full cycles of 6 plus 4 and 2
+    assert_eq!(picture.top_field_order_cnt, 48);
+}
+
+#[test]
+fn poc_type_2() {
+    let sps = sps_with(2, 2);
+    let mut dpb = Dpb::new(3);
+
+    assert_eq!(decode(&mut dpb, &idr(0), &sps).pic_order_cnt(), 0);
+    assert_eq!(decode(&mut dpb, &reference(1, 0), &sps).pic_order_cnt(), 2);
+    assert_eq!(
+        decode(&mut dpb, &non_reference(2, 0), &sps).pic_order_cnt(),
+        3
+    );
+    assert_eq!(decode(&mut dpb, &reference(2, 0), &sps).pic_order_cnt(), 4);
+
+    // Across the frame_num wrap
+    for frame_num in 3..16 {
+        decode(&mut dpb, &reference(frame_num, 0), &sps);
+    }
+    assert_eq!(decode(&mut dpb, &reference(0, 0), &sps).pic_order_cnt(), 32);
+}
+
+#[test]
+fn slot_assignment() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    let slots: Vec<_> = [idr(0), reference(1, 2), reference(2, 4), reference(3, 6)]
+        .iter()
+        .map(|header| decode(&mut dpb, header, &sps).slot)
+        .collect();
+    // Slot 0 is freed by the sliding window once frame_num 2 is decoded
+    assert_eq!(slots, [0, 1, 2, 0]);
+
+    // Non-reference pictures borrow the free slot without keeping it
+    let slot = decode(&mut dpb, &non_reference(4, 7), &sps).slot;
+    assert_eq!(slot, 1);
+    assert_eq!(decode(&mut dpb, &reference(4, 8), &sps).slot, 1);
+}
+
+#[test]
+fn errors() {
+    let sps = sps_with(0, 2);
+
+    let mut dpb = Dpb::new(3);
+    decode(&mut dpb, &idr(0), &sps);
+    let mut header = reference(1, 2);
+    header.field_pic_flag = true;
+    assert!(dpb.start_picture(&header, &sps).is_err());
+
+    // No room for the current picture next to max_num_ref_frames references
+    let mut dpb = Dpb::new(2);
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    assert!(dpb.start_picture(&reference(2, 4), &sps).is_err());
+}
+
+#[test]
+fn clear() {
+    let sps = sps_with(0, 2);
+    let mut dpb = Dpb::new(3);
+
+    decode(&mut dpb, &idr(0), &sps);
+    decode(&mut dpb, &reference(1, 2), &sps);
+    dpb.clear();
+    assert!(dpb.references().is_empty());
+    assert_eq!(dpb.slot_count(), 3);
+}
@@ -0,0 +1,555 @@
+use ash::vk;
+use ash_video::encoder::backend::{BackendCapabilities, PictureInfo, ReferenceSlot};
+use ash_video::encoder::mock::{Call, MockBackend};
+use ash_video::encoder::{
+    EncodedPicture, Encoder, EncoderConfig, FrameType, GopStructure, RateControl,
+    NO_REFERENCE_PICTURE,
+};
+use ash_video::h264::{NalUnitHeader, NalUnitType, NalUnits};
+use ash_video::yuv::Nv12Frame;
+use ash_video::Timestamp;
+
+fn frame(width: u32, height: u32, value: u8) -> Nv12Frame {
+    Nv12Frame {
+        width,
+        height,
+        y: vec![value; width as usize * height as usize],
+        uv: vec![128; width.div_ceil(2) as usize * height.div_ceil(2) as usize * 2],
+    }
+}
+
+/// Encodes `count` frames and flushes, returning the pictures in decode order.
+fn encode_all(encoder: &mut Encoder<MockBackend>, count: u64) -> Vec<EncodedPicture> {
+    let EncoderConfig { width, height, .. } = *encoder.config();
+    let mut pictures = Vec::new();
+    for index in 0..count {
+        pictures.extend(
+            encoder
+                .encode(
+                    frame(width, height, index as u8),
+                    Timestamp::new(index as i64, 30),
+                )
+                .unwrap(),
+        );
+    }
+    pictures.extend(encoder.flush().unwrap());
+    pictures
+}
+
+fn slots(references: &[ReferenceSlot]) -> Vec<usize> {
+    references.iter().map(|reference| reference.slot).collect()
+}
+
+fn list0(picture: &PictureInfo) -> Vec<u8> {
+    let lists = &picture.std_reference_lists;
+    lists.RefPicList0[..lists.num_ref_idx_l0_active_minus1 as usize + 1].to_vec()
+}
+
+fn list1(picture: &PictureInfo) -> Vec<u8> {
+    let lists = &picture.std_reference_lists;
+    lists.RefPicList1[..lists.num_ref_idx_l1_active_minus1 as usize + 1].to_vec()
+}
+
+fn nal_unit_types(data: &[u8]) -> Vec<NalUnitType> {
+    NalUnits::new(data)
+        .map(|nal| NalUnitHeader::parse(nal.data).unwrap().nal_unit_type)
+        .collect()
+}
+
+#[test]
+fn gop_frame_types() {
+    let types = |gop: GopStructure, count: u64| {
+        (0..count)
+            .map(|index| match gop.frame_type(index) {
+                FrameType::Idr => 'D',
+                FrameType::I => 'I',
+                FrameType::P => 'P',
+                FrameType::B => 'B',
+            })
+            .collect::<String>()
+    };
+
+    let gop = GopStructure::default();
+    assert_eq!(types(gop, 62), format!("D{}D{}", "P".repeat(59), "P"));
+    assert_eq!(gop.gop_frame_count(), 60);
+
+    let gop = GopStructure {
+        idr_period: 0,
+        intra_period: 0,
+        consecutive_b_frames: 0,
+    };
+    assert_eq!(types(gop, 5), "DPPPP");
+
+    // The frame before an IDR picture can not be a B picture
+    let gop = GopStructure {
+        idr_period: 8,
+        intra_period: 0,
+        consecutive_b_frames: 2,
+    };
+    assert_eq!(types(gop, 10), "DBBPBBPPDB");
+
+    let gop = GopStructure {
+        idr_period: 12,
+        intra_period: 6,
+        consecutive_b_frames: 1,
+    };
+    assert_eq!(types(gop, 13), "DBPBPBIBPBPPD");
+    assert_eq!(gop.gop_frame_count(), 6);
+    gop.validate().unwrap();
+
+    let gop = GopStructure {
+        intra_period: 5,
+        ..gop
+    };
+    assert!(gop.validate().is_err());
+}
+
+#[test]
+fn p_pictures_refer_to_previous() {
+    let config = EncoderConfig {
+        max_reference_frames: 2,
+        ..EncoderConfig::new(100, 50)
+    };
+    let mut encoder = Encoder::with_backend(MockBackend::default(), config).unwrap();
+    let pictures = encode_all(&mut encoder, 6);
+
+    // The session is macroblock aligned with a slot for each reference and one to encode
+    // into, the SPS crops back to the frame size
+    let calls = &encoder.backend().calls;
+    match &calls[0] {
+        Call::CreateSession(info) => {
+            assert_eq!(info.coded_extent, (112, 64));
+            assert_eq!(info.frame_rate, (30, 1));
+            assert_eq!(info.dpb_slots, 3);
+            assert_eq!(info.max_active_reference_pictures, 2);
+        }
+        call => panic!("expected the session to be created first, got {:?}", call),
+    }
+    match &calls[1] {
+        Call::UpdateParameters(parameter_sets) => {
+            let sps = parameter_sets.sps(0).unwrap();
+            assert_eq!(sps.coded_extent(), (112, 64));
+            assert_eq!(sps.crop_rect(), (0, 0, 100, 50));
+            assert_eq!(sps.max_num_ref_frames, 2);
+            assert_eq!(sps.max_num_reorder_frames(), 0);
+            assert_eq!(sps.frame_duration(), Some(Timestamp::new(1, 30)));
+            assert_eq!(parameter_sets.active(0).unwrap().1, sps);
+        }
+        call => panic!("expected the parameter sets, got {:?}", call),
+    }
+    assert_eq!(calls.len(), 2 + 3 * 6);
+
+    // Slots are reused once a picture leaves the sliding window, the newest reference
+    // comes first
+    let expected = [
+        (Some(0), vec![]),
+        (Some(1), vec![0]),
+        (Some(2), vec![1, 0]),
+        (Some(0), vec![2, 1]),
+        (Some(1), vec![0, 2]),
+        (Some(2), vec![1, 0]),
+    ];
+    let encodes: Vec<_> = encoder.backend().encodes().collect();
+    assert_eq!(encodes.len(), expected.len());
+    for (index, ((picture, setup_slot, references), (setup, list))) in
+        encodes.into_iter().zip(expected).enumerate()
+    {
+        let info = &picture.std_picture_info;
+        assert_eq!(setup_slot.map(|setup_slot| setup_slot.slot), setup);
+        assert_eq!(
+            slots(references),
+            list.iter().map(|&slot| slot as usize).collect::<Vec<_>>()
+        );
+        assert_eq!(info.frame_num, index as u32);
+        assert_eq!(info.PicOrderCnt, 2 * index as i32);
+        assert_eq!(info.flags.IdrPicFlag(), (index == 0) as u32);
+        assert_eq!(info.flags.is_reference(), 1);
+        assert_eq!(setup_slot.unwrap().info.FrameNum, index as u32);
+        if index > 0 {
+            assert_eq!(
+                list0(picture),
+                list.iter().map(|&slot| slot as u8).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                picture
+                    .std_slice_header
+                    .flags
+                    .num_ref_idx_active_override_flag(),
+                (list.len() > 1) as u32
+            );
+        }
+        assert!(picture.std_reference_lists.RefPicList0[list.len().max(1)..]
+            .iter()
+            .all(|&slot| slot == NO_REFERENCE_PICTURE));
+    }
+
+    // The IDR picture carries the parameter sets
+    assert_eq!(pictures.len(), 6);
+    assert_eq!(pictures[0].frame_type, FrameType::Idr);
+    assert!(pictures[0].data.starts_with(encoder.parameter_sets()));
+    assert_eq!(
+        nal_unit_types(&pictures[0].data),
+        [NalUnitType::Sps, NalUnitType::Pps, NalUnitType::IdrSlice]
+    );
+    for (index, picture) in pictures.iter().enumerate().skip(1) {
+        assert_eq!(picture.frame_type, FrameType::P);
+        assert_eq!(picture.pts, Timestamp::new(index as i64, 30));
+        assert_eq!(nal_unit_types(&picture.data), [NalUnitType::Slice]);
+        assert_eq!(
+            NalUnitHeader::parse(NalUnits::new(&picture.data).next().unwrap().data)
+                .unwrap()
+                .nal_ref_idc,
+            2
+        );
+    }
+}
+
+#[test]
+fn idr_period_restarts_pictures() {
+    let config = EncoderConfig {
+        gop: GopStructure {
+            idr_period: 3,
+            ..GopStructure::default()
+        },
+        ..EncoderConfig::new(64, 64)
+    };
+    let mut encoder = Encoder::with_backend(MockBackend::default(), config).unwrap();
+    let pictures = encode_all(&mut encoder, 7);
+
+    let types: Vec<_> = pictures.iter().map(|picture| picture.frame_type).collect();
+    assert_eq!(
+        types,
+        [
+            FrameType::Idr,
+            FrameType::P,
+            FrameType::P,
+            FrameType::Idr,
+            FrameType::P,
+            FrameType::P,
+            FrameType::Idr
+        ]
+    );
+    for (index, (picture, _, references)) in encoder.backend().encodes().enumerate() {
+        let info = &picture.std_picture_info;
+        assert_eq!(info.frame_num, index as u32 % 3);
+        assert_eq!(info.PicOrderCnt, 2 * (index as i32 % 3));
+        assert_eq!(references.is_empty(), index % 3 == 0);
+        // Neighbouring IDR pictures differ in idr_pic_id
+        if index % 3 == 0 {
+            assert_eq!(info.idr_pic_id, index as u16 / 3);
+        }
+    }
+    for picture in pictures
+        .iter()
+        .filter(|picture| picture.frame_type == FrameType::Idr)
+    {
+        assert!(picture.data.starts_with(encoder.parameter_sets()));
+    }
+}
+
+#[test]
+fn b_pictures_wait_for_the_next_reference() {
+    let config = EncoderConfig {
+        gop: GopStructure {
+            idr_period: 0,
+            intra_period: 0,
+            consecutive_b_frames: 2,
+        },
+        max_reference_frames: 2,
+        ..EncoderConfig::new(64, 64)
+    };
+    let mut encoder = Encoder::with_backend(MockBackend::default(), config).unwrap();
+    match &encoder.backend().calls[1] {
+        Call::UpdateParameters(parameter_sets) => {
+            assert_eq!(parameter_sets.sps(0).unwrap().max_num_reorder_frames(), 1);
+        }
+        call => panic!("expected the parameter sets, got {:?}", call),
+    }
+
+    // Nothing comes out for B frames until the reference picture after them
+    assert_eq!(
+        encoder
+            .encode(frame(64, 64, 0), Timestamp::new(0, 30))
+            .unwrap()
+            .len(),
+        1
+    );
+    for index in 1..3 {
+        assert!(encoder
+            .encode(frame(64, 64, 0), Timestamp::new(index, 30))
+            .unwrap()
+            .is_empty());
+    }
+    let mut pictures = encoder
+        .encode(frame(64, 64, 0), Timestamp::new(3, 30))
+        .unwrap();
+    for index in 4..9 {
+        pictures.extend(
+            encoder
+                .encode(frame(64, 64, 0), Timestamp::new(index, 30))
+                .unwrap(),
+        );
+    }
+    // The last frame becomes a P picture at the end of the stream
+    pictures.extend(encoder.flush().unwrap());
+    assert!(encoder.flush().unwrap().is_empty());
+
+    let order: Vec<_> = pictures
+        .iter()
+        .map(|picture| (picture.pts.value, picture.frame_type))
+        .collect();
+    assert_eq!(
+        order,
+        [
+            (3, FrameType::P),
+            (1, FrameType::B),
+            (2, FrameType::B),
+            (6, FrameType::P),
+            (4, FrameType::B),
+            (5, FrameType::B),
+            (8, FrameType::P),
+            (7, FrameType::B),
+        ]
+    );
+    for picture in &pictures {
+        let nal_unit_type = match picture.frame_type {
+            FrameType::B | FrameType::P => NalUnitType::Slice,
+            _ => unreachable!(),
+        };
+        assert_eq!(nal_unit_types(&picture.data), [nal_unit_type]);
+    }
+
+    // B pictures refer to the references before them in list 0, after them in list 1,
+    // and are not kept themselves
+    let encodes: Vec<_> = encoder.backend().encodes().skip(1).collect();
+    let expected = [
+        (Some(1), vec![0], vec![], 1, 6),
+        (None, vec![0], vec![1], 2, 2),
+        (None, vec![0], vec![1], 2, 4),
+        (Some(2), vec![1, 0], vec![], 2, 12),
+        (None, vec![1], vec![2], 3, 8),
+        (None, vec![1], vec![2], 3, 10),
+        (Some(0), vec![2, 1], vec![], 3, 16),
+        (None, vec![2], vec![0], 4, 14),
+    ];
+    assert_eq!(encodes.len(), expected.len());
+    for ((picture, setup_slot, references), (setup, l0, l1, frame_num, poc)) in
+        encodes.into_iter().zip(expected)
+    {
+        let info = &picture.std_picture_info;
+        assert_eq!(setup_slot.map(|setup_slot| setup_slot.slot), setup);
+        assert_eq!(info.flags.is_reference(), setup.is_some() as u32);
+        assert_eq!(info.frame_num, frame_num);
+        assert_eq!(info.PicOrderCnt, poc);
+        assert_eq!(list0(picture), l0);
+        let b_picture = setup.is_none();
+        if b_picture {
+            assert_eq!(list1(picture), l1);
+        } else {
+            assert_eq!(
+                picture.std_reference_lists.RefPicList1[0],
+                NO_REFERENCE_PICTURE
+            );
+        }
+        assert_eq!(
+            picture.std_slice_header.flags.direct_spatial_mv_pred_flag(),
+            b_picture as u32
+        );
+        let bound: Vec<_> = l0.iter().chain(&l1).map(|&slot| slot as usize).collect();
+        assert_eq!(slots(references), bound);
+    }
+}
+
+#[test]
+fn rate_control_changes() {
+    let mut encoder =
+        Encoder::with_backend(MockBackend::default(), EncoderConfig::new(64, 64)).unwrap();
+    for index in 0..2 {
+        encoder
+            .encode(frame(64, 64, 0), Timestamp::new(index, 30))
+            .unwrap();
+    }
+    let cbr = RateControl::ConstantBitrate { bitrate: 2_000_000 };
+    encoder.set_rate_control(cbr).unwrap();
+    for index in 2..4 {
+        encoder
+            .encode(frame(64, 64, 0), Timestamp::new(index, 30))
+            .unwrap();
+    }
+
+    // The session is reset with the configured rate control, which is only passed again
+    // when it changes
+    let begins: Vec<_> = encoder
+        .backend()
+        .calls
+        .iter()
+        .filter_map(|call| match call {
+            Call::BeginCoding {
+                reset,
+                rate_control,
+                ..
+            } => Some((*reset, *rate_control)),
+            _ => None,
+        })
+        .collect();
+    let cqp = RateControl::ConstantQp {
+        i: 24,
+        p: 26,
+        b: 28,
+    };
+    assert_eq!(
+        begins,
+        [
+            (true, Some(cqp)),
+            (false, None),
+            (false, Some(cbr)),
+            (false, None)
+        ]
+    );
+    let qps: Vec<_> = encoder
+        .backend()
+        .encodes()
+        .map(|(picture, _, _)| picture.constant_qp)
+        .collect();
+    assert_eq!(qps, [Some(24), Some(26), None, None]);
+
+    // Within what the backend supports
+    for rate_control in [
+        RateControl::ConstantQp {
+            i: 24,
+            p: 26,
+            b: 52,
+        },
+        RateControl::ConstantBitrate { bitrate: 0 },
+        RateControl::ConstantBitrate {
+            bitrate: 200_000_000,
+        },
+        RateControl::VariableBitrate {
+            average_bitrate: 4_000_000,
+            max_bitrate: 2_000_000,
+        },
+    ] {
+        assert!(
+            encoder.set_rate_control(rate_control).is_err(),
+            "{:?}",
+            rate_control
+        );
+    }
+    assert_eq!(encoder.config().rate_control, cbr);
+
+    let mut backend = MockBackend::default();
+    backend.capabilities.rate_control_modes = vk::VideoEncodeRateControlModeFlagsKHR::DISABLED
+        | vk::VideoEncodeRateControlModeFlagsKHR::CBR;
+    let config = EncoderConfig {
+        rate_control: RateControl::VariableBitrate {
+            average_bitrate: 2_000_000,
+            max_bitrate: 4_000_000,
+        },
+        ..EncoderConfig::new(64, 64)
+    };
+    assert!(Encoder::with_backend(backend, config).is_err());
+}
+
+#[test]
+fn backend_limits() {
+    let capabilities = MockBackend::default().capabilities;
+    let with = |capabilities: BackendCapabilities, config: EncoderConfig| {
+        Encoder::with_backend(MockBackend::new(capabilities), config)
+    };
+    let b_frames = GopStructure {
+        consecutive_b_frames: 1,
+        ..GopStructure::default()
+    };
+
+    // Sizes are even and fit the coded extent once macroblock aligned
+    let hd = BackendCapabilities {
+        max_coded_extent: (1920, 1088),
+        ..capabilities
+    };
+    assert!(with(hd, EncoderConfig::new(1920, 1080)).is_ok());
+    assert!(with(hd, EncoderConfig::new(1920, 1090)).is_err());
+    assert!(with(capabilities, EncoderConfig::new(63, 64)).is_err());
+    assert!(with(capabilities, EncoderConfig::new(0, 64)).is_err());
+
+    // B pictures need list 1 and a reference on either side
+    let config = EncoderConfig {
+        gop: b_frames,
+        ..EncoderConfig::new(64, 64)
+    };
+    let no_b = BackendCapabilities {
+        max_l1_references: 0,
+        ..capabilities
+    };
+    assert!(with(no_b, config).is_err());
+    let two_slots = BackendCapabilities {
+        max_dpb_slots: 2,
+        ..capabilities
+    };
+    assert!(with(two_slots, config).is_err());
+    assert!(with(two_slots, EncoderConfig::new(64, 64)).is_ok());
+
+    // Reference frames are limited by the active references
+    let config = EncoderConfig {
+        max_reference_frames: 8,
+        ..EncoderConfig::new(64, 64)
+    };
+    let encoder = with(
+        BackendCapabilities {
+            max_active_reference_pictures: 3,
+            ..capabilities
+        },
+        config,
+    )
+    .unwrap();
+    match &encoder.backend().calls[0] {
+        Call::CreateSession(info) => {
+            assert_eq!(info.dpb_slots, 4);
+            assert_eq!(info.max_active_reference_pictures, 3);
+        }
+        call => panic!("expected the session to be created first, got {:?}", call),
+    }
+
+    let invalid_gop = EncoderConfig {
+        gop: GopStructure {
+            intra_period: 3,
+            ..b_frames
+        },
+        ..EncoderConfig::new(64, 64)
+    };
+    assert!(with(capabilities, invalid_gop).is_err());
+}
+
+#[test]
+fn frames_must_fit_and_encode() {
+    let mut encoder =
+        Encoder::with_backend(MockBackend::default(), EncoderConfig::new(64, 64)).unwrap();
+    assert!(encoder
+        .encode(frame(64, 48, 0), Timestamp::new(0, 30))
+        .is_err());
+    encoder
+        .encode(frame(64, 64, 0), Timestamp::new(0, 30))
+        .unwrap();
+
+    // A failed picture is an error rather than an empty picture
+    encoder.backend_mut().status = vk::QueryResultStatusKHR::ERROR;
+    assert!(encoder
+        .encode(frame(64, 64, 0), Timestamp::new(1, 30))
+        .is_err());
+}
+
+#[test]
+fn nv12_pad_repeats_edges() {
+    let frame = Nv12Frame {
+        width: 3,
+        height: 2,
+        y: vec![1, 2, 3, 4, 5, 6],
+        uv: vec![10, 20, 30, 40],
+    };
+    let padded = frame.pad(4, 4);
+    assert_eq!((padded.width, padded.height), (4, 4));
+    assert_eq!(padded.y, [1, 2, 3, 3, 4, 5, 6, 6, 4, 5, 6, 6, 4, 5, 6, 6]);
+    assert_eq!(padded.uv, [10, 20, 30, 40, 10, 20, 30, 40]);
+
+    // Never shrinks
+    assert_eq!(frame.pad(2, 2), frame);
+}
@@ -0,0 +1,207 @@
+use ash_video::annexb::AnnexBReader;
+use ash_video::h264::dpb::Dpb;
+use ash_video::h264::output::OutputQueue;
+use ash_video::h264::slice;
+use ash_video::h264::sps::Sps;
+
+mod common;
+use common::{ANNEXB_STREAM, MP4_STREAM};
+
+/// Pushes (POC, IDR) pairs tagged with their decoding order and returns the output after
+/// each push.
+fn run(queue: &mut OutputQueue<usize>, pictures: &[(i32, bool)]) -> Vec<Vec<usize>> {
+    pictures
+        .iter()
+        .enumerate()
+        .map(|(index, &(pic_order_cnt, idr))| queue.push(pic_order_cnt, idr, index))
+        .collect()
+}
+
+#[test]
+fn no_reordering() {
+    let mut queue = OutputQueue::new(0, 1);
+    let output = run(&mut queue, &[(0, true), (2, false), (4, false)]);
+    assert_eq!(output, [vec![0], vec![1], vec![2]]);
+    assert!(queue.is_empty());
+    assert!(queue.flush().is_empty());
+}
+
+#[test]
+fn b_frames() {
+    // I0 P6 B2 B4 P12 B8 B10 in decoding order
+    let mut queue = OutputQueue::new(2, 4);
+    let output = run(
+        &mut queue,
+        &[
+            (0, true),
+            (6, false),
+            (2, false),
+            (4, false),
+            (12, false),
+            (8, false),
+            (10, false),
+        ],
+    );
+    assert_eq!(
+        output,
+        [vec![], vec![], vec![0], vec![2], vec![3], vec![1], vec![5]]
+    );
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue.iter().copied().collect::<Vec<_>>(), [4, 6]);
+    assert_eq!(queue.flush(), [6, 4]);
+}
+
+#[test]
+fn b_pyramid() {
+    // I0 P16 B8 b4 b2 b6 B12 b10 b14, up to three pictures are decoded ahead
+    let pictures = [
+        (0, true),
+        (16, false),
+        (8, false),
+        (4, false),
+        (2, false),
+        (6, false),
+        (12, false),
+        (10, false),
+        (14, false),
+    ];
+    let mut queue = OutputQueue::new(3, 4);
+    let mut output: Vec<_> = run(&mut queue, &pictures).into_iter().flatten().collect();
+    output.extend(queue.flush());
+
+    let pic_order_cnts: Vec<_> = output.iter().map(|&index| pictures[index].0).collect();
+    assert_eq!(pic_order_cnts, [0, 2, 4, 6, 8, 10, 12, 14, 16]);
+}
+
+#[test]
+fn too_few_reorder_frames() {
+    // A stream reordering more than signalled comes out in the wrong order, but complete
+    let pictures = [(0, true), (6, false), (2, false), (4, false)];
+    let mut queue = OutputQueue::new(1, 4);
+    let output = run(&mut queue, &pictures);
+    assert_eq!(output, [vec![], vec![0], vec![2], vec![3]]);
+    assert_eq!(queue.flush(), [1]);
+}
+
+#[test]
+fn max_dec_frame_buffering() {
+    let mut queue = OutputQueue::new(4, 2);
+    let output = run(&mut queue, &[(0, true), (6, false), (2, false), (4, false)]);
+    assert_eq!(output, [vec![], vec![], vec![0], vec![2]]);
+    assert_eq!(queue.flush(), [3, 1]);
+}
+
+#[test]
+fn idr_flushes() {
+    let mut queue = OutputQueue::new(2, 4);
+    let output = run(
+        &mut queue,
+        &[(0, true), (4, false), (2, false), (0, true), (4, false)],
+    );
+    // Pictures before the second IDR are output before it, whatever their POC
+    assert_eq!(output, [vec![], vec![], vec![0], vec![2, 1], vec![]]);
+    assert_eq!(queue.flush(), [3, 4]);
+}
+
+#[test]
+fn mmco5_restarts_poc() {
+    // The MMCO 5 picture gets POC 0 and flushes like an IDR picture
+    let mut queue = OutputQueue::new(1, 2);
+    let output = run(&mut queue, &[(0, true), (8, false), (0, true), (2, false)]);
+    assert_eq!(output, [vec![], vec![0], vec![1], vec![2]]);
+    assert_eq!(queue.flush(), [3]);
+}
+
+#[test]
+fn equal_poc_keeps_decoding_order() {
+    let mut queue = OutputQueue::new(3, 3);
+    run(&mut queue, &[(0, true), (2, false), (2, false)]);
+    assert_eq!(queue.flush(), [0, 1, 2]);
+}
+
+#[test]
+fn negative_poc() {
+    // Leading pictures before the I picture they depend on
+    let mut queue = OutputQueue::new(2, 3);
+    let output = run(
+        &mut queue,
+        &[(0, false), (-4, false), (-2, false), (6, false)],
+    );
+    assert_eq!(output, [vec![], vec![], vec![1], vec![2]]);
+    assert_eq!(queue.flush(), [0, 3]);
+}
+
+fn sps() -> Sps {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    let parameter_sets = AnnexBReader::read_parameter_sets(&data).unwrap();
+    parameter_sets.sps(0).unwrap().clone()
+}
+
+#[test]
+fn sps_bitstream_restriction() {
+    let mut sps = sps();
+    let vui = sps.vui.get_or_insert_with(Default::default);
+    vui.bitstream_restriction_flag = true;
+    vui.max_num_reorder_frames = 2;
+    vui.max_dec_frame_buffering = 3;
+    assert_eq!(sps.max_num_reorder_frames(), 2);
+    assert_eq!(sps.max_dec_frame_buffering(), 3);
+
+    let mut queue = OutputQueue::from_sps(&sps);
+    let output = run(&mut queue, &[(0, true), (4, false), (2, false)]);
+    assert_eq!(output, [vec![], vec![], vec![0]]);
+}
+
+#[test]
+fn sps_inferred_limits() {
+    let mut sps = sps();
+    sps.vui = None;
+
+    // 1920x1088 at level 4.0: 32768 / 8160 macroblocks
+    sps.level_idc = 40;
+    sps.pic_width_in_mbs_minus1 = 119;
+    sps.pic_height_in_map_units_minus1 = 67;
+    sps.frame_mbs_only_flag = true;
+    assert_eq!(sps.max_dpb_frames(), 4);
+    assert_eq!(sps.max_num_reorder_frames(), 4);
+    assert_eq!(sps.max_dec_frame_buffering(), 4);
+
+    // Small pictures at a high level are capped at 16 frames
+    sps.level_idc = 51;
+    sps.pic_width_in_mbs_minus1 = 21;
+    sps.pic_height_in_map_units_minus1 = 17;
+    assert_eq!(sps.max_dpb_frames(), 16);
+
+    // High 10 Intra and friends never reorder
+    sps.profile_idc = 110;
+    sps.constraint_set_flags = 0x10;
+    assert_eq!(sps.max_num_reorder_frames(), 0);
+    assert_eq!(sps.max_dec_frame_buffering(), 0);
+}
+
+#[test]
+fn mp4_stream_output_order() {
+    let (mut parameter_sets, access_units) = common::read_mp4(MP4_STREAM);
+    let sps = parameter_sets.sps(0).unwrap().clone();
+    let mut dpb = Dpb::new(sps.max_num_ref_frames as usize + 1);
+    let mut queue = OutputQueue::from_sps(&sps);
+
+    let mut output = Vec::new();
+    for (access_unit, pts) in access_units {
+        let slices = slice::parse_slices(&access_unit, &mut parameter_sets).unwrap();
+        let header = &slices[0].header;
+
+        let picture = dpb.start_picture(header, &sps).unwrap();
+        let picture = dpb.finish_picture(header, &sps, picture).unwrap();
+        output.extend(queue.push(
+            picture.pic_order_cnt(),
+            picture.idr || picture.has_mmco5,
+            pts,
+        ));
+        assert!(queue.len() <= sps.max_num_reorder_frames() as usize);
+    }
+    output.extend(queue.flush());
+
+    assert_eq!(output.len(), 300);
+    assert!(output.windows(2).all(|pair| pair[0] < pair[1]));
+}
@@ -0,0 +1,240 @@
+use ash_video::annexb::AnnexBReader;
+use ash_video::h264::pps::{Pps, SliceGroupMap};
+use ash_video::h264::sps::{Sps, DEFAULT_8X8_INTER};
+
+mod common;
+use common::bitwriter::BitWriter;
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/a.mp4");
+
+fn sps() -> Sps {
+    let (parameter_sets, _) = common::read_mp4(SAMPLE);
+    parameter_sets.sps(0).unwrap().clone()
+}
+
+/// PPS 0 of SPS 0 with CAVLC, pic_init_qp 26 and chroma_qp_index_offset 3. `slice_groups`
+/// writes num_slice_groups_minus1 and the map, `extension` the syntax elements that follow
+/// redundant_pic_cnt_present_flag.
+fn write_pps(
+    slice_groups: impl FnOnce(&mut BitWriter),
+    num_ref_idx_l0_default_active_minus1: u32,
+    weighted_bipred_idc: u64,
+    extension: impl FnOnce(&mut BitWriter),
+) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer.ue(0).ue(0).flag(false).flag(false);
+    slice_groups(&mut writer);
+    writer
+        .ue(num_ref_idx_l0_default_active_minus1)
+        .ue(0)
+        .flag(false)
+        .bits(weighted_bipred_idc, 2)
+        .se(0)
+        .se(0)
+        .se(3)
+        .flag(true)
+        .flag(false)
+        .flag(false);
+    extension(&mut writer);
+    writer.nal(3, 8)
+}
+
+fn assert_sample_pps(pps: &Pps) {
+    assert_eq!(pps.pic_parameter_set_id, 0);
+    assert_eq!(pps.seq_parameter_set_id, 0);
+    assert!(pps.entropy_coding_mode_flag);
+    assert!(!pps.bottom_field_pic_order_in_frame_present_flag);
+    assert_eq!(pps.num_slice_groups_minus1, 0);
+    assert_eq!(pps.slice_group_map, None);
+    assert_eq!(pps.num_ref_idx_l0_default_active_minus1, 2);
+    assert_eq!(pps.num_ref_idx_l1_default_active_minus1, 0);
+    assert!(pps.weighted_pred_flag);
+    assert_eq!(pps.weighted_bipred_idc, 0);
+    assert_eq!(pps.pic_init_qp_minus26, -16);
+    assert_eq!(pps.chroma_qp_index_offset, -2);
+    assert!(pps.deblocking_filter_control_present_flag);
+    assert!(!pps.constrained_intra_pred_flag);
+    assert!(!pps.redundant_pic_cnt_present_flag);
+    assert!(pps.transform_8x8_mode_flag);
+    assert!(!pps.pic_scaling_matrix_present_flag);
+    assert_eq!(pps.second_chroma_qp_index_offset, -2);
+}
+
+#[test]
+fn mp4_sample() {
+    let (parameter_sets, _) = common::read_mp4(SAMPLE);
+    assert_sample_pps(parameter_sets.pps(0).unwrap());
+}
+
+#[test]
+fn annexb_sample() {
+    let data = std::fs::read(common::ANNEXB_STREAM).unwrap();
+    let parameter_sets = AnnexBReader::read_parameter_sets(&data).unwrap();
+    let (pps, sps) = parameter_sets.active(0).unwrap();
+    assert_sample_pps(pps);
+    assert_eq!(pps.scaling_lists, sps.scaling_lists);
+
+    let std = pps.to_std();
+    assert!(std.pps.pScalingLists.is_null());
+    assert_eq!(std.pps.pic_init_qp_minus26, -16);
+    assert_eq!(std.pps.second_chroma_qp_index_offset, -2);
+}
+
+#[test]
+fn bipred_sample() {
+    let (parameter_sets, _) = common::read_mp4(common::MP4_STREAM);
+    let pps = parameter_sets.pps(0).unwrap();
+
+    assert_eq!(pps.num_ref_idx_l0_default_active_minus1, 15);
+    assert_eq!(pps.weighted_bipred_idc, 2);
+    assert_eq!(pps.pic_init_qp_minus26, 1);
+}
+
+#[test]
+fn slice_groups() {
+    let sps = sps();
+
+    let nal = write_pps(
+        |writer| {
+            writer.ue(2).ue(2).ue(0).ue(12).ue(23).ue(45);
+        },
+        0,
+        0,
+        |_| {},
+    );
+    let pps = Pps::parse(&nal, |id| (id == 0).then_some(&sps)).unwrap();
+    assert_eq!(pps.num_slice_groups_minus1, 2);
+    assert_eq!(
+        pps.slice_group_map,
+        Some(SliceGroupMap::Foreground {
+            top_left: vec![0, 23],
+            bottom_right: vec![12, 45],
+        })
+    );
+    // Without the trailing syntax elements the Cr offset is inferred from the Cb one.
+    assert!(!pps.transform_8x8_mode_flag);
+    assert_eq!(pps.second_chroma_qp_index_offset, 3);
+
+    let nal = write_pps(
+        |writer| {
+            writer
+                .ue(2)
+                .ue(6)
+                .ue(3)
+                .bits(0, 2)
+                .bits(1, 2)
+                .bits(2, 2)
+                .bits(1, 2);
+        },
+        0,
+        0,
+        |_| {},
+    );
+    let pps = Pps::parse(&nal, |id| (id == 0).then_some(&sps)).unwrap();
+    assert_eq!(
+        pps.slice_group_map,
+        Some(SliceGroupMap::Explicit {
+            slice_group_id: vec![0, 1, 2, 1],
+        })
+    );
+
+    let nal = write_pps(
+        |writer| {
+            writer.ue(1).ue(4).flag(true).ue(9);
+        },
+        0,
+        0,
+        |_| {},
+    );
+    let pps = Pps::parse(&nal, |id| (id == 0).then_some(&sps)).unwrap();
+    assert_eq!(
+        pps.slice_group_map,
+        Some(SliceGroupMap::Changing {
+            slice_group_map_type: 4,
+            slice_group_change_direction_flag: true,
+            slice_group_change_rate_minus1: 9,
+        })
+    );
+}
+
+#[test]
+fn scaling_list_fallback() {
+    let mut sps = sps();
+    sps.seq_scaling_matrix_present_flag = true;
+    sps.scaling_lists.present_mask = 0b1100_1001;
+    sps.scaling_lists.list_4x4[0] = [20; 16];
+    sps.scaling_lists.list_4x4[3] = [22; 16];
+    sps.scaling_lists.list_8x8[0] = [24; 64];
+    sps.scaling_lists.list_8x8[1] = [28; 64];
+
+    let nal = write_pps(
+        |writer| {
+            writer.ue(0);
+        },
+        0,
+        0,
+        |writer| {
+            writer.flag(true).flag(true);
+            // Sl_4x4_Intra_Cb is flat 16, Sl_8x8_Inter_Y switches to the default table.
+            writer.flag(false).flag(true).se(8).se(-16);
+            writer.bits(0, 5).flag(true).se(-8);
+            writer.se(-4);
+        },
+    );
+    let pps = Pps::parse(&nal, |id| (id == 0).then_some(&sps)).unwrap();
+
+    assert!(pps.transform_8x8_mode_flag);
+    assert!(pps.pic_scaling_matrix_present_flag);
+    assert_eq!(pps.second_chroma_qp_index_offset, -4);
+
+    // Lists missing from the PPS fall back to the SPS ones (fall-back rule B).
+    let lists = &pps.scaling_lists;
+    assert_eq!(lists.present_mask, 0b1000_0010);
+    assert_eq!(lists.use_default_mask, 0b1000_0000);
+    assert_eq!(lists.list_4x4[0], [20; 16]);
+    assert_eq!(lists.list_4x4[1], [16; 16]);
+    assert_eq!(lists.list_4x4[2], [16; 16]);
+    assert_eq!(lists.list_4x4[3], [22; 16]);
+    assert_eq!(lists.list_4x4[5], [22; 16]);
+    assert_eq!(lists.list_8x8[0], [24; 64]);
+    assert_eq!(lists.list_8x8[1], DEFAULT_8X8_INTER);
+
+    let std = pps.to_std();
+    assert!(!std.pps.pScalingLists.is_null());
+    let std_lists = unsafe { &*std.pps.pScalingLists };
+    assert_eq!(std_lists.ScalingList4x4[0], [20; 16]);
+}
+
+#[test]
+fn invalid() {
+    let sps = sps();
+    let no_slice_groups = |writer: &mut BitWriter| {
+        writer.ue(0);
+    };
+
+    let nal = write_pps(no_slice_groups, 0, 0, |_| {});
+    assert!(Pps::parse(&nal, |id| (id == 0).then_some(&sps)).is_ok());
+    assert!(Pps::parse(&nal, |_| None).is_err());
+
+    let nal = write_pps(no_slice_groups, 32, 0, |_| {});
+    assert!(Pps::parse(&nal, |id| (id == 0).then_some(&sps)).is_err());
+    let nal = write_pps(no_slice_groups, 0, 3, |_| {});
+    assert!(Pps::parse(&nal, |id| (id == 0).then_some(&sps)).is_err());
+    let nal = write_pps(no_slice_groups, 0, 0, |writer| {
+        writer.flag(false).flag(false).se(13);
+    });
+    assert!(Pps::parse(&nal, |id| (id == 0).then_some(&sps)).is_err());
+    let nal = write_pps(
+        |writer| {
+            writer.ue(1).ue(7);
+        },
+        0,
+        0,
+        |_| {},
+    );
+    assert!(Pps::parse(&nal, |id| (id == 0).then_some(&sps)).is_err());
+
+    let mut nal = write_pps(no_slice_groups, 0, 0, |_| {});
+    nal[0] = 0x67;
+    assert!(Pps::parse(&nal, |id| (id == 0).then_some(&sps)).is_err());
+}
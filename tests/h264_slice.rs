@@ -0,0 +1,376 @@
+use ash_video::h264::slice::{
+    parse_slices, Mmco, PredWeightTable, RefPicListModification, SliceHeader, SliceType,
+    WeightEntry,
+};
+use ash_video::h264::ParameterSets;
+
+mod common;
+use common::bitwriter::BitWriter;
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/a.mp4");
+
+/// Slice headers of the first slice of every access unit.
+fn read_headers(path: &str) -> (ParameterSets, Vec<SliceHeader>) {
+    let (mut parameter_sets, access_units) = common::read_mp4(path);
+    let headers = access_units
+        .iter()
+        .map(|(access_unit, _)| {
+            let slices = parse_slices(access_unit, &mut parameter_sets).unwrap();
+            slices[0].header.clone()
+        })
+        .collect();
+    (parameter_sets, headers)
+}
+
+#[test]
+fn mp4_sample() {
+    let (_, headers) = read_headers(SAMPLE);
+    assert_eq!(headers.len(), 30);
+
+    let idr = &headers[0];
+    assert_eq!(idr.slice_type, SliceType::I);
+    assert!(idr.idr_pic_flag);
+    assert_eq!(idr.nal_ref_idc, 3);
+    assert_eq!((idr.frame_num, idr.idr_pic_id), (0, 0));
+    assert_eq!(idr.pred_weight_table, None);
+    assert!(
+        !idr.dec_ref_pic_marking
+            .as_ref()
+            .unwrap()
+            .long_term_reference_flag
+    );
+    assert_eq!(idr.slice_qp_delta, -3);
+    assert_eq!(idr.disable_deblocking_filter_idc, 1);
+    assert_eq!(idr.header_bits, 24);
+
+    // MaxPicNum is 16, abs_diff_pic_num_minus1 15 wraps around to the current picture number.
+    let p = &headers[2];
+    assert_eq!(p.slice_type, SliceType::P);
+    assert_eq!((p.nal_ref_idc, p.frame_num), (2, 2));
+    assert!(!p.num_ref_idx_active_override_flag);
+    assert_eq!(p.num_ref_idx_l0_active_minus1, 2);
+    assert_eq!(
+        p.ref_pic_list_modification_l0,
+        [
+            RefPicListModification::ShortTermSubtract(0),
+            RefPicListModification::ShortTermSubtract(15),
+            RefPicListModification::ShortTermSubtract(0),
+        ]
+    );
+    assert!(p.ref_pic_list_modification_l1.is_empty());
+    let weighted = WeightEntry {
+        luma_weight: Some((1, -1)),
+        chroma_weight: None,
+    };
+    assert_eq!(
+        p.pred_weight_table,
+        Some(PredWeightTable {
+            luma_log2_weight_denom: 0,
+            chroma_log2_weight_denom: 0,
+            l0: vec![WeightEntry::default(), weighted, WeightEntry::default()],
+            l1: Vec::new(),
+        })
+    );
+    assert_eq!(p.header_bits, 52);
+
+    let p = &headers[3];
+    assert!(p.num_ref_idx_active_override_flag);
+    assert_eq!(p.num_ref_idx_l0_active_minus1, 3);
+    assert_eq!(p.ref_pic_list_modification_l0.len(), 4);
+    assert_eq!(p.pred_weight_table.as_ref().unwrap().l0.len(), 4);
+
+    let idr = &headers[10];
+    assert!(idr.idr_pic_flag);
+    assert_eq!((idr.frame_num, idr.idr_pic_id), (0, 1));
+    assert!(idr.is_new_picture(&headers[9], 2));
+    assert!(!headers[9].is_new_picture(&headers[9], 2));
+}
+
+#[test]
+fn bipred_sample() {
+    let (_, headers) = read_headers(common::MP4_STREAM);
+
+    let b = &headers[2];
+    assert_eq!(b.slice_type, SliceType::B);
+    assert!(b.is_reference());
+    assert!(b.direct_spatial_mv_pred_flag);
+    assert_eq!(b.pic_order_cnt_lsb, 6);
+    // weighted_bipred_idc 2 is implicit, there is no table to read.
+    assert_eq!(b.pred_weight_table, None);
+
+    let b = &headers[3];
+    assert!(!b.is_reference());
+    assert_eq!(b.dec_ref_pic_marking, None);
+    assert_eq!(
+        (
+            b.num_ref_idx_l0_active_minus1,
+            b.num_ref_idx_l1_active_minus1
+        ),
+        (0, 1)
+    );
+
+    let p = &headers[7];
+    assert_eq!(p.slice_type, SliceType::P);
+    assert_eq!(
+        p.ref_pic_list_modification_l0,
+        [
+            RefPicListModification::ShortTermSubtract(1),
+            RefPicListModification::ShortTermSubtract(0),
+            RefPicListModification::ShortTermAdd(1),
+            RefPicListModification::ShortTermSubtract(0),
+        ]
+    );
+    assert_eq!(
+        p.pred_weight_table.as_ref().unwrap().l0[3].luma_weight,
+        Some((1, -1))
+    );
+
+    let b = &headers[9];
+    assert!(!b.is_reference());
+    assert_eq!(
+        b.ref_pic_list_modification_l0,
+        [
+            RefPicListModification::ShortTermSubtract(3),
+            RefPicListModification::ShortTermSubtract(0),
+            RefPicListModification::ShortTermAdd(1),
+        ]
+    );
+    assert!(b.ref_pic_list_modification_l1.is_empty());
+}
+
+/// The parameter sets of samples/a.mp4: CABAC, pic_order_cnt_type 2, four bit frame_num,
+/// weighted P prediction and deblocking filter control. PPS 1 adds explicit weighted
+/// bi-prediction.
+fn parameter_sets() -> ParameterSets {
+    let (mut parameter_sets, _) = common::read_mp4(SAMPLE);
+    let mut pps = parameter_sets.pps(0).unwrap().clone();
+    pps.pic_parameter_set_id = 1;
+    pps.weighted_bipred_idc = 1;
+    parameter_sets.insert_pps(pps);
+    parameter_sets
+}
+
+/// Writes slice_qp_delta and the deblocking filter syntax, then the first bits of
+/// slice_data() so the header is followed by something.
+fn finish_slice(writer: &mut BitWriter, nal_ref_idc: u8) -> (Vec<u8>, usize) {
+    writer.se(-4).ue(0).se(1).se(-2);
+    let header_bits = writer.position();
+    writer.bits(0xa5a5, 16);
+    (writer.nal(nal_ref_idc, 1), header_bits)
+}
+
+#[test]
+fn p_slice() {
+    let parameter_sets = parameter_sets();
+
+    let mut writer = BitWriter::default();
+    writer.ue(0).ue(5).ue(0).bits(7, 4);
+    writer.flag(true).ue(1);
+    // ref_pic_list_modification()
+    writer.flag(true).ue(2).ue(5).ue(1).ue(0).ue(3);
+    // pred_weight_table()
+    writer.ue(5).ue(3);
+    writer.flag(true).se(-7).se(12);
+    writer.flag(true).se(2).se(-3).se(4).se(-5);
+    writer.flag(false).flag(false);
+    // dec_ref_pic_marking()
+    writer.flag(true);
+    writer.ue(1).ue(3);
+    writer.ue(2).ue(1);
+    writer.ue(3).ue(0).ue(2);
+    writer.ue(4).ue(3);
+    writer.ue(6).ue(1);
+    writer.ue(0);
+    writer.ue(1);
+    let (nal, header_bits) = finish_slice(&mut writer, 2);
+
+    let header = SliceHeader::parse(&nal, &parameter_sets).unwrap();
+    assert_eq!(header.slice_type, SliceType::P);
+    assert_eq!(header.frame_num, 7);
+    assert!(header.num_ref_idx_active_override_flag);
+    assert_eq!(header.num_ref_idx_l0_active_minus1, 1);
+    assert_eq!(
+        header.ref_pic_list_modification_l0,
+        [
+            RefPicListModification::LongTerm(5),
+            RefPicListModification::ShortTermAdd(0),
+        ]
+    );
+    assert_eq!(
+        header.pred_weight_table,
+        Some(PredWeightTable {
+            luma_log2_weight_denom: 5,
+            chroma_log2_weight_denom: 3,
+            l0: vec![
+                WeightEntry {
+                    luma_weight: Some((-7, 12)),
+                    chroma_weight: Some([(2, -3), (4, -5)]),
+                },
+                WeightEntry::default(),
+            ],
+            l1: Vec::new(),
+        })
+    );
+
+    let marking = header.dec_ref_pic_marking.as_ref().unwrap();
+    assert!(marking.adaptive_ref_pic_marking_mode_flag);
+    assert_eq!(
+        marking.mmco,
+        [
+            Mmco::ForgetShortTerm {
+                difference_of_pic_nums_minus1: 3
+            },
+            Mmco::ForgetLongTerm {
+                long_term_pic_num: 1
+            },
+            Mmco::ShortTermToLongTerm {
+                difference_of_pic_nums_minus1: 0,
+                long_term_frame_idx: 2
+            },
+            Mmco::MaxLongTermFrameIdx {
+                max_long_term_frame_idx_plus1: 3
+            },
+            Mmco::CurrentToLongTerm {
+                long_term_frame_idx: 1
+            },
+        ]
+    );
+    assert!(!header.has_mmco5());
+
+    assert_eq!(header.cabac_init_idc, 1);
+    assert_eq!(header.slice_qp_delta, -4);
+    assert_eq!(header.disable_deblocking_filter_idc, 0);
+    assert_eq!(
+        (
+            header.slice_alpha_c0_offset_div2,
+            header.slice_beta_offset_div2
+        ),
+        (1, -2)
+    );
+    assert_eq!(header.header_bits, header_bits);
+}
+
+#[test]
+fn b_slice() {
+    let parameter_sets = parameter_sets();
+
+    let mut writer = BitWriter::default();
+    writer.ue(0).ue(6).ue(1).bits(3, 4);
+    writer.flag(false).flag(true).ue(0).ue(1);
+    // ref_pic_list_modification() of both lists
+    writer.flag(false);
+    writer.flag(true).ue(0).ue(2).ue(3);
+    // pred_weight_table() with one L0 and two L1 entries
+    writer.ue(6).ue(6);
+    writer.flag(true).se(3).se(0).flag(false);
+    writer.flag(false).flag(true).se(-1).se(1).se(0).se(2);
+    writer.flag(false).flag(false);
+    writer.ue(2);
+    let (nal, header_bits) = finish_slice(&mut writer, 0);
+
+    let header = SliceHeader::parse(&nal, &parameter_sets).unwrap();
+    assert_eq!(header.slice_type, SliceType::B);
+    assert!(!header.direct_spatial_mv_pred_flag);
+    assert_eq!(
+        (
+            header.num_ref_idx_l0_active_minus1,
+            header.num_ref_idx_l1_active_minus1
+        ),
+        (0, 1)
+    );
+    assert!(header.ref_pic_list_modification_l0.is_empty());
+    assert_eq!(
+        header.ref_pic_list_modification_l1,
+        [RefPicListModification::ShortTermSubtract(2)]
+    );
+
+    let table = header.pred_weight_table.as_ref().unwrap();
+    assert_eq!(
+        (table.luma_log2_weight_denom, table.chroma_log2_weight_denom),
+        (6, 6)
+    );
+    assert_eq!(
+        table.l0,
+        [WeightEntry {
+            luma_weight: Some((3, 0)),
+            chroma_weight: None,
+        }]
+    );
+    assert_eq!(
+        table.l1,
+        [
+            WeightEntry {
+                luma_weight: None,
+                chroma_weight: Some([(-1, 1), (0, 2)]),
+            },
+            WeightEntry::default(),
+        ]
+    );
+
+    assert_eq!(header.dec_ref_pic_marking, None);
+    assert_eq!(header.cabac_init_idc, 2);
+    assert_eq!(header.header_bits, header_bits);
+}
+
+#[test]
+fn mmco5() {
+    let parameter_sets = parameter_sets();
+
+    let mut writer = BitWriter::default();
+    writer.ue(0).ue(7).ue(0).bits(2, 4);
+    writer.flag(true).ue(1).ue(0).ue(5).ue(0);
+    let (nal, header_bits) = finish_slice(&mut writer, 1);
+
+    let header = SliceHeader::parse(&nal, &parameter_sets).unwrap();
+    assert_eq!(header.slice_type, SliceType::I);
+    assert_eq!(
+        header.dec_ref_pic_marking.as_ref().unwrap().mmco,
+        [
+            Mmco::ForgetShortTerm {
+                difference_of_pic_nums_minus1: 0
+            },
+            Mmco::ForgetAll,
+        ]
+    );
+    assert!(header.has_mmco5());
+    assert_eq!(header.header_bits, header_bits);
+
+    let mut writer = BitWriter::default();
+    writer.ue(0).ue(7).ue(0).bits(2, 4);
+    writer.flag(true).ue(7).ue(0);
+    let (nal, _) = finish_slice(&mut writer, 1);
+    assert!(SliceHeader::parse(&nal, &parameter_sets).is_err());
+}
+
+/// Headers are not limited in size, this one spans more than a kilobyte of escaped data.
+#[test]
+fn long_header() {
+    let parameter_sets = parameter_sets();
+
+    let mut writer = BitWriter::default();
+    writer.ue(0).ue(5).ue(0).bits(1, 4);
+    writer.flag(false).flag(false);
+    writer.ue(0).ue(0).bits(0, 6);
+    writer.flag(true);
+    for i in 0..66 {
+        writer.ue(3).ue(u32::MAX - 1).ue(u32::MAX - 1 - i);
+    }
+    writer.ue(0);
+    writer.ue(0);
+    let (nal, header_bits) = finish_slice(&mut writer, 2);
+    assert!(nal.len() > 1024);
+
+    let header = SliceHeader::parse(&nal, &parameter_sets).unwrap();
+    let mmco = &header.dec_ref_pic_marking.as_ref().unwrap().mmco;
+    assert_eq!(mmco.len(), 66);
+    assert_eq!(
+        mmco[65],
+        Mmco::ShortTermToLongTerm {
+            difference_of_pic_nums_minus1: u32::MAX - 1,
+            long_term_frame_idx: u32::MAX - 66,
+        }
+    );
+    assert_eq!(header.slice_qp_delta, -4);
+    assert_eq!(header.slice_beta_offset_div2, -2);
+    assert_eq!(header.header_bits, header_bits);
+}
@@ -0,0 +1,211 @@
+use ash_video::annexb::AnnexBReader;
+use ash_video::h264::sps::{Sps, DEFAULT_4X4_INTER, DEFAULT_4X4_INTRA, DEFAULT_8X8_INTRA};
+use ash_video::Timestamp;
+
+mod common;
+use common::bitwriter::BitWriter;
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/a.mp4");
+
+/// High profile level 3.0 4:2:0 with pic_order_cnt_type 0, four reference frames and no VUI.
+fn high_profile_sps(
+    scaling_lists: Option<fn(&mut BitWriter)>,
+    width_in_mbs_minus1: u32,
+    height_in_map_units_minus1: u32,
+    frame_mbs_only_flag: bool,
+    frame_crop: Option<[u32; 4]>,
+) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer
+        .bits(100, 8)
+        .bits(0, 8)
+        .bits(30, 8)
+        .ue(0)
+        .ue(1)
+        .ue(0)
+        .ue(0)
+        .flag(false)
+        .flag(scaling_lists.is_some());
+    if let Some(scaling_lists) = scaling_lists {
+        scaling_lists(&mut writer);
+    }
+    writer
+        .ue(0)
+        .ue(0)
+        .ue(2)
+        .ue(4)
+        .flag(false)
+        .ue(width_in_mbs_minus1)
+        .ue(height_in_map_units_minus1)
+        .flag(frame_mbs_only_flag);
+    if !frame_mbs_only_flag {
+        writer.flag(false);
+    }
+    writer.flag(true).flag(frame_crop.is_some());
+    for offset in frame_crop.into_iter().flatten() {
+        writer.ue(offset);
+    }
+    writer.flag(false).nal(3, 7)
+}
+
+#[test]
+fn mp4_sample() {
+    let (parameter_sets, _) = common::read_mp4(SAMPLE);
+    let sps = parameter_sets.sps(0).unwrap();
+
+    assert_eq!(sps.profile_idc, 100);
+    assert_eq!(sps.constraint_set_flags, 0);
+    assert_eq!(sps.level_idc, 11);
+    assert_eq!(sps.std_level_idc(), 1);
+    assert_eq!(sps.chroma_format_idc, 1);
+    assert!(!sps.seq_scaling_matrix_present_flag);
+    assert_eq!(sps.coded_extent(), (176, 144));
+    assert!(!sps.frame_cropping_flag);
+    assert_eq!(sps.crop_rect(), (0, 0, 176, 144));
+    assert_eq!(sps.pic_order_cnt_type, 2);
+    assert_eq!(sps.max_frame_num(), 16);
+    assert_eq!(sps.max_num_ref_frames, 3);
+
+    let vui = sps.vui.as_ref().unwrap();
+    assert!(vui.aspect_ratio_info_present_flag);
+    assert_eq!(vui.aspect_ratio_idc, 1);
+    assert!(vui.timing_info_present_flag);
+    assert_eq!((vui.num_units_in_tick, vui.time_scale), (1, 60));
+    assert!(!vui.fixed_frame_rate_flag);
+    assert!(vui.nal_hrd_parameters.is_none());
+    assert!(vui.vcl_hrd_parameters.is_none());
+    assert_eq!(sps.frame_duration(), Some(Timestamp::new(1, 30)));
+    assert!(vui.bitstream_restriction_flag);
+    assert_eq!(sps.max_dec_frame_buffering(), 3);
+    assert_eq!(sps.max_num_reorder_frames(), 0);
+}
+
+#[test]
+fn annexb_sample() {
+    let data = std::fs::read(common::ANNEXB_STREAM).unwrap();
+    let parameter_sets = AnnexBReader::read_parameter_sets(&data).unwrap();
+    let sps = parameter_sets.sps(0).unwrap();
+
+    assert_eq!(sps.profile_idc, 100);
+    assert_eq!(sps.level_idc, 11);
+    assert_eq!(sps.coded_extent(), (176, 144));
+    assert_eq!(sps.crop_rect(), (0, 0, 176, 144));
+    assert_eq!(sps.pic_order_cnt_type, 2);
+    assert_eq!(sps.max_num_ref_frames, 3);
+
+    let vui = sps.vui.as_ref().unwrap();
+    assert!(!vui.aspect_ratio_info_present_flag);
+    assert_eq!((vui.num_units_in_tick, vui.time_scale), (1, 60));
+    assert_eq!(sps.frame_duration(), Some(Timestamp::new(1, 30)));
+    assert_eq!(sps.max_dec_frame_buffering(), 3);
+}
+
+#[test]
+fn cropped_sample() {
+    let (parameter_sets, _) = common::read_mp4(common::MP4_STREAM);
+    let sps = parameter_sets.sps(0).unwrap();
+
+    assert_eq!(sps.level_idc, 31);
+    assert_eq!(sps.coded_extent(), (640, 368));
+    assert_eq!(
+        (
+            sps.frame_crop_left_offset,
+            sps.frame_crop_right_offset,
+            sps.frame_crop_top_offset,
+            sps.frame_crop_bottom_offset
+        ),
+        (0, 0, 0, 4)
+    );
+    assert_eq!(sps.crop_rect(), (0, 0, 640, 360));
+    assert_eq!(sps.pic_order_cnt_type, 0);
+    assert_eq!(sps.max_pic_order_cnt_lsb(), 256);
+    assert_eq!(sps.max_num_ref_frames, 16);
+    assert_eq!(sps.max_dec_frame_buffering(), 16);
+    assert_eq!(sps.max_num_reorder_frames(), 2);
+}
+
+#[test]
+fn scaling_lists() {
+    let nal = high_profile_sps(
+        Some(|writer| {
+            // Sl_4x4_Intra_Y switches to the default table, Sl_4x4_Intra_Cb is inferred from it.
+            writer.flag(true).se(-8).flag(false);
+            // Sl_4x4_Intra_Cr is flat 16, the inter lists fall back to Default_4x4_Inter.
+            writer
+                .flag(true)
+                .se(8)
+                .se(-16)
+                .flag(false)
+                .flag(false)
+                .flag(false);
+            // Sl_8x8_Intra_Y is inferred, Sl_8x8_Inter_Y is flat 12.
+            writer.flag(false).flag(true).se(4).se(-12);
+        }),
+        21,
+        17,
+        true,
+        None,
+    );
+    let sps = Sps::parse(&nal).unwrap();
+
+    assert!(sps.seq_scaling_matrix_present_flag);
+    assert_eq!(sps.pic_order_cnt_type, 0);
+    assert_eq!(sps.max_num_ref_frames, 4);
+    assert_eq!(sps.coded_extent(), (352, 288));
+
+    let lists = &sps.scaling_lists;
+    assert_eq!(lists.present_mask, 0b1000_0101);
+    assert_eq!(lists.use_default_mask, 0b1);
+    assert_eq!(lists.list_4x4[0], DEFAULT_4X4_INTRA);
+    assert_eq!(lists.list_4x4[1], DEFAULT_4X4_INTRA);
+    assert_eq!(lists.list_4x4[2], [16; 16]);
+    assert_eq!(lists.list_4x4[3], DEFAULT_4X4_INTER);
+    assert_eq!(lists.list_4x4[5], DEFAULT_4X4_INTER);
+    assert_eq!(lists.list_8x8[0], DEFAULT_8X8_INTRA);
+    assert_eq!(lists.list_8x8[1], [12; 64]);
+
+    let std = sps.to_std();
+    assert!(!std.sps.pScalingLists.is_null());
+    let std_lists = unsafe { &*std.sps.pScalingLists };
+    assert_eq!(std_lists.scaling_list_present_mask, 0b1000_0101);
+    assert_eq!(std_lists.ScalingList8x8[1], [12; 64]);
+}
+
+#[test]
+fn level_limits() {
+    // 1055 macroblocks in either direction is the most level 6.2 allows.
+    let nal = high_profile_sps(None, 1054, 0, true, None);
+    assert_eq!(Sps::parse(&nal).unwrap().coded_extent(), (16880, 16));
+    let nal = high_profile_sps(None, 0, 526, false, None);
+    assert_eq!(Sps::parse(&nal).unwrap().coded_extent(), (16, 16864));
+
+    for (width, height, frame_mbs_only_flag) in [
+        (1055, 0, true),
+        (u32::MAX - 1, 0, true),
+        (0, 527, false),
+        (0, u32::MAX - 1, true),
+    ] {
+        let nal = high_profile_sps(None, width, height, frame_mbs_only_flag, None);
+        assert!(Sps::parse(&nal).is_err(), "{}x{}", width, height);
+    }
+}
+
+#[test]
+fn cropping_limits() {
+    // 176x144, the crop units of 4:2:0 frames are two luma samples.
+    let nal = high_profile_sps(None, 10, 8, true, Some([43, 44, 0, 71]));
+    assert_eq!(Sps::parse(&nal).unwrap().crop_rect(), (86, 0, 2, 2));
+    let nal = high_profile_sps(None, 10, 8, false, Some([0, 0, 35, 35]));
+    assert_eq!(Sps::parse(&nal).unwrap().crop_rect(), (0, 140, 176, 8));
+
+    for frame_crop in [
+        [44, 44, 0, 0],
+        [0, 0, 72, 0],
+        [0, u32::MAX - 1, 0, 0],
+        [u32::MAX - 1, u32::MAX - 1, 0, 0],
+        [0, 0, 0, u32::MAX - 1],
+    ] {
+        let nal = high_profile_sps(None, 10, 8, true, Some(frame_crop));
+        assert!(Sps::parse(&nal).is_err(), "{:?}", frame_crop);
+    }
+}
@@ -0,0 +1,613 @@
+use ash_video::codec::{self, Codec};
+use ash_video::h265::dpb::Dpb;
+use ash_video::h265::hvcc::HevcDecoderConfiguration;
+use ash_video::h265::slice::SliceSegmentHeader;
+use ash_video::h265::{NalUnitHeader, NalUnitType, ParameterSets};
+use ash_video::mp4::{Boxes, VideoSampleEntry};
+
+/// Writes RBSP syntax elements, there is no H.265 sample stream to take them from.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u64, count: usize) -> &mut Self {
+        for i in (0..count).rev() {
+            if self.bits & 7 == 0 {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+        self
+    }
+
+    fn flag(&mut self, value: bool) -> &mut Self {
+        self.bits(value as u64, 1)
+    }
+
+    fn ue(&mut self, value: u32) -> &mut Self {
+        let value = value as u64 + 1;
+        let length = 64 - value.leading_zeros() as usize;
+        self.bits(0, length - 1).bits(value, length)
+    }
+
+    fn se(&mut self, value: i32) -> &mut Self {
+        let code = if value > 0 { 2 * value - 1 } else { -2 * value };
+        self.ue(code as u32)
+    }
+
+    /// The NAL unit with its header, rbsp_trailing_bits and emulation prevention bytes.
+    fn nal(&mut self, nal_unit_type: u8, nuh_temporal_id_plus1: u8) -> Vec<u8> {
+        self.flag(true);
+        while self.bits & 7 != 0 {
+            self.flag(false);
+        }
+
+        let mut nal = vec![nal_unit_type << 1, nuh_temporal_id_plus1];
+        let mut zeros = 0;
+        for &byte in self.bytes.iter() {
+            if zeros == 2 && byte <= 3 {
+                nal.push(3);
+                zeros = 0;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            nal.push(byte);
+        }
+        nal
+    }
+}
+
+/// Main profile, level 3.1
+fn profile_tier_level(writer: &mut BitWriter) {
+    writer
+        .bits(0, 2)
+        .flag(false)
+        .bits(1, 5)
+        .bits(0x6000_0000, 32)
+        .flag(true)
+        .flag(false)
+        .flag(false)
+        .flag(true)
+        .bits(0, 44)
+        .bits(93, 8);
+}
+
+/// sps_max_dec_pic_buffering_minus1 4, sps_max_num_reorder_pics 2
+fn dec_pic_buf_mgr(writer: &mut BitWriter) {
+    writer.flag(true).ue(4).ue(2).ue(0);
+}
+
+fn vps() -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer
+        .bits(0, 4)
+        .bits(0b11, 2)
+        .bits(0, 6)
+        .bits(0, 3)
+        .flag(true);
+    writer.bits(0xffff, 16);
+    profile_tier_level(&mut writer);
+    dec_pic_buf_mgr(&mut writer);
+    writer.bits(0, 6).ue(0).flag(false).flag(false);
+    writer.nal(32, 1)
+}
+
+/// 1920x1080 coded as 1920x1088, MaxPicOrderCntLsb 256. The two short-term sets are
+/// {-1} and {-2, -1}, the second one predicted from the first.
+fn sps(long_term_ref_pics_present_flag: bool) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer.bits(0, 4).bits(0, 3).flag(true);
+    profile_tier_level(&mut writer);
+    writer.ue(0).ue(1).ue(1920).ue(1088);
+    writer.flag(true).ue(0).ue(0).ue(0).ue(4);
+    writer.ue(0).ue(0).ue(4);
+    dec_pic_buf_mgr(&mut writer);
+    writer.ue(0).ue(3).ue(0).ue(3).ue(0).ue(0);
+    // scaling_list_enabled_flag, amp, sao, pcm
+    writer.flag(false).flag(true).flag(true).flag(false);
+
+    writer.ue(2);
+    // num_negative_pics 1, num_positive_pics 0, delta_poc_s0_minus1 0, used
+    writer.ue(1).ue(0).ue(0).flag(true);
+    // Predicted with deltaRps -1, both pictures used
+    writer.flag(true).flag(true).ue(0).flag(true).flag(true);
+
+    writer.flag(long_term_ref_pics_present_flag);
+    if long_term_ref_pics_present_flag {
+        writer.ue(0);
+    }
+    // sps_temporal_mvp_enabled_flag, strong_intra_smoothing, vui, extension
+    writer.flag(true).flag(true).flag(false).flag(false);
+    writer.nal(33, 1)
+}
+
+fn pps() -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer.ue(0).ue(0).flag(false).flag(false).bits(0, 3);
+    writer.flag(false).flag(false).ue(0).ue(0).se(0);
+    writer.flag(false).flag(false).flag(false).se(0).se(0);
+    writer.flag(false).flag(false).flag(false).flag(false);
+    writer.flag(false).flag(false).flag(true).flag(false);
+    writer.flag(false).flag(false).ue(0).flag(false).flag(false);
+    writer.nal(34, 1)
+}
+
+fn parameter_sets(long_term_ref_pics_present_flag: bool) -> ParameterSets {
+    let mut parameter_sets = ParameterSets::default();
+    for nal in [vps(), sps(long_term_ref_pics_present_flag), pps()] {
+        assert!(parameter_sets.add_nal(&nal).unwrap());
+    }
+    parameter_sets
+}
+
+/// The short-term set of a slice segment header.
+enum Rps {
+    /// short_term_ref_pic_set_idx into the SPS sets
+    Sps(u64),
+    /// {1, 2}, predicted from the second SPS set with deltaRps 2
+    Predicted,
+}
+
+/// First slice segment of a picture. `long_term` holds (poc_lsb_lt, used_by_curr_pic_lt_flag)
+/// of long-term pictures without MSB, it is `None` when the SPS has no long-term pictures.
+fn slice(
+    nal_unit_type: u8,
+    nuh_temporal_id_plus1: u8,
+    pic_order_cnt_lsb: u64,
+    rps: Rps,
+    long_term: Option<&[(u64, bool)]>,
+) -> Vec<u8> {
+    let irap = (16..=23).contains(&nal_unit_type);
+    let idr = nal_unit_type == 19 || nal_unit_type == 20;
+
+    let mut writer = BitWriter::default();
+    writer.flag(true);
+    if irap {
+        writer.flag(false);
+    }
+    writer.ue(0).ue(if irap { 2 } else { 1 });
+    if !idr {
+        writer.bits(pic_order_cnt_lsb, 8);
+        match rps {
+            Rps::Sps(idx) => writer.flag(true).bits(idx, 1),
+            Rps::Predicted => writer
+                .flag(false)
+                .flag(true)
+                .ue(0)
+                .flag(false)
+                .ue(1)
+                .flag(true)
+                .flag(true)
+                .flag(true),
+        };
+        if let Some(long_term) = long_term {
+            writer.ue(long_term.len() as u32);
+            for &(poc_lsb_lt, used) in long_term {
+                writer.bits(poc_lsb_lt, 8).flag(used).flag(false);
+            }
+        }
+        writer.flag(true);
+    }
+    // Some slice data
+    writer.bits(0xa5a5, 16);
+    writer.nal(nal_unit_type, nuh_temporal_id_plus1)
+}
+
+fn header(parameter_sets: &ParameterSets, nal: &[u8]) -> SliceSegmentHeader {
+    SliceSegmentHeader::parse(nal, parameter_sets).unwrap()
+}
+
+#[test]
+fn nal_unit_header() {
+    let header = NalUnitHeader::parse(&[0x40, 0x01]).unwrap();
+    assert_eq!(header.nal_unit_type, NalUnitType::Vps);
+    assert_eq!(header.nuh_layer_id, 0);
+    assert_eq!(header.temporal_id(), 0);
+
+    let header = NalUnitHeader::parse(&[0x02, 0x0b]).unwrap();
+    assert_eq!(header.nal_unit_type, NalUnitType::TrailR);
+    assert_eq!(header.nuh_layer_id, 1);
+    assert_eq!(header.temporal_id(), 2);
+
+    assert!(NalUnitHeader::parse(&[0x80, 0x01]).is_err());
+    assert!(NalUnitHeader::parse(&[0x02, 0x00]).is_err());
+    assert!(NalUnitHeader::parse(&[0x02]).is_err());
+
+    assert!(NalUnitType::Cra.is_irap());
+    assert!(!NalUnitType::RaslN.is_irap());
+    assert!(NalUnitType::Reserved(22).is_irap());
+    assert!(NalUnitType::Reserved(31).is_vcl());
+    assert!(!NalUnitType::PrefixSei.is_vcl());
+}
+
+#[test]
+fn sequence_parameter_set() {
+    let parameter_sets = parameter_sets(false);
+    let sps = parameter_sets.sps(0).unwrap();
+    assert_eq!(sps.profile_tier_level.profile_idc(), 1);
+    assert_eq!(sps.coded_extent(), (1920, 1088));
+    assert_eq!(sps.crop_rect(), (0, 0, 1920, 1080));
+    assert_eq!(sps.max_pic_order_cnt_lsb(), 256);
+    assert_eq!(sps.max_dec_pic_buffering(), 5);
+    assert_eq!(sps.max_num_reorder_pics(), 2);
+    assert_eq!(sps.ctb_size(), 64);
+
+    let (pps, active_sps) = parameter_sets.active(0).unwrap();
+    assert_eq!(pps.pps_seq_parameter_set_id, 0);
+    assert_eq!(active_sps, sps);
+    assert!(parameter_sets.active(1).is_err());
+
+    let sets = &sps.short_term_ref_pic_sets;
+    assert_eq!(sets[0].delta_poc_s0, [-1]);
+    assert!(sets[0].delta_poc_s1.is_empty());
+    assert!(sets[1].inter_ref_pic_set_prediction_flag);
+    assert_eq!(sets[1].delta_poc_s0, [-1, -2]);
+    assert_eq!(sets[1].used_by_curr_pic_s0, [true, true]);
+    assert!(sets[1].delta_poc_s1.is_empty());
+}
+
+#[test]
+fn slice_segment_header() {
+    let parameter_sets = parameter_sets(false);
+    let sps = parameter_sets.sps(0).unwrap();
+
+    let idr = header(&parameter_sets, &slice(19, 1, 0, Rps::Sps(0), None));
+    assert_eq!(idr.nal_unit_type, NalUnitType::IdrWRadl);
+    assert!(idr.first_slice_segment_in_pic_flag);
+    assert!(idr.short_term_ref_pic_set(sps).is_none());
+
+    let trail = header(&parameter_sets, &slice(1, 2, 7, Rps::Sps(1), None));
+    assert_eq!(trail.temporal_id, 1);
+    assert_eq!(trail.slice_pic_order_cnt_lsb, 7);
+    assert!(trail.short_term_ref_pic_set_sps_flag);
+    assert_eq!(trail.short_term_ref_pic_set_idx, 1);
+    assert_eq!(
+        trail.short_term_ref_pic_set(sps).unwrap().delta_poc_s0,
+        [-1, -2]
+    );
+    assert!(trail.slice_temporal_mvp_enabled_flag);
+
+    let predicted = header(&parameter_sets, &slice(1, 1, 8, Rps::Predicted, None));
+    let set = predicted.short_term_ref_pic_set(sps).unwrap();
+    assert!(set.delta_poc_s0.is_empty());
+    assert_eq!(set.delta_poc_s1, [1, 2]);
+    assert_eq!(predicted.st_ref_pic_set_bits, 9);
+    assert_eq!(predicted.num_delta_pocs_of_ref_rps_idx(sps), 2);
+}
+
+#[test]
+fn hvcc() {
+    let nal_units = [vps(), sps(false), pps()];
+    let mut record = vec![
+        1, 0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 93, 0xf0, 0, 0xfc, 0xfd, 0xf8, 0xf8, 0, 0,
+        0x0f,
+    ];
+    record.push(nal_units.len() as u8);
+    for nal in nal_units.iter() {
+        record.push(0x80 | (nal[0] >> 1));
+        record.extend_from_slice(&1u16.to_be_bytes());
+        record.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        record.extend_from_slice(nal);
+    }
+
+    let config = HevcDecoderConfiguration::parse(&record).unwrap();
+    assert_eq!(config.general_profile_idc, 1);
+    assert!(!config.general_tier_flag);
+    assert_eq!(config.general_level_idc, 93);
+    assert_eq!(config.chroma_format_idc, 1);
+    assert_eq!((config.bit_depth_luma, config.bit_depth_chroma), (8, 8));
+    assert_eq!(config.length_size, 4);
+    assert_eq!(config.nal_units, nal_units);
+
+    let parameter_sets = codec::ParameterSets::from(config.parameter_sets().unwrap());
+    let stream_info = parameter_sets.stream_info().unwrap();
+    assert_eq!(stream_info.codec, Codec::H265);
+    assert_eq!(stream_info.coded_extent, (1920, 1088));
+    assert_eq!(stream_info.crop_rect, (0, 0, 1920, 1080));
+    assert_eq!(stream_info.frame_duration, None);
+
+    assert!(HevcDecoderConfiguration::parse(&record[..22]).is_err());
+    assert!(HevcDecoderConfiguration::parse(&record[..record.len() - 1]).is_err());
+}
+
+#[test]
+fn dpb_pic_order_cnt_and_rps() {
+    let parameter_sets = parameter_sets(false);
+    let sps = parameter_sets.sps(0).unwrap();
+    let mut dpb = Dpb::new(4);
+
+    let idr = dpb
+        .start_picture(
+            &header(&parameter_sets, &slice(19, 1, 0, Rps::Sps(0), None)),
+            sps,
+        )
+        .unwrap()
+        .unwrap();
+    assert!(idr.irap && idr.idr && idr.no_rasl_output_flag);
+    assert_eq!(idr.pic_order_cnt, 0);
+    dpb.finish_picture(&idr);
+
+    // Each picture refers to the two before it
+    let mut slots = vec![idr.slot];
+    for pic_order_cnt in 1..=4 {
+        let picture = dpb
+            .start_picture(
+                &header(
+                    &parameter_sets,
+                    &slice(1, 1, pic_order_cnt as u64, Rps::Sps(1), None),
+                ),
+                sps,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(picture.pic_order_cnt, pic_order_cnt);
+        let before = slots.len().checked_sub(2).map(|index| slots[index]);
+        assert_eq!(picture.st_curr_before, [slots.last().copied(), before]);
+        assert!(picture.st_curr_after.is_empty());
+        assert!(picture.lt_curr.is_empty());
+        dpb.finish_picture(&picture);
+        slots.push(picture.slot);
+    }
+    // The slots of pictures that left the RPS are reused
+    assert_eq!(slots, [0, 1, 2, 0, 1]);
+    assert_eq!(dpb.references().len(), 3);
+
+    // A missing reference picture
+    let picture = dpb
+        .start_picture(
+            &header(&parameter_sets, &slice(1, 1, 10, Rps::Sps(0), None)),
+            sps,
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(picture.st_curr_before, [None]);
+    assert!(dpb.references().is_empty());
+}
+
+#[test]
+fn dpb_pic_order_cnt_wraps() {
+    let parameter_sets = parameter_sets(false);
+    let sps = parameter_sets.sps(0).unwrap();
+    let mut dpb = Dpb::new(4);
+
+    // A CRA picture with a large POC, followed by pictures after the LSB wrapped
+    let mut pocs = Vec::new();
+    for (nal_unit_type, pic_order_cnt_lsb) in [(21, 250), (1, 251), (1, 255), (1, 4), (1, 100)] {
+        let picture = dpb
+            .start_picture(
+                &header(
+                    &parameter_sets,
+                    &slice(nal_unit_type, 1, pic_order_cnt_lsb, Rps::Sps(0), None),
+                ),
+                sps,
+            )
+            .unwrap()
+            .unwrap();
+        dpb.finish_picture(&picture);
+        pocs.push(picture.pic_order_cnt);
+    }
+    // Decoding starts at the CRA picture, its MSB is 0
+    assert_eq!(pocs, [250, 251, 255, 260, 356]);
+
+    // Sub-layer non-reference and higher sub-layer pictures do not move prevTid0Pic
+    let mut dpb = Dpb::new(4);
+    for (nal_unit_type, nuh_temporal_id_plus1, pic_order_cnt_lsb, pic_order_cnt) in [
+        (19, 1, 0, 0),
+        (1, 1, 100, 100),
+        (0, 1, 200, 200),
+        (1, 2, 220, 220),
+        (1, 1, 20, 20),
+    ] {
+        let picture = dpb
+            .start_picture(
+                &header(
+                    &parameter_sets,
+                    &slice(
+                        nal_unit_type,
+                        nuh_temporal_id_plus1,
+                        pic_order_cnt_lsb,
+                        Rps::Sps(0),
+                        None,
+                    ),
+                ),
+                sps,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(picture.pic_order_cnt, pic_order_cnt);
+        assert_eq!(picture.is_reference, nal_unit_type != 0);
+        dpb.finish_picture(&picture);
+    }
+}
+
+#[test]
+fn dpb_skips_undecodable_pictures() {
+    let parameter_sets = parameter_sets(false);
+    let sps = parameter_sets.sps(0).unwrap();
+    let mut dpb = Dpb::new(4);
+    let start = |dpb: &mut Dpb, nal_unit_type, pic_order_cnt_lsb| {
+        let header = header(
+            &parameter_sets,
+            &slice(nal_unit_type, 1, pic_order_cnt_lsb, Rps::Sps(0), None),
+        );
+        let picture = dpb.start_picture(&header, sps).unwrap();
+        if let Some(picture) = &picture {
+            dpb.finish_picture(picture);
+        }
+        picture
+    };
+
+    // Nothing before the first IRAP picture
+    assert!(start(&mut dpb, 1, 5).is_none());
+    // The RASL pictures of the CRA picture decoding starts at, but not the RADL ones
+    let cra = start(&mut dpb, 21, 16).unwrap();
+    assert!(cra.no_rasl_output_flag);
+    assert!(start(&mut dpb, 8, 14).is_none());
+    assert!(start(&mut dpb, 6, 15).is_some());
+    assert!(start(&mut dpb, 1, 17).is_some());
+
+    // A later CRA picture has decodable RASL pictures
+    let cra = start(&mut dpb, 21, 24).unwrap();
+    assert!(!cra.no_rasl_output_flag);
+    assert_eq!(cra.pic_order_cnt, 24);
+    assert!(start(&mut dpb, 9, 23).is_some());
+
+    // Until an end of sequence, after which it is handled like the first one
+    dpb.end_sequence();
+    assert!(start(&mut dpb, 1, 30).is_none());
+    let cra = start(&mut dpb, 21, 32).unwrap();
+    assert!(cra.no_rasl_output_flag);
+    assert_eq!(cra.pic_order_cnt, 32);
+    assert!(start(&mut dpb, 8, 31).is_none());
+}
+
+#[test]
+fn dpb_held_slots() {
+    let parameter_sets = parameter_sets(false);
+    let sps = parameter_sets.sps(0).unwrap();
+    let mut dpb = Dpb::new(2);
+
+    let idr = dpb
+        .start_picture(
+            &header(&parameter_sets, &slice(20, 1, 0, Rps::Sps(0), None)),
+            sps,
+        )
+        .unwrap()
+        .unwrap();
+    dpb.finish_picture(&idr);
+    dpb.hold_slot(idr.slot);
+
+    // The IDR picture drops out of the RPS but its slot is still waiting for output
+    let trail = |lsb| header(&parameter_sets, &slice(1, 1, lsb, Rps::Sps(0), None));
+    let picture = dpb.start_picture(&trail(5), sps).unwrap().unwrap();
+    assert_ne!(picture.slot, idr.slot);
+    dpb.finish_picture(&picture);
+    dpb.hold_slot(picture.slot);
+    assert!(dpb.start_picture(&trail(7), sps).is_err());
+
+    dpb.release_slot(idr.slot);
+    let picture = dpb.start_picture(&trail(8), sps).unwrap().unwrap();
+    assert_eq!(picture.slot, idr.slot);
+
+    // Clearing keeps the held slot and waits for the next IRAP picture
+    dpb.clear();
+    assert!(dpb.references().is_empty());
+    assert!(dpb.start_picture(&trail(9), sps).unwrap().is_none());
+    let picture = dpb
+        .start_picture(
+            &header(&parameter_sets, &slice(21, 1, 12, Rps::Sps(0), None)),
+            sps,
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(picture.slot, idr.slot);
+}
+
+#[test]
+fn dpb_long_term_references() {
+    let parameter_sets = parameter_sets(true);
+    let sps = parameter_sets.sps(0).unwrap();
+    let mut dpb = Dpb::new(4);
+    let decode = |dpb: &mut Dpb, nal: Vec<u8>| {
+        let picture = dpb
+            .start_picture(&header(&parameter_sets, &nal), sps)
+            .unwrap()
+            .unwrap();
+        dpb.finish_picture(&picture);
+        picture
+    };
+
+    let idr = decode(&mut dpb, slice(19, 1, 0, Rps::Sps(0), None));
+    let first = decode(&mut dpb, slice(1, 1, 1, Rps::Sps(0), Some(&[(0, true)])));
+    assert_eq!(first.st_curr_before, [None]);
+    assert_eq!(first.lt_curr, [Some(idr.slot)]);
+    assert!(dpb
+        .references()
+        .iter()
+        .any(|picture| picture.slot == idr.slot && picture.long_term));
+
+    // The long-term picture is no short-term candidate anymore, and stays as long as the
+    // following pictures keep it, used or not
+    let second = decode(&mut dpb, slice(1, 1, 2, Rps::Sps(1), Some(&[(0, false)])));
+    assert_eq!(second.st_curr_before, [Some(first.slot), None]);
+    assert!(second.lt_curr.is_empty());
+    assert_eq!(dpb.references().len(), 3);
+
+    let third = decode(&mut dpb, slice(1, 1, 3, Rps::Sps(0), Some(&[])));
+    assert_eq!(third.st_curr_before, [Some(second.slot)]);
+    assert!(dpb.references().iter().all(|picture| !picture.long_term));
+}
+
+/// A box with a 32 bit size.
+fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(box_type);
+    data.extend_from_slice(payload);
+    data
+}
+
+#[test]
+fn mp4_boxes() {
+    let mut data = mp4_box(b"ftyp", b"isom");
+    // largesize
+    data.extend_from_slice(&1u32.to_be_bytes());
+    data.extend_from_slice(b"free");
+    data.extend_from_slice(&18u64.to_be_bytes());
+    data.extend_from_slice(&[1, 2]);
+    // Up to the end of the file
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.extend_from_slice(b"mdat");
+    data.extend_from_slice(&[3, 4, 5]);
+
+    let boxes: Vec<_> = Boxes::new(&data).map(Result::unwrap).collect();
+    assert_eq!(
+        boxes,
+        [
+            (*b"ftyp", &b"isom"[..]),
+            (*b"free", &[1, 2][..]),
+            (*b"mdat", &[3, 4, 5][..])
+        ]
+    );
+
+    let mut boxes = Boxes::new(&data[..data.len() - 14]);
+    assert!(boxes.next().unwrap().is_ok());
+    assert!(boxes.next().unwrap().is_err());
+    assert!(boxes.next().is_none());
+    assert!(Boxes::new(&data[..6]).next().unwrap().is_err());
+}
+
+#[test]
+fn mp4_video_sample_entry() {
+    let trak = |track_id: u32, format: &[u8; 4]| {
+        let mut tkhd = vec![0; 12];
+        tkhd.extend_from_slice(&track_id.to_be_bytes());
+        tkhd.resize(84, 0);
+
+        let mut entry = vec![0; 78];
+        entry[24..26].copy_from_slice(&1920u16.to_be_bytes());
+        entry[26..28].copy_from_slice(&1080u16.to_be_bytes());
+        entry.extend(mp4_box(b"hvcC", &[1, 2, 3]));
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(mp4_box(format, &entry));
+
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let mdia = mp4_box(b"mdia", &mp4_box(b"minf", &stbl));
+        mp4_box(b"trak", &[mp4_box(b"tkhd", &tkhd), mdia].concat())
+    };
+    let moov = mp4_box(b"moov", &[trak(1, b"mp4a"), trak(2, b"hev1")].concat());
+    let data = [mp4_box(b"ftyp", b"isom"), moov].concat();
+
+    let entry = VideoSampleEntry::find(&data, 2).unwrap();
+    assert_eq!(&entry.format, b"hev1");
+    assert_eq!((entry.width, entry.height), (1920, 1080));
+    assert_eq!(entry.child(b"hvcC"), Some(&[1, 2, 3][..]));
+    assert_eq!(entry.child(b"btrt"), None);
+
+    assert!(VideoSampleEntry::find(&data, 3).is_err());
+    assert!(VideoSampleEntry::find(&data[..20], 2).is_err());
+}
@@ -0,0 +1,609 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::anyhow;
+use ash_video::demux::ContainerFormat;
+use ash_video::h264::avcc::AvcDecoderConfiguration;
+use ash_video::hls::{self, MediaInitialization, Playlist, Variant};
+use ash_video::ts::CLOCK_RATE;
+use ash_video::{codec, http, stream, Timestamp};
+
+mod common;
+use common::mpegts::{pes, Muxer, VIDEO_PID};
+use common::{fmp4, MP4_STREAM};
+
+const SAMPLES_PER_FRAGMENT: usize = 60;
+
+type AccessUnits = Vec<(Vec<u8>, Timestamp)>;
+
+enum Response {
+    Body(Vec<u8>),
+    Redirect(String),
+}
+
+/// The paths a [`Server`] answers, which tests may change while it runs.
+type Routes = Arc<Mutex<Vec<(String, Response)>>>;
+
+/// A stand-in for a web server, one request per connection. Playlists are sent chunked and
+/// range requests answered with 206.
+struct Server {
+    url: String,
+    routes: Routes,
+    /// The requested paths, with their byte ranges
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Server {
+    fn start(routes: Vec<(String, Response)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let routes = Arc::new(Mutex::new(routes));
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let (server_routes, server_log) = (routes.clone(), log.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut lines = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line.trim_end().is_empty() {
+                        break;
+                    }
+                    lines.push(line.trim_end().to_string());
+                }
+                let path = lines[0].split(' ').nth(1).unwrap().to_string();
+                let range: Option<Range<usize>> = lines.iter().find_map(|line| {
+                    let (first, last) = line.strip_prefix("Range: bytes=")?.split_once('-')?;
+                    Some(first.parse().unwrap()..last.parse::<usize>().unwrap() + 1)
+                });
+                server_log.lock().unwrap().push(match &range {
+                    Some(range) => format!("{} {:?}", path, range),
+                    None => path.clone(),
+                });
+
+                let routes = server_routes.lock().unwrap();
+                let response = routes.iter().find(|(p, _)| *p == path).map(|(_, r)| r);
+                let mut writer = stream;
+                let head = match response {
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+                    Some(Response::Redirect(location)) => format!(
+                        "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                        location
+                    ),
+                    Some(Response::Body(body)) if path.ends_with(".m3u8") => {
+                        let mut response = "HTTP/1.1 200 OK\r\n\
+                                            Content-Type: application/vnd.apple.mpegurl\r\n\
+                                            Transfer-Encoding: chunked\r\n\r\n"
+                            .to_string();
+                        for chunk in body.chunks(100) {
+                            response += &format!("{:x}\r\n", chunk.len());
+                            response += std::str::from_utf8(chunk).unwrap();
+                            response += "\r\n";
+                        }
+                        response + "0\r\n\r\n"
+                    }
+                    Some(Response::Body(body)) => {
+                        let (status, body) = match &range {
+                            Some(range) => ("206 Partial Content", &body[range.clone()]),
+                            None => ("200 OK", &body[..]),
+                        };
+                        writer
+                            .write_all(
+                                format!(
+                                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                                    status,
+                                    body.len()
+                                )
+                                .as_bytes(),
+                            )
+                            .unwrap();
+                        writer.write_all(body).unwrap();
+                        continue;
+                    }
+                };
+                writer.write_all(head.as_bytes()).unwrap();
+            }
+        });
+        Self { url, routes, log }
+    }
+
+    fn set(&self, path: &str, response: Response) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.retain(|(p, _)| p != path);
+        routes.push((path.to_string(), response));
+    }
+
+    fn log(&self) -> Vec<String> {
+        self.log.lock().unwrap().clone()
+    }
+}
+
+/// The sample file as fragmented MP4 in a single file, the init section and the segments
+/// addressed with byte ranges.
+fn fmp4_variant() -> (String, Vec<u8>) {
+    let data = std::fs::read(MP4_STREAM).unwrap();
+    let (init, segments) = fmp4::fragment(&data, SAMPLES_PER_FRAGMENT);
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:2\n#EXT-X-PLAYLIST-TYPE:VOD\n\
+         #EXT-X-MAP:URI=\"bbb.mp4\",BYTERANGE=\"{}@0\"\n",
+        init.len()
+    );
+    for (index, segment) in segments.iter().enumerate() {
+        playlist += "#EXTINF:2.0,\n";
+        playlist += &match index {
+            // The others follow the previous range
+            0 => format!("#EXT-X-BYTERANGE:{}@{}\n", segment.len(), init.len()),
+            _ => format!("#EXT-X-BYTERANGE:{}\n", segment.len()),
+        };
+        playlist += "bbb.mp4\n";
+    }
+    playlist += "#EXT-X-ENDLIST\n";
+    (playlist, [init, segments.concat()].concat())
+}
+
+/// The access units of the sample file in transport stream segments of
+/// `SAMPLES_PER_FRAGMENT` access units, each with the parameter sets in front as segments
+/// are demuxed on their own. Each segment comes with the access units a demuxer reads back
+/// from it.
+fn ts_segments() -> Vec<(Vec<u8>, AccessUnits)> {
+    let data = std::fs::read(MP4_STREAM).unwrap();
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(&data)).unwrap();
+    let avcc = context
+        .tracks
+        .iter()
+        .find_map(|track| match &track.stsd.as_ref()?.descriptions[0] {
+            mp4parse::SampleEntry::Video(entry) => match &entry.codec_specific {
+                mp4parse::VideoCodecSpecific::AVCConfig(avcc) => Some(avcc),
+                _ => None,
+            },
+            _ => None,
+        })
+        .unwrap();
+    let config = AvcDecoderConfiguration::parse(avcc).unwrap();
+    let parameter_sets: Vec<u8> = config
+        .sps
+        .iter()
+        .chain(&config.pps)
+        .flat_map(|set| [0, 0, 0, 1].iter().chain(set).copied())
+        .collect();
+
+    let (_, access_units) = common::read_mp4(MP4_STREAM);
+    let segments = access_units.chunks(SAMPLES_PER_FRAGMENT).map(|chunk| {
+        chunk
+            .iter()
+            .enumerate()
+            .map(|(index, (access_unit, pts))| {
+                let access_unit = match index {
+                    0 => [&parameter_sets[..], access_unit].concat(),
+                    _ => access_unit.clone(),
+                };
+                (access_unit, pts.rescale(CLOCK_RATE))
+            })
+            .collect::<AccessUnits>()
+    });
+
+    segments
+        .map(|segment| {
+            let mut muxer = Muxer::new();
+            muxer.write_tables(0x1b);
+            for (index, (access_unit, pts)) in segment.iter().enumerate() {
+                muxer.write(
+                    VIDEO_PID,
+                    &pes(Some(pts.value), None, access_unit),
+                    index == 0,
+                );
+            }
+            (muxer.finish(), segment)
+        })
+        .collect()
+}
+
+fn media_playlist(target_duration: u64, uris: &[String], end_list: bool) -> String {
+    let mut playlist = format!("#EXTM3U\n#EXT-X-TARGETDURATION:{}\n", target_duration);
+    for uri in uris {
+        playlist += &format!("#EXTINF:{}.0,\n{}\n", target_duration, uri);
+    }
+    if end_list {
+        playlist += "#EXT-X-ENDLIST\n";
+    }
+    playlist
+}
+
+const MASTER_PLAYLIST: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=20000000,RESOLUTION=3840x2160,CODECS=\"avc1.640033\"
+uhd/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1000000,RESOLUTION=640x360,CODECS=\"avc1.64001e,mp4a.40.2\"
+fmp4/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,CODECS=\"avc1.64001e\"
+ts/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.2\"
+audio/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=900000,RESOLUTION=1280x720,CODECS=\"hvc1.1.6.L93.B0\"
+hevc/index.m3u8
+";
+
+/// A device that decodes H.264 up to 1080p and no H.265.
+fn max_coded_extent(codec: codec::Codec) -> anyhow::Result<(u32, u32)> {
+    match codec {
+        codec::Codec::H264 => Ok((1920, 1088)),
+        _ => Err(anyhow!("Unsupported codec {:?}", codec)),
+    }
+}
+
+fn serve_master() -> (Server, AccessUnits) {
+    let (fmp4_playlist, fmp4_data) = fmp4_variant();
+    let (segments, ts_access_units): (Vec<_>, Vec<_>) = ts_segments().into_iter().unzip();
+    let uris: Vec<String> = (0..segments.len()).map(|i| format!("{}.ts", i)).collect();
+
+    let mut routes = vec![
+        (
+            "/master.m3u8".to_string(),
+            Response::Body(MASTER_PLAYLIST.into()),
+        ),
+        // Moved within the same directory
+        (
+            "/fmp4/index.m3u8".to_string(),
+            Response::Redirect("/fmp4/playlist.m3u8".to_string()),
+        ),
+        (
+            "/fmp4/playlist.m3u8".to_string(),
+            Response::Body(fmp4_playlist.into()),
+        ),
+        ("/fmp4/bbb.mp4".to_string(), Response::Body(fmp4_data)),
+        (
+            "/ts/index.m3u8".to_string(),
+            Response::Body(media_playlist(2, &uris, true).into()),
+        ),
+    ];
+    for (uri, segment) in uris.iter().zip(segments) {
+        routes.push((format!("/ts/{}", uri), Response::Body(segment)));
+    }
+    (Server::start(routes), ts_access_units.concat())
+}
+
+#[test]
+fn plays_fragmented_mp4_variant() {
+    let (server, _) = serve_master();
+    let stream = stream::open_hls(
+        &format!("{}/master.m3u8", server.url),
+        None,
+        max_coded_extent,
+    )
+    .unwrap();
+    assert_eq!(stream.format, ContainerFormat::Mp4);
+    assert_eq!((stream.width, stream.height), (640, 360));
+
+    let (parameter_sets, access_units) = common::read_mp4(MP4_STREAM);
+    assert_eq!(
+        stream.parameter_sets,
+        codec::ParameterSets::from(parameter_sets)
+    );
+    let received: AccessUnits = stream.access_units.map(Result::unwrap).collect();
+    assert_eq!(received.len(), access_units.len());
+    assert!(received == access_units);
+
+    // The init section once, then every segment by its byte range
+    let log = server.log();
+    assert_eq!(
+        log[..3],
+        ["/master.m3u8", "/fmp4/index.m3u8", "/fmp4/playlist.m3u8"]
+    );
+    assert!(log[3].starts_with("/fmp4/bbb.mp4 0.."));
+    let segments = access_units.len().div_ceil(SAMPLES_PER_FRAGMENT);
+    assert_eq!(log.len(), 4 + segments);
+    assert!(log[4..]
+        .iter()
+        .all(|path| path.starts_with("/fmp4/bbb.mp4 ")));
+}
+
+#[test]
+fn plays_transport_stream_variant_within_bandwidth() {
+    let (server, ts_access_units) = serve_master();
+    let stream = stream::open_hls(
+        &format!("{}/master.m3u8", server.url),
+        Some(900_000),
+        max_coded_extent,
+    )
+    .unwrap();
+    assert_eq!(stream.format, ContainerFormat::MpegTs);
+
+    let (parameter_sets, _) = common::read_mp4(MP4_STREAM);
+    assert_eq!(
+        stream.parameter_sets,
+        codec::ParameterSets::from(parameter_sets)
+    );
+    let (width, height) = stream.parameter_sets.stream_info().unwrap().coded_extent;
+    assert_eq!((stream.width as u32, stream.height as u32), (width, height));
+
+    let received: AccessUnits = stream.access_units.map(Result::unwrap).collect();
+    assert_eq!(received.len(), ts_access_units.len());
+    assert!(received == ts_access_units);
+    assert!(server
+        .log()
+        .iter()
+        .all(|path| path == "/master.m3u8" || path.starts_with("/ts/")));
+}
+
+#[test]
+fn follows_live_playlist() {
+    let (segments, ts_access_units): (Vec<_>, Vec<_>) = ts_segments().into_iter().unzip();
+    assert!(segments.len() > 2);
+    let uris: Vec<String> = (0..segments.len()).map(|i| format!("{}.ts", i)).collect();
+    let first_two = ts_access_units[0].len() + ts_access_units[1].len();
+
+    let mut routes = vec![(
+        "/live.m3u8".to_string(),
+        Response::Body(media_playlist(1, &uris[..2], false).into()),
+    )];
+    for (uri, segment) in uris.iter().zip(segments) {
+        routes.push((format!("/{}", uri), Response::Body(segment)));
+    }
+    let server = Server::start(routes);
+
+    let url = format!("{}/live.m3u8", server.url);
+    let mut stream = stream::open_hls(&url, None, max_coded_extent).unwrap();
+    let mut received: AccessUnits = stream
+        .access_units
+        .by_ref()
+        .take(first_two)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(server.log(), ["/live.m3u8", "/0.ts", "/1.ts"]);
+
+    // The stream waits for the server to add the rest
+    server.set(
+        "/live.m3u8",
+        Response::Body(media_playlist(1, &uris, true).into()),
+    );
+    received.extend(stream.access_units.map(Result::unwrap));
+    assert!(received == ts_access_units.concat());
+    assert_eq!(server.log()[3], "/live.m3u8");
+}
+
+#[test]
+fn server_errors() {
+    let server = Server::start(vec![
+        (
+            "/loop.m3u8".to_string(),
+            Response::Redirect("/loop.m3u8".to_string()),
+        ),
+        (
+            "/empty.m3u8".to_string(),
+            Response::Body("#EXTM3U\n#EXT-X-ENDLIST\n".into()),
+        ),
+        (
+            "/master.m3u8".to_string(),
+            Response::Body(MASTER_PLAYLIST.into()),
+        ),
+    ]);
+    let open = |path: &str| {
+        stream::open_hls(&format!("{}{}", server.url, path), None, |_| {
+            Err(anyhow!("No video decode queue"))
+        })
+    };
+    assert!(open("/missing.m3u8").is_err());
+    assert!(open("/loop.m3u8").is_err());
+    assert!(open("/empty.m3u8").is_err());
+    // The reason the decoder supports no variant is kept
+    let err = open("/master.m3u8").err().unwrap();
+    assert!(format!("{:#}", err).contains("No video decode queue"));
+
+    assert!(http::get("https://example.com/index.m3u8", None)
+        .unwrap_err()
+        .to_string()
+        .contains("HTTPS"));
+}
+
+/// Answers a single request on a new port with `response`, returns the URL to request.
+fn respond_once(response: &'static [u8]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/segment.ts", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 && line.trim_end() != "" {
+            line.clear();
+        }
+        // The client may give up before the whole response is written
+        let _ = (&stream).write_all(response);
+    });
+    url
+}
+
+#[test]
+fn untrusted_sizes() {
+    // Lengths far beyond the data that follows are read up to the end of the connection
+    // rather than allocated up front
+    let url = respond_once(b"HTTP/1.1 200 OK\r\nContent-Length: 1000000000000000\r\n\r\nshort");
+    let err = http::get(&url, None).unwrap_err();
+    assert!(err.to_string().contains("Connection closed after 5 of"));
+    let url = respond_once(
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffff\r\nshort",
+    );
+    assert!(http::get(&url, None).is_err());
+
+    let url = respond_once(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nshort");
+    assert_eq!(http::get(&url, None).unwrap(), b"short");
+    assert!(http::get(&url, Some(5..5)).is_err());
+
+    // Waiting half the target duration for a live playlist to change
+    let playlist =
+        match Playlist::parse(&format!("#EXTM3U\n#EXT-X-TARGETDURATION:{}\n", u64::MAX)).unwrap() {
+            Playlist::Media(playlist) => playlist,
+            playlist => panic!("expected a media playlist, got {:?}", playlist),
+        };
+    let mut fetcher = hls::SegmentFetcher::new("http://127.0.0.1:1/live.m3u8", playlist);
+    assert!(fetcher.next_segment().is_err());
+}
+
+#[test]
+fn parse_playlists() {
+    let variants = match Playlist::parse(MASTER_PLAYLIST).unwrap() {
+        Playlist::Master(variants) => variants,
+        playlist => panic!("expected a master playlist, got {:?}", playlist),
+    };
+    assert_eq!(variants.len(), 5);
+    assert_eq!(
+        variants[1],
+        Variant {
+            uri: "fmp4/index.m3u8".to_string(),
+            bandwidth: 1_000_000,
+            resolution: Some((640, 360)),
+            codecs: vec!["avc1.64001e".to_string(), "mp4a.40.2".to_string()],
+        }
+    );
+    let codecs: Vec<_> = variants.iter().map(Variant::codec).collect();
+    assert_eq!(
+        codecs,
+        [
+            Some(codec::Codec::H264),
+            Some(codec::Codec::H264),
+            Some(codec::Codec::H264),
+            None,
+            Some(codec::Codec::H265),
+        ]
+    );
+
+    let media = "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-MEDIA-SEQUENCE:41
+#EXT-X-KEY:METHOD=NONE
+#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"800@0\"
+#EXTINF:5.5,first
+#EXT-X-BYTERANGE:1000@800
+all.mp4
+#EXTINF:6,
+#EXT-X-BYTERANGE:500
+all.mp4
+
+#EXT-X-DISCONTINUITY
+#EXTINF:4.25,
+other.mp4
+";
+    let playlist = match Playlist::parse(media).unwrap() {
+        Playlist::Media(playlist) => playlist,
+        playlist => panic!("expected a media playlist, got {:?}", playlist),
+    };
+    assert_eq!(playlist.target_duration, 6);
+    assert_eq!(playlist.media_sequence, 41);
+    assert!(!playlist.end_list);
+    let segments: Vec<_> = playlist
+        .segments
+        .iter()
+        .map(|segment| {
+            (
+                segment.uri.as_str(),
+                segment.duration,
+                segment.byte_range.clone(),
+                segment.discontinuity,
+            )
+        })
+        .collect();
+    assert_eq!(
+        segments,
+        [
+            ("all.mp4", 5.5, Some(800..1800), false),
+            ("all.mp4", 6.0, Some(1800..2300), false),
+            ("other.mp4", 4.25, None, true),
+        ]
+    );
+    assert!(playlist.segments.iter().all(|segment| segment.map
+        == Some(MediaInitialization {
+            uri: "init.mp4".to_string(),
+            byte_range: Some(0..800),
+        })));
+
+    assert!(Playlist::parse("#EXT-X-TARGETDURATION:6\n").is_err());
+    assert!(Playlist::parse("#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\n").is_err());
+    assert!(Playlist::parse("#EXTM3U\n#EXT-X-TARGETDURATION:6\nsegment.ts\n").is_err());
+    assert!(Playlist::parse("#EXTM3U\n#EXT-X-BYTERANGE:100\n#EXTINF:1,\na.ts\n").is_err());
+    // Byte ranges that end past u64::MAX
+    assert!(Playlist::parse(&format!(
+        "#EXTM3U\n#EXT-X-BYTERANGE:2@{}\n#EXTINF:1,\na.ts\n",
+        u64::MAX - 1
+    ))
+    .is_err());
+    assert!(Playlist::parse(&format!(
+        "#EXTM3U\n#EXT-X-BYTERANGE:{}@0\n#EXTINF:1,\na.ts\n#EXT-X-BYTERANGE:1\n#EXTINF:1,\na.ts\n",
+        u64::MAX
+    ))
+    .is_err());
+}
+
+#[test]
+fn select_variants() {
+    let variant =
+        |uri: &str, bandwidth: u64, resolution: Option<(u32, u32)>, codecs: &str| Variant {
+            uri: uri.to_string(),
+            bandwidth,
+            resolution,
+            codecs: codecs
+                .split(',')
+                .filter(|c| !c.is_empty())
+                .map(Into::into)
+                .collect(),
+        };
+    let variants = [
+        variant("1080", 5_000_000, Some((1920, 1080)), "avc1.640028"),
+        variant("720", 3_000_000, Some((1280, 720)), "avc1.64001f"),
+        variant("720-low", 2_000_000, Some((1280, 720)), "avc1.64001f"),
+        variant("unknown", 1_000_000, None, ""),
+        variant("av1", 4_000_000, Some((1920, 1080)), "av01.0.08M.08"),
+    ];
+    let select = |max_bandwidth, max_width, max_height| {
+        let mut queries = Vec::new();
+        let selected = hls::select_variant(&variants, max_bandwidth, |codec| {
+            queries.push(codec);
+            match codec {
+                codec::Codec::H264 => Ok((max_width, max_height)),
+                _ => Err(anyhow!("Unsupported codec")),
+            }
+        })
+        .map(|variant| variant.uri.clone());
+        // Once per codec
+        assert_eq!(queries, [codec::Codec::H264, codec::Codec::Av1]);
+        selected.unwrap()
+    };
+
+    assert_eq!(select(None, 4096, 4096), "1080");
+    // The largest picture first, then the highest bandwidth
+    assert_eq!(select(None, 1280, 720), "720");
+    assert_eq!(select(Some(2_500_000), 4096, 4096), "720-low");
+    // Below every variant, the lowest bandwidth
+    assert_eq!(select(Some(100), 4096, 4096), "unknown");
+    assert_eq!(select(Some(100), 640, 480), "unknown");
+
+    assert!(hls::select_variant(&variants[..3], None, |_| Ok((640, 480))).is_err());
+    assert!(hls::select_variant(&[], None, |_| Ok((4096, 4096))).is_err());
+}
+
+#[test]
+fn resolve_urls() {
+    let base = "http://example.com:8080/live/index.m3u8?token=1";
+    assert_eq!(
+        http::resolve(base, "seg1.ts"),
+        "http://example.com:8080/live/seg1.ts"
+    );
+    assert_eq!(
+        http::resolve(base, "/seg1.ts"),
+        "http://example.com:8080/seg1.ts"
+    );
+    assert_eq!(
+        http::resolve(base, "//cdn.example.com/seg1.ts"),
+        "http://cdn.example.com/seg1.ts"
+    );
+    assert_eq!(
+        http::resolve(base, "http://other.example.com/a.ts"),
+        "http://other.example.com/a.ts"
+    );
+    assert_eq!(
+        http::resolve("http://example.com", "index.m3u8"),
+        "http://example.com/index.m3u8"
+    );
+}
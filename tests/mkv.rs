@@ -0,0 +1,525 @@
+use ash_video::demux::{self, ContainerFormat};
+use ash_video::h264::avcc::AvcDecoderConfiguration;
+use ash_video::mkv::{id, CuePoint, Frames, Matroska, MatroskaReader};
+use ash_video::{mp4, stream, Timestamp};
+
+mod common;
+use common::MP4_STREAM;
+
+/// An element with an 8 byte size, which keeps the cluster positions easy to compute.
+fn element(element_id: u32, payload: &[u8]) -> Vec<u8> {
+    let id_bytes = element_id.to_be_bytes();
+    let leading = element_id.leading_zeros() as usize / 8;
+    let mut data = id_bytes[leading..].to_vec();
+    data.push(0x01);
+    data.extend_from_slice(&(payload.len() as u64).to_be_bytes()[1..]);
+    data.extend_from_slice(payload);
+    data
+}
+
+fn uint(element_id: u32, value: u64) -> Vec<u8> {
+    element(element_id, &value.to_be_bytes())
+}
+
+fn concat(elements: &[Vec<u8>]) -> Vec<u8> {
+    elements.concat()
+}
+
+fn ebml_header(doc_type: &str) -> Vec<u8> {
+    element(id::EBML, &element(id::DOC_TYPE, doc_type.as_bytes()))
+}
+
+fn video_track(number: u64, codec_id: &str, codec_private: &[u8]) -> Vec<u8> {
+    element(
+        id::TRACK_ENTRY,
+        &concat(&[
+            uint(id::TRACK_NUMBER, number),
+            uint(id::TRACK_TYPE, 1),
+            element(id::CODEC_ID, codec_id.as_bytes()),
+            element(id::CODEC_PRIVATE, codec_private),
+            uint(id::DEFAULT_DURATION, 40_000_000),
+            element(
+                id::VIDEO,
+                &concat(&[uint(id::PIXEL_WIDTH, 640), uint(id::PIXEL_HEIGHT, 360)]),
+            ),
+        ]),
+    )
+}
+
+fn block(track: u64, relative: i16, flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut block = vec![0x80 | track as u8];
+    block.extend_from_slice(&relative.to_be_bytes());
+    block.push(flags);
+    block.extend_from_slice(data);
+    block
+}
+
+fn simple_block(track: u64, relative: i16, keyframe: bool, data: &[u8]) -> Vec<u8> {
+    let flags = if keyframe { 0x80 } else { 0 };
+    element(id::SIMPLE_BLOCK, &block(track, relative, flags, data))
+}
+
+fn cluster(timestamp: u64, blocks: &[Vec<u8>]) -> Vec<u8> {
+    let mut payload = uint(id::TIMESTAMP, timestamp);
+    payload.extend(blocks.concat());
+    element(id::CLUSTER, &payload)
+}
+
+/// A file with the given Segment children.
+fn matroska(segment: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = ebml_header("matroska");
+    data.extend(element(id::SEGMENT, &segment.concat()));
+    data
+}
+
+/// Segment children with the clusters last and Cues pointing at every cluster, the cues
+/// placed before the clusters as muxers do after a second pass.
+fn segment_with_cues(track: u64, head: &[Vec<u8>], clusters: &[(u64, Vec<u8>)]) -> Vec<Vec<u8>> {
+    let cues = |positions: &[u64]| {
+        let points: Vec<Vec<u8>> = clusters
+            .iter()
+            .zip(positions)
+            .map(|((timestamp, _), &position)| {
+                element(
+                    id::CUE_POINT,
+                    &concat(&[
+                        uint(id::CUE_TIME, *timestamp),
+                        element(
+                            id::CUE_TRACK_POSITIONS,
+                            &concat(&[
+                                uint(id::CUE_TRACK, track),
+                                uint(id::CUE_CLUSTER_POSITION, position),
+                            ]),
+                        ),
+                    ]),
+                )
+            })
+            .collect();
+        element(id::CUES, &points.concat())
+    };
+
+    // The cues have the same size whatever the positions are
+    let head_size: usize = head.iter().map(Vec::len).sum();
+    let mut position = (head_size + cues(&vec![0; clusters.len()]).len()) as u64;
+    let mut positions = Vec::new();
+    for (_, cluster) in clusters {
+        positions.push(position);
+        position += cluster.len() as u64;
+    }
+
+    let mut segment = head.to_vec();
+    segment.push(cues(&positions));
+    segment.extend(clusters.iter().map(|(_, cluster)| cluster.clone()));
+    segment
+}
+
+fn info(timestamp_scale: u64) -> Vec<u8> {
+    element(
+        id::INFO,
+        &concat(&[
+            uint(id::TIMESTAMP_SCALE, timestamp_scale),
+            element(id::DURATION, &2000f64.to_be_bytes()),
+        ]),
+    )
+}
+
+fn frames(matroska: &Matroska, track: u64) -> Vec<(i64, bool, Vec<u8>)> {
+    matroska
+        .frames(track)
+        .map(|frame| {
+            let frame = frame.unwrap();
+            (
+                frame.pts.as_nanos(),
+                frame.is_keyframe,
+                frame.bytes.to_vec(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn probe() {
+    let data = matroska(&[]);
+    assert_eq!(demux::probe(&data), Some(ContainerFormat::Matroska));
+}
+
+#[test]
+fn tracks_and_info() {
+    let data = matroska(&[
+        info(1_000_000),
+        element(
+            id::TRACKS,
+            &concat(&[video_track(1, "V_AV1", &[0x81, 0x00, 0x0c, 0x00])]),
+        ),
+    ]);
+    let matroska = Matroska::parse(&data).unwrap();
+    assert_eq!(matroska.doc_type, "matroska");
+    assert_eq!(matroska.timestamp_scale, 1_000_000);
+    assert_eq!(matroska.duration, Some(Timestamp::new(2, 1)));
+
+    let track = matroska.video_track().unwrap();
+    assert_eq!(track.number, 1);
+    assert_eq!(track.codec_id, "V_AV1");
+    assert_eq!(track.codec_private, &[0x81, 0x00, 0x0c, 0x00]);
+    assert_eq!(track.default_duration, Some(40_000_000));
+    assert_eq!((track.width, track.height), (640, 360));
+
+    let mut webm = ebml_header("webm");
+    webm.extend(element(id::SEGMENT, &[]));
+    assert_eq!(Matroska::parse(&webm).unwrap().doc_type, "webm");
+    let mut other = ebml_header("mka");
+    other.extend(element(id::SEGMENT, &[]));
+    assert!(Matroska::parse(&other).is_err());
+}
+
+#[test]
+fn simple_blocks() {
+    let data = matroska(&[
+        info(1_000_000),
+        element(id::TRACKS, &video_track(1, "V_AV1", &[])),
+        cluster(
+            0,
+            &[
+                simple_block(1, 0, true, b"key"),
+                simple_block(2, 0, true, b"audio"),
+                simple_block(1, 40, false, b"inter"),
+            ],
+        ),
+        cluster(1000, &[simple_block(1, -40, false, b"early")]),
+    ]);
+    let matroska = Matroska::parse(&data).unwrap();
+    assert_eq!(
+        frames(&matroska, 1),
+        [
+            (0, true, b"key".to_vec()),
+            (40_000_000, false, b"inter".to_vec()),
+            (960_000_000, false, b"early".to_vec()),
+        ]
+    );
+    assert_eq!(frames(&matroska, 2), [(0, true, b"audio".to_vec())]);
+}
+
+#[test]
+fn block_groups() {
+    let group = |relative: i16, data: &[u8], reference: bool| {
+        let mut children = element(id::BLOCK, &block(1, relative, 0, data));
+        if reference {
+            children.extend(element(id::REFERENCE_BLOCK, &(-40i8).to_be_bytes()));
+        }
+        element(id::BLOCK_GROUP, &children)
+    };
+    let data = matroska(&[cluster(0, &[group(0, b"I", false), group(40, b"P", true)])]);
+    let matroska = Matroska::parse(&data).unwrap();
+    assert_eq!(
+        frames(&matroska, 1),
+        [(0, true, b"I".to_vec()), (40_000_000, false, b"P".to_vec())]
+    );
+}
+
+#[test]
+fn lacing() {
+    let track = element(id::TRACKS, &video_track(1, "V_AV1", &[]));
+    let expected = [
+        (0, true, b"abc".to_vec()),
+        (40_000_000, false, b"de".to_vec()),
+        (80_000_000, false, b"f".to_vec()),
+    ];
+
+    // Xiph
+    let laced = block(
+        1,
+        0,
+        0x80 | 0b010,
+        &[2, 3, 2, b'a', b'b', b'c', b'd', b'e', b'f'],
+    );
+    let data = matroska(&[
+        track.clone(),
+        cluster(0, &[element(id::SIMPLE_BLOCK, &laced)]),
+    ]);
+    assert_eq!(frames(&Matroska::parse(&data).unwrap(), 1), expected);
+
+    // EBML, 3 followed by a difference of -1, 0xbe being 62 - 63
+    let laced = block(
+        1,
+        0,
+        0x80 | 0b110,
+        &[2, 0x83, 0xbe, b'a', b'b', b'c', b'd', b'e', b'f'],
+    );
+    let data = matroska(&[
+        track.clone(),
+        cluster(0, &[element(id::SIMPLE_BLOCK, &laced)]),
+    ]);
+    assert_eq!(frames(&Matroska::parse(&data).unwrap(), 1), expected);
+
+    // Fixed size
+    let laced = block(1, 0, 0x80 | 0b100, &[1, b'a', b'b', b'c', b'd']);
+    let data = matroska(&[
+        track.clone(),
+        cluster(0, &[element(id::SIMPLE_BLOCK, &laced)]),
+    ]);
+    assert_eq!(
+        frames(&Matroska::parse(&data).unwrap(), 1),
+        [
+            (0, true, b"ab".to_vec()),
+            (40_000_000, false, b"cd".to_vec())
+        ]
+    );
+
+    let laced = block(1, 0, 0x80 | 0b100, &[1, b'a', b'b', b'c']);
+    let data = matroska(&[track, cluster(0, &[element(id::SIMPLE_BLOCK, &laced)])]);
+    let matroska = Matroska::parse(&data).unwrap();
+    assert!(matroska.frames(1).next().unwrap().is_err());
+}
+
+#[test]
+fn unknown_size_clusters() {
+    let unknown_size_cluster = |timestamp: u64, blocks: &[Vec<u8>]| {
+        let mut data = vec![
+            0x1f, 0x43, 0xb6, 0x75, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ];
+        data.extend(uint(id::TIMESTAMP, timestamp));
+        data.extend(blocks.concat());
+        data
+    };
+
+    // A live capture, the Segment is of unknown size as well
+    let mut data = ebml_header("webm");
+    data.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0xff]);
+    data.extend(unknown_size_cluster(0, &[simple_block(1, 0, true, b"a")]));
+    data.extend(unknown_size_cluster(
+        100,
+        &[
+            simple_block(1, 0, false, b"b"),
+            simple_block(1, 40, false, b"c"),
+        ],
+    ));
+
+    let matroska = Matroska::parse(&data).unwrap();
+    assert_eq!(
+        frames(&matroska, 1),
+        [
+            (0, true, b"a".to_vec()),
+            (100_000_000, false, b"b".to_vec()),
+            (140_000_000, false, b"c".to_vec()),
+        ]
+    );
+}
+
+/// The frames of track 1 read one cluster at a time.
+fn streamed_frames(data: &[u8]) -> Vec<(i64, bool, Vec<u8>)> {
+    let mut reader = MatroskaReader::new(std::io::Cursor::new(data)).unwrap();
+    let matroska = Matroska::parse(reader.header()).unwrap();
+    let (timestamp_scale, track) = (matroska.timestamp_scale, &matroska.tracks[0]);
+    let (number, default_duration) = (track.number, track.default_duration);
+
+    let mut frames = Vec::new();
+    let mut cluster = Vec::new();
+    while reader.read_cluster(&mut cluster).unwrap() {
+        for frame in
+            Frames::from_cluster(&cluster, timestamp_scale, number, default_duration).unwrap()
+        {
+            let frame = frame.unwrap();
+            frames.push((
+                frame.pts.as_nanos(),
+                frame.is_keyframe,
+                frame.bytes.to_vec(),
+            ));
+        }
+    }
+    frames
+}
+
+#[test]
+fn cluster_at_a_time() {
+    let tracks = element(id::TRACKS, &video_track(1, "V_MPEG4/ISO/AVC", &[]));
+    let clusters = [
+        (0, cluster(0, &[simple_block(1, 0, true, b"a")])),
+        (
+            100,
+            cluster(
+                100,
+                &[
+                    simple_block(2, 0, true, b"x"),
+                    simple_block(1, 0, false, b"b"),
+                ],
+            ),
+        ),
+    ];
+    let mut segment = segment_with_cues(1, &[info(1_000_000), tracks.clone()], &clusters);
+    segment.push(element(id::TAGS, b""));
+    let data = matroska(&segment);
+
+    let matroska = Matroska::parse(&data).unwrap();
+    assert_eq!(streamed_frames(&data), frames(&matroska, 1));
+
+    // Only the elements Matroska::parse needs are read up front, the cues are skipped
+    let reader = MatroskaReader::new(std::io::Cursor::new(&data)).unwrap();
+    let header = Matroska::parse(reader.header()).unwrap();
+    assert_eq!(header.tracks.len(), 1);
+    assert_eq!(header.duration, matroska.duration);
+    assert!(header.cues.is_empty());
+    assert!(header.frames(1).next().is_none());
+
+    // A live capture with a Segment and clusters of unknown size
+    let mut live = ebml_header("webm");
+    live.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0xff]);
+    live.extend(tracks);
+    for (timestamp, payload) in [(0, b"a"), (40, b"b")] {
+        live.extend_from_slice(&[0x1f, 0x43, 0xb6, 0x75, 0xff]);
+        live.extend(uint(id::TIMESTAMP, timestamp));
+        live.extend(simple_block(1, 0, timestamp == 0, payload));
+    }
+    assert_eq!(
+        streamed_frames(&live),
+        [(0, true, b"a".to_vec()), (40_000_000, false, b"b".to_vec()),]
+    );
+
+    // A file cut short in its last cluster
+    let mut reader = MatroskaReader::new(std::io::Cursor::new(&data[..data.len() - 20])).unwrap();
+    let mut cluster = Vec::new();
+    assert!(reader.read_cluster(&mut cluster).unwrap());
+    assert!(reader.read_cluster(&mut cluster).is_err());
+}
+
+#[test]
+fn timestamp_scale() {
+    // 1/30000 s ticks do not divide a second in nanoseconds evenly
+    let data = matroska(&[
+        info(33_333),
+        cluster(30_000, &[simple_block(1, 3, true, b"a")]),
+    ]);
+    let matroska = Matroska::parse(&data).unwrap();
+    assert_eq!(frames(&matroska, 1)[0].0, 30_003 * 33_333);
+
+    assert!(Matroska::parse(&self::matroska(&[info(0)])).is_err());
+}
+
+#[test]
+fn seek() {
+    let clusters: Vec<(u64, Vec<u8>)> = (0..4)
+        .map(|index| {
+            let timestamp = index * 1000;
+            let blocks = [
+                simple_block(1, 0, true, &[index as u8, 0]),
+                simple_block(1, 500, false, &[index as u8, 1]),
+            ];
+            (timestamp, cluster(timestamp, &blocks))
+        })
+        .collect();
+    let head = [
+        info(1_000_000),
+        element(id::TRACKS, &video_track(1, "V_AV1", &[])),
+    ];
+    let data = matroska(&segment_with_cues(1, &head, &clusters));
+    let matroska = Matroska::parse(&data).unwrap();
+    assert_eq!(matroska.cues.len(), 4);
+    assert_eq!(
+        matroska.cues[1],
+        CuePoint {
+            time: 1000,
+            track: 1,
+            cluster_position: matroska.cues[0].cluster_position + clusters[0].1.len() as u64,
+        }
+    );
+
+    let first_frame = |time: Timestamp| {
+        let frame = matroska.seek(1, time).unwrap().next().unwrap().unwrap();
+        (
+            frame.pts.as_nanos(),
+            frame.is_keyframe,
+            frame.bytes.to_vec(),
+        )
+    };
+    assert_eq!(first_frame(Timestamp::ZERO), (0, true, vec![0, 0]));
+    assert_eq!(
+        first_frame(Timestamp::new(2, 1)),
+        (2_000_000_000, true, vec![2, 0])
+    );
+    assert_eq!(
+        first_frame(Timestamp::new(3700, 1000)),
+        (3_000_000_000, true, vec![3, 0])
+    );
+    // Past the last cue the last cluster is still decoded from its keyframe
+    assert_eq!(
+        first_frame(Timestamp::new(20, 1)),
+        (3_000_000_000, true, vec![3, 0])
+    );
+    assert!(matroska.seek(2, Timestamp::ZERO).is_err());
+
+    // Without cues the cluster timestamps are searched instead
+    let segment: Vec<Vec<u8>> = head
+        .iter()
+        .cloned()
+        .chain(clusters.iter().map(|(_, cluster)| cluster.clone()))
+        .collect();
+    let data = self::matroska(&segment);
+    let matroska = Matroska::parse(&data).unwrap();
+    assert!(matroska.cues.is_empty());
+    let frames: Vec<_> = matroska
+        .seek(1, Timestamp::new(2500, 1000))
+        .unwrap()
+        .map(|frame| frame.unwrap().bytes.to_vec())
+        .collect();
+    assert_eq!(frames, [vec![2, 0], vec![2, 1], vec![3, 0], vec![3, 1]]);
+}
+
+/// The samples of the MP4 sample stream in Matroska, one cluster per frame with a
+/// nanosecond TimestampScale so that the timestamps survive exactly, and an avcC
+/// CodecPrivate.
+#[test]
+fn same_access_units_as_mp4() {
+    let (_, mp4_access_units) = common::read_mp4(MP4_STREAM);
+
+    let data = std::fs::read(MP4_STREAM).unwrap();
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(&data)).unwrap();
+    let track = context
+        .tracks
+        .iter()
+        .find(|track| track.track_type == mp4parse::TrackType::Video)
+        .unwrap();
+    let avcc = match &track.stsd.as_ref().unwrap().descriptions[0] {
+        mp4parse::SampleEntry::Video(entry) => match &entry.codec_specific {
+            mp4parse::VideoCodecSpecific::AVCConfig(avcc) => avcc.to_vec(),
+            _ => panic!("expected avcC"),
+        },
+        _ => panic!("expected a video sample entry"),
+    };
+    let sample_table = mp4::SampleTable::new(track, context.timescale).unwrap();
+
+    let mut segment = vec![
+        info(1),
+        element(id::TRACKS, &video_track(1, "V_MPEG4/ISO/AVC", &avcc)),
+    ];
+    for sample in sample_table.iter(&data) {
+        let sample = sample.unwrap();
+        segment.push(cluster(
+            sample.pts.as_nanos() as u64,
+            &[simple_block(1, 0, sample.is_keyframe, sample.bytes)],
+        ));
+    }
+    let mkv = matroska(&segment);
+    assert_eq!(demux::probe(&mkv), Some(ContainerFormat::Matroska));
+
+    let matroska = Matroska::parse(&mkv).unwrap();
+    let track = matroska.video_track().unwrap();
+    let config = AvcDecoderConfiguration::parse(track.codec_private).unwrap();
+    let access_units: Vec<(Vec<u8>, Timestamp)> = matroska
+        .frames(track.number)
+        .map(|frame| {
+            let frame = frame.unwrap();
+            (frame.to_annexb(config.length_size).unwrap(), frame.pts)
+        })
+        .collect();
+
+    // Read from a stream, one cluster at a time
+    let stream = stream::open(std::io::Cursor::new(mkv)).unwrap();
+    assert_eq!(stream.format, ContainerFormat::Matroska);
+    let streamed: Vec<(Vec<u8>, Timestamp)> = stream.access_units.map(Result::unwrap).collect();
+    assert!(streamed == access_units);
+
+    assert_eq!(access_units.len(), mp4_access_units.len());
+    for ((mkv_au, mkv_pts), (mp4_au, mp4_pts)) in access_units.iter().zip(&mp4_access_units) {
+        assert_eq!(mkv_au, mp4_au);
+        assert_eq!(mkv_pts.as_nanos(), mp4_pts.as_nanos());
+    }
+}
@@ -0,0 +1,235 @@
+use ash_video::mp4::{
+    SampleInfo, SampleTable, TrackExtends, TrackFragmentHeader, TrackRun, TrackRunSample,
+    TrackTiming,
+};
+use ash_video::{stream, Timestamp};
+
+mod common;
+use common::fmp4::{self, full_box, init_segment, mp4_box, trex, words, NON_SYNC};
+use common::MP4_STREAM;
+
+const SAMPLES_PER_FRAGMENT: usize = 48;
+
+/// The video track of `data` read like `main` does, fragments included.
+fn read_samples(data: &[u8]) -> (SampleTable, Vec<(Vec<u8>, Timestamp, bool)>) {
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(data)).unwrap();
+    let track = context
+        .tracks
+        .iter()
+        .find(|track| track.track_type == mp4parse::TrackType::Video)
+        .unwrap();
+    let mut sample_table = SampleTable::new(track, context.timescale).unwrap();
+    sample_table
+        .add_fragments(data, track.track_id.unwrap())
+        .unwrap();
+    let samples = sample_table
+        .iter(data)
+        .map(|sample| {
+            let sample = sample.unwrap();
+            (sample.bytes.to_vec(), sample.pts, sample.is_keyframe)
+        })
+        .collect();
+    (sample_table, samples)
+}
+
+#[test]
+fn same_samples_as_progressive() {
+    let data = std::fs::read(MP4_STREAM).unwrap();
+    let (progressive_table, progressive) = read_samples(&data);
+    let (init, segments) = fmp4::fragment(&data, SAMPLES_PER_FRAGMENT);
+    // One init segment followed by the media segments, all in one file
+    let fragmented = [init, segments.concat()].concat();
+
+    let (fragmented_table, fragmented_samples) = read_samples(&fragmented);
+    assert_eq!(fragmented_samples.len(), progressive.len());
+    assert!(fragmented_samples == progressive);
+    assert_eq!(fragmented_table.end_dts, progressive_table.end_dts);
+    assert_eq!(fragmented_table.duration(), progressive_table.duration());
+
+    // Read from a stream, one moof at a time
+    let (_, access_units) = common::read_mp4(MP4_STREAM);
+    let stream = stream::open(std::io::Cursor::new(fragmented)).unwrap();
+    let streamed: Vec<(Vec<u8>, Timestamp)> = stream.access_units.map(Result::unwrap).collect();
+    assert!(streamed == access_units);
+
+    // The sample table of the init segment is empty
+    let (_, init_samples) = read_samples(&init_segment(&data, &[]));
+    assert!(init_samples.is_empty());
+}
+
+#[test]
+fn fragment_boxes() {
+    let trex = trex(1, 512, 100, NON_SYNC);
+    let mvex = mp4_box(b"mvex", &trex);
+    let moov = mp4_box(b"moov", &mvex);
+    assert_eq!(
+        TrackExtends::find(&moov, 1).unwrap(),
+        TrackExtends {
+            default_sample_description_index: 1,
+            default_sample_duration: 512,
+            default_sample_size: 100,
+            default_sample_flags: NON_SYNC,
+        }
+    );
+    assert_eq!(
+        TrackExtends::find(&moov, 2).unwrap(),
+        TrackExtends::default()
+    );
+
+    // base-data-offset and default-sample-size present
+    let tfhd = [
+        &words(&[0x11, 7])[..],
+        &4096u64.to_be_bytes(),
+        &words(&[64]),
+    ]
+    .concat();
+    assert_eq!(
+        TrackFragmentHeader::parse(&tfhd).unwrap(),
+        TrackFragmentHeader {
+            track_id: 7,
+            base_data_offset: Some(4096),
+            sample_description_index: None,
+            default_sample_duration: None,
+            default_sample_size: Some(64),
+            default_sample_flags: None,
+            default_base_is_moof: false,
+        }
+    );
+    assert!(TrackFragmentHeader::parse(&tfhd[..12]).is_err());
+
+    // Version 1 with a negative composition offset and first-sample-flags
+    let trun = [
+        &words(&[0x0100_0a05, 2, (-8i32) as u32, 0])[..],
+        &words(&[10, (-1024i32) as u32]),
+        &words(&[20, 512]),
+    ]
+    .concat();
+    assert_eq!(
+        TrackRun::parse(&trun).unwrap(),
+        TrackRun {
+            data_offset: Some(-8),
+            first_sample_flags: Some(0),
+            samples: vec![
+                TrackRunSample {
+                    duration: None,
+                    size: Some(10),
+                    flags: None,
+                    composition_offset: -1024,
+                },
+                TrackRunSample {
+                    duration: None,
+                    size: Some(20),
+                    flags: None,
+                    composition_offset: 512,
+                },
+            ],
+        }
+    );
+    assert!(TrackRun::parse(&trun[..trun.len() - 1]).is_err());
+    // A sample_count the box cannot hold
+    assert!(TrackRun::parse(&words(&[0x200, u32::MAX])).is_err());
+    assert!(TrackRun::parse(&words(&[0, u32::MAX])).is_err());
+}
+
+/// Two track fragments in one moof, the other track first, with the data offsets and
+/// decode times left to the defaults.
+#[test]
+fn fragment_defaults() {
+    let mvex = mp4_box(
+        b"mvex",
+        &[trex(1, 1000, 2, NON_SYNC), trex(2, 0, 3, 0)].concat(),
+    );
+    let moov = mp4_box(b"moov", &mvex);
+
+    let trafs = |sample_data_start: u32| {
+        // Track 2, three samples of three bytes at the first data_offset
+        let other = mp4_box(
+            b"traf",
+            &[
+                full_box(b"tfhd", 0, 0, &words(&[2])),
+                full_box(b"trun", 0, 0x01, &words(&[3, sample_data_start])),
+            ]
+            .concat(),
+        );
+        // Track 1 follows the data of track 2, in two runs and with the first sample a
+        // sync sample
+        let track = mp4_box(
+            b"traf",
+            &[
+                full_box(b"tfhd", 0, 0, &words(&[1])),
+                full_box(b"trun", 0, 0x04, &words(&[2, 0])),
+                full_box(b"trun", 0, 0x200, &words(&[1, 4])),
+            ]
+            .concat(),
+        );
+        [other, track].concat()
+    };
+    let moof_size = mp4_box(b"moof", &trafs(0)).len() as u32;
+    let moof = mp4_box(b"moof", &trafs(moof_size + 8));
+    let mdat = mp4_box(b"mdat", b"xxxyyyzzzaabbcccc");
+
+    // A second fragment without tfdt continues the decode times
+    let second = mp4_box(
+        b"moof",
+        &mp4_box(
+            b"traf",
+            &[
+                full_box(b"tfhd", 0, 0x02_0000 | 0x20, &words(&[1, 0])),
+                full_box(b"trun", 0, 0x01, &words(&[1, 0])),
+            ]
+            .concat(),
+        ),
+    );
+
+    let mdat_data = (moov.len() + moof.len() + 8) as u64;
+    let second_offset = mdat_data + mdat.len() as u64 - 8;
+    let data = [moov, moof, mdat, second].concat();
+    let mut sample_table = SampleTable {
+        samples: Vec::new(),
+        timing: TrackTiming {
+            timescale: 1000,
+            media_time: 0,
+            empty_duration: Timestamp::ZERO,
+            duration: None,
+        },
+        end_dts: 0,
+    };
+    sample_table.add_fragments(&data, 1).unwrap();
+
+    assert_eq!(
+        sample_table.samples,
+        [
+            SampleInfo {
+                offset: mdat_data + 9,
+                size: 2,
+                dts: 0,
+                pts: 0,
+                is_keyframe: true,
+            },
+            SampleInfo {
+                offset: mdat_data + 11,
+                size: 2,
+                dts: 1000,
+                pts: 1000,
+                is_keyframe: false,
+            },
+            SampleInfo {
+                offset: mdat_data + 13,
+                size: 4,
+                dts: 2000,
+                pts: 2000,
+                is_keyframe: false,
+            },
+            // default-base-is-moof with a zero data_offset and sync sample defaults
+            SampleInfo {
+                offset: second_offset,
+                size: 2,
+                dts: 3000,
+                pts: 3000,
+                is_keyframe: true,
+            },
+        ]
+    );
+    assert_eq!(sample_table.end_dts, 4000);
+    assert_eq!(sample_table.duration(), Timestamp::new(4, 1));
+}
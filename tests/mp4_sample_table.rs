@@ -0,0 +1,296 @@
+use ash_video::mp4::{SampleInfo, SampleTable};
+use ash_video::Timestamp;
+
+mod common;
+use common::fmp4::{full_box, rewrite_boxes, words};
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/a.mp4");
+
+/// The sample table of the first video track.
+fn sample_table(data: &[u8]) -> anyhow::Result<SampleTable> {
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(data)).unwrap();
+    let track = context
+        .tracks
+        .iter()
+        .find(|track| track.track_type == mp4parse::TrackType::Video)
+        .unwrap();
+    SampleTable::new(track, context.timescale)
+}
+
+/// Sample table boxes, `(count, value)` runs where the box has them.
+struct Tables<'a> {
+    stts: &'a [(u32, u32)],
+    ctts: Option<&'a [(u32, i32)]>,
+    /// `(first_chunk, samples_per_chunk)`
+    stsc: &'a [(u32, u32)],
+    /// `(sample_size, entry_sizes)`
+    stsz: (u32, &'a [u32]),
+    stco: &'a [u64],
+    stss: Option<&'a [u32]>,
+}
+
+impl Tables<'_> {
+    fn boxes(&self, box_type: &[u8; 4]) -> Option<Vec<u8>> {
+        let runs = |runs: &[(u32, u32)]| {
+            let mut payload = words(&[runs.len() as u32]);
+            for &(count, value) in runs {
+                payload.extend(words(&[count, value]));
+            }
+            payload
+        };
+        Some(match box_type {
+            b"stts" => {
+                let mut boxes = full_box(b"stts", 0, 0, &runs(self.stts));
+                if let Some(ctts) = self.ctts {
+                    let ctts: Vec<_> = ctts
+                        .iter()
+                        .map(|&(count, offset)| (count, offset as u32))
+                        .collect();
+                    boxes.extend(full_box(b"ctts", 1, 0, &runs(&ctts)));
+                }
+                boxes
+            }
+            b"stsc" => {
+                let mut payload = words(&[self.stsc.len() as u32]);
+                for &(first_chunk, samples_per_chunk) in self.stsc {
+                    payload.extend(words(&[first_chunk, samples_per_chunk, 1]));
+                }
+                full_box(b"stsc", 0, 0, &payload)
+            }
+            b"stsz" => {
+                let (sample_size, sizes) = self.stsz;
+                let count = if sample_size == 0 {
+                    sizes.len() as u32
+                } else {
+                    self.stts.iter().map(|&(count, _)| count).sum()
+                };
+                let payload = [words(&[sample_size, count]), words(sizes)].concat();
+                full_box(b"stsz", 0, 0, &payload)
+            }
+            b"stco" if self.stco.iter().all(|&offset| offset <= u32::MAX as u64) => {
+                let offsets: Vec<_> = self.stco.iter().map(|&offset| offset as u32).collect();
+                let payload = [words(&[offsets.len() as u32]), words(&offsets)].concat();
+                full_box(b"stco", 0, 0, &payload)
+            }
+            b"stco" => {
+                let mut payload = words(&[self.stco.len() as u32]);
+                for offset in self.stco {
+                    payload.extend(offset.to_be_bytes());
+                }
+                full_box(b"co64", 0, 0, &payload)
+            }
+            b"stss" => match self.stss {
+                Some(stss) => {
+                    let payload = [words(&[stss.len() as u32]), words(stss)].concat();
+                    full_box(b"stss", 0, 0, &payload)
+                }
+                None => Vec::new(),
+            },
+            b"ctts" => Vec::new(),
+            _ => return None,
+        })
+    }
+
+    /// samples/a.mp4 with its sample tables replaced.
+    fn file(&self) -> Vec<u8> {
+        let data = std::fs::read(SAMPLE).unwrap();
+        rewrite_boxes(&data, &mut |box_type, _| self.boxes(box_type))
+    }
+}
+
+/// Ten samples of 100 to 190 bytes in six chunks holding 2, 2, 3, 1, 1 and 1 samples, the
+/// first four decoded 100 units apart and the others 200. Samples 1 and 8 are sync samples.
+const TABLES: Tables = Tables {
+    stts: &[(4, 100), (6, 200)],
+    ctts: Some(&[(1, 200), (2, -100), (7, 0)]),
+    stsc: &[(1, 2), (3, 3), (4, 1)],
+    stsz: (0, &[100, 110, 120, 130, 140, 150, 160, 170, 180, 190]),
+    stco: &[1000, 2000, 3000, 4000, 5000, 6000],
+    stss: Some(&[1, 8]),
+};
+
+#[test]
+fn sample_file() {
+    let data = std::fs::read(SAMPLE).unwrap();
+    let sample_table = sample_table(&data).unwrap();
+
+    // One chunk at 48 holding all 30 samples, 512 units apart at 15360 Hz, no ctts
+    assert_eq!(sample_table.len(), 30);
+    assert_eq!(
+        sample_table.samples[..3],
+        [
+            SampleInfo {
+                offset: 48,
+                size: 624,
+                dts: 0,
+                pts: 0,
+                is_keyframe: true
+            },
+            SampleInfo {
+                offset: 672,
+                size: 90,
+                dts: 512,
+                pts: 512,
+                is_keyframe: false
+            },
+            SampleInfo {
+                offset: 762,
+                size: 64,
+                dts: 1024,
+                pts: 1024,
+                is_keyframe: false
+            },
+        ]
+    );
+    for (index, pair) in sample_table.samples.windows(2).enumerate() {
+        assert_eq!(pair[1].offset, pair[0].offset + pair[0].size as u64);
+        assert_eq!(pair[1].dts, (index as u64 + 1) * 512);
+        assert_eq!(pair[1].pts, pair[1].dts as i64);
+    }
+    let keyframes: Vec<_> = (0..30)
+        .filter(|&index| sample_table.samples[index].is_keyframe)
+        .collect();
+    assert_eq!(keyframes, [0, 10, 20]);
+
+    assert_eq!(sample_table.end_dts, 15360);
+    assert_eq!(sample_table.duration(), Timestamp::new(1, 1));
+
+    let samples: Vec<_> = sample_table
+        .iter(&data)
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(samples[1].pts, Timestamp::new(1, 30));
+    assert_eq!(samples[1].bytes, &data[672..762]);
+}
+
+#[test]
+fn reordered_file() {
+    let data = std::fs::read(common::MP4_STREAM).unwrap();
+    let sample_table = sample_table(&data).unwrap();
+
+    // ctts of an IPBB.. stream, before the edit list shifts it by 1024
+    let pts: Vec<_> = sample_table.samples[..8]
+        .iter()
+        .map(|sample| sample.pts - sample.dts as i64)
+        .collect();
+    assert_eq!(pts, [1024, 3584, 1536, 0, 0, 512, 512, 4608]);
+    assert_eq!(sample_table.samples[0].offset, 3906);
+    assert_eq!(sample_table.samples[1].offset, 3906 + 75637);
+}
+
+#[test]
+fn chunks() {
+    let sample_table = sample_table(&TABLES.file()).unwrap();
+
+    let located: Vec<_> = sample_table
+        .samples
+        .iter()
+        .map(|sample| (sample.offset, sample.size))
+        .collect();
+    assert_eq!(
+        located,
+        [
+            (1000, 100),
+            (1100, 110),
+            (2000, 120),
+            (2120, 130),
+            (3000, 140),
+            (3140, 150),
+            (3290, 160),
+            (4000, 170),
+            (5000, 180),
+            (6000, 190),
+        ]
+    );
+
+    let times: Vec<_> = sample_table
+        .samples
+        .iter()
+        .map(|sample| (sample.dts, sample.pts))
+        .collect();
+    assert_eq!(
+        times,
+        [
+            (0, 200),
+            (100, 0),
+            (200, 100),
+            (300, 300),
+            (400, 400),
+            (600, 600),
+            (800, 800),
+            (1000, 1000),
+            (1200, 1200),
+            (1400, 1400),
+        ]
+    );
+    assert_eq!(sample_table.end_dts, 1600);
+
+    let keyframes: Vec<_> = (0..10)
+        .filter(|&index| sample_table.samples[index].is_keyframe)
+        .collect();
+    assert_eq!(keyframes, [0, 7]);
+}
+
+#[test]
+fn constant_size_and_co64() {
+    let tables = Tables {
+        ctts: None,
+        stsz: (50, &[]),
+        stco: &[
+            0x1_0000_0000,
+            0x1_0000_1000,
+            0x1_0000_2000,
+            0x1_0000_3000,
+            0x1_0000_4000,
+            0x1_0000_5000,
+        ],
+        stss: None,
+        ..TABLES
+    };
+    let sample_table = sample_table(&tables.file()).unwrap();
+
+    assert_eq!(sample_table.len(), 10);
+    assert!(sample_table
+        .samples
+        .iter()
+        .all(|sample| sample.size == 50 && sample.is_keyframe && sample.pts == sample.dts as i64));
+    assert_eq!(sample_table.samples[3].offset, 0x1_0000_1032);
+    assert_eq!(sample_table.samples[6].offset, 0x1_0000_2064);
+    assert_eq!(sample_table.samples[9].offset, 0x1_0000_5000);
+
+    // The samples lie beyond the end of the file
+    let data = tables.file();
+    assert!(sample_table.iter(&data).next().unwrap().is_err());
+}
+
+#[test]
+fn inconsistent_tables() {
+    // first_chunk counts from 1
+    let tables = Tables {
+        stsc: &[(0, 2)],
+        ..TABLES
+    };
+    assert!(sample_table(&tables.file()).is_err());
+
+    // The chunks hold 9 of the 10 samples
+    let tables = Tables {
+        stsc: &[(1, 2), (3, 3), (4, 1)],
+        stco: &[1000, 2000, 3000, 4000, 5000],
+        ..TABLES
+    };
+    assert!(sample_table(&tables.file()).is_err());
+
+    // stts describes 9 of the 10 samples
+    let tables = Tables {
+        stts: &[(4, 100), (5, 200)],
+        ..TABLES
+    };
+    assert!(sample_table(&tables.file()).is_err());
+
+    // The samples of the last chunk end past the largest co64 offset
+    let tables = Tables {
+        stco: &[1000, 2000, 3000, 4000, 5000, u64::MAX - 100],
+        ..TABLES
+    };
+    assert!(sample_table(&tables.file()).is_err());
+}
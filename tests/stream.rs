@@ -0,0 +1,214 @@
+use std::cell::Cell;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::rc::Rc;
+
+use ash_video::annexb::AnnexBReader;
+use ash_video::demux::ContainerFormat;
+use ash_video::ts::{self, CLOCK_RATE};
+use ash_video::{codec, stream, Codec, Timestamp};
+
+mod common;
+use common::mpegts::{pes, Muxer, VIDEO_PID};
+use common::{ANNEXB_STREAM, MP4_STREAM};
+
+/// The sample stream has an IDR picture every 10 frames
+const GOP_LENGTH: usize = 10;
+
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.set(self.read.get() + read as u64);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Panics when read past `limit`.
+struct ReadAheadLimit<R> {
+    inner: R,
+    limit: Rc<Cell<u64>>,
+}
+
+impl<R: Read + Seek> Read for ReadAheadLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let position = self.inner.stream_position()?;
+        let read = self.inner.read(buf)?;
+        let end = position + read as u64;
+        assert!(
+            end <= self.limit.get(),
+            "read up to {} with the limit at {}",
+            end,
+            self.limit.get()
+        );
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for ReadAheadLimit<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// The access units of the sample stream four times over, each with filler data so that a
+/// group of pictures takes several reads of [`stream::READ_SIZE`].
+fn padded_access_units() -> Vec<Vec<u8>> {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    let access_units: Vec<Vec<u8>> = AnnexBReader::new(&data)
+        .map(|access_unit| {
+            let mut access_unit = access_unit.unwrap().data.to_vec();
+            access_unit.extend_from_slice(&[0, 0, 0, 1, 0x0c]);
+            access_unit.extend_from_slice(&[0xff; 3000]);
+            access_unit.push(0x80);
+            access_unit
+        })
+        .collect();
+    [&access_units[..]; 4].concat()
+}
+
+/// Opens `data`, whose access units end at `ends` in it, and reads them one at a time
+/// while no more than a group of pictures past the access unit asked for may be read.
+fn read_one_gop_ahead(data: Vec<u8>, ends: &[u64]) -> Vec<(Vec<u8>, Timestamp)> {
+    let gop_size = ends
+        .windows(GOP_LENGTH + 1)
+        .map(|window| window[GOP_LENGTH] - window[0])
+        .max()
+        .unwrap();
+    assert!(gop_size > 4 * stream::READ_SIZE);
+
+    let limit = Rc::new(Cell::new(ends[0] + gop_size));
+    let stream = stream::open(ReadAheadLimit {
+        inner: Cursor::new(data),
+        limit: limit.clone(),
+    })
+    .unwrap();
+
+    let mut access_units = stream.access_units;
+    let mut read = Vec::new();
+    for end in ends {
+        limit.set(end + gop_size);
+        read.push(access_units.next().unwrap().unwrap());
+    }
+    limit.set(u64::MAX);
+    assert!(access_units.next().is_none());
+    read
+}
+
+#[test]
+fn annexb_read_ahead() {
+    let access_units = padded_access_units();
+    let ends: Vec<u64> = access_units
+        .iter()
+        .scan(0, |end, access_unit| {
+            *end += access_unit.len() as u64;
+            Some(*end)
+        })
+        .collect();
+
+    let read = read_one_gop_ahead(access_units.concat(), &ends);
+    let data: Vec<Vec<u8>> = read.into_iter().map(|(data, _)| data).collect();
+    assert!(data == access_units);
+}
+
+#[test]
+fn ts_read_ahead() {
+    let access_units = padded_access_units();
+    let mut muxer = Muxer::new();
+    muxer.write_tables(0x1b);
+    let mut ends = Vec::new();
+    for (index, access_unit) in access_units.iter().enumerate() {
+        let pts = index as i64 * 3600;
+        muxer.write(
+            VIDEO_PID,
+            &pes(Some(pts), None, access_unit),
+            index % GOP_LENGTH == 0,
+        );
+        ends.push((muxer.packets.len() * ts::PACKET_SIZE) as u64);
+    }
+
+    let read = read_one_gop_ahead(muxer.finish(), &ends);
+    let expected: Vec<(Vec<u8>, Timestamp)> = access_units
+        .into_iter()
+        .enumerate()
+        .map(|(index, access_unit)| (access_unit, Timestamp::new(index as i64 * 3600, CLOCK_RATE)))
+        .collect();
+    assert!(read == expected);
+}
+
+#[test]
+fn mp4_samples_on_demand() {
+    let (parameter_sets, mp4_access_units) = common::read_mp4(MP4_STREAM);
+    let size = std::fs::metadata(MP4_STREAM).unwrap().len();
+
+    let read = Rc::new(Cell::new(0));
+    let stream = stream::open(CountingReader {
+        inner: std::fs::File::open(MP4_STREAM).unwrap(),
+        read: read.clone(),
+    })
+    .unwrap();
+    assert_eq!(stream.format, ContainerFormat::Mp4);
+    assert_eq!(
+        stream.parameter_sets,
+        codec::ParameterSets::from(parameter_sets)
+    );
+    assert_eq!((stream.width, stream.height), (640, 360));
+
+    // Opening reads the movie header but none of the media data
+    let read_on_open = read.get();
+    assert!(
+        read_on_open < size / 4,
+        "read {} of {} bytes",
+        read_on_open,
+        size
+    );
+
+    let mut access_units = stream.access_units;
+    let first = access_units.next().unwrap().unwrap();
+    assert_eq!(first, mp4_access_units[0]);
+    let read_after_first = read.get();
+
+    let mut rest: Vec<(Vec<u8>, Timestamp)> = access_units.map(Result::unwrap).collect();
+    rest.insert(0, first);
+    assert!(rest == mp4_access_units);
+    assert!(read.get() > read_after_first);
+    // Every sample is read once
+    assert!(read.get() <= read_on_open + size);
+}
+
+#[test]
+fn annexb_in_memory() {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    let stream = stream::open(Cursor::new(data.clone())).unwrap();
+    assert_eq!(stream.format, ContainerFormat::AnnexB);
+    assert_eq!(stream.parameter_sets.codec(), Codec::H264);
+
+    let access_units: Vec<Vec<u8>> = stream
+        .access_units
+        .map(|access_unit| access_unit.unwrap().0)
+        .collect();
+    let expected: Vec<Vec<u8>> = AnnexBReader::new(&data)
+        .map(|access_unit| access_unit.unwrap().data.to_vec())
+        .collect();
+    assert!(access_units == expected);
+}
+
+#[test]
+fn invalid_files() {
+    assert!(stream::open(Cursor::new(Vec::new())).is_err());
+    assert!(stream::open(Cursor::new(b"not a video file".to_vec())).is_err());
+
+    // The mdat of a truncated file does not fit it
+    let data = std::fs::read(MP4_STREAM).unwrap();
+    assert!(stream::open(Cursor::new(data[..data.len() / 2].to_vec())).is_err());
+}
@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use ash_video::mp4::{SampleTable, TrackTiming};
+use ash_video::Timestamp;
+
+mod common;
+use common::fmp4::{full_box, rewrite_boxes, words};
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/a.mp4");
+
+#[test]
+fn rescale() {
+    // 29.97 Hz frame durations
+    assert_eq!(Timestamp::new(1001, 30000).rescale(90000).value, 3003);
+    assert_eq!(Timestamp::new(3003, 90000).rescale(1000).value, 33);
+    assert_eq!(Timestamp::new(512, 15360).rescale(90000).value, 3000);
+
+    // Rounded to the nearest unit, ties away from zero
+    assert_eq!(Timestamp::new(1, 3).rescale(1000).value, 333);
+    assert_eq!(Timestamp::new(2, 3).rescale(1000).value, 667);
+    assert_eq!(Timestamp::new(-2, 3).rescale(1000).value, -667);
+    assert_eq!(Timestamp::new(1, 2).rescale(1).value, 1);
+    assert_eq!(Timestamp::new(-1, 2).rescale(1).value, -1);
+    assert_eq!(Timestamp::new(1499, 1000).rescale(1).value, 1);
+
+    // Clamped instead of overflowing
+    assert_eq!(Timestamp::new(i64::MAX, 1).rescale(90000).value, i64::MAX);
+    assert_eq!(Timestamp::new(i64::MIN, 1).rescale(90000).value, i64::MIN);
+
+    assert_eq!(Timestamp::new(1, 30).as_nanos(), 33_333_333);
+    assert_eq!(Timestamp::new(-1, 30).as_nanos(), -33_333_333);
+    assert_eq!(
+        Timestamp::new(3, 2).as_duration(),
+        Some(Duration::from_millis(1500))
+    );
+    assert_eq!(Timestamp::new(-1, 90000).as_duration(), None);
+    assert_eq!(
+        Timestamp::from(Duration::from_millis(40)),
+        Timestamp::new(1, 25)
+    );
+}
+
+#[test]
+fn compare() {
+    // Equal values in different timescales
+    assert_eq!(Timestamp::new(1, 30), Timestamp::new(512, 15360));
+    assert_eq!(Timestamp::new(1, 30), Timestamp::new(3000, 90000));
+    assert_eq!(Timestamp::ZERO, Timestamp::new(0, 90000));
+    assert_ne!(Timestamp::new(1001, 30000), Timestamp::new(1, 30));
+
+    assert!(Timestamp::new(1001, 30000) > Timestamp::new(1, 30));
+    assert!(Timestamp::new(-1, 1) < Timestamp::new(0, 90000));
+    assert!(Timestamp::new(i64::MAX, 1) > Timestamp::new(i64::MAX, 2));
+    let mut timestamps = vec![
+        Timestamp::new(4, 90000),
+        Timestamp::new(-1, 2),
+        Timestamp::new(1, 30000),
+    ];
+    timestamps.sort();
+    assert_eq!(
+        timestamps,
+        [
+            Timestamp::new(-1, 2),
+            Timestamp::new(1, 30000),
+            Timestamp::new(4, 90000)
+        ]
+    );
+
+    assert_eq!(Timestamp::new(1, 30).to_string(), "0.033s");
+}
+
+#[test]
+fn arithmetic() {
+    // Exact in the least common multiple of the timescales
+    let sum = Timestamp::new(1, 30) + Timestamp::new(1, 25);
+    assert_eq!((sum.value, sum.timescale), (11, 150));
+    let sum = Timestamp::new(1, 1000) + Timestamp::new(1, 90000);
+    assert_eq!((sum.value, sum.timescale), (91, 90000));
+    let difference = Timestamp::new(1, 1000) - Timestamp::new(1, 90000);
+    assert_eq!((difference.value, difference.timescale), (89, 90000));
+    assert_eq!(-Timestamp::new(1, 30), Timestamp::new(-1, 30));
+
+    // Nanoseconds when the common multiple does not fit a u32
+    let sum = Timestamp::new(1, 1_000_003) + Timestamp::new(1, 999_983);
+    assert_eq!((sum.value, sum.timescale), (2000, 1_000_000_000));
+
+    // Saturating
+    let sum = Timestamp::new(i64::MAX, 1) + Timestamp::new(1, 1);
+    assert_eq!(sum.value, i64::MAX);
+    assert_eq!((-Timestamp::new(i64::MIN, 1)).value, i64::MAX);
+}
+
+#[test]
+fn presentation_time() {
+    // An empty edit of half a second, then the media from 3003 on
+    let timing = TrackTiming {
+        timescale: 90000,
+        media_time: 3003,
+        empty_duration: Timestamp::new(500, 1000),
+        duration: None,
+    };
+    assert_eq!(timing.presentation_time(3003), Timestamp::new(1, 2));
+    assert_eq!(
+        timing.presentation_time(0),
+        Timestamp::new(1, 2) - Timestamp::new(3003, 90000)
+    );
+    assert_eq!(timing.presentation_time(93003), Timestamp::new(3, 2));
+    // Saturates instead of overflowing
+    assert!(timing.presentation_time(i64::MIN) < Timestamp::ZERO);
+}
+
+/// samples/a.mp4 with `edits` as its edit list, `(segment_duration, media_time)` pairs
+/// with the duration in movie (1 kHz) and the media time in track (15360 Hz) units.
+fn with_edit_list(edits: &[(u32, i32)]) -> Vec<u8> {
+    let data = std::fs::read(SAMPLE).unwrap();
+    rewrite_boxes(&data, &mut |box_type, _| match box_type {
+        b"elst" => {
+            let mut payload = words(&[edits.len() as u32]);
+            for &(segment_duration, media_time) in edits {
+                payload.extend(words(&[segment_duration, media_time as u32, 0x0001_0000]));
+            }
+            Some(full_box(b"elst", 0, 0, &payload))
+        }
+        _ => None,
+    })
+}
+
+fn read_sample_table(data: &[u8]) -> SampleTable {
+    let context = mp4parse::read_mp4(&mut std::io::Cursor::new(data)).unwrap();
+    SampleTable::new(&context.tracks[0], context.timescale).unwrap()
+}
+
+#[test]
+fn edit_lists() {
+    // Without an offset presentation starts with the first sample
+    let data = std::fs::read(SAMPLE).unwrap();
+    let sample_table = read_sample_table(&data);
+    assert_eq!(sample_table.timing.media_time, 0);
+    assert_eq!(sample_table.timing.empty_duration, Timestamp::ZERO);
+    let sample = sample_table.iter(&data).nth(3).unwrap().unwrap();
+    assert_eq!(
+        (sample.dts, sample.pts),
+        (Timestamp::new(1, 10), Timestamp::new(1, 10))
+    );
+
+    // B-frame delay compensation, the first picture is presented at 0 and decoded before
+    let data = std::fs::read(common::MP4_STREAM).unwrap();
+    let sample_table = read_sample_table(&data);
+    assert_eq!(sample_table.timing.media_time, 1024);
+    let samples: Vec<_> = sample_table
+        .iter(&data)
+        .take(4)
+        .map(|sample| {
+            let sample = sample.unwrap();
+            (sample.dts, sample.pts)
+        })
+        .collect();
+    assert_eq!(
+        samples,
+        [
+            (Timestamp::new(-1, 15), Timestamp::ZERO),
+            (Timestamp::new(-1, 30), Timestamp::new(1, 5)),
+            (Timestamp::ZERO, Timestamp::new(1, 10)),
+            (Timestamp::new(1, 30), Timestamp::new(1, 30)),
+        ]
+    );
+
+    // A leading empty edit of 250 ms in the movie timescale delays the track
+    let data = with_edit_list(&[(250, -1), (1000, 1024)]);
+    let sample_table = read_sample_table(&data);
+    assert_eq!(sample_table.timing.empty_duration, Timestamp::new(1, 4));
+    assert_eq!(sample_table.timing.media_time, 1024);
+    let sample = sample_table.iter(&data).next().unwrap().unwrap();
+    assert_eq!(
+        sample.pts,
+        Timestamp::new(1, 4) - Timestamp::new(1024, 15360)
+    );
+    let sample = sample_table.iter(&data).nth(2).unwrap().unwrap();
+    assert_eq!(sample.pts, Timestamp::new(1, 4));
+
+    // An empty edit alone is ignored
+    let data = with_edit_list(&[(250, -1)]);
+    let sample_table = read_sample_table(&data);
+    assert_eq!(sample_table.timing.empty_duration, Timestamp::ZERO);
+    assert_eq!(sample_table.timing.media_time, 0);
+
+    // Without edts the media timeline is the presentation timeline
+    let data = rewrite_boxes(&std::fs::read(SAMPLE).unwrap(), &mut |box_type, _| {
+        (box_type == b"edts").then(Vec::new)
+    });
+    let sample_table = read_sample_table(&data);
+    assert_eq!(sample_table.timing.media_time, 0);
+    assert_eq!(sample_table.timing.empty_duration, Timestamp::ZERO);
+    assert_eq!(sample_table.duration(), Timestamp::new(1, 1));
+}
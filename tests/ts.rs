@@ -0,0 +1,283 @@
+use ash_video::annexb::AnnexBReader;
+use ash_video::demux::{self, ContainerFormat};
+use ash_video::ts::{self, VideoStreamType, CLOCK_RATE};
+use ash_video::{codec, Timestamp};
+
+mod common;
+use common::mpegts::{pes, Muxer, AUDIO_PID, VIDEO_PID};
+use common::ANNEXB_STREAM;
+
+const FRAME_DURATION: i64 = 3600;
+
+/// The access units of the raw stream, each carried in its own PES packet.
+fn annexb_access_units() -> Vec<Vec<u8>> {
+    let data = std::fs::read(ANNEXB_STREAM).unwrap();
+    AnnexBReader::new(&data)
+        .map(|access_unit| access_unit.unwrap().data.to_vec())
+        .collect()
+}
+
+fn mux(access_units: &[Vec<u8>], first_pts: i64) -> Muxer {
+    let mut muxer = Muxer::new();
+    muxer.write_tables(0x1b);
+    for (index, access_unit) in access_units.iter().enumerate() {
+        let pts = first_pts + index as i64 * FRAME_DURATION;
+        muxer.write(AUDIO_PID, &[0xaa; 100], false);
+        muxer.write(
+            VIDEO_PID,
+            &pes(Some(pts), Some(pts), access_unit),
+            index == 0,
+        );
+    }
+    muxer
+}
+
+fn expected(access_units: &[Vec<u8>], first_pts: i64) -> Vec<(Vec<u8>, Timestamp)> {
+    access_units
+        .iter()
+        .enumerate()
+        .map(|(index, access_unit)| {
+            let pts = first_pts + index as i64 * FRAME_DURATION;
+            (access_unit.clone(), Timestamp::new(pts, CLOCK_RATE))
+        })
+        .collect()
+}
+
+fn frame_duration() -> Timestamp {
+    Timestamp::new(FRAME_DURATION, CLOCK_RATE)
+}
+
+#[test]
+fn probe() {
+    let access_units = annexb_access_units();
+    let data = mux(&access_units, 0).finish();
+    assert_eq!(demux::probe(&data), Some(ContainerFormat::MpegTs));
+    assert_eq!(ts::packet_size(&data), Some(ts::PACKET_SIZE));
+
+    // A 4 byte arrival timestamp in front of every packet
+    let m2ts: Vec<u8> = data
+        .chunks(ts::PACKET_SIZE)
+        .flat_map(|packet| [0, 0, 0, 0].iter().chain(packet).copied())
+        .collect();
+    assert_eq!(demux::probe(&m2ts), Some(ContainerFormat::MpegTs));
+    assert_eq!(ts::packet_size(&m2ts), Some(ts::M2TS_PACKET_SIZE));
+    assert_eq!(
+        ts::demux(&m2ts).unwrap().data,
+        ts::demux(&data).unwrap().data
+    );
+}
+
+#[test]
+fn same_access_units_as_annexb() {
+    let access_units = annexb_access_units();
+    assert!(access_units.len() > 1);
+    let data = mux(&access_units, 90_000).finish();
+
+    let stream = ts::demux(&data).unwrap();
+    assert_eq!(stream.pid, VIDEO_PID);
+    assert_eq!(stream.stream_type, VideoStreamType::H264);
+    assert_eq!(stream.packets.len(), access_units.len());
+    assert_eq!(stream.lost_packets, 0);
+    assert!(stream.packets[0].random_access);
+    assert_eq!(
+        stream.packets[1].dts,
+        Some(Timestamp::new(93_600, CLOCK_RATE))
+    );
+
+    let raw = std::fs::read(ANNEXB_STREAM).unwrap();
+    assert_eq!(stream.data, raw);
+    assert_eq!(
+        stream.parameter_sets().unwrap(),
+        codec::ParameterSets::from(AnnexBReader::read_parameter_sets(&raw).unwrap())
+    );
+    assert_eq!(
+        stream.access_units(frame_duration()).unwrap(),
+        expected(&access_units, 90_000)
+    );
+}
+
+#[test]
+fn unaligned_pes_packets() {
+    // The elementary stream cut into PES packets regardless of the access units, the PTS
+    // belongs to the first access unit starting in each packet
+    let access_units = annexb_access_units();
+    let raw = std::fs::read(ANNEXB_STREAM).unwrap();
+    let mut muxer = Muxer::new();
+    muxer.write_tables(0x1b);
+    let chunk_size = raw.len() / 2 + 1;
+    muxer.write(VIDEO_PID, &pes(Some(0), None, &raw[..chunk_size]), true);
+    muxer.write(
+        VIDEO_PID,
+        &pes(Some(900_000), None, &raw[chunk_size..]),
+        false,
+    );
+
+    let stream = ts::demux(&muxer.finish()).unwrap();
+    let demuxed = stream.access_units(frame_duration()).unwrap();
+    let data: Vec<_> = demuxed.iter().map(|(data, _)| data.clone()).collect();
+    assert_eq!(data, access_units);
+
+    // The access unit the second packet cuts into keeps counting from the first PTS
+    let mut offset = 0;
+    let second = access_units
+        .iter()
+        .position(|access_unit| {
+            let start = offset;
+            offset += access_unit.len();
+            start >= chunk_size
+        })
+        .unwrap();
+    for (index, (_, pts)) in demuxed.iter().enumerate() {
+        let expected = if index < second {
+            index as i64 * FRAME_DURATION
+        } else {
+            900_000 + (index - second) as i64 * FRAME_DURATION
+        };
+        assert_eq!(*pts, Timestamp::new(expected, CLOCK_RATE), "{}", index);
+    }
+}
+
+#[test]
+fn continuity_counter() {
+    let access_units = annexb_access_units();
+    let muxer = mux(&access_units, 0);
+    let video_packets: Vec<usize> = muxer
+        .packets
+        .iter()
+        .enumerate()
+        .filter(|(_, packet)| u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff == VIDEO_PID)
+        .map(|(index, _)| index)
+        .collect();
+    // The first PES spans several packets
+    let lost = video_packets[1];
+    assert_eq!(muxer.packets[lost][1] & 0x40, 0);
+
+    // A lost packet drops its PES packet
+    let mut packets = muxer.packets.clone();
+    packets.remove(lost);
+    let stream = ts::demux(&packets.concat()).unwrap();
+    assert_eq!(stream.lost_packets, 1);
+    assert_eq!(stream.packets.len(), access_units.len() - 1);
+
+    // Unless the discontinuity is signalled, here by the packet after the lost one
+    let mut packets = muxer.packets.clone();
+    let next = &packets[video_packets[2]];
+    assert_eq!(next[3] & 0x20, 0);
+    let mut discontinuous = next[..4].to_vec();
+    discontinuous[3] |= 0x20;
+    discontinuous.extend_from_slice(&[1, 0x80]);
+    discontinuous.extend_from_slice(&next[4..ts::PACKET_SIZE - 2]);
+    packets[video_packets[2]] = discontinuous;
+    packets.remove(lost);
+    let stream = ts::demux(&packets.concat()).unwrap();
+    assert_eq!(stream.lost_packets, 0);
+    assert_eq!(stream.packets.len(), access_units.len());
+
+    // Duplicate packets are dropped
+    let mut packets = muxer.packets.clone();
+    let duplicate = packets[lost].clone();
+    packets.insert(lost, duplicate);
+    let stream = ts::demux(&packets.concat()).unwrap();
+    assert_eq!(stream.lost_packets, 0);
+    assert_eq!(
+        stream.access_units(frame_duration()).unwrap(),
+        expected(&access_units, 0)
+    );
+
+    // As are packets with the transport_error_indicator set
+    let mut packets = muxer.packets.clone();
+    packets[lost][1] |= 0x80;
+    assert_eq!(ts::demux(&packets.concat()).unwrap().lost_packets, 1);
+}
+
+#[test]
+fn timestamp_wrap() {
+    let access_units = annexb_access_units();
+    let first_pts = (1 << 33) - FRAME_DURATION;
+    let data = mux(&access_units, first_pts).finish();
+
+    // Written modulo 2^33, read back as they were
+    let stream = ts::demux(&data).unwrap();
+    assert_eq!(
+        stream.access_units(frame_duration()).unwrap(),
+        expected(&access_units, first_pts)
+    );
+}
+
+#[test]
+fn starts_at_parameter_sets() {
+    // A capture that starts with the tail of an earlier group of pictures
+    let access_units = annexb_access_units();
+    let mut muxer = Muxer::new();
+    muxer.write_tables(0x1b);
+    muxer.write(
+        VIDEO_PID,
+        &pes(Some(0), None, access_units.last().unwrap()),
+        false,
+    );
+    for (index, access_unit) in access_units.iter().enumerate() {
+        let pts = (index as i64 + 1) * FRAME_DURATION;
+        muxer.write(VIDEO_PID, &pes(Some(pts), None, access_unit), index == 0);
+    }
+
+    let stream = ts::demux(&muxer.finish()).unwrap();
+    assert_eq!(stream.packets.len(), access_units.len() + 1);
+    let demuxed = stream.access_units(frame_duration()).unwrap();
+    assert_eq!(demuxed, expected(&access_units, FRAME_DURATION));
+}
+
+#[test]
+fn h265_pes_packets() {
+    // NAL unit headers only, the H.265 path splits on PES packets without parsing slices
+    let vps = [0, 0, 0, 1, 0x40, 0x01, 0xaa];
+    let idr = [0, 0, 0, 1, 0x26, 0x01, 0xbb];
+    let trail = [0, 0, 0, 1, 0x02, 0x01, 0xcc];
+
+    let mut muxer = Muxer::new();
+    muxer.write_tables(0x24);
+    muxer.write(VIDEO_PID, &pes(Some(0), None, &trail), false);
+    muxer.write(
+        VIDEO_PID,
+        &pes(Some(3600), Some(0), &[&vps[..], &idr].concat()),
+        true,
+    );
+    muxer.write(VIDEO_PID, &pes(None, None, &trail), false);
+
+    let stream = ts::demux(&muxer.finish()).unwrap();
+    assert_eq!(stream.stream_type, VideoStreamType::H265);
+    assert_eq!(stream.packets[1].dts, Some(Timestamp::new(0, CLOCK_RATE)));
+    assert_eq!(
+        stream.access_units(frame_duration()).unwrap(),
+        [
+            ([&vps[..], &idr].concat(), Timestamp::new(3600, CLOCK_RATE)),
+            (trail.to_vec(), Timestamp::new(7200, CLOCK_RATE)),
+        ]
+    );
+}
+
+#[test]
+fn invalid_streams() {
+    // No PMT
+    let mut muxer = Muxer::new();
+    muxer.write(0, &[0; 20], false);
+    assert!(ts::demux(&muxer.finish()).is_err());
+
+    // A PMT without a video stream we decode
+    let mut muxer = Muxer::new();
+    muxer.write_tables(0x02);
+    assert!(ts::demux(&muxer.finish()).is_err());
+
+    // A corrupt PAT
+    let mut muxer = Muxer::new();
+    muxer.write_tables(0x1b);
+    muxer.packets[0][ts::PACKET_SIZE - 1] ^= 0xff;
+    assert!(ts::demux(&muxer.finish()).is_err());
+
+    // Every truncation of a valid stream demuxes or fails cleanly
+    let data = mux(&annexb_access_units(), 0).finish();
+    for len in (0..data.len()).step_by(47) {
+        if let Ok(stream) = ts::demux(&data[..len]) {
+            let _ = stream.access_units(frame_duration());
+        }
+    }
+}